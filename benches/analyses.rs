@@ -0,0 +1,199 @@
+// Throughput benchmarks for the analyses and checkers, run with `cargo bench`.
+//
+// The corpora are synthetic functions built directly in the lifted IR, bucketed by block count,
+// so no binaries are needed. Each benchmark's mean time per function is compared against
+// benches/baseline.json; a slowdown above VERIWASM_BENCH_THRESHOLD (default 0.2 = 20%) fails the
+// run. VERIWASM_BENCH_SAVE=1 overwrites the baseline with the current results.
+
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Instant;
+use veriwasm::analyses::heap_analyzer::HeapAnalyzer;
+use veriwasm::analyses::stack_analyzer::{StackAnalyzer, UntrackedRsp};
+use veriwasm::analyses::{run_worklist, ControlFlow};
+use veriwasm::checkers::heap_checker::check_heap;
+use veriwasm::checkers::stack_checker::check_stack;
+use veriwasm::lattices::switchlattice::JumpTableLoads;
+use veriwasm::utils::compiler::{Compiler, CompilerMetadata};
+use veriwasm::utils::ir::{mk_value_i64, Binopcode, IRMap, MemArg, MemArgs, Stmt, Unopcode, ValSize, Value};
+use yaxpeax_x86::long_mode::Opcode;
+
+const BASELINE_PATH: &str = "benches/baseline.json";
+const ITERATIONS: u32 = 10;
+
+// A synthetic function: a ladder of blocks where each block either falls through or skips the
+// next one, so every join point has two predecessors.
+struct Ladder {
+    num_blocks: u64,
+}
+
+const BLOCK_SIZE: u64 = 0x100;
+
+impl ControlFlow for Ladder {
+    fn entrypoint(&self) -> u64 {
+        0
+    }
+
+    fn successors(&self, addr: u64) -> Vec<u64> {
+        let idx = addr / BLOCK_SIZE;
+        (idx + 1..std::cmp::min(idx + 3, self.num_blocks)).map(|succ| succ * BLOCK_SIZE).collect()
+    }
+}
+
+fn reg(regnum: u8, size: ValSize) -> Value {
+    Value::Reg(regnum, size)
+}
+
+fn stack_slot() -> Value {
+    Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64)))
+}
+
+fn rsp_adjust(opcode: Binopcode) -> Stmt {
+    Stmt::Binop(opcode, reg(4, ValSize::Size64), reg(4, ValSize::Size64), mk_value_i64(8))
+}
+
+// spill, bound and access the heap, then restore
+fn block_body(num_accesses: usize) -> Vec<Vec<Stmt>> {
+    let mut instrs = vec![
+        vec![rsp_adjust(Binopcode::Sub), Stmt::Unop(Unopcode::Mov, stack_slot(), reg(3, ValSize::Size64))],
+    ];
+    for _ in 0..num_accesses {
+        instrs.push(vec![Stmt::Unop(Unopcode::Mov, reg(0, ValSize::Size32), reg(1, ValSize::Size32))]);
+        instrs.push(vec![Stmt::Unop(
+            Unopcode::Mov,
+            reg(2, ValSize::Size32),
+            Value::Mem(ValSize::Size32, MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Reg(0, ValSize::Size64))),
+        )]);
+    }
+    instrs.push(vec![Stmt::Unop(Unopcode::Mov, reg(3, ValSize::Size64), stack_slot()), rsp_adjust(Binopcode::Add)]);
+    instrs
+}
+
+fn make_function(num_blocks: u64, num_accesses: usize) -> (Ladder, IRMap) {
+    let mut irmap = IRMap::new();
+    for idx in 0..num_blocks {
+        let start = idx * BLOCK_SIZE;
+        let mut block: Vec<(u64, Vec<Stmt>)> = block_body(num_accesses)
+            .into_iter()
+            .enumerate()
+            .map(|(offset, stmts)| (start + offset as u64, stmts))
+            .collect();
+        let end = start + block.len() as u64;
+        if idx + 2 < num_blocks {
+            block.push((end, vec![Stmt::Branch(Opcode::JZ, mk_value_i64(((idx + 2) * BLOCK_SIZE) as i64))]));
+        } else if idx + 1 == num_blocks {
+            block.push((end, vec![Stmt::Ret]));
+        }
+        irmap.insert(start, block);
+    }
+    (Ladder { num_blocks: num_blocks }, irmap)
+}
+
+// (bucket name, number of functions, blocks per function, heap accesses per block)
+const BUCKETS: [(&str, usize, u64, usize); 3] = [
+    ("small", 24, 8, 2),
+    ("medium", 12, 64, 4),
+    ("large", 4, 512, 4),
+];
+
+fn time_per_function<F: FnMut(&(Ladder, IRMap))>(corpus: &Vec<(Ladder, IRMap)>, mut f: F) -> f64 {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        for func in corpus {
+            f(func);
+        }
+    }
+    start.elapsed().as_secs_f64() / (ITERATIONS as f64 * corpus.len() as f64)
+}
+
+fn run_benchmarks() -> BTreeMap<String, f64> {
+    let metadata = CompilerMetadata {
+        compiler: Compiler::Lucet,
+        model: Compiler::Lucet.model(),
+        guest_table_0: 0,
+        lucet_tables: 0,
+        lucet_probestack: 0,
+        lucet_safe_page: None,
+        globals_size: -1,
+        call_table_size: -1,
+        caller_window: 0,
+        strict_stack: false,
+        strict_rebound: false,
+        runtime_fields: vec![],
+        wamr_memories: Default::default(),
+        wamr_tables: None,
+        options: Default::default(),
+        abi: Default::default(),
+        readonly_data: vec![],
+        wasmtime_layout: Default::default(),
+    };
+    let heap_analyzer = HeapAnalyzer { metadata: metadata.clone() };
+    let stack_analyzer = StackAnalyzer { metadata: metadata, stack_limit_cmps: Default::default() };
+    let mut results = BTreeMap::new();
+    for (bucket, num_funcs, num_blocks, num_accesses) in BUCKETS.iter() {
+        let corpus: Vec<(Ladder, IRMap)> = (0..*num_funcs).map(|_| make_function(*num_blocks, *num_accesses)).collect();
+
+        results.insert(format!("{}/heap_worklist", bucket), time_per_function(&corpus, |(cfg, irmap)| {
+            run_worklist(cfg, irmap, &heap_analyzer);
+        }));
+        results.insert(format!("{}/stack_worklist", bucket), time_per_function(&corpus, |(cfg, irmap)| {
+            run_worklist(cfg, irmap, &stack_analyzer);
+        }));
+
+        let heap_results: Vec<_> = corpus.iter().map(|(cfg, irmap)| run_worklist(cfg, irmap, &heap_analyzer)).collect();
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            for ((_, irmap), result) in corpus.iter().zip(heap_results.iter()) {
+                assert!(check_heap(result.clone(), irmap, &heap_analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok());
+            }
+        }
+        results.insert(
+            format!("{}/heap_checker", bucket),
+            start.elapsed().as_secs_f64() / (ITERATIONS as f64 * corpus.len() as f64),
+        );
+
+        results.insert(format!("{}/end_to_end", bucket), time_per_function(&corpus, |(cfg, irmap)| {
+            assert!(check_heap(run_worklist(cfg, irmap, &heap_analyzer), irmap, &heap_analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok());
+            assert!(check_stack(run_worklist(cfg, irmap, &stack_analyzer), irmap, &stack_analyzer).is_ok());
+        }));
+    }
+    results
+}
+
+fn main() {
+    let threshold: f64 = env::var("VERIWASM_BENCH_THRESHOLD")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0.2);
+    let results = run_benchmarks();
+
+    if env::var("VERIWASM_BENCH_SAVE").is_ok() {
+        let data = serde_json::to_string_pretty(&results).unwrap();
+        fs::write(BASELINE_PATH, data).expect("Unable to write baseline");
+        println!("Saved baseline to {}", BASELINE_PATH);
+    }
+
+    let baseline: HashMap<String, f64> = match fs::read_to_string(BASELINE_PATH) {
+        Ok(data) => serde_json::from_str(&data).expect("Malformed baseline"),
+        Err(_) => {
+            println!("No baseline at {} (run with VERIWASM_BENCH_SAVE=1 to create one)", BASELINE_PATH);
+            HashMap::new()
+        }
+    };
+    let mut regressed = false;
+    for (name, secs) in &results {
+        match baseline.get(name) {
+            Some(base) => {
+                let change = (secs - base) / base;
+                let flag = if change > threshold { regressed = true; "  REGRESSION" } else { "" };
+                println!("{:<24} {:>12.3}us  baseline {:>12.3}us  {:>+7.1}%{}", name, secs * 1e6, base * 1e6, change * 100.0, flag);
+            }
+            None => println!("{:<24} {:>12.3}us", name, secs * 1e6),
+        }
+    }
+    if regressed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,35 @@
+// Emits VERIWASM_BUILD_ID, a hash of the sources the verifier is built from, which keys the results
+// and CFGs kept across runs (see verifier_key). Hashing the running executable instead would hash
+// whatever program loaded the cdylib.
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+// the verifier's own sources, and the decoder and lifter it depends on
+const SOURCES: [&str; 5] = ["src", "yaxpeax-x86/src", "yaxpeax-core/src", "Cargo.toml", "Cargo.lock"];
+
+fn hash_path(path: &Path, hasher: &mut DefaultHasher) {
+    if path.is_dir() {
+        let mut entries: Vec<_> = match fs::read_dir(path) {
+            Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+            Err(_) => return,
+        };
+        entries.sort();
+        for entry in entries {
+            hash_path(&entry, hasher);
+        }
+    } else if let Ok(data) = fs::read(path) {
+        path.to_string_lossy().hash(hasher);
+        data.hash(hasher);
+    }
+}
+
+fn main() {
+    let mut hasher = DefaultHasher::new();
+    for source in SOURCES.iter() {
+        println!("cargo:rerun-if-changed={}", source);
+        hash_path(Path::new(source), &mut hasher);
+    }
+    println!("cargo:rustc-env=VERIWASM_BUILD_ID={:016x}", hasher.finish());
+}
@@ -35,6 +35,8 @@ def get_data(filenames):
     for filename in filenames:
         with open(filename) as f:
             data = json.load(f)
+        if isinstance(data, dict):
+            data = data['functions']
         dataset[filename] = data
     return dataset
 
@@ -0,0 +1,74 @@
+/*
+ * C interface to the VeriWasm verifier, built as the libveriwasm cdylib (`--features ffi`).
+ * Declares what src/ffi.rs exports; the two have to be changed together.
+ *
+ * All strings are UTF-8 and NUL-terminated. Strings returned by the report getters are owned by
+ * the report and live until veriwasm_report_free. No call unwinds into the caller: a panic in the
+ * verifier is returned as VERIWASM_ERR_INTERNAL.
+ */
+#ifndef VERIWASM_H
+#define VERIWASM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The same meaning as the exit status of `veriwasm`, where the two overlap. */
+#define VERIWASM_OK 0
+#define VERIWASM_UNSAFE 1
+#define VERIWASM_ERR_LOAD 2
+#define VERIWASM_ERR_INVALID_ARGUMENT 3
+#define VERIWASM_ERR_INTERNAL 4
+#define VERIWASM_ERR_IO 5
+
+#define VERIWASM_COMPILER_LUCET 0
+#define VERIWASM_COMPILER_WAMR 1
+#define VERIWASM_COMPILER_DETECT 2
+#define VERIWASM_COMPILER_WASMTIME 3
+
+/*
+ * Set struct_size to sizeof(VeriwasmOptions). Fields past it, or partly past it, keep their
+ * defaults, so fields can be added at the end without breaking callers built against an older
+ * header.
+ */
+typedef struct VeriwasmOptions {
+    uint32_t struct_size;
+    uint32_t compiler;        /* VERIWASM_COMPILER_*, default detected from the module's symbols */
+    int64_t globals_size;     /* size of global data in memory (WAMR-only), default -1 */
+    int64_t call_table_size;  /* # of functions in the indirect call table (WAMR-only), default -1 */
+    uint32_t num_jobs;        /* number of parallel threads, default 1 */
+    uint8_t keep_going;       /* verify the remaining functions after one fails, default 0 */
+} VeriwasmOptions;
+
+typedef struct VeriwasmReport VeriwasmReport;
+
+/*
+ * Verifies the module at path. options may be NULL for the defaults. On VERIWASM_OK and
+ * VERIWASM_UNSAFE, *report is set to a report to free with veriwasm_report_free; otherwise it is
+ * set to NULL.
+ */
+int veriwasm_verify(const char *path, const VeriwasmOptions *options, VeriwasmReport **report);
+
+size_t veriwasm_report_function_count(const VeriwasmReport *report);
+/* 1 if the function was verified, 0 if it failed, -1 for an index out of range. */
+int veriwasm_report_function_verified(const VeriwasmReport *report, size_t index);
+uint64_t veriwasm_report_function_addr(const VeriwasmReport *report, size_t index);
+const char *veriwasm_report_function_name(const VeriwasmReport *report, size_t index);
+/* "cfi", "heap", "stack" or "call" for a function one property rejected, NULL otherwise. */
+const char *veriwasm_report_function_rule_id(const VeriwasmReport *report, size_t index);
+/* Why the function failed, NULL if it was verified. */
+const char *veriwasm_report_function_reason(const VeriwasmReport *report, size_t index);
+
+/* The report as JSON (the stats file's `verification` section); free with veriwasm_string_free. */
+char *veriwasm_report_to_json(const VeriwasmReport *report);
+void veriwasm_string_free(char *s);
+void veriwasm_report_free(VeriwasmReport *report);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* VERIWASM_H */
@@ -1,31 +1,35 @@
-use crate::utils::lifter::IRBlock;
+use crate::utils::ir::IRBlock;
 use crate::analyses::reaching_defs::ReachingDefnAnalyzer;
+use crate::analyses::value_numbering::number_block;
 use crate::analyses::AbstractAnalyzer;
 use crate::analyses::AnalysisResult;
-use crate::utils::ir_utils::{extract_stack_offset, is_stack_access};
-use crate::lattices::calllattice::{CallCheckLattice, CallCheckValue, CallCheckValueLattice};
+use crate::utils::ir_utils::{branch_opcode, extract_stack_offset, flags_reach_branch, imm_to_addr, in_bounds_side, is_stack_access, is_zf};
+use crate::lattices::calllattice::{CallCheckLattice, CallCheckValue, CallCheckValueLattice, WamrTableId};
 use crate::lattices::davlattice::DAV;
 use crate::lattices::reachingdefslattice::{LocIdx, ReachLattice};
 use crate::lattices::stacklattice::StackSlot;
-use crate::lattices::heaplattice::{WAMR_MODULEINSTANCE_OFFSET, WAMR_FUNCPTRS_OFFSET, WAMR_FUNCTYPE_OFFSET, WAMR_GLOBALS_OFFSET};
-use crate::lattices::VarState;
-use crate::utils::lifter::{Binopcode, IRMap, MemArg, MemArgs, ValSize, Value};
-use crate::utils::utils::{CompilerMetadata, Compiler};
+use crate::lattices::heaplattice::{WAMR_MODULEINSTANCE_OFFSET, WAMR_FUNCPTRS_OFFSET, WAMR_FUNCTYPE_OFFSET};
+use crate::lattices::{Lattice, VarState};
+use crate::utils::ir::{Binopcode, IRMap, MemArg, MemArgs, Stmt, Unopcode, ValSize, Value};
+use crate::utils::compiler::{AnalysisOptions, CompilerMetadata, Compiler};
+use crate::utils::wasmtime_layout::{WASMTIME_ANYFUNC_FUNC_PTR_OFFSET, WASMTIME_ANYFUNC_VMCTX_OFFSET, WASMTIME_TABLE_ENTRY_SIZE};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::default::Default;
+use yaxpeax_x86::long_mode::Opcode;
 
 pub struct CallAnalyzer {
     pub metadata: CompilerMetadata,
     pub reaching_defs: AnalysisResult<ReachLattice>,
     pub reaching_analyzer: ReachingDefnAnalyzer,
+    // number of branches whose bounds check was not applied because of intervening instructions
+    pub withheld_refinements: AtomicUsize,
 }
 
 impl AbstractAnalyzer<CallCheckLattice> for CallAnalyzer {
 
     fn init_state(&self) -> CallCheckLattice {
         let mut result: CallCheckLattice = Default::default();
-        if let Compiler::Wamr = self.compiler() {
-            result.regs.rdi = CallCheckValueLattice::new(CallCheckValue::WamrExecEnv);
-        }
+        self.metadata.model.init_call_state(&mut result);
         result
     }
 
@@ -33,6 +37,30 @@ impl AbstractAnalyzer<CallCheckLattice> for CallAnalyzer {
         self.metadata.compiler
     }
 
+    fn paranoid(&self) -> bool {
+        self.metadata.options.paranoid
+    }
+
+    fn options(&self) -> AnalysisOptions {
+        self.metadata.options
+    }
+
+    fn on_call(&self, in_state: &mut CallCheckLattice) -> () {
+        in_state.on_call(self.metadata.compiler);
+        in_state.forget_caller_window(self.metadata.caller_window);
+        // see HeapAnalyzer::on_call
+        if self.metadata.strict_rebound {
+            in_state.forget(|v| match v.v {
+                Some(CallCheckValue::CheckedVal)
+                | Some(CallCheckValue::WamrChecked(_))
+                | Some(CallCheckValue::WamrTableChecked(_))
+                | Some(CallCheckValue::PtrOffset(DAV::Checked))
+                | Some(CallCheckValue::TableEntryPtr) => true,
+                _ => false,
+            });
+        }
+    }
+
     fn analyze_block(
         &self,
         state:  &CallCheckLattice,
@@ -59,9 +87,17 @@ impl AbstractAnalyzer<CallCheckLattice> for CallAnalyzer {
         in_state: &mut CallCheckLattice,
         dst: &Value,
         src: &Value,
-        _loc_idx: &LocIdx,
+        loc_idx: &LocIdx,
     ) -> () {
-        in_state.set(dst, self.aeval_unop(&in_state, src))
+        // zf is only the source of a SETcc, which writes 0 or 1 rather than the CheckFlag a cmp
+        // left in it
+        if is_zf(src) {
+            in_state.set(dst, Default::default())
+        } else if let Some(table) = self.wamr_descriptor_load(in_state, src, loc_idx) {
+            in_state.set(dst, CallCheckValueLattice::new(CallCheckValue::WamrTableDescriptor(table)))
+        } else {
+            in_state.set(dst, self.aeval_unop(&in_state, src))
+        }
     }
 
     fn aexec_binop(
@@ -74,13 +110,13 @@ impl AbstractAnalyzer<CallCheckLattice> for CallAnalyzer {
         loc_idx: &LocIdx,
     ) -> () {
         match opcode {
-            Binopcode::Cmp => {
-                match self.compiler() {
-                    Compiler::Lucet => self.lucet_handle_cmp(in_state, src1, src2),
-                    Compiler::Wamr => self.wamr_handle_cmp(in_state, src1, src2),
-                }
-            },
+            Binopcode::Cmp => self.metadata.model.refine_cmp(in_state, src1, src2, loc_idx),
             Binopcode::Test => (),
+            // a conditional move leaves whichever of its sources the flags pick, so what both hold
+            Binopcode::Cmov => {
+                let v = self.aeval_unop(in_state, src1).meet(&self.aeval_unop(in_state, src2), loc_idx);
+                in_state.set(dst, v)
+            }
             _ => in_state.set(dst, self.aeval_binop(in_state, opcode, src1, src2, loc_idx)),
         }
     }
@@ -95,34 +131,66 @@ impl AbstractAnalyzer<CallCheckLattice> for CallAnalyzer {
         if succ_addrs.len() == 2 {
             let mut not_branch_state = in_state.clone();
             let mut branch_state = in_state.clone();
-            if let Some(CallCheckValue::CheckFlag(val, regnum)) = not_branch_state.regs.zf.v {
-                let new_val = CallCheckValueLattice {
-                    v: match self.compiler() {
-                           Compiler::Lucet => Some(CallCheckValue::CheckedVal),
-                           Compiler::Wamr => Some(CallCheckValue::WamrChecked(val)),
+            // branch_state is the in-bounds side, the taken successor unless found otherwise
+            let mut taken = true;
+            if let Some(CallCheckValue::CheckFlag(val, regnum, index_first, cmp_loc)) = not_branch_state.regs.zf.v {
+                let ir_block = irmap.get(addr).unwrap();
+                // the flag is only trusted if nothing between the cmp and the branch could have
+                // changed it behind the lifter's back
+                if !flags_reach_branch(&ir_block, &cmp_loc) {
+                    self.withheld_refinements.fetch_add(1, Ordering::Relaxed);
+                    branch_state.regs.zf = Default::default();
+                    not_branch_state.regs.zf = Default::default();
+                    return self.order_branch_states(succ_addrs, taken, branch_state, not_branch_state);
+                }
+                // which side of the branch the index is in bounds on, and what it is known to be there
+                let refinement = branch_opcode(&ir_block)
+                    .and_then(|opcode| in_bounds_side(&opcode, index_first))
+                    .and_then(|(taken, strict)| self.metadata.model.checked_value(val, strict).map(|v| (taken, v)));
+                let checked_val = match refinement {
+                    Some((taken_side, checked_val)) => {
+                        taken = taken_side;
+                        checked_val
+                    }
+                    None => {
+                        branch_state.regs.zf = Default::default();
+                        not_branch_state.regs.zf = Default::default();
+                        return self.order_branch_states(succ_addrs, taken, branch_state, not_branch_state);
                     }
                 };
+                let new_val = CallCheckValueLattice::new(checked_val);
                 branch_state.regs.set(
                     &regnum,
                     &ValSize::Size64,
                     new_val.clone(),
                 );
                 //1. propagate checked values
-                let defs_state = self.reaching_defs.get(addr).unwrap();
-                let ir_block = irmap.get(addr).unwrap();
-                let defs_state = self.reaching_analyzer.analyze_block(defs_state, ir_block);
+                let defs_state = self.reaching_analyzer.fetch_exit_def(&self.reaching_defs, *addr);
                 let checked_defs = defs_state.regs.get(&regnum, &ValSize::Size64);
+                let options = self.metadata.options;
+                // registers holding the checked value recomputed rather than copied have other defs
+                let mut numbering = if options.propagate_checks && options.value_numbering {
+                    Some(number_block(&ir_block))
+                } else {
+                    None
+                };
+                let checked_number = numbering.as_mut().map(|numbering| numbering.reg(regnum));
                 for idx in 0..15 {
                     let reg_def = defs_state.regs.get(&idx, &ValSize::Size64);
-                    if (!reg_def.is_empty()) && (reg_def == checked_defs) {
+                    let same_value = match (numbering.as_mut(), checked_number) {
+                        (Some(numbering), Some(checked_number)) => numbering.reg(idx) == checked_number,
+                        _ => false,
+                    };
+                    if options.propagate_checks && (((!reg_def.is_empty()) && (reg_def == checked_defs)) || same_value) {
                         branch_state
                             .regs
                             .set(&idx, &ValSize::Size64, new_val.clone());
                     }
                 }
 
+                let propagate_to_stack = options.propagate_checks && options.propagate_checks_to_stack && options.stack_tracking;
                 for (stack_offset, stack_slot) in defs_state.stack.map.iter() {
-                    if !checked_defs.is_empty() && (stack_slot.value == checked_defs) {
+                    if propagate_to_stack && !checked_defs.is_empty() && (stack_slot.value == checked_defs) {
                         let vv = StackSlot {
                             size: stack_slot.size,
                             value: new_val.clone(),
@@ -150,7 +218,7 @@ impl AbstractAnalyzer<CallCheckLattice> for CallAnalyzer {
                 for (stack_offset, stack_slot) in not_branch_state.stack.map.iter() {
                     let stack_val = stack_slot.value.v.clone();
                     if let Some(CallCheckValue::PtrOffset(DAV::Unchecked(stack_def))) = stack_val {
-                        if !checked_defs.is_empty() && (stack_def == checked_defs) {
+                        if options.stack_tracking && !checked_defs.is_empty() && (stack_def == checked_defs) {
                         let v = StackSlot {
                             size: stack_slot.size,
                             value: checked_ptr.clone(),
@@ -162,18 +230,19 @@ impl AbstractAnalyzer<CallCheckLattice> for CallAnalyzer {
             }
             branch_state.regs.zf = Default::default();
             not_branch_state.regs.zf = Default::default();
-
-            match self.compiler() {
-                Compiler::Lucet => return vec![
-                    (succ_addrs[0].clone(), not_branch_state),
-                    (succ_addrs[1].clone(), branch_state),
-                ],
-                Compiler::Wamr => return vec![
-                    (succ_addrs[0].clone(), branch_state),
-                    (succ_addrs[1].clone(), not_branch_state),
-                ],
-            }
+            self.order_branch_states(succ_addrs, taken, branch_state, not_branch_state)
         } else {
+            // a cmp's flags are only used by the branch ending its block, so a bounds check that
+            // falls through into another block is lost
+            if let Some(CallCheckValue::CheckFlag(_, _, _, cmp_loc)) = in_state.regs.zf.v {
+                if irmap.get(addr).map_or(false, |block| block.iter().any(|(addr, _)| *addr == cmp_loc.addr)) {
+                    let succs: Vec<String> = succ_addrs.iter().map(|succ| format!("0x{:x}", succ)).collect();
+                    log::warn!(
+                        "cmp at 0x{:x} reaches the end of block 0x{:x} without a branch; its bounds check isn't trusted in [{}]",
+                        cmp_loc.addr, addr, succs.join(", ")
+                    );
+                }
+            }
             succ_addrs
                 .into_iter()
                 .map(|addr| (addr.clone(), in_state.clone()))
@@ -193,6 +262,12 @@ pub fn is_table_size(in_state: &CallCheckLattice, memargs: &MemArgs) -> bool {
 }
 
 pub fn is_fn_ptr(in_state: &CallCheckLattice, memargs: &MemArgs) -> bool {
+    // mem[TableEntryPtr + 8], when the table base and offset were added up beforehand
+    if let MemArgs::Mem2Args(MemArg::Reg(regnum, size), MemArg::Imm(_, _, 8)) = memargs {
+        if let Some(CallCheckValue::TableEntryPtr) = in_state.regs.get(regnum, size).v {
+            return true;
+        }
+    }
     if let MemArgs::Mem3Args(
         MemArg::Reg(regnum1, size1),
         MemArg::Reg(regnum2, size2),
@@ -221,39 +296,68 @@ pub fn is_fn_ptr(in_state: &CallCheckLattice, memargs: &MemArgs) -> bool {
 }
 
 impl CallAnalyzer {
-    fn lucet_handle_cmp(&self, in_state: &mut CallCheckLattice, src1: &Value, src2: &Value) {
-        match (src1, src2) {
-            (Value::Reg(regnum1,size1), Value::Reg(regnum2, size2)) => {
-                if let Some(CallCheckValue::TableSize) = in_state.regs.get(regnum2, size2).v{
-                    in_state.regs.zf =
-                        CallCheckValueLattice::new(CallCheckValue::CheckFlag(0, *regnum1))
-                }
-                if let Some(CallCheckValue::TableSize) = in_state.regs.get(regnum1, size1).v{
-                    in_state.regs.zf =
-                        CallCheckValueLattice::new(CallCheckValue::CheckFlag(0, *regnum2))
-                }
-            }
-            _ => (),
+    // align_succ_addrs puts the fall-through successor first and the branch target second
+    fn order_branch_states(
+        &self,
+        succ_addrs: &Vec<u64>,
+        taken: bool,
+        branch_state: CallCheckLattice,
+        not_branch_state: CallCheckLattice,
+    ) -> Vec<(u64, CallCheckLattice)> {
+        if !taken {
+            vec![
+                (succ_addrs[0].clone(), branch_state),
+                (succ_addrs[1].clone(), not_branch_state),
+            ]
+        } else {
+            vec![
+                (succ_addrs[0].clone(), not_branch_state),
+                (succ_addrs[1].clone(), branch_state),
+            ]
         }
     }
 
-    fn wamr_handle_cmp(&self, in_state: &mut CallCheckLattice, src1: &Value, src2: &Value) {
-        match (src1, src2) {
-            (Value::Imm(_, _, immval), Value::Reg(regnum, regsize)) |
-            (Value::Reg(regnum, regsize), Value::Imm(_, _, immval)) => {
-                match in_state.regs.get(regnum, regsize).v {
-                    Some(_) => (),
-                    _ => in_state.regs.zf = CallCheckValueLattice::new(CallCheckValue::CheckFlag(*immval as u32, *regnum)),
+    // The load of a funcref table descriptor of a multi-table Wamr module. One through a register
+    // index is named after its location, and what an earlier pass of a loop through the same
+    // load left is forgotten first, so a size and a function pointer table of different passes,
+    // possibly of different tables, never pair up.
+    fn wamr_descriptor_load(&self, in_state: &mut CallCheckLattice, src: &Value, loc_idx: &LocIdx) -> Option<WamrTableId> {
+        let tables = match (self.compiler(), &self.metadata.wamr_tables) {
+            (Compiler::Wamr, Some(tables)) => tables,
+            _ => return None,
+        };
+        match src {
+            Value::Mem(memsize, MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, immval))) => {
+                if let Some(CallCheckValue::WamrModuleInstance) = in_state.regs.get(regnum, &ValSize::Size64).v {
+                    return tables.descriptor_at(*immval, (memsize.to_u32() / 8) as i64).map(WamrTableId::Const);
                 }
-            },
+            }
+            Value::Mem(ValSize::Size64, MemArgs::MemScaleDisp(MemArg::Reg(regnum, ValSize::Size64),
+                                                              MemArg::Reg(_, _), MemArg::Imm(_, _, 8),
+                                                              MemArg::Imm(_, _, disp))) => {
+                if *disp != tables.descriptors {
+                    return None;
+                }
+                if let Some(CallCheckValue::WamrModuleInstance) = in_state.regs.get(regnum, &ValSize::Size64).v {
+                    let table = WamrTableId::LoadedAt(*loc_idx);
+                    let stale = |v: &CallCheckValueLattice| v.v.as_ref().and_then(|v| v.wamr_table()) == Some(table);
+                    in_state.forget(stale);
+                    if stale(&in_state.regs.zf) {
+                        in_state.regs.zf = Default::default();
+                    }
+                    return Some(table);
+                }
+            }
             _ => (),
         }
+        None
     }
 
     pub fn aeval_unop(&self, in_state: &CallCheckLattice, value: &Value) -> CallCheckValueLattice {
         match self.compiler() {
             Compiler::Lucet => self.lucet_aeval_unop(in_state, value),
             Compiler::Wamr => self.wamr_aeval_unop(in_state, value),
+            Compiler::Wasmtime => self.wasmtime_aeval_unop(in_state, value),
         }
     }
 
@@ -269,19 +373,23 @@ impl CallAnalyzer {
                         v: Some(CallCheckValue::FnPtr),
                     };
                 } else if is_stack_access(value) {
-                    let offset = extract_stack_offset(memargs);
-                    return in_state.stack.get(offset, memsize.to_u32() / 8);
+                    return match extract_stack_offset(memargs) {
+                        Some(offset) => in_state.stack.get(offset, memsize.to_u32() / 8),
+                        // an indexed stack access may read any slot
+                        None => Default::default(),
+                    };
                 }
             }
 
             Value::Reg(regnum, size) => return in_state.regs.get(regnum, size),
 
-            Value::Imm(_, _, immval) => {
-                if (*immval as u64) == self.metadata.guest_table_0 {
+            Value::Imm(immtype, immsize, immval) => {
+                let addr = imm_to_addr(immtype, immsize, *immval);
+                if self.metadata.is_addr(addr, self.metadata.guest_table_0) {
                     return CallCheckValueLattice {
                         v: Some(CallCheckValue::GuestTableBase),
                     };
-                } else if (*immval as u64) == self.metadata.lucet_tables {
+                } else if self.metadata.is_addr(addr, self.metadata.lucet_tables) {
                     return CallCheckValueLattice {
                         v: Some(CallCheckValue::LucetTablesBase),
                     };
@@ -294,6 +402,32 @@ impl CallAnalyzer {
     fn wamr_aeval_unop(&self, in_state: &CallCheckLattice, value: &Value) -> CallCheckValueLattice {
         match value {
             Value::Mem(_memsize, memargs) => {
+                // the fields of a funcref table descriptor, and its function pointers
+                if let Some(tables) = &self.metadata.wamr_tables {
+                    match memargs {
+                        MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, immval)) => {
+                            if let Some(CallCheckValue::WamrTableDescriptor(table)) = in_state.regs.get(regnum, &ValSize::Size64).v {
+                                if *immval == tables.size_field {
+                                    return CallCheckValueLattice::new(CallCheckValue::WamrTableSize(table));
+                                } else if *immval == tables.base_field {
+                                    return CallCheckValueLattice::new(CallCheckValue::WamrTableFuncPtrs(table));
+                                }
+                            }
+                        }
+                        MemArgs::MemScale(MemArg::Reg(regnum, ValSize::Size64),
+                                          MemArg::Reg(idx_regnum, ValSize::Size64), MemArg::Imm(_, _, 8)) => {
+                            // the call checker checks the index when this is the call target
+                            if let Some(CallCheckValue::WamrTableFuncPtrs(table)) = in_state.regs.get(regnum, &ValSize::Size64).v {
+                                if let Some(CallCheckValue::WamrTableChecked(checked)) = in_state.regs.get(idx_regnum, &ValSize::Size64).v {
+                                    if checked == table {
+                                        return CallCheckValueLattice::new(CallCheckValue::FnPtr);
+                                    }
+                                }
+                            }
+                        }
+                        _ => (),
+                    }
+                }
                 match memargs {
                     MemArgs::Mem2Args(MemArg::Reg(regnum, regsize), 
                                       MemArg::Imm(_, _, WAMR_MODULEINSTANCE_OFFSET)) => {
@@ -316,13 +450,15 @@ impl CallAnalyzer {
                     },
                     MemArgs::Mem2Args(MemArg::Reg(base_regnum, ValSize::Size64), MemArg::Imm(_, _, immval)) |
                     MemArgs::MemScaleDisp(MemArg::Reg(base_regnum, ValSize::Size64),
-                                          MemArg::Reg(_, ValSize::Size64), MemArg::Imm(_, _, 4),
+                                          MemArg::Reg(_, _), MemArg::Imm(_, _, 4),
                                           MemArg::Imm(_, _, immval)) => {
                         // the safety of these accesses is checked in the actual call checker,
                         // the purpose of this code is just to pass on the fact that the result of
-                        // this access will be a validated pointer
+                        // this access will be a validated pointer. Only entries of the function
+                        // index table are function indices: not the global data before it, nor
+                        // the table's sizes in the 8 bytes before that
                         if let Some(CallCheckValue::WamrModuleInstance) = in_state.regs.get(base_regnum, &ValSize::Size64).v {
-                            if *immval >= WAMR_GLOBALS_OFFSET-8 {
+                            if *immval >= self.metadata.wamr_table_offset() {
                                 return CallCheckValueLattice { v: Some(CallCheckValue::WamrFuncIdx) };
                             }
                         }
@@ -335,6 +471,55 @@ impl CallAnalyzer {
         Default::default()
     }
 
+    fn wasmtime_aeval_unop(&self, in_state: &CallCheckLattice, value: &Value) -> CallCheckValueLattice {
+        let layout = &self.metadata.wasmtime_layout;
+        match value {
+            Value::Mem(memsize, memargs) => {
+                match memargs {
+                    MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, offset)) => {
+                        match in_state.regs.get(regnum, &ValSize::Size64).v {
+                            Some(CallCheckValue::WasmtimeVMContext) if *offset == layout.table_size => {
+                                return CallCheckValueLattice::new(CallCheckValue::TableSize);
+                            }
+                            Some(CallCheckValue::WasmtimeVMContext) if *offset == layout.table_base => {
+                                return CallCheckValueLattice::new(CallCheckValue::WasmtimeTableBase);
+                            }
+                            Some(CallCheckValue::WasmtimeAnyfunc) if *offset == WASMTIME_ANYFUNC_FUNC_PTR_OFFSET => {
+                                return CallCheckValueLattice::new(CallCheckValue::FnPtr);
+                            }
+                            Some(CallCheckValue::WasmtimeAnyfunc) if *offset == WASMTIME_ANYFUNC_VMCTX_OFFSET => {
+                                return CallCheckValueLattice::new(CallCheckValue::WasmtimeVMContext);
+                            }
+                            _ => (),
+                        }
+                    }
+                    MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) if WASMTIME_ANYFUNC_FUNC_PTR_OFFSET == 0 => {
+                        if let Some(CallCheckValue::WasmtimeAnyfunc) = in_state.regs.get(regnum, &ValSize::Size64).v {
+                            return CallCheckValueLattice::new(CallCheckValue::FnPtr);
+                        }
+                    }
+                    // an entry of the function table, whose index the call checker checks
+                    MemArgs::MemScale(MemArg::Reg(regnum, ValSize::Size64), MemArg::Reg(_, _), MemArg::Imm(_, _, WASMTIME_TABLE_ENTRY_SIZE)) => {
+                        if let Some(CallCheckValue::WasmtimeTableBase) = in_state.regs.get(regnum, &ValSize::Size64).v {
+                            return CallCheckValueLattice::new(CallCheckValue::WasmtimeAnyfunc);
+                        }
+                    }
+                    _ => (),
+                }
+                if is_stack_access(value) {
+                    return match extract_stack_offset(memargs) {
+                        Some(offset) => in_state.stack.get(offset, memsize.to_u32() / 8),
+                        // an indexed stack access may read any slot
+                        None => Default::default(),
+                    };
+                }
+            }
+            Value::Reg(regnum, size) => return in_state.regs.get(regnum, size),
+            Value::Imm(_, _, _) => (),
+        }
+        Default::default()
+    }
+
     //checked_val << 4
     pub fn aeval_binop(
         &self,
@@ -344,6 +529,19 @@ impl CallAnalyzer {
         src2: &Value,
         loc_idx: &LocIdx,
     ) -> CallCheckValueLattice {
+        //guest_table_0 + checked_val << 4, e.g. after a movabs of the table address
+        if let (Binopcode::Add, Value::Reg(regnum1, size1), Value::Reg(regnum2, size2)) = (opcode, src1, src2) {
+            return match (in_state.regs.get(regnum1, size1).v, in_state.regs.get(regnum2, size2).v) {
+                (Some(CallCheckValue::GuestTableBase), Some(CallCheckValue::PtrOffset(DAV::Checked)))
+                | (Some(CallCheckValue::PtrOffset(DAV::Checked)), Some(CallCheckValue::GuestTableBase)) => {
+                    CallCheckValueLattice::new(CallCheckValue::TableEntryPtr)
+                }
+                (Some(CallCheckValue::GuestTableBase), _) | (_, Some(CallCheckValue::GuestTableBase)) => {
+                    CallCheckValueLattice::new(CallCheckValue::UncheckedTableEntryPtr)
+                }
+                _ => Default::default(),
+            };
+        }
         if let Binopcode::Shl = opcode {
             if let (Value::Reg(regnum1, size1), Value::Imm(_, _, 4)) = (src1, src2) {
                 if let Some(CallCheckValue::CheckedVal) = in_state.regs.get(regnum1, size1).v {
@@ -362,3 +560,268 @@ impl CallAnalyzer {
         Default::default()
     }
 }
+
+#[test]
+fn call_analyzer_shl_test() {
+    use crate::utils::ir::ImmType;
+    use std::collections::HashMap;
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let analyzer = CallAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: HashMap::new(),
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata, VW_CFG::new(0), IRMap::new()),
+        withheld_refinements: AtomicUsize::new(0),
+    };
+    let mut state: CallCheckLattice = Default::default();
+    state.regs.rax = CallCheckValueLattice::new(CallCheckValue::CheckedVal);
+    let rax = Value::Reg(0, ValSize::Size64);
+    let shift = |amount| Value::Imm(ImmType::Unsigned, ValSize::Size8, amount);
+    let loc_idx = LocIdx { addr: 0, idx: 0 };
+
+    // only a shift by 4 turns a checked index into a table offset; the shl half of a
+    // `shl r, 32; shr r, 32` truncation must not
+    assert_eq!(
+        analyzer.aeval_binop(&state, &Binopcode::Shl, &rax, &shift(4), &loc_idx),
+        CallCheckValueLattice::new(CallCheckValue::PtrOffset(DAV::Checked))
+    );
+    assert_eq!(
+        analyzer.aeval_binop(&state, &Binopcode::Shl, &rax, &shift(32), &loc_idx),
+        CallCheckValueLattice::default()
+    );
+    assert_eq!(
+        analyzer.aeval_binop(&state, &Binopcode::Shr, &rax, &shift(32), &loc_idx),
+        CallCheckValueLattice::default()
+    );
+}
+
+#[test]
+fn call_analyzer_flag_clobber_test() {
+    use crate::utils::ir::ImmType;
+    use std::collections::HashMap;
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let mut reaching_defs = HashMap::new();
+    reaching_defs.insert(0, ReachLattice::default());
+    let rax = Value::Reg(0, ValSize::Size64);
+    let rcx = Value::Reg(1, ValSize::Size64);
+    let rdx = Value::Reg(2, ValSize::Size64);
+    let cmp = Stmt::Binop(Binopcode::Cmp, rcx.clone(), rcx.clone(), rax.clone());
+    let jb = Stmt::Branch(Opcode::JB, Value::Imm(ImmType::Signed, ValSize::Size64, 0x20));
+    let mut state: CallCheckLattice = Default::default();
+    state.regs.rax = CallCheckValueLattice::new(CallCheckValue::TableSize);
+
+    // returns the value of rcx on the taken branch, and whether the refinement was withheld
+    let run = |block: IRBlock| {
+        let mut irmap = IRMap::new();
+        irmap.insert(0, block.clone());
+        let analyzer = CallAnalyzer {
+            metadata: metadata.clone(),
+            reaching_defs: reaching_defs.clone(),
+            reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), VW_CFG::new(0), irmap.clone()),
+            withheld_refinements: AtomicUsize::new(0),
+        };
+        let out_state = analyzer.analyze_block(&state, &block);
+        let succs = analyzer.process_branch(&irmap, &out_state, &vec![0x10, 0x20], &0);
+        (succs[1].1.regs.rcx.clone(), analyzer.withheld_refinements.load(Ordering::Relaxed) == 1)
+    };
+    let checked = CallCheckValueLattice::new(CallCheckValue::CheckedVal);
+
+    // cmp rcx, rax; mov rdx, rcx; lea rdx, [rdx + 8]; jb
+    let block = vec![
+        (0, vec![cmp.clone()]),
+        (3, vec![Stmt::Unop(Unopcode::Mov, rdx.clone(), rcx.clone())]),
+        (6, vec![Stmt::Binop(Binopcode::Add, rdx.clone(), rdx.clone(), crate::utils::ir::mk_value_i64(8))]),
+        (10, vec![jb.clone()]),
+    ];
+    assert_eq!(run(block), (checked, false));
+
+    // an instruction the lifter doesn't know the flag effects of
+    let block = vec![(0, vec![cmp.clone()]), (3, vec![Stmt::Undefined]), (6, vec![jb.clone()])];
+    assert_eq!(run(block), (CallCheckValueLattice::default(), true));
+
+    // a clear without a zf clear, and a test that doesn't reset the CheckFlag
+    let block = vec![(0, vec![cmp.clone()]), (3, vec![Stmt::Clear(rdx.clone(), vec![])]), (6, vec![jb.clone()])];
+    assert_eq!(run(block), (CallCheckValueLattice::default(), true));
+    let block = vec![(0, vec![cmp]), (3, vec![Stmt::Binop(Binopcode::Test, rdx.clone(), rdx.clone(), rdx)]), (6, vec![jb])];
+    assert_eq!(run(block), (CallCheckValueLattice::default(), true));
+}
+
+#[test]
+fn call_analyzer_value_numbering_test() {
+    use crate::utils::ir::ImmType;
+    use std::collections::HashMap;
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+    let mut metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let reg32 = |regnum: u8| Value::Reg(regnum, ValSize::Size32);
+    let reg64 = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let slot = Value::Mem(
+        ValSize::Size32,
+        MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, 0x10)),
+    );
+    let mask = Value::Imm(ImmType::Signed, ValSize::Size32, 0xffff);
+    // mov ecx, [rsp+0x10]; and ecx, 0xffff; mov edx, [rsp+0x10]; and edx, 0xffff; cmp rcx, rax; jb
+    let block = vec![
+        (0x0, vec![Stmt::Unop(Unopcode::Mov, reg32(1), slot.clone())]),
+        (0x4, vec![Stmt::Binop(Binopcode::And, reg32(1), reg32(1), mask.clone())]),
+        (0xa, vec![Stmt::Unop(Unopcode::Mov, reg32(2), slot)]),
+        (0xe, vec![Stmt::Binop(Binopcode::And, reg32(2), reg32(2), mask)]),
+        (0x14, vec![Stmt::Binop(Binopcode::Cmp, reg64(1), reg64(1), reg64(0))]),
+        (0x17, vec![Stmt::Branch(Opcode::JB, Value::Imm(ImmType::Signed, ValSize::Size64, 0x20))]),
+    ];
+    let mut irmap = IRMap::new();
+    irmap.insert(0, block.clone());
+    let mut state: CallCheckLattice = Default::default();
+    state.regs.rax = CallCheckValueLattice::new(CallCheckValue::TableSize);
+
+    // returns the value of rdx, the recomputed copy of the checked rcx, on the taken branch
+    let run = |metadata: &CompilerMetadata| {
+        let mut reaching_defs = HashMap::new();
+        reaching_defs.insert(0, ReachLattice::default());
+        let analyzer = CallAnalyzer {
+            metadata: metadata.clone(),
+            reaching_defs: reaching_defs,
+            reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), VW_CFG::new(0), irmap.clone()),
+            withheld_refinements: AtomicUsize::new(0),
+        };
+        let out_state = analyzer.analyze_block(&state, &block);
+        let succs = analyzer.process_branch(&irmap, &out_state, &vec![0x10, 0x20], &0);
+        assert_eq!(succs[1].1.regs.rcx, CallCheckValueLattice::new(CallCheckValue::CheckedVal));
+        succs[1].1.regs.rdx.clone()
+    };
+    // the two computations have different defs, so only value numbering sees rdx was checked too
+    assert_eq!(run(&metadata), CallCheckValueLattice::default());
+    metadata.options.value_numbering = true;
+    assert_eq!(run(&metadata), CallCheckValueLattice::new(CallCheckValue::CheckedVal));
+}
+
+#[test]
+fn call_analyzer_carry_flag_test() {
+    use crate::utils::lifter::lift;
+    use std::collections::HashMap;
+    use yaxpeax_arch::{Arch, Decoder};
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+    use yaxpeax_x86::long_mode::Arch as AMD64;
+
+    let metadata = |compiler: Compiler| CompilerMetadata::for_test(compiler);
+    // lifts a cmp and a conditional branch, and returns the value of rcx on the fall-through
+    // (0x10) and taken (0x20) successors
+    let run = |compiler: Compiler, state: &CallCheckLattice, cmp: &[u8], jcc: &[u8]| {
+        let metadata = metadata(compiler);
+        let decoder = <AMD64 as Arch>::Decoder::default();
+        let cmp = decoder.decode(cmp.iter().cloned()).unwrap();
+        let jcc = decoder.decode(jcc.iter().cloned()).unwrap();
+        let block = vec![
+            (0, lift(&cmp, &0, &metadata).unwrap()),
+            (4, lift(&jcc, &4, &metadata).unwrap()),
+        ];
+        let mut irmap = IRMap::new();
+        irmap.insert(0, block.clone());
+        let mut reaching_defs = HashMap::new();
+        reaching_defs.insert(0, ReachLattice::default());
+        let analyzer = CallAnalyzer {
+            metadata: metadata.clone(),
+            reaching_defs: reaching_defs,
+            reaching_analyzer: ReachingDefnAnalyzer::new(metadata, VW_CFG::new(0), irmap.clone()),
+            withheld_refinements: AtomicUsize::new(0),
+        };
+        let out_state = analyzer.analyze_block(state, &block);
+        let succs = analyzer.process_branch(&irmap, &out_state, &vec![0x10, 0x20], &0);
+        assert_eq!(analyzer.withheld_refinements.load(Ordering::Relaxed), 0);
+        (succs[0].1.regs.rcx.v.clone(), succs[1].1.regs.rcx.v.clone())
+    };
+    let cmp_rcx_rax: &[u8] = &[0x48, 0x39, 0xc1];
+    let cmp_rax_rcx: &[u8] = &[0x48, 0x39, 0xc8];
+    let jb: &[u8] = &[0x72, 0x1a];
+    let jae: &[u8] = &[0x73, 0x1a];
+    let ja: &[u8] = &[0x77, 0x1a];
+    let jbe: &[u8] = &[0x76, 0x1a];
+    let jz: &[u8] = &[0x74, 0x1a];
+
+    // Lucet: rax holds the table size
+    let lucet = Compiler::Lucet;
+    let mut state: CallCheckLattice = Default::default();
+    state.regs.rax = CallCheckValueLattice::new(CallCheckValue::TableSize);
+    let checked = Some(CallCheckValue::CheckedVal);
+    // cmp rcx, rax; jb: checked when taken (this used to depend on the successors' layout alone)
+    assert_eq!(run(lucet, &state, cmp_rcx_rax, jb), (None, checked.clone()));
+    // cmp rcx, rax; jae: checked on the fall-through
+    assert_eq!(run(lucet, &state, cmp_rcx_rax, jae), (checked.clone(), None));
+    // cmp rax, rcx; ja and jbe: the same checks with the operands swapped
+    assert_eq!(run(lucet, &state, cmp_rax_rcx, ja), (None, checked.clone()));
+    assert_eq!(run(lucet, &state, cmp_rax_rcx, jbe), (checked, None));
+    // rcx <= table size is one entry too many, and jz checks nothing
+    assert_eq!(run(lucet, &state, cmp_rcx_rax, jbe), (None, None));
+    assert_eq!(run(lucet, &state, cmp_rcx_rax, jz), (None, None));
+
+    // WAMR: cmp ecx, 7, whose bound the call checker holds against the table size
+    let wamr = Compiler::Wamr;
+    let state: CallCheckLattice = Default::default();
+    let cmp_ecx_7: &[u8] = &[0x83, 0xf9, 0x07];
+    let checked = Some(CallCheckValue::WamrChecked(7));
+    assert_eq!(run(wamr, &state, cmp_ecx_7, ja), (checked.clone(), None));
+    assert_eq!(run(wamr, &state, cmp_ecx_7, jb), (None, checked.clone()));
+    assert_eq!(run(wamr, &state, cmp_ecx_7, jae), (checked, None));
+    assert_eq!(run(wamr, &state, cmp_ecx_7, jz), (None, None));
+}
+
+#[test]
+fn call_analyzer_setcc_test() {
+    use std::collections::HashMap;
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let reg64 = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    // cmp rcx, rax; setb dl; movzx edx, dl
+    let block = vec![
+        (0x0, vec![Stmt::Binop(Binopcode::Cmp, reg64(1), reg64(1), reg64(0))]),
+        (0x3, vec![Stmt::Unop(Unopcode::Set, Value::Reg(2, ValSize::Size8), Value::Reg(16, ValSize::Size8))]),
+        (0x6, vec![Stmt::Unop(Unopcode::MovZx, Value::Reg(2, ValSize::Size32), Value::Reg(2, ValSize::Size8))]),
+    ];
+    let mut irmap = IRMap::new();
+    irmap.insert(0, block.clone());
+    let analyzer = CallAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: HashMap::new(),
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata, VW_CFG::new(0), irmap),
+        withheld_refinements: AtomicUsize::new(0),
+    };
+    let mut state: CallCheckLattice = Default::default();
+    state.regs.rax = CallCheckValueLattice::new(CallCheckValue::TableSize);
+    let out_state = analyzer.analyze_block(&state, &block);
+    // the flag stays in zf for the branch, and the 0 or 1 in rdx is no flag
+    assert!(matches!(out_state.regs.zf.v, Some(CallCheckValue::CheckFlag(..))));
+    assert_eq!(out_state.regs.rdx, CallCheckValueLattice::default());
+}
+
+#[test]
+fn call_analyzer_cmov_test() {
+    use std::collections::HashMap;
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let analyzer = CallAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: HashMap::new(),
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata, VW_CFG::new(0), IRMap::new()),
+        withheld_refinements: AtomicUsize::new(0),
+    };
+    let rax = Value::Reg(0, ValSize::Size64);
+    let rcx = Value::Reg(1, ValSize::Size64);
+    let loc_idx = LocIdx { addr: 0, idx: 0 };
+    // cmovb rax, rcx
+    let run = |rax_v: Option<CallCheckValue>, rcx_v: Option<CallCheckValue>| {
+        let mut state: CallCheckLattice = Default::default();
+        state.regs.rax = CallCheckValueLattice { v: rax_v };
+        state.regs.rcx = CallCheckValueLattice { v: rcx_v };
+        analyzer.aexec(&mut state, &Stmt::Binop(Binopcode::Cmov, rax.clone(), rax.clone(), rcx.clone()), &loc_idx);
+        state.regs.rax.v
+    };
+
+    assert_eq!(run(Some(CallCheckValue::CheckedVal), Some(CallCheckValue::CheckedVal)), Some(CallCheckValue::CheckedVal));
+    assert_eq!(run(Some(CallCheckValue::CheckedVal), None), None);
+    assert_eq!(run(None, Some(CallCheckValue::CheckedVal)), None);
+}
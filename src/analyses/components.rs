@@ -0,0 +1,339 @@
+use crate::analyses::{align_succ_addrs, check_entry, check_meet, AbstractAnalyzer, AnalysisResult, ControlFlow};
+use crate::lattices::reachingdefslattice::LocIdx;
+use crate::lattices::{Lattice, VarState};
+use crate::utils::ir::IRMap;
+use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::panic;
+use std::thread;
+
+/*
+ * The worklist algorithm run over the strongly connected components of a CFG, for functions too
+ * big to analyze on one thread. A component's blocks only depend on the blocks of the components
+ * before it in the condensation, so each component is iterated to its fixed point on its own, once
+ * all of them have handed on their states to it. Components at the same depth of the condensation
+ * don't depend on each other at all, and are iterated on separate threads.
+ *
+ * The fixed point doesn't depend on the order blocks are visited in as long as the meet is
+ * commutative and associative (which --paranoid checks), so the states are the ones `iterate`
+ * reaches, however the components are scheduled.
+ */
+
+// (the block an edge leaves, the block it enters, the state it hands on)
+type Exit<State> = (u64, u64, State);
+
+/// The blocks reachable from the entry of `cfg`, grouped into strongly connected components (each
+/// in address order), in a topological order of the condensation: edges between components only
+/// go from earlier to later ones.
+pub fn components<C: ControlFlow>(cfg: &C) -> Vec<Vec<u64>> {
+    // Tarjan's algorithm, with an explicit stack of the blocks being visited and how many of
+    // their successors were visited so far
+    let mut index: HashMap<u64, usize> = HashMap::new();
+    let mut lowlink: HashMap<u64, usize> = HashMap::new();
+    let mut on_stack: HashSet<u64> = HashSet::new();
+    let mut stack: Vec<u64> = vec![];
+    let mut visiting: Vec<(u64, Vec<u64>, usize)> = vec![];
+    let mut components = vec![];
+    let enter = |addr: u64, index: &mut HashMap<u64, usize>, lowlink: &mut HashMap<u64, usize>| {
+        let n = index.len();
+        index.insert(addr, n);
+        lowlink.insert(addr, n);
+        (addr, cfg.successors(addr), 0)
+    };
+    let entry = cfg.entrypoint();
+    visiting.push(enter(entry, &mut index, &mut lowlink));
+    stack.push(entry);
+    on_stack.insert(entry);
+    while let Some(top) = visiting.len().checked_sub(1) {
+        let (addr, next) = (visiting[top].0, visiting[top].2);
+        if let Some(succ) = visiting[top].1.get(next).copied() {
+            visiting[top].2 += 1;
+            if !index.contains_key(&succ) {
+                visiting.push(enter(succ, &mut index, &mut lowlink));
+                stack.push(succ);
+                on_stack.insert(succ);
+            } else if on_stack.contains(&succ) {
+                let low = min(lowlink[&addr], index[&succ]);
+                lowlink.insert(addr, low);
+            }
+            continue;
+        }
+        visiting.pop();
+        if let Some((parent, _, _)) = visiting.last() {
+            let low = min(lowlink[parent], lowlink[&addr]);
+            lowlink.insert(*parent, low);
+        }
+        if lowlink[&addr] == index[&addr] {
+            let mut component = vec![];
+            loop {
+                let block = stack.pop().unwrap();
+                on_stack.remove(&block);
+                component.push(block);
+                if block == addr {
+                    break;
+                }
+            }
+            component.sort();
+            components.push(component);
+        }
+    }
+    // Tarjan's algorithm finds a component after every component it reaches
+    components.reverse();
+    components
+}
+
+/// `iterate` by components (see above), splitting a depth of the condensation across threads if
+/// its components have at least `min_parallel_blocks` blocks between them.
+pub fn iterate_components<C, T, State>(cfg: &C, irmap: &IRMap, analyzer: &T, min_parallel_blocks: usize) -> AnalysisResult<State>
+where
+    C: ControlFlow + Sync,
+    T: AbstractAnalyzer<State> + Sync,
+    State: VarState + Lattice + Clone + Send,
+{
+    let components = components(cfg);
+    let mut component_of: HashMap<u64, usize> = HashMap::new();
+    for (i, component) in components.iter().enumerate() {
+        for addr in component.iter() {
+            component_of.insert(*addr, i);
+        }
+    }
+    // the depth of each component: one more than the deepest component with an edge to it
+    let mut depths = vec![0; components.len()];
+    for (i, component) in components.iter().enumerate() {
+        for addr in component.iter() {
+            for succ in cfg.successors(*addr) {
+                let j = component_of[&succ];
+                if j != i {
+                    depths[j] = max(depths[j], depths[i] + 1);
+                }
+            }
+        }
+    }
+    let mut levels: Vec<Vec<usize>> = vec![vec![]; depths.iter().max().map_or(0, |depth| depth + 1)];
+    for (i, depth) in depths.iter().enumerate() {
+        levels[*depth].push(i);
+    }
+
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let entry = cfg.entrypoint();
+    let entry_state = analyzer.init_state();
+    // the states blocks of components still to be iterated are entered with
+    let mut entries: HashMap<u64, State> = HashMap::new();
+    entries.insert(entry, entry_state.clone());
+    let mut statemap: AnalysisResult<State> = HashMap::new();
+    for level in levels {
+        // a component nothing hands a state on to is never reached
+        let jobs: Vec<(&[u64], HashMap<u64, State>)> = level
+            .iter()
+            .map(|i| {
+                let component = &components[*i];
+                (component.as_slice(), component.iter().filter_map(|addr| entries.remove(addr).map(|state| (*addr, state))).collect())
+            })
+            .filter(|(_, entered)| !entered.is_empty())
+            .collect();
+        let blocks: usize = jobs.iter().map(|(component, _)| component.len()).sum();
+        let component_of = &component_of;
+        let results: Vec<(HashMap<u64, State>, Vec<Exit<State>>)> = if jobs.len() > 1 && threads > 1 && blocks >= min_parallel_blocks {
+            let mut shares: Vec<Vec<(&[u64], HashMap<u64, State>)>> = (0..threads.min(jobs.len())).map(|_| vec![]).collect();
+            let share_count = shares.len();
+            for (i, job) in jobs.into_iter().enumerate() {
+                shares[i % share_count].push(job);
+            }
+            thread::scope(|scope| {
+                let handles: Vec<_> = shares
+                    .into_iter()
+                    .map(|share| {
+                        scope.spawn(move || {
+                            share
+                                .into_iter()
+                                .map(|(component, entered)| iterate_component(cfg, irmap, analyzer, component, component_of, entered))
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                    .collect();
+                // a panic on a worker (a meet error) is the panic of the whole run
+                handles.into_iter().flat_map(|handle| handle.join().unwrap_or_else(|e| panic::resume_unwind(e))).collect()
+            })
+        } else {
+            jobs.into_iter().map(|(component, entered)| iterate_component(cfg, irmap, analyzer, component, component_of, entered)).collect()
+        };
+        for (states, exits) in results {
+            statemap.extend(states);
+            for (addr, succ_addr, branch_state) in exits {
+                let loc = LocIdx { addr: addr, idx: 0 };
+                let merged = match entries.get(&succ_addr) {
+                    Some(old_state) => {
+                        if analyzer.paranoid() {
+                            check_meet(old_state, &branch_state, None, &loc, succ_addr);
+                        }
+                        old_state.meet(&branch_state, &loc)
+                    }
+                    None => branch_state,
+                };
+                entries.insert(succ_addr, merged);
+            }
+        }
+    }
+    if analyzer.paranoid() {
+        check_entry(&entry_state, statemap.get(&entry).unwrap(), entry);
+    }
+    statemap
+}
+
+// Iterates the blocks of `component` to a fixed point from the states they were `entered` with,
+// returning their states and the last state each edge out of the component handed on.
+fn iterate_component<C, T, State>(
+    cfg: &C,
+    irmap: &IRMap,
+    analyzer: &T,
+    component: &[u64],
+    component_of: &HashMap<u64, usize>,
+    entered: HashMap<u64, State>,
+) -> (HashMap<u64, State>, Vec<Exit<State>>)
+where
+    C: ControlFlow,
+    T: AbstractAnalyzer<State>,
+    State: VarState + Lattice + Clone,
+{
+    let this = component_of[&component[0]];
+    let mut worklist: VecDeque<u64> = component.iter().copied().filter(|addr| entered.contains_key(addr)).collect();
+    let mut statemap = entered;
+    let mut last_met: HashMap<u64, State> = HashMap::new();
+    // a block is last analyzed once its state stopped changing, so the last state an edge hands
+    // on is the one it hands on at the fixed point
+    let mut exits: HashMap<(u64, u64), State> = HashMap::new();
+    while let Some(addr) = worklist.pop_front() {
+        let irblock = irmap.get(&addr).unwrap();
+        let new_state = analyzer.analyze_block(statemap.get(&addr).unwrap(), &irblock);
+        let succ_addrs = align_succ_addrs(addr, cfg.successors(addr));
+        for (succ_addr, branch_state) in analyzer.process_branch(irmap, &new_state, &succ_addrs, &addr) {
+            if component_of.get(&succ_addr) != Some(&this) {
+                exits.insert((addr, succ_addr), branch_state);
+                continue;
+            }
+            let has_change = match statemap.get(&succ_addr) {
+                Some(old_state) => {
+                    let loc = LocIdx { addr: addr, idx: 0 };
+                    let merged_state = old_state.meet(&branch_state, &loc);
+                    if analyzer.paranoid() {
+                        check_meet(old_state, &branch_state, last_met.get(&succ_addr), &loc, succ_addr);
+                        last_met.insert(succ_addr, branch_state);
+                    }
+                    if merged_state > *old_state {
+                        log::error!("{:?} {:?}", merged_state, old_state);
+                        panic!("Meet monoticity error");
+                    }
+                    let has_change = *old_state != merged_state;
+                    statemap.insert(succ_addr, merged_state);
+                    has_change
+                }
+                None => {
+                    statemap.insert(succ_addr, branch_state);
+                    true
+                }
+            };
+            if has_change && !worklist.contains(&succ_addr) {
+                worklist.push_back(succ_addr);
+            }
+        }
+    }
+    let mut exits: Vec<Exit<State>> = exits.into_iter().map(|((addr, succ_addr), state)| (addr, succ_addr, state)).collect();
+    // meet them in a fixed order
+    exits.sort_by_key(|(addr, succ_addr, _)| (*addr, *succ_addr));
+    (statemap, exits)
+}
+
+#[test]
+fn worklist_components_test() {
+    use crate::analyses::iterate;
+    use crate::utils::compiler::Compiler;
+    use crate::utils::ir::{Binopcode, Stmt, Value};
+    use std::cmp::Ordering;
+
+    // sets of facts, met by intersecting them
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    struct Facts(u64);
+    impl PartialOrd for Facts {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            match (self.0 & other.0 == self.0, self.0 & other.0 == other.0) {
+                (true, true) => Some(Ordering::Equal),
+                (true, false) => Some(Ordering::Less),
+                (false, true) => Some(Ordering::Greater),
+                (false, false) => None,
+            }
+        }
+    }
+    impl Lattice for Facts {
+        fn meet(&self, other: &Self, _loc: &LocIdx) -> Self {
+            Facts(self.0 & other.0)
+        }
+    }
+    impl VarState for Facts {
+        type Var = ();
+        fn get(&mut self, _index: &Value) -> Option<()> { None }
+        fn set(&mut self, _index: &Value, _v: ()) {}
+        fn set_to_bot(&mut self, _index: &Value) {}
+        fn on_call(&mut self, _compiler: Compiler) {}
+        fn adjust_stack_offset(&mut self, _opcode: &Binopcode, _dst: &Value, _src1: &Value, _src2: &Value) {}
+    }
+    // each block kills some facts and generates others, and each edge generates some more
+    struct GenKill(u64);
+    impl GenKill {
+        fn mask(&self, a: u64, b: u64) -> u64 {
+            let mut x = self.0 ^ a.wrapping_mul(0x9e3779b97f4a7c15) ^ b.wrapping_mul(0xc2b2ae3d27d4eb4f);
+            x ^= x >> 29;
+            x.wrapping_mul(0xbf58476d1ce4e5b9) & 0xffff
+        }
+    }
+    impl AbstractAnalyzer<Facts> for GenKill {
+        fn init_state(&self) -> Facts {
+            Facts(0xffff)
+        }
+        fn process_branch(&self, _irmap: &IRMap, in_state: &Facts, succ_addrs: &Vec<u64>, addr: &u64) -> Vec<(u64, Facts)> {
+            let out = (in_state.0 & !self.mask(*addr, 1)) | (self.mask(*addr, 2) & self.mask(*addr, 3));
+            succ_addrs.iter().map(|succ| (*succ, Facts(out | (self.mask(*addr, *succ) & self.mask(*succ, 4))))).collect()
+        }
+    }
+    struct Random(HashMap<u64, Vec<u64>>);
+    impl ControlFlow for Random {
+        fn entrypoint(&self) -> u64 { 0 }
+        fn successors(&self, addr: u64) -> Vec<u64> { self.0[&addr].clone() }
+    }
+
+    let mut seed: u64 = 0x853c49e6748fea9b;
+    let mut next = |bound: u64| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed % bound
+    };
+    for round in 0..200 {
+        let blocks = 1 + next(24);
+        let mut succs = HashMap::new();
+        let mut irmap = IRMap::new();
+        for i in 0..blocks {
+            let count = next(3);
+            let mut targets: Vec<u64> = (0..count).map(|_| next(blocks) * 0x10).collect();
+            targets.dedup();
+            succs.insert(i * 0x10, targets);
+            irmap.insert(i * 0x10, vec![(i * 0x10, vec![Stmt::Ret])]);
+        }
+        let cfg = Random(succs);
+        let analyzer = GenKill(round);
+
+        // components come after every component with an edge to them
+        let components = components(&cfg);
+        let position: HashMap<u64, usize> =
+            components.iter().enumerate().flat_map(|(i, c)| c.iter().map(move |addr| (*addr, i))).collect();
+        for (addr, targets) in cfg.0.iter().filter(|(addr, _)| position.contains_key(addr)) {
+            for target in targets {
+                assert!(position[addr] <= position[target]);
+            }
+        }
+
+        let sequential = iterate(&cfg, &irmap, &analyzer, None, None);
+        assert_eq!(sequential.len(), position.len());
+        assert_eq!(iterate_components(&cfg, &irmap, &analyzer, 0), sequential, "round {}", round);
+        assert_eq!(iterate_components(&cfg, &irmap, &analyzer, usize::MAX), sequential, "round {}", round);
+    }
+}
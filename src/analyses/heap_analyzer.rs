@@ -1,14 +1,16 @@
 use crate::analyses::AbstractAnalyzer;
-use crate::utils::ir_utils::{extract_stack_offset, is_stack_access};
+use crate::utils::ir_utils::{branch_opcode, extract_stack_offset, flags_reach_branch, imm_to_addr, in_bounds_side, is_stack_access};
 use crate::lattices::heaplattice::{HeapLattice, HeapValue, HeapValueLattice};
 use crate::lattices::heaplattice::{WAMR_MODULEINSTANCE_OFFSET, 
                                    WAMR_STACKLIMIT_OFFSET,
-                                   WAMR_HEAPBASE_OFFSET, 
                                    WAMR_FUNCPTRS_OFFSET, WAMR_FUNCTYPE_OFFSET};
 use crate::lattices::reachingdefslattice::LocIdx;
-use crate::lattices::VarState;
-use crate::utils::lifter::{MemArg, MemArgs, ValSize, Value, Binopcode};
-use crate::utils::utils::{CompilerMetadata, Compiler};
+use crate::lattices::{Lattice, VarState};
+use crate::utils::ir::{dividend, Extension, IRBlock, IRMap, MemArg, MemArgs, ValSize, Value, Binopcode, Stmt, Unopcode};
+use crate::utils::compiler::{AnalysisOptions, CompilerMetadata, Compiler};
+use crate::utils::compiler_model::LucetModel;
+use crate::utils::wasmtime_layout::{WASMTIME_ANYFUNC_VMCTX_OFFSET, WASMTIME_TABLE_ENTRY_SIZE};
+use crate::utils::wamr_layout::{WamrTables, WAMR_BOUND_WIDTHS};
 use std::default::Default;
 
 pub struct HeapAnalyzer {
@@ -18,10 +20,7 @@ pub struct HeapAnalyzer {
 impl AbstractAnalyzer<HeapLattice> for HeapAnalyzer {
     fn init_state(&self) -> HeapLattice {
         let mut result: HeapLattice = Default::default();
-        match self.metadata.compiler {
-            Compiler::Lucet => result.regs.rdi = HeapValueLattice::new(HeapValue::HeapBase),
-            Compiler::Wamr => result.regs.rdi = HeapValueLattice::new(HeapValue::WamrExecEnv),
-        }
+        self.metadata.model.init_heap_state(&mut result);
         result
     }
 
@@ -29,6 +28,109 @@ impl AbstractAnalyzer<HeapLattice> for HeapAnalyzer {
         self.metadata.compiler
     }
 
+    fn paranoid(&self) -> bool {
+        self.metadata.options.paranoid
+    }
+
+    fn options(&self) -> AnalysisOptions {
+        self.metadata.options
+    }
+
+    fn on_call(&self, in_state: &mut HeapLattice) -> () {
+        in_state.on_call(self.metadata.compiler);
+        in_state.forget_caller_window(self.metadata.caller_window);
+        // the callee may not be the one we expect, so bounds have to be re-established after it
+        if self.metadata.strict_rebound {
+            in_state.forget(|v| matches!(
+                v.v,
+                Some(HeapValue::Bounded(_)) | Some(HeapValue::CheckedHeapOffset(_)) | Some(HeapValue::CheckedTableIndex) | Some(HeapValue::CheckedTableOffset)
+            ));
+        }
+    }
+
+    // A Wamr bounds check (see wamr_bound_check) or a Lucet table size check (see
+    // lucet_table_check) marks the compared register on the side of the branch it is in bounds
+    // on. The check only lives in zf until the branch ending its block.
+    fn process_branch(
+        &self,
+        irmap: &IRMap,
+        in_state: &HeapLattice,
+        succ_addrs: &Vec<u64>,
+        addr: &u64,
+    ) -> Vec<(u64, HeapLattice)> {
+        let mut state = in_state.clone();
+        let check = state.regs.zf.v;
+        state.regs.zf = Default::default();
+        let compared = match check {
+            Some(HeapValue::WamrMemBoundCheck(regnum, index_first, _, cmp_loc)) |
+            Some(HeapValue::TableSizeCheck(regnum, index_first, cmp_loc)) => Some((regnum, index_first, cmp_loc)),
+            _ => None,
+        };
+        if let (Some((regnum, index_first, cmp_loc)), 2) = (compared, succ_addrs.len()) {
+            let ir_block = irmap.get(addr).unwrap();
+            let side = if flags_reach_branch(&ir_block, &cmp_loc) && !written_after(&ir_block, &cmp_loc, regnum) {
+                branch_opcode(&ir_block).and_then(|opcode| in_bounds_side(&opcode, index_first))
+            } else {
+                None
+            };
+            let refinement = side.and_then(|(taken, strict)| check.and_then(|check| checked_value(check, strict)).map(|v| (taken, v)));
+            if let Some((taken, v)) = refinement {
+                let mut checked = state.clone();
+                checked.regs.set(&regnum, &ValSize::Size64, HeapValueLattice::new(v));
+                // align_succ_addrs puts the fall-through successor first and the branch target second
+                return if taken {
+                    vec![(succ_addrs[0], state), (succ_addrs[1], checked)]
+                } else {
+                    vec![(succ_addrs[0], checked), (succ_addrs[1], state)]
+                };
+            }
+        }
+        succ_addrs.iter().map(|succ| (*succ, state.clone())).collect()
+    }
+
+    fn aexec(&self, in_state: &mut HeapLattice, ir_instr: &Stmt, loc_idx: &LocIdx) -> () {
+        match ir_instr {
+            // a SETcc writes 0 or 1 (to the low byte, which like other 8-bit writes is taken to
+            // bound the whole register)
+            Stmt::Unop(Unopcode::Set, dst, _) if self.metadata.options.bound_8bit => {
+                in_state.set(dst, HeapValueLattice::new(HeapValue::Bounded(2)));
+                self.untrack_stack(in_state, dst)
+            }
+            Stmt::Clear(dst, _srcs) => in_state.set_to_bot(dst),
+            Stmt::Unop(_, dst, src) => {
+                match ir_instr.mov_kind().map(|kind| (kind.extension, kind.src_bits)) {
+                    // a zero extension is below 2^(source width) whatever the source holds
+                    Some((Extension::Zero, src_bits)) => {
+                        let mut bound = 1u64 << src_bits.min(63);
+                        if let Some(src_bound) = self.aeval_unop(in_state, src).v.and_then(|v| v.bound()) {
+                            bound = bound.min(src_bound);
+                        }
+                        let v = self.narrow_to_dst(dst, HeapValueLattice::new(HeapValue::Bounded(bound)));
+                        in_state.set(dst, v);
+                    }
+                    // a sign extension only keeps a bound that leaves the source's sign bit clear
+                    Some((Extension::Sign, src_bits)) => {
+                        let mut v = self.aeval_unop(in_state, src);
+                        if !v.v.map_or(false, |v| v.bounded_by(1u64 << (src_bits - 1))) {
+                            v = Default::default();
+                        }
+                        let v = self.narrow_to_dst(dst, v);
+                        in_state.set(dst, v);
+                    }
+                    _ => self.aexec_unop(in_state, &dst, &src, loc_idx),
+                }
+                self.untrack_stack(in_state, dst)
+            }
+            Stmt::Binop(opcode, dst, src1, src2) => {
+                self.aexec_binop(in_state, opcode, dst, src1, src2, loc_idx);
+                self.untrack_stack(in_state, dst);
+                in_state.adjust_stack_offset(opcode, dst, src1, src2)
+            }
+            Stmt::Call(_, _) => self.on_call(in_state),
+            _ => (),
+        }
+    }
+
     fn aexec_unop(
         &self,
         in_state: &mut HeapLattice,
@@ -36,47 +138,128 @@ impl AbstractAnalyzer<HeapLattice> for HeapAnalyzer {
         src: &Value,
         _loc_idx: &LocIdx,
     ) -> () {
-        let mut v: HeapValueLattice = self.aeval_unop(in_state, src);
-        match dst {
-            // in x86, mov'ing to a smaller register clears the upper bits of the corresponding
-            // 64b register. We need to communicate this state to enable checking of future
-            // accesses that use the 64b register (for Wamr).
-            Value::Reg(_, ValSize::Size32) | 
-            Value::Reg(_, ValSize::Size16) => {
-                if v == HeapValueLattice::default() {
-                    v = HeapValueLattice::new(HeapValue::Bounded4GB);
-                }
-            },
-            Value::Reg(_, ValSize::Size8) => {
-                if v == HeapValueLattice::default() {
-                    v = HeapValueLattice::new(HeapValue::Bounded256B);
-                } else if let Some(HeapValue::Bounded4GB) = v.v {
-                    v = HeapValueLattice::new(HeapValue::Bounded256B);
-                }
-            },
-            _ => (),
-        }
+        let v = self.narrow_to_dst(dst, self.aeval_unop(in_state, src));
         in_state.set(dst, v)
     }
 
     fn aexec_binop(
         &self,
         in_state: &mut HeapLattice,
-        _opcode: &Binopcode,
+        opcode: &Binopcode,
         dst: &Value,
-        _src1: &Value,
-        _src2: &Value,
-        _loc_idx: &LocIdx,
+        src1: &Value,
+        src2: &Value,
+        loc_idx: &LocIdx,
     ) -> () {
+        if let Compiler::Lucet = self.metadata.compiler {
+            if let Some(v) = self.lucet_table_binop(in_state, opcode, src1, src2) {
+                in_state.set(dst, v);
+                return;
+            }
+        }
+        if let Binopcode::Cmp | Binopcode::Test = opcode {
+            in_state.regs.zf = match self.metadata.compiler {
+                Compiler::Lucet => self.lucet_table_check(in_state, opcode, src1, src2, loc_idx),
+                _ => self.wamr_bound_check(in_state, opcode, src1, src2, loc_idx),
+            };
+        } else if let Value::Reg(regnum, ValSize::Size64) = dst {
+            // the offset was checked, not whatever it is made into (the rules below keep the
+            // value of a 64b register for arithmetic they know nothing about)
+            match in_state.regs.get(regnum, &ValSize::Size64).v {
+                Some(HeapValue::CheckedHeapOffset(_)) |
+                Some(HeapValue::CheckedTableIndex) |
+                Some(HeapValue::CheckedTableOffset) |
+                Some(HeapValue::GuestTable0Entry) => in_state.set_to_bot(dst),
+                _ => (),
+            }
+        }
+        // a conditional move leaves whichever of its sources the flags pick, so what both hold
+        if let Binopcode::Cmov = opcode {
+            let v = self.aeval_unop(in_state, src1).meet(&self.aeval_unop(in_state, src2), loc_idx);
+            let v = self.narrow_to_dst(dst, v);
+            in_state.set(dst, v);
+            return;
+        }
+        if let Compiler::Lucet = self.metadata.compiler {
+            if let Some(v) = self.lucet_globals_ptr_binop(in_state, opcode, src1, src2) {
+                in_state.set(dst, v);
+                return;
+            }
+        }
+        if let Binopcode::Div | Binopcode::Idiv = opcode {
+            let v = match self.div_bound(in_state, opcode, dst, src1, src2) {
+                Some(bound) => HeapValueLattice::new(HeapValue::Bounded(bound)),
+                None => Default::default(),
+            };
+            let v = self.narrow_to_dst(dst, v);
+            in_state.set(dst, v);
+            return;
+        }
+        if let Some(bound) = self.bounded_binop(in_state, opcode, src1, src2) {
+            in_state.set(dst, HeapValueLattice::new(HeapValue::Bounded(bound)));
+            return;
+        }
+        // a compare or test writes nothing, so a tighter bound on a 32-bit operand stands
+        if let (Binopcode::Cmp, Value::Reg(regnum, ValSize::Size32)) | (Binopcode::Test, Value::Reg(regnum, ValSize::Size32)) = (opcode, dst) {
+            if in_state.regs.get(regnum, &ValSize::Size64).v.map_or(false, |v| v.bounded_by(1 << 32)) {
+                return;
+            }
+        }
+        match (opcode, dst, src2) {
+            // a logical right shift of a 64b register by at least 32 leaves at most 32 significant
+            // bits. This is how `shl r, 32; shr r, 32` truncations (i32.wrap_i64) end. x86 masks
+            // the count to 6 bits, so e.g. `shr r, 64` leaves r unchanged.
+            (Binopcode::Shr, Value::Reg(_, ValSize::Size64), Value::Imm(_, _, shift)) if (*shift & 63) >= 32 => {
+                in_state.set(dst, HeapValueLattice::new(HeapValue::Bounded4GB));
+                return;
+            },
+            // any other shift of a 64b register tells us nothing about the result
+            (Binopcode::Shl, Value::Reg(_, ValSize::Size64), _) |
+            (Binopcode::Shr, Value::Reg(_, ValSize::Size64), _) => {
+                in_state.set_to_bot(dst);
+                return;
+            },
+            _ => (),
+        }
         if let Value::Reg(_, ValSize::Size32) = dst {
             // in x86, mov'ing to a 32b register clears the upper 32b of the corresponding
             // 64b register. We need to communicate this state to enable checking of future
             // accesses that use the 64b register (for Wamr).
-            in_state.set(dst, HeapValueLattice::new(HeapValue::Bounded4GB));
+            if self.metadata.options.bound_32bit {
+                in_state.set(dst, HeapValueLattice::new(HeapValue::Bounded4GB));
+            } else {
+                in_state.set_to_bot(dst);
+            }
         }
     }
 }
 
+// What a register compared by `check` is known to be on the in-bounds side of the branch, if that
+// side is enough to call it checked: `strict` when it excludes the bound itself
+fn checked_value(check: HeapValue, strict: bool) -> Option<HeapValue> {
+    match check {
+        // the bound is the memory size less `width`, so an offset at most the bound leaves
+        // `width` bytes in bounds, and one below it a byte more
+        HeapValue::WamrMemBoundCheck(_, _, width, _) => {
+            Some(HeapValue::CheckedHeapOffset(if strict { width + 1 } else { width }))
+        }
+        // a table index has to be below the table size itself
+        HeapValue::TableSizeCheck(_, _, _) if strict => Some(HeapValue::CheckedTableIndex),
+        _ => None,
+    }
+}
+
+// Whether an instruction after the cmp at `cmp_loc` in `ir_block` writes register `regnum`, so
+// that what the cmp checked isn't what the register holds at the branch
+fn written_after(ir_block: &IRBlock, cmp_loc: &LocIdx, regnum: u8) -> bool {
+    ir_block.iter().filter(|(addr, _)| *addr > cmp_loc.addr).any(|(_, ir_stmts)| {
+        ir_stmts.iter().any(|stmt| match stmt {
+            Stmt::Clear(Value::Reg(dst, _), _) | Stmt::Unop(_, Value::Reg(dst, _), _) | Stmt::Binop(_, Value::Reg(dst, _), _, _) => *dst == regnum,
+            _ => false,
+        })
+    })
+}
+
 pub fn lucet_is_globalbase_access(in_state: &HeapLattice, memargs: &MemArgs) -> bool {
     if let MemArgs::Mem2Args(arg1, _arg2) = memargs {
         if let MemArg::Reg(regnum, size) = arg1 {
@@ -92,6 +275,14 @@ pub fn lucet_is_globalbase_access(in_state: &HeapLattice, memargs: &MemArgs) ->
     false
 }
 
+// mem[lucet_tables + 8], the size of guest_table_0
+pub fn lucet_is_tablesize_access(in_state: &HeapLattice, memargs: &MemArgs) -> bool {
+    if let MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, 8)) = memargs {
+        return in_state.regs.get(regnum, &ValSize::Size64).v == Some(HeapValue::LucetTables);
+    }
+    false
+}
+
 /*
  * Helper function to check for accesses of the form mem[base_val + offset]
  */
@@ -138,14 +329,12 @@ pub fn wamr_is_stacklimit_access(in_state: &HeapLattice, memargs: &MemArgs) -> b
 }
 
 /*
- * Checks if a memory access is to Wamr's heap base pointer within the current AOTModuleInstance.
- *  The access must be of the form mem[WamrModuleInstance + WAMR_HEAPBASE_OFFSET] 
- *  (see lattices/heaplattice.rs for more details)
+ * Checks if a memory access is to one of Wamr's heap base pointers within the current AOTModuleInstance.
+ *  The access must be of the form mem[base + offset] for one of the `heap_bases`, by default only
+ *  mem[WamrModuleInstance + WAMR_HEAPBASE_OFFSET] (see CompilerMetadata::wamr_heap_bases)
  */
-pub fn wamr_is_heapbase_access(in_state: &HeapLattice, memargs: &MemArgs) -> bool {
-    return wamr_access_helper(in_state, memargs, 
-                       HeapValue::WamrModuleInstance, 
-                       WAMR_HEAPBASE_OFFSET);
+pub fn wamr_is_heapbase_access(in_state: &HeapLattice, memargs: &MemArgs, heap_bases: &[(HeapValue, i64)]) -> bool {
+    heap_bases.iter().any(|(base_val, offset)| wamr_access_helper(in_state, memargs, *base_val, *offset))
 }
 
 /*
@@ -170,24 +359,341 @@ pub fn wamr_is_funcptrs_access(in_state: &HeapLattice, memargs: &MemArgs) -> boo
                        WAMR_FUNCPTRS_OFFSET);
 }
 
+/// With several funcref tables, the loads of a table descriptor and of its function pointer
+/// table. The call checker checks the descriptor index against the table count.
+fn wamr_table_descriptor_access(in_state: &HeapLattice, memsize: &ValSize, memargs: &MemArgs, tables: &WamrTables) -> Option<HeapValue> {
+    let width = (memsize.to_u32() / 8) as i64;
+    match memargs {
+        MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, offset)) => {
+            match in_state.regs.get(regnum, &ValSize::Size64).v {
+                Some(HeapValue::WamrModuleInstance) if tables.descriptor_at(*offset, width).is_some() => {
+                    Some(HeapValue::WamrTableDescriptor)
+                }
+                Some(HeapValue::WamrTableDescriptor) if *offset == tables.base_field && width == 8 => {
+                    Some(HeapValue::WamrFuncPtrsTable)
+                }
+                _ => None,
+            }
+        }
+        MemArgs::MemScaleDisp(MemArg::Reg(regnum, ValSize::Size64), MemArg::Reg(_, _),
+                              MemArg::Imm(_, _, 8), MemArg::Imm(_, _, disp)) => {
+            match in_state.regs.get(regnum, &ValSize::Size64).v {
+                Some(HeapValue::WamrModuleInstance) if *disp == tables.descriptors && width == 8 => {
+                    Some(HeapValue::WamrTableDescriptor)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn globals_offset(v: &HeapValueLattice) -> Option<i64> {
+    match v.v {
+        Some(HeapValue::GlobalsBase) => Some(0),
+        Some(HeapValue::GlobalsPtr(offset)) => Some(offset),
+        _ => None,
+    }
+}
+
 impl HeapAnalyzer {
+    /// The value `v` written to `dst` leaves in the whole register: a 32, 16 or 8-bit write
+    /// clears or keeps the upper bits, which bounds the register (within the options).
+    fn narrow_to_dst(&self, dst: &Value, mut v: HeapValueLattice) -> HeapValueLattice {
+        match dst {
+            // in x86, mov'ing to a smaller register clears the upper bits of the corresponding
+            // 64b register. We need to communicate this state to enable checking of future
+            // accesses that use the 64b register (for Wamr).
+            Value::Reg(_, ValSize::Size32) if self.metadata.options.bound_32bit => {
+                if v == HeapValueLattice::default() {
+                    v = HeapValueLattice::new(HeapValue::Bounded4GB);
+                }
+            },
+            Value::Reg(_, ValSize::Size16) if self.metadata.options.bound_32bit => {
+                if v == HeapValueLattice::default() {
+                    v = HeapValueLattice::new(HeapValue::Bounded(1 << 16));
+                } else if let Some(bound) = v.v.and_then(|v| v.bound()) {
+                    v = HeapValueLattice::new(HeapValue::Bounded(bound.min(1 << 16)));
+                }
+            },
+            Value::Reg(_, ValSize::Size8) if self.metadata.options.bound_8bit => {
+                if v == HeapValueLattice::default() {
+                    v = HeapValueLattice::new(HeapValue::Bounded256B);
+                } else if let Some(bound) = v.v.and_then(|v| v.bound()) {
+                    v = HeapValueLattice::new(HeapValue::Bounded(bound.min(256)));
+                }
+            },
+            _ => (),
+        }
+        v
+    }
+
+    /*
+     * The flag a cmp (or test) leaves in zf: a Wamr bounds check when it compares a 64b register
+     * with the memory bound, in a register or straight from the ModuleInstance, and nothing
+     * otherwise. WAMR without guard pages checks every heap offset this way before the access,
+     * and traps when it is above the bound.
+     */
+    fn wamr_bound_check(&self, in_state: &mut HeapLattice, opcode: &Binopcode, src1: &Value, src2: &Value, loc_idx: &LocIdx) -> HeapValueLattice {
+        if let Binopcode::Test = opcode {
+            return Default::default();
+        }
+        let bound_width = |analyzer: &Self, in_state: &mut HeapLattice, value: &Value| match analyzer.aeval_unop(in_state, value).v {
+            Some(HeapValue::WamrMemBound(width)) => Some(width),
+            _ => None,
+        };
+        if let Value::Reg(regnum, ValSize::Size64) = src1 {
+            if let Some(width) = bound_width(self, in_state, src2) {
+                return HeapValueLattice::new(HeapValue::WamrMemBoundCheck(*regnum, true, width, *loc_idx));
+            }
+        }
+        if let Value::Reg(regnum, ValSize::Size64) = src2 {
+            if let Some(width) = bound_width(self, in_state, src1) {
+                return HeapValueLattice::new(HeapValue::WamrMemBoundCheck(*regnum, false, width, *loc_idx));
+            }
+        }
+        Default::default()
+    }
+
+    /*
+     * The flag a Lucet cmp leaves in zf: a table size check when it compares a register with
+     * the size of guest_table_0, and nothing otherwise. Lucet checks a call_indirect's index this
+     * way before indexing the table.
+     */
+    fn lucet_table_check(&self, in_state: &mut HeapLattice, opcode: &Binopcode, src1: &Value, src2: &Value, loc_idx: &LocIdx) -> HeapValueLattice {
+        if let Binopcode::Test = opcode {
+            return Default::default();
+        }
+        let is_table_size = |analyzer: &Self, in_state: &mut HeapLattice, value: &Value| {
+            analyzer.aeval_unop(in_state, value).v == Some(HeapValue::LucetTableSize)
+        };
+        if let Value::Reg(regnum, _) = src1 {
+            if is_table_size(self, in_state, src2) {
+                return HeapValueLattice::new(HeapValue::TableSizeCheck(*regnum, true, *loc_idx));
+            }
+        }
+        if let Value::Reg(regnum, _) = src2 {
+            if is_table_size(self, in_state, src1) {
+                return HeapValueLattice::new(HeapValue::TableSizeCheck(*regnum, false, *loc_idx));
+            }
+        }
+        Default::default()
+    }
+
+    /*
+     * Indexing guest_table_0 by hand, when its address was materialized in a register: an index
+     * checked against the table size (see process_branch) is scaled by the entry size with
+     * `shl r, 4` and added to the table address. Only that sum is a GuestTable0Entry; the table
+     * plus anything else is unknown. Returns None if neither is involved.
+     */
+    fn lucet_table_binop(
+        &self,
+        in_state: &HeapLattice,
+        opcode: &Binopcode,
+        src1: &Value,
+        src2: &Value,
+    ) -> Option<HeapValueLattice> {
+        let value_of = |v: &Value| match v {
+            Value::Reg(regnum, ValSize::Size64) => in_state.regs.get(regnum, &ValSize::Size64).v,
+            _ => None,
+        };
+        match (opcode, value_of(src1), value_of(src2), src2) {
+            (Binopcode::Shl, Some(HeapValue::CheckedTableIndex), _, Value::Imm(_, _, 4)) => {
+                Some(HeapValueLattice::new(HeapValue::CheckedTableOffset))
+            }
+            (Binopcode::Add, Some(HeapValue::GuestTable0), Some(HeapValue::CheckedTableOffset), _) |
+            (Binopcode::Add, Some(HeapValue::CheckedTableOffset), Some(HeapValue::GuestTable0), _) => {
+                Some(HeapValueLattice::new(HeapValue::GuestTable0Entry))
+            }
+            (Binopcode::Add, Some(HeapValue::GuestTable0), _, _) |
+            (Binopcode::Add, _, Some(HeapValue::GuestTable0), _) => Some(Default::default()),
+            _ => None,
+        }
+    }
+
+    /*
+     * Arithmetic on a pointer into the globals area (e.g. `lea rbx, [globals_base + 0x10]`).
+     * Adding or subtracting an immediate keeps it a GlobalsPtr as long as it stays in bounds;
+     * anything else makes it unknown. Returns None if no globals pointer is involved.
+     */
+    fn lucet_globals_ptr_binop(
+        &self,
+        in_state: &HeapLattice,
+        opcode: &Binopcode,
+        src1: &Value,
+        src2: &Value,
+    ) -> Option<HeapValueLattice> {
+        let offset_of = |v: &Value| match v {
+            Value::Reg(regnum, ValSize::Size64) => globals_offset(&in_state.regs.get(regnum, &ValSize::Size64)),
+            _ => None,
+        };
+        match (opcode, offset_of(src1), src2) {
+            (Binopcode::Add, Some(base), Value::Imm(_, _, imm)) |
+            (Binopcode::Sub, Some(base), Value::Imm(_, _, imm)) => {
+                let offset = if let Binopcode::Add = opcode { base + imm } else { base - imm };
+                if offset >= 0 && offset < LucetModel.globals_size(&self.metadata) {
+                    Some(HeapValueLattice::new(HeapValue::GlobalsPtr(offset)))
+                } else {
+                    Some(Default::default())
+                }
+            },
+            (Binopcode::Cmp, _, _) | (Binopcode::Test, _, _) => None,
+            (_, Some(_), _) => Some(Default::default()),
+            (_, None, _) => offset_of(src2).map(|_| Default::default()),
+        }
+    }
+
+    /// The bound of a non-negative immediate or a Bounded register.
+    fn bound_of(&self, in_state: &mut HeapLattice, value: &Value) -> Option<u64> {
+        match value {
+            Value::Imm(_, _, imm) if *imm >= 0 => Some(*imm as u64 + 1),
+            Value::Reg(_, _) => self.aeval_unop(in_state, value).v.and_then(|v| v.bound()),
+            _ => None,
+        }
+    }
+
+    /*
+     * Arithmetic on bounded values, e.g. turning a SETcc result into a table offset:
+     * `shl r, k` (or `lea r, [r*2^k]`) and `shr r, k` scale the bound, an add sums the bounds and
+     * `and r, imm` bounds r by the mask (or, for a negative mask, which only clears bits, keeps r's
+     * bound). Only results still within 4GB are tracked, as the lattice keeps no looser bound;
+     * anything looser, or overflowing, is left to the rules below.
+     */
+    fn bounded_binop(&self, in_state: &mut HeapLattice, opcode: &Binopcode, src1: &Value, src2: &Value) -> Option<u64> {
+        let bound = match (opcode, src2) {
+            (Binopcode::Shl, Value::Imm(_, _, shift)) if *shift >= 0 && *shift < 32 => {
+                let max = self.bound_of(in_state, src1)?.checked_sub(1)?;
+                max.checked_mul(1 << *shift)?.checked_add(1)?
+            }
+            // masking with a non-negative immediate leaves at most the mask
+            (Binopcode::And, Value::Imm(_, _, mask)) if *mask >= 0 => {
+                let mask = (*mask as u64).checked_add(1)?;
+                self.bound_of(in_state, src1).map_or(mask, |bound| bound.min(mask))
+            }
+            (Binopcode::And, Value::Imm(_, _, _)) => self.bound_of(in_state, src1)?,
+            // x86 masks the count to the operand width, so a count past it isn't the shift done
+            (Binopcode::Shr, Value::Imm(_, _, shift)) if *shift >= 0 && *shift < src1.width() as i64 * 8 => {
+                let max = self.bound_of(in_state, src1)?.checked_sub(1)?;
+                (max >> *shift) + 1
+            }
+            (Binopcode::Add, _) => {
+                let max1 = self.bound_of(in_state, src1)?.checked_sub(1)?;
+                let max2 = self.bound_of(in_state, src2)?.checked_sub(1)?;
+                max1.checked_add(max2)?.checked_add(1)?
+            }
+            _ => return None,
+        };
+        if bound <= 1 << 32 {
+            Some(bound)
+        } else {
+            None
+        }
+    }
+
+    /*
+     * The bound of an unsigned division's result (see Binopcode::Div). The quotient is no larger
+     * than the dividend when its high half is zero, as after the usual `xor edx, edx`, and the
+     * remainder is below the divisor. A signed division isn't bounded beyond its width.
+     */
+    fn div_bound(&self, in_state: &mut HeapLattice, opcode: &Binopcode, dst: &Value, src1: &Value, src2: &Value) -> Option<u64> {
+        if let Binopcode::Idiv = opcode {
+            return None;
+        }
+        let (high, _) = dividend(dst);
+        let bound = match dst {
+            Value::Reg(0, _) if self.bound_of(in_state, &high) == Some(1) => self.bound_of(in_state, src1)?,
+            Value::Reg(2, _) => self.bound_of(in_state, src2)?,
+            _ => return None,
+        };
+        if bound <= 1 << 32 {
+            Some(bound)
+        } else {
+            None
+        }
+    }
+
+    /*
+     * A register read. The low 32 bits or fewer are no larger than the whole register, except
+     * that under x32 the low 32 bits of a pointer are the pointer.
+     */
+    fn aeval_reg(&self, in_state: &mut HeapLattice, regnum: u8, size: &ValSize) -> HeapValueLattice {
+        if let ValSize::SizeOther = size {
+            return Default::default();
+        };
+        let v = in_state.regs.get(&regnum, &ValSize::Size64);
+        if size.to_u32() > 32 {
+            return v;
+        }
+        if size.to_u32() == self.metadata.abi.pointer_bits() && v.v.map_or(false, |v| v.bound().is_none()) {
+            return v;
+        }
+        let bound = v.v.and_then(|v| v.bound());
+        HeapValueLattice::new(HeapValue::Bounded(bound.map_or(1 << 32, |b| b.min(1 << 32))))
+    }
+
     pub fn aeval_unop(&self, in_state: &mut HeapLattice, value: &Value) -> HeapValueLattice {
         match self.metadata.compiler {
             Compiler::Lucet => self.lucet_aeval_unop(in_state, value),
             Compiler::Wamr => self.wamr_aeval_unop(in_state, value),
+            Compiler::Wasmtime => self.wasmtime_aeval_unop(in_state, value),
         }
     }
 
+    fn wasmtime_aeval_unop(&self, in_state: &mut HeapLattice, value: &Value) -> HeapValueLattice {
+        let layout = &self.metadata.wasmtime_layout;
+        match value {
+            Value::Mem(memsize, memargs) => {
+                match memargs {
+                    MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, offset)) => {
+                        match in_state.regs.get(regnum, &ValSize::Size64).v {
+                            Some(HeapValue::WasmtimeVMContext) if *offset == layout.heap_base => {
+                                return HeapValueLattice::new(HeapValue::HeapBase);
+                            }
+                            Some(HeapValue::WasmtimeVMContext) if *offset == layout.table_base => {
+                                return HeapValueLattice::new(HeapValue::WasmtimeTableBase);
+                            }
+                            // the callee's VMContext, for an indirect call
+                            Some(HeapValue::WasmtimeAnyfunc) if *offset == WASMTIME_ANYFUNC_VMCTX_OFFSET => {
+                                return HeapValueLattice::new(HeapValue::WasmtimeVMContext);
+                            }
+                            _ => (),
+                        }
+                    }
+                    MemArgs::MemScale(MemArg::Reg(regnum, ValSize::Size64), MemArg::Reg(_, _), MemArg::Imm(_, _, WASMTIME_TABLE_ENTRY_SIZE)) => {
+                        if let Some(HeapValue::WasmtimeTableBase) = in_state.regs.get(regnum, &ValSize::Size64).v {
+                            return HeapValueLattice::new(HeapValue::WasmtimeAnyfunc);
+                        }
+                    }
+                    _ => (),
+                }
+                if is_stack_access(value) {
+                    return match extract_stack_offset(memargs) {
+                        Some(offset) => in_state.stack.get(offset, memsize.to_u32() / 8),
+                        // an indexed stack access may read any slot
+                        None => Default::default(),
+                    };
+                }
+            }
+            Value::Reg(regnum, size) => return self.aeval_reg(in_state, *regnum, size),
+            Value::Imm(_, _, immval) => {
+                if (*immval >= 0) && (*immval < (1 << 32)) {
+                    return HeapValueLattice::new(HeapValue::Bounded(*immval as u64 + 1));
+                }
+            }
+        }
+        Default::default()
+    }
+
     fn wamr_aeval_unop(&self, in_state: &mut HeapLattice, value: &Value) -> HeapValueLattice {
         match value {
-            Value::Mem(_memsize, memargs) => {
+            Value::Mem(memsize, memargs) => {
                 if wamr_is_stacklimit_access(in_state, memargs) {
                     return HeapValueLattice::new(HeapValue::WamrStackLimit);
                 }
                 if wamr_is_moduleinstance_access(in_state, memargs) {
                     return HeapValueLattice::new(HeapValue::WamrModuleInstance);
                 }
-                if wamr_is_heapbase_access(in_state, memargs) {
+                if wamr_is_heapbase_access(in_state, memargs, &self.metadata.wamr_heap_bases()) {
                     return HeapValueLattice::new(HeapValue::HeapBase);
                 }
                 if wamr_is_functype_access(in_state, memargs) {
@@ -196,18 +702,33 @@ impl HeapAnalyzer {
                 if wamr_is_funcptrs_access(in_state, memargs) {
                     return HeapValueLattice::new(HeapValue::WamrFuncPtrsTable);
                 }
+                if let (Some(memory), ValSize::Size64) = (self.metadata.wamr_mem_bound(), memsize) {
+                    for width in WAMR_BOUND_WIDTHS.iter() {
+                        if wamr_access_helper(in_state, memargs, HeapValue::WamrModuleInstance, memory.bound_offset(*width)) {
+                            return HeapValueLattice::new(HeapValue::WamrMemBound(*width));
+                        }
+                    }
+                }
+                if let Some(tables) = &self.metadata.wamr_tables {
+                    if let Some(v) = wamr_table_descriptor_access(in_state, memsize, memargs, tables) {
+                        return HeapValueLattice::new(v);
+                    }
+                }
+                // a reload of a spilled value, e.g. a bounded index under register pressure
+                if is_stack_access(value) {
+                    return match extract_stack_offset(memargs) {
+                        Some(offset) => in_state.stack.get(offset, memsize.to_u32() / 8),
+                        // an indexed stack access may read any slot
+                        None => Default::default(),
+                    };
+                }
             },
-            Value::Reg(regnum, size) => {
-                if let ValSize::SizeOther = size {
-                    return Default::default();
-                };
-                if size.to_u32() <= 32 {
-                    return HeapValueLattice::new(HeapValue::Bounded4GB);
-                } else {
-                    return in_state.regs.get(regnum, &ValSize::Size64);
+            Value::Reg(regnum, size) => return self.aeval_reg(in_state, *regnum, size),
+            Value::Imm(_, _, immval) => {
+                if (*immval >= 0) && (*immval < (1 << 32)) {
+                    return HeapValueLattice::new(HeapValue::Bounded(*immval as u64 + 1));
                 }
             },
-            Value::Imm(_, _, _immval) => {},
         }
         Default::default()
     }
@@ -218,34 +739,702 @@ impl HeapAnalyzer {
                 if lucet_is_globalbase_access(in_state, memargs) {
                     return HeapValueLattice::new(HeapValue::GlobalsBase);
                 }
+                if lucet_is_tablesize_access(in_state, memargs) {
+                    return HeapValueLattice::new(HeapValue::LucetTableSize);
+                }
                 if is_stack_access(value) {
-                    let offset = extract_stack_offset(memargs);
-                    let v = in_state.stack.get(offset, memsize.to_u32() / 8);
-                    return v;
+                    return match extract_stack_offset(memargs) {
+                        Some(offset) => in_state.stack.get(offset, memsize.to_u32() / 8),
+                        // an indexed stack access may read any slot
+                        None => Default::default(),
+                    };
                 }
             }
 
-            Value::Reg(regnum, size) => {
-                if let ValSize::SizeOther = size {
-                    return Default::default();
-                };
-                if size.to_u32() <= 32 {
-                    return HeapValueLattice::new(HeapValue::Bounded4GB);
-                } else {
-                    return in_state.regs.get(regnum, &ValSize::Size64);
-                }
-            }
+            Value::Reg(regnum, size) => return self.aeval_reg(in_state, *regnum, size),
 
-            Value::Imm(_, _, immval) => {
-                if (*immval as u64) == self.metadata.guest_table_0 {
+            Value::Imm(immtype, immsize, immval) => {
+                let addr = imm_to_addr(immtype, immsize, *immval);
+                if self.metadata.is_addr(addr, self.metadata.guest_table_0) {
                     return HeapValueLattice::new(HeapValue::GuestTable0);
-                } else if (*immval as u64) == self.metadata.lucet_tables {
+                } else if self.metadata.is_addr(addr, self.metadata.lucet_tables) {
                     return HeapValueLattice::new(HeapValue::LucetTables);
+                } else if self.metadata.lucet_safe_page.map_or(false, |page| self.metadata.is_addr(addr, page)) {
+                    return HeapValueLattice::new(HeapValue::SafePage);
                 } else if (*immval >= 0) && (*immval < (1 << 32)) {
-                    return HeapValueLattice::new(HeapValue::Bounded4GB);
+                    return HeapValueLattice::new(HeapValue::Bounded(*immval as u64 + 1));
                 }
             }
         }
         Default::default()
     }
 }
+
+#[test]
+fn heap_analyzer_truncation_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::ir::{IRMap, ImmType};
+
+    let analyzer = HeapAnalyzer {
+        metadata: CompilerMetadata::for_test(Compiler::Lucet),
+    };
+    let rax = Value::Reg(0, ValSize::Size64);
+    let shl = Stmt::Binop(Binopcode::Shl, rax.clone(), rax.clone(), Value::Imm(ImmType::Unsigned, ValSize::Size8, 32));
+    let shr = |shift| Stmt::Binop(Binopcode::Shr, rax.clone(), rax.clone(), Value::Imm(ImmType::Unsigned, ValSize::Size8, shift));
+    // mov ecx, [rdi + rax]
+    let access = Stmt::Unop(
+        Unopcode::Mov,
+        Value::Reg(1, ValSize::Size32),
+        Value::Mem(ValSize::Size32, MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Reg(0, ValSize::Size64))),
+    );
+
+    // a shift by less than 32 is not credited, and neither is the shl on its own
+    let mut state = analyzer.init_state();
+    state.regs.rax = HeapValueLattice::new(HeapValue::Bounded4GB);
+    analyzer.aexec(&mut state, &shr(16), &LocIdx { addr: 0, idx: 0 });
+    assert_eq!(state.regs.rax, HeapValueLattice::default());
+    state.regs.rax = HeapValueLattice::new(HeapValue::Bounded4GB);
+    analyzer.aexec(&mut state, &shl, &LocIdx { addr: 0, idx: 0 });
+    assert_eq!(state.regs.rax, HeapValueLattice::default());
+
+    // shl rax, 32; shr rax, 32; mov ecx, [rdi + rax]
+    let mut irmap = IRMap::new();
+    irmap.insert(0, vec![(0, vec![shl.clone()]), (4, vec![shr(32)]), (8, vec![access.clone()])]);
+    let result = run_worklist(&SingleBlock(0), &irmap, &analyzer);
+    assert!(check_heap(result, &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok());
+
+    // shl rax, 32; mov ecx, [rdi + rax]
+    irmap.insert(0, vec![(0, vec![shl]), (8, vec![access])]);
+    let result = run_worklist(&SingleBlock(0), &irmap, &analyzer);
+    assert!(check_heap(result, &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_err());
+}
+
+#[test]
+fn heap_analyzer_high_table_test() {
+    use crate::utils::ir::ImmType;
+
+    // tables above 2GB, as in large modules
+    let analyzer = HeapAnalyzer {
+        metadata: CompilerMetadata {
+            guest_table_0: 0x8000_1000,
+            lucet_tables: 0x9000_2000,
+            ..CompilerMetadata::for_test(Compiler::Lucet)
+        },
+    };
+    let mut state = analyzer.init_state();
+    let imm32 = |addr: u64| Value::Imm(ImmType::Signed, ValSize::Size32, addr as u32 as i32 as i64);
+    let imm64 = |addr: u64| Value::Imm(ImmType::Signed, ValSize::Size64, addr as i64);
+    assert_eq!(analyzer.aeval_unop(&mut state, &imm32(0x8000_1000)), HeapValueLattice::new(HeapValue::GuestTable0));
+    assert_eq!(analyzer.aeval_unop(&mut state, &imm64(0x8000_1000)), HeapValueLattice::new(HeapValue::GuestTable0));
+    assert_eq!(analyzer.aeval_unop(&mut state, &imm32(0x9000_2000)), HeapValueLattice::new(HeapValue::LucetTables));
+    assert_eq!(analyzer.aeval_unop(&mut state, &imm64(0x9000_2000)), HeapValueLattice::new(HeapValue::LucetTables));
+    // a sign-extended 64-bit immediate is not the table
+    assert_ne!(analyzer.aeval_unop(&mut state, &imm64(0xffff_ffff_8000_1000)), HeapValueLattice::new(HeapValue::GuestTable0));
+}
+
+#[test]
+fn heap_analyzer_x32_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::compiler::Abi;
+    use crate::utils::ir::{IRMap, ImmType};
+
+    let analyzer = |abi: Abi| HeapAnalyzer {
+        metadata: CompilerMetadata {
+            guest_table_0: 0x1000,
+            abi: abi,
+            ..CompilerMetadata::for_test(Compiler::Lucet)
+        },
+    };
+    let eax = Value::Reg(0, ValSize::Size32);
+    let ebx = Value::Reg(3, ValSize::Size32);
+    let edi = Value::Reg(7, ValSize::Size32);
+    let slot = Value::Mem(ValSize::Size32, MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64)));
+    // mov eax, eax; mov ebx, edi; mov [rsp], ebx; mov ebx, [rsp]; <arith>; mov eax, [rbx + rax]
+    let check = |abi: Abi, arith: Vec<Stmt>| {
+        let mut stmts = vec![
+            Stmt::Unop(Unopcode::Mov, eax.clone(), eax.clone()),
+            Stmt::Unop(Unopcode::Mov, ebx.clone(), edi.clone()),
+            Stmt::Unop(Unopcode::Mov, slot.clone(), ebx.clone()),
+            Stmt::Unop(Unopcode::Mov, ebx.clone(), slot.clone()),
+        ];
+        stmts.extend(arith);
+        stmts.push(Stmt::Unop(
+            Unopcode::Mov,
+            eax.clone(),
+            Value::Mem(ValSize::Size32, MemArgs::Mem2Args(MemArg::Reg(3, ValSize::Size64), MemArg::Reg(0, ValSize::Size64))),
+        ));
+        let mut irmap = IRMap::new();
+        irmap.insert(0, stmts.into_iter().enumerate().map(|(i, stmt)| (4 * i as u64, vec![stmt])).collect());
+        let analyzer = analyzer(abi);
+        let result = run_worklist(&SingleBlock(0), &irmap, &analyzer);
+        check_heap(result, &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+
+    // the heap base survives 32-bit moves and 4-byte spills only under x32
+    assert!(check(Abi::X32, vec![]));
+    assert!(!check(Abi::Lp64, vec![]));
+    // arithmetic on the pointer in a 32-bit register truncates it
+    let add = Stmt::Binop(Binopcode::Add, ebx.clone(), ebx.clone(), Value::Imm(ImmType::Signed, ValSize::Size32, 0x10));
+    assert!(!check(Abi::X32, vec![add]));
+    let rbx = Value::Reg(3, ValSize::Size64);
+    let shift = |opcode| Stmt::Binop(opcode, rbx.clone(), rbx.clone(), Value::Imm(ImmType::Unsigned, ValSize::Size8, 32));
+    assert!(!check(Abi::X32, vec![shift(Binopcode::Shl), shift(Binopcode::Shr)]));
+
+    // only the low 32 bits of an address are compared against the metadata
+    let mut state = analyzer(Abi::X32).init_state();
+    let sign_extended = Value::Imm(ImmType::Signed, ValSize::Size64, 0xffff_ffff_0000_1000u64 as i64);
+    assert_eq!(analyzer(Abi::X32).aeval_unop(&mut state, &sign_extended), HeapValueLattice::new(HeapValue::GuestTable0));
+    assert_ne!(analyzer(Abi::Lp64).aeval_unop(&mut state, &sign_extended), HeapValueLattice::new(HeapValue::GuestTable0));
+}
+
+#[test]
+fn heap_analyzer_globals_ptr_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::ir::{IRMap, ImmType};
+
+    let analyzer = HeapAnalyzer {
+        metadata: CompilerMetadata::for_test(Compiler::Lucet),
+    };
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let mem = |regnum: u8, offset: i64| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)),
+    );
+    let deref = |regnum: u8| Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)));
+    // sub rsp, 8; mov rax, [rdi - 16]; lea rbx, [rax + <offset>]; mov [rsp], rbx; call f;
+    // mov rcx, [rsp]; mov rdx, <access through rcx>; add rsp, 8; ret
+    let check = |offset: i64, access: Value| {
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![
+            (0x0, vec![Stmt::Binop(Binopcode::Sub, reg(4), reg(4), imm(8))]),
+            (0x4, vec![Stmt::Unop(Unopcode::Mov, reg(0), mem(7, -16))]),
+            (0x8, vec![Stmt::Binop(Binopcode::Add, reg(3), reg(0), imm(offset))]),
+            (0xc, vec![Stmt::Unop(Unopcode::Mov, deref(4), reg(3))]),
+            (0x10, vec![Stmt::Call(Value::Imm(ImmType::Signed, ValSize::Size32, 0x100), 5)]),
+            (0x15, vec![Stmt::Unop(Unopcode::Mov, reg(1), deref(4))]),
+            (0x19, vec![Stmt::Unop(Unopcode::Mov, reg(2), access)]),
+            (0x1c, vec![Stmt::Binop(Binopcode::Add, reg(4), reg(4), imm(8))]),
+            (0x20, vec![Stmt::Ret]),
+        ]);
+        check_heap(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+
+    assert!(check(0x10, deref(1)));
+    assert!(check(0x10, mem(1, 0x20)));
+    // the pointer itself is past the globals
+    assert!(!check(LucetModel::GLOBALS_SIZE, deref(1)));
+    // the access runs off the end of the globals
+    assert!(!check(LucetModel::GLOBALS_SIZE - 8, mem(1, 8)));
+    assert!(!check(0x10, mem(1, -0x20)));
+}
+
+#[test]
+fn heap_analyzer_strict_rebound_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::ir::{IRMap, ImmType};
+
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let reg32 = |regnum: u8| Value::Reg(regnum, ValSize::Size32);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let stack = |offset: i64| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)),
+    );
+    let heap = Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Reg(0, ValSize::Size64)),
+    );
+    // sub rsp, 16; mov eax, edx; mov [rsp], rax; mov [rsp + 8], rdi; call f;
+    // mov rdi, [rsp + 8]; mov rax, [rsp]; <recheck>; mov rcx, [rdi + rax]; add rsp, 16; ret
+    let check = |strict_rebound: bool, recheck: Vec<Stmt>| {
+        let analyzer = HeapAnalyzer {
+            metadata: CompilerMetadata {
+                strict_rebound: strict_rebound,
+                ..CompilerMetadata::for_test(Compiler::Lucet)
+            },
+        };
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![
+            (0x0, vec![Stmt::Binop(Binopcode::Sub, reg(4), reg(4), imm(16))]),
+            (0x4, vec![Stmt::Unop(Unopcode::Mov, reg32(0), reg32(2))]),
+            (0x6, vec![Stmt::Unop(Unopcode::Mov, stack(0), reg(0))]),
+            (0xa, vec![Stmt::Unop(Unopcode::Mov, stack(8), reg(7))]),
+            (0xf, vec![Stmt::Call(Value::Imm(ImmType::Signed, ValSize::Size32, 0x100), 5)]),
+            (0x14, vec![Stmt::Unop(Unopcode::Mov, reg(7), stack(8))]),
+            (0x19, vec![Stmt::Unop(Unopcode::Mov, reg(0), stack(0))]),
+            (0x1d, recheck),
+            (0x1f, vec![Stmt::Unop(Unopcode::Mov, reg(1), heap.clone())]),
+            (0x23, vec![Stmt::Binop(Binopcode::Add, reg(4), reg(4), imm(16))]),
+            (0x27, vec![Stmt::Ret]),
+        ]);
+        check_heap(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+    // mov eax, eax
+    let truncate = vec![Stmt::Unop(Unopcode::Mov, reg32(0), reg32(0))];
+
+    // the spilled bound survives the call...
+    assert!(check(false, vec![]));
+    // ...unless bounds have to be re-established after calls; the spilled heap base is still fine
+    assert!(!check(true, vec![]));
+    assert!(check(true, truncate));
+}
+
+#[test]
+fn heap_analyzer_options_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::ir::{IRMap, ImmType};
+
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let reg32 = |regnum: u8| Value::Reg(regnum, ValSize::Size32);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let stack = |offset: i64| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)),
+    );
+    let heap = Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Reg(0, ValSize::Size64)),
+    );
+    // sub rsp, 16; mov eax, edx; <spill and reload rax and rdi>; mov rcx, [rdi + rax]; add rsp, 16; ret
+    let check = |options: &str, spill: bool| {
+        let analyzer = HeapAnalyzer {
+            metadata: CompilerMetadata {
+                options: AnalysisOptions::parse(options).unwrap(),
+                ..CompilerMetadata::for_test(Compiler::Lucet)
+            },
+        };
+        let mut block = vec![
+            (0x0, vec![Stmt::Binop(Binopcode::Sub, reg(4), reg(4), imm(16))]),
+            (0x4, vec![Stmt::Unop(Unopcode::Mov, reg32(0), reg32(2))]),
+        ];
+        if spill {
+            block.push((0x6, vec![Stmt::Unop(Unopcode::Mov, stack(0), reg(0))]));
+            block.push((0xa, vec![Stmt::Unop(Unopcode::Mov, stack(8), reg(7))]));
+            block.push((0xf, vec![Stmt::Unop(Unopcode::Mov, reg(7), stack(8))]));
+            block.push((0x14, vec![Stmt::Unop(Unopcode::Mov, reg(0), stack(0))]));
+        }
+        block.push((0x18, vec![Stmt::Unop(Unopcode::Mov, reg(1), heap.clone())]));
+        block.push((0x1c, vec![Stmt::Binop(Binopcode::Add, reg(4), reg(4), imm(16))]));
+        block.push((0x20, vec![Stmt::Ret]));
+        let mut irmap = IRMap::new();
+        irmap.insert(0, block);
+        check_heap(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+
+    assert!(check("", false));
+    assert!(check("", true));
+    // without the 32-bit rule, mov eax, edx says nothing about rax
+    assert!(!check("bound_32bit=false", false));
+    assert!(check("bound_8bit=false", false));
+    // without stack tracking, the reloaded index and heap base are unknown
+    assert!(check("stack_tracking=false", false));
+    assert!(!check("stack_tracking=false", true));
+}
+
+#[test]
+fn heap_analyzer_setcc_offset_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::ir::{IRMap, ImmType};
+
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let check = |globals_size: i64, setcc: bool| {
+        let analyzer = HeapAnalyzer {
+            metadata: CompilerMetadata {
+                globals_size: globals_size,
+                ..CompilerMetadata::for_test(Compiler::Lucet)
+            },
+        };
+        // mov rbx, [rdi - 0x10]  (the globals base)
+        let mut block = vec![(0x0, vec![Stmt::Unop(
+            Unopcode::Mov,
+            reg(3),
+            Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size8, -0x10))),
+        )])];
+        // sete al
+        if setcc {
+            block.push((0x4, vec![Stmt::Unop(Unopcode::Set, Value::Reg(0, ValSize::Size8), Value::Reg(16, ValSize::Size8))]));
+        }
+        // movzx eax, al; lea rax, [rax*8]; mov rcx, [rbx + rax]
+        block.push((0x7, vec![Stmt::Unop(Unopcode::MovZx, Value::Reg(0, ValSize::Size32), Value::Reg(0, ValSize::Size8))]));
+        block.push((0xa, vec![Stmt::Binop(Binopcode::Shl, reg(0), reg(0), Value::Imm(ImmType::Unsigned, ValSize::Size8, 3))]));
+        block.push((0x12, vec![Stmt::Unop(
+            Unopcode::Mov,
+            reg(1),
+            Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(3, ValSize::Size64), MemArg::Reg(0, ValSize::Size64))),
+        )]));
+        let mut irmap = IRMap::new();
+        irmap.insert(0, block);
+        check_heap(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+
+    // a two-entry table of 8-byte globals: the offset is 0 or 8
+    assert!(check(16, true));
+    assert!(!check(15, true));
+    // without the setcc, al could be anything and the scaled offset is unbounded
+    assert!(!check(16, false));
+}
+
+#[test]
+fn extension_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::ir::IRMap;
+
+    let heap = |size: ValSize, index: u8| Value::Mem(
+        size,
+        MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Reg(index, ValSize::Size64)),
+    );
+    let reg = |regnum: u8, size: ValSize| Value::Reg(regnum, size);
+    // movzx ecx, cl; <extend rax, [rdi + rcx]>; mov rdx, [rdi + rax]
+    let check = |bound_32bit: bool, extend: Stmt| {
+        let mut metadata = CompilerMetadata::for_test(Compiler::Lucet);
+        metadata.options.bound_32bit = bound_32bit;
+        let analyzer = HeapAnalyzer { metadata: metadata };
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![
+            (0x0, vec![Stmt::Unop(Unopcode::MovZx, reg(1, ValSize::Size32), reg(1, ValSize::Size8))]),
+            (0x3, vec![extend]),
+            (0x6, vec![Stmt::Unop(Unopcode::Mov, reg(2, ValSize::Size64), heap(ValSize::Size64, 0))]),
+        ]);
+        check_heap(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+
+    for bound_32bit in vec![true, false] {
+        // movzx eax, byte [rdi + rcx]; movzx rax, word [rdi + rcx]: bounded whatever was loaded
+        assert!(check(bound_32bit, Stmt::Unop(Unopcode::MovZx, reg(0, ValSize::Size32), heap(ValSize::Size8, 1))));
+        assert!(check(bound_32bit, Stmt::Unop(Unopcode::MovZx, reg(0, ValSize::Size64), heap(ValSize::Size16, 1))));
+        // movsxd rax, dword [rdi + rcx]: possibly negative
+        assert!(!check(bound_32bit, Stmt::Unop(Unopcode::MovSx, reg(0, ValSize::Size64), heap(ValSize::Size32, 1))));
+        // movsxd rax, ecx keeps ecx's bound, below 2^31; movsxd rax, edx has none
+        assert!(check(bound_32bit, Stmt::Unop(Unopcode::MovSx, reg(0, ValSize::Size64), reg(1, ValSize::Size32))));
+        assert!(!check(bound_32bit, Stmt::Unop(Unopcode::MovSx, reg(0, ValSize::Size64), reg(2, ValSize::Size32))));
+    }
+    // movsx eax, byte [rdi + rcx]: the 32-bit write still clears the upper half of rax
+    assert!(check(true, Stmt::Unop(Unopcode::MovSx, reg(0, ValSize::Size32), heap(ValSize::Size8, 1))));
+    assert!(!check(false, Stmt::Unop(Unopcode::MovSx, reg(0, ValSize::Size32), heap(ValSize::Size8, 1))));
+}
+
+#[test]
+fn heap_analyzer_mask_test() {
+    use crate::utils::ir::ImmType;
+
+    let analyzer = HeapAnalyzer {
+        metadata: CompilerMetadata::for_test(Compiler::Lucet),
+    };
+    let loc = LocIdx { addr: 0, idx: 0 };
+    let rax = Value::Reg(0, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size32, v);
+    let binop = |opcode: Binopcode, v: i64| Stmt::Binop(opcode, rax.clone(), rax.clone(), imm(v));
+    // the value of rax after running `stmts` from an unknown rax
+    let run = |stmts: Vec<Stmt>| {
+        let mut state: HeapLattice = Default::default();
+        for stmt in stmts {
+            analyzer.aexec(&mut state, &stmt, &loc);
+        }
+        state.regs.rax.v
+    };
+
+    // and rax, 0xfffff
+    assert_eq!(run(vec![binop(Binopcode::And, 0xfffff)]), Some(HeapValue::Bounded(0x100000)));
+    assert_eq!(run(vec![binop(Binopcode::And, 0x7fff_ffff)]), Some(HeapValue::Bounded(1 << 31)));
+    // and rax, 0xfffff; shr rax, 4 / shl rax, 4
+    assert_eq!(run(vec![binop(Binopcode::And, 0xfffff), binop(Binopcode::Shr, 4)]), Some(HeapValue::Bounded(0x10000)));
+    assert_eq!(run(vec![binop(Binopcode::And, 0xfffff), binop(Binopcode::Shl, 4)]), Some(HeapValue::Bounded(0xfffff1)));
+    // and rax, 0xfffff; shl rax, 20: past 4GB, so unknown
+    assert_eq!(run(vec![binop(Binopcode::And, 0xfffff), binop(Binopcode::Shl, 20)]), None);
+    // a negative mask only clears bits: it keeps a bound, but makes none
+    assert_eq!(run(vec![binop(Binopcode::And, 0xfffff), binop(Binopcode::And, -16)]), Some(HeapValue::Bounded(0x100000)));
+    assert_eq!(run(vec![binop(Binopcode::And, -16)]), None);
+    // shr rax, 8 of an unknown rax is unknown, shr rax, 32 leaves 32 bits
+    assert_eq!(run(vec![binop(Binopcode::Shr, 8)]), None);
+    assert_eq!(run(vec![binop(Binopcode::Shr, 32)]), Some(HeapValue::Bounded4GB));
+    // shr eax, 32 shifts by 0 too, rather than clearing eax
+    let eax = Value::Reg(0, ValSize::Size32);
+    let shr_eax = Stmt::Binop(Binopcode::Shr, eax.clone(), eax, imm(32));
+    assert_ne!(run(vec![binop(Binopcode::And, 0xfffff), shr_eax]), Some(HeapValue::Bounded(1)));
+    // shr rax, 0x40 shifts by 0, so rax stays unknown
+    assert_eq!(run(vec![binop(Binopcode::Shr, 0x40)]), None);
+    assert_eq!(run(vec![binop(Binopcode::Shr, 0x60)]), Some(HeapValue::Bounded4GB));
+}
+
+#[test]
+fn heap_analyzer_div_test() {
+    use crate::analyses::{run_worklist, ControlFlow};
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::ir::{IRMap, ImmType};
+    use yaxpeax_x86::long_mode::Opcode;
+
+    // test ecx, ecx; je trap, falling through to the division
+    struct TrapCheck;
+    impl ControlFlow for TrapCheck {
+        fn entrypoint(&self) -> u64 { 0 }
+        fn successors(&self, addr: u64) -> Vec<u64> { if addr == 0 { vec![0x10, 0x40] } else { vec![] } }
+    }
+
+    let reg = |regnum: u8, size: ValSize| Value::Reg(regnum, size);
+    let heap = |index: u8| Value::Mem(
+        ValSize::Size32,
+        MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Reg(index, ValSize::Size64)),
+    );
+    let div = |opcode: Binopcode, size: ValSize| vec![
+        Stmt::Binop(opcode.clone(), reg(0, size), reg(0, size), reg(1, size)),
+        Stmt::Binop(opcode, reg(2, size), reg(2, size), reg(1, size)),
+    ];
+    // <setup>; <div>; mov ebx, [rdi + <index>]
+    let check = |setup: Vec<Stmt>, div: Vec<Stmt>, index: u8| {
+        let analyzer = HeapAnalyzer {
+            metadata: CompilerMetadata::for_test(Compiler::Lucet),
+        };
+        let ecx = reg(1, ValSize::Size32);
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![
+            (0x0, vec![Stmt::Binop(Binopcode::Test, ecx.clone(), ecx.clone(), ecx)]),
+            (0x2, vec![Stmt::Branch(Opcode::JE, Value::Imm(ImmType::Signed, ValSize::Size64, 0x40))]),
+        ]);
+        irmap.insert(0x10, vec![
+            (0x10, setup),
+            (0x18, div),
+            (0x20, vec![Stmt::Unop(Unopcode::Mov, reg(3, ValSize::Size32), heap(index))]),
+        ]);
+        irmap.insert(0x40, vec![(0x40, vec![Stmt::Undefined])]);
+        check_heap(run_worklist(&TrapCheck, &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+    let zero_rdx = Stmt::Unop(Unopcode::Mov, reg(2, ValSize::Size32), Value::Imm(ImmType::Signed, ValSize::Size32, 0));
+    let unknown_rax = Stmt::Clear(reg(0, ValSize::Size64), vec![]);
+    let bounded_rax = Stmt::Unop(Unopcode::Mov, reg(0, ValSize::Size32), reg(6, ValSize::Size32));
+
+    // div ecx: a 32-bit quotient and remainder, whatever was divided
+    assert!(check(vec![unknown_rax.clone()], div(Binopcode::Div, ValSize::Size32), 0));
+    assert!(check(vec![unknown_rax.clone()], div(Binopcode::Div, ValSize::Size32), 2));
+    // xor edx, edx; mov eax, esi; div rcx: the quotient is below 4GB, the remainder below rcx
+    assert!(check(vec![zero_rdx.clone(), bounded_rax.clone()], div(Binopcode::Div, ValSize::Size64), 0));
+    assert!(!check(vec![zero_rdx.clone(), bounded_rax.clone()], div(Binopcode::Div, ValSize::Size64), 2));
+    // unless the high half of the dividend is zero, a 64-bit quotient has no bound
+    assert!(!check(vec![bounded_rax.clone()], div(Binopcode::Div, ValSize::Size64), 0));
+    assert!(!check(vec![zero_rdx.clone(), unknown_rax], div(Binopcode::Div, ValSize::Size64), 0));
+    // a signed division is only bounded by its width
+    assert!(check(vec![zero_rdx.clone(), bounded_rax.clone()], div(Binopcode::Idiv, ValSize::Size32), 0));
+    assert!(!check(vec![zero_rdx, bounded_rax], div(Binopcode::Idiv, ValSize::Size64), 0));
+}
+
+#[test]
+fn heap_analyzer_wamr_bounds_check_test() {
+    use crate::analyses::{run_worklist, ControlFlow};
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::ir::ImmType;
+    use crate::utils::wamr_layout::{WamrMemories, WamrMemory};
+    use yaxpeax_x86::long_mode::Opcode;
+    use crate::lattices::heaplattice::{WAMR_HEAPBASE_OFFSET, WAMR_MEMBOUNDS_OFFSET};
+
+    // <check>; j<cc> 0x40, with the access at 0x10 and a trap at 0x40
+    struct BoundsCheck;
+    impl ControlFlow for BoundsCheck {
+        fn entrypoint(&self) -> u64 { 0 }
+        fn successors(&self, addr: u64) -> Vec<u64> { if addr == 0 { vec![0x10, 0x40] } else { vec![] } }
+    }
+
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let field = |regnum: u8, offset: i64| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size32, offset)),
+    );
+    let load = |dst: u8, src: Value| Stmt::Unop(Unopcode::Mov, reg(dst), src);
+    let cmp = |src1: Value, src2: Value| Stmt::Binop(Binopcode::Cmp, src1.clone(), src1, src2);
+    // mov ebx, [rcx + rsi]
+    let access = vec![Stmt::Unop(Unopcode::Mov, Value::Reg(3, ValSize::Size32), Value::Mem(
+        ValSize::Size32,
+        MemArgs::Mem2Args(MemArg::Reg(1, ValSize::Size64), MemArg::Reg(6, ValSize::Size64)),
+    ))];
+    let memory = WamrMemory { heap_base: WAMR_HEAPBASE_OFFSET, mem_bound: WAMR_MEMBOUNDS_OFFSET };
+    // mov rax, [ExecEnv + 0x10]; mov rcx, [rax + heap base]; mov rdx, [rax + the bound of
+    // `width`-byte accesses]; <check>; j<cc> trap; <access>, or <access> in the trap, with
+    // `memories` in the layout
+    let check_bound = |width: u32, bounds_checks: bool, memories: usize, check: Vec<(u64, Vec<Stmt>)>, opcode: Opcode, trap: Vec<Stmt>, before_access: Vec<Stmt>| {
+        let mut options: AnalysisOptions = Default::default();
+        options.wamr_bounds_checks = bounds_checks;
+        let analyzer = HeapAnalyzer {
+            metadata: CompilerMetadata {
+                wamr_memories: WamrMemories(vec![memory; memories]),
+                options: options,
+                ..CompilerMetadata::for_test(Compiler::Wamr)
+            },
+        };
+        let mut block = vec![
+            (0x0, vec![load(0, field(7, WAMR_MODULEINSTANCE_OFFSET))]),
+            (0x4, vec![load(1, field(0, WAMR_HEAPBASE_OFFSET))]),
+            (0x8, vec![load(2, field(0, memory.bound_offset(width)))]),
+        ];
+        block.extend(check);
+        block.push((0xe, vec![Stmt::Branch(opcode, Value::Imm(ImmType::Signed, ValSize::Size64, 0x40))]));
+        let mut irmap = IRMap::new();
+        irmap.insert(0, block);
+        irmap.insert(0x10, vec![(0x10, before_access), (0x14, access.clone())]);
+        let mut trap = trap;
+        trap.push(Stmt::Undefined);
+        irmap.insert(0x40, vec![(0x40, trap)]);
+        check_heap(run_worklist(&BoundsCheck, &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+    // the access is 4 bytes wide
+    let check_with = |bounds_checks: bool, memories: usize, check: Vec<(u64, Vec<Stmt>)>, opcode: Opcode, trap: Vec<Stmt>, before_access: Vec<Stmt>| {
+        check_bound(4, bounds_checks, memories, check, opcode, trap, before_access)
+    };
+    let check = |bounds_checks: bool, memories: usize, check: Vec<(u64, Vec<Stmt>)>, opcode: Opcode, trap: Vec<Stmt>| {
+        check_with(bounds_checks, memories, check, opcode, trap, vec![])
+    };
+    let cmp_rsi = vec![(0xc, vec![cmp(reg(6), reg(2))])];
+
+    // cmp rsi, rdx; ja trap: rsi is in bounds at the access, with the flag
+    assert!(check(true, 1, cmp_rsi.clone(), Opcode::JA, vec![]));
+    assert!(!check(false, 1, cmp_rsi.clone(), Opcode::JA, vec![]));
+    // and only with a single memory, whose bound it is
+    assert!(!check(true, 2, cmp_rsi.clone(), Opcode::JA, vec![]));
+    // but not in the trap, or if the branch says nothing about the bound
+    assert!(!check(true, 1, cmp_rsi.clone(), Opcode::JA, access.clone()));
+    assert!(!check(true, 1, cmp_rsi.clone(), Opcode::JB, vec![]));
+    assert!(!check(true, 1, cmp_rsi.clone(), Opcode::JG, vec![]));
+    // cmp rdx, rsi; jb trap, or the bound straight from the ModuleInstance
+    assert!(check(true, 1, vec![(0xc, vec![cmp(reg(2), reg(6))])], Opcode::JB, vec![]));
+    assert!(check(true, 1, vec![(0xc, vec![cmp(reg(6), field(0, memory.bound_offset(4)))])], Opcode::JA, vec![]));
+    // the bound of narrower accesses leaves the last bytes of the 4-byte access out of bounds
+    assert!(!check(true, 1, vec![(0xc, vec![cmp(reg(6), field(0, memory.bound_offset(2)))])], Opcode::JA, vec![]));
+    assert!(!check_bound(2, true, 1, cmp_rsi.clone(), Opcode::JA, vec![], vec![]));
+    assert!(!check_bound(1, true, 1, cmp_rsi.clone(), Opcode::JA, vec![], vec![]));
+    assert!(check_bound(8, true, 1, cmp_rsi.clone(), Opcode::JA, vec![], vec![]));
+    // rsi below the bound (jae trap) leaves a byte more in bounds than rsi at most the bound
+    // (ja trap), so 3 bytes with the bound of 2-byte accesses, still short of 4
+    assert!(!check_bound(2, true, 1, cmp_rsi.clone(), Opcode::JNB, vec![], vec![]));
+    assert!(check_bound(4, true, 1, cmp_rsi.clone(), Opcode::JNB, vec![], vec![]));
+    // a test, or a compare with anything else, isn't a check
+    let test = Stmt::Binop(Binopcode::Test, reg(6), reg(6), reg(2));
+    assert!(!check(true, 1, vec![(0xc, vec![test])], Opcode::JA, vec![]));
+    assert!(!check(true, 1, vec![(0xc, vec![cmp(reg(6), reg(1))])], Opcode::JA, vec![]));
+    // nor does it survive rsi changing, before the branch or after it, or the flags changing
+    let add_rsi = Stmt::Binop(Binopcode::Add, reg(6), reg(6), Value::Imm(ImmType::Signed, ValSize::Size32, 8));
+    assert!(!check(true, 1, vec![(0xa, vec![cmp(reg(6), reg(2))]), (0xc, vec![add_rsi.clone()])], Opcode::JA, vec![]));
+    assert!(!check_with(true, 1, cmp_rsi.clone(), Opcode::JA, vec![], vec![add_rsi]));
+    assert!(check_with(true, 1, cmp_rsi.clone(), Opcode::JA, vec![], vec![load(0, reg(6))]));
+    let mut clobbered = cmp_rsi.clone();
+    clobbered.push((0xd, vec![Stmt::Clear(Value::Reg(16, ValSize::Size8), vec![])]));
+    assert!(!check(true, 1, clobbered, Opcode::JA, vec![]));
+}
+
+#[test]
+fn heap_analyzer_lucet_table_check_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::Fork;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::ir::ImmType;
+    use yaxpeax_x86::long_mode::Opcode;
+
+    // large code model: guest_table_0 lives above 4GB and is loaded with movabs
+    let analyzer = HeapAnalyzer {
+        metadata: CompilerMetadata {
+            guest_table_0: 0x1_0000_2000,
+            lucet_tables: 0x1_0000_1000,
+            ..CompilerMetadata::for_test(Compiler::Lucet)
+        },
+    };
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let field = |regnum: u8, offset: i64| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)),
+    );
+    let shl = |shift: i64| Stmt::Binop(Binopcode::Shl, reg(1), reg(1), imm(shift));
+    let load = Stmt::Unop(Unopcode::Mov, reg(2), field(0, 8));
+    // movabs rax, lucet_tables; mov rdx, [rax + 8]; cmp rcx, rdx; j<cc> 0x20, then at 0x10
+    // <scale>; movabs rax, guest_table_0; add rax, rcx; <use>, and a trap at 0x20
+    let check = |opcode: Opcode, scale: Vec<Stmt>, use_entry: Stmt| {
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![
+            (0x0, vec![Stmt::Unop(Unopcode::Mov, reg(0), imm(0x1_0000_1000))]),
+            (0xa, vec![Stmt::Unop(Unopcode::Mov, reg(2), field(0, 8))]),
+            (0xe, vec![Stmt::Binop(Binopcode::Cmp, reg(1), reg(1), reg(2))]),
+            (0x11, vec![Stmt::Branch(opcode, imm(0x20))]),
+        ]);
+        irmap.insert(0x10, vec![
+            (0x10, scale),
+            (0x14, vec![Stmt::Unop(Unopcode::Mov, reg(0), imm(0x1_0000_2000))]),
+            (0x1e, vec![Stmt::Binop(Binopcode::Add, reg(0), reg(0), reg(1))]),
+            (0x21, vec![use_entry]),
+        ]);
+        irmap.insert(0x20, vec![(0x20, vec![Stmt::Undefined])]);
+        check_heap(run_worklist(&Fork, &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+
+    // rcx below the table size (jae trap), scaled by the entry size: both words of the entry
+    assert!(check(Opcode::JNB, vec![shl(4)], load.clone()));
+    let first = Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(0, ValSize::Size64)));
+    assert!(check(Opcode::JNB, vec![shl(4)], Stmt::Unop(Unopcode::Mov, reg(2), first)));
+    // but not past them
+    assert!(!check(Opcode::JNB, vec![shl(4)], Stmt::Unop(Unopcode::Mov, reg(2), field(0, 16))));
+    // rcx at most the table size (ja trap) may index one entry past the table
+    assert!(!check(Opcode::JA, vec![shl(4)], load.clone()));
+    // nor is it checked in the trap, or unscaled, or scaled by anything else
+    assert!(!check(Opcode::JB, vec![shl(4)], load.clone()));
+    assert!(!check(Opcode::JNB, vec![], load.clone()));
+    assert!(!check(Opcode::JNB, vec![shl(3)], load.clone()));
+    // and the table is only ever read
+    assert!(!check(Opcode::JNB, vec![shl(4)], Stmt::Unop(Unopcode::Mov, field(0, 8), reg(2))));
+}
+
+#[test]
+fn heap_analyzer_cmov_test() {
+    let analyzer = HeapAnalyzer {
+        metadata: CompilerMetadata::for_test(Compiler::Lucet),
+    };
+    let loc = LocIdx { addr: 0, idx: 0 };
+    let rax = Value::Reg(0, ValSize::Size64);
+    let rcx = Value::Reg(1, ValSize::Size64);
+    // cmovb rax, rcx
+    let cmov = Stmt::Binop(Binopcode::Cmov, rax.clone(), rax.clone(), rcx.clone());
+    let run = |rax_v: Option<HeapValue>, rcx_v: Option<HeapValue>| {
+        let mut state: HeapLattice = Default::default();
+        state.regs.rax = HeapValueLattice { v: rax_v };
+        state.regs.rcx = HeapValueLattice { v: rcx_v };
+        analyzer.aexec(&mut state, &cmov, &loc);
+        state.regs.rax.v
+    };
+
+    assert_eq!(run(Some(HeapValue::Bounded4GB), Some(HeapValue::Bounded4GB)), Some(HeapValue::Bounded4GB));
+    // rax keeps its value when the move doesn't happen, so both have to be bounded
+    assert_eq!(run(Some(HeapValue::Bounded4GB), None), None);
+    assert_eq!(run(None, Some(HeapValue::Bounded4GB)), None);
+    assert_eq!(run(Some(HeapValue::HeapBase), Some(HeapValue::Bounded4GB)), None);
+    // the mitigation's cmov leaves the heap base or the safe page
+    assert_eq!(run(Some(HeapValue::HeapBase), Some(HeapValue::SafePage)), Some(HeapValue::HeapBaseOrSafePage));
+}
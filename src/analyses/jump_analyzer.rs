@@ -1,11 +1,13 @@
 use crate::analyses::reaching_defs::ReachingDefnAnalyzer;
 use crate::analyses::{run_worklist, AbstractAnalyzer, AnalysisResult};
-use crate::lattices::reachingdefslattice::{LocIdx, ReachLattice};
+use crate::lattices::reachingdefslattice::{LocIdx, ReachLattice, ReachingDefnLattice};
 use crate::lattices::stacklattice::StackSlot;
 use crate::lattices::switchlattice::{SwitchLattice, SwitchValue, SwitchValueLattice};
 use crate::lattices::VarState;
-use crate::utils::lifter::{Binopcode, IRMap, MemArg, MemArgs, ValSize, Value};
-use crate::utils::utils::{get_rsp_offset, CompilerMetadata, Compiler};
+use crate::utils::ir::{Binopcode, IRMap, MemArg, MemArgs, ValSize, Value};
+use crate::utils::compiler::{CompilerMetadata, Compiler};
+use crate::utils::ir_utils::{branch_opcode, in_bounds_side};
+use crate::utils::utils::get_rsp_offset;
 use std::default::Default;
 use yaxpeax_core::analyses::control_flow::VW_CFG;
 
@@ -29,6 +31,15 @@ impl AbstractAnalyzer<SwitchLattice> for SwitchAnalyzer {
         self.metadata.compiler
     }
 
+    fn paranoid(&self) -> bool {
+        self.metadata.options.paranoid
+    }
+
+    fn on_call(&self, in_state: &mut SwitchLattice) -> () {
+        in_state.on_call(self.metadata.compiler);
+        in_state.forget_caller_window(self.metadata.caller_window);
+    }
+
     fn aexec_unop(
         &self,
         in_state: &mut SwitchLattice,
@@ -49,15 +60,16 @@ impl AbstractAnalyzer<SwitchLattice> for SwitchAnalyzer {
         loc_idx: &LocIdx,
     ) -> () {
         if let Binopcode::Cmp = opcode {
-            match (src1, src2) {
-                (Value::Reg(regnum, _), Value::Imm(_, _, imm))
-                | (Value::Imm(_, _, imm), Value::Reg(regnum, _)) => {
-                    let reg_def = self.reaching_analyzer.fetch_def(&self.reaching_defs, loc_idx);
-                    let src_loc = reg_def.regs.get(regnum, &ValSize::Size64);
-                    in_state.regs.zf =
-                        SwitchValueLattice::new(SwitchValue::ZF(*imm as u32, *regnum, src_loc));
-                }
-                _ => (),
+            let compared = match (src1, src2) {
+                (Value::Reg(regnum, _), Value::Imm(_, _, imm)) => Some((regnum, imm, true)),
+                (Value::Imm(_, _, imm), Value::Reg(regnum, _)) => Some((regnum, imm, false)),
+                _ => None,
+            };
+            if let Some((regnum, imm, index_first)) = compared {
+                let reg_def = self.reaching_analyzer.fetch_def(&self.reaching_defs, loc_idx);
+                let src_loc = reg_def.regs.get(regnum, &ValSize::Size64);
+                in_state.regs.zf =
+                    SwitchValueLattice::new(SwitchValue::ZF(*imm as u32, *regnum, index_first, src_loc));
             }
         }
         
@@ -68,6 +80,8 @@ impl AbstractAnalyzer<SwitchLattice> for SwitchAnalyzer {
         }
     }
 
+    // The index compared against the jump table's size is bounded on the side of the branch it is
+    // in bounds on; the other side is where it falls back to the default target.
     fn process_branch(
         &self,
         irmap: &IRMap,
@@ -78,48 +92,22 @@ impl AbstractAnalyzer<SwitchLattice> for SwitchAnalyzer {
         if succ_addrs.len() == 2 {
             let mut not_branch_state = in_state.clone();
             let mut branch_state = in_state.clone();
-            if let Some(SwitchValue::ZF(bound, regnum, checked_defs)) = &in_state.regs.zf.v {
-                not_branch_state.regs.set(
-                    &regnum,
-                    &ValSize::Size64,
-                    SwitchValueLattice {
-                        v: Some(SwitchValue::UpperBound(*bound)),
-                    },
-                );
-                let defs_state = self.reaching_defs.get(addr).unwrap();
-                let ir_block = irmap.get(addr).unwrap();
-                let defs_state = self.reaching_analyzer.analyze_block(defs_state, ir_block);
-                //propagate bound across registers with the same reaching def
-                for idx in 0..15 {
-                    if idx != *regnum {
-                        let reg_def = defs_state.regs.get(&idx, &ValSize::Size64);
-                        if (!reg_def.is_empty()) && (&reg_def == checked_defs) {
-                            not_branch_state.regs.set(
-                                &idx,
-                                &ValSize::Size64,
-                                SwitchValueLattice {
-                                    v: Some(SwitchValue::UpperBound(*bound)),
-                                },
-                            );
-                        }
-                    }
-                }
-                //propagate bound across stack slots with the same upper bound
-                for (stack_offset, stack_slot) in defs_state.stack.map.iter() {
-                    if !checked_defs.is_empty() && (&stack_slot.value == checked_defs) {
-                        let v = SwitchValueLattice {
-                            v: Some(SwitchValue::UpperBound(*bound)),
-                        };
-                        let vv = StackSlot {
-                            size: stack_slot.size,
-                            value: v,
-                        };
-                        not_branch_state.stack.map.insert(*stack_offset, vv);
+            branch_state.regs.zf = Default::default();
+            not_branch_state.regs.zf = Default::default();
+            if let Some(SwitchValue::ZF(bound, regnum, index_first, checked_defs)) = &in_state.regs.zf.v {
+                let side = irmap
+                    .get(addr)
+                    .and_then(|ir_block| branch_opcode(ir_block))
+                    .and_then(|opcode| in_bounds_side(&opcode, *index_first));
+                if let Some((taken, strict)) = side {
+                    // index <= bound leaves one more entry than index < bound
+                    let bound = if strict { Some(*bound) } else { bound.checked_add(1) };
+                    if let Some(bound) = bound {
+                        let checked = if taken { &mut branch_state } else { &mut not_branch_state };
+                        self.bound_index(checked, *addr, *regnum, checked_defs, bound);
                     }
                 }
             }
-            branch_state.regs.zf = Default::default();
-            not_branch_state.regs.zf = Default::default();
             vec![
                 (succ_addrs[0].clone(), not_branch_state),
                 (succ_addrs[1].clone(), branch_state),
@@ -134,6 +122,47 @@ impl AbstractAnalyzer<SwitchLattice> for SwitchAnalyzer {
 }
 
 impl SwitchAnalyzer {
+    // Bounds register `regnum`, checked at the end of the block at `addr`, by `bound`, along with
+    // the registers and stack slots holding the same definition of it
+    fn bound_index(&self, state: &mut SwitchLattice, addr: u64, regnum: u8, checked_defs: &ReachingDefnLattice, bound: u32) {
+        state.regs.set(
+            &regnum,
+            &ValSize::Size64,
+            SwitchValueLattice {
+                v: Some(SwitchValue::UpperBound(bound)),
+            },
+        );
+        let defs_state = self.reaching_analyzer.fetch_exit_def(&self.reaching_defs, addr);
+        //propagate bound across registers with the same reaching def
+        for idx in 0..15 {
+            if idx != regnum {
+                let reg_def = defs_state.regs.get(&idx, &ValSize::Size64);
+                if (!reg_def.is_empty()) && (&reg_def == checked_defs) {
+                    state.regs.set(
+                        &idx,
+                        &ValSize::Size64,
+                        SwitchValueLattice {
+                            v: Some(SwitchValue::UpperBound(bound)),
+                        },
+                    );
+                }
+            }
+        }
+        //propagate bound across stack slots with the same upper bound
+        for (stack_offset, stack_slot) in defs_state.stack.map.iter() {
+            if !checked_defs.is_empty() && (&stack_slot.value == checked_defs) {
+                let v = SwitchValueLattice {
+                    v: Some(SwitchValue::UpperBound(bound)),
+                };
+                let vv = StackSlot {
+                    size: stack_slot.size,
+                    value: v,
+                };
+                state.stack.map.insert(*stack_offset, vv);
+            }
+        }
+    }
+
     fn aeval_unop_mem(
         &self,
         in_state: &SwitchLattice,
@@ -207,3 +236,41 @@ impl SwitchAnalyzer {
         Default::default()
     }
 }
+
+#[test]
+fn jump_table_bound_side_test() {
+    use crate::utils::ir::{ImmType, Stmt};
+    use yaxpeax_x86::long_mode::Opcode;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let rax = Value::Reg(0, ValSize::Size64);
+    let sides = |opcode: Opcode, index_first: bool| {
+        // cmp rax, 4 (or cmp 4, rax); j<opcode> 0x20
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![(0, vec![Stmt::Branch(opcode, Value::Imm(ImmType::Signed, ValSize::Size64, 0x20))])]);
+        let reaching_analyzer = ReachingDefnAnalyzer::new(metadata.clone(), VW_CFG::new(0), irmap.clone());
+        let mut reaching_defs = AnalysisResult::new();
+        reaching_defs.insert(0, reaching_analyzer.init_state());
+        let analyzer = SwitchAnalyzer {
+            metadata: metadata.clone(),
+            reaching_defs: reaching_defs,
+            reaching_analyzer: reaching_analyzer,
+        };
+        let mut state: SwitchLattice = Default::default();
+        state.regs.zf = SwitchValueLattice::new(SwitchValue::ZF(4, 0, index_first, ReachingDefnLattice::default()));
+        let succs = analyzer.process_branch(&irmap, &state, &vec![0x10, 0x20], &0);
+        let mut succs = succs.into_iter().map(|(_, mut state)| state.get(&rax).and_then(|v| v.v));
+        (succs.next().unwrap(), succs.next().unwrap())
+    };
+
+    // jae falls through to the table with rax below 4, and jumps to the default target with it
+    // unbounded
+    assert_eq!(sides(Opcode::JNB, true), (Some(SwitchValue::UpperBound(4)), None));
+    // and jb the other way round
+    assert_eq!(sides(Opcode::JB, true), (None, Some(SwitchValue::UpperBound(4))));
+    assert_eq!(sides(Opcode::JA, false), (None, Some(SwitchValue::UpperBound(4))));
+    // ja leaves rax at most 4 on the fall-through, 5 entries
+    assert_eq!(sides(Opcode::JA, true), (Some(SwitchValue::UpperBound(5)), None));
+    // and je says nothing about the bound on either side
+    assert_eq!(sides(Opcode::JZ, true), (None, None));
+}
@@ -1,17 +1,35 @@
+#[cfg(feature = "loader")]
 pub mod call_analyzer;
+mod components;
 pub mod heap_analyzer;
+#[cfg(feature = "loader")]
 pub mod jump_analyzer;
+#[cfg(feature = "loader")]
 pub mod reaching_defs;
 pub mod stack_analyzer;
+pub mod taint_analyzer;
+#[cfg(test)]
+pub mod test_cfgs;
+pub mod value_numbering;
 use crate::lattices::reachingdefslattice::LocIdx;
 use crate::lattices::{Lattice, VarState};
-use crate::utils::lifter::{Binopcode, IRBlock, IRMap, Stmt, Value};
-use crate::utils::utils::Compiler;
+use crate::utils::ir::{Binopcode, IRBlock, IRMap, Stmt, Value};
+use crate::utils::compiler::{AnalysisOptions, Compiler};
+use crate::utils::ir_utils::is_stack_access;
 use std::collections::{HashMap, VecDeque};
-use yaxpeax_core::analyses::control_flow::VW_CFG;
+use std::env;
 
 pub type AnalysisResult<T> = HashMap<u64, T>;
 
+/// The view of a control-flow graph that the worklist algorithm needs: where to start and which
+/// blocks follow a given block. With the `loader` feature this is implemented for yaxpeax-core's
+/// `VW_CFG`; users of the core layer can implement it for their own graph representation.
+/// `run_worklist` may share it between threads, so it only takes a `Sync` one.
+pub trait ControlFlow {
+    fn entrypoint(&self) -> u64;
+    fn successors(&self, addr: u64) -> Vec<u64>;
+}
+
 pub trait AbstractAnalyzer<State: Lattice + VarState + Clone> {
     fn init_state(&self) -> State {
         Default::default()
@@ -50,16 +68,31 @@ pub trait AbstractAnalyzer<State: Lattice + VarState + Clone> {
     fn aexec(&self, in_state: &mut State, ir_instr: &Stmt, loc_idx: &LocIdx) -> () {
         match ir_instr {
             Stmt::Clear(dst, _srcs) => in_state.set_to_bot(dst),
-            Stmt::Unop(_, dst, src) => self.aexec_unop(in_state, &dst, &src, loc_idx),
+            Stmt::Unop(_, dst, src) => {
+                self.aexec_unop(in_state, &dst, &src, loc_idx);
+                self.untrack_stack(in_state, dst)
+            }
             Stmt::Binop(opcode, dst, src1, src2) => {
                 self.aexec_binop(in_state, opcode, dst, src1, src2, loc_idx);
+                self.untrack_stack(in_state, dst);
                 in_state.adjust_stack_offset(opcode, dst, src1, src2)
             }
-            Stmt::Call(_) => in_state.on_call(self.compiler()),
+            Stmt::Call(_, _) => self.on_call(in_state),
             _ => (),
         }
     }
 
+    // Without stack tracking, nothing is known about a value read back from the stack
+    fn untrack_stack(&self, in_state: &mut State, dst: &Value) {
+        if !self.options().stack_tracking && is_stack_access(dst) {
+            in_state.set_to_bot(dst)
+        }
+    }
+
+    fn on_call(&self, in_state: &mut State) -> () {
+        in_state.on_call(self.compiler())
+    }
+
     fn analyze_block(
         &self,
         state: &State,
@@ -84,6 +117,14 @@ pub trait AbstractAnalyzer<State: Lattice + VarState + Clone> {
     fn compiler(&self) -> Compiler {
         Compiler::Lucet
     }
+
+    fn options(&self) -> AnalysisOptions {
+        AnalysisOptions::default()
+    }
+
+    fn paranoid(&self) -> bool {
+        false
+    }
 }
 
 
@@ -109,22 +150,128 @@ fn align_succ_addrs(addr: u64, succ_addrs: Vec<u64>) -> Vec<u64> {
     panic!("Unreachable");
 }
 
-pub fn run_worklist<T: AbstractAnalyzer<State>, State: VarState + Lattice + Clone>(
-    cfg: &VW_CFG,
+// With `--paranoid`: the result of the worklist only doesn't depend on the order blocks are
+// visited in if meet is commutative and associative, so check that on the states actually met
+fn check_meet<State: Lattice>(a: &State, b: &State, prev: Option<&State>, loc: &LocIdx, succ_addr: u64) {
+    if a.meet(b, loc) != b.meet(a, loc) {
+        log::error!("{:?} {:?}", a, b);
+        panic!("Meet commutativity error at 0x{:x}", succ_addr);
+    }
+    if let Some(c) = prev {
+        if a.meet(c, loc).meet(b, loc) != a.meet(&c.meet(b, loc), loc) {
+            log::error!("{:?} {:?} {:?}", a, c, b);
+            panic!("Meet associativity error at 0x{:x}", succ_addr);
+        }
+    }
+}
+
+/// How much work a run of the worklist took, from `run_worklist_instrumented`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorklistStats {
+    // blocks taken off the worklist, counting every revisit
+    pub iterations: usize,
+    // states met into a block that already had one
+    pub meets: usize,
+    pub block_visits: HashMap<u64, usize>,
+}
+
+impl WorklistStats {
+    /// The most times any one block was analyzed, which is how long the slowest loop took to
+    /// reach a fixed point.
+    pub fn max_block_visits(&self) -> usize {
+        self.block_visits.values().cloned().max().unwrap_or(0)
+    }
+}
+
+/// Functions with at least this many blocks are analyzed a strongly connected component of their
+/// CFG at a time, with independent components on separate threads (see components.rs)
+pub const PARALLEL_WORKLIST_BLOCKS: usize = 4096;
+// the fewest blocks a depth of the condensation needs to be split across threads
+const PARALLEL_LEVEL_BLOCKS: usize = 256;
+/// Set (to anything) to always analyze functions on one thread, for debugging
+pub const SEQUENTIAL_ENV: &str = "VERIWASM_SEQUENTIAL";
+
+/// The states at the entry of each block reachable from the entry of `cfg`, at the fixed point of
+/// `analyzer`. The states are the same whether or not the function is big enough to be analyzed on
+/// several threads.
+pub fn run_worklist<C, T, State>(cfg: &C, irmap: &IRMap, analyzer: &T) -> AnalysisResult<State>
+where
+    C: ControlFlow + Sync,
+    T: AbstractAnalyzer<State> + Sync,
+    State: VarState + Lattice + Clone + Send,
+{
+    if irmap.len() >= PARALLEL_WORKLIST_BLOCKS && env::var_os(SEQUENTIAL_ENV).is_none() {
+        return components::iterate_components(cfg, irmap, analyzer, PARALLEL_LEVEL_BLOCKS);
+    }
+    iterate(cfg, irmap, analyzer, None, None)
+}
+
+/// `run_worklist`, also counting the iterations and meets it took.
+pub fn run_worklist_instrumented<C: ControlFlow, T: AbstractAnalyzer<State>, State: VarState + Lattice + Clone>(
+    cfg: &C,
     irmap: &IRMap,
     analyzer: &T,
+) -> (AnalysisResult<State>, WorklistStats) {
+    let mut stats = WorklistStats::default();
+    let result = iterate(cfg, irmap, analyzer, Some(&mut stats), None);
+    (result, stats)
+}
+
+/// `run_worklist_instrumented`, recording the order blocks are visited in, or visiting them in a
+/// recorded order (see `Scheduling`).
+pub fn run_worklist_scheduled<C: ControlFlow, T: AbstractAnalyzer<State>, State: VarState + Lattice + Clone>(
+    cfg: &C,
+    irmap: &IRMap,
+    analyzer: &T,
+    scheduling: Scheduling,
+) -> (AnalysisResult<State>, WorklistStats) {
+    let mut stats = WorklistStats::default();
+    let result = iterate(cfg, irmap, analyzer, Some(&mut stats), Some(scheduling));
+    (result, stats)
+}
+
+fn iterate<C: ControlFlow, T: AbstractAnalyzer<State>, State: VarState + Lattice + Clone>(
+    cfg: &C,
+    irmap: &IRMap,
+    analyzer: &T,
+    mut stats: Option<&mut WorklistStats>,
+    mut scheduling: Option<Scheduling>,
 ) -> AnalysisResult<State> {
     let mut statemap: HashMap<u64, State> = HashMap::new();
     let mut worklist: VecDeque<u64> = VecDeque::new();
-    worklist.push_back(cfg.entrypoint);
-    statemap.insert(cfg.entrypoint, analyzer.init_state());
+    // how many times each block was taken off the worklist, and how much of the schedule being
+    // replayed has been
+    let mut visits: HashMap<u64, u32> = HashMap::new();
+    let mut replayed = 0;
+    // the state each block was last met with, for checking associativity
+    let mut last_met: HashMap<u64, State> = HashMap::new();
+    // The function entry is a predecessor of the entry block of its own: init_state is met into
+    // the entry block's state along with any back edges to it, so a loop back to the first block
+    // can only take facts away from what the caller guarantees
+    let entry = cfg.entrypoint();
+    let entry_state = analyzer.init_state();
+    worklist.push_back(entry);
+    statemap.insert(entry, entry_state.clone());
 
     while !worklist.is_empty() {
-        let addr = worklist.pop_front().unwrap();
+        let addr = match scheduling.as_mut() {
+            Some(Scheduling::Replay(schedule, notes)) if notes.fallback.is_none() => {
+                replay_next(schedule, notes, &mut replayed, &mut worklist, irmap)
+            }
+            _ => worklist.pop_front().unwrap(),
+        };
+        let visit = visits.entry(addr).or_insert(0);
+        *visit += 1;
+        let visit = *visit;
+        let mut changed = false;
+        if let Some(stats) = stats.as_mut() {
+            stats.iterations += 1;
+            *stats.block_visits.entry(addr).or_insert(0) += 1;
+        }
         let irblock = irmap.get(&addr).unwrap();
         let state = statemap.get(&addr).unwrap();
-        let new_state = analyzer.analyze_block(state, irblock);
-        let succ_addrs_unaligned: Vec<u64> = cfg.graph.neighbors(addr).collect();
+        let new_state = analyzer.analyze_block(state, &irblock);
+        let succ_addrs_unaligned: Vec<u64> = cfg.successors(addr);
         let succ_addrs: Vec<u64> = align_succ_addrs(addr, succ_addrs_unaligned);
         //println!("Processing Block: 0x{:x} -> {:?}", addr, succ_addrs);
         for (succ_addr, branch_state) in
@@ -133,10 +280,18 @@ pub fn run_worklist<T: AbstractAnalyzer<State>, State: VarState + Lattice + Clon
             let has_change = 
                 if statemap.contains_key(&succ_addr) {
                     let old_state = statemap.get(&succ_addr).unwrap();
-                    let merged_state = old_state.meet(&branch_state, &LocIdx { addr: addr, idx: 0 });
+                    let loc = LocIdx { addr: addr, idx: 0 };
+                    let merged_state = old_state.meet(&branch_state, &loc);
+                    if let Some(stats) = stats.as_mut() {
+                        stats.meets += 1;
+                    }
+                    if analyzer.paranoid() {
+                        check_meet(old_state, &branch_state, last_met.get(&succ_addr), &loc, succ_addr);
+                        last_met.insert(succ_addr, branch_state);
+                    }
 
                     if merged_state > *old_state {
-                        println!("{:?} {:?}", merged_state, old_state);
+                        log::error!("{:?} {:?}", merged_state, old_state);
                         panic!("Meet monoticity error");
                     }
                     let has_change = *old_state != merged_state;
@@ -148,10 +303,328 @@ pub fn run_worklist<T: AbstractAnalyzer<State>, State: VarState + Lattice + Clon
                     true
                 };
 
+            changed |= has_change;
             if has_change && !worklist.contains(&succ_addr) {
                 worklist.push_back(succ_addr);
             }
         }
+        if let Some(Scheduling::Record(schedule)) = scheduling.as_mut() {
+            schedule.push(Visit { addr: addr, visit: visit, changed: changed });
+        }
+    }
+    // visits recorded after the fixed point this run reached
+    if let Some(Scheduling::Replay(schedule, notes)) = scheduling {
+        if notes.fallback.is_none() {
+            notes.skipped.extend(schedule[replayed..].iter().cloned());
+        }
+    }
+    if analyzer.paranoid() {
+        check_entry(&entry_state, statemap.get(&entry).unwrap(), entry);
     }
     statemap
 }
+
+/// One block taken off the worklist: which, the how manyth time it was taken off, and whether
+/// that changed the state of any of its successors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Visit {
+    pub addr: u64,
+    pub visit: u32,
+    pub changed: bool,
+}
+
+/// What `run_worklist_scheduled` does with the order blocks are visited in.
+pub enum Scheduling<'a> {
+    // append each visit to the schedule
+    Record(&'a mut Vec<Visit>),
+    // take blocks off the worklist in the order of the schedule, noting how that went
+    Replay(&'a [Visit], &'a mut ReplayNotes),
+}
+
+/// How replaying a schedule went: the visits skipped since their block wasn't on the worklist
+/// when they came up, and why the run fell back to the usual order part way, if it did.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayNotes {
+    pub skipped: Vec<Visit>,
+    pub fallback: Option<String>,
+}
+
+// Takes the block of the next visit of `schedule` off the worklist, skipping the visits of blocks
+// that aren't on it. A schedule naming a block the CFG doesn't have, or running out before the
+// worklist does, no longer fits the function, so the rest of the run takes blocks in the usual
+// order.
+fn replay_next(schedule: &[Visit], notes: &mut ReplayNotes, replayed: &mut usize, worklist: &mut VecDeque<u64>, irmap: &IRMap) -> u64 {
+    while let Some(visit) = schedule.get(*replayed) {
+        *replayed += 1;
+        if !irmap.contains_key(&visit.addr) {
+            notes.fallback = Some(format!("visit {} is of 0x{:x}, which isn't a block", *replayed, visit.addr));
+            return worklist.pop_front().unwrap();
+        }
+        if let Some(idx) = worklist.iter().position(|addr| *addr == visit.addr) {
+            return worklist.remove(idx).unwrap();
+        }
+        notes.skipped.push(*visit);
+    }
+    notes.fallback = Some(format!("the schedule ran out with {} blocks left on the worklist", worklist.len()));
+    worklist.pop_front().unwrap()
+}
+
+// With `--paranoid`: the entry block may lose facts to back edges, but may never know more than
+// init_state, which is all the caller guarantees
+fn check_entry<State: Lattice>(init: &State, state: &State, entry: u64) {
+    if !(state <= init) {
+        log::error!("{:?} {:?}", state, init);
+        panic!("Entry state error at 0x{:x}", entry);
+    }
+}
+
+#[test]
+fn paranoid_meet_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::utils::compiler::CompilerMetadata;
+    use std::cmp::Ordering;
+    use std::panic;
+    use crate::analyses::test_cfgs::Diamond;
+
+    // a broken lattice whose meet keeps the left-hand state
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    struct LeftBiased(u64);
+    impl PartialOrd for LeftBiased {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            if self == other { Some(Ordering::Equal) } else { None }
+        }
+    }
+    impl Lattice for LeftBiased {
+        fn meet(&self, _other: &Self, _loc: &LocIdx) -> Self {
+            self.clone()
+        }
+    }
+    impl VarState for LeftBiased {
+        type Var = ();
+        fn get(&mut self, _index: &Value) -> Option<()> { None }
+        fn set(&mut self, _index: &Value, _v: ()) {}
+        fn set_to_bot(&mut self, _index: &Value) {}
+        fn on_call(&mut self, _compiler: Compiler) {}
+        fn adjust_stack_offset(&mut self, _opcode: &Binopcode, _dst: &Value, _src1: &Value, _src2: &Value) {}
+    }
+    // each block hands on a state naming it
+    struct Naming(bool);
+    impl AbstractAnalyzer<LeftBiased> for Naming {
+        fn process_branch(&self, _irmap: &IRMap, _in_state: &LeftBiased, succ_addrs: &Vec<u64>, addr: &u64) -> Vec<(u64, LeftBiased)> {
+            succ_addrs.iter().map(|succ| (*succ, LeftBiased(*addr))).collect()
+        }
+        fn paranoid(&self) -> bool {
+            self.0
+        }
+    }
+    let mut irmap = IRMap::new();
+    for addr in vec![0, 0x10, 0x20, 0x30] {
+        irmap.insert(addr, vec![(addr, vec![Stmt::Ret])]);
+    }
+
+    // unnoticed unless paranoid
+    assert_eq!(run_worklist(&Diamond, &irmap, &Naming(false)).get(&0x30), Some(&LeftBiased(0x10)));
+    let caught = panic::catch_unwind(panic::AssertUnwindSafe(|| run_worklist(&Diamond, &irmap, &Naming(true)))).unwrap_err();
+    assert_eq!(caught.downcast_ref::<String>().map(|s| s.as_str()), Some("Meet commutativity error at 0x30"));
+
+    // the real lattices pass
+    let mut metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    metadata.options.paranoid = true;
+    let heap_analyzer = HeapAnalyzer { metadata: metadata };
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| run_worklist(&Diamond, &irmap, &heap_analyzer))).is_ok());
+}
+
+#[test]
+fn entry_loop_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{MemArg, MemArgs, Unopcode, ValSize};
+    use std::cmp::Ordering;
+    use std::panic;
+    use crate::analyses::test_cfgs::EntryLoop;
+
+    // a broken lattice whose meet gains facts, but commutatively, and without ever comparing
+    // greater than either side
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    struct Max(u64);
+    impl PartialOrd for Max {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            if self == other { Some(Ordering::Equal) } else { None }
+        }
+    }
+    impl Lattice for Max {
+        fn meet(&self, other: &Self, _loc: &LocIdx) -> Self {
+            Max(self.0.max(other.0))
+        }
+    }
+    impl VarState for Max {
+        type Var = ();
+        fn get(&mut self, _index: &Value) -> Option<()> { None }
+        fn set(&mut self, _index: &Value, _v: ()) {}
+        fn set_to_bot(&mut self, _index: &Value) {}
+        fn on_call(&mut self, _compiler: Compiler) {}
+        fn adjust_stack_offset(&mut self, _opcode: &Binopcode, _dst: &Value, _src1: &Value, _src2: &Value) {}
+    }
+    struct Naming(bool);
+    impl AbstractAnalyzer<Max> for Naming {
+        fn process_branch(&self, _irmap: &IRMap, _in_state: &Max, succ_addrs: &Vec<u64>, addr: &u64) -> Vec<(u64, Max)> {
+            succ_addrs.iter().map(|succ| (*succ, Max(*addr + 1))).collect()
+        }
+        fn paranoid(&self) -> bool {
+            self.0
+        }
+    }
+    let mut irmap = IRMap::new();
+    for addr in vec![0, 0x10, 0x20] {
+        irmap.insert(addr, vec![(addr, vec![Stmt::Ret])]);
+    }
+    // the back edge's state replaces what init_state says about the entry, unless paranoid
+    assert_eq!(run_worklist(&EntryLoop, &irmap, &Naming(false)).get(&0), Some(&Max(0x11)));
+    let caught = panic::catch_unwind(panic::AssertUnwindSafe(|| run_worklist(&EntryLoop, &irmap, &Naming(true)))).unwrap_err();
+    assert_eq!(caught.downcast_ref::<String>().map(|s| s.as_str()), Some("Entry state error at 0x0"));
+
+    // mov eax, [rdi] in the loop header, with rdi clobbered or not before the back edge
+    let mut metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    metadata.options.paranoid = true;
+    let analyzer = HeapAnalyzer { metadata: metadata };
+    let check = |clobber: bool| {
+        let load = Stmt::Unop(
+            Unopcode::Mov,
+            Value::Reg(0, ValSize::Size32),
+            Value::Mem(ValSize::Size32, MemArgs::Mem1Arg(MemArg::Reg(7, ValSize::Size64))),
+        );
+        let body = if clobber {
+            Stmt::Unop(Unopcode::Mov, Value::Reg(7, ValSize::Size64), Value::Reg(0, ValSize::Size64))
+        } else {
+            Stmt::Unop(Unopcode::Mov, Value::Reg(1, ValSize::Size64), Value::Reg(0, ValSize::Size64))
+        };
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![(0, vec![load])]);
+        irmap.insert(0x10, vec![(0x10, vec![body])]);
+        irmap.insert(0x20, vec![(0x20, vec![Stmt::Ret])]);
+        // the entry assertion stays silent either way
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| run_worklist(&EntryLoop, &irmap, &analyzer))).unwrap();
+        check_heap(result, &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).map_err(|failure| failure.loc.addr)
+    };
+    assert_eq!(check(false), Ok(()));
+    // the back edge takes the heap base out of rdi, which the caller's guarantee doesn't bring back
+    assert_eq!(check(true), Err(0));
+}
+
+#[test]
+fn worklist_stats_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{Unopcode, ValSize};
+    use crate::analyses::test_cfgs::EntryLoop;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let analyzer = HeapAnalyzer { metadata: metadata };
+    // the loop takes the heap base out of rdi, so the loop is gone round twice
+    let mut irmap = IRMap::new();
+    irmap.insert(0, vec![(0, vec![Stmt::Ret])]);
+    irmap.insert(0x10, vec![(0x10, vec![Stmt::Unop(Unopcode::Mov, Value::Reg(7, ValSize::Size64), Value::Reg(0, ValSize::Size64))])]);
+    irmap.insert(0x20, vec![(0x20, vec![Stmt::Ret])]);
+
+    let (result, stats) = run_worklist_instrumented(&EntryLoop, &irmap, &analyzer);
+    assert_eq!(result, run_worklist(&EntryLoop, &irmap, &analyzer));
+    assert_eq!((stats.iterations, stats.meets, stats.max_block_visits()), (5, 4, 2));
+    assert_eq!(stats.block_visits.get(&0x20), Some(&1));
+    assert_eq!(WorklistStats::default().max_block_visits(), 0);
+}
+
+#[test]
+fn worklist_schedule_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{Unopcode, ValSize};
+    use std::cmp::Ordering;
+    use crate::analyses::test_cfgs::{Diamond, EntryLoop};
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let analyzer = HeapAnalyzer { metadata: metadata };
+    let mut irmap = IRMap::new();
+    irmap.insert(0, vec![(0, vec![Stmt::Ret])]);
+    irmap.insert(0x10, vec![(0x10, vec![Stmt::Unop(Unopcode::Mov, Value::Reg(7, ValSize::Size64), Value::Reg(0, ValSize::Size64))])]);
+    irmap.insert(0x20, vec![(0x20, vec![Stmt::Ret])]);
+    let replay = |schedule: &[Visit]| {
+        let mut notes = ReplayNotes::default();
+        let (result, _) = run_worklist_scheduled(&EntryLoop, &irmap, &analyzer, Scheduling::Replay(schedule, &mut notes));
+        (result, notes)
+    };
+
+    let mut schedule = vec![];
+    let (recorded, stats) = run_worklist_scheduled(&EntryLoop, &irmap, &analyzer, Scheduling::Record(&mut schedule));
+    assert_eq!(recorded, run_worklist(&EntryLoop, &irmap, &analyzer));
+    assert_eq!(schedule.len(), stats.iterations);
+    assert_eq!(schedule[0], Visit { addr: 0, visit: 1, changed: true });
+    assert_eq!(schedule.iter().filter(|visit| visit.addr == 0x10).map(|visit| visit.visit).max(), Some(2));
+    // the last visit changes nothing, which is how the worklist ran dry
+    assert!(!schedule.last().unwrap().changed);
+    assert_eq!(replay(&schedule), (recorded.clone(), ReplayNotes::default()));
+
+    // a visit of a block not on the worklist is skipped
+    let mut extra = schedule.clone();
+    extra.insert(1, Visit { addr: 0x20, visit: 1, changed: false });
+    let (result, notes) = replay(&extra);
+    assert_eq!(result, recorded);
+    assert_eq!((notes.skipped.len(), notes.fallback), (1, None));
+    // a schedule cut short, or naming a block the CFG doesn't have, falls back to the usual order
+    let (result, notes) = replay(&schedule[..2]);
+    assert_eq!(result, recorded);
+    assert!(notes.fallback.unwrap().starts_with("the schedule ran out"));
+    let mut stale = schedule.clone();
+    stale[1].addr = 0x18;
+    let (result, notes) = replay(&stale);
+    assert_eq!(result, recorded);
+    assert_eq!(notes.fallback, Some("visit 2 is of 0x18, which isn't a block".to_string()));
+    assert!(notes.skipped.is_empty());
+
+    // a broken lattice whose meet keeps the left-hand state, so the state of a join point depends
+    // on which predecessor got there first
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    struct LeftBiased(u64);
+    impl PartialOrd for LeftBiased {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            if self == other { Some(Ordering::Equal) } else { None }
+        }
+    }
+    impl Lattice for LeftBiased {
+        fn meet(&self, _other: &Self, _loc: &LocIdx) -> Self {
+            self.clone()
+        }
+    }
+    impl VarState for LeftBiased {
+        type Var = ();
+        fn get(&mut self, _index: &Value) -> Option<()> { None }
+        fn set(&mut self, _index: &Value, _v: ()) {}
+        fn set_to_bot(&mut self, _index: &Value) {}
+        fn on_call(&mut self, _compiler: Compiler) {}
+        fn adjust_stack_offset(&mut self, _opcode: &Binopcode, _dst: &Value, _src1: &Value, _src2: &Value) {}
+    }
+    struct Naming;
+    impl AbstractAnalyzer<LeftBiased> for Naming {
+        fn process_branch(&self, _irmap: &IRMap, _in_state: &LeftBiased, succ_addrs: &Vec<u64>, addr: &u64) -> Vec<(u64, LeftBiased)> {
+            succ_addrs.iter().map(|succ| (*succ, LeftBiased(*addr))).collect()
+        }
+    }
+    let mut irmap = IRMap::new();
+    for addr in vec![0, 0x10, 0x20, 0x30] {
+        irmap.insert(addr, vec![(addr, vec![Stmt::Ret])]);
+    }
+    // a run that happened to visit 0x20 first is reproduced exactly
+    let visit = |addr: u64, changed: bool| Visit { addr: addr, visit: 1, changed: changed };
+    let other_order = vec![visit(0, true), visit(0x20, true), visit(0x10, false), visit(0x30, false)];
+    let mut notes = ReplayNotes::default();
+    let (result, _) = run_worklist_scheduled(&Diamond, &irmap, &Naming, Scheduling::Replay(&other_order, &mut notes));
+    assert_eq!(result.get(&0x30), Some(&LeftBiased(0x20)));
+    assert_eq!(notes, ReplayNotes::default());
+    // where the usual order visits 0x10 first
+    let mut usual_order = vec![];
+    let (result, _) = run_worklist_scheduled(&Diamond, &irmap, &Naming, Scheduling::Record(&mut usual_order));
+    assert_eq!(result.get(&0x30), Some(&LeftBiased(0x10)));
+    assert_eq!(usual_order[1], visit(0x10, true));
+}
@@ -1,8 +1,10 @@
 use crate::analyses::{run_worklist, AbstractAnalyzer, AnalysisResult};
 use crate::lattices::reachingdefslattice::{singleton, LocIdx, ReachLattice, loc};
-use crate::lattices::VarState;
-use crate::utils::lifter::{Binopcode, IRMap, Stmt, Unopcode};
-use crate::utils::utils::{CompilerMetadata, Compiler};
+use crate::lattices::{Lattice, VarState};
+use crate::utils::ir::{Binopcode, Extension, IRMap, Stmt, Unopcode};
+use crate::utils::compiler::{CompilerMetadata, Compiler};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
 use yaxpeax_core::analyses::control_flow::VW_CFG;
 
 //Top level function
@@ -11,42 +13,72 @@ pub fn analyze_reaching_defs(
     irmap: &IRMap,
     metadata: &CompilerMetadata,
 ) -> AnalysisResult<ReachLattice> {
-    run_worklist(cfg, irmap, &ReachingDefnAnalyzer {metadata: metadata.clone(), cfg: cfg.clone(), irmap: irmap.clone()})
+    run_worklist(cfg, irmap, &ReachingDefnAnalyzer::new(metadata.clone(), cfg.clone(), irmap.clone()))
 }
 
 pub struct ReachingDefnAnalyzer {
     pub metadata: CompilerMetadata,
     pub cfg: VW_CFG,
     pub irmap: IRMap,
+    // the defs reaching every statement of the blocks asked about so far (see block_defs)
+    cache: Mutex<HashMap<u64, BlockDefs>>,
+}
+
+// the defs reaching each statement of a block, and its end
+struct BlockDefs {
+    before: BTreeMap<LocIdx, ReachLattice>,
+    exit: ReachLattice,
 }
 
 impl ReachingDefnAnalyzer{
-    //1. get enclosing block addr
-    //2. get result for that block start
-    //3. run reaching def up to that point
-    pub fn fetch_def(&self, result: &AnalysisResult<ReachLattice>, loc_idx: &LocIdx) -> ReachLattice{
-        if self.cfg.blocks.contains_key(&loc_idx.addr){
-            return result.get(&loc_idx.addr).unwrap().clone();
+    pub fn new(metadata: CompilerMetadata, cfg: VW_CFG, irmap: IRMap) -> Self {
+        ReachingDefnAnalyzer {
+            metadata: metadata,
+            cfg: cfg,
+            irmap: irmap,
+            cache: Mutex::new(HashMap::new()),
         }
-        let block_addr = self.cfg.prev_block(loc_idx.addr).unwrap().start;
-        let irblock = self.irmap.get(&block_addr).unwrap();
-        let mut def_state = result.get(&block_addr).unwrap().clone();
-        for (addr, instruction) in irblock.iter() {
-            for (idx, ir_insn) in instruction.iter().enumerate() {
-                if &loc_idx.addr == addr && (loc_idx.idx as usize) == idx{
-                    return def_state
-                }
-                self.aexec(
-                    &mut def_state,
-                    ir_insn,
-                    &LocIdx {
+    }
+
+    // Runs `f` on the defs reaching each statement of the block at `block_addr`, replaying the
+    // block from its entry state in `result` the first time it is asked about. Every block is
+    // only replayed once, however many of its statements are asked about, so `result` has to be
+    // the same for every call.
+    fn block_defs<T>(&self, result: &AnalysisResult<ReachLattice>, block_addr: u64, f: impl FnOnce(&BlockDefs) -> T) -> T {
+        let mut cache = self.cache.lock().unwrap();
+        let defs = cache.entry(block_addr).or_insert_with(|| {
+            let irblock = self.irmap.get(&block_addr).unwrap();
+            let mut def_state = result.get(&block_addr).unwrap().clone();
+            let mut before = BTreeMap::new();
+            for (addr, instruction) in irblock.iter() {
+                for (idx, ir_insn) in instruction.iter().enumerate() {
+                    let loc_idx = LocIdx {
                         addr: *addr,
                         idx: idx as u32,
-                    },
-                );
+                    };
+                    before.insert(loc_idx, def_state.clone());
+                    self.aexec(&mut def_state, ir_insn, &loc_idx);
+                }
             }
-        }
-        unimplemented!()        
+            BlockDefs { before: before, exit: def_state }
+        });
+        f(defs)
+    }
+
+    /// The defs reaching the statement at `loc_idx`.
+    pub fn fetch_def(&self, result: &AnalysisResult<ReachLattice>, loc_idx: &LocIdx) -> ReachLattice{
+        let block_addr = if self.cfg.blocks.contains_key(&loc_idx.addr) {
+            loc_idx.addr
+        } else {
+            self.cfg.prev_block(loc_idx.addr).unwrap().start
+        };
+        self.block_defs(result, block_addr, |defs| defs.before.get(loc_idx).cloned())
+            .unwrap_or_else(|| panic!("No statement at {:?} in the block at 0x{:x}", loc_idx, block_addr))
+    }
+
+    /// The defs reaching the end of the block at `block_addr`.
+    pub fn fetch_exit_def(&self, result: &AnalysisResult<ReachLattice>, block_addr: u64) -> ReachLattice {
+        self.block_defs(result, block_addr, |defs| defs.exit.clone())
     }
 }
 
@@ -103,10 +135,18 @@ impl AbstractAnalyzer<ReachLattice> for ReachingDefnAnalyzer {
         self.metadata.compiler
     }
 
+    fn paranoid(&self) -> bool {
+        self.metadata.options.paranoid
+    }
+
     fn aexec(&self, in_state: &mut ReachLattice, ir_instr: &Stmt, loc_idx: &LocIdx) -> () {
         match ir_instr {
             Stmt::Clear(dst, _) => in_state.set(dst, singleton(loc_idx.clone())),
-            Stmt::Unop(Unopcode::Mov, dst, src) => {
+            // a zero extension keeps the (unsigned) value, a sign extension is a new one
+            Stmt::Unop(_, dst, _) if ir_instr.mov_kind().map(|kind| kind.extension) == Some(Extension::Sign) => {
+                in_state.set(dst, singleton(loc_idx.clone()))
+            }
+            Stmt::Unop(Unopcode::Mov, dst, src) | Stmt::Unop(Unopcode::MovZx, dst, src) | Stmt::Unop(Unopcode::MovSx, dst, src) => {
                 if let Some(v) = in_state.get(src) {
                     if v.defs.is_empty() {
                         in_state.set(dst, singleton(loc_idx.clone()));
@@ -124,11 +164,16 @@ impl AbstractAnalyzer<ReachLattice> for ReachingDefnAnalyzer {
             Stmt::Binop(Binopcode::Test, _, _, _) => {
                 //Ignore test
             }
+            // the move may not happen, so the defs dst already had still reach
+            Stmt::Binop(Binopcode::Cmov, dst, _, _) => {
+                let old = in_state.get(dst).unwrap_or_default();
+                in_state.set(dst, old.meet(&singleton(loc_idx.clone()), loc_idx))
+            }
             Stmt::Binop(opcode, dst, src1, src2) => {
                 in_state.adjust_stack_offset(opcode, dst, src1, src2);
                 in_state.set(dst, singleton(loc_idx.clone()))
             }
-            Stmt::Call(_) =>
+            Stmt::Call(_, _) =>
             {
                 in_state.regs.rax = loc(loc_idx.addr, 0);
                 in_state.regs.rcx = loc(loc_idx.addr, 1);
@@ -150,3 +195,67 @@ impl AbstractAnalyzer<ReachLattice> for ReachingDefnAnalyzer {
         }
     }
 }
+
+#[test]
+fn cached_defs_test() {
+    use crate::utils::ir::{ImmType, MemArg, MemArgs, ValSize, Value};
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let slot = Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, 8)),
+    );
+    // two blocks, one with several statements lifted from a single instruction
+    let mut irmap = IRMap::new();
+    irmap.insert(0x0, vec![
+        (0x0, vec![Stmt::Unop(Unopcode::Mov, reg(0), reg(7))]),
+        (0x3, vec![Stmt::Binop(Binopcode::Shl, reg(0), reg(0), imm(4)), Stmt::Clear(reg(1), vec![])]),
+        (0x8, vec![Stmt::Unop(Unopcode::Mov, slot.clone(), reg(0))]),
+        (0xc, vec![Stmt::Binop(Binopcode::Cmp, reg(0), reg(0), imm(16))]),
+    ]);
+    irmap.insert(0x10, vec![
+        (0x10, vec![Stmt::Unop(Unopcode::Mov, reg(2), slot)]),
+        (0x14, vec![Stmt::Call(reg(2), 2)]),
+        (0x16, vec![Stmt::Unop(Unopcode::MovSx, reg(3), reg(0))]),
+    ]);
+    let analyzer = ReachingDefnAnalyzer::new(metadata, VW_CFG::new(0), irmap.clone());
+    let mut result = AnalysisResult::new();
+    result.insert(0x0, analyzer.init_state());
+    result.insert(0x10, analyzer.analyze_block(&analyzer.init_state(), &irmap.get(&0x0).unwrap()));
+
+    for (block_addr, irblock) in irmap.iter() {
+        // what replaying the block up to each statement used to compute for every lookup
+        let mut replayed = result[&block_addr].clone();
+        for (addr, instruction) in irblock.iter() {
+            for (idx, ir_insn) in instruction.iter().enumerate() {
+                let loc_idx = LocIdx { addr: *addr, idx: idx as u32 };
+                assert_eq!(analyzer.block_defs(&result, block_addr, |defs| defs.before[&loc_idx].clone()), replayed);
+                analyzer.aexec(&mut replayed, ir_insn, &loc_idx);
+            }
+        }
+        assert_eq!(analyzer.fetch_exit_def(&result, block_addr), analyzer.analyze_block(&result[&block_addr], &irblock));
+    }
+    // each block was replayed once
+    assert_eq!(analyzer.cache.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn cmov_defs_test() {
+    use crate::utils::ir::{ValSize, Value};
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let analyzer = ReachingDefnAnalyzer::new(metadata, VW_CFG::new(0), IRMap::new());
+    let rax = Value::Reg(0, ValSize::Size64);
+    let rcx = Value::Reg(1, ValSize::Size64);
+    let mut state = analyzer.init_state();
+    // cmovb rax, rcx: either the entry def of rax or the cmov itself reaches past it
+    let cmov_loc = LocIdx { addr: 0x10, idx: 0 };
+    analyzer.aexec(&mut state, &Stmt::Binop(Binopcode::Cmov, rax.clone(), rax.clone(), rcx), &cmov_loc);
+    assert_eq!(state.regs.rax, loc(0xdeadbeef, 0).meet(&singleton(cmov_loc), &cmov_loc));
+    // while a plain write replaces the old defs
+    let add_loc = LocIdx { addr: 0x14, idx: 0 };
+    analyzer.aexec(&mut state, &Stmt::Binop(Binopcode::Add, rax.clone(), rax.clone(), rax), &add_loc);
+    assert_eq!(state.regs.rax, singleton(add_loc));
+}
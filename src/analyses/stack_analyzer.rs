@@ -1,33 +1,133 @@
-use crate::analyses::AbstractAnalyzer;
-use crate::utils::ir_utils::{get_imm_offset, is_rsp, is_callee_saved_reg, memarg_is_stack};
+use crate::analyses::{run_worklist, AbstractAnalyzer, AnalysisResult, ControlFlow};
+use crate::analyses::heap_analyzer::{wamr_is_stacklimit_access, HeapAnalyzer};
+use crate::utils::ir_utils::{branch_opcode, flags_reach_branch, in_bounds_side, is_rsp, is_callee_saved_reg, memarg_is_stack, rsp_adjustment, writes_narrow_rsp};
 use crate::lattices::reachingdefslattice::LocIdx;
-use crate::lattices::stackgrowthlattice::{StackGrowthLattice, WAMR_STACK_LOWER_BOUND};
-use crate::utils::lifter::{Unopcode, Binopcode, Stmt, Value, MemArgs};
-use crate::utils::utils::{CompilerMetadata, Compiler};
+use crate::lattices::heaplattice::{HeapLattice, HeapValue, WAMR_EXCEPTION_OFFSET};
+use crate::lattices::stackgrowthlattice::{RspTracking, StackGrowthLattice, WAMR_STACK_LOWER_BOUND};
+use crate::utils::ir::{IRBlock, IRMap, Unopcode, Binopcode, MemArg, MemArgs, Stmt, ValSize, Value};
+use crate::utils::compiler::{CompilerMetadata, Compiler};
 use std::collections::HashMap;
 
+/// The cmps of a Wamr function that compare a register against the stack limit in its ExecEnv,
+/// each with whether the limit is the first operand (see `wamr_stack_limit_cmps`).
+pub type StackLimitCmps = HashMap<LocIdx, bool>;
+
 pub struct StackAnalyzer {
     pub metadata: CompilerMetadata,
+    // a conditional branch on one of these checks rsp against the stack limit (Wamr)
+    pub stack_limit_cmps: StackLimitCmps,
 }
 
 impl AbstractAnalyzer<StackGrowthLattice> for StackAnalyzer {
     fn init_state(&self) -> StackGrowthLattice {
-        StackGrowthLattice::new((0, 4096, HashMap::new()))
+        StackGrowthLattice::new((0, 4096, HashMap::new(), false, None, None, false))
     }
 
     fn compiler(&self) -> Compiler {
         self.metadata.compiler
     }
 
+    fn paranoid(&self) -> bool {
+        self.metadata.options.paranoid
+    }
+
+    // The side of a branch on a stack limit check where rsp is above the limit is checked down to
+    // where the check put it (Wamr)
+    fn process_branch(
+        &self,
+        irmap: &IRMap,
+        in_state: &StackGrowthLattice,
+        succ_addrs: &Vec<u64>,
+        addr: &u64,
+    ) -> Vec<(u64, StackGrowthLattice)> {
+        if let (Compiler::Wamr, 2) = (self.compiler(), succ_addrs.len()) {
+            if let Some((taken, limit)) = self.stack_limit_check(&irmap.get(addr).unwrap(), in_state) {
+                let mut checked = in_state.clone();
+                if let Some((_, _, _, _, _, lowest, _)) = &mut checked.v {
+                    *lowest = Some(lowest.map_or(limit, |lowest| std::cmp::min(lowest, limit)));
+                }
+                // align_succ_addrs puts the fall-through successor first and the branch target second
+                return if taken {
+                    vec![(succ_addrs[0], in_state.clone()), (succ_addrs[1], checked)]
+                } else {
+                    vec![(succ_addrs[0], checked), (succ_addrs[1], in_state.clone())]
+                };
+            }
+        }
+        succ_addrs.iter().map(|succ| (*succ, in_state.clone())).collect()
+    }
+
     fn aexec(&self, in_state: &mut StackGrowthLattice, ir_instr: &Stmt, loc_idx: &LocIdx) -> () {
+        // a write to esp, sp or spl loses the stack state, which the stack checker reports
+        if writes_narrow_rsp(ir_instr) {
+            in_state.v = None;
+            return;
+        }
         match self.compiler() {
-            Compiler::Lucet => self.lucet_aexec(in_state, ir_instr, loc_idx),
+            // Wasmtime code comes from Cranelift too
+            Compiler::Lucet | Compiler::Wasmtime => self.lucet_aexec(in_state, ir_instr, loc_idx),
             Compiler::Wamr => self.wamr_aexec(in_state, ir_instr, loc_idx),
         }
+        track_frame_pointer(in_state, ir_instr);
+        track_allocation(in_state);
     }
 }
 
 impl StackAnalyzer {
+    /// Whether `ir_block`, whose stack state at its end is `state`, ends in a conditional branch on
+    /// its last stack limit check of `rsp + c` (`cmp rsp, limit`, or `lea rax, [rsp+c]; cmp rax,
+    /// limit`), and if so, whether rsp + c is above the limit on the taken side, and the
+    /// stackgrowth rsp + c is at. rsp can't be written between computing rsp + c and the branch.
+    pub fn stack_limit_check(&self, ir_block: &IRBlock, state: &StackGrowthLattice) -> Option<(bool, i64)> {
+        let stmts: Vec<(LocIdx, &Stmt)> = ir_block
+            .iter()
+            .flat_map(|(addr, ir_stmts)| ir_stmts.iter().enumerate().map(move |(idx, stmt)| (LocIdx { addr: *addr, idx: idx as u32 }, stmt)))
+            .collect();
+        let cmp = stmts.iter().rposition(|(loc, _)| self.stack_limit_cmps.contains_key(loc))?;
+        let (cmp_loc, limit_first) = (stmts[cmp].0, self.stack_limit_cmps[&stmts[cmp].0]);
+        let checked = match stmts[cmp].1 {
+            Stmt::Binop(Binopcode::Cmp, _, src1, src2) => if limit_first { src2 } else { src1 },
+            _ => return None,
+        };
+        // where rsp + c was computed, and c
+        let (def, offset) = if is_rsp(checked) {
+            (cmp, 0)
+        } else {
+            let def = stmts[..cmp].iter().rposition(|(_, stmt)| writes_reg(stmt, checked))?;
+            let offset = match stmts[def].1 {
+                Stmt::Unop(Unopcode::Mov, _, src) if is_rsp(src) => 0,
+                Stmt::Binop(opcode, _, src1, src2) if is_rsp(src1) && matches!(opcode, Binopcode::Add | Binopcode::Sub) => {
+                    rsp_adjustment(opcode, src2)?
+                }
+                _ => return None,
+            };
+            (def, offset)
+        };
+        if stmts[def..].iter().any(|(_, stmt)| writes_rsp(stmt)) || !flags_reach_branch(ir_block, &cmp_loc) {
+            return None;
+        }
+        // the limit is the cmp's `index`, and checked in bounds when below rsp + c
+        let (taken, _strict) = in_bounds_side(&branch_opcode(ir_block)?, limit_first)?;
+        Some((taken, state.get_stackgrowth()? + offset))
+    }
+
+    /// Whether `stmt` leaves %rsp somewhere this analysis doesn't follow (so that it no longer
+    /// points into the frame under analysis), given the stack state right before it.
+    pub fn loses_rsp(&self, state: &StackGrowthLattice, stmt: &Stmt) -> bool {
+        if writes_narrow_rsp(stmt) {
+            return true;
+        }
+        match stmt {
+            Stmt::Clear(dst, _) => is_rsp(dst),
+            Stmt::Unop(_, dst, src) => is_rsp(dst) && !(is_frame_pointer(src) && state.get_frame_pointer().is_some()),
+            Stmt::Binop(Binopcode::Cmp, _, _, _) | Stmt::Binop(Binopcode::Test, _, _, _) => false,
+            Stmt::Binop(opcode, dst, src1, src2) if is_rsp(dst) => {
+                !is_rsp(src1) || rsp_adjustment(opcode, src2).is_none()
+            }
+            _ => false,
+        }
+    }
+
     fn lucet_aexec(&self, in_state: &mut StackGrowthLattice, ir_instr: &Stmt, _loc_idx: &LocIdx) -> () {
         match ir_instr {
             Stmt::Clear(dst, _) => {
@@ -35,8 +135,8 @@ impl StackAnalyzer {
                     in_state.clear();
                 }
             }
-            Stmt::Unop(_, dst, _) => {
-                if is_rsp(dst) {
+            Stmt::Unop(_, dst, src) => {
+                if is_rsp(dst) && !restore_frame(in_state, src) {
                     in_state.clear();
                 }
             }
@@ -45,25 +145,30 @@ impl StackAnalyzer {
             Stmt::Binop(opcode, dst, src1, src2) => {
                 if is_rsp(dst) {
                     if is_rsp(src1) {
-                        let offset = get_imm_offset(src2);
-                        if let Some((x, probestack, _)) = &mut in_state.v {
-                            match opcode {
-                                Binopcode::Add => {
-                                    *x += offset;
-                                }
-                                Binopcode::Sub => {
-                                    if (offset - *x) > *probestack + 4096 {
-                                        panic!("Probestack, _ violation")
-                                    } else if (offset - *x) > *probestack {
-                                        //if we touch next page after the space
-                                        //we've probed, it cannot skip guard page
-                                        *x -= offset;
-                                        *probestack += 4096;
-                                        return;
-                                    }
+                        // an RSP write we can't follow loses the stack state, which the stack
+                        // checker reports
+                        let adjustment = match rsp_adjustment(opcode, src2) {
+                            Some(adjustment) => adjustment,
+                            None => {
+                                in_state.v = None;
+                                return;
+                            }
+                        };
+                        if let Some((x, probestack, _, _, _, _, _)) = &mut in_state.v {
+                            if adjustment >= 0 {
+                                *x += adjustment;
+                            } else {
+                                let offset = -adjustment;
+                                if (offset - *x) > *probestack + 4096 {
+                                    panic!("Probestack, _ violation")
+                                } else if (offset - *x) > *probestack {
+                                    //if we touch next page after the space
+                                    //we've probed, it cannot skip guard page
                                     *x -= offset;
+                                    *probestack += 4096;
+                                    return;
                                 }
-                                _ => panic!("Illegal RSP write"),
+                                *x -= offset;
                             }
                         } else {
                             in_state.clear();
@@ -74,7 +179,7 @@ impl StackAnalyzer {
                 }
             }
             Stmt::ProbeStack(new_probestack) => {
-                if let Some((x, probestack, _)) = &mut in_state.v {
+                if let Some((x, probestack, _, _, _, _, _)) = &mut in_state.v {
                     let probed = (((*new_probestack / 4096) + 1) * 4096) as i64; // Assumes page size of 4096
                     *x -= *new_probestack as i64;
                     *probestack = probed;
@@ -95,6 +200,8 @@ impl StackAnalyzer {
                 }
             },
             Stmt::Unop(opcode, dst, src) => self.wamr_handle_unop(in_state, opcode, dst, src),
+            Stmt::Binop(Binopcode::Cmp, _, _, _) => (),
+            Stmt::Binop(Binopcode::Test, _, _, _) => (),
             Stmt::Binop(opcode, dst, src1, src2) => self.wamr_handle_binop(in_state, opcode, dst, src1, src2),
             _ => (),
         }
@@ -102,55 +209,32 @@ impl StackAnalyzer {
 
     fn wamr_handle_unop(&self, in_state: &mut StackGrowthLattice, 
                          _opcode: &Unopcode, dst: &Value, src: &Value) -> () {
-        // arbitrarily modifying RSP should invalidate all our analysis
-        if is_rsp(dst) {
+        // arbitrarily modifying RSP should invalidate all our analysis, unless it goes back to
+        // the frame pointer
+        if is_rsp(dst) && !restore_frame(in_state, src) {
             in_state.clear();
-        } 
-
-        // if a callee-saved register is being stored to a stack offset, keep track of it
-        // internally to ensure it's not modified during the function, and is restored properly.
-        if is_callee_saved_reg(src) {
-            if let Value::Reg(regnum, regsize) = src {
-                if let Value::Mem(_memsize, memargs) = dst {
-                    if let MemArgs::Mem1Arg(memarg) = memargs {
-                        if memarg_is_stack(memarg) {
-                            assert!(regsize.to_u32() == 64);
-                            if let Some((stack_growth, _probestack, saved)) = &mut in_state.v {
-                                // pushing a callee-saved register
-                                assert!(*stack_growth <= 0, 
-                                        "stack growth should be within the current stack frame!");
-                                assert!(!saved.contains_key(regnum), 
-                                        "pushed the same register twice!");
-                                saved.insert(*regnum, *stack_growth);
-                            } else {
-                                panic!("pushing callee-saved register without any known stack state!");
-                            }
-                        }
-                    }
-                }
-            }
         }
-        // if a callee-saved register is being restored from the stack, let's make sure that the
-        // stack offset matches the offset when it was pushed.
-        if is_callee_saved_reg(dst) {
-            if let Value::Reg(regnum, regsize) = dst {
-                if let Value::Mem(_memsize, memargs) = src {
-                    if let MemArgs::Mem1Arg(memarg) = memargs {
-                        if memarg_is_stack(memarg) {
-                            assert!(regsize.to_u32() == 64);
-                            if let Some((stack_growth, _probestack, saved)) = &mut in_state.v {
-                                // popping a callee-saved register
-                                assert!(saved.contains_key(regnum), 
-                                        "popping register that was never pushed!");
-                                assert!(*saved.get(regnum).unwrap() == *stack_growth, 
-                                        "mismatched push/pop for callee-saved register!");
-                                saved.remove(regnum);
-                            } else {
-                                panic!("pushing callee-saved register without any known stack state!");
-                            }
-                        }
-                    }
-                }
+
+        // a save or restore that doesn't match up loses the stack state; the stack checker
+        // reports why at this statement
+        if wamr_saved_reg_error(in_state, dst, src).is_some() {
+            in_state.v = None;
+            return;
+        }
+        let (save, restore) = (saved_reg(in_state, dst, src), restored_reg(in_state, dst, src));
+        if let Some((_stack_growth, _probestack, saved, raised, _, _, _)) = &mut in_state.v {
+            // if a callee-saved register is being stored to a stack offset, keep track of it
+            // internally to ensure it's not modified during the function, and is restored properly.
+            if let Some((regnum, slot)) = save {
+                saved.insert(regnum, slot);
+            }
+            // if a callee-saved register is being restored from the stack, the stack offset
+            // matches the offset when it was pushed (checked above)
+            if let Some((regnum, _)) = restore {
+                saved.remove(&regnum);
+            }
+            if is_exception_store(dst) {
+                *raised = true;
             }
         }
         // It's possible that the analysis above is not precise (it may accidentally identify
@@ -163,24 +247,307 @@ impl StackAnalyzer {
         // handle RSP modifications
         if is_rsp(dst) {
             if is_rsp(src1) {
-                let offset = get_imm_offset(src2);
-                if let Some((x, _, _)) = &mut in_state.v {
-                    match opcode {
-                        Binopcode::Add => {
-                            *x += offset;
-                        }
-                        Binopcode::Sub => {
-                            if (offset - *x) < WAMR_STACK_LOWER_BOUND {
-                                panic!("Stack growing past guard pages!")
-                            }
-                            *x -= offset;
+                let adjustment = match rsp_adjustment(opcode, src2) {
+                    Some(adjustment) => adjustment,
+                    None => {
+                        in_state.v = None;
+                        return;
+                    }
+                };
+                if let Some((x, _, _, _, _, _, _)) = &mut in_state.v {
+                    if adjustment >= 0 {
+                        *x += adjustment;
+                    } else {
+                        let offset = -adjustment;
+                        if (offset - *x) < WAMR_STACK_LOWER_BOUND {
+                            panic!("Stack growing past guard pages!")
                         }
-                        _ => panic!("Illegal RSP write"),
+                        *x -= offset;
                     }
                 } else {
                     in_state.clear();
                 }
+            } else {
+                in_state.clear();
+            }
+        }
+    }
+}
+
+// The callee-saved register `dst <- src` saves to the stack, and where (as a stack growth), if it
+// saves one: a push, or a store to a frame slot (`mov [rsp+0x10], rbx`) of a register that isn't
+// saved yet. A store of one that is already saved is just a spill.
+fn saved_reg(state: &StackGrowthLattice, dst: &Value, src: &Value) -> Option<(u8, i64)> {
+    let stack_growth = state.v.as_ref().map(|(stack_growth, _, _, _, _, _, _)| *stack_growth);
+    match (dst, src) {
+        (Value::Mem(_, MemArgs::Mem1Arg(memarg)), Value::Reg(regnum, _))
+            if is_callee_saved_reg(src) && memarg_is_stack(memarg) => Some((*regnum, stack_growth.unwrap_or(0))),
+        (Value::Mem(_, MemArgs::Mem2Args(memarg, MemArg::Imm(_, _, offset))), Value::Reg(regnum, _))
+            if is_callee_saved_reg(src) && memarg_is_stack(memarg) => match &state.v {
+                Some((stack_growth, _, saved, _, _, _, _)) if !saved.contains_key(regnum) => Some((*regnum, stack_growth + offset)),
+                _ => None,
+            },
+        _ => None,
+    }
+}
+
+// The callee-saved register `dst <- src` restores from the stack, and where from, if it restores
+// one: a pop, or a load from the slot it was saved to (`mov rbx, [rsp+0x10]`, as epilogues that
+// adjust rsp once do). A load from any other slot is just a reload of a spill.
+fn restored_reg(state: &StackGrowthLattice, dst: &Value, src: &Value) -> Option<(u8, i64)> {
+    let stack_growth = state.v.as_ref().map(|(stack_growth, _, _, _, _, _, _)| *stack_growth);
+    match (dst, src) {
+        (Value::Reg(regnum, _), Value::Mem(_, MemArgs::Mem1Arg(memarg)))
+            if is_callee_saved_reg(dst) && memarg_is_stack(memarg) => Some((*regnum, stack_growth.unwrap_or(0))),
+        (Value::Reg(regnum, _), Value::Mem(_, MemArgs::Mem2Args(memarg, MemArg::Imm(_, _, offset))))
+            if is_callee_saved_reg(dst) && memarg_is_stack(memarg) => match &state.v {
+                Some((stack_growth, _, saved, _, _, _, _)) if saved.get(regnum) == Some(&(stack_growth + offset)) => {
+                    Some((*regnum, stack_growth + offset))
+                }
+                _ => None,
+            },
+        _ => None,
+    }
+}
+
+fn is_frame_pointer(v: &Value) -> bool {
+    match v {
+        Value::Reg(5, ValSize::Size64) => true,
+        _ => false,
+    }
+}
+
+// Once rsp goes below where it was at entry, the path has allocated a frame, even after freeing it
+fn track_allocation(state: &mut StackGrowthLattice) {
+    if let Some((stack_growth, _, _, _, _, _, allocated)) = &mut state.v {
+        if *stack_growth < 0 {
+            *allocated = true;
+        }
+    }
+}
+
+// `mov rsp, src` going back to the stackgrowth %rbp was set at, if src is %rbp and it is a frame
+// pointer (as in `leave`)
+fn restore_frame(state: &mut StackGrowthLattice, src: &Value) -> bool {
+    if let (true, Some((stack_growth, _, _, _, Some(frame_pointer), _, _))) = (is_frame_pointer(src), &mut state.v) {
+        *stack_growth = *frame_pointer;
+        return true;
+    }
+    false
+}
+
+// Keeps track of whether %rbp is a frame pointer: `mov rbp, rsp` (or `lea rbp, [rsp+c]`) makes it
+// one, adding to or subtracting from it keeps it one, and any other write to it doesn't
+fn track_frame_pointer(state: &mut StackGrowthLattice, stmt: &Stmt) {
+    let is_rbp = |v: &Value| match v {
+        Value::Reg(5, _) => true,
+        _ => false,
+    };
+    let is_rbp64 = |v: &Value| match v {
+        Value::Reg(5, ValSize::Size64) => true,
+        _ => false,
+    };
+    if let Some((stack_growth, _, _, _, frame_pointer, _, _)) = &mut state.v {
+        match stmt {
+            Stmt::Unop(Unopcode::Mov, dst, src) if is_rbp64(dst) && is_rsp(src) => *frame_pointer = Some(*stack_growth),
+            Stmt::Binop(Binopcode::Cmp, _, _, _) | Stmt::Binop(Binopcode::Test, _, _, _) => (),
+            Stmt::Binop(opcode, dst, src1, src2) if is_rbp64(dst) && (is_rsp(src1) || is_rbp64(src1)) => {
+                let base = if is_rsp(src1) { Some(*stack_growth) } else { *frame_pointer };
+                *frame_pointer = match (opcode, base, rsp_adjustment(opcode, src2)) {
+                    (Binopcode::Add, Some(base), Some(adjustment)) | (Binopcode::Sub, Some(base), Some(adjustment)) => {
+                        Some(base + adjustment)
+                    }
+                    _ => None,
+                };
+            }
+            Stmt::Unop(Unopcode::Xchg, dst, src) if is_rbp(dst) || is_rbp(src) => *frame_pointer = None,
+            Stmt::Clear(dst, _) | Stmt::Unop(_, dst, _) | Stmt::Binop(_, dst, _, _) if is_rbp(dst) => *frame_pointer = None,
+            _ => (),
+        }
+    }
+}
+
+// A store to the exception field of what is taken to be the ModuleInstance, which is how Wamr
+// raises an exception before leaving through its exception epilogue
+fn is_exception_store(dst: &Value) -> bool {
+    match dst {
+        Value::Mem(_, MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, WAMR_EXCEPTION_OFFSET))) => *regnum != 4,
+        _ => false,
+    }
+}
+
+/// What is wrong with `dst <- src` saving or restoring a callee-saved register in `state`, if it
+/// does one and something is (Wamr).
+pub fn wamr_saved_reg_error(state: &StackGrowthLattice, dst: &Value, src: &Value) -> Option<String> {
+    if let Some((regnum, slot)) = saved_reg(state, dst, src) {
+        if let Value::Reg(_, regsize) = src {
+            if regsize.to_u32() != 64 {
+                return Some(format!("saving part of callee-saved register r{}", regnum));
+            }
+        }
+        return match &state.v {
+            None => Some(format!("saving callee-saved register r{} without a known stack state", regnum)),
+            Some(_) if slot > 0 => {
+                Some(format!("saving callee-saved register r{} outside the current stack frame", regnum))
+            }
+            Some((_, _, saved, _, _, _, _)) if saved.contains_key(&regnum) => {
+                Some(format!("saving callee-saved register r{} twice", regnum))
+            }
+            Some(_) => None,
+        };
+    }
+    if let Some((regnum, slot)) = restored_reg(state, dst, src) {
+        if let Value::Reg(_, regsize) = dst {
+            if regsize.to_u32() != 64 {
+                return Some(format!("restoring part of callee-saved register r{}", regnum));
+            }
+        }
+        return match &state.v {
+            None => Some(format!("restoring callee-saved register r{} without a known stack state", regnum)),
+            Some((_, _, saved, _, _, _, _)) => match saved.get(&regnum) {
+                None => Some(format!("restoring callee-saved register r{} that was never saved", regnum)),
+                Some(offset) if *offset != slot => Some(format!(
+                    "restoring callee-saved register r{} from stack growth {}, but it was saved at {}",
+                    regnum, slot, offset
+                )),
+                Some(_) => None,
+            },
+        };
+    }
+    None
+}
+
+// Whether `stmt` writes register `reg` (which must be a register)
+fn writes_reg(stmt: &Stmt, reg: &Value) -> bool {
+    let regnum = match reg {
+        Value::Reg(regnum, _) => regnum,
+        _ => return false,
+    };
+    match stmt {
+        Stmt::Binop(Binopcode::Cmp, _, _, _) | Stmt::Binop(Binopcode::Test, _, _, _) => false,
+        Stmt::Clear(Value::Reg(dst, _), _) | Stmt::Unop(_, Value::Reg(dst, _), _) | Stmt::Binop(_, Value::Reg(dst, _), _, _) => dst == regnum,
+        Stmt::Unop(Unopcode::Xchg, _, Value::Reg(src, _)) => src == regnum,
+        Stmt::Call(_, _) => true,
+        _ => false,
+    }
+}
+
+// Whether `stmt` may move rsp
+fn writes_rsp(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Call(_, _) | Stmt::Ret | Stmt::ProbeStack(_) => true,
+        _ => writes_reg(stmt, &Value::Reg(4, ValSize::Size64)) || writes_narrow_rsp(stmt),
+    }
+}
+
+// Whether `v` is the Wamr stack limit according to the heap state `state`: a register holding it,
+// or a read of it out of the ExecEnv
+fn is_stack_limit(state: &HeapLattice, v: &Value) -> bool {
+    match v {
+        Value::Reg(regnum, ValSize::Size64) => state.regs.get(regnum, &ValSize::Size64).v == Some(HeapValue::WamrStackLimit),
+        Value::Mem(ValSize::Size64, memargs) => wamr_is_stacklimit_access(state, memargs),
+        _ => false,
+    }
+}
+
+/// The cmps of a 64-bit register against the stack limit in the function's ExecEnv, found from
+/// the heap analysis of a Wamr function. Other functions have none.
+pub fn wamr_stack_limit_cmps(
+    irmap: &IRMap,
+    heap_result: &AnalysisResult<HeapLattice>,
+    heap_analyzer: &HeapAnalyzer,
+) -> StackLimitCmps {
+    let mut cmps = StackLimitCmps::new();
+    if heap_analyzer.metadata.compiler != Compiler::Wamr {
+        return cmps;
+    }
+    let is_reg = |v: &Value| matches!(v, Value::Reg(_, ValSize::Size64));
+    for (block_addr, state) in heap_result.iter() {
+        let mut state = state.clone();
+        for (addr, ir_stmts) in irmap.get(block_addr).unwrap().iter() {
+            for (idx, ir_stmt) in ir_stmts.iter().enumerate() {
+                let loc = LocIdx { addr: *addr, idx: idx as u32 };
+                if let Stmt::Binop(Binopcode::Cmp, _, src1, src2) = ir_stmt {
+                    match (is_stack_limit(&state, src1), is_stack_limit(&state, src2)) {
+                        (true, false) if is_reg(src2) => {
+                            cmps.insert(loc, true);
+                        }
+                        (false, true) if is_reg(src1) => {
+                            cmps.insert(loc, false);
+                        }
+                        _ => (),
+                    }
+                }
+                heap_analyzer.aexec(&mut state, ir_stmt, &loc);
+            }
+        }
+    }
+    cmps
+}
+
+/// The statements at which %rsp no longer points into the frame under analysis, each with the
+/// address of the instruction that lost track of it.
+pub type UntrackedRsp = HashMap<LocIdx, u64>;
+
+// Propagates the first place the stack analysis lost track of rsp along each path: a statement
+// in `losses` loses it, at the address it maps to
+struct RspAnalyzer<'a> {
+    losses: &'a HashMap<LocIdx, u64>,
+}
+
+impl AbstractAnalyzer<RspTracking> for RspAnalyzer<'_> {
+    fn aexec(&self, in_state: &mut RspTracking, _ir_instr: &Stmt, loc_idx: &LocIdx) -> () {
+        if in_state.lost_at.is_none() {
+            in_state.lost_at = self.losses.get(loc_idx).copied();
+        }
+    }
+}
+
+/// Where %rsp is no longer tracked by the stack analysis: after a write to it the analysis can't
+/// follow (see `StackAnalyzer::loses_rsp`), and wherever the stack state is unknown. Accesses
+/// through %rsp there aren't known to hit the stack.
+pub fn untracked_rsp<C: ControlFlow>(
+    cfg: &C,
+    irmap: &IRMap,
+    stack_result: &AnalysisResult<StackGrowthLattice>,
+    analyzer: &StackAnalyzer,
+) -> UntrackedRsp {
+    // statements after which rsp is lost, and statements reached without a stack state (with the
+    // address it was first unknown at in their block)
+    let mut losses = HashMap::new();
+    let mut unknown = HashMap::new();
+    for (block_addr, state) in stack_result.iter() {
+        let mut state = state.clone();
+        let mut unknown_since = None;
+        for (addr, ir_stmts) in irmap.get(block_addr).unwrap().iter() {
+            for (idx, ir_stmt) in ir_stmts.iter().enumerate() {
+                let loc = LocIdx { addr: *addr, idx: idx as u32 };
+                if state.v.is_none() {
+                    let since = *unknown_since.get_or_insert(*addr);
+                    unknown.insert(loc, since);
+                    losses.insert(loc, since);
+                } else if analyzer.loses_rsp(&state, ir_stmt) {
+                    losses.insert(loc, *addr);
+                }
+                analyzer.aexec(&mut state, ir_stmt, &loc);
+            }
+        }
+    }
+
+    let rsp_analyzer = RspAnalyzer { losses: &losses };
+    let rsp_result = run_worklist(cfg, irmap, &rsp_analyzer);
+    let mut untracked = UntrackedRsp::new();
+    for (block_addr, mut state) in rsp_result {
+        for (addr, ir_stmts) in irmap.get(&block_addr).unwrap().iter() {
+            for (idx, ir_stmt) in ir_stmts.iter().enumerate() {
+                let loc = LocIdx { addr: *addr, idx: idx as u32 };
+                // an unknown stack state without an earlier loss is where paths that disagree met
+                if let Some(lost_at) = state.lost_at.or_else(|| unknown.get(&loc).copied()) {
+                    untracked.insert(loc, lost_at);
+                }
+                rsp_analyzer.aexec(&mut state, ir_stmt, &loc);
             }
         }
     }
+    untracked
 }
@@ -0,0 +1,140 @@
+use crate::analyses::AbstractAnalyzer;
+use crate::lattices::heaplattice::WAMR_GLOBALS_OFFSET;
+use crate::lattices::reachingdefslattice::LocIdx;
+use crate::lattices::taintlattice::TaintLattice;
+use crate::lattices::{BooleanLattice, VarState};
+use crate::utils::compiler::{Compiler, CompilerMetadata};
+use crate::utils::ir::{dividend, Binopcode, IRBlock, IRMap, MemArg, MemArgs, Stmt, Unopcode, ValSize, Value};
+use crate::utils::ir_utils::{is_stack_access, is_zf};
+
+/* Tracks which values may come from guest-controlled data without having been bounds checked.
+ * Values loaded from linear memory or from guest globals are tainted, as are function arguments
+ * and call results. A value is cleaned by masking it with an immediate, or by a cmp on the side
+ * of the following branch that the call analyzer treats as in-bounds.
+ * Loads through a clean pointer plus a constant are treated as loads of runtime metadata
+ * (ModuleInstance fields, table pointers, ...), which is what they are in practice; the one
+ * exception is the Wamr globals area.
+ */
+pub struct TaintAnalyzer {
+    pub metadata: CompilerMetadata,
+}
+
+impl AbstractAnalyzer<TaintLattice> for TaintAnalyzer {
+    fn init_state(&self) -> TaintLattice {
+        let mut result: TaintLattice = Default::default();
+        // rdi is the HeapBase (Lucet) or ExecEnv (Wamr), rsp is the stack pointer
+        result.regs.set(&7, &ValSize::Size64, BooleanLattice::new(true));
+        result.regs.set(&4, &ValSize::Size64, BooleanLattice::new(true));
+        result
+    }
+
+    fn compiler(&self) -> Compiler {
+        self.metadata.compiler
+    }
+
+    fn paranoid(&self) -> bool {
+        self.metadata.options.paranoid
+    }
+
+    fn aexec(&self, in_state: &mut TaintLattice, ir_instr: &Stmt, _loc_idx: &LocIdx) -> () {
+        match ir_instr {
+            Stmt::Clear(dst, srcs) => {
+                let clean = srcs.iter().all(|src| self.aeval(in_state, src).is_true());
+                in_state.set(dst, BooleanLattice::new(clean))
+            }
+            Stmt::Unop(Unopcode::Set, dst, _) => in_state.set(dst, BooleanLattice::new(true)),
+            Stmt::Unop(Unopcode::Mov, dst, src) | Stmt::Unop(Unopcode::MovZx, dst, src) | Stmt::Unop(Unopcode::MovSx, dst, src) => {
+                let v = self.aeval(in_state, src);
+                in_state.set(dst, v)
+            }
+            Stmt::Binop(Binopcode::Cmp, _, _, _) | Stmt::Binop(Binopcode::Test, _, _, _) => (),
+            // a division reads both halves of the dividend (the remainder's low half being the
+            // quotient by then, which is as tainted as the dividend was)
+            Stmt::Binop(Binopcode::Div, dst, _, src2) | Stmt::Binop(Binopcode::Idiv, dst, _, src2) => {
+                let (high, low) = dividend(dst);
+                let clean = [high, low, src2.clone()].iter().all(|src| self.aeval(in_state, src).is_true());
+                in_state.set(dst, BooleanLattice::new(clean))
+            }
+            Stmt::Binop(opcode, dst, src1, src2) => {
+                let v = match (opcode, src2) {
+                    (Binopcode::And, Value::Imm(_, _, _)) => BooleanLattice::new(true),
+                    _ => BooleanLattice::new(
+                        self.aeval(in_state, src1).is_true() && self.aeval(in_state, src2).is_true(),
+                    ),
+                };
+                in_state.set(dst, v);
+                in_state.adjust_stack_offset(opcode, dst, src1, src2)
+            }
+            Stmt::Call(_, _) => in_state.on_call(self.compiler()),
+            _ => (),
+        }
+    }
+
+    fn process_branch(
+        &self,
+        irmap: &IRMap,
+        in_state: &TaintLattice,
+        succ_addrs: &Vec<u64>,
+        addr: &u64,
+    ) -> Vec<(u64, TaintLattice)> {
+        let mut succs: Vec<(u64, TaintLattice)> =
+            succ_addrs.iter().map(|succ| (*succ, in_state.clone())).collect();
+        if succ_addrs.len() == 2 {
+            if let Some(regnum) = irmap.get(addr).and_then(|block| last_compared_reg(&block)) {
+                // same orientation as the call analyzer's bounds check refinement
+                let checked = match self.compiler() {
+                    Compiler::Lucet | Compiler::Wasmtime => 1,
+                    Compiler::Wamr => 0,
+                };
+                succs[checked].1.regs.set(&regnum, &ValSize::Size64, BooleanLattice::new(true));
+            }
+        }
+        succs
+    }
+}
+
+// the register compared by the last flag-setting statement of a block, if that is a cmp
+fn last_compared_reg(ir_block: &IRBlock) -> Option<u8> {
+    for (_addr, ir_stmts) in ir_block.iter().rev() {
+        for ir_stmt in ir_stmts.iter().rev() {
+            match ir_stmt {
+                Stmt::Binop(Binopcode::Cmp, _, Value::Reg(regnum, _), _) => return Some(*regnum),
+                Stmt::Binop(Binopcode::Cmp, _, _, _) | Stmt::Binop(Binopcode::Test, _, _, _) => return None,
+                Stmt::Clear(dst, _) if is_zf(dst) => return None,
+                _ => (),
+            }
+        }
+    }
+    None
+}
+
+impl TaintAnalyzer {
+    pub fn aeval(&self, in_state: &mut TaintLattice, value: &Value) -> BooleanLattice {
+        match value {
+            Value::Imm(_, _, _) => BooleanLattice::new(true),
+            Value::Reg(_, ValSize::SizeOther) => Default::default(),
+            Value::Reg(regnum, size) => in_state.regs.get(regnum, size),
+            Value::Mem(_, memargs) => {
+                if is_stack_access(value) {
+                    return in_state.get(value).unwrap_or_default();
+                }
+                BooleanLattice::new(self.is_metadata_load(in_state, memargs))
+            }
+        }
+    }
+
+    fn is_metadata_load(&self, in_state: &TaintLattice, memargs: &MemArgs) -> bool {
+        match memargs {
+            MemArgs::Mem1Arg(MemArg::Reg(regnum, size)) => in_state.regs.get(regnum, size).is_true(),
+            MemArgs::Mem2Args(MemArg::Reg(regnum, size), MemArg::Imm(_, _, offset)) => {
+                if let Compiler::Wamr = self.compiler() {
+                    if *offset >= WAMR_GLOBALS_OFFSET - 8 {
+                        return false;
+                    }
+                }
+                in_state.regs.get(regnum, size).is_true()
+            }
+            _ => false,
+        }
+    }
+}
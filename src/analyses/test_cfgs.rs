@@ -0,0 +1,63 @@
+//! Control flow graphs of a few blocks, for running analyses over IRMaps built by hand in tests.
+use crate::analyses::ControlFlow;
+
+/// One block, starting at the given address
+pub struct SingleBlock(pub u64);
+
+impl ControlFlow for SingleBlock {
+    fn entrypoint(&self) -> u64 { self.0 }
+    fn successors(&self, _addr: u64) -> Vec<u64> { vec![] }
+}
+
+/// 0 branches to 0x10 and 0x20, which both end the function
+pub struct Fork;
+
+impl ControlFlow for Fork {
+    fn entrypoint(&self) -> u64 { 0 }
+    fn successors(&self, addr: u64) -> Vec<u64> {
+        if addr == 0 { vec![0x10, 0x20] } else { vec![] }
+    }
+}
+
+/// 0 falls through to 0x10 or jumps to 0x20, and 0x10 falls through to 0x20
+pub struct Triangle;
+
+impl ControlFlow for Triangle {
+    fn entrypoint(&self) -> u64 { 0 }
+    fn successors(&self, addr: u64) -> Vec<u64> {
+        match addr {
+            0 => vec![0x10, 0x20],
+            0x10 => vec![0x20],
+            _ => vec![],
+        }
+    }
+}
+
+/// 0 branches to 0x10 and 0x20, which both continue at 0x30
+pub struct Diamond;
+
+impl ControlFlow for Diamond {
+    fn entrypoint(&self) -> u64 { 0 }
+    fn successors(&self, addr: u64) -> Vec<u64> {
+        match addr {
+            0 => vec![0x10, 0x20],
+            0x10 | 0x20 => vec![0x30],
+            _ => vec![],
+        }
+    }
+}
+
+/// A loop through the entry block: 0 falls through to 0x10, which jumps back to 0 or falls
+/// through to 0x20
+pub struct EntryLoop;
+
+impl ControlFlow for EntryLoop {
+    fn entrypoint(&self) -> u64 { 0 }
+    fn successors(&self, addr: u64) -> Vec<u64> {
+        match addr {
+            0 => vec![0x10],
+            0x10 => vec![0, 0x20],
+            _ => vec![],
+        }
+    }
+}
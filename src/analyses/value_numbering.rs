@@ -0,0 +1,230 @@
+use crate::utils::ir::{Binopcode, Extension, IRBlock, MemArg, MemArgs, Stmt, Unopcode, UpperBits, ValSize, Value};
+use std::collections::HashMap;
+
+/*
+ * Local value numbering: which registers hold the same value at the end of a block, even when it
+ * was computed twice (`mov eax, [rsp+0x10]; and eax, 0xffff; mov ecx, [rsp+0x10]; and ecx, 0xffff`)
+ * and so has different reaching defs. Each register content gets a number from the operation
+ * that produced it and the numbers of its operands; anything the numbering doesn't model gets a
+ * fresh number, so equal numbers always mean equal values. Loads are numbered by their address
+ * and are only equal between two writes to memory.
+ */
+
+pub type ValueNumber = u32;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Expr {
+    Const(u32, i64),
+    // the low bits of a value, and a value zero or sign extended from its low bits
+    Trunc(u32, ValueNumber),
+    ZeroExt(u32, ValueNumber),
+    SignExt(u32, ValueNumber),
+    Op(Binopcode, u32, ValueNumber, ValueNumber),
+    // the size of the load, the memory writes before it, and the numbers of its address operands
+    Load(u32, u32, Vec<Option<ValueNumber>>),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ValueNumbering {
+    regs: HashMap<u8, ValueNumber>,
+    exprs: HashMap<Expr, ValueNumber>,
+    next: ValueNumber,
+    // bumped by every write to memory, which may change what any load reads
+    memory_epoch: u32,
+}
+
+fn bits(size: &ValSize) -> u32 {
+    size.to_u32()
+}
+
+impl ValueNumbering {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn fresh(&mut self) -> ValueNumber {
+        self.next += 1;
+        self.next
+    }
+
+    fn number(&mut self, expr: Expr) -> ValueNumber {
+        if let Some(vn) = self.exprs.get(&expr) {
+            return *vn;
+        }
+        let vn = self.fresh();
+        self.exprs.insert(expr, vn);
+        vn
+    }
+
+    /// The number of the full (64-bit) content of register `regnum`. A register not written in
+    /// the block holds its entry value, which gets a number the first time it is asked for.
+    pub fn reg(&mut self, regnum: u8) -> ValueNumber {
+        if let Some(vn) = self.regs.get(&regnum) {
+            return *vn;
+        }
+        let vn = self.fresh();
+        self.regs.insert(regnum, vn);
+        vn
+    }
+
+    fn mem_arg(&mut self, arg: &MemArg) -> Option<ValueNumber> {
+        match arg {
+            MemArg::Reg(regnum, size) => Some(self.read_reg(*regnum, size)),
+            MemArg::Imm(_, size, v) => Some(self.number(Expr::Const(bits(size), *v))),
+        }
+    }
+
+    fn read_reg(&mut self, regnum: u8, size: &ValSize) -> ValueNumber {
+        match size {
+            // an xmm register, which isn't numbered
+            ValSize::SizeOther => self.fresh(),
+            ValSize::Size64 => self.reg(regnum),
+            _ => {
+                let vn = self.reg(regnum);
+                self.number(Expr::Trunc(bits(size), vn))
+            }
+        }
+    }
+
+    fn operand(&mut self, value: &Value) -> ValueNumber {
+        match value {
+            Value::Reg(regnum, size) => self.read_reg(*regnum, size),
+            Value::Imm(_, size, v) => self.number(Expr::Const(bits(size), *v)),
+            Value::Mem(size, memargs) => {
+                // the shape of the address is part of the key, so [a + b] and [a + b*1] differ
+                let args = match memargs {
+                    MemArgs::Mem1Arg(a) => vec![None, self.mem_arg(a)],
+                    MemArgs::Mem2Args(a, b) => vec![None, None, self.mem_arg(a), self.mem_arg(b)],
+                    MemArgs::Mem3Args(a, b, c) => vec![None, None, None, self.mem_arg(a), self.mem_arg(b), self.mem_arg(c)],
+                    MemArgs::MemScale(a, b, c) => vec![self.mem_arg(a), self.mem_arg(b), self.mem_arg(c)],
+                    MemArgs::MemScaleDisp(a, b, c, d) => vec![self.mem_arg(a), self.mem_arg(b), self.mem_arg(c), self.mem_arg(d)],
+                };
+                self.number(Expr::Load(bits(size), self.memory_epoch, args))
+            }
+        }
+    }
+
+    // Writes `vn` to `dst`: a 32-bit register write zero extends, and an 8 or 16-bit one merges
+    // with the old content, which isn't modelled
+    fn write(&mut self, dst: &Value, vn: Option<ValueNumber>) {
+        match dst {
+            Value::Reg(regnum, size) => {
+                let vn = match (size, dst.upper_bits(), vn) {
+                    // not a general purpose register, though it may share its number with one
+                    (ValSize::SizeOther, _, _) => self.fresh(),
+                    (_, UpperBits::Whole, Some(vn)) => vn,
+                    (_, UpperBits::Cleared, Some(vn)) => self.number(Expr::ZeroExt(32, vn)),
+                    _ => self.fresh(),
+                };
+                self.regs.insert(*regnum, vn);
+            }
+            Value::Mem(_, _) => self.memory_epoch += 1,
+            Value::Imm(_, _, _) => (),
+        }
+    }
+
+    fn clobber(&mut self) {
+        for regnum in 0..16 {
+            let vn = self.fresh();
+            self.regs.insert(regnum, vn);
+        }
+        self.memory_epoch += 1;
+    }
+
+    pub fn exec(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Unop(Unopcode::Mov, dst, src) | Stmt::Unop(Unopcode::MovZx, dst, src) | Stmt::Unop(Unopcode::MovSx, dst, src) => {
+                let kind = stmt.mov_kind().unwrap();
+                let mut vn = self.operand(src);
+                if kind.src_bits < src.width() * 8 {
+                    vn = self.number(Expr::Trunc(kind.src_bits, vn));
+                }
+                let extended = match kind.extension {
+                    Extension::None => vn,
+                    Extension::Zero => self.number(Expr::ZeroExt(kind.src_bits, vn)),
+                    Extension::Sign => self.number(Expr::SignExt(kind.src_bits, vn)),
+                };
+                self.write(dst, Some(extended));
+            }
+            Stmt::Unop(Unopcode::Xchg, dst, src) => {
+                self.write(dst, None);
+                self.write(src, None);
+            }
+            Stmt::Binop(Binopcode::Cmp, _, _, _) | Stmt::Binop(Binopcode::Test, _, _, _) => (),
+            // which source is left depends on the flags, which aren't numbered
+            Stmt::Binop(Binopcode::Cmov, dst, _, _) => self.write(dst, None),
+            // a division also reads the high half of the dividend, which isn't one of its sources
+            Stmt::Binop(Binopcode::Div, dst, _, _) | Stmt::Binop(Binopcode::Idiv, dst, _, _) => self.write(dst, None),
+            Stmt::Binop(opcode, dst, src1, src2) => {
+                let (mut vn1, mut vn2) = (self.operand(src1), self.operand(src2));
+                if let Binopcode::Add | Binopcode::And = opcode {
+                    if vn2 < vn1 {
+                        std::mem::swap(&mut vn1, &mut vn2);
+                    }
+                }
+                let vn = self.number(Expr::Op(opcode.clone(), dst.width() * 8, vn1, vn2));
+                self.write(dst, Some(vn));
+            }
+            Stmt::Clear(dst, _) | Stmt::Unop(Unopcode::Set, dst, _) => self.write(dst, None),
+            Stmt::Call(_, _) | Stmt::Undefined | Stmt::ProbeStack(_) => self.clobber(),
+            Stmt::Branch(_, _) | Stmt::Ret | Stmt::Forbidden(_) => (),
+        }
+    }
+}
+
+/// The value numbers at the end of `irblock`, numbering its entry values afresh.
+pub fn number_block(irblock: &IRBlock) -> ValueNumbering {
+    let mut numbering = ValueNumbering::new();
+    for (_addr, instruction) in irblock.iter() {
+        for stmt in instruction.iter() {
+            numbering.exec(stmt);
+        }
+    }
+    numbering
+}
+
+#[test]
+fn value_numbering_test() {
+    use crate::utils::ir::ImmType;
+
+    let reg32 = |regnum: u8| Value::Reg(regnum, ValSize::Size32);
+    let reg64 = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size32, v);
+    let slot = |offset: i64| Value::Mem(
+        ValSize::Size32,
+        MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)),
+    );
+    let load_and_mask = |regnum: u8, offset: i64| {
+        vec![
+            Stmt::Unop(Unopcode::Mov, reg32(regnum), slot(offset)),
+            Stmt::Binop(Binopcode::And, reg32(regnum), reg32(regnum), imm(0xffff)),
+        ]
+    };
+    let run = |stmts: Vec<Stmt>| {
+        let block = stmts.into_iter().enumerate().map(|(i, stmt)| (i as u64 * 4, vec![stmt])).collect();
+        number_block(&block)
+    };
+
+    // mov eax, [rsp+0x10]; and eax, 0xffff; mov ecx, [rsp+0x10]; and ecx, 0xffff
+    let mut numbering = run([load_and_mask(0, 0x10), load_and_mask(1, 0x10)].concat());
+    assert_eq!(numbering.reg(0), numbering.reg(1));
+    // a copy has the same number, and a different computation doesn't
+    let mut numbering = run([load_and_mask(0, 0x10), vec![Stmt::Unop(Unopcode::Mov, reg64(2), reg64(0))], load_and_mask(1, 0x18)].concat());
+    assert_eq!(numbering.reg(0), numbering.reg(2));
+    assert_ne!(numbering.reg(0), numbering.reg(1));
+
+    // a store in between may change what the second load reads
+    let store = Stmt::Unop(Unopcode::Mov, slot(0x20), reg32(3));
+    let mut numbering = run([load_and_mask(0, 0x10), vec![store], load_and_mask(1, 0x10)].concat());
+    assert_ne!(numbering.reg(0), numbering.reg(1));
+    // as may a call, which also clobbers the registers
+    let call = Stmt::Call(reg64(2), 2);
+    let mut numbering = run([load_and_mask(0, 0x10), vec![call], load_and_mask(1, 0x10)].concat());
+    assert_ne!(numbering.reg(0), numbering.reg(1));
+    // the operation itself isn't modelled for an 8-bit write
+    let mut numbering = run(vec![
+        Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size8), reg64(5)),
+        Stmt::Unop(Unopcode::Mov, Value::Reg(1, ValSize::Size8), reg64(5)),
+    ]);
+    assert_ne!(numbering.reg(0), numbering.reg(1));
+}
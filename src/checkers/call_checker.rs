@@ -1,17 +1,21 @@
 use crate::analyses::call_analyzer::CallAnalyzer;
 use crate::analyses::{AbstractAnalyzer, AnalysisResult};
-use crate::checkers::Checker;
+use crate::checkers::{CheckFailure, Checker};
 use crate::lattices::calllattice::{CallCheckLattice, CallCheckValue};
 use crate::lattices::davlattice::DAV;
 use crate::lattices::reachingdefslattice::LocIdx;
 use crate::lattices::heaplattice::WAMR_GLOBALS_OFFSET;
-use crate::utils::lifter::{IRMap, MemArg, MemArgs, Stmt, ValSize, Value};
-use crate::utils::utils::Compiler;
+use crate::utils::ir::{IRMap, MemArg, MemArgs, Stmt, ValSize, Value};
+use crate::utils::compiler::{Compiler, WamrTableRead};
+use crate::utils::compiler_model::CompilerModel;
+use crate::utils::ir_utils::{is_tail_dispatch, rel_target};
+use crate::utils::valid_funcs::ValidFuncs;
+use crate::utils::wasmtime_layout::{WASMTIME_ANYFUNC_FUNC_PTR_OFFSET, WASMTIME_TABLE_ENTRY_SIZE};
 
 pub struct CallChecker<'a> {
     irmap: &'a IRMap,
     analyzer: &'a CallAnalyzer,
-    funcs: &'a Vec<u64>,
+    funcs: &'a ValidFuncs,
     plt: &'a (u64,u64),
     // x86_64_data: &x86_64Data,
 }
@@ -20,10 +24,10 @@ pub fn check_calls(
     result: AnalysisResult<CallCheckLattice>,
     irmap: &IRMap,
     analyzer: &CallAnalyzer,
-    funcs: &Vec<u64>,
+    funcs: &ValidFuncs,
     plt: &(u64,u64),
     // x86_64_data: &x86_64Data,
-) -> bool {
+) -> Result<(), CheckFailure> {
     CallChecker {
         irmap,
         analyzer,
@@ -34,8 +38,90 @@ pub fn check_calls(
     .check(result)
 }
 
+/// Which rule accepted the target of a direct call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallTargetRule {
+    GuestFunction,
+    PltSlot,
+}
+
+/// A direct call site, with its target and the rule that accepted it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectCallSite {
+    pub addr: u64,
+    pub target: u64,
+    pub rule: CallTargetRule,
+}
+
+/// Whether `target` is in the PLT range `plt`. A module without a PLT (a PE, or an ELF without
+/// `.plt`) has an empty range, which no target is in.
+pub fn in_plt(target: u64, plt: &(u64, u64)) -> bool {
+    let (plt_start, plt_end) = *plt;
+    plt_start < plt_end && target >= plt_start && target < plt_end
+}
+
+/// A direct call must land exactly on the start of a known function (or, for Lucet, of a PLT
+/// slot). Guest functions take precedence: a target that is a known function is accepted as
+/// one even if it also lies in the PLT range. Returns the rule that accepts `target`, or why
+/// no rule does.
+fn direct_call_target(target: u64, model: &dyn CompilerModel, funcs: &ValidFuncs, plt: &(u64, u64)) -> Result<CallTargetRule, &'static str> {
+    if funcs.contains(target) {
+        return Ok(CallTargetRule::GuestFunction);
+    }
+    if in_plt(target, plt) {
+        return match model.plt_target_error(target - plt.0) {
+            None => Ok(CallTargetRule::PltSlot),
+            Some(reason) => Err(reason),
+        };
+    }
+    Err("not a function entry")
+}
+
+fn direct_call_target_error(target: u64, model: &dyn CompilerModel, funcs: &ValidFuncs, plt: &(u64, u64)) -> Option<&'static str> {
+    direct_call_target(target, model, funcs, plt).err()
+}
+
+fn is_valid_direct_call_target(target: u64, model: &dyn CompilerModel, funcs: &ValidFuncs, plt: &(u64, u64)) -> bool {
+    direct_call_target_error(target, model, funcs, plt).is_none()
+}
+
+/// Checks the targets of every direct call in a function. This doesn't need any abstract state,
+/// so it runs for every function, including ones without indirect calls that never reach the
+/// full call analysis. Returns the direct call sites validated, or the first one that is
+/// invalid.
+pub fn check_direct_calls(
+    irmap: &IRMap,
+    model: &dyn CompilerModel,
+    funcs: &ValidFuncs,
+    plt: &(u64,u64),
+) -> Result<Vec<DirectCallSite>, CheckFailure> {
+    let mut sites = Vec::new();
+    for (_block_addr, ir_block) in irmap {
+        for (addr, ir_stmts) in ir_block.iter() {
+            for (idx, ir_stmt) in ir_stmts.iter().enumerate() {
+                if let Stmt::Call(Value::Imm(_, immsize, imm), len) = ir_stmt {
+                    let target = rel_target(immsize, *imm, *addr, *len as u64);
+                    match direct_call_target(target, model, funcs, plt) {
+                        Ok(rule) => sites.push(DirectCallSite { addr: *addr, target, rule }),
+                        Err(reason) => {
+                            log::error!("0x{:x} Failure Case: Direct Call to 0x{:x} ({})", addr, target, reason);
+                            return Err(CheckFailure {
+                                loc: LocIdx { addr: *addr, idx: idx as u32 },
+                                stmt: ir_stmt.clone(),
+                                description: format!("direct call to 0x{:x} ({}; {})", target, reason, funcs.describe_nearest(target)),
+                                state: String::new(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(sites)
+}
+
 impl Checker<CallCheckLattice> for CallChecker<'_> {
-    fn check(&self, result: AnalysisResult<CallCheckLattice>) -> bool {
+    fn check(&self, result: AnalysisResult<CallCheckLattice>) -> Result<(), CheckFailure> {
         self.check_state_at_statements(result)
     }
 
@@ -46,24 +132,44 @@ impl Checker<CallCheckLattice> for CallChecker<'_> {
         self.analyzer.aexec(state, ir_stmt, loc)
     }
 
-    fn check_statement(&self, state: &CallCheckLattice, ir_stmt: &Stmt, loc_idx: &LocIdx) -> bool {
+    fn paranoid(&self) -> bool {
+        self.analyzer.paranoid()
+    }
+
+    fn check_statement(&self, state: &CallCheckLattice, ir_stmt: &Stmt, loc_idx: &LocIdx) -> Result<(), String> {
         //1. Check that all indirect calls use resolved function pointer
-        if let Stmt::Call(v) = ir_stmt {
-            if !self.check_indirect_call(state, v, loc_idx) {
-                println!("0x{:x} Failure Case: Indirect Call {:?}", loc_idx.addr, v);
-                return false;
+        if let Stmt::Call(v, len) = ir_stmt {
+            if !self.check_indirect_call(state, v, *len, loc_idx) {
+                log::error!("0x{:x} Failure Case: Indirect Call {:?}", loc_idx.addr, v);
+                return Err(format!("indirect call through {:?}, not a checked function pointer", v));
+            }
+        }
+        // ... and tail calls (see is_tail_dispatch), by the same rules
+        if let Stmt::Branch(_, v) = ir_stmt {
+            if is_tail_dispatch(ir_stmt, self.analyzer.compiler()) && !self.check_indirect_call(state, v, 0, loc_idx) {
+                log::error!("0x{:x} Failure Case: Indirect Tail Call {:?}", loc_idx.addr, v);
+                return Err(format!("indirect tail call through {:?}, not a checked function pointer", v));
             }
         }
 
-        // 2. Check that lookup is using resolved DAV
-        if let Stmt::Unop(_, _, Value::Mem(_, memargs)) = ir_stmt {
-            if !self.check_calltable_lookup(state, memargs) {
-                println!("0x{:x} Failure Case: Lookup Call: {:?}", loc_idx.addr, memargs);
-                print_mem_access(state, memargs);
-                return false;
+        // 2. Check that lookup is using resolved DAV, wherever the heap checker may accept it as
+        // a table access (not just as the source of a move)
+        let operands: Vec<&Value> = match ir_stmt {
+            Stmt::Unop(_, dst, src) => vec![dst, src],
+            Stmt::Binop(_, dst, src1, src2) => vec![dst, src1, src2],
+            Stmt::Clear(dst, srcs) => std::iter::once(dst).chain(srcs.iter()).collect(),
+            _ => vec![],
+        };
+        for operand in operands {
+            if let Value::Mem(memsize, memargs) = operand {
+                if !self.check_calltable_lookup(state, memsize, memargs) {
+                    log::error!("0x{:x} Failure Case: Lookup Call: {:?}", loc_idx.addr, memargs);
+                    print_mem_access(state, memargs);
+                    return Err(format!("call table lookup with an unchecked index: {}", mem_access_repr(state, memargs)));
+                }
             }
         }
-        true
+        Ok(())
     }
 }
 
@@ -72,11 +178,13 @@ impl CallChecker<'_> {
         &self,
         state: &CallCheckLattice,
         target: &Value,
+        len: u8,
         loc_idx: &LocIdx,
     ) -> bool {
         match self.analyzer.compiler() {
-            Compiler::Lucet => self.lucet_check_indirect_call(state, target, loc_idx),
-            Compiler::Wamr => self.wamr_check_indirect_call(state, target, loc_idx),
+            Compiler::Lucet => self.lucet_check_indirect_call(state, target, len, loc_idx),
+            Compiler::Wamr => self.wamr_check_indirect_call(state, target, len, loc_idx),
+            Compiler::Wasmtime => self.wasmtime_check_indirect_call(state, target, len, loc_idx),
         }
     }
 
@@ -84,6 +192,7 @@ impl CallChecker<'_> {
         &self,
         state: &CallCheckLattice,
         target: &Value,
+        len: u8,
         loc_idx: &LocIdx,
     ) -> bool {
         match target {
@@ -92,15 +201,13 @@ impl CallChecker<'_> {
                     return true;
                 }
                 else{
-                    println!("{:?}", state.regs.get(regnum, size).v)
+                    log::error!("{:?}", state.regs.get(regnum, size).v)
                 }
             }
             Value::Mem(_, _) => return false,
-            Value::Imm(_, _, imm) => {
-                let target = (*imm + (loc_idx.addr as i64) + 5) as u64;
-                let (plt_start, plt_end) = self.plt;
-                return self.funcs.contains(&target) || 
-                ((target >= *plt_start) && (target < *plt_end)) ; 
+            Value::Imm(_, immsize, imm) => {
+                let target = rel_target(immsize, *imm, loc_idx.addr, len as u64);
+                return is_valid_direct_call_target(target, self.analyzer.metadata.model, self.funcs, self.plt);
             }, 
         }
         false
@@ -110,6 +217,7 @@ impl CallChecker<'_> {
         &self,
         state: &CallCheckLattice,
         target: &Value,
+        len: u8,
         loc_idx: &LocIdx,
     ) -> bool {
         match target {
@@ -123,31 +231,83 @@ impl CallChecker<'_> {
                             if let Some(CallCheckValue::WamrFuncIdx) = state.regs.get(idx_regnum, &ValSize::Size64).v {
                                 return true;
                             } else {
-                                println!("indirect call without valid function index: {:?}", 
+                                log::error!("indirect call without valid function index: {:?}", 
                                          state.regs.get(idx_regnum, &ValSize::Size64).v);
                                 return false;
                             }
+                        } else if let Some(CallCheckValue::WamrTableFuncPtrs(table)) = state.regs.get(base_regnum, base_regsize).v {
+                            // one of several funcref tables: the index has to be checked against
+                            // the count of the same table the function pointers are of
+                            match state.regs.get(idx_regnum, &ValSize::Size64).v {
+                                Some(CallCheckValue::WamrTableChecked(checked)) if checked == table => return true,
+                                other => {
+                                    log::error!("indirect call through the function pointers of {:?} with index {:?}", table, other);
+                                    return false;
+                                }
+                            }
                         } else {
-                            println!("indirect call without valid base address: {:?}", memargs);
+                            log::error!("indirect call without valid base address: {:?}", memargs);
                             return false;
                         }
                     },
                     _ => (),
                 }
             },
-            Value::Imm(_, _, imm) => {
-                let target = (*imm + (loc_idx.addr as i64) + 5) as u64;
-                return self.funcs.contains(&target);
+            // a function pointer loaded from a funcref table, see CallAnalyzer::wamr_aeval_unop
+            Value::Reg(regnum, size) => {
+                if let Some(CallCheckValue::FnPtr) = state.regs.get(regnum, size).v {
+                    return true;
+                }
+            },
+            Value::Imm(_, immsize, imm) => {
+                let target = rel_target(immsize, *imm, loc_idx.addr, len as u64);
+                return is_valid_direct_call_target(target, self.analyzer.metadata.model, self.funcs, self.plt);
             }, 
-            _ => (),
         }
         false
     }
 
-    fn check_calltable_lookup(&self, state: &CallCheckLattice, memargs: &MemArgs) -> bool {
+    fn wasmtime_check_indirect_call(
+        &self,
+        state: &CallCheckLattice,
+        target: &Value,
+        len: u8,
+        loc_idx: &LocIdx,
+    ) -> bool {
+        match target {
+            Value::Reg(regnum, size) => {
+                if let Some(CallCheckValue::FnPtr) = state.regs.get(regnum, size).v {
+                    return true;
+                }
+                log::error!("indirect call without a checked function pointer: {:?}", state.regs.get(regnum, size).v);
+            }
+            // Cranelift may call through the anyfunc's function pointer directly
+            Value::Mem(_, MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)))
+                if WASMTIME_ANYFUNC_FUNC_PTR_OFFSET == 0 => {
+                if let Some(CallCheckValue::WasmtimeAnyfunc) = state.regs.get(regnum, &ValSize::Size64).v {
+                    return true;
+                }
+            }
+            Value::Mem(_, MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, offset)))
+                if *offset == WASMTIME_ANYFUNC_FUNC_PTR_OFFSET => {
+                if let Some(CallCheckValue::WasmtimeAnyfunc) = state.regs.get(regnum, &ValSize::Size64).v {
+                    return true;
+                }
+            }
+            Value::Mem(_, _) => (),
+            Value::Imm(_, immsize, imm) => {
+                let target = rel_target(immsize, *imm, loc_idx.addr, len as u64);
+                return is_valid_direct_call_target(target, self.analyzer.metadata.model, self.funcs, self.plt);
+            },
+        }
+        false
+    }
+
+    fn check_calltable_lookup(&self, state: &CallCheckLattice, memsize: &ValSize, memargs: &MemArgs) -> bool {
         match self.analyzer.compiler() {
             Compiler::Lucet => self.lucet_check_calltable_lookup(state, memargs),
-            Compiler::Wamr => self.wamr_check_calltable_lookup(state, memargs),
+            Compiler::Wamr => self.wamr_check_calltable_lookup(state, memsize, memargs),
+            Compiler::Wasmtime => self.wasmtime_check_calltable_lookup(state, memargs),
         }
     }
 
@@ -174,31 +334,107 @@ impl CallChecker<'_> {
                 | (Some(CallCheckValue::GuestTableBase), _x) => return false,
                 (_x, _y) => return true, // not a calltable lookup
             },
+            // lookups through a table entry pointer computed with an add
+            MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) => {
+                match state.regs.get(regnum, &ValSize::Size64).v {
+                    Some(CallCheckValue::UncheckedTableEntryPtr) => return false,
+                    _ => return true,
+                }
+            }
+            MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, immval)) => {
+                match state.regs.get(regnum, &ValSize::Size64).v {
+                    Some(CallCheckValue::TableEntryPtr) => return *immval == 0 || *immval == 8,
+                    Some(CallCheckValue::UncheckedTableEntryPtr) => return false,
+                    _ => return true,
+                }
+            }
             _ => return true, //not a calltable lookup?
         }
     }
 
-    fn wamr_check_calltable_lookup(&self, state: &CallCheckLattice, memargs: &MemArgs) -> bool {
-        let lower_bound = WAMR_GLOBALS_OFFSET;
-        let upper_bound = lower_bound + self.analyzer.metadata.globals_size;
+    fn wasmtime_check_calltable_lookup(&self, state: &CallCheckLattice, memargs: &MemArgs) -> bool {
+        match memargs {
+            // the cases here must match the jump table accesses the Wasmtime model accepts
+            MemArgs::MemScale(MemArg::Reg(base_regnum, ValSize::Size64),
+                              MemArg::Reg(idx_regnum, idx_size), MemArg::Imm(_, _, WASMTIME_TABLE_ENTRY_SIZE)) => {
+                if let Some(CallCheckValue::WasmtimeTableBase) = state.regs.get(base_regnum, &ValSize::Size64).v {
+                    if let Some(CallCheckValue::CheckedVal) = state.regs.get(idx_regnum, idx_size).v {
+                        return true;
+                    }
+                    log::error!("table lookup without a checked index: {:?}", state.regs.get(idx_regnum, idx_size).v);
+                    return false;
+                }
+                true
+            }
+            _ => true, // not a calltable lookup
+        }
+    }
+
+    fn wamr_check_calltable_lookup(&self, state: &CallCheckLattice, memsize: &ValSize, memargs: &MemArgs) -> bool {
+        let metadata = &self.analyzer.metadata;
+        let width = (memsize.to_u32() / 8) as i64;
         match memargs {
-            // the cases here must match Case 1 for check_jump_table_access in the heap checker
+            // the cases here must match Case 1 for check_jump_table_access in the heap checker,
+            // both sides classify the access with wamr_table_read
+            // a constant index: the entry has to be in the table (global data before it isn't a
+            // function index, see the call analyzer)
             MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, immval)) => {
                 if let Some(CallCheckValue::WamrModuleInstance) = state.regs.get(regnum, &ValSize::Size64).v {
-                    if *immval >= lower_bound {
-                        return *immval >= lower_bound && *immval <= upper_bound;
+                    if let WamrTableRead::Entry { in_bounds } = metadata.wamr_table_read(*immval, width, false) {
+                        return in_bounds;
                     }
                 }
             },
+            // a checked index: WamrChecked only records the bound the index was compared with, not
+            // whether the comparison was strict, so the bound itself has to be a valid index
             MemArgs::MemScaleDisp(MemArg::Reg(base_regnum, ValSize::Size64),
-                                  MemArg::Reg(idx_regnum, ValSize::Size64), MemArg::Imm(_, _, 4),
-                                  MemArg::Imm(_, _, WAMR_GLOBALS_OFFSET)) => {
+                                  MemArg::Reg(idx_regnum, idx_size), MemArg::Imm(_, _, 4),
+                                  MemArg::Imm(_, _, disp)) => {
                 if let Some(CallCheckValue::WamrModuleInstance) = state.regs.get(base_regnum, &ValSize::Size64).v {
-                    if let Some(CallCheckValue::WamrChecked(val)) = state.regs.get(idx_regnum, &ValSize::Size64).v {
-                        return val < (self.analyzer.metadata.globals_size as u32);
-                    } else {
-                        println!("unchecked index into the function index table!");
-                        return false;
+                    match metadata.wamr_table_read(*disp, width, true) {
+                        WamrTableRead::Outside => (),
+                        WamrTableRead::Indexed => {
+                            if let Some(CallCheckValue::WamrChecked(val)) = state.regs.get(idx_regnum, idx_size).v {
+                                return (val as i64) < metadata.call_table_size;
+                            } else {
+                                log::error!("unchecked index into the function index table!");
+                                return false;
+                            }
+                        },
+                        _ => {
+                            log::error!("index into the ModuleInstance at 0x{:x}, not the function index table at 0x{:x}!", disp, metadata.wamr_table_offset());
+                            return false;
+                        },
+                    }
+                }
+            },
+            // with several funcref tables, a descriptor picked with a register has to be one of
+            // them (must match Case 4 for check_jump_table_access in the heap checker)
+            MemArgs::MemScaleDisp(MemArg::Reg(base_regnum, ValSize::Size64),
+                                  MemArg::Reg(idx_regnum, idx_size), MemArg::Imm(_, _, 8),
+                                  MemArg::Imm(_, _, disp)) => {
+                if let (Some(CallCheckValue::WamrModuleInstance), Some(tables)) = (state.regs.get(base_regnum, &ValSize::Size64).v, &metadata.wamr_tables) {
+                    if *disp == tables.descriptors {
+                        if let Some(CallCheckValue::WamrChecked(val)) = state.regs.get(idx_regnum, idx_size).v {
+                            return val < tables.count;
+                        } else {
+                            log::error!("unchecked index into the funcref table descriptors!");
+                            return false;
+                        }
+                    }
+                }
+            },
+            // the function pointers of one of several funcref tables, with an index checked
+            // against the count of the same table (must match Case 3 for check_jump_table_access)
+            MemArgs::MemScale(MemArg::Reg(regnum, ValSize::Size64),
+                              MemArg::Reg(idx_regnum, ValSize::Size64), MemArg::Imm(_, _, 8)) => {
+                if let Some(CallCheckValue::WamrTableFuncPtrs(table)) = state.regs.get(regnum, &ValSize::Size64).v {
+                    match state.regs.get(idx_regnum, &ValSize::Size64).v {
+                        Some(CallCheckValue::WamrTableChecked(checked)) if checked == table => return true,
+                        _ => {
+                            log::error!("funcref table lookup with an index not checked against its count!");
+                            return false;
+                        }
                     }
                 }
             },
@@ -209,7 +445,7 @@ impl CallChecker<'_> {
                 if let Some(CallCheckValue::WamrFuncTypeTable) = state.regs.get(regnum, &ValSize::Size64).v {
                     return true;
                 } else {
-                    println!("function type table lookup without valid index!");
+                    log::error!("function type table lookup without valid index!");
                     return false;
                 }
             }
@@ -226,27 +462,27 @@ pub fn memarg_repr(state: &CallCheckLattice, memarg: &MemArg) -> String {
     }
 }
 
-pub fn print_mem_access(state: &CallCheckLattice, memargs: &MemArgs) {
+pub fn mem_access_repr(state: &CallCheckLattice, memargs: &MemArgs) -> String {
     match memargs {
-        MemArgs::Mem1Arg(x) => println!("mem[{:?}]", memarg_repr(state, x)),
-        MemArgs::Mem2Args(x, y) => println!(
+        MemArgs::Mem1Arg(x) => format!("mem[{:?}]", memarg_repr(state, x)),
+        MemArgs::Mem2Args(x, y) => format!(
             "mem[{:?} + {:?}]",
             memarg_repr(state, x),
             memarg_repr(state, y)
         ),
-        MemArgs::Mem3Args(x, y, z) => println!(
+        MemArgs::Mem3Args(x, y, z) => format!(
             "mem[{:?} + {:?} + {:?}]",
             memarg_repr(state, x),
             memarg_repr(state, y),
             memarg_repr(state, z)
         ),
-        MemArgs::MemScale(x, y, z) => println!(
+        MemArgs::MemScale(x, y, z) => format!(
             "mem[{:?} + {:?} * {:?}]",
             memarg_repr(state, x),
             memarg_repr(state, y),
             memarg_repr(state, z)
         ),
-        MemArgs::MemScaleDisp(w, x, y, z) => println!(
+        MemArgs::MemScaleDisp(w, x, y, z) => format!(
             "mem[{:?} + {:?}*{:?} + {:?}]",
             memarg_repr(state, w),
             memarg_repr(state, x),
@@ -255,3 +491,591 @@ pub fn print_mem_access(state: &CallCheckLattice, memargs: &MemArgs) {
         ),
     }
 }
+
+pub fn print_mem_access(state: &CallCheckLattice, memargs: &MemArgs) {
+    log::error!("{}", mem_access_repr(state, memargs));
+}
+
+#[test]
+fn direct_call_test() {
+    use crate::utils::compiler_model::{LucetModel, WamrModel};
+    use crate::utils::ir::ImmType;
+    use crate::utils::valid_funcs::FuncSource;
+
+    // call at 0x100 (5 bytes) to funcA = 0x200, and to funcA + 5
+    let call_to = |target: i64| Stmt::Call(Value::Imm(ImmType::Signed, ValSize::Size32, target - 0x105), 5);
+    let funcs = ValidFuncs::from_addrs(&[0x100, 0x200], FuncSource::Symbol);
+    let plt = (0x1000, 0x1100);
+
+    let mut irmap = IRMap::new();
+    irmap.insert(0x100, vec![(0x100, vec![call_to(0x200)])]);
+    assert_eq!(check_direct_calls(&irmap, &LucetModel, &funcs, &plt).ok().map(|sites| sites.len()), Some(1));
+    assert_eq!(check_direct_calls(&irmap, &WamrModel, &funcs, &plt).ok().map(|sites| sites.len()), Some(1));
+
+    irmap.insert(0x100, vec![(0x100, vec![call_to(0x205)])]);
+    assert_eq!(check_direct_calls(&irmap, &LucetModel, &funcs, &plt).ok(), None);
+    assert_eq!(check_direct_calls(&irmap, &WamrModel, &funcs, &plt).ok(), None);
+    // the failure names the functions around the target, and where they came from
+    let mut sourced = funcs.clone();
+    sourced.insert(0x300, "aot_invoke_native", FuncSource::Native);
+    assert_eq!(
+        check_direct_calls(&irmap, &WamrModel, &sourced, &plt).unwrap_err().description,
+        "direct call to 0x205 (not a function entry; nearest valid targets: 0x200 0x200 (symbol) below, 0x300 aot_invoke_native (native) above)"
+    );
+
+    // PLT targets are only valid for Lucet, and only at the start of a slot
+    irmap.insert(0x100, vec![(0x100, vec![call_to(0x1010)])]);
+    assert_eq!(check_direct_calls(&irmap, &LucetModel, &funcs, &plt).ok().map(|sites| sites.len()), Some(1));
+    assert_eq!(check_direct_calls(&irmap, &WamrModel, &funcs, &plt).ok(), None);
+    irmap.insert(0x100, vec![(0x100, vec![call_to(0x1016)])]);
+    assert_eq!(check_direct_calls(&irmap, &LucetModel, &funcs, &plt).ok(), None);
+    assert_eq!(direct_call_target_error(0x1016, &LucetModel, &funcs, &plt), Some("interior of a PLT slot"));
+
+    // a 6-byte call (with a redundant prefix) at 0x100 is relative to 0x106
+    let prefixed_call = Stmt::Call(Value::Imm(ImmType::Signed, ValSize::Size32, 0x200 - 0x106), 6);
+    irmap.insert(0x100, vec![(0x100, vec![prefixed_call])]);
+    assert_eq!(check_direct_calls(&irmap, &LucetModel, &funcs, &plt).ok().map(|sites| sites.len()), Some(1));
+    assert_eq!(check_direct_calls(&irmap, &WamrModel, &funcs, &plt).ok().map(|sites| sites.len()), Some(1));
+
+    // a function inside the PLT range is accepted as a guest function, even where the PLT
+    // rule would reject it
+    let funcs = ValidFuncs::from_addrs(&[0x100, 0x1016], FuncSource::Symbol);
+    irmap.insert(0x100, vec![(0x100, vec![call_to(0x1016)])]);
+    let expected = vec![DirectCallSite { addr: 0x100, target: 0x1016, rule: CallTargetRule::GuestFunction }];
+    assert_eq!(check_direct_calls(&irmap, &LucetModel, &funcs, &plt).ok(), Some(expected.clone()));
+    assert_eq!(check_direct_calls(&irmap, &WamrModel, &funcs, &plt).ok(), Some(expected));
+    irmap.insert(0x100, vec![(0x100, vec![call_to(0x1010)])]);
+    assert_eq!(check_direct_calls(&irmap, &LucetModel, &funcs, &plt).map(|sites| sites[0].rule).ok(), Some(CallTargetRule::PltSlot));
+
+    // without a PLT, no call is a PLT call, not even one to 0
+    let no_plt = (0, 0);
+    assert!(!in_plt(0, &no_plt));
+    assert_eq!(direct_call_target_error(0, &LucetModel, &funcs, &no_plt), Some("not a function entry"));
+    irmap.insert(0x100, vec![(0x100, vec![call_to(0x1010)])]);
+    assert_eq!(check_direct_calls(&irmap, &LucetModel, &funcs, &no_plt).ok(), None);
+}
+
+#[test]
+fn table_entry_ptr_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::analyses::reaching_defs::ReachingDefnAnalyzer;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::lattices::calllattice::CallCheckValueLattice;
+    use crate::lattices::heaplattice::{HeapLattice, HeapValue, HeapValueLattice};
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{Binopcode, ImmType, Unopcode};
+    use std::sync::atomic::AtomicUsize;
+    use std::collections::HashMap;
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+    // large code model: guest_table_0 lives above 4GB and is loaded with movabs
+    let metadata = CompilerMetadata {
+        guest_table_0: 0x1_0000_2000,
+        lucet_tables: 0x1_0000_1000,
+        ..CompilerMetadata::for_test(Compiler::Lucet)
+    };
+    let analyzer = CallAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: HashMap::new(),
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), VW_CFG::new(0), IRMap::new()),
+        withheld_refinements: AtomicUsize::new(0),
+    };
+    let heap_analyzer = HeapAnalyzer { metadata: metadata };
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let entry = |offset: i64| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(0, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)),
+    );
+    // movabs rax, guest_table_0; add rax, rcx; mov rdx, [rax + 8]; call rdx
+    let mut irmap = IRMap::new();
+    irmap.insert(0, vec![
+        (0x0, vec![Stmt::Unop(Unopcode::Mov, reg(0), Value::Imm(ImmType::Signed, ValSize::Size64, 0x1_0000_2000))]),
+        (0xa, vec![Stmt::Binop(Binopcode::Add, reg(0), reg(0), reg(1))]),
+        (0xd, vec![Stmt::Unop(Unopcode::Mov, reg(2), entry(8))]),
+        (0x11, vec![Stmt::Call(reg(2), 2)]),
+    ]);
+    let check = |rcx: CallCheckValueLattice| {
+        let mut state: CallCheckLattice = Default::default();
+        state.regs.rcx = rcx;
+        let mut result = AnalysisResult::new();
+        result.insert(0, state);
+        check_calls(result, &irmap, &analyzer, &vec![], &(0, 0)).is_ok()
+    };
+    // rcx holds a bounds-checked index << 4
+    assert!(check(CallCheckValueLattice::new(CallCheckValue::PtrOffset(DAV::Checked))));
+    assert!(!check(CallCheckValueLattice::default()));
+
+    // the heap checker only takes the entry for an offset checked against the table size too
+    let check_heap_with = |rcx: HeapValue| {
+        let mut heap_state: HeapLattice = heap_analyzer.init_state();
+        heap_state.regs.rcx = HeapValueLattice::new(rcx);
+        let mut heap_result = AnalysisResult::new();
+        heap_result.insert(0, heap_state);
+        check_heap(heap_result, &irmap, &heap_analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+    assert!(check_heap_with(HeapValue::CheckedTableOffset));
+    assert!(!check_heap_with(HeapValue::Bounded4GB));
+}
+
+#[test]
+fn wamr_call_table_bound_test() {
+    use crate::analyses::reaching_defs::ReachingDefnAnalyzer;
+    use crate::lattices::calllattice::CallCheckValueLattice;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{ImmType, Unopcode};
+    use std::sync::atomic::AtomicUsize;
+    use std::collections::HashMap;
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+    // 4096 bytes of globals, then a table of 4 function indices
+    let metadata = CompilerMetadata {
+        globals_size: 4096,
+        call_table_size: 4,
+        ..CompilerMetadata::for_test(Compiler::Wamr)
+    };
+    let table_offset = metadata.wamr_table_offset();
+    assert_eq!(table_offset, WAMR_GLOBALS_OFFSET + 4096);
+    assert_eq!(metadata.wamr_table_end(), table_offset + 16);
+    let analyzer = CallAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: HashMap::new(),
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata, VW_CFG::new(0), IRMap::new()),
+        withheld_refinements: AtomicUsize::new(0),
+    };
+    let imm = |v: i64| MemArg::Imm(ImmType::Signed, ValSize::Size64, v);
+    // mov eax, <lookup>; ret, with the ModuleInstance in rbx and an index checked against `bound` in rcx
+    let check = |lookup: MemArgs, bound: u32| {
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![
+            (0, vec![Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size32), Value::Mem(ValSize::Size32, lookup))]),
+            (7, vec![Stmt::Ret]),
+        ]);
+        let mut state: CallCheckLattice = Default::default();
+        state.regs.rbx = CallCheckValueLattice::new(CallCheckValue::WamrModuleInstance);
+        state.regs.rcx = CallCheckValueLattice::new(CallCheckValue::WamrChecked(bound));
+        let mut result = AnalysisResult::new();
+        result.insert(0, state);
+        check_calls(result, &irmap, &analyzer, &vec![], &(0, 0)).is_ok()
+    };
+    let indexed = |disp: i64| MemArgs::MemScaleDisp(MemArg::Reg(3, ValSize::Size64), MemArg::Reg(1, ValSize::Size64), imm(4), imm(disp));
+    let constant = |offset: i64| MemArgs::Mem2Args(MemArg::Reg(3, ValSize::Size64), imm(offset));
+
+    // indices up to 3 are in the table; 7 is past it, though well within the globals size
+    assert!(check(indexed(table_offset), 3));
+    assert!(!check(indexed(table_offset), 4));
+    assert!(!check(indexed(table_offset), 7));
+    // indexing from anywhere but the table's start reads global data as function indices
+    assert!(!check(indexed(WAMR_GLOBALS_OFFSET), 3));
+    assert!(check(constant(table_offset + 12), 0));
+    assert!(!check(constant(table_offset + 16), 0));
+    // global data and the table sizes aren't function indices, so reading them is left to the heap checker
+    assert!(check(constant(WAMR_GLOBALS_OFFSET + 8), 0));
+    assert!(check(constant(table_offset - 8), 0));
+    let global = Value::Mem(ValSize::Size32, constant(WAMR_GLOBALS_OFFSET + 8));
+    let mut state: CallCheckLattice = Default::default();
+    state.regs.rbx = CallCheckValueLattice::new(CallCheckValue::WamrModuleInstance);
+    assert_eq!(analyzer.aeval_unop(&state, &global), CallCheckValueLattice::default());
+    let entry = Value::Mem(ValSize::Size32, constant(table_offset));
+    assert_eq!(analyzer.aeval_unop(&state, &entry), CallCheckValueLattice::new(CallCheckValue::WamrFuncIdx));
+}
+
+#[test]
+fn wamr_table_read_consistency_test() {
+    use crate::analyses::reaching_defs::ReachingDefnAnalyzer;
+    use crate::lattices::calllattice::CallCheckValueLattice;
+    use crate::lattices::heaplattice::{HeapLattice, HeapValue, HeapValueLattice};
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{Binopcode, ImmType, Unopcode};
+    use std::sync::atomic::AtomicUsize;
+    use std::collections::HashMap;
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+    // 4096 bytes of globals, then a table of 4 function indices
+    let metadata = CompilerMetadata {
+        globals_size: 4096,
+        call_table_size: 4,
+        ..CompilerMetadata::for_test(Compiler::Wamr)
+    };
+    let (table_offset, table_end) = (metadata.wamr_table_offset(), metadata.wamr_table_end());
+    let analyzer = CallAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: HashMap::new(),
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), VW_CFG::new(0), IRMap::new()),
+        withheld_refinements: AtomicUsize::new(0),
+    };
+    let imm = |v: i64| MemArg::Imm(ImmType::Signed, ValSize::Size64, v);
+    // the ModuleInstance in rbx and an index checked against `bound` in rcx, for both checkers
+    let mut heap_state: HeapLattice = Default::default();
+    heap_state.regs.rbx = HeapValueLattice::new(HeapValue::WamrModuleInstance);
+    let heap_accepts = |access: &Value| {
+        metadata.model.is_global_access(&metadata, &heap_state, access)
+            || metadata.model.is_jump_table_access(&metadata, &heap_state, access, &LocIdx { addr: 0, idx: 0 }, &JumpTableLoads::new())
+    };
+    let call_accepts = |stmt: Stmt, bound: u32| {
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![(0, vec![stmt]), (7, vec![Stmt::Ret])]);
+        let mut state: CallCheckLattice = Default::default();
+        state.regs.rbx = CallCheckValueLattice::new(CallCheckValue::WamrModuleInstance);
+        state.regs.rcx = CallCheckValueLattice::new(CallCheckValue::WamrChecked(bound));
+        let mut result = AnalysisResult::new();
+        result.insert(0, state);
+        check_calls(result, &irmap, &analyzer, &vec![], &(0, 0)).is_ok()
+    };
+    let load = |access: &Value| Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size32), access.clone());
+    let add = |access: &Value| Stmt::Binop(Binopcode::Add, Value::Reg(0, ValSize::Size32), Value::Reg(0, ValSize::Size32), access.clone());
+
+    let disps = vec![
+        WAMR_GLOBALS_OFFSET - 16, WAMR_GLOBALS_OFFSET - 8, WAMR_GLOBALS_OFFSET - 4, WAMR_GLOBALS_OFFSET,
+        WAMR_GLOBALS_OFFSET + 8, table_offset - 8, table_offset - 4, table_offset, table_offset + 4,
+        table_offset + 12, table_offset + 16, table_offset + 0x1000,
+    ];
+    for &disp in disps.iter() {
+        for &size in [ValSize::Size32, ValSize::Size64].iter() {
+            let width = (size.to_u32() / 8) as i64;
+            // whatever constant read the heap checker accepts stays below the table's end, and
+            // the call checker agrees on the ones that read function indices
+            let constant = Value::Mem(size, MemArgs::Mem2Args(MemArg::Reg(3, ValSize::Size64), imm(disp)));
+            if heap_accepts(&constant) {
+                assert!(disp + width <= table_end, "0x{:x}", disp);
+            }
+            if disp >= table_offset {
+                assert_eq!(call_accepts(load(&constant), 0), heap_accepts(&constant), "0x{:x}", disp);
+            }
+            // whatever indexed read the heap checker accepts, the call checker bounds the index
+            // of so that it stays in the table, however the read is used
+            let indexed = Value::Mem(size, MemArgs::MemScaleDisp(MemArg::Reg(3, ValSize::Size64), MemArg::Reg(1, ValSize::Size64), imm(4), imm(disp)));
+            if heap_accepts(&indexed) {
+                assert!(disp + 4 * (metadata.call_table_size - 1) + width <= table_end, "0x{:x}", disp);
+                for stmt in vec![load(&indexed), add(&indexed)] {
+                    assert!(call_accepts(stmt.clone(), 3));
+                    assert!(!call_accepts(stmt, 4));
+                }
+            } else if disp >= WAMR_GLOBALS_OFFSET - 8 {
+                assert!(!call_accepts(load(&indexed), 0), "0x{:x}", disp);
+                assert!(!call_accepts(add(&indexed), 0), "0x{:x}", disp);
+            }
+        }
+    }
+
+    // an indexed read far past the table used to pass both: the heap checker took it for a table
+    // access, and the call checker only looked at moves
+    let past = Value::Mem(ValSize::Size32, MemArgs::MemScaleDisp(MemArg::Reg(3, ValSize::Size64), MemArg::Reg(1, ValSize::Size64), imm(4), imm(table_offset + 0x1000)));
+    assert!(!heap_accepts(&past));
+    assert!(!call_accepts(add(&past), 0));
+    assert_eq!(metadata.wamr_table_read(table_offset + 0x1000, 4, true), WamrTableRead::Misindexed);
+    assert_eq!(metadata.wamr_table_read(table_offset, 4, true), WamrTableRead::Indexed);
+    assert_eq!(metadata.wamr_table_read(table_offset, 8, true), WamrTableRead::Misindexed);
+    assert_eq!(metadata.wamr_table_read(table_offset + 12, 4, false), WamrTableRead::Entry { in_bounds: true });
+    assert_eq!(metadata.wamr_table_read(table_offset - 4, 8, false), WamrTableRead::Global { in_bounds: true });
+    assert_eq!(metadata.wamr_table_read(WAMR_GLOBALS_OFFSET - 16, 4, false), WamrTableRead::Outside);
+}
+
+#[test]
+fn wasmtime_indirect_call_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::analyses::reaching_defs::ReachingDefnAnalyzer;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::lattices::calllattice::CallCheckValueLattice;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{ImmType, Unopcode};
+    use crate::utils::wasmtime_layout::WasmtimeLayout;
+    use std::sync::atomic::AtomicUsize;
+    use std::collections::HashMap;
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+    let metadata = CompilerMetadata {
+        wasmtime_layout: WasmtimeLayout::default(),
+        ..CompilerMetadata::for_test(Compiler::Wasmtime)
+    };
+    let analyzer = CallAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: HashMap::new(),
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), VW_CFG::new(0), IRMap::new()),
+        withheld_refinements: AtomicUsize::new(0),
+    };
+    let heap_analyzer = HeapAnalyzer { metadata: metadata.clone() };
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let field = |regnum: u8, offset: i64| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)),
+    );
+    let table_entry = Value::Mem(
+        ValSize::Size64,
+        MemArgs::MemScale(MemArg::Reg(0, ValSize::Size64), MemArg::Reg(1, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, 8)),
+    );
+    // mov rax, [rdi + table_base]; mov rdx, [rax + rcx*8]; mov rdi, [rdx + 16]; mov r8, [rdx]; call r8
+    let mut irmap = IRMap::new();
+    irmap.insert(0, vec![
+        (0x0, vec![Stmt::Unop(Unopcode::Mov, reg(0), field(7, metadata.wasmtime_layout.table_base))]),
+        (0x4, vec![Stmt::Unop(Unopcode::Mov, reg(2), table_entry)]),
+        (0x8, vec![Stmt::Unop(Unopcode::Mov, reg(7), field(2, 16))]),
+        (0xc, vec![Stmt::Unop(Unopcode::Mov, reg(8), Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(2, ValSize::Size64))))]),
+        (0xf, vec![Stmt::Call(reg(8), 3)]),
+    ]);
+    let check = |rcx: CallCheckValueLattice| {
+        let mut state: CallCheckLattice = analyzer.init_state();
+        state.regs.rcx = rcx;
+        let mut result = AnalysisResult::new();
+        result.insert(0, state);
+        check_calls(result, &irmap, &analyzer, &vec![], &(0, 0)).is_ok()
+    };
+    // rcx holds an index checked against the table size
+    assert!(check(CallCheckValueLattice::new(CallCheckValue::CheckedVal)));
+    assert!(!check(CallCheckValueLattice::default()));
+
+    let mut heap_result = AnalysisResult::new();
+    heap_result.insert(0, heap_analyzer.init_state());
+    assert!(check_heap(heap_result, &irmap, &heap_analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok());
+}
+
+#[test]
+fn call_pre_state_test() {
+    use crate::analyses::reaching_defs::ReachingDefnAnalyzer;
+    use crate::lattices::calllattice::CallCheckValueLattice;
+    use crate::utils::compiler::CompilerMetadata;
+    use std::sync::atomic::AtomicUsize;
+    use std::collections::HashMap;
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+    let mut metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    metadata.options.paranoid = true;
+    let analyzer = CallAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: HashMap::new(),
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata, VW_CFG::new(0), IRMap::new()),
+        withheld_refinements: AtomicUsize::new(0),
+    };
+    // call rdx; call rdx: the first call is checked against rdx before the call clears it
+    let rdx = Value::Reg(2, ValSize::Size64);
+    let mut irmap = IRMap::new();
+    irmap.insert(0, vec![(0x0, vec![Stmt::Call(rdx.clone(), 2)]), (0x2, vec![Stmt::Call(rdx, 2)])]);
+    let mut state: CallCheckLattice = Default::default();
+    state.regs.rdx = CallCheckValueLattice::new(CallCheckValue::FnPtr);
+    let mut result = AnalysisResult::new();
+    result.insert(0, state);
+    assert_eq!(check_calls(result, &irmap, &analyzer, &vec![], &(0, 0)).unwrap_err().loc.addr, 0x2);
+}
+
+#[test]
+fn wamr_multiple_tables_test() {
+    use crate::analyses::reaching_defs::ReachingDefnAnalyzer;
+    use crate::lattices::calllattice::{CallCheckValueLattice, CheckBound, WamrTableId};
+    use crate::lattices::heaplattice::{HeapLattice, HeapValue, HeapValueLattice};
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{ImmType, Unopcode};
+    use crate::utils::wamr_layout::WamrTables;
+    use std::sync::atomic::AtomicUsize;
+    use std::collections::HashMap;
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+    // two funcref tables, their descriptor pointers at 0x1c0, each descriptor with its element
+    // count at 0 and its function pointers at 8
+    let tables = WamrTables { descriptors: 0x1c0, count: 2, size_field: 0, base_field: 8 };
+    let metadata = CompilerMetadata {
+        globals_size: 0x100,
+        call_table_size: 0,
+        wamr_tables: Some(tables),
+        ..CompilerMetadata::for_test(Compiler::Wamr)
+    };
+    let analyzer = CallAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: HashMap::new(),
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), VW_CFG::new(0), IRMap::new()),
+        withheld_refinements: AtomicUsize::new(0),
+    };
+    let imm = |v: i64| MemArg::Imm(ImmType::Signed, ValSize::Size64, v);
+    let reg = |regnum: u8| MemArg::Reg(regnum, ValSize::Size64);
+    let mov = |dst: u8, src: MemArgs| Stmt::Unop(Unopcode::Mov, Value::Reg(dst, ValSize::Size64), Value::Mem(ValSize::Size64, src));
+    let constant_descriptor = |table: i64| MemArgs::Mem2Args(reg(3), imm(tables.descriptors + 8 * table));
+    let indexed_descriptor = MemArgs::MemScaleDisp(reg(3), reg(6), imm(8), imm(tables.descriptors));
+    let func_ptrs = MemArgs::Mem2Args(reg(2), imm(tables.base_field));
+    let entry = MemArgs::MemScale(reg(2), reg(1), imm(8));
+    let loaded = WamrTableId::LoadedAt(LocIdx { addr: 0, idx: 0 });
+    // load a descriptor into rdx (or find the one loaded through a register there), its function
+    // pointers over it, and call one of them, with the ModuleInstance in rbx, rcx the function
+    // index and rsi the table index
+    let calls = |descriptor: Option<MemArgs>, index: CallCheckValue, table: CallCheckValue, through_reg: bool| {
+        let call = if through_reg {
+            vec![(8, vec![mov(0, entry.clone())]), (12, vec![Stmt::Call(Value::Reg(0, ValSize::Size64), 2)])]
+        } else {
+            vec![(8, vec![Stmt::Call(Value::Mem(ValSize::Size64, entry.clone()), 3)])]
+        };
+        let mut irmap = IRMap::new();
+        let load = descriptor.map(|descriptor| (0, vec![mov(2, descriptor)]));
+        irmap.insert(0, load.into_iter().chain(vec![(4, vec![mov(2, func_ptrs.clone())])])
+            .chain(call).chain(vec![(14, vec![Stmt::Ret])]).collect());
+        let mut state: CallCheckLattice = Default::default();
+        state.regs.rdx = CallCheckValueLattice::new(CallCheckValue::WamrTableDescriptor(loaded));
+        state.regs.rbx = CallCheckValueLattice::new(CallCheckValue::WamrModuleInstance);
+        state.regs.rcx = CallCheckValueLattice::new(index);
+        state.regs.rsi = CallCheckValueLattice::new(table);
+        let mut result = AnalysisResult::new();
+        result.insert(0, state);
+        check_calls(result, &irmap, &analyzer, &vec![], &(0, 0)).is_ok()
+    };
+    let checked = |table: WamrTableId| CallCheckValue::WamrTableChecked(table);
+    let no_table = CallCheckValue::WamrChecked(0);
+
+    // a function index checked against the count of the table it indexes
+    for &through_reg in [false, true].iter() {
+        assert!(calls(Some(constant_descriptor(1)), checked(WamrTableId::Const(1)), no_table.clone(), through_reg));
+        assert!(!calls(Some(constant_descriptor(1)), checked(WamrTableId::Const(0)), no_table.clone(), through_reg));
+        assert!(!calls(Some(constant_descriptor(1)), CallCheckValue::WamrChecked(0), no_table.clone(), through_reg));
+        // a table picked with a register is named after the load
+        assert!(calls(None, checked(loaded), no_table.clone(), through_reg));
+        assert!(!calls(None, checked(WamrTableId::Const(1)), no_table.clone(), through_reg));
+    }
+    // the table index has to be below the count, and the load forgets an index checked against
+    // the count of what it loaded before
+    assert!(!calls(Some(indexed_descriptor.clone()), checked(loaded), CallCheckValue::WamrChecked(1), false));
+    let picks = |table: CallCheckValue| {
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![(0, vec![mov(2, indexed_descriptor.clone())]), (4, vec![Stmt::Ret])]);
+        let mut state: CallCheckLattice = Default::default();
+        state.regs.rbx = CallCheckValueLattice::new(CallCheckValue::WamrModuleInstance);
+        state.regs.rsi = CallCheckValueLattice::new(table);
+        let mut result = AnalysisResult::new();
+        result.insert(0, state);
+        check_calls(result, &irmap, &analyzer, &vec![], &(0, 0)).is_ok()
+    };
+    assert!(picks(CallCheckValue::WamrChecked(1)));
+    assert!(!picks(CallCheckValue::WamrChecked(2)));
+    assert!(!picks(CallCheckValue::WamrFuncIdx));
+    // only a strict comparison with the element count checks an index
+    let bound = CheckBound::WamrTableSize(loaded);
+    assert_eq!(metadata.model.checked_value(bound, true), Some(checked(loaded)));
+    assert_eq!(metadata.model.checked_value(bound, false), None);
+
+    // the load of a descriptor through a register forgets what the previous pass through it left
+    let mut state: CallCheckLattice = Default::default();
+    state.regs.rbx = CallCheckValueLattice::new(CallCheckValue::WamrModuleInstance);
+    state.regs.rcx = CallCheckValueLattice::new(checked(loaded));
+    state.regs.rdi = CallCheckValueLattice::new(checked(WamrTableId::Const(0)));
+    analyzer.aexec(&mut state, &mov(2, indexed_descriptor.clone()), &LocIdx { addr: 0, idx: 0 });
+    assert_eq!(state.regs.rdx, CallCheckValueLattice::new(CallCheckValue::WamrTableDescriptor(loaded)));
+    assert_eq!(state.regs.rcx, Default::default());
+    assert_eq!(state.regs.rdi, CallCheckValueLattice::new(checked(WamrTableId::Const(0))));
+
+    // the heap checker accepts the same descriptor, count and function pointer reads
+    let mut heap_state: HeapLattice = Default::default();
+    heap_state.regs.rbx = HeapValueLattice::new(HeapValue::WamrModuleInstance);
+    heap_state.regs.rdx = HeapValueLattice::new(HeapValue::WamrTableDescriptor);
+    let heap_accepts = |access: MemArgs, size: ValSize| {
+        let access = Value::Mem(size, access);
+        metadata.model.is_metadata_access(&metadata, &heap_state, &access)
+            || metadata.model.is_jump_table_access(&metadata, &heap_state, &access, &LocIdx { addr: 0, idx: 0 }, &JumpTableLoads::new())
+    };
+    assert!(heap_accepts(constant_descriptor(1), ValSize::Size64));
+    assert!(heap_accepts(indexed_descriptor, ValSize::Size64));
+    assert!(heap_accepts(MemArgs::Mem2Args(reg(2), imm(tables.size_field)), ValSize::Size32));
+    assert!(heap_accepts(func_ptrs, ValSize::Size64));
+    // past the descriptors, or a field of a descriptor it doesn't have
+    assert!(!heap_accepts(constant_descriptor(2), ValSize::Size64));
+    assert!(!heap_accepts(MemArgs::Mem2Args(reg(2), imm(16)), ValSize::Size64));
+}
+
+#[test]
+fn wamr_tail_dispatch_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::analyses::reaching_defs::ReachingDefnAnalyzer;
+    use crate::analyses::stack_analyzer::StackAnalyzer;
+    use crate::analyses::{run_worklist, ControlFlow};
+    use crate::checkers::heap_checker::check_heap;
+    use crate::checkers::stack_checker::check_stack;
+    use crate::lattices::heaplattice::{WAMR_FUNCPTRS_OFFSET, WAMR_MODULEINSTANCE_OFFSET};
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{Binopcode, ImmType, Unopcode};
+    use crate::utils::ir_utils::{count_indirect_calls, has_indirect_jumps};
+    use std::sync::atomic::AtomicUsize;
+    use std::collections::HashMap;
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+    use yaxpeax_x86::long_mode::Opcode;
+
+    // a wrapper is a single block, ending in the tail call
+    struct Wrapper;
+    impl ControlFlow for Wrapper {
+        fn entrypoint(&self) -> u64 { 0 }
+        fn successors(&self, _addr: u64) -> Vec<u64> { vec![] }
+    }
+
+    // 4096 bytes of globals, then a table of 4 function indices
+    let metadata = CompilerMetadata {
+        globals_size: 4096,
+        call_table_size: 4,
+        ..CompilerMetadata::for_test(Compiler::Wamr)
+    };
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let field = |regnum: u8, offset: i64| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size32, offset)),
+    );
+    let mov = |dst: Value, src: Value| Stmt::Unop(Unopcode::Mov, dst, src);
+    let rsp = reg(4);
+    let push_rbx = vec![
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), Value::Imm(ImmType::Signed, ValSize::Size64, 8)),
+        mov(Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64))), reg(3)),
+    ];
+    let jmp = Stmt::Branch(Opcode::JMP, Value::Mem(
+        ValSize::Size64,
+        MemArgs::MemScale(MemArg::Reg(1, ValSize::Size64), MemArg::Reg(2, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, 8)),
+    ));
+    let index = Value::Mem(ValSize::Size32, MemArgs::Mem2Args(
+        MemArg::Reg(0, ValSize::Size64),
+        MemArg::Imm(ImmType::Signed, ValSize::Size32, metadata.wamr_table_offset() + 4),
+    ));
+    // mov rax, [rdi + 0x10]; mov rcx, [rax + 0x28]; mov edx, <index>; <before>; jmp [rcx + rdx*8],
+    // checked for its stack, heap and calls
+    let verify = |index: Value, before: Vec<Stmt>| {
+        let mut stmts = vec![
+            mov(reg(0), field(7, WAMR_MODULEINSTANCE_OFFSET)),
+            mov(reg(1), field(0, WAMR_FUNCPTRS_OFFSET)),
+            mov(Value::Reg(2, ValSize::Size32), index),
+        ];
+        stmts.extend(before);
+        stmts.push(jmp.clone());
+        let mut irmap = IRMap::new();
+        irmap.insert(0, stmts.into_iter().enumerate().map(|(i, stmt)| (i as u64 * 4, vec![stmt])).collect());
+        assert!(!has_indirect_jumps(&irmap, Compiler::Wamr));
+        assert_eq!(count_indirect_calls(&irmap, Compiler::Wamr), 1);
+
+        let stack_analyzer = StackAnalyzer { metadata: metadata.clone(), stack_limit_cmps: Default::default() };
+        let stack = check_stack(run_worklist(&Wrapper, &irmap, &stack_analyzer), &irmap, &stack_analyzer).is_ok();
+        let heap_analyzer = HeapAnalyzer { metadata: metadata.clone() };
+        let heap = check_heap(run_worklist(&Wrapper, &irmap, &heap_analyzer), &irmap, &heap_analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok();
+        let call_analyzer = CallAnalyzer {
+            metadata: metadata.clone(),
+            reaching_defs: HashMap::new(),
+            reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), VW_CFG::new(0), IRMap::new()),
+            withheld_refinements: AtomicUsize::new(0),
+        };
+        let calls = check_calls(run_worklist(&Wrapper, &irmap, &call_analyzer), &irmap, &call_analyzer, &vec![], &(0, 0)).map_err(|e| e.description);
+        (stack, heap, calls)
+    };
+
+    // the wrapper tail-calls the function at a slot of the function index table
+    assert_eq!(verify(index.clone(), vec![]), (true, true, Ok(())));
+    // but not through an index from anywhere else
+    let (stack, heap, calls) = verify(Value::Reg(6, ValSize::Size32), vec![]);
+    assert!(stack && heap);
+    assert!(calls.unwrap_err().contains("indirect tail call"));
+    // and the callee returns to the wrapper's caller, so the stack has to be as it was on entry,
+    // with the ExecEnv still in rdi
+    assert_eq!(verify(index.clone(), push_rbx).0, false);
+    assert_eq!(verify(index.clone(), vec![mov(reg(7), reg(0))]).1, false);
+
+    // elsewhere it is an indirect jump like any other
+    let mut irmap = IRMap::new();
+    irmap.insert(0, vec![(0, vec![jmp.clone()])]);
+    assert!(has_indirect_jumps(&irmap, Compiler::Lucet));
+    assert!(!is_tail_dispatch(&Stmt::Branch(Opcode::JNE, Value::Mem(ValSize::Size64, MemArgs::MemScale(
+        MemArg::Reg(1, ValSize::Size64), MemArg::Reg(2, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, 8),
+    ))), Compiler::Wamr));
+}
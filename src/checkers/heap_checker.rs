@@ -1,22 +1,65 @@
 use crate::analyses::heap_analyzer::HeapAnalyzer;
+use crate::analyses::stack_analyzer::UntrackedRsp;
 use crate::analyses::{AbstractAnalyzer, AnalysisResult};
-use crate::checkers::Checker;
-use crate::utils::ir_utils::{is_mem_access, is_stack_access};
+use crate::checkers::{CheckFailure, Checker};
+use crate::utils::ir_utils::{is_mem_access, is_stack_access, is_tail_dispatch};
 use crate::lattices::heaplattice::{HeapLattice, HeapValue};
-use crate::lattices::heaplattice::{WAMR_MODULEINSTANCE_OFFSET, 
-                                   WAMR_HEAPBASE_OFFSET, WAMR_EXCEPTION_OFFSET, WAMR_MEMBOUNDS_OFFSET, 
-                                   WAMR_GLOBALS_OFFSET,
-                                   WAMR_STACKLIMIT_OFFSET,
-                                   WAMR_FUNCTYPE_OFFSET, WAMR_FUNCPTRS_OFFSET,
-                                   WAMR_PAGECNT_OFFSET};
 use crate::lattices::reachingdefslattice::LocIdx;
-use crate::utils::lifter::{IRMap, MemArg, MemArgs, Stmt, ValSize, Value};
-use crate::utils::utils::Compiler;
+use crate::lattices::switchlattice::JumpTableLoads;
+use crate::utils::ir::{IRMap, MemArg, MemArgs, Stmt, Unopcode, ValSize, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+/// The rule a memory access was accepted by, so reviewers can compare the checker's reading of
+/// an access with their own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessClass {
+    Stack,
+    // the heap base, plus offsets bounded to stay in the guard region
+    Heap,
+    // a runtime field the compiler model whitelists
+    Metadata,
+    Global,
+    // a br_table load the switch analysis found
+    JumpTable,
+    // a load from one of the module's read-only data sections
+    ReadOnlyData,
+}
+
+impl AccessClass {
+    /// The stable name of the class, as shown in --dump-cfg and the HTML report.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccessClass::Stack => "stack",
+            AccessClass::Heap => "heap-base+bounded",
+            AccessClass::Metadata => "metadata",
+            AccessClass::Global => "globals",
+            AccessClass::JumpTable => "jump-table",
+            AccessClass::ReadOnlyData => "rodata",
+        }
+    }
+}
+
+/// The class of each memory operand the heap checker accepted, by statement.
+pub type AccessClasses = BTreeMap<LocIdx, Vec<(String, AccessClass)>>;
+
+/// One `<addr>[<stmt>]: <operand>: <class>` line per access, in address order.
+pub fn access_class_lines(accesses: &AccessClasses) -> Vec<String> {
+    accesses
+        .iter()
+        .flat_map(|(loc, classes)| {
+            classes.iter().map(move |(access, class)| format!("0x{:x}[{}]: {}: {}", loc.addr, loc.idx, access, class.name()))
+        })
+        .collect()
+}
 
 pub struct HeapChecker<'a> {
     irmap: &'a IRMap,
     analyzer: &'a HeapAnalyzer,
     func_addrs: &'a Vec<(u64, std::string::String)>,
+    jump_tables: &'a JumpTableLoads,
+    untracked_rsp: &'a UntrackedRsp,
+    classes: RefCell<AccessClasses>,
 }
 
 pub fn check_heap(
@@ -24,17 +67,36 @@ pub fn check_heap(
     irmap: &IRMap,
     analyzer: &HeapAnalyzer,
     func_addrs: &Vec<(u64, std::string::String)>,
-) -> bool {
-    HeapChecker {
+    jump_tables: &JumpTableLoads,
+    untracked_rsp: &UntrackedRsp,
+) -> Result<(), CheckFailure> {
+    classify_heap(result, irmap, analyzer, func_addrs, jump_tables, untracked_rsp).0
+}
+
+/// Checks heap safety like `check_heap`, also returning the class of every access accepted
+/// before the check finished.
+pub fn classify_heap(
+    result: AnalysisResult<HeapLattice>,
+    irmap: &IRMap,
+    analyzer: &HeapAnalyzer,
+    func_addrs: &Vec<(u64, std::string::String)>,
+    jump_tables: &JumpTableLoads,
+    untracked_rsp: &UntrackedRsp,
+) -> (Result<(), CheckFailure>, AccessClasses) {
+    let checker = HeapChecker {
         irmap: irmap,
         analyzer: analyzer,
         func_addrs: func_addrs,
-    }
-    .check(result)
+        jump_tables: jump_tables,
+        untracked_rsp: untracked_rsp,
+        classes: RefCell::new(AccessClasses::new()),
+    };
+    let checked = checker.check(result);
+    (checked, checker.classes.into_inner())
 }
 
 impl Checker<HeapLattice> for HeapChecker<'_> {
-    fn check(&self, result: AnalysisResult<HeapLattice>) -> bool {
+    fn check(&self, result: AnalysisResult<HeapLattice>) -> Result<(), CheckFailure> {
         self.check_state_at_statements(result)
     }
 
@@ -45,161 +107,197 @@ impl Checker<HeapLattice> for HeapChecker<'_> {
         self.analyzer.aexec(state, ir_stmt, loc)
     }
 
-    fn check_statement(&self, state: &HeapLattice, ir_stmt: &Stmt, _loc_idx: &LocIdx) -> bool {
+    fn paranoid(&self) -> bool {
+        self.analyzer.paranoid()
+    }
+
+    fn check_statement(&self, state: &HeapLattice, ir_stmt: &Stmt, loc_idx: &LocIdx) -> Result<(), String> {
+        let check_access = |access: &Value| {
+            self.check_rsp_tracked(state, access, loc_idx)?;
+            if is_mem_access(access) && !self.check_mem_access(state, access, loc_idx) {
+                return Err(format!("memory access outside the sandbox: {}", mem_access_repr(state, access)));
+            }
+            Ok(())
+        };
         match ir_stmt {
             //1. Check that at each call rdi has the expected value
-            Stmt::Call(target) => {
-                match self.analyzer.metadata.compiler {
-                    Compiler::Lucet => {
-                        // For Lucet, this means rdi points to the HeapBase
-                        match state.regs.rdi.v {
-                            Some(HeapValue::HeapBase) => (),
-                            _ => {
-                                println!("Call failure {:?}", state.stack.get(0, 8));
-                                return false;
-                            }
-                        }
-                    },
-                    Compiler::Wamr => {
-                        // For Wamr, this means rdi points to the current ExecEnv
-                        match state.regs.rdi.v {
-                            Some(HeapValue::WamrExecEnv) => (),
-                            _ => {
-                                if let Value::Imm(_, _, addr) = target {
-                                    // handle the exception of calling trusted functions like
-                                    // aot_invoke_native and aot_enlarge_memory
-                                    for (a, _) in self.func_addrs {
-                                        if (*addr as u64) == *a {
-                                            println!("Called aot function without correct value in %rdi!");
-                                            return false;
-                                        }
-                                    }
-                                } else {
-                                    println!("Invalid call instruction: {:?}", ir_stmt);
-                                    return false;
-                                }
-                            }
-                        }
-                    },
+            Stmt::Call(target, len) => {
+                let metadata = &self.analyzer.metadata;
+                if !metadata.model.check_call_site(state, target, *len, loc_idx, self.func_addrs) {
+                    return Err(format!("call to {:?} without the expected context in rdi (the heap base, or the WAMR exec env)", target));
                 }
             }
-            //2. Check that all load and store are safe
-            Stmt::Unop(_, dst, src) => {
-                if is_mem_access(dst) && !self.check_mem_access(state, dst){
-                    return false;
+            // ... and at each tail call (see is_tail_dispatch)
+            Stmt::Branch(_, target) if is_tail_dispatch(ir_stmt, self.analyzer.compiler()) => {
+                let metadata = &self.analyzer.metadata;
+                if !metadata.model.check_call_site(state, target, 0, loc_idx, self.func_addrs) {
+                    return Err(format!("tail call through {:?} without the expected context in rdi (the WAMR exec env)", target));
                 }
-                //stack read: probestack <= stackgrowth + c < 8K
-                if is_mem_access(src) && !self.check_mem_access(state, src){
-                    return false;
+            }
+            //2. Check that atomic read-modify-writes only target memory that may be written
+            Stmt::Unop(Unopcode::Xchg, dst, src) => {
+                self.check_rsp_tracked(state, dst, loc_idx)?;
+                if is_mem_access(dst) && !self.check_rmw_access(state, dst, loc_idx) {
+                    return Err(format!("atomic read-modify-write of memory that isn't writable: {}", mem_access_repr(state, dst)));
                 }
+                check_access(src)?;
+            }
+            //3. Check that all load and store are safe
+            Stmt::Unop(_, dst, src) => {
+                self.check_not_readonly(state, dst)?;
+                check_not_table_entry(state, dst)?;
+                check_access(dst)?;
+                //stack read: probestack <= stackgrowth + c < 8K
+                check_access(src)?;
             }
 
             Stmt::Binop(_, dst, src1, src2) => {
-                if is_mem_access(dst) && !self.check_mem_access(state, dst){
-                    return false;
-                }
-                if is_mem_access(src1) && !self.check_mem_access(state, src1){
-                    return false;
-                }
-                if is_mem_access(src2) && !self.check_mem_access(state, src2){
-                    return false;
-                }
+                self.check_not_readonly(state, dst)?;
+                check_not_table_entry(state, dst)?;
+                check_access(dst)?;
+                check_access(src1)?;
+                check_access(src2)?;
             }
             Stmt::Clear(dst, srcs) => {
-                if is_mem_access(dst) && !self.check_mem_access(state, dst){
-                    return false;
-                }
+                self.check_not_readonly(state, dst)?;
+                check_not_table_entry(state, dst)?;
+                check_access(dst)?;
                 for src in srcs {
-                    if is_mem_access(src) && !self.check_mem_access(state, src){
-                        return false;
-                    }
+                    check_access(src)?;
                 }
             }
             _ => (),
         }
-        true
+        Ok(())
+    }
+}
+
+// guest_table_0 is only read, to look up the target of a call_indirect, so its entries are
+// metadata to loads alone
+fn check_not_table_entry(state: &HeapLattice, dst: &Value) -> Result<(), String> {
+    let is_table = |arg: &MemArg| match arg {
+        MemArg::Reg(regnum, ValSize::Size64) => matches!(
+            state.regs.get(regnum, &ValSize::Size64).v,
+            Some(HeapValue::GuestTable0) | Some(HeapValue::GuestTable0Entry)
+        ),
+        _ => false,
+    };
+    let args = match dst {
+        Value::Mem(_, MemArgs::Mem1Arg(arg)) => vec![arg],
+        Value::Mem(_, MemArgs::Mem2Args(arg1, arg2)) => vec![arg1, arg2],
+        Value::Mem(_, MemArgs::Mem3Args(arg1, arg2, arg3)) |
+        Value::Mem(_, MemArgs::MemScale(arg1, arg2, arg3)) => vec![arg1, arg2, arg3],
+        Value::Mem(_, MemArgs::MemScaleDisp(arg1, arg2, arg3, arg4)) => vec![arg1, arg2, arg3, arg4],
+        _ => vec![],
+    };
+    if args.into_iter().any(is_table) {
+        return Err(format!("store to a guest_table_0 entry: {}", mem_access_repr(state, dst)));
     }
+    Ok(())
+}
+
+// A register at least as tightly bounded as `limit`
+fn bounded_by(state: &HeapLattice, regnum: &u8, size: &ValSize, limit: u64) -> bool {
+    state.regs.get(regnum, size).v.map_or(false, |v| v.bounded_by(limit))
+}
+
+// Linear memory is followed by a guard region; together they span 8GB from the heap base
+pub const HEAP_GUARD_LIMIT: u64 = 1 << 33;
+
+// Whether all `width` bytes at heapbase + index*scale + disp stay below the end of the
+// guard region for every value the (bounded) index may hold
+fn scaled_in_guard(state: &HeapLattice, regnum: &u8, size: &ValSize, scale: i64, disp: i64, width: u64) -> bool {
+    let bound = match state.regs.get(regnum, size).v.and_then(|v| v.bound()) {
+        Some(bound) => bound,
+        None => return false,
+    };
+    if scale <= 0 || disp < 0 {
+        return false;
+    }
+    let max_offset = (bound - 1)
+        .checked_mul(scale as u64)
+        .and_then(|offset| offset.checked_add(disp as u64));
+    max_offset
+        .and_then(|offset| offset.checked_add(width))
+        .map_or(false, |end| end <= HEAP_GUARD_LIMIT)
+}
+
+// Vector accesses are lifted without their width; the widest the lifter sees is an xmm load
+const UNSIZED_ACCESS_BYTES: u64 = 16;
+
+// The number of bytes memory operand `access` may touch
+fn access_bytes(access: &Value) -> u64 {
+    match access {
+        Value::Mem(ValSize::SizeOther, _) => UNSIZED_ACCESS_BYTES,
+        _ => access.width() as u64,
+    }
+}
+
+// Whether `access` is at an absolute address (a rip-relative operand, see lifter::rebase_rip) all
+// of whose bytes are in one of `ranges`
+fn in_readonly_data(ranges: &[(u64, u64)], access: &Value) -> bool {
+    if let Value::Mem(_, MemArgs::Mem1Arg(MemArg::Imm(_, _, addr))) = access {
+        let width = access_bytes(access);
+        let start = *addr as u64;
+        return start
+            .checked_add(width)
+            .map_or(false, |end| ranges.iter().any(|(lo, hi)| *lo <= start && end <= *hi));
+    }
+    false
+}
+
+// Whether register `regnum` holds the heap base
+fn is_heap_base(state: &HeapLattice, regnum: &u8) -> bool {
+    state.regs.get(regnum, &ValSize::Size64).v == Some(HeapValue::HeapBase)
+}
+
+// The safe page is a single page, with no guard region after it
+pub const SAFE_PAGE_SIZE: i64 = 4096;
+
+// Whether `access` is at a constant offset from the heap base or the safe page (see
+// HeapValue::is_heap_base_or_safe_page) that keeps all of its bytes within the first page of both
+fn in_safe_page(state: &HeapLattice, access: &Value) -> bool {
+    let (regnum, offset) = match access {
+        Value::Mem(_, MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64))) => (regnum, 0),
+        Value::Mem(_, MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, offset))) => (regnum, *offset),
+        _ => return false,
+    };
+    let width = access_bytes(access) as i64;
+    let base = state.regs.get(regnum, &ValSize::Size64).v;
+    base.map_or(false, |v| v.is_heap_base_or_safe_page()) && 0 <= offset && offset <= SAFE_PAGE_SIZE - width
 }
 
 impl HeapChecker<'_> {
     fn check_global_access(&self, state: &HeapLattice, access: &Value) -> bool {
-        match self.analyzer.compiler() {
-            Compiler::Lucet => {
-                if let Value::Mem(_, memargs) = access {
-                    match memargs {
-                        MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) => {
-                            if let Some(HeapValue::GlobalsBase) = state.regs.get(regnum, &ValSize::Size64).v
-                            {
-                                return true;
-                            }
-                        }
-                        MemArgs::Mem2Args(
-                            MemArg::Reg(regnum, ValSize::Size64),
-                            MemArg::Imm(_, _, globals_offset),
-                        ) => {
-                            if let Some(HeapValue::GlobalsBase) = state.regs.get(regnum, &ValSize::Size64).v
-                            {
-                                return *globals_offset <= 4096;
-                            }
-                        }
-                        _ => return false,
-                    }
-                }
-                false
-            }
-            Compiler::Wamr => {
-                if let Value::Mem(memsize, memargs) = access {
-                    match memargs {
-                        /*
-                        MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) => {
-                            // accessing the base global variable memory
-                            if let Some(HeapValue::GlobalsBase) = state.regs.get(regnum, &ValSize::Size64).v {
-                                // allowed regardless of the size of global memory in order to
-                                // enable calling aot_invoke_native with a parameter
-                                return true;
-                            }
-                        },
-                        */
-                        MemArgs::Mem2Args(
-                            MemArg::Reg(regnum, ValSize::Size64),
-                            MemArg::Imm(_, _, globals_offset),
-                        ) => {
-                            // accessing an offset from global variable memory
-                            if let Some(HeapValue::WamrModuleInstance) = state.regs.get(regnum, &ValSize::Size64).v {
-                                if *globals_offset >= (WAMR_GLOBALS_OFFSET - 8) {
-                                    let upper_bound = WAMR_GLOBALS_OFFSET + self.analyzer.metadata.globals_size;
-                                    println!("upper bound: {:x}, offset: {:x}", upper_bound, *globals_offset+((memsize.to_u32()/8) as i64));
-                                    return (*globals_offset+((memsize.to_u32()/8) as i64)) <= upper_bound;
-                                }
-                            }
-                        },
-                        _ => return false,
-                    }
-                }
-                false
-            },
-        }
+        let metadata = &self.analyzer.metadata;
+        metadata.model.is_global_access(metadata, state, access)
     }
 
     fn check_heap_access(&self, state: &HeapLattice, access: &Value) -> bool {
+        if in_safe_page(state, access) {
+            return true;
+        }
         if let Value::Mem(_, memargs) = access {
             match memargs {
                 // if only arg is heapbase
                 MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) => {
-                    if let Some(HeapValue::HeapBase) = state.regs.get(regnum, &ValSize::Size64).v {
+                    if is_heap_base(state, regnum) {
                         return true;
                     }
                 }
                 // if arg1 is heapbase and arg2 is bounded
                 MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), memarg2) => {
-                    if let Some(HeapValue::HeapBase) = state.regs.get(regnum, &ValSize::Size64).v {
+                    if is_heap_base(state, regnum) {
                         match memarg2 {
                             MemArg::Reg(regnum2, size2) => {
-                                if let Some(HeapValue::Bounded4GB) =
-                                    state.regs.get(regnum2, size2).v
-                                {
+                                if bounded_by(state, regnum2, size2, 1 << 32) {
                                     return true;
                                 }
+                                // or checked against the memory bound for accesses at least as wide
+                                // (--wamr-bounds-checks)
+                                if let (ValSize::Size64, Some(HeapValue::CheckedHeapOffset(width))) = (size2, state.regs.get(regnum2, size2).v) {
+                                    return access_bytes(access) <= width as u64;
+                                }
                             }
                             MemArg::Imm(_, _, v) => return *v <= 0xffffffff,
                         }
@@ -213,33 +311,28 @@ impl HeapChecker<'_> {
                     let arg2 = memarg3;
                     let mut reg: Option<&u8> = None;
                     if let MemArg::Reg(reg1, ValSize::Size64) = memarg1 {
-                        if let Some(HeapValue::HeapBase) = state.regs.get(reg1, &ValSize::Size64).v {
+                        if is_heap_base(state, reg1) {
                             reg = Some(reg1);
                         }
                     } 
                     if let MemArg::Reg(reg2, ValSize::Size64) = memarg2 {
-                        if let Some(HeapValue::HeapBase) = state.regs.get(reg2, &ValSize::Size64).v {
+                        if is_heap_base(state, reg2) {
                             reg = Some(reg2);
                             arg1 = memarg1;
                         }
                     }
                     // check that the access is bounded
                     if let Some(regnum) = reg {
-                        if let Some(HeapValue::HeapBase) = state.regs.get(regnum, &ValSize::Size64).v {
+                        if is_heap_base(state, regnum) {
                             match (arg1, arg2) {
                                 (MemArg::Reg(regnum2, size2), MemArg::Imm(_, _, v))
                                 | (MemArg::Imm(_, _, v), MemArg::Reg(regnum2, size2)) => {
-                                    if let Some(HeapValue::Bounded4GB) =
-                                        state.regs.get(regnum2, size2).v
-                                    {
+                                    if bounded_by(state, regnum2, size2, 1 << 32) {
                                         return *v <= 0xffffffff;
                                     }
                                 }
                                 (MemArg::Reg(regnum2, size2), MemArg::Reg(regnum3, size3)) => {
-                                    if let (Some(HeapValue::Bounded4GB), Some(HeapValue::Bounded4GB)) = (
-                                        state.regs.get(regnum2, size2).v,
-                                        state.regs.get(regnum3, size3).v,
-                                    ) {
+                                    if bounded_by(state, regnum2, size2, 1 << 32) && bounded_by(state, regnum3, size3, 1 << 32) {
                                         return true;
                                     }
                                 }
@@ -248,18 +341,20 @@ impl HeapChecker<'_> {
                         }
                     }
                 },
-                MemArgs::MemScale(base, disp, scale) => {
-                    match (base, disp, scale) {
-                        (MemArg::Reg(base_regnum, ValSize::Size64), 
-                         MemArg::Reg(disp_regnum, disp_regsize), MemArg::Imm(_, _, immval)) => {
-                            if let (Some(HeapValue::HeapBase), Some(HeapValue::Bounded256B)) = (
-                                state.regs.get(base_regnum, &ValSize::Size64).v,
-                                state.regs.get(disp_regnum, &disp_regsize).v,
-                            ) {
-                                return *immval < (1 << 25);
-                            }
-                        },
-                        _ => return false,
+                // if arg1 is heapbase and the scaled index (plus displacement) stays in the guard region
+                MemArgs::MemScale(MemArg::Reg(base_regnum, ValSize::Size64), MemArg::Reg(index_regnum, index_size), MemArg::Imm(_, _, scale)) => {
+                    if is_heap_base(state, base_regnum) {
+                        return scaled_in_guard(state, index_regnum, index_size, *scale, 0, access_bytes(access));
+                    }
+                },
+                MemArgs::MemScaleDisp(
+                    MemArg::Reg(base_regnum, ValSize::Size64),
+                    MemArg::Reg(index_regnum, index_size),
+                    MemArg::Imm(_, _, scale),
+                    MemArg::Imm(_, _, disp),
+                ) => {
+                    if is_heap_base(state, base_regnum) {
+                        return scaled_in_guard(state, index_regnum, index_size, *scale, *disp, access_bytes(access));
                     }
                 },
                 _ => return false,
@@ -268,188 +363,109 @@ impl HeapChecker<'_> {
         false
     }
 
-    fn lucet_check_metadata_access(&self, state: &HeapLattice, access: &Value) -> bool {
-        if let Value::Mem(_size, memargs) = access {
-            match memargs{
-                //Case 1: mem[globals_base]
-                MemArgs::Mem1Arg(MemArg::Reg(regnum,ValSize::Size64)) => {
-                    if let  Some(HeapValue::GlobalsBase) = state.regs.get(regnum,&ValSize::Size64).v {
-                        return true
-                    }
-                },
-                //Case 2: mem[lucet_tables + 8]
-                MemArgs::Mem2Args(MemArg::Reg(regnum,ValSize::Size64), MemArg::Imm(_,_,8)) => {
-                    if let Some(HeapValue::LucetTables) = state.regs.get(regnum,&ValSize::Size64).v{
-                        return true
-                    }
-                },
-                MemArgs::Mem2Args(MemArg::Reg(regnum1,ValSize::Size64), MemArg::Reg(regnum2,ValSize::Size64)) => {
-                    if let Some(HeapValue::GuestTable0) = state.regs.get(regnum1,&ValSize::Size64).v{
-                        return true
-                    }
-                    if let Some(HeapValue::GuestTable0) = state.regs.get(regnum2,&ValSize::Size64).v{
-                        return true
-                    }
-                },
-                MemArgs::Mem3Args(MemArg::Reg(regnum1,ValSize::Size64),MemArg::Reg(regnum2,ValSize::Size64), MemArg::Imm(_,_,8)) => {
-                    match (state.regs.get(regnum1,&ValSize::Size64).v,state.regs.get(regnum2,&ValSize::Size64).v){
-                        (Some(HeapValue::GuestTable0),_) => return true,
-                        (_,Some(HeapValue::GuestTable0)) => return true,
-                        _ => ()
-                    }
-                }
-                _ => return false
-            }
+    fn check_metadata_access(&self, state: &HeapLattice, access: &Value) -> bool {
+        let metadata = &self.analyzer.metadata;
+        metadata.model.is_metadata_access(metadata, state, access)
+    }
+
+    fn check_readonly_access(&self, access: &Value) -> bool {
+        in_readonly_data(&self.analyzer.metadata.readonly_data, access)
+    }
+
+    // Read-only data is only ever loaded from, so a store to it is as wrong as a store anywhere else
+    // outside the sandbox
+    fn check_not_readonly(&self, state: &HeapLattice, dst: &Value) -> Result<(), String> {
+        if self.check_readonly_access(dst) {
+            return Err(format!("store to read-only data: {}", mem_access_repr(state, dst)));
         }
-        false
+        Ok(())
     }
 
-    fn wamr_check_metadata_access(&self, state: &HeapLattice, access: &Value) -> bool {
-        if let Value::Mem(_size, memargs) = access {
-            match memargs {
-                //Case 1: mem[WamrExecEnv+WAMR_MODULEINSTANCE_OFFSET]
-                MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, WAMR_MODULEINSTANCE_OFFSET)) => {
-                    if let Some(HeapValue::WamrExecEnv) = state.regs.get(regnum, &ValSize::Size64).v {
-                        return true;
-                    }
-                },
-                //Case 2: mem[WamrModuleInstance+WAMR_HEAPBASE_OFFSET]
-                MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, WAMR_HEAPBASE_OFFSET)) => {
-                    if let Some(HeapValue::WamrModuleInstance) = state.regs.get(regnum, &ValSize::Size64).v {
-                        return true;
-                    }
-                },
-                //Case 3: mem[WamrModuleInstance+WAMR_EXCEPTION_OFFSET]
-                MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, WAMR_EXCEPTION_OFFSET)) => {
-                    if let Some(HeapValue::WamrModuleInstance) = state.regs.get(regnum, &ValSize::Size64).v {
-                        return true;
-                    }
-                },
-                //Case 4: mem[WamrModuleInstance+WAMR_MEMBOUNDS_OFFSET]
-                MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, WAMR_MEMBOUNDS_OFFSET)) => {
-                    if let Some(HeapValue::WamrModuleInstance) = state.regs.get(regnum, &ValSize::Size64).v {
-                        return true;
-                    }
-                },
-                //Case 5: mem[WamrExecEnv+WAMR_STACKLIMIT_OFFSET]
-                MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, WAMR_STACKLIMIT_OFFSET)) => {
-                    if let Some(HeapValue::WamrExecEnv) = state.regs.get(regnum, &ValSize::Size64).v {
-                        return true;
-                    }
-                },
-                //Case 6: mem[WamrModuleInstance+WAMR_FUNCTYPE_OFFSET]
-                MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, WAMR_FUNCTYPE_OFFSET)) => {
-                    if let Some(HeapValue::WamrModuleInstance) = state.regs.get(regnum, &ValSize::Size64).v {
-                        return true;
-                    }
-                },
-                //Case 7: mem[WamrModuleInstance+WAMR_FUNCPTRS_OFFSET]
-                MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, WAMR_FUNCPTRS_OFFSET)) => {
-                    if let Some(HeapValue::WamrModuleInstance) = state.regs.get(regnum, &ValSize::Size64).v {
-                        return true;
-                    }
-                },
-                //Case 8: mem[WamrModuleInstance+WAMR_PAGECNT_OFFSET]
-                MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, WAMR_PAGECNT_OFFSET)) => {
-                    if let Some(HeapValue::WamrModuleInstance) = state.regs.get(regnum, &ValSize::Size64).v {
-                        return true;
-                    }
-                },
-                //Case 9: mem[WamrStackLimit]
-                MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) => {
-                    if let Some(HeapValue::WamrStackLimit) = state.regs.get(regnum,&ValSize::Size64).v {
-                        return true;
-                    }
-                }
-                _ => return false,
+    // An access through %rsp is only a stack access while %rsp points into the frame under analysis
+    fn check_rsp_tracked(&self, state: &HeapLattice, access: &Value, loc_idx: &LocIdx) -> Result<(), String> {
+        if is_mem_access(access) && is_stack_access(access) {
+            if let Some(lost_at) = self.untracked_rsp.get(loc_idx) {
+                return Err(format!(
+                    "stack access after rsp was lost track of at 0x{:x}: {}",
+                    lost_at,
+                    mem_access_repr(state, access)
+                ));
             }
         }
-        false
+        Ok(())
     }
 
-    fn check_metadata_access(&self, state: &HeapLattice, access: &Value) -> bool {
-        match self.analyzer.metadata.compiler {
-            Compiler::Lucet => return self.lucet_check_metadata_access(state, access),
-            Compiler::Wamr => return self.wamr_check_metadata_access(state, access),
-        }
+    fn check_jump_table_access(&self, state: &HeapLattice, access: &Value, loc_idx: &LocIdx) -> bool {
+        let metadata = &self.analyzer.metadata;
+        metadata.model.is_jump_table_access(metadata, state, access, loc_idx, self.jump_tables)
     }
 
-    fn check_jump_table_access(&self, state: &HeapLattice, access: &Value) -> bool {
-        match self.analyzer.metadata.compiler {
-            Compiler::Lucet => {
-                if let Value::Mem(_size, memargs) = access {
-                    match memargs {
-                        MemArgs::MemScale(_, _, MemArg::Imm(_, _, 4)) => return true,
-                        _ => return false,
-                    }
-                }
-                false
-            },
-            Compiler::Wamr => {
-                if let Value::Mem(_size, memargs) = access {
-                    match memargs {
-                        // Case 1: an access to the table of function indexes
-                        MemArgs::MemScaleDisp(MemArg::Reg(regnum, ValSize::Size64),
-                                              MemArg::Reg(_, _), MemArg::Imm(_, _, 4),
-                                              MemArg::Imm(_, _, immval)) => {
-                            if let Some(HeapValue::WamrModuleInstance) = state.regs.get(regnum, &ValSize::Size64).v {
-                                if *immval >= WAMR_GLOBALS_OFFSET ||
-                                        *immval == WAMR_GLOBALS_OFFSET - 4 ||
-                                        *immval == WAMR_GLOBALS_OFFSET - 8 {
-                                    // responsibility of call checker to check this is in-bounds
-                                    return true;
-                                }
-                            }
-                        },
-                        // Case 2: an access to the table of function types
-                        MemArgs::MemScale(MemArg::Reg(regnum, ValSize::Size64), 
-                                          MemArg::Reg(_, ValSize::Size64), MemArg::Imm(_, _, 4)) => {
-                            if let Some(HeapValue::WamrFuncTypeTable) = state.regs.get(regnum, &ValSize::Size64).v {
-                                // responsibility of call checker to check this is a valid index
-                                return true;
-                            }
-                        },
-                        // Case 3: an access to the table of function pointers
-                        MemArgs::MemScale(MemArg::Reg(regnum, ValSize::Size64), 
-                                          MemArg::Reg(_, ValSize::Size64), MemArg::Imm(_, _, 8)) => {
-                            if let Some(HeapValue::WamrFuncPtrsTable) = state.regs.get(regnum, &ValSize::Size64).v {
-                                // responsibility of call checker to check this is a valid index
-                                return true;
-                            }
-                        },
-                        _ => return false,
-                    }
-                }
+    // Runtime metadata is only read (or stored to plainly) by generated code; an RMW has to hit the
+    // stack, the heap, a global, or a field the runtime expects to be written
+    fn check_rmw_access(&self, state: &HeapLattice, access: &Value, loc_idx: &LocIdx) -> bool {
+        let metadata = &self.analyzer.metadata;
+        let class = if is_stack_access(access) {
+            AccessClass::Stack
+        } else if self.check_heap_access(state, access) {
+            AccessClass::Heap
+        } else if self.check_global_access(state, access) {
+            AccessClass::Global
+        } else if metadata.model.is_runtime_writable(metadata, state, access) {
+            AccessClass::Metadata
+        } else {
+            log::error!("Atomic read-modify-write of memory that isn't writable!");
+            print_mem_access(state, access);
+            return false;
+        };
+        self.record_class(access, loc_idx, class);
+        true
+    }
+
+    fn check_mem_access(&self, state: &HeapLattice, access: &Value, loc_idx: &LocIdx) -> bool {
+        match self.classify_mem_access(state, access, loc_idx) {
+            Some(class) => {
+                self.record_class(access, loc_idx, class);
+                true
+            }
+            None => {
+                log::error!("None of the memory accesses!");
+                print_mem_access(state, access);
                 false
-            },
+            }
         }
     }
 
-    fn check_mem_access(&self, state: &HeapLattice, access: &Value) -> bool {
+    fn classify_mem_access(&self, state: &HeapLattice, access: &Value, loc_idx: &LocIdx) -> Option<AccessClass> {
         // Case 1: its a stack access
         if is_stack_access(access) {
-            return true;
+            return Some(AccessClass::Stack);
         }
         // Case 2: its a heap access
         if self.check_heap_access(state, access) {
-            return true;
+            return Some(AccessClass::Heap);
         };
         // Case 3: its a metadata access
         if self.check_metadata_access(state, access) {
-            return true;
+            return Some(AccessClass::Metadata);
         };
         // Case 4: its a globals access
         if self.check_global_access(state, access) {
-            return true;
+            return Some(AccessClass::Global);
         };
         // Case 5: Jump table access
-        if self.check_jump_table_access(state, access) {
-            return true;
+        if self.check_jump_table_access(state, access, loc_idx) {
+            return Some(AccessClass::JumpTable);
         };
-        // Case 6: its unknown
-        println!("None of the memory accesses!");
-        print_mem_access(state, access);
-        return false;
+        // Case 6: its a load from read-only data
+        if self.check_readonly_access(access) {
+            return Some(AccessClass::ReadOnlyData);
+        };
+        // Case 7: its unknown
+        None
+    }
+
+    fn record_class(&self, access: &Value, loc_idx: &LocIdx, class: AccessClass) {
+        self.classes.borrow_mut().entry(*loc_idx).or_default().push((format!("{:?}", access), class));
     }
 }
 
@@ -460,34 +476,519 @@ pub fn memarg_repr(state: &HeapLattice, memarg: &MemArg) -> String {
     }
 }
 
-pub fn print_mem_access(state: &HeapLattice, access: &Value) {
-    if let Value::Mem(_, memargs) = access {
-        match memargs {
-            MemArgs::Mem1Arg(x) => println!("mem[{:?}]", memarg_repr(state, x)),
-            MemArgs::Mem2Args(x, y) => println!(
+pub fn mem_access_repr(state: &HeapLattice, access: &Value) -> String {
+    match access {
+        Value::Mem(_, memargs) => match memargs {
+            MemArgs::Mem1Arg(x) => format!("mem[{:?}]", memarg_repr(state, x)),
+            MemArgs::Mem2Args(x, y) => format!(
                 "mem[{:?} + {:?}]",
                 memarg_repr(state, x),
                 memarg_repr(state, y)
             ),
-            MemArgs::Mem3Args(x, y, z) => println!(
+            MemArgs::Mem3Args(x, y, z) => format!(
                 "mem[{:?} + {:?} + {:?}]",
                 memarg_repr(state, x),
                 memarg_repr(state, y),
                 memarg_repr(state, z)
             ),
-            MemArgs::MemScale(x, y, z) => println!(
+            MemArgs::MemScale(x, y, z) => format!(
                 "mem[{:?} + {:?} * {:?}]",
                 memarg_repr(state, x),
                 memarg_repr(state, y),
                 memarg_repr(state, z)
             ),
-            MemArgs::MemScaleDisp(w, x, y, z) => println!(
+            MemArgs::MemScaleDisp(w, x, y, z) => format!(
                 "mem[{:?} + {:?}*{:?} + {:?}]",
                 memarg_repr(state, w),
                 memarg_repr(state, x),
                 memarg_repr(state, y),
                 memarg_repr(state, z)
             ),
+        },
+        _ => format!("{:?}", access),
+    }
+}
+
+pub fn print_mem_access(state: &HeapLattice, access: &Value) {
+    log::error!("{}", mem_access_repr(state, access));
+}
+
+#[test]
+fn jump_table_access_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::ir::{ImmType, Unopcode};
+
+    let analyzer = HeapAnalyzer {
+        metadata: CompilerMetadata::for_test(Compiler::Lucet),
+    };
+    // mov eax, [rbx + rcx*4]; ret
+    let load = Stmt::Unop(
+        Unopcode::Mov,
+        Value::Reg(0, ValSize::Size32),
+        Value::Mem(ValSize::Size32, MemArgs::MemScale(
+            MemArg::Reg(3, ValSize::Size64),
+            MemArg::Reg(1, ValSize::Size64),
+            MemArg::Imm(ImmType::Signed, ValSize::Size32, 4),
+        )),
+    );
+    let mut irmap = IRMap::new();
+    irmap.insert(0, vec![(0, vec![load]), (3, vec![Stmt::Ret])]);
+    let check = |jump_tables: &JumpTableLoads| {
+        check_heap(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer, &vec![], jump_tables, &UntrackedRsp::new()).is_ok()
+    };
+
+    // rbx and rcx are unknown, so this reads arbitrary memory
+    assert!(!check(&JumpTableLoads::new()));
+    // a br_table load the switch analysis found
+    let mut jump_tables = JumpTableLoads::new();
+    jump_tables.insert(0, (0x1000, 4));
+    assert!(check(&jump_tables));
+    // a different load in the same function doesn't count
+    let mut jump_tables = JumpTableLoads::new();
+    jump_tables.insert(3, (0x1000, 4));
+    assert!(!check(&jump_tables));
+}
+
+#[test]
+fn runtime_field_rmw_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::lattices::heaplattice::{WAMR_FUNCPTRS_OFFSET, WAMR_MODULEINSTANCE_OFFSET};
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::ir::ImmType;
+    use crate::utils::wamr_layout::RuntimeField;
+
+    let mem = |size: ValSize, regnum: u8, offset: i64| Value::Mem(
+        size,
+        MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)),
+    );
+    let ecx = Value::Reg(1, ValSize::Size32);
+    // mov rax, [rdi + 0x10] (the ModuleInstance); <stmt>; ret
+    let check = |stmt: Stmt| {
+        let analyzer = HeapAnalyzer {
+            metadata: CompilerMetadata {
+                globals_size: 0,
+                runtime_fields: vec![RuntimeField { offset: 0x1c0, width: 4 }],
+                ..CompilerMetadata::for_test(Compiler::Wamr)
+            },
+        };
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![
+            (0, vec![Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size64), mem(ValSize::Size64, 7, WAMR_MODULEINSTANCE_OFFSET))]),
+            (4, vec![stmt]),
+            (8, vec![Stmt::Ret]),
+        ]);
+        check_heap(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+
+    // xchg [rax + 0x1c0], ecx on a configured lock word
+    assert!(check(Stmt::Unop(Unopcode::Xchg, mem(ValSize::Size32, 0, 0x1c0), ecx.clone())));
+    assert!(check(Stmt::Unop(Unopcode::Mov, ecx.clone(), mem(ValSize::Size32, 0, 0x1c0))));
+    // an unconfigured offset, and a wider access than the field
+    assert!(!check(Stmt::Unop(Unopcode::Xchg, mem(ValSize::Size32, 0, 0x1d0), ecx.clone())));
+    assert!(!check(Stmt::Unop(Unopcode::Xchg, mem(ValSize::Size64, 0, 0x1c0), Value::Reg(1, ValSize::Size64))));
+    // the function pointer table may be read, but not exchanged
+    assert!(check(Stmt::Unop(Unopcode::Mov, Value::Reg(1, ValSize::Size64), mem(ValSize::Size64, 0, WAMR_FUNCPTRS_OFFSET))));
+    assert!(!check(Stmt::Unop(Unopcode::Xchg, mem(ValSize::Size64, 0, WAMR_FUNCPTRS_OFFSET), Value::Reg(1, ValSize::Size64))));
+}
+
+#[test]
+fn scaled_heap_access_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::ir::{Binopcode, ImmType, Unopcode};
+
+    let analyzer = HeapAnalyzer {
+        metadata: CompilerMetadata::for_test(Compiler::Lucet),
+    };
+    let imm = |v: i64| MemArg::Imm(ImmType::Signed, ValSize::Size32, v);
+    let rcx = Value::Reg(1, ValSize::Size64);
+    // <bound rcx>; mov rax, [rdi + rcx*scale + disp]
+    let check = |bound: Stmt, scale: i64, disp: i64| {
+        let load = Stmt::Unop(
+            Unopcode::Mov,
+            Value::Reg(0, ValSize::Size64),
+            Value::Mem(ValSize::Size64, MemArgs::MemScaleDisp(MemArg::Reg(7, ValSize::Size64), MemArg::Reg(1, ValSize::Size64), imm(scale), imm(disp))),
+        );
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![(0, vec![bound]), (4, vec![load]), (8, vec![Stmt::Ret])]);
+        check_heap(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+    let and = |mask: i64| Stmt::Binop(Binopcode::And, rcx.clone(), rcx.clone(), Value::Imm(ImmType::Signed, ValSize::Size32, mask));
+    let mov32 = Stmt::Unop(Unopcode::Mov, Value::Reg(1, ValSize::Size32), Value::Reg(2, ValSize::Size32));
+
+    // and rcx, 0xffff: at most 0xffff * 8 + 0x100 past the heap base
+    assert!(check(and(0xffff), 8, 0x100));
+    // mov ecx, edx: up to 4GB * 8, past the guard region
+    assert!(!check(mov32.clone(), 8, 0));
+    // but unscaled, that's within it
+    assert!(check(mov32, 1, 0x1000));
+    // mov rcx, 3 (a small immediate)
+    assert!(check(Stmt::Unop(Unopcode::Mov, rcx.clone(), Value::Imm(ImmType::Signed, ValSize::Size32, 3)), 8, 0));
+    // a negative mask, or displacement, bounds nothing
+    assert!(!check(and(-16), 8, 0));
+    assert!(!check(and(0xff), 8, -8));
+    // an unbounded index
+    assert!(!check(Stmt::Clear(rcx.clone(), vec![]), 1, 0));
+}
+
+#[test]
+fn masked_heap_store_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::ir::{Binopcode, ImmType, Unopcode};
+
+    let analyzer = HeapAnalyzer {
+        metadata: CompilerMetadata::for_test(Compiler::Lucet),
+    };
+    let rax = Value::Reg(0, ValSize::Size64);
+    let binop = |opcode: Binopcode, v: i64| Stmt::Binop(opcode, rax.clone(), rax.clone(), Value::Imm(ImmType::Signed, ValSize::Size32, v));
+    // <index rax>; mov [rdi + rax], rbx
+    let check = |index: Vec<Stmt>| {
+        let store = Stmt::Unop(
+            Unopcode::Mov,
+            Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Reg(0, ValSize::Size64))),
+            Value::Reg(3, ValSize::Size64),
+        );
+        let mut block: Vec<(u64, Vec<Stmt>)> = index.into_iter().enumerate().map(|(i, s)| (i as u64 * 4, vec![s])).collect();
+        block.push((block.len() as u64 * 4, vec![store]));
+        let mut irmap = IRMap::new();
+        irmap.insert(0, block);
+        check_heap(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+
+    // and rax, 0xfffff
+    assert!(check(vec![binop(Binopcode::And, 0xfffff)]));
+    // and rax, 0xfffff; shl rax, 8 / shr rax, 8
+    assert!(check(vec![binop(Binopcode::And, 0xfffff), binop(Binopcode::Shl, 8)]));
+    assert!(check(vec![binop(Binopcode::And, 0xfffff), binop(Binopcode::Shr, 8)]));
+    // and rax, 0xfffff; shl rax, 24: the bound overflows 4GB
+    assert!(!check(vec![binop(Binopcode::And, 0xfffff), binop(Binopcode::Shl, 24)]));
+    // no mask, or one that only clears the low bits
+    assert!(!check(vec![]));
+    assert!(!check(vec![binop(Binopcode::And, -16)]));
+}
+
+#[test]
+#[cfg(feature = "loader")]
+fn spectre_safe_page_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::lifter::lift;
+    use yaxpeax_arch::{Arch, Decoder};
+    use yaxpeax_x86::long_mode::{Arch as AMD64, Opcode};
+
+    // see tests/spectre/mitigated.s
+    let code = include_bytes!("../../tests/spectre/mitigated.bin");
+    let check = |safe_page: Option<u64>, start: u64| {
+        let analyzer = HeapAnalyzer {
+            metadata: CompilerMetadata {
+                lucet_safe_page: safe_page,
+                ..CompilerMetadata::for_test(Compiler::Lucet)
+            },
+        };
+        let mut block = Vec::new();
+        let mut addr = start;
+        loop {
+            let instr = <AMD64 as Arch>::Decoder::default().decode(code[addr as usize..].iter().cloned()).unwrap();
+            block.push((addr, lift(&instr, &addr, &analyzer.metadata).unwrap()));
+            addr += instr.length as u64;
+            if instr.opcode == Opcode::RETURN {
+                break;
+            }
         }
+        let mut irmap = IRMap::new();
+        irmap.insert(start, block);
+        check_heap(run_worklist(&SingleBlock(start), &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+
+    // the access is through the heap base or the safe page, whichever the cmov picked, and stays
+    // within the first page of both
+    assert!(check(Some(0x5000), 0x0));
+    assert!(check(Some(0x5000), 0x40));
+    // but not one byte further, nor at an index, as nothing follows the safe page
+    assert!(!check(Some(0x5000), 0x80));
+    assert!(!check(Some(0x5000), 0xc0));
+    // a "safe page" other than the declared one may be anywhere
+    assert!(!check(Some(0x6000), 0x0));
+    // as may any page of a module that isn't mitigated
+    assert!(!check(None, 0x0));
+}
+
+#[test]
+fn setcc_scaled_index_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::utils::compiler::{AnalysisOptions, Compiler, CompilerMetadata};
+    use crate::utils::ir::{ImmType, Unopcode};
+
+    let imm = |v: i64| MemArg::Imm(ImmType::Signed, ValSize::Size32, v);
+    // [setb al]; movzx eax, al; mov [rdi + rax*4 + disp], ecx
+    let check = |options: AnalysisOptions, setcc: bool, disp: i64| {
+        let analyzer = HeapAnalyzer {
+            metadata: CompilerMetadata {
+                options: options,
+                ..CompilerMetadata::for_test(Compiler::Lucet)
+            },
+        };
+        let mut block = vec![];
+        if setcc {
+            block.push((0x0, vec![Stmt::Unop(Unopcode::Set, Value::Reg(0, ValSize::Size8), Value::Reg(16, ValSize::Size8))]));
+        }
+        block.push((0x3, vec![Stmt::Unop(Unopcode::MovZx, Value::Reg(0, ValSize::Size32), Value::Reg(0, ValSize::Size8))]));
+        block.push((0x6, vec![Stmt::Unop(
+            Unopcode::Mov,
+            Value::Mem(ValSize::Size32, MemArgs::MemScaleDisp(MemArg::Reg(7, ValSize::Size64), MemArg::Reg(0, ValSize::Size64), imm(4), imm(disp))),
+            Value::Reg(1, ValSize::Size32),
+        )]));
+        block.push((0xa, vec![Stmt::Ret]));
+        let mut irmap = IRMap::new();
+        irmap.insert(0, block);
+        check_heap(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+
+    // the index is 0 or 1, so the access ends at most 8 bytes past the displacement, right at the
+    // end of the guard region here
+    let near_limit = HEAP_GUARD_LIMIT as i64 - 8;
+    assert!(check(Default::default(), true, near_limit));
+    // but a byte further its last byte is past it
+    assert!(!check(Default::default(), true, near_limit + 1));
+    // any byte scaled by 4 is too far there, but not at a usual displacement
+    assert!(!check(Default::default(), false, near_limit));
+    assert!(check(Default::default(), false, 0x100));
+    // the movzx bounds the index by a byte even without bound_8bit, which the setcc then can't tighten
+    let options = AnalysisOptions { bound_8bit: false, ..Default::default() };
+    assert!(check(options, true, 0x100));
+    assert!(!check(options, true, near_limit));
+}
+
+#[test]
+fn access_class_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::ir::ImmType;
+
+    let analyzer = HeapAnalyzer {
+        metadata: CompilerMetadata {
+            lucet_tables: 0x9000_2000,
+            ..CompilerMetadata::for_test(Compiler::Lucet)
+        },
+    };
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let imm = |v: i64| MemArg::Imm(ImmType::Signed, ValSize::Size64, v);
+    let mov = |dst: Value, src: Value| Stmt::Unop(Unopcode::Mov, dst, src);
+    let stack = Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), imm(8)));
+    let heap = Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), imm(0x10)));
+    let global = Value::Mem(ValSize::Size32, MemArgs::Mem2Args(MemArg::Reg(1, ValSize::Size64), imm(8)));
+    let table = Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(2, ValSize::Size64), imm(8)));
+    let jump_table = Value::Mem(ValSize::Size32, MemArgs::MemScale(
+        MemArg::Reg(3, ValSize::Size64),
+        MemArg::Reg(1, ValSize::Size64),
+        MemArg::Imm(ImmType::Signed, ValSize::Size32, 4),
+    ));
+    let wild = Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(13, ValSize::Size64)));
+    // mov rax, [rsp + 8]; mov rcx, [rdi + 0x10] (the globals base); mov eax, [rcx + 8];
+    // mov rdx, lucet_tables; mov rax, [rdx + 8]; mov eax, [rbx + rcx*4]; mov rax, [r13]
+    let mut irmap = IRMap::new();
+    irmap.insert(0, vec![
+        (0, vec![mov(reg(0), stack.clone())]),
+        (4, vec![mov(reg(1), heap.clone())]),
+        (8, vec![mov(Value::Reg(0, ValSize::Size32), global.clone())]),
+        (0xc, vec![mov(reg(2), Value::Imm(ImmType::Signed, ValSize::Size64, 0x9000_2000))]),
+        (0x10, vec![mov(reg(0), table.clone())]),
+        (0x14, vec![mov(Value::Reg(0, ValSize::Size32), jump_table.clone())]),
+        (0x18, vec![mov(reg(0), wild)]),
+    ]);
+    let mut jump_tables = JumpTableLoads::new();
+    jump_tables.insert(0x14, (0x1000, 4));
+
+    let (checked, accesses) = classify_heap(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer, &vec![], &jump_tables, &UntrackedRsp::new());
+    // the access through r13 is unknown, and every access before it was classified
+    assert_eq!(checked.unwrap_err().loc.addr, 0x18);
+    let expected: AccessClasses = vec![
+        (0, &stack, AccessClass::Stack),
+        (4, &heap, AccessClass::Heap),
+        (8, &global, AccessClass::Global),
+        (0x10, &table, AccessClass::Metadata),
+        (0x14, &jump_table, AccessClass::JumpTable),
+    ]
+    .into_iter()
+    .map(|(addr, access, class)| (LocIdx { addr: addr, idx: 0 }, vec![(format!("{:?}", access), class)]))
+    .collect();
+    assert_eq!(accesses, expected);
+    assert_eq!(access_class_lines(&accesses)[4], format!("0x14[0]: {:?}: jump-table", jump_table));
+}
+
+#[test]
+fn wamr_spilled_index_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::lattices::stackgrowthlattice::WAMR_CALLER_WINDOW;
+    use crate::utils::ir::{Binopcode, ImmType, Unopcode};
+
+    let analyzer = HeapAnalyzer {
+        metadata: CompilerMetadata {
+            globals_size: 0,
+            call_table_size: 0,
+            caller_window: WAMR_CALLER_WINDOW,
+            ..CompilerMetadata::for_test(Compiler::Wamr)
+        },
+    };
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let imm = |v: i64| MemArg::Imm(ImmType::Signed, ValSize::Size64, v);
+    let load = |dst: u8, base: u8, offset: i64| {
+        Stmt::Unop(Unopcode::Mov, reg(dst), Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(base, ValSize::Size64), imm(offset))))
+    };
+    let slot = |offset: i64| Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), imm(offset)));
+    // mov rbx, [rdi + 0x10]; mov r12, [rbx + 0x150]; mov eax, esi; <spill and reload rax>;
+    // mov [r12 + rax], rbx
+    let check = |spill: Vec<Stmt>| {
+        let mut stmts = vec![
+            load(3, 7, 0x10),
+            load(12, 3, 0x150),
+            Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size32), Value::Reg(6, ValSize::Size32)),
+        ];
+        stmts.extend(spill);
+        stmts.push(Stmt::Unop(
+            Unopcode::Mov,
+            Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(12, ValSize::Size64), MemArg::Reg(0, ValSize::Size64))),
+            reg(3),
+        ));
+        let block: Vec<(u64, Vec<Stmt>)> = stmts.into_iter().enumerate().map(|(i, s)| (i as u64 * 4, vec![s])).collect();
+        let mut irmap = IRMap::new();
+        irmap.insert(0, block);
+        check_heap(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok()
+    };
+    let spill = Stmt::Unop(Unopcode::Mov, slot(8), reg(0));
+    let clobber = Stmt::Binop(Binopcode::Add, reg(0), reg(0), reg(2));
+
+    assert!(check(vec![]));
+    assert!(!check(vec![clobber.clone()]));
+    // mov [rsp + 8], rax; add rax, rdx; mov rax, [rsp + 8]
+    assert!(check(vec![spill.clone(), clobber.clone(), Stmt::Unop(Unopcode::Mov, reg(0), slot(8))]));
+    // a reload from another slot, or after the slot was overwritten
+    assert!(!check(vec![spill.clone(), clobber.clone(), Stmt::Unop(Unopcode::Mov, reg(0), slot(16))]));
+    assert!(!check(vec![spill.clone(), Stmt::Unop(Unopcode::Mov, slot(8), reg(2)), Stmt::Unop(Unopcode::Mov, reg(0), slot(8))]));
+    // mov [rsp + 8], rax; call; mov rax, [rsp + 8]: the callee may write its caller's window
+    let call = Stmt::Call(Value::Imm(ImmType::Signed, ValSize::Size32, 0x100), 5);
+    assert!(!check(vec![spill, call.clone(), Stmt::Unop(Unopcode::Mov, reg(0), slot(8))]));
+    // but not the slots above it
+    let above = slot(WAMR_CALLER_WINDOW + 8);
+    assert!(check(vec![Stmt::Unop(Unopcode::Mov, above.clone(), reg(0)), call, Stmt::Unop(Unopcode::Mov, reg(0), above)]));
+}
+
+#[test]
+fn untracked_rsp_access_test() {
+    use crate::analyses::stack_analyzer::{untracked_rsp, StackAnalyzer};
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::{SingleBlock, Triangle};
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::ir::{Binopcode, ImmType};
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let heap_analyzer = HeapAnalyzer { metadata: metadata.clone() };
+    let stack_analyzer = StackAnalyzer { metadata: metadata, stack_limit_cmps: Default::default() };
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let sub_rsp = |v: i64| Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(v));
+    // mov rcx, [rsp + 8]
+    let load = Stmt::Unop(
+        Unopcode::Mov,
+        Value::Reg(1, ValSize::Size64),
+        Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, 8))),
+    );
+    let check = |blocks: Vec<(u64, Vec<Stmt>)>| {
+        let mut irmap = IRMap::new();
+        for (addr, stmts) in blocks {
+            irmap.insert(addr, stmts.into_iter().enumerate().map(|(i, s)| (addr + i as u64 * 4, vec![s])).collect());
+        }
+        let stack_result = run_worklist(&Triangle, &irmap, &stack_analyzer);
+        let untracked = untracked_rsp(&Triangle, &irmap, &stack_result, &stack_analyzer);
+        check_heap(run_worklist(&Triangle, &irmap, &heap_analyzer), &irmap, &heap_analyzer, &vec![], &JumpTableLoads::new(), &untracked)
+            .map_err(|failure| (failure.loc.addr, failure.description))
+    };
+
+    // sub rsp, 16; ... mov rcx, [rsp + 8]; add rsp, 16; ret
+    assert!(check(vec![
+        (0, vec![sub_rsp(16)]),
+        (0x10, vec![Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size64), imm(1))]),
+        (0x20, vec![load.clone(), sub_rsp(-16), Stmt::Ret]),
+    ])
+    .is_ok());
+    // mov rsp, rax; ... mov rcx, [rsp + 8] reads wherever rax pointed
+    let (addr, description) = check(vec![
+        (0, vec![sub_rsp(16), Stmt::Unop(Unopcode::Mov, rsp.clone(), Value::Reg(0, ValSize::Size64))]),
+        (0x10, vec![Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size64), imm(1))]),
+        (0x20, vec![load.clone(), Stmt::Ret]),
+    ])
+    .unwrap_err();
+    assert_eq!(addr, 0x20);
+    assert!(description.starts_with("stack access after rsp was lost track of at 0x4"));
+    // as does one where the paths to it left rsp at different depths
+    let (addr, description) = check(vec![
+        (0, vec![sub_rsp(16)]),
+        (0x10, vec![sub_rsp(8)]),
+        (0x20, vec![Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size64), imm(1)), load.clone(), Stmt::Ret]),
+    ])
+    .unwrap_err();
+    assert_eq!(addr, 0x24);
+    assert!(description.starts_with("stack access after rsp was lost track of at 0x20"));
+
+    // lea rsp, [rax + 8]; mov rcx, [rsp + 8] loses rsp whichever compiler built the module
+    for compiler in vec![Compiler::Lucet, Compiler::Wamr] {
+        let stack_analyzer = StackAnalyzer { metadata: CompilerMetadata::for_test(compiler), stack_limit_cmps: Default::default() };
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![
+            (0, vec![Stmt::Binop(Binopcode::Add, rsp.clone(), Value::Reg(0, ValSize::Size64), imm(8))]),
+            (4, vec![load.clone()]),
+            (8, vec![Stmt::Ret]),
+        ]);
+        let stack_result = run_worklist(&SingleBlock(0), &irmap, &stack_analyzer);
+        let untracked = untracked_rsp(&SingleBlock(0), &irmap, &stack_result, &stack_analyzer);
+        assert_eq!(untracked.get(&LocIdx { addr: 4, idx: 0 }), Some(&0));
     }
 }
+
+#[test]
+fn readonly_data_access_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::ir::ImmType;
+
+    let analyzer = HeapAnalyzer {
+        metadata: CompilerMetadata {
+            readonly_data: vec![(0x2000, 0x2040)],
+            ..CompilerMetadata::for_test(Compiler::Lucet)
+        },
+    };
+    let absolute = |size: ValSize, addr: i64| Value::Mem(size, MemArgs::Mem1Arg(MemArg::Imm(ImmType::Unsigned, ValSize::Size64, addr)));
+    let xmm0 = Value::Reg(0, ValSize::SizeOther);
+    let check = |stmt: Stmt| {
+        let mut irmap = IRMap::new();
+        irmap.insert(0, vec![(0, vec![stmt])]);
+        let result = run_worklist(&SingleBlock(0), &irmap, &analyzer);
+        classify_heap(result, &irmap, &analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new())
+    };
+
+    // movsd xmm0, [rip + ...]: an f64 constant in .rodata
+    let (checked, classes) = check(Stmt::Unop(Unopcode::Mov, xmm0.clone(), absolute(ValSize::Size64, 0x2038)));
+    assert!(checked.is_ok());
+    assert_eq!(classes[&LocIdx { addr: 0, idx: 0 }][0].1, AccessClass::ReadOnlyData);
+    // a load running past the end of the section, an unsized one assumed 16 bytes wide
+    assert!(check(Stmt::Unop(Unopcode::Mov, xmm0.clone(), absolute(ValSize::Size64, 0x203c))).0.is_err());
+    assert!(check(Stmt::Unop(Unopcode::Mov, xmm0.clone(), absolute(ValSize::SizeOther, 0x2030))).0.is_ok());
+    assert!(check(Stmt::Unop(Unopcode::Mov, xmm0.clone(), absolute(ValSize::SizeOther, 0x2038))).0.is_err());
+    // a load from outside any read-only section
+    assert!(check(Stmt::Unop(Unopcode::Mov, xmm0.clone(), absolute(ValSize::Size64, 0x1ff8))).0.is_err());
+    // and a store to one
+    let store = check(Stmt::Unop(Unopcode::Mov, absolute(ValSize::Size64, 0x2000), Value::Reg(0, ValSize::Size64)));
+    assert!(store.0.unwrap_err().description.starts_with("store to read-only data"));
+}
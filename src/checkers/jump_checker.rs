@@ -0,0 +1,128 @@
+use crate::analyses::jump_analyzer::SwitchAnalyzer;
+use crate::analyses::{AbstractAnalyzer, AnalysisResult};
+use crate::checkers::{CheckFailure, Checker};
+use crate::lattices::reachingdefslattice::LocIdx;
+use crate::lattices::switchlattice::{SwitchLattice, SwitchValueLattice};
+use crate::utils::ir::{IRMap, MemArg, MemArgs, Stmt, Value};
+use crate::utils::ir_utils::is_tail_dispatch;
+use std::collections::{BTreeSet, HashMap};
+
+/*
+ * Control flow integrity of indirect jumps: each one must have been resolved by the jump resolver
+ * (see jump_resolver.rs) into a switch through a bounded table, and every target it was resolved
+ * to must start a block of the function. A jump the resolver didn't recognize is a verification
+ * failure here rather than a panic while building the CFG. Wamr tail dispatches (see
+ * is_tail_dispatch) leave the function, and are checked as calls instead.
+ */
+
+// addr of the jump -> its targets
+pub type SwitchTargets = HashMap<u64, Vec<i64>>;
+
+pub struct JumpChecker<'a> {
+    irmap: &'a IRMap,
+    analyzer: &'a SwitchAnalyzer,
+    switch_targets: &'a SwitchTargets,
+    block_starts: &'a BTreeSet<u64>,
+}
+
+pub fn check_jumps(
+    result: AnalysisResult<SwitchLattice>,
+    irmap: &IRMap,
+    analyzer: &SwitchAnalyzer,
+    switch_targets: &SwitchTargets,
+    block_starts: &BTreeSet<u64>,
+) -> Result<(), CheckFailure> {
+    JumpChecker {
+        irmap: irmap,
+        analyzer: analyzer,
+        switch_targets: switch_targets,
+        block_starts: block_starts,
+    }
+    .check(result)
+}
+
+// The abstract value of the register a jump through `target` is indexed by
+fn jump_index(state: &SwitchLattice, target: &Value) -> SwitchValueLattice {
+    match target {
+        Value::Reg(regnum, size) => state.regs.get(regnum, size),
+        Value::Mem(_, MemArgs::MemScale(_, MemArg::Reg(regnum, size), _)) => state.regs.get(regnum, size),
+        Value::Mem(_, MemArgs::Mem1Arg(MemArg::Reg(regnum, size))) => state.regs.get(regnum, size),
+        _ => Default::default(),
+    }
+}
+
+impl Checker<SwitchLattice> for JumpChecker<'_> {
+    fn check(&self, result: AnalysisResult<SwitchLattice>) -> Result<(), CheckFailure> {
+        self.check_state_at_statements(result)
+    }
+
+    fn irmap(&self) -> &IRMap {
+        self.irmap
+    }
+
+    fn aexec(&self, state: &mut SwitchLattice, ir_stmt: &Stmt, loc: &LocIdx) {
+        self.analyzer.aexec(state, ir_stmt, loc)
+    }
+
+    fn paranoid(&self) -> bool {
+        self.analyzer.paranoid()
+    }
+
+    fn check_statement(&self, state: &SwitchLattice, ir_stmt: &Stmt, loc_idx: &LocIdx) -> Result<(), String> {
+        match ir_stmt {
+            Stmt::Branch(_, Value::Imm(_, _, _)) => Ok(()),
+            // a tail call leaves the function, through a function pointer the call checker checks
+            _ if is_tail_dispatch(ir_stmt, self.analyzer.compiler()) => Ok(()),
+            Stmt::Branch(_, target) => match self.switch_targets.get(&loc_idx.addr) {
+                None => Err(format!("cfi.unresolved-jump index {:?}", jump_index(state, target).v)),
+                Some(targets) => match targets.iter().find(|t| **t < 0 || !self.block_starts.contains(&(**t as u64))) {
+                    Some(outside) => Err(format!(
+                        "cfi.jump-target-outside-function 0x{:x} (index {:?})",
+                        outside,
+                        jump_index(state, target).v
+                    )),
+                    None => Ok(()),
+                },
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn jump_checker_test() {
+    use crate::analyses::reaching_defs::ReachingDefnAnalyzer;
+    use crate::lattices::switchlattice::SwitchValue;
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::ir::ValSize;
+    use yaxpeax_core::analyses::control_flow::VW_CFG;
+    use yaxpeax_x86::long_mode::Opcode;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let analyzer = SwitchAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: AnalysisResult::new(),
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata, VW_CFG::new(0), IRMap::new()),
+    };
+    let rax = Value::Reg(0, ValSize::Size64);
+    // jmp rax, with rax a switch target of a table of 2 entries at 0x8000
+    let mut irmap = IRMap::new();
+    irmap.insert(0x10, vec![(0x10, vec![Stmt::Branch(Opcode::JMP, rax.clone())])]);
+    let block_starts: BTreeSet<u64> = vec![0x10, 0x20, 0x30].into_iter().collect();
+    let check = |switch_targets: &SwitchTargets| {
+        let mut state: SwitchLattice = Default::default();
+        state.regs.rax = SwitchValueLattice::new(SwitchValue::JmpTarget(0x8000, 2));
+        let mut result = AnalysisResult::new();
+        result.insert(0x10, state);
+        check_jumps(result, &irmap, &analyzer, switch_targets, &block_starts)
+    };
+
+    let mut switch_targets = SwitchTargets::new();
+    assert!(check(&switch_targets).unwrap_err().description.starts_with("cfi.unresolved-jump index Some(JmpTarget(32768, 2))"));
+    switch_targets.insert(0x10, vec![0x20, 0x30]);
+    assert!(check(&switch_targets).is_ok());
+    switch_targets.insert(0x10, vec![0x20, 0x40]);
+    let failure = check(&switch_targets).unwrap_err();
+    assert_eq!(failure.loc.addr, 0x10);
+    assert!(failure.description.starts_with("cfi.jump-target-outside-function 0x40"));
+}
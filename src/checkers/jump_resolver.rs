@@ -1,81 +1,77 @@
 use crate::analyses::jump_analyzer::SwitchAnalyzer;
 use crate::analyses::{AbstractAnalyzer, AnalysisResult};
 use crate::lattices::reachingdefslattice::LocIdx;
-use crate::lattices::switchlattice::{SwitchLattice, SwitchValue, SwitchValueLattice};
-use crate::utils::lifter::{IRMap, Stmt, Value, MemArgs, MemArg};
-use crate::utils::utils::Compiler;
+use crate::lattices::switchlattice::{JumpTableLoads, SwitchLattice, SwitchValue, SwitchValueLattice};
+use crate::utils::ir::{IRMap, Stmt, Value, MemArgs, MemArg};
+use crate::utils::compiler::Compiler;
+use crate::utils::ir_utils::is_tail_dispatch;
 use std::collections::HashMap;
 use yaxpeax_core::memory::repr::process::ModuleData;
 use yaxpeax_core::memory::MemoryRepr;
 
-fn load_target(program: &ModuleData, addr: u64) -> i64 {
-    let b0 = program.read(addr).unwrap() as u32;
-    let b1 = (program.read(addr + 1).unwrap() as u32) << 8;
-    let b2 = (program.read(addr + 2).unwrap() as u32) << 16;
-    let b3 = (program.read(addr + 3).unwrap() as u32) << 24;
-    (b0 + b1 + b2 + b3) as i64
+/// Where jump table entries are read from: the loaded module, or the bytes of it a repro bundle
+/// kept (see minimize.rs).
+pub trait TableMemory {
+    fn read_byte(&self, addr: u64) -> Option<u8>;
 }
 
-fn extract_jmp_targets(program: &ModuleData, aval: &SwitchValueLattice, compiler: Compiler) -> Vec<i64> {
+impl TableMemory for ModuleData {
+    fn read_byte(&self, addr: u64) -> Option<u8> {
+        self.read(addr)
+    }
+}
+
+fn load_target(program: &dyn TableMemory, addr: u64) -> Option<i64> {
+    let b0 = program.read_byte(addr)? as u32;
+    let b1 = (program.read_byte(addr + 1)? as u32) << 8;
+    let b2 = (program.read_byte(addr + 2)? as u32) << 16;
+    let b3 = (program.read_byte(addr + 3)? as u32) << 24;
+    Some((b0 + b1 + b2 + b3) as i64)
+}
+
+// None if aval isn't a bounded jump table, or the table isn't in the module
+fn extract_jmp_targets(program: &dyn TableMemory, aval: &SwitchValueLattice, compiler: Compiler) -> Option<Vec<i64>> {
     let mut targets: Vec<i64> = Vec::new();
     match aval.v {
         Some(SwitchValue::JmpTarget(base, upper_bound)) => {
             for idx in 0..upper_bound {
                 let addr = match compiler {
-                    Compiler::Lucet => base + idx * 4,
+                    Compiler::Lucet | Compiler::Wasmtime => base + idx * 4,
                     Compiler::Wamr => base + idx * 8, 
                 };
-                let target = load_target(program, addr.into());
+                let target = load_target(program, addr.into())?;
                 let resolved_target = match compiler {
-                    Compiler::Lucet => ((base as i32) + (target as i32)) as i64,
+                    Compiler::Lucet | Compiler::Wasmtime => ((base as i32) + (target as i32)) as i64,
                     Compiler::Wamr => target,
                 };
 
                 targets.push(resolved_target);
             }
         }
-        _ => panic!("Jump Targets Broken, target = {:?}", aval.v),
+        _ => return None,
     }
-    targets
+    Some(targets)
 }
 
-fn wamr_resolve_indirect_jump(program: &ModuleData,
-                              state: &mut SwitchLattice, 
-                              switch_targets: &mut HashMap<u64, Vec<i64>>,
-                              addr: &u64,
-                              memargs: &MemArgs) {
+// Wamr jumps through a table of 8-byte addresses, `jmp [base + idx*8]` with idx bounds checked
+fn wamr_resolve_indirect_jump(program: &dyn TableMemory, state: &SwitchLattice, memargs: &MemArgs) -> Option<Vec<i64>> {
     match memargs {
-        MemArgs::MemScale(base, disp, scale) => {
-            if let MemArg::Imm(_, _, baseval) = base {
-                if let MemArg::Reg(regnum, regsize) = disp {
-                    if let MemArg::Imm(_, _, scaleval) = scale {
-                        let aval = state.regs.get(regnum, regsize);
-                        if let Some(SwitchValue::UpperBound(bound)) = aval.v {
-                            let jmpbase = *baseval as u32;
-                            assert!(*scaleval == 8, "Illegal scale value in indirect jump!");
-                            let jmpbound = SwitchValueLattice::new(SwitchValue::JmpTarget(jmpbase, bound));
-                            let targets = extract_jmp_targets(program, &jmpbound, Compiler::Wamr);
-                            switch_targets.insert(*addr, targets);
-                        } else {
-                            panic!("Scaled jump with unbounded register!");
-                        }
-                    } else {
-                        panic!("Scaled jump with no immediate scaling!");
-                    }
-                } else {
-                    panic!("Scaled jump with no register displacement!");
+        MemArgs::MemScale(MemArg::Imm(_, _, baseval), MemArg::Reg(regnum, regsize), MemArg::Imm(_, _, 8)) => {
+            match state.regs.get(regnum, regsize).v {
+                Some(SwitchValue::UpperBound(bound)) => {
+                    let jmpbound = SwitchValueLattice::new(SwitchValue::JmpTarget(*baseval as u32, bound));
+                    extract_jmp_targets(program, &jmpbound, Compiler::Wamr)
                 }
-            } else {
-                panic!("Scaled jump with no immediate base!");
+                _ => None,
             }
-        },
-        _ => panic!("Unrecognized jump!"),
+        }
+        _ => None,
     }
 }
 
 // addr -> vec of targets
 pub fn resolve_jumps(
-    program: &ModuleData,
+    program: &dyn TableMemory,
     result: AnalysisResult<SwitchLattice>,
     irmap: &IRMap,
     analyzer: &SwitchAnalyzer,
@@ -83,7 +79,7 @@ pub fn resolve_jumps(
     let mut switch_targets: HashMap<u64, Vec<i64>> = HashMap::new();
 
     for (block_addr, mut state) in result.clone() {
-        for (addr, ir_stmts) in irmap.get(&block_addr).unwrap() {
+        for (addr, ir_stmts) in irmap.get(&block_addr).unwrap().iter() {
             for (idx, ir_stmt) in ir_stmts.iter().enumerate() {
                 analyzer.aexec(
                     &mut state,
@@ -98,18 +94,23 @@ pub fn resolve_jumps(
     }
 
     for (block_addr, mut state) in result {
-        for (addr, ir_stmts) in irmap.get(&block_addr).unwrap() {
+        for (addr, ir_stmts) in irmap.get(&block_addr).unwrap().iter() {
             for (idx, ir_stmt) in ir_stmts.iter().enumerate() {
                 match ir_stmt {
+                    // tail calls, which the call checker checks instead
+                    _ if is_tail_dispatch(ir_stmt, analyzer.compiler()) => (),
+                    // Jumps that aren't recognized are left unresolved, for the jump checker to report
                     Stmt::Branch(_, Value::Reg(regnum, regsize)) => {
                         let aval = state.regs.get(regnum, regsize);
-                        let targets = extract_jmp_targets(program, &aval, Compiler::Lucet);
-                        switch_targets.insert(*addr, targets);
+                        if let Some(targets) = extract_jmp_targets(program, &aval, Compiler::Lucet) {
+                            switch_targets.insert(*addr, targets);
+                        }
                     }
                     Stmt::Branch(_, Value::Mem(_, memargs)) => {
-                        match analyzer.compiler() {
-                            Compiler::Lucet => panic!("Illegal Jump!"),
-                            Compiler::Wamr => wamr_resolve_indirect_jump(program, &mut state, &mut switch_targets, addr, memargs),
+                        if analyzer.compiler() == Compiler::Wamr {
+                            if let Some(targets) = wamr_resolve_indirect_jump(program, &state, memargs) {
+                                switch_targets.insert(*addr, targets);
+                            }
                         }
                     }
                     _ => (),
@@ -128,3 +129,85 @@ pub fn resolve_jumps(
     }
     switch_targets
 }
+
+/// The jump table bytes resolve_jumps reads the targets of each indirect jump from, as half-open
+/// address ranges: Lucet tables of 4-byte offsets, and Wamr tables of 8-byte addresses.
+pub fn jump_table_reads(
+    result: AnalysisResult<SwitchLattice>,
+    irmap: &IRMap,
+    analyzer: &SwitchAnalyzer,
+) -> Vec<(u64, u64)> {
+    let mut reads: Vec<(u64, u64)> = Vec::new();
+    for (block_addr, mut state) in result {
+        for (addr, ir_stmts) in irmap.get(&block_addr).unwrap().iter() {
+            for (idx, ir_stmt) in ir_stmts.iter().enumerate() {
+                match ir_stmt {
+                    Stmt::Branch(_, Value::Reg(regnum, regsize)) => {
+                        if let Some(SwitchValue::JmpTarget(base, bound)) = state.regs.get(regnum, regsize).v {
+                            reads.push((base as u64, base as u64 + (bound as u64) * 4));
+                        }
+                    }
+                    Stmt::Branch(_, Value::Mem(_, MemArgs::MemScale(MemArg::Imm(_, _, base), MemArg::Reg(regnum, regsize), MemArg::Imm(_, _, 8)))) => {
+                        if let Some(SwitchValue::UpperBound(bound)) = state.regs.get(regnum, regsize).v {
+                            let base = *base as u32 as u64;
+                            reads.push((base, base + (bound as u64) * 8));
+                        }
+                    }
+                    _ => (),
+                }
+
+                analyzer.aexec(
+                    &mut state,
+                    ir_stmt,
+                    &LocIdx {
+                        addr: *addr,
+                        idx: idx as u32,
+                    },
+                );
+            }
+        }
+    }
+    reads.sort();
+    reads.dedup();
+    reads
+}
+
+/// The loads from a Lucet jump table, i.e. `mov r, [base + idx*4]` where base is a switch base and
+/// idx has been bounds checked. These are the only scale-4 accesses the heap checker accepts as
+/// jump table accesses.
+pub fn jump_table_loads(
+    result: AnalysisResult<SwitchLattice>,
+    irmap: &IRMap,
+    analyzer: &SwitchAnalyzer,
+) -> JumpTableLoads {
+    let mut loads: JumpTableLoads = HashMap::new();
+    for (block_addr, mut state) in result {
+        for (addr, ir_stmts) in irmap.get(&block_addr).unwrap().iter() {
+            for (idx, ir_stmt) in ir_stmts.iter().enumerate() {
+                if let Stmt::Unop(_, _, Value::Mem(_, MemArgs::MemScale(
+                    MemArg::Reg(regnum1, size1),
+                    MemArg::Reg(regnum2, size2),
+                    MemArg::Imm(_, _, 4),
+                ))) = ir_stmt
+                {
+                    if let (Some(SwitchValue::SwitchBase(base)), Some(SwitchValue::UpperBound(bound))) = (
+                        state.regs.get(regnum1, size1).v,
+                        state.regs.get(regnum2, size2).v,
+                    ) {
+                        loads.insert(*addr, (base, bound));
+                    }
+                }
+
+                analyzer.aexec(
+                    &mut state,
+                    ir_stmt,
+                    &LocIdx {
+                        addr: *addr,
+                        idx: idx as u32,
+                    },
+                );
+            }
+        }
+    }
+    loads
+}
@@ -1,47 +1,184 @@
 use crate::analyses::AnalysisResult;
 use crate::lattices::reachingdefslattice::LocIdx;
 use crate::lattices::Lattice;
-use crate::utils::lifter::IRMap;
-use crate::utils::lifter::Stmt;
+use crate::utils::ir::IRMap;
+use crate::utils::ir::Stmt;
+use std::fmt;
 
+#[cfg(feature = "loader")]
 pub mod call_checker;
 pub mod heap_checker;
+#[cfg(feature = "loader")]
+pub mod jump_checker;
+#[cfg(feature = "loader")]
 pub mod jump_resolver;
+pub mod prologue_checker;
 pub mod stack_checker;
+pub mod taint_checker;
 
+/// The statement a checker rejected, where it is, and why.
+#[derive(Clone, Debug)]
+pub struct CheckFailure {
+    pub loc: LocIdx,
+    pub stmt: Stmt,
+    // what the check found wrong with the statement
+    pub description: String,
+    // the abstract state right before the statement (empty for checks without one)
+    pub state: String,
+}
+
+impl fmt::Display for CheckFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:x}: {:?}", self.loc.addr, self.stmt)
+    }
+}
+
+/// Checks a property of every statement of a function against the results of an analysis.
+///
+/// Rules are checked against the state *before* the statement executes: check_statement sees the
+/// state the statement's own aexec is then applied to. Rules rely on this, e.g. the heap checker
+/// examines rdi at a call before the call clears it, and the Wamr stack checker accepts
+/// `mov rbx, [rsp]` restoring rbx because rbx is still saved until that same statement restores
+/// it. A rule that needs a statement's effect must compute it itself.
 pub trait Checker<State: Lattice + Clone> {
-    fn check(&self, result: AnalysisResult<State>) -> bool;
+    fn check(&self, result: AnalysisResult<State>) -> Result<(), CheckFailure>;
     fn irmap(&self) -> &IRMap;
     fn aexec(&self, state: &mut State, ir_stmt: &Stmt, loc: &LocIdx);
 
-    fn check_state_at_statements(&self, result: AnalysisResult<State>) -> bool {
+    /// With --paranoid, debug builds assert that no statement is checked against a state it was
+    /// already applied to.
+    fn paranoid(&self) -> bool {
+        false
+    }
+
+    /// Checks every statement against the state right before it, stopping at the first one
+    /// that fails.
+    fn check_state_at_statements(&self, result: AnalysisResult<State>) -> Result<(), CheckFailure> {
+        let paranoid = self.paranoid();
         for (block_addr, mut state) in result {
-            for (addr, ir_stmts) in self.irmap().get(&block_addr).unwrap() {
-                //println!("analyzing block at {:x}", addr);
+            // the last statement applied to the state
+            let mut applied: Option<LocIdx> = None;
+            for (addr, ir_stmts) in self.irmap().get(&block_addr).unwrap().iter() {
                 for (idx, ir_stmt) in ir_stmts.iter().enumerate() {
-                    //println!("checking statement: {:?}", ir_stmt);
-                    if !self.check_statement(
-                        &state,
-                        ir_stmt,
-                        &LocIdx {
-                            addr: *addr,
-                            idx: idx as u32,
-                        },
-                    ) {
-                        return false;
+                    log::trace!("0x{:x} checking statement: {:?}", addr, ir_stmt);
+                    let loc = LocIdx {
+                        addr: *addr,
+                        idx: idx as u32,
+                    };
+                    if paranoid {
+                        debug_assert!(applied.map_or(true, |applied| applied < loc), "0x{:x}: checking a statement already applied to the state", addr);
                     }
-                    self.aexec(
-                        &mut state,
-                        ir_stmt,
-                        &LocIdx {
-                            addr: *addr,
-                            idx: idx as u32,
-                        },
-                    );
+                    let checked = match ir_stmt {
+                        Stmt::Forbidden(opcode) => Err(format!("cfi.forbidden-instruction {:?}", opcode)),
+                        _ => self.check_statement(&state, ir_stmt, &loc),
+                    };
+                    if let Err(description) = checked {
+                        log::error!("0x{:x} failing statement: {:?} ({})", addr, ir_stmt, description);
+                        return Err(CheckFailure {
+                            loc: loc,
+                            stmt: ir_stmt.clone(),
+                            description: description,
+                            state: format!("{:?}", state),
+                        });
+                    }
+                    self.aexec(&mut state, ir_stmt, &loc);
+                    applied = Some(loc);
                 }
             }
         }
-        true
+        Ok(())
     }
-    fn check_statement(&self, state: &State, ir_stmt: &Stmt, loc_idx: &LocIdx) -> bool;
+    /// Checks one statement against the state right before it, describing what's wrong with it
+    /// if it fails.
+    fn check_statement(&self, state: &State, ir_stmt: &Stmt, loc_idx: &LocIdx) -> Result<(), String>;
+}
+
+#[test]
+fn forbidden_instruction_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::checkers::stack_checker::check_stack;
+    use crate::analyses::stack_analyzer::StackAnalyzer;
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use yaxpeax_x86::long_mode::Opcode;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let heap_analyzer = HeapAnalyzer { metadata: metadata.clone() };
+    let stack_analyzer = StackAnalyzer { metadata: metadata, stack_limit_cmps: Default::default() };
+    let mut irmap = IRMap::new();
+    irmap.insert(0, vec![(0, vec![Stmt::Ret])]);
+    assert!(check_heap(run_worklist(&SingleBlock(0), &irmap, &heap_analyzer), &irmap, &heap_analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok());
+    assert!(check_stack(run_worklist(&SingleBlock(0), &irmap, &stack_analyzer), &irmap, &stack_analyzer).is_ok());
+
+    // syscall; ret
+    irmap.insert(0, vec![(0, vec![Stmt::Forbidden(Opcode::SYSCALL)]), (2, vec![Stmt::Ret])]);
+    let failure = check_heap(run_worklist(&SingleBlock(0), &irmap, &heap_analyzer), &irmap, &heap_analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).unwrap_err();
+    assert_eq!(failure.loc, LocIdx { addr: 0, idx: 0 });
+    assert_eq!(failure.to_string(), "0x0: Forbidden(SYSCALL)");
+    assert_eq!(failure.description, "cfi.forbidden-instruction SYSCALL");
+    assert!(check_stack(run_worklist(&SingleBlock(0), &irmap, &stack_analyzer), &irmap, &stack_analyzer).is_err());
+}
+
+#[test]
+fn pre_state_check_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::analyses::stack_analyzer::StackAnalyzer;
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::checkers::stack_checker::check_stack;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::ir::{Binopcode, ImmType, MemArg, MemArgs, Unopcode, ValSize, Value};
+
+    let metadata = |compiler: Compiler| {
+        let mut metadata = CompilerMetadata::for_test(compiler);
+        metadata.options.paranoid = true;
+        metadata
+    };
+    let block = |stmts: Vec<Stmt>| {
+        let mut irmap = IRMap::new();
+        irmap.insert(0, stmts.into_iter().enumerate().map(|(i, s)| (i as u64 * 4, vec![s])).collect());
+        irmap
+    };
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let call = Stmt::Call(Value::Imm(ImmType::Signed, ValSize::Size32, 0x100), 5);
+
+    // heap: a call is checked against rdi before the call clears it, so only the second of two
+    // calls in a row is missing the heap base
+    let heap_analyzer = HeapAnalyzer { metadata: metadata(Compiler::Lucet) };
+    let check = |irmap: &IRMap| {
+        check_heap(run_worklist(&SingleBlock(0), irmap, &heap_analyzer), irmap, &heap_analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new())
+            .map_err(|failure| failure.loc.addr)
+    };
+    assert_eq!(check(&block(vec![call.clone(), Stmt::Ret])), Ok(()));
+    assert_eq!(check(&block(vec![call.clone(), call.clone(), Stmt::Ret])), Err(4));
+
+    // stack (Wamr): push rbx is checked before rbx is recorded as saved, so its write doesn't
+    // clobber rbx's own slot, and pop rbx before rbx is recorded as restored, so it may write rbx;
+    // writing rbx after that fails
+    let stack_analyzer = StackAnalyzer { metadata: metadata(Compiler::Wamr), stack_limit_cmps: Default::default() };
+    let rsp = reg(4);
+    let top = Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64)));
+    let push_pop = |after: Vec<Stmt>| {
+        let mut stmts = vec![
+            Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(8)),
+            Stmt::Unop(Unopcode::Mov, top.clone(), reg(3)),
+            Stmt::Unop(Unopcode::Mov, reg(3), top.clone()),
+            Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(8)),
+        ];
+        stmts.extend(after);
+        stmts.push(Stmt::Ret);
+        let irmap = block(stmts);
+        check_stack(run_worklist(&SingleBlock(0), &irmap, &stack_analyzer), &irmap, &stack_analyzer)
+            .map_err(|failure| failure.loc.addr)
+    };
+    assert_eq!(push_pop(vec![]), Ok(()));
+    assert_eq!(push_pop(vec![Stmt::Unop(Unopcode::Mov, reg(3), reg(0))]), Err(0x10));
 }
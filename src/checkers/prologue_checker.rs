@@ -0,0 +1,166 @@
+use crate::lattices::heaplattice::{WAMR_MODULEINSTANCE_OFFSET, WAMR_STACKLIMIT_OFFSET};
+use crate::utils::ir::{Binopcode, MemArg, MemArgs, Stmt, Unopcode, ValSize, Value};
+use crate::utils::ir_utils::{is_stack_access, is_zf};
+use std::collections::HashSet;
+
+// how many lifted statements we look at before giving up on finding a prologue
+pub const PROLOGUE_WINDOW: usize = 32;
+
+// The ways a Wamr AOT function is known to start. Every AOT function receives the ExecEnv in
+// %rdi and, after some frame setup, either loads the ModuleInstance out of it or checks the
+// native stack boundary stored in it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrologueTemplate {
+    ModuleInstanceLoad, // mov r, [env + WAMR_MODULEINSTANCE_OFFSET]
+    StackLimitLoad,     // mov r, [env + WAMR_STACKLIMIT_OFFSET]
+    StackLimitCmp,      // cmp r, [env + WAMR_STACKLIMIT_OFFSET]
+}
+
+// the ExecEnv field read by `v`, if it is a read through one of the registers holding the ExecEnv
+fn execenv_field(v: &Value, env_regs: &HashSet<u8>) -> Option<i64> {
+    if let Value::Mem(_, MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, offset))) = v {
+        if env_regs.contains(regnum) {
+            return Some(*offset);
+        }
+    }
+    None
+}
+
+fn written_reg(v: &Value) -> Option<u8> {
+    if let Value::Reg(regnum, _) = v {
+        return Some(*regnum);
+    }
+    None
+}
+
+// Frame setup may write any register except the ones holding the ExecEnv, and may only write
+// memory on the stack. Returns false if `dst` is not a legal destination before the prologue.
+fn frame_setup_write(dst: &Value, env_regs: &mut HashSet<u8>) -> bool {
+    if is_stack_access(dst) || is_zf(dst) {
+        return true;
+    }
+    match written_reg(dst) {
+        Some(regnum) => {
+            env_regs.remove(&regnum);
+            !env_regs.is_empty()
+        }
+        None => false,
+    }
+}
+
+/// Matches the start of a function against the Wamr AOT prologue templates. Anything other than
+/// frame setup (stack adjustment, callee-saved spills, register shuffles that keep the ExecEnv
+/// live) before the template matches means this is not an AOT guest function.
+pub fn match_wamr_prologue(stmts: &[Stmt]) -> Option<PrologueTemplate> {
+    let mut env_regs: HashSet<u8> = HashSet::new();
+    env_regs.insert(7); // rdi
+    for stmt in stmts.iter().take(PROLOGUE_WINDOW) {
+        match stmt {
+            Stmt::Unop(Unopcode::Mov, dst, src) => {
+                match execenv_field(src, &env_regs) {
+                    Some(WAMR_MODULEINSTANCE_OFFSET) => return Some(PrologueTemplate::ModuleInstanceLoad),
+                    Some(WAMR_STACKLIMIT_OFFSET) => return Some(PrologueTemplate::StackLimitLoad),
+                    Some(_) => return None,
+                    None => (),
+                }
+                if let (Value::Reg(dst_reg, ValSize::Size64), Value::Reg(src_reg, ValSize::Size64)) = (dst, src) {
+                    if env_regs.contains(src_reg) {
+                        env_regs.insert(*dst_reg);
+                        continue;
+                    }
+                }
+                if !frame_setup_write(dst, &mut env_regs) {
+                    return None;
+                }
+            }
+            Stmt::Binop(Binopcode::Cmp, _, src1, src2) => {
+                if execenv_field(src1, &env_regs) == Some(WAMR_STACKLIMIT_OFFSET)
+                    || execenv_field(src2, &env_regs) == Some(WAMR_STACKLIMIT_OFFSET)
+                {
+                    return Some(PrologueTemplate::StackLimitCmp);
+                }
+            }
+            Stmt::Binop(Binopcode::Test, _, _, _) => (),
+            Stmt::Binop(_, dst, _, _) | Stmt::Clear(dst, _) | Stmt::Unop(_, dst, _) => {
+                if !frame_setup_write(dst, &mut env_regs) {
+                    return None;
+                }
+            }
+            Stmt::Undefined | Stmt::Ret | Stmt::Branch(_, _) | Stmt::Call(_, _) | Stmt::ProbeStack(_)
+            | Stmt::Forbidden(_) => {
+                return None
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+fn rsp_adjust(opcode: Binopcode, amount: i64) -> Stmt {
+    Stmt::Binop(opcode, Value::Reg(4, ValSize::Size64), Value::Reg(4, ValSize::Size64), crate::utils::ir::mk_value_i64(amount))
+}
+
+#[cfg(test)]
+fn push(regnum: u8) -> Vec<Stmt> {
+    vec![
+        rsp_adjust(Binopcode::Sub, 8),
+        Stmt::Unop(Unopcode::Mov, Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64))), Value::Reg(regnum, ValSize::Size64)),
+    ]
+}
+
+#[cfg(test)]
+fn field(regnum: u8, offset: i64) -> Value {
+    use crate::utils::ir::ImmType;
+    Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)))
+}
+
+#[test]
+fn wamr_prologue_test() {
+    // push rbp; push rbx; sub rsp, 0x28; mov rax, [rdi+0x10]
+    let mut stmts = push(5);
+    stmts.extend(push(3));
+    stmts.push(rsp_adjust(Binopcode::Sub, 0x28));
+    stmts.push(Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size64), field(7, WAMR_MODULEINSTANCE_OFFSET)));
+    assert_eq!(match_wamr_prologue(&stmts), Some(PrologueTemplate::ModuleInstanceLoad));
+
+    // mov rbx, rdi; lea rax, [rsp-0x40]; cmp rax, [rbx+0x18]
+    let stmts = vec![
+        Stmt::Unop(Unopcode::Mov, Value::Reg(3, ValSize::Size64), Value::Reg(7, ValSize::Size64)),
+        Stmt::Binop(Binopcode::Add, Value::Reg(0, ValSize::Size64), Value::Reg(4, ValSize::Size64), crate::utils::ir::mk_value_i64(-0x40)),
+        Stmt::Binop(Binopcode::Cmp, Value::Reg(0, ValSize::Size64), Value::Reg(0, ValSize::Size64), field(3, WAMR_STACKLIMIT_OFFSET)),
+    ];
+    assert_eq!(match_wamr_prologue(&stmts), Some(PrologueTemplate::StackLimitCmp));
+
+    // mov rcx, [rdi+0x18]
+    let stmts = vec![Stmt::Unop(Unopcode::Mov, Value::Reg(1, ValSize::Size64), field(7, WAMR_STACKLIMIT_OFFSET))];
+    assert_eq!(match_wamr_prologue(&stmts), Some(PrologueTemplate::StackLimitLoad));
+}
+
+#[test]
+fn wamr_prologue_helper_test() {
+    // runtime helpers that happen to take a pointer in rdi: clobbering it first...
+    let mut stmts = push(5);
+    stmts.push(Stmt::Unop(Unopcode::Mov, Value::Reg(7, ValSize::Size64), Value::Reg(6, ValSize::Size64)));
+    stmts.push(Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size64), field(7, WAMR_MODULEINSTANCE_OFFSET)));
+    assert_eq!(match_wamr_prologue(&stmts), None);
+
+    // ...reading some other field of it...
+    let stmts = vec![Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size64), field(7, 0x8))];
+    assert_eq!(match_wamr_prologue(&stmts), None);
+
+    // ...writing through it...
+    let stmts = vec![
+        Stmt::Unop(Unopcode::Mov, field(7, 0x20), Value::Reg(6, ValSize::Size64)),
+        Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size64), field(7, WAMR_MODULEINSTANCE_OFFSET)),
+    ];
+    assert_eq!(match_wamr_prologue(&stmts), None);
+
+    // ...or calling out before touching the ExecEnv
+    let mut stmts = push(5);
+    stmts.push(Stmt::Call(crate::utils::ir::mk_value_i64(0x100), 5));
+    stmts.push(Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size64), field(7, WAMR_MODULEINSTANCE_OFFSET)));
+    assert_eq!(match_wamr_prologue(&stmts), None);
+
+    // an empty function is not a guest function either
+    assert_eq!(match_wamr_prologue(&vec![]), None);
+}
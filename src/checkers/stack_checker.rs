@@ -1,12 +1,16 @@
-use crate::analyses::stack_analyzer::StackAnalyzer;
+use crate::analyses::stack_analyzer::{wamr_saved_reg_error, StackAnalyzer};
 use crate::analyses::{AbstractAnalyzer, AnalysisResult};
-use crate::checkers::Checker;
-use crate::utils::ir_utils::{get_imm_mem_offset, is_stack_access, is_callee_saved_reg};
+use crate::checkers::{CheckFailure, Checker};
+use crate::utils::ir_utils::{
+    extract_stack_offset, get_imm_mem_offset, is_callee_saved_reg, is_rsp, is_stack_access, is_tail_dispatch, rel_target,
+    rsp_adjustment, writes_narrow_rsp,
+};
 use crate::lattices::reachingdefslattice::LocIdx;
-use crate::lattices::stackgrowthlattice::{StackGrowthLattice, WAMR_STACK_UPPER_BOUND, WAMR_STACK_LOWER_BOUND};
-use crate::utils::lifter::{IRMap, MemArgs, Stmt, Value};
-use crate::utils::utils::Compiler;
-use std::collections::HashMap;
+use crate::lattices::stackgrowthlattice::{wamr_stack_lower_bound, StackGrowthLattice, WAMR_STACK_UPPER_BOUND};
+use crate::utils::ir::{Binopcode, IRMap, MemArgs, Stmt, Value};
+use crate::utils::compiler::Compiler;
+use std::cmp::max;
+use std::collections::{BTreeMap, HashMap};
 
 pub struct StackChecker<'a> {
     irmap: &'a IRMap,
@@ -17,7 +21,7 @@ pub fn check_stack(
     result: AnalysisResult<StackGrowthLattice>,
     irmap: &IRMap,
     analyzer: &StackAnalyzer,
-) -> bool {
+) -> Result<(), CheckFailure> {
     StackChecker {
         irmap: irmap,
         analyzer: analyzer,
@@ -29,7 +33,7 @@ pub fn check_stack(
 fn is_callee_saved_reg_safe(dst: &Value, state: &StackGrowthLattice) -> bool {
     if is_callee_saved_reg(dst) {
         if let Value::Reg(regnum, _regsize) = dst {
-            if let Some((_, _, saved)) = &state.v {
+            if let Some((_, _, saved, _, _, _, _)) = &state.v {
                 if !saved.contains_key(regnum) {
                     return false;
                 }
@@ -41,10 +45,19 @@ fn is_callee_saved_reg_safe(dst: &Value, state: &StackGrowthLattice) -> bool {
     true
 }
 
-/// Checks if a stack write will clobber a saved register
-fn write_clobbers_callee_saved_reg(offset: i64, saved: &HashMap<u8, i64>) -> bool {
+/// Checks if `access` is a stack access at an offset from rsp that isn't an immediate, such as
+/// `[rsp + rax]`, which may be to any slot
+fn is_indexed_stack_access(access: &Value) -> bool {
+    match access {
+        Value::Mem(_, memargs) => is_stack_access(access) && extract_stack_offset(memargs).is_none(),
+        _ => false,
+    }
+}
+
+/// Checks if a stack write of `width` bytes will clobber (part of) a saved register
+fn write_clobbers_callee_saved_reg(offset: i64, width: i64, saved: &HashMap<u8, i64>) -> bool {
     for saved_offset in saved.values() {
-        if *saved_offset == offset {
+        if offset < *saved_offset + 8 && *saved_offset < offset + width {
             return true;
         }
     }
@@ -52,7 +65,7 @@ fn write_clobbers_callee_saved_reg(offset: i64, saved: &HashMap<u8, i64>) -> boo
 }
 
 impl Checker<StackGrowthLattice> for StackChecker<'_> {
-    fn check(&self, result: AnalysisResult<StackGrowthLattice>) -> bool {
+    fn check(&self, result: AnalysisResult<StackGrowthLattice>) -> Result<(), CheckFailure> {
         self.check_state_at_statements(result)
     }
 
@@ -63,26 +76,50 @@ impl Checker<StackGrowthLattice> for StackChecker<'_> {
         self.analyzer.aexec(state, ir_stmt, loc)
     }
 
+    fn paranoid(&self) -> bool {
+        self.analyzer.paranoid()
+    }
+
     fn check_statement(
         &self,
         state: &StackGrowthLattice,
         ir_stmt: &Stmt,
-        _loc_idx: &LocIdx,
-    ) -> bool {
-        //1, stackgrowth is never Bottom or >= 0
-        match state.v {
-            None => {
-                println!("Failure Case: Stackgrowth = None");
-                return false;
+        loc_idx: &LocIdx,
+    ) -> Result<(), String> {
+        //1. rsp is only ever adjusted in ways we can follow
+        if let Stmt::Binop(opcode, dst, src1, src2) = ir_stmt {
+            match opcode {
+                Binopcode::Cmp | Binopcode::Test => (),
+                _ => {
+                    if is_rsp(dst) && is_rsp(src1) && rsp_adjustment(opcode, src2).is_none() {
+                        return Err("illegal rsp write".to_string());
+                    }
+                }
             }
-            Some((stackgrowth, _, _)) => {
+        }
+        if writes_narrow_rsp(ir_stmt) {
+            return Err("write to rsp through esp, sp or spl".to_string());
+        }
+
+        //2. callee-saved registers are restored from where they were saved (Wamr); a mismatch
+        // loses the stack state, so this comes first to say why
+        if let (Compiler::Wamr, Stmt::Unop(_, dst, src)) = (self.analyzer.compiler(), ir_stmt) {
+            if let Some(error) = wamr_saved_reg_error(state, dst, src) {
+                return Err(error);
+            }
+        }
+
+        //3. stackgrowth is never Bottom or >= 0
+        match state.v {
+            None => return Err("stack growth unknown".to_string()),
+            Some((stackgrowth, _, _, _, _, _, _)) => {
                 if stackgrowth > 0 {
-                    return false;
+                    return Err(format!("stack growth {} is above the return address", stackgrowth));
                 }
             }
         }
 
-        // 2. Reads and writes are in bounds
+        // 4. Reads and writes are in bounds
         match ir_stmt {
             //encapsulates both load and store
             Stmt::Unop(_, dst, src) =>
@@ -91,29 +128,41 @@ impl Checker<StackGrowthLattice> for StackChecker<'_> {
                 // (for Wamr only)
                 if let Compiler::Wamr = self.analyzer.compiler() { 
                     if !is_callee_saved_reg_safe(dst, state) {
-                        println!("modifying a callee-saved register before saving/after restoring!");
-                        return false;
+                        return Err("callee-saved register modified before it was saved or after it was restored".to_string());
+                    }
+                }
+
+                // stack accesses are at a known offset from rsp
+                for access in [dst, src].iter() {
+                    if is_indexed_stack_access(access) {
+                        return Err(format!("stack access at a register offset: {:?}", access));
+                    }
+                }
+
+                // (Wamr) no further below the guard pages than where rsp was last checked against
+                // the stack limit, or the function's entry
+                if let Compiler::Wamr = self.analyzer.compiler() {
+                    let lower = wamr_stack_lower_bound(state.get_checked_limit());
+                    for access in [dst, src].iter() {
+                        match stack_slot(state, access) {
+                            Some((start, _)) if start <= lower => {
+                                return Err(format!("stack accessed beyond checked limit: {:?} at stack growth {}, below {}", access, start, lower));
+                            }
+                            _ => (),
+                        }
                     }
                 }
 
                 // stack write: probestack <= stackgrowth + c < 0
                 if is_stack_access(dst) {
                     if !self.check_stack_write(state, dst) {
-                        println!(
-                            "check_stack_write failed: access = {:?} state = {:?}",
-                            dst, state
-                        );
-                        return false;
+                        return Err(format!("stack write out of bounds: {:?}", dst));
                     }
                 }
                 //stack read: probestack <= stackgrowth + c < 8K
                 else if is_stack_access(src) {
                     if !self.check_stack_read(state, src) {
-                        println!(
-                            "check_stack_read failed: access = {:?} state = {:?}",
-                            src, state
-                        );
-                        return false;
+                        return Err(format!("stack read out of bounds: {:?}", src));
                     }
                 }
             },
@@ -122,41 +171,62 @@ impl Checker<StackGrowthLattice> for StackChecker<'_> {
                 // (for Wamr only)
                 if let Compiler::Wamr = self.analyzer.compiler() { 
                     if !is_callee_saved_reg_safe(dst, state) {
-                        println!("modifying a callee-saved register before saving/after restoring!");
-                        return false;
+                        return Err("callee-saved register modified before it was saved or after it was restored".to_string());
                     }
                 }
             },
             _ => (),
         }
 
-        // 3. For all rets stackgrowth = 0
-        if let Stmt::Ret = ir_stmt {
-            if let Some((stackgrowth, _, _)) = state.v {
-                if stackgrowth != 0 {
-                    println!("stackgrowth != 0 at ret: stackgrowth = {:?}", stackgrowth);
-                    return false;
+        // 5. For all rets (and tail calls, see is_tail_dispatch) stackgrowth = 0, and (Wamr) every
+        // callee-saved register was restored, unless the ret is only reached after raising an
+        // exception and that is allowed
+        let exit = match ir_stmt {
+            Stmt::Ret => Some("ret"),
+            _ if is_tail_dispatch(ir_stmt, self.analyzer.compiler()) => Some("tail call"),
+            _ => None,
+        };
+        if let Some(exit) = exit {
+            if let Some((stackgrowth, _, saved, raised, _, _, _)) = &state.v {
+                if *stackgrowth != 0 {
+                    return Err(format!("stack growth {} at {}", stackgrowth, exit));
+                }
+                if !saved.is_empty() {
+                    let mut unrestored: Vec<u8> = saved.keys().copied().collect();
+                    unrestored.sort();
+                    if *raised && self.analyzer.metadata.options.exception_epilogue {
+                        log::warn!(
+                            "{} at 0x{:x} leaves callee-saved registers {:?} unrestored after raising an exception",
+                            exit, loc_idx.addr, unrestored
+                        );
+                    } else {
+                        return Err(format!("callee-saved registers {:?} not restored at {}", unrestored, exit));
+                    }
                 }
             }
         }
 
-        true
+        Ok(())
     }
 }
 
 impl StackChecker<'_> {
     fn lucet_check_stack_read(&self, state: &StackGrowthLattice, src: &Value) -> bool {
         if let Value::Mem(_, memargs) = src {
+            let width = src.width() as i64;
             match memargs {
                 MemArgs::Mem1Arg(_memarg) => {
                     return (-state.get_probestack().unwrap() <= state.get_stackgrowth().unwrap())
-                        && (state.get_stackgrowth().unwrap() < 8096)
+                        && (state.get_stackgrowth().unwrap() + width <= 8096)
                 }
                 MemArgs::Mem2Args(_memarg1, memarg2) => {
-                    let offset = get_imm_mem_offset(memarg2);
+                    let offset = match get_imm_mem_offset(memarg2) {
+                        Some(offset) => offset,
+                        None => return false,
+                    };
                     return (-state.get_probestack().unwrap()
                         <= state.get_stackgrowth().unwrap() + offset)
-                        && (state.get_stackgrowth().unwrap() + offset < 8096);
+                        && (state.get_stackgrowth().unwrap() + offset + width <= 8096);
                 }
                 _ => return false, //stack accesses should never have 3 args
             }
@@ -166,16 +236,20 @@ impl StackChecker<'_> {
 
     fn lucet_check_stack_write(&self, state: &StackGrowthLattice, dst: &Value) -> bool {
         if let Value::Mem(_, memargs) = dst {
+            let width = dst.width() as i64;
             match memargs {
                 MemArgs::Mem1Arg(_memarg) => {
                     return (-state.get_probestack().unwrap() <= state.get_stackgrowth().unwrap())
-                        && (state.get_stackgrowth().unwrap() < 0);
+                        && (state.get_stackgrowth().unwrap() + width <= 0);
                 }
                 MemArgs::Mem2Args(_memarg1, memarg2) => {
-                    let offset = get_imm_mem_offset(memarg2);
+                    let offset = match get_imm_mem_offset(memarg2) {
+                        Some(offset) => offset,
+                        None => return false,
+                    };
                     return (-state.get_probestack().unwrap()
                         <= state.get_stackgrowth().unwrap() + offset)
-                        && (state.get_stackgrowth().unwrap() + offset < 0);
+                        && (state.get_stackgrowth().unwrap() + offset + width <= 0);
                 }
                 _ => return false, //stack accesses should never have 3 args
             }
@@ -183,21 +257,28 @@ impl StackChecker<'_> {
         panic!("Unreachable")
     }
 
+    fn wamr_stack_offset(stackgrowth: i64, memargs: &MemArgs) -> Option<i64> {
+        match memargs {
+            MemArgs::Mem1Arg(_memarg) => Some(stackgrowth),
+            MemArgs::Mem2Args(_memarg1, memarg2) => get_imm_mem_offset(memarg2).map(|offset| stackgrowth + offset),
+            _ => None, //stack accesses should never have 3 args
+        }
+    }
+
     fn wamr_check_stack_read(&self, state: &StackGrowthLattice, src: &Value) -> bool {
         if let Value::Mem(_, memargs) = src {
-            if let Some((stackgrowth, _, _)) = &state.v {
-                match memargs {
-                    MemArgs::Mem1Arg(_memarg) => {
-                        return *stackgrowth < WAMR_STACK_UPPER_BOUND &&
-                               *stackgrowth > WAMR_STACK_LOWER_BOUND;
-                    },
-                    MemArgs::Mem2Args(_memarg1, memarg2) => {
-                        let offset = stackgrowth + get_imm_mem_offset(memarg2);
-                        return offset < WAMR_STACK_UPPER_BOUND &&
-                               offset > WAMR_STACK_LOWER_BOUND;
-                    },
-                    _ => return false, //stack accesses should never have 3 args
+            if let Some((stackgrowth, _, _, _, _, _, _)) = &state.v {
+                let offset = match Self::wamr_stack_offset(*stackgrowth, memargs) {
+                    Some(offset) => offset,
+                    None => return false,
+                };
+                let width = src.width() as i64;
+                // the return address is off limits in strict mode
+                if self.analyzer.metadata.strict_stack && offset < 8 && offset + width > 0 {
+                    return false;
                 }
+                return offset + width <= WAMR_STACK_UPPER_BOUND &&
+                       offset > wamr_stack_lower_bound(state.get_checked_limit());
             }
         }
         panic!("Unreachable")
@@ -205,25 +286,24 @@ impl StackChecker<'_> {
 
     fn wamr_check_stack_write(&self, state: &StackGrowthLattice, dst: &Value) -> bool {
         if let Value::Mem(_, memargs) = dst {
-            if let Some((stackgrowth, _, saved)) = &state.v {
-                match memargs {
-                    MemArgs::Mem1Arg(_memarg) => {
-                        if write_clobbers_callee_saved_reg(*stackgrowth, saved) {
-                            return false;
-                        }
-                        return *stackgrowth < 0 &&
-                               *stackgrowth > WAMR_STACK_LOWER_BOUND;
-                    },
-                    MemArgs::Mem2Args(_memarg1, memarg2) => {
-                        let offset = *stackgrowth + get_imm_mem_offset(memarg2);
-                        if write_clobbers_callee_saved_reg(offset, saved) {
-                            return false;
-                        }
-                        return offset < 0 &&
-                               offset > WAMR_STACK_LOWER_BOUND;
-                    },
-                    _ => return false, //stack accesses should never have 3 args
+            if let Some((stackgrowth, _, saved, _, _, _, allocated)) = &state.v {
+                let offset = match Self::wamr_stack_offset(*stackgrowth, memargs) {
+                    Some(offset) => offset,
+                    None => return false,
+                };
+                let width = dst.width() as i64;
+                if write_clobbers_callee_saved_reg(offset, width, saved) {
+                    return false;
+                }
+                // until a frame is allocated on the path here, the caller's argument spill area
+                // above the return address may be written
+                if !*allocated &&
+                   offset >= 8 &&
+                   offset + width <= 8 + self.analyzer.metadata.caller_window {
+                    return true;
                 }
+                return offset + width <= 0 &&
+                       offset > wamr_stack_lower_bound(state.get_checked_limit());
             }
         }
         panic!("Unreachable")
@@ -231,15 +311,661 @@ impl StackChecker<'_> {
 
     fn check_stack_read(&self, state: &StackGrowthLattice, src: &Value) -> bool {
         match self.analyzer.compiler() {
-            Compiler::Lucet => self.lucet_check_stack_read(state, src),
+            Compiler::Lucet | Compiler::Wasmtime => self.lucet_check_stack_read(state, src),
             Compiler::Wamr => self.wamr_check_stack_read(state, src),
         }
     }
 
     fn check_stack_write(&self, state: &StackGrowthLattice, src: &Value) -> bool {
         match self.analyzer.compiler() {
-            Compiler::Lucet => self.lucet_check_stack_write(state, src),
+            Compiler::Lucet | Compiler::Wasmtime => self.lucet_check_stack_write(state, src),
             Compiler::Wamr => self.wamr_check_stack_write(state, src),
         }
     }
 }
+
+/// How much of the stack argument area a function uses: the bytes of outgoing stack arguments it
+/// writes for each of its calls (above rsp at the call), and the bytes of its own stack arguments
+/// it reads through rsp (above its return address).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StackArgExtents {
+    // call site -> its target, if it is a direct call, and the outgoing arguments written for it
+    pub outgoing: BTreeMap<u64, (Option<u64>, i64)>,
+    pub max_arg_read: i64,
+}
+
+impl StackArgExtents {
+    /// The most outgoing stack arguments written for any of the calls.
+    pub fn max_outgoing(&self) -> i64 {
+        self.outgoing.values().map(|(_, written)| *written).max().unwrap_or(0)
+    }
+}
+
+/// A direct call writing fewer stack arguments than its callee reads, which then reads whatever
+/// the caller left on its stack.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StackArgMismatch {
+    pub callee: u64,
+    pub caller: u64,
+    pub call_site: u64,
+    pub read: i64,
+    pub written: i64,
+}
+
+// The stack growth `access` starts at and its width, if it is a stack access at a known offset
+fn stack_slot(state: &StackGrowthLattice, access: &Value) -> Option<(i64, i64)> {
+    match access {
+        Value::Mem(_, memargs) if is_stack_access(access) => {
+            Some((state.get_stackgrowth()? + extract_stack_offset(memargs)?, access.width() as i64))
+        }
+        _ => None,
+    }
+}
+
+/// Walks the stack states of a function like the stack checker, adding up its `StackArgExtents`.
+/// The outgoing arguments of a call are the stack writes at or above rsp since the last call in
+/// its block; ones written in an earlier block aren't counted.
+pub fn stack_arg_extents(
+    result: &AnalysisResult<StackGrowthLattice>,
+    irmap: &IRMap,
+    analyzer: &StackAnalyzer,
+) -> StackArgExtents {
+    let mut extents = StackArgExtents::default();
+    for (block_addr, state) in result.iter() {
+        let mut state = state.clone();
+        // the stack slots written since the last call, as stack growths (start, end)
+        let mut written: Vec<(i64, i64)> = vec![];
+        for (addr, ir_stmts) in irmap.get(block_addr).unwrap().iter() {
+            for (idx, ir_stmt) in ir_stmts.iter().enumerate() {
+                let (dsts, srcs) = match ir_stmt {
+                    Stmt::Unop(_, dst, src) => (vec![dst], vec![src]),
+                    Stmt::Binop(_, dst, src1, src2) => (vec![dst], vec![src1, src2]),
+                    _ => (vec![], vec![]),
+                };
+                // the caller's stack arguments start right above the return address
+                for (start, width) in srcs.into_iter().filter_map(|src| stack_slot(&state, src)) {
+                    if start >= 8 {
+                        extents.max_arg_read = max(extents.max_arg_read, start + width - 8);
+                    }
+                }
+                for (start, width) in dsts.into_iter().filter_map(|dst| stack_slot(&state, dst)) {
+                    written.push((start, start + width));
+                }
+                if let Stmt::Call(target, len) = ir_stmt {
+                    if let Some(rsp) = state.get_stackgrowth() {
+                        let outgoing = written.iter().filter(|(start, _)| *start >= rsp).map(|(_, end)| end - rsp).max();
+                        let direct = match target {
+                            Value::Imm(_, immsize, imm) => Some(rel_target(immsize, *imm, *addr, *len as u64)),
+                            _ => None,
+                        };
+                        extents.outgoing.insert(*addr, (direct, outgoing.unwrap_or(0)));
+                    }
+                    written.clear();
+                }
+                analyzer.aexec(&mut state, ir_stmt, &LocIdx { addr: *addr, idx: idx as u32 });
+            }
+        }
+    }
+    extents
+}
+
+/// Pairs the direct calls of `funcs` (by address) with their callees among them, returning each
+/// callee that reads more stack arguments than its least generous direct caller writes.
+pub fn stack_arg_mismatches(funcs: &BTreeMap<u64, StackArgExtents>) -> Vec<StackArgMismatch> {
+    // callee -> the caller writing the fewest stack arguments for it, where, and how many
+    let mut least: BTreeMap<u64, (u64, u64, i64)> = BTreeMap::new();
+    for (caller, extents) in funcs.iter() {
+        for (call_site, (target, written)) in extents.outgoing.iter() {
+            if let Some(callee) = target {
+                let entry = least.entry(*callee).or_insert((*caller, *call_site, *written));
+                if *written < entry.2 {
+                    *entry = (*caller, *call_site, *written);
+                }
+            }
+        }
+    }
+    least
+        .into_iter()
+        .filter_map(|(callee, (caller, call_site, written))| {
+            let read = funcs.get(&callee)?.max_arg_read;
+            if read > written {
+                Some(StackArgMismatch { callee: callee, caller: caller, call_site: call_site, read: read, written: written })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn wamr_caller_window_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::lattices::stackgrowthlattice::WAMR_CALLER_WINDOW;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{Binopcode, ImmType, MemArg, Unopcode, ValSize};
+
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let rdi = Value::Reg(7, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let stack = |v: i64| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, v)),
+    );
+    let analyzer = StackAnalyzer {
+        metadata: CompilerMetadata {
+            caller_window: WAMR_CALLER_WINDOW,
+            ..CompilerMetadata::for_test(Compiler::Wamr)
+        },
+        stack_limit_cmps: Default::default(),
+    };
+    let check = |stmts: Vec<Stmt>| {
+        let mut block: Vec<(u64, Vec<Stmt>)> = stmts.into_iter().enumerate().map(|(i, s)| (i as u64 * 4, vec![s])).collect();
+        block.push((block.len() as u64 * 4, vec![Stmt::Ret]));
+        let mut irmap = IRMap::new();
+        irmap.insert(0, block);
+        check_stack(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer).is_ok()
+    };
+
+    // mov [rsp + 8], rdi; sub rsp, 16; add rsp, 16
+    assert!(check(vec![
+        Stmt::Unop(Unopcode::Mov, stack(8), rdi.clone()),
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(16)),
+        Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(16)),
+    ]));
+    // sub rsp, 16; mov [rsp + 24], rdi; add rsp, 16
+    assert!(!check(vec![
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(16)),
+        Stmt::Unop(Unopcode::Mov, stack(24), rdi.clone()),
+        Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(16)),
+    ]));
+    // sub rsp, 16; add rsp, 16; mov [rsp + 8], rdi: rsp is back where it was at entry, but the
+    // frame has been set up
+    assert!(!check(vec![
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(16)),
+        Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(16)),
+        Stmt::Unop(Unopcode::Mov, stack(8), rdi.clone()),
+    ]));
+    // mov [rsp + 8 + window], rdi
+    assert!(!check(vec![Stmt::Unop(Unopcode::Mov, stack(8 + WAMR_CALLER_WINDOW), rdi.clone())]));
+    // mov [rsp], rdi
+    assert!(!check(vec![Stmt::Unop(Unopcode::Mov, stack(0), rdi.clone())]));
+}
+
+#[test]
+fn rsp_alignment_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{ImmType, MemArg, Unopcode, ValSize};
+
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let r10 = Value::Reg(10, ValSize::Size64);
+    let rax = Value::Reg(0, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size32, v);
+    let stack = |v: i64| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, v)),
+    );
+    let check = |compiler: Compiler, stmts: &Vec<Stmt>| {
+        let analyzer = StackAnalyzer {
+            metadata: CompilerMetadata::for_test(compiler),
+            stack_limit_cmps: Default::default(),
+        };
+        let mut block: Vec<(u64, Vec<Stmt>)> = stmts.iter().enumerate().map(|(i, s)| (i as u64 * 4, vec![s.clone()])).collect();
+        block.push((block.len() as u64 * 4, vec![Stmt::Ret]));
+        let mut irmap = IRMap::new();
+        irmap.insert(0, block);
+        check_stack(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer).is_ok()
+    };
+    let aligned = |mask: Value, restore: Stmt| vec![
+        Stmt::Unop(Unopcode::Mov, r10.clone(), rsp.clone()),
+        Stmt::Binop(Binopcode::And, rsp.clone(), rsp.clone(), mask),
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(64)),
+        Stmt::Unop(Unopcode::Mov, stack(32), rax.clone()),
+        restore,
+    ];
+
+    for compiler in vec![Compiler::Lucet, Compiler::Wamr] {
+        // mov r10, rsp; and rsp, -32; sub rsp, 64; mov [rsp + 32], rax; mov rsp, r10
+        assert!(check(compiler, &aligned(imm(-32), Stmt::Unop(Unopcode::Mov, rsp.clone(), r10.clone()))));
+        // ... add rsp, 64: rsp is only known to within the alignment at the ret
+        assert!(!check(compiler, &aligned(imm(-32), Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(64)))));
+        // and rsp, rax; and rsp, -24
+        assert!(!check(compiler, &aligned(rax.clone(), Stmt::Unop(Unopcode::Mov, rsp.clone(), r10.clone()))));
+        assert!(!check(compiler, &aligned(imm(-24), Stmt::Unop(Unopcode::Mov, rsp.clone(), r10.clone()))));
+    }
+}
+
+#[test]
+fn wamr_exception_epilogue_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::{Fork, Diamond};
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{ImmType, MemArg, Unopcode, ValSize};
+    use yaxpeax_x86::long_mode::Opcode;
+
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let top = Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64)));
+    let push = |regnum: u8| vec![
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(8)),
+        Stmt::Unop(Unopcode::Mov, top.clone(), reg(regnum)),
+    ];
+    let pop = |regnum: u8| vec![
+        Stmt::Unop(Unopcode::Mov, reg(regnum), top.clone()),
+        Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(8)),
+    ];
+    // mov [rdi + WAMR_EXCEPTION_OFFSET], eax
+    let raise = Stmt::Unop(
+        Unopcode::Mov,
+        Value::Mem(ValSize::Size32, MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, 0x68))),
+        Value::Reg(0, ValSize::Size32),
+    );
+    let branch = Stmt::Branch(Opcode::JZ, imm(0x20));
+    let block = |addr: u64, stmts: Vec<Stmt>| -> Vec<(u64, Vec<Stmt>)> {
+        stmts.into_iter().enumerate().map(|(i, stmt)| (addr + i as u64, vec![stmt])).collect()
+    };
+    let check = |exception_epilogue: bool, shared: bool, blocks: Vec<(u64, Vec<Stmt>)>| {
+        let mut metadata = CompilerMetadata::for_test(Compiler::Wamr);
+        metadata.options.exception_epilogue = exception_epilogue;
+        let analyzer = StackAnalyzer { metadata: metadata, stack_limit_cmps: Default::default() };
+        let mut irmap = IRMap::new();
+        for (addr, stmts) in blocks {
+            irmap.insert(addr, block(addr, stmts));
+        }
+        let result = if shared { run_worklist(&Diamond, &irmap, &analyzer) } else { run_worklist(&Fork, &irmap, &analyzer) };
+        check_stack(result, &irmap, &analyzer)
+            .map_err(|failure| (failure.loc.addr, failure.description))
+    };
+    let concat = |parts: Vec<Vec<Stmt>>| -> Vec<Stmt> { parts.into_iter().flatten().collect() };
+
+    // push rbx; mov rbx, rdi; jz; then pop rbx; ret on both sides
+    let normal = vec![
+        (0, concat(vec![push(3), vec![Stmt::Unop(Unopcode::Mov, reg(3), reg(7)), branch.clone()]])),
+        (0x10, concat(vec![pop(3), vec![Stmt::Ret]])),
+        (0x20, concat(vec![pop(3), vec![Stmt::Ret]])),
+    ];
+    assert_eq!(check(false, false, normal.clone()), Ok(()));
+    assert_eq!(check(true, false, normal), Ok(()));
+
+    // one side raises an exception and returns without restoring rbx
+    let epilogue = |raises: bool| vec![
+        (0, concat(vec![push(3), vec![Stmt::Unop(Unopcode::Mov, reg(3), reg(7)), branch.clone()]])),
+        (0x10, concat(vec![pop(3), vec![Stmt::Ret]])),
+        (0x20, (if raises { vec![raise.clone()] } else { vec![] }).into_iter()
+            .chain(vec![Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(8)), Stmt::Ret])
+            .collect()),
+    ];
+    let unrestored = Err((0x22, "callee-saved registers [3] not restored at ret".to_string()));
+    assert_eq!(check(false, false, epilogue(true)), unrestored);
+    assert_eq!(check(true, false, epilogue(true)), Ok(()));
+    assert_eq!(check(true, false, epilogue(false)), Err((0x21, "callee-saved registers [3] not restored at ret".to_string())));
+
+    // a shared epilogue reached from frames that saved different registers: this used to panic
+    let shared = vec![
+        (0, concat(vec![push(3), vec![branch.clone()]])),
+        (0x10, concat(vec![push(12), vec![Stmt::Branch(Opcode::JMP, imm(0x30))]])),
+        (0x20, vec![Stmt::Branch(Opcode::JMP, imm(0x30))]),
+        (0x30, concat(vec![pop(3), vec![Stmt::Ret]])),
+    ];
+    for exception_epilogue in vec![false, true] {
+        assert_eq!(
+            check(exception_epilogue, true, shared.clone()),
+            Err((0x30, "restoring callee-saved register r3 without a known stack state".to_string()))
+        );
+    }
+    // restoring a register that was never saved, or from the wrong place
+    assert_eq!(
+        check(false, false, vec![(0, concat(vec![pop(3), vec![Stmt::Ret]]))]),
+        Err((0, "restoring callee-saved register r3 that was never saved".to_string()))
+    );
+    assert_eq!(
+        check(false, false, vec![(0, concat(vec![push(3), push(12), pop(3), pop(12), vec![Stmt::Ret]]))]),
+        Err((4, "restoring callee-saved register r3 from stack growth -16, but it was saved at -8".to_string()))
+    );
+}
+
+#[test]
+fn stack_write_width_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{ImmType, MemArg, Unopcode, ValSize};
+
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let stack = |size: ValSize, v: i64| Value::Mem(
+        size,
+        MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, v)),
+    );
+    let top = Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64)));
+    let check = |compiler: Compiler, stmts: Vec<Stmt>| {
+        let analyzer = StackAnalyzer {
+            metadata: CompilerMetadata::for_test(compiler),
+            stack_limit_cmps: Default::default(),
+        };
+        let mut block: Vec<(u64, Vec<Stmt>)> = stmts.into_iter().enumerate().map(|(i, s)| (i as u64 * 4, vec![s])).collect();
+        block.push((block.len() as u64 * 4, vec![Stmt::Ret]));
+        let mut irmap = IRMap::new();
+        irmap.insert(0, block);
+        check_stack(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer).is_ok()
+    };
+
+    // push rbx; sub rsp, 8; mov [rsp + 4], <write>; add rsp, 8; pop rbx
+    let saved = |write: Value| vec![
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(8)),
+        Stmt::Unop(Unopcode::Mov, top.clone(), reg(3)),
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(8)),
+        Stmt::Unop(Unopcode::Mov, write, imm(0)),
+        Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(8)),
+        Stmt::Unop(Unopcode::Mov, reg(3), top.clone()),
+        Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(8)),
+    ];
+    // a 4-byte write ends where rbx was saved, but an 8-byte one overlaps its slot
+    assert!(check(Compiler::Wamr, saved(stack(ValSize::Size32, 4))));
+    assert!(check(Compiler::Wamr, saved(stack(ValSize::Size64, 0))));
+    assert!(!check(Compiler::Wamr, saved(stack(ValSize::Size64, 4))));
+
+    // sub rsp, 8; mov [rsp + 4], <write>; add rsp, 8: an 8-byte write would reach the return address
+    let framed = |write: Value| vec![
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(8)),
+        Stmt::Unop(Unopcode::Mov, write, imm(0)),
+        Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(8)),
+    ];
+    for compiler in vec![Compiler::Lucet, Compiler::Wamr] {
+        assert!(check(compiler, framed(stack(ValSize::Size32, 4))));
+        assert!(!check(compiler, framed(stack(ValSize::Size64, 4))));
+    }
+}
+
+#[test]
+fn wamr_frame_slot_restore_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{ImmType, MemArg, Unopcode, ValSize};
+
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let slot = |v: i64| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, v)),
+    );
+    let check = |stmts: Vec<Stmt>| {
+        let analyzer = StackAnalyzer {
+            metadata: CompilerMetadata::for_test(Compiler::Wamr),
+            stack_limit_cmps: Default::default(),
+        };
+        let mut block: Vec<(u64, Vec<Stmt>)> = stmts.into_iter().enumerate().map(|(i, s)| (i as u64 * 4, vec![s])).collect();
+        block.push((block.len() as u64 * 4, vec![Stmt::Ret]));
+        let mut irmap = IRMap::new();
+        irmap.insert(0, block);
+        check_stack(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer)
+            .map_err(|failure| (failure.loc.addr, failure.description))
+    };
+
+    // sub rsp, 0x18; mov [rsp+0x10], rbx; mov rbx, rdi; mov [rsp+8], rbx; mov rbx, [rsp+8];
+    // <restore>; add rsp, 0x18; ret
+    let frame = |restore: Vec<Stmt>| -> Vec<Stmt> {
+        vec![
+            Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(0x18)),
+            Stmt::Unop(Unopcode::Mov, slot(0x10), reg(3)),
+            Stmt::Unop(Unopcode::Mov, reg(3), reg(7)),
+            // a spill and reload of rbx in between is neither a save nor a restore
+            Stmt::Unop(Unopcode::Mov, slot(8), reg(3)),
+            Stmt::Unop(Unopcode::Mov, reg(3), slot(8)),
+        ]
+        .into_iter()
+        .chain(restore)
+        .chain(vec![Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(0x18))])
+        .collect()
+    };
+    assert_eq!(check(frame(vec![Stmt::Unop(Unopcode::Mov, reg(3), slot(0x10))])), Ok(()));
+    assert_eq!(check(frame(vec![])), Err((0x18, "callee-saved registers [3] not restored at ret".to_string())));
+    // loading rbx from the spill slot doesn't restore it
+    assert_eq!(
+        check(frame(vec![Stmt::Unop(Unopcode::Mov, reg(3), slot(8))])),
+        Err((0x1c, "callee-saved registers [3] not restored at ret".to_string()))
+    );
+}
+
+#[test]
+fn frame_pointer_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{ImmType, MemArg, Unopcode, ValSize};
+
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let rbp = Value::Reg(5, ValSize::Size64);
+    let rax = Value::Reg(0, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let top = Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64)));
+    let slot = Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, 8)),
+    );
+    let check = |compiler: Compiler, stmts: Vec<Stmt>| {
+        let analyzer = StackAnalyzer {
+            metadata: CompilerMetadata::for_test(compiler),
+            stack_limit_cmps: Default::default(),
+        };
+        let mut block: Vec<(u64, Vec<Stmt>)> = stmts.into_iter().enumerate().map(|(i, s)| (i as u64 * 4, vec![s])).collect();
+        block.push((block.len() as u64 * 4, vec![Stmt::Ret]));
+        let mut irmap = IRMap::new();
+        irmap.insert(0, block);
+        check_stack(run_worklist(&SingleBlock(0), &irmap, &analyzer), &irmap, &analyzer)
+    };
+
+    // push rbp; mov rbp, rsp; sub rsp, 0x20; mov [rsp+8], rax; <body>; leave; ret
+    let framed = |body: Vec<Stmt>| -> Vec<Stmt> {
+        vec![
+            Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(8)),
+            Stmt::Unop(Unopcode::Mov, top.clone(), rbp.clone()),
+            Stmt::Unop(Unopcode::Mov, rbp.clone(), rsp.clone()),
+            Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(0x20)),
+            Stmt::Unop(Unopcode::Mov, slot.clone(), rax.clone()),
+        ]
+        .into_iter()
+        .chain(body)
+        .chain(vec![
+            Stmt::Unop(Unopcode::Mov, rsp.clone(), rbp.clone()),
+            Stmt::Unop(Unopcode::Mov, rbp.clone(), top.clone()),
+            Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(8)),
+        ])
+        .collect()
+    };
+    for compiler in vec![Compiler::Lucet, Compiler::Wamr] {
+        assert!(check(compiler, framed(vec![])).is_ok());
+        // more stack taken in the body is given back by the leave
+        assert!(check(compiler, framed(vec![Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(0x10))])).is_ok());
+        // once rbp is overwritten it no longer says where the frame is
+        assert!(check(compiler, framed(vec![Stmt::Unop(Unopcode::Mov, rbp.clone(), rax.clone())])).is_err());
+    }
+
+    // rsp moved by a register, an indexed stack access and a write to esp are rejected, not panicked on
+    let indexed = Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Reg(0, ValSize::Size64)),
+    );
+    let esp = Value::Reg(4, ValSize::Size32);
+    for compiler in vec![Compiler::Lucet, Compiler::Wamr] {
+        assert!(check(compiler, vec![Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), rax.clone())]).is_err());
+        assert!(check(compiler, framed(vec![Stmt::Unop(Unopcode::Mov, indexed.clone(), rax.clone())])).is_err());
+        assert!(check(compiler, framed(vec![Stmt::Unop(Unopcode::Mov, rax.clone(), indexed.clone())])).is_err());
+        assert!(check(compiler, vec![Stmt::Unop(Unopcode::Mov, esp.clone(), Value::Reg(0, ValSize::Size32))]).is_err());
+    }
+}
+
+#[test]
+fn stack_arg_extents_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{ImmType, MemArg, Unopcode, ValSize};
+
+    let analyzer = StackAnalyzer {
+        metadata: CompilerMetadata::for_test(Compiler::Lucet),
+        stack_limit_cmps: Default::default(),
+    };
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let rax = Value::Reg(0, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let slot = |offset: i64| {
+        Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)))
+    };
+    // the extents of the function at `addr` made of `stmts`, 4 bytes each, and a ret
+    let extents = |addr: u64, stmts: Vec<Stmt>| {
+        let mut block: Vec<(u64, Vec<Stmt>)> = stmts.into_iter().enumerate().map(|(i, s)| (addr + i as u64 * 4, vec![s])).collect();
+        block.push((addr + block.len() as u64 * 4, vec![Stmt::Ret]));
+        let mut irmap = IRMap::new();
+        irmap.insert(addr, block);
+        let result = run_worklist(&SingleBlock(addr), &irmap, &analyzer);
+        let extents = stack_arg_extents(&result, &irmap, &analyzer);
+        assert!(check_stack(result, &irmap, &analyzer).is_ok());
+        extents
+    };
+
+    // sub rsp, 16; mov [rsp], rax; mov [rsp+8], rax; call 0x100; add rsp, 16; ret
+    let caller = extents(0, vec![
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(16)),
+        Stmt::Unop(Unopcode::Mov, slot(0), rax.clone()),
+        Stmt::Unop(Unopcode::Mov, slot(8), rax.clone()),
+        Stmt::Call(Value::Imm(ImmType::Signed, ValSize::Size32, 0x100 - 17), 5),
+        Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(16)),
+    ]);
+    assert_eq!(caller.outgoing.get(&12), Some(&(Some(0x100), 16)));
+    assert_eq!(caller.max_outgoing(), 16);
+    assert_eq!(caller.max_arg_read, 0);
+
+    // mov rax, [rsp+8]; mov rax, [rsp+<last>]; ret reads both stack arguments, or one past them
+    let callee = |last: i64| extents(0x100, vec![Stmt::Unop(Unopcode::Mov, rax.clone(), slot(8)), Stmt::Unop(Unopcode::Mov, rax.clone(), slot(last))]);
+    let matched = callee(16);
+    assert_eq!(matched.max_arg_read, 16);
+    let mut funcs = BTreeMap::new();
+    funcs.insert(0, caller.clone());
+    funcs.insert(0x100, matched);
+    assert_eq!(stack_arg_mismatches(&funcs), vec![]);
+
+    funcs.insert(0x100, callee(24));
+    assert_eq!(
+        stack_arg_mismatches(&funcs),
+        vec![StackArgMismatch { callee: 0x100, caller: 0, call_site: 12, read: 24, written: 16 }]
+    );
+    // a callee that isn't among the functions can't be checked
+    funcs.remove(&0x100);
+    assert_eq!(stack_arg_mismatches(&funcs), vec![]);
+}
+
+#[test]
+fn wamr_stack_limit_check_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::analyses::stack_analyzer::wamr_stack_limit_cmps;
+    use crate::analyses::{run_worklist, ControlFlow};
+    use crate::lattices::heaplattice::WAMR_STACKLIMIT_OFFSET;
+    use crate::lattices::stackgrowthlattice::WAMR_STACK_LOWER_BOUND;
+    use crate::utils::compiler::CompilerMetadata;
+    use crate::utils::ir::{ImmType, MemArg, Unopcode, ValSize};
+    use yaxpeax_x86::long_mode::Opcode;
+
+    // 0x0 checks the stack limit, falling through to 0x10 or trapping at 0x20
+    struct Checked;
+    impl ControlFlow for Checked {
+        fn entrypoint(&self) -> u64 { 0 }
+        fn successors(&self, addr: u64) -> Vec<u64> {
+            match addr {
+                0 => vec![0x10, 0x20],
+                _ => vec![],
+            }
+        }
+    }
+
+    let metadata = CompilerMetadata::for_test(Compiler::Wamr);
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let rax = Value::Reg(0, ValSize::Size64);
+    let rdi = Value::Reg(7, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let execenv = |offset: i64| {
+        Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)))
+    };
+    let stack = |v: i64| {
+        Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, v)))
+    };
+    let frame = 0x10000;
+    // `head` sets up a frame of `frame` bytes and ends in a branch to the trap at 0x20, and the body
+    // at 0x10 writes to `slot` in the frame
+    let check = |head: Vec<Stmt>, slot: i64| {
+        let mut irmap = IRMap::new();
+        irmap.insert(0, head.into_iter().enumerate().map(|(i, stmt)| (i as u64 * 4, vec![stmt])).collect());
+        irmap.insert(
+            0x10,
+            vec![
+                (0x10, vec![Stmt::Unop(Unopcode::Mov, stack(slot), rdi.clone())]),
+                (0x14, vec![Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(frame))]),
+                (0x18, vec![Stmt::Ret]),
+            ],
+        );
+        irmap.insert(0x20, vec![(0x20, vec![Stmt::Undefined])]);
+        let heap_analyzer = HeapAnalyzer { metadata: metadata.clone() };
+        let heap_result = run_worklist(&Checked, &irmap, &heap_analyzer);
+        let analyzer = StackAnalyzer {
+            metadata: metadata.clone(),
+            stack_limit_cmps: wamr_stack_limit_cmps(&irmap, &heap_result, &heap_analyzer),
+        };
+        check_stack(run_worklist(&Checked, &irmap, &analyzer), &irmap, &analyzer).map_err(|site| site.description)
+    };
+    let sub_frame = Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(frame));
+    let jb = Stmt::Branch(Opcode::JB, imm(0x20));
+
+    // sub rsp, 0x10000; mov rax, [rdi + 0x18]; cmp rsp, rax; jb trap: the whole frame is checked
+    let cmp_rsp = vec![
+        sub_frame.clone(),
+        Stmt::Unop(Unopcode::Mov, rax.clone(), execenv(WAMR_STACKLIMIT_OFFSET)),
+        Stmt::Binop(Binopcode::Cmp, rsp.clone(), rsp.clone(), rax.clone()),
+        jb.clone(),
+    ];
+    assert_eq!(check(cmp_rsp.clone(), 0), Ok(()));
+    // and so are the guard pages below it, but not further down
+    assert_eq!(check(cmp_rsp.clone(), -0x1000), Ok(()));
+    assert!(check(cmp_rsp, WAMR_STACK_LOWER_BOUND).unwrap_err().starts_with("stack accessed beyond checked limit"));
+
+    // sub rsp, 0x10000; lea rax, [rsp + 0x10]; cmp rax, [rdi + 0x18]; jb trap: checked down to
+    // 0x10 above rsp, which leaves all but 0x10 bytes of the guard pages below it
+    let cmp_lea = vec![
+        sub_frame.clone(),
+        Stmt::Binop(Binopcode::Add, rax.clone(), rsp.clone(), imm(0x10)),
+        Stmt::Binop(Binopcode::Cmp, rax.clone(), rax.clone(), execenv(WAMR_STACKLIMIT_OFFSET)),
+        jb.clone(),
+    ];
+    assert_eq!(check(cmp_lea.clone(), 0), Ok(()));
+    assert!(check(cmp_lea, 0x10 + WAMR_STACK_LOWER_BOUND).is_err());
+
+    // without a check of the limit, the frame is only protected by the guard pages below the entry
+    let unchecked = vec![
+        sub_frame.clone(),
+        Stmt::Unop(Unopcode::Mov, rax.clone(), execenv(WAMR_STACKLIMIT_OFFSET + 8)),
+        Stmt::Binop(Binopcode::Cmp, rsp.clone(), rsp.clone(), rax.clone()),
+        jb.clone(),
+    ];
+    assert!(check(unchecked, 0).unwrap_err().starts_with("stack accessed beyond checked limit"));
+    // nor does a check the body is on the wrong side of (jae to 0x20 when rsp is above the limit)
+    let wrong_side = vec![
+        sub_frame.clone(),
+        Stmt::Unop(Unopcode::Mov, rax.clone(), execenv(WAMR_STACKLIMIT_OFFSET)),
+        Stmt::Binop(Binopcode::Cmp, rsp.clone(), rsp.clone(), rax.clone()),
+        Stmt::Branch(Opcode::JNB, imm(0x20)),
+    ];
+    assert!(check(wrong_side, 0).unwrap_err().starts_with("stack accessed beyond checked limit"));
+    // a check of rsp moved after the cmp doesn't count
+    let moved = vec![
+        Stmt::Unop(Unopcode::Mov, rax.clone(), execenv(WAMR_STACKLIMIT_OFFSET)),
+        Stmt::Binop(Binopcode::Cmp, rsp.clone(), rsp.clone(), rax.clone()),
+        sub_frame,
+        jb,
+    ];
+    assert!(check(moved, 0).unwrap_err().starts_with("stack accessed beyond checked limit"));
+}
@@ -0,0 +1,159 @@
+use crate::analyses::taint_analyzer::TaintAnalyzer;
+use crate::analyses::{AbstractAnalyzer, AnalysisResult};
+use crate::checkers::{CheckFailure, Checker};
+use crate::lattices::reachingdefslattice::LocIdx;
+use crate::lattices::taintlattice::TaintLattice;
+use crate::utils::ir::{IRMap, MemArg, MemArgs, Stmt, Value};
+use std::cell::RefCell;
+
+/// A scaled (table-style) index that may be guest-controlled and was never bounds checked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaintFinding {
+    pub sink: u64,
+    pub regnum: u8,
+    // the last write to the index register in the sink's block, if there is one
+    pub source: Option<u64>,
+}
+
+pub struct TaintChecker<'a> {
+    irmap: &'a IRMap,
+    analyzer: &'a TaintAnalyzer,
+    findings: RefCell<Vec<TaintFinding>>,
+}
+
+/// Audits the indices of jump table and call table lookups. Findings are advisory: the heap and
+/// call checkers are what decide whether a function is safe.
+pub fn check_taint(
+    result: AnalysisResult<TaintLattice>,
+    irmap: &IRMap,
+    analyzer: &TaintAnalyzer,
+) -> Vec<TaintFinding> {
+    let checker = TaintChecker {
+        irmap,
+        analyzer,
+        findings: RefCell::new(vec![]),
+    };
+    let _ = checker.check(result);
+    let mut findings = checker.findings.into_inner();
+    findings.sort_by_key(|finding| finding.sink);
+    findings
+}
+
+impl Checker<TaintLattice> for TaintChecker<'_> {
+    fn check(&self, result: AnalysisResult<TaintLattice>) -> Result<(), CheckFailure> {
+        self.check_state_at_statements(result)
+    }
+
+    fn irmap(&self) -> &IRMap {
+        self.irmap
+    }
+
+    fn aexec(&self, state: &mut TaintLattice, ir_stmt: &Stmt, loc: &LocIdx) {
+        self.analyzer.aexec(state, ir_stmt, loc)
+    }
+
+    fn paranoid(&self) -> bool {
+        self.analyzer.paranoid()
+    }
+
+    fn check_statement(&self, state: &TaintLattice, ir_stmt: &Stmt, loc_idx: &LocIdx) -> Result<(), String> {
+        let operands: Vec<&Value> = match ir_stmt {
+            Stmt::Clear(dst, srcs) => std::iter::once(dst).chain(srcs.iter()).collect(),
+            Stmt::Unop(_, dst, src) => vec![dst, src],
+            Stmt::Binop(_, dst, src1, src2) => vec![dst, src1, src2],
+            Stmt::Branch(_, target) | Stmt::Call(target, _) => vec![target],
+            _ => vec![],
+        };
+        for operand in operands {
+            if let Value::Mem(_, MemArgs::MemScale(_, MemArg::Reg(regnum, size), _))
+            | Value::Mem(_, MemArgs::MemScaleDisp(_, MemArg::Reg(regnum, size), _, _)) = operand
+            {
+                if !state.regs.get(regnum, size).is_true() {
+                    let finding = TaintFinding {
+                        sink: loc_idx.addr,
+                        regnum: *regnum,
+                        source: self.taint_source(loc_idx, *regnum),
+                    };
+                    if !self.findings.borrow().contains(&finding) {
+                        self.findings.borrow_mut().push(finding);
+                    }
+                }
+            }
+        }
+        // findings are reported, not enforced
+        Ok(())
+    }
+}
+
+impl TaintChecker<'_> {
+    fn taint_source(&self, loc_idx: &LocIdx, regnum: u8) -> Option<u64> {
+        let (_, ir_block) = self
+            .irmap
+            .iter()
+            .find(|(_, ir_block)| ir_block.iter().any(|(addr, _)| *addr == loc_idx.addr))?;
+        let mut source = None;
+        for (addr, ir_stmts) in ir_block.iter().take_while(|(addr, _)| *addr < loc_idx.addr) {
+            for ir_stmt in ir_stmts {
+                match ir_stmt {
+                    Stmt::Clear(Value::Reg(dst, _), _)
+                    | Stmt::Unop(_, Value::Reg(dst, _), _)
+                    | Stmt::Binop(_, Value::Reg(dst, _), _, _)
+                        if *dst == regnum =>
+                    {
+                        source = Some(*addr)
+                    }
+                    _ => (),
+                }
+            }
+        }
+        source
+    }
+}
+
+#[test]
+fn taint_checker_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::Fork;
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::ir::{Binopcode, ImmType, Unopcode, ValSize};
+    use yaxpeax_x86::long_mode::Opcode;
+
+    let analyzer = TaintAnalyzer {
+        metadata: CompilerMetadata::for_test(Compiler::Lucet),
+    };
+    let rax = Value::Reg(0, ValSize::Size64);
+    let imm = |v| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    // mov rax, [rdi + rcx]
+    let load_index = Stmt::Unop(
+        Unopcode::Mov,
+        rax.clone(),
+        Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Reg(1, ValSize::Size64))),
+    );
+    // mov rdx, [rbx + rax * 8]
+    let lookup = Stmt::Unop(
+        Unopcode::Mov,
+        Value::Reg(2, ValSize::Size64),
+        Value::Mem(ValSize::Size64, MemArgs::MemScale(MemArg::Reg(3, ValSize::Size64), MemArg::Reg(0, ValSize::Size64), MemArg::Imm(ImmType::Unsigned, ValSize::Size32, 8))),
+    );
+
+    // tainted and checked: load, cmp, branch, then look up on the in-bounds side
+    let mut irmap = IRMap::new();
+    irmap.insert(0, vec![
+        (0, vec![load_index.clone()]),
+        (4, vec![Stmt::Binop(Binopcode::Cmp, rax.clone(), rax.clone(), imm(16))]),
+        (8, vec![Stmt::Branch(Opcode::JB, imm(0x20))]),
+    ]);
+    irmap.insert(0x10, vec![(0x10, vec![Stmt::Ret])]);
+    irmap.insert(0x20, vec![(0x20, vec![lookup.clone()]), (0x24, vec![Stmt::Ret])]);
+    let result = run_worklist(&Fork, &irmap, &analyzer);
+    assert_eq!(check_taint(result, &irmap, &analyzer), vec![]);
+
+    // tainted and unchecked
+    irmap.insert(0, vec![(0, vec![load_index]), (4, vec![lookup]), (8, vec![Stmt::Ret])]);
+    irmap.insert(0x20, vec![(0x20, vec![Stmt::Ret])]);
+    let result = run_worklist(&Fork, &irmap, &analyzer);
+    assert_eq!(
+        check_taint(result, &irmap, &analyzer),
+        vec![TaintFinding { sink: 4, regnum: 0, source: Some(0) }]
+    );
+}
@@ -0,0 +1,302 @@
+//! A C ABI around `verify_module`, for embedding the verifier without shelling out to the binary.
+//! `include/veriwasm.h` declares everything here and has to be kept in step with it.
+//!
+//! Every entry point catches panics, which are reported as `VERIWASM_ERR_INTERNAL` rather than
+//! unwinding into the caller. Strings are UTF-8 and NUL-terminated; those returned by the report
+//! getters live as long as the report.
+
+use crate::utils::compiler::Compiler;
+use crate::utils::verification_report::{FunctionResult, VerificationReport};
+use crate::verify::{verify_module, VerifyError, VerifyOptions};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+// The same meaning as the exit status of `veriwasm`, where the two overlap.
+pub const VERIWASM_OK: c_int = 0;
+pub const VERIWASM_UNSAFE: c_int = 1;
+pub const VERIWASM_ERR_LOAD: c_int = 2;
+pub const VERIWASM_ERR_INVALID_ARGUMENT: c_int = 3;
+pub const VERIWASM_ERR_INTERNAL: c_int = 4;
+pub const VERIWASM_ERR_IO: c_int = 5;
+
+pub const VERIWASM_COMPILER_LUCET: u32 = 0;
+pub const VERIWASM_COMPILER_WAMR: u32 = 1;
+pub const VERIWASM_COMPILER_DETECT: u32 = 2;
+pub const VERIWASM_COMPILER_WASMTIME: u32 = 3;
+
+/// Mirrors `VeriwasmOptions` in the header. `struct_size` is the caller's `sizeof`: fields past
+/// it, or partly past it, keep their defaults, so callers built against an older header keep
+/// working, and fields added after ours are ignored.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct VeriwasmOptions {
+    pub struct_size: u32,
+    pub compiler: u32,
+    pub globals_size: i64,
+    pub call_table_size: i64,
+    pub num_jobs: u32,
+    pub keep_going: u8,
+}
+
+impl Default for VeriwasmOptions {
+    fn default() -> Self {
+        let defaults = VerifyOptions::default();
+        VeriwasmOptions {
+            struct_size: std::mem::size_of::<VeriwasmOptions>() as u32,
+            compiler: VERIWASM_COMPILER_DETECT,
+            globals_size: defaults.globals_size,
+            call_table_size: defaults.call_table_size,
+            num_jobs: defaults.num_jobs,
+            keep_going: defaults.keep_going as u8,
+        }
+    }
+}
+
+impl VeriwasmOptions {
+    unsafe fn read(options: *const VeriwasmOptions) -> Result<VeriwasmOptions, ()> {
+        let mut read = VeriwasmOptions::default();
+        if options.is_null() {
+            return Ok(read);
+        }
+        let size = ptr::read_unaligned(options as *const u32) as usize;
+        if size < std::mem::size_of::<u32>() {
+            return Err(());
+        }
+        // a size ending partway into a field only covers the fields before it
+        let size = read.field_ends().iter().copied().filter(|end| *end <= size).max().unwrap_or(0);
+        ptr::copy_nonoverlapping(options as *const u8, &mut read as *mut VeriwasmOptions as *mut u8, size);
+        Ok(read)
+    }
+
+    // The offset each field ends at, in order
+    fn field_ends(&self) -> [usize; 6] {
+        let base = self as *const VeriwasmOptions as usize;
+        let end = |field: usize, size: usize| field - base + size;
+        [
+            end(&self.struct_size as *const u32 as usize, 4),
+            end(&self.compiler as *const u32 as usize, 4),
+            end(&self.globals_size as *const i64 as usize, 8),
+            end(&self.call_table_size as *const i64 as usize, 8),
+            end(&self.num_jobs as *const u32 as usize, 4),
+            end(&self.keep_going as *const u8 as usize, 1),
+        ]
+    }
+
+    fn to_verify_options(&self) -> Result<VerifyOptions, ()> {
+        let compiler = match self.compiler {
+            VERIWASM_COMPILER_LUCET => Some(Compiler::Lucet),
+            VERIWASM_COMPILER_WAMR => Some(Compiler::Wamr),
+            VERIWASM_COMPILER_WASMTIME => Some(Compiler::Wasmtime),
+            VERIWASM_COMPILER_DETECT => None,
+            _ => return Err(()),
+        };
+        Ok(VerifyOptions {
+            compiler: compiler,
+            globals_size: self.globals_size,
+            call_table_size: self.call_table_size,
+            num_jobs: self.num_jobs.max(1),
+            keep_going: self.keep_going != 0,
+            ..VerifyOptions::default()
+        })
+    }
+}
+
+struct FunctionStrings {
+    name: CString,
+    rule_id: Option<CString>,
+    reason: Option<CString>,
+}
+
+/// Opaque to C: the report, with its strings kept NUL-terminated for the getters.
+pub struct VeriwasmReport {
+    report: VerificationReport,
+    strings: Vec<FunctionStrings>,
+}
+
+fn c_string(s: &str) -> CString {
+    // an interior NUL can't be passed through, so cut the string there
+    CString::new(s.split('\0').next().unwrap_or("")).unwrap()
+}
+
+impl VeriwasmReport {
+    fn new(report: VerificationReport) -> VeriwasmReport {
+        let strings = report
+            .functions
+            .iter()
+            .map(|f| FunctionStrings {
+                name: c_string(&f.name),
+                rule_id: f.rule_id().map(c_string),
+                reason: f.reason.as_ref().map(|r| c_string(r)),
+            })
+            .collect();
+        VeriwasmReport { report: report, strings: strings }
+    }
+
+    fn function(&self, index: usize) -> Option<(&FunctionResult, &FunctionStrings)> {
+        Some((self.report.functions.get(index)?, self.strings.get(index)?))
+    }
+}
+
+fn guarded<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+fn opt_ptr(s: Option<&CString>) -> *const c_char {
+    s.map_or(ptr::null(), |s| s.as_ptr())
+}
+
+/// Verifies the module at `path`. `options` may be NULL for the defaults. On `VERIWASM_OK` and
+/// `VERIWASM_UNSAFE`, `*report` is set to a report the caller frees with `veriwasm_report_free`;
+/// otherwise it is set to NULL.
+#[no_mangle]
+pub unsafe extern "C" fn veriwasm_verify(path: *const c_char, options: *const VeriwasmOptions, report: *mut *mut VeriwasmReport) -> c_int {
+    guarded(VERIWASM_ERR_INTERNAL, || {
+        if report.is_null() {
+            return VERIWASM_ERR_INVALID_ARGUMENT;
+        }
+        *report = ptr::null_mut();
+        if path.is_null() {
+            return VERIWASM_ERR_INVALID_ARGUMENT;
+        }
+        let path = match CStr::from_ptr(path).to_str() {
+            Ok(path) => path,
+            Err(_) => return VERIWASM_ERR_INVALID_ARGUMENT,
+        };
+        let config = match VeriwasmOptions::read(options).and_then(|o| o.to_verify_options()) {
+            Ok(config) => config,
+            Err(_) => return VERIWASM_ERR_INVALID_ARGUMENT,
+        };
+        let (code, result) = match verify_module(Path::new(path), config) {
+            Ok(result) => (VERIWASM_OK, result),
+            Err(VerifyError::Unsafe(result)) => (VERIWASM_UNSAFE, result),
+            Err(VerifyError::Load(_)) => return VERIWASM_ERR_LOAD,
+            Err(VerifyError::Io(_)) => return VERIWASM_ERR_IO,
+        };
+        *report = Box::into_raw(Box::new(VeriwasmReport::new(result)));
+        code
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn veriwasm_report_function_count(report: *const VeriwasmReport) -> usize {
+    guarded(0, || report.as_ref().map_or(0, |r| r.report.functions.len()))
+}
+
+/// 1 if the function was verified, 0 if it failed, -1 for an index out of range.
+#[no_mangle]
+pub unsafe extern "C" fn veriwasm_report_function_verified(report: *const VeriwasmReport, index: usize) -> c_int {
+    guarded(-1, || match report.as_ref().and_then(|r| r.function(index)) {
+        Some((func, _)) => func.passed() as c_int,
+        None => -1,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn veriwasm_report_function_addr(report: *const VeriwasmReport, index: usize) -> u64 {
+    guarded(0, || report.as_ref().and_then(|r| r.function(index)).map_or(0, |(func, _)| func.addr))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn veriwasm_report_function_name(report: *const VeriwasmReport, index: usize) -> *const c_char {
+    guarded(ptr::null(), || opt_ptr(report.as_ref().and_then(|r| r.function(index)).map(|(_, s)| &s.name)))
+}
+
+/// The property ("cfi", "heap", "stack" or "call") a failing function was rejected by, or NULL if it
+/// passed or its failure isn't down to one property (a panic, or a cached failure).
+#[no_mangle]
+pub unsafe extern "C" fn veriwasm_report_function_rule_id(report: *const VeriwasmReport, index: usize) -> *const c_char {
+    guarded(ptr::null(), || opt_ptr(report.as_ref().and_then(|r| r.function(index)).and_then(|(_, s)| s.rule_id.as_ref())))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn veriwasm_report_function_reason(report: *const VeriwasmReport, index: usize) -> *const c_char {
+    guarded(ptr::null(), || opt_ptr(report.as_ref().and_then(|r| r.function(index)).and_then(|(_, s)| s.reason.as_ref())))
+}
+
+/// The report as the `verification` section of the stats file. The caller frees the string with
+/// `veriwasm_string_free`.
+#[no_mangle]
+pub unsafe extern "C" fn veriwasm_report_to_json(report: *const VeriwasmReport) -> *mut c_char {
+    guarded(ptr::null_mut(), || match report.as_ref() {
+        Some(r) => c_string(&r.report.to_json().to_string()).into_raw(),
+        None => ptr::null_mut(),
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn veriwasm_string_free(s: *mut c_char) {
+    guarded((), || {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn veriwasm_report_free(report: *mut VeriwasmReport) {
+    guarded((), || {
+        if !report.is_null() {
+            drop(Box::from_raw(report));
+        }
+    })
+}
+
+#[test]
+fn ffi_report_test() {
+    use crate::checkers::CheckFailure;
+    use crate::lattices::reachingdefslattice::LocIdx;
+    use crate::utils::ir::Stmt;
+    use crate::utils::verification_report::Property;
+
+    let mut result = VerificationReport::default();
+    result.add(FunctionResult::verified("guest_func_0", 0x1000));
+    let site = CheckFailure {
+        loc: LocIdx { addr: 0x2010, idx: 0 },
+        stmt: Stmt::Ret,
+        description: "stack growth -8 at ret".to_string(),
+        state: String::new(),
+    };
+    result.add(FunctionResult::failed("guest_func_1", 0x2000, Some(Property::Stack), "Not Stack Safe", Some(&site)));
+    result.add(FunctionResult::failed("guest_func_2", 0x3000, None, "Not Heap Safe (cached)", None));
+    let report = Box::into_raw(Box::new(VeriwasmReport::new(result)));
+
+    let string = |s: *const c_char| if s.is_null() { None } else { Some(unsafe { CStr::from_ptr(s) }.to_str().unwrap().to_string()) };
+    unsafe {
+        assert_eq!(veriwasm_report_function_count(report), 3);
+        assert_eq!(veriwasm_report_function_verified(report, 0), 1);
+        assert_eq!(veriwasm_report_function_verified(report, 1), 0);
+        assert_eq!(veriwasm_report_function_verified(report, 3), -1);
+        assert_eq!(veriwasm_report_function_addr(report, 1), 0x2000);
+        assert_eq!(string(veriwasm_report_function_name(report, 1)), Some("guest_func_1".to_string()));
+        assert_eq!(string(veriwasm_report_function_rule_id(report, 0)), None);
+        assert_eq!(string(veriwasm_report_function_rule_id(report, 1)), Some("stack".to_string()));
+        assert_eq!(string(veriwasm_report_function_rule_id(report, 2)), None);
+        assert_eq!(string(veriwasm_report_function_reason(report, 2)), Some("Not Heap Safe (cached)".to_string()));
+        assert!(veriwasm_report_function_name(ptr::null(), 0).is_null());
+
+        let json = veriwasm_report_to_json(report);
+        let parsed: serde_json::Value = serde_json::from_str(&string(json).unwrap()).unwrap();
+        assert_eq!(parsed["failed"], 2);
+        veriwasm_string_free(json);
+        veriwasm_report_free(report);
+
+        let mut out: *mut VeriwasmReport = ptr::null_mut();
+        assert_eq!(veriwasm_verify(ptr::null(), ptr::null(), &mut out), VERIWASM_ERR_INVALID_ARGUMENT);
+        let missing = CString::new("./veriwasm_public_data/missing.so").unwrap();
+        assert_eq!(veriwasm_verify(missing.as_ptr(), ptr::null(), &mut out), VERIWASM_ERR_LOAD);
+        assert!(out.is_null());
+
+        // a caller built against a header with only the first two fields
+        let mut options = VeriwasmOptions::default();
+        options.struct_size = 8;
+        options.compiler = VERIWASM_COMPILER_WAMR;
+        options.num_jobs = 7;
+        let read = VeriwasmOptions::read(&options).unwrap();
+        assert_eq!((read.compiler, read.num_jobs), (VERIWASM_COMPILER_WAMR, 1));
+        options.compiler = 9;
+        assert_eq!(veriwasm_verify(missing.as_ptr(), &options, &mut out), VERIWASM_ERR_INVALID_ARGUMENT);
+    }
+}
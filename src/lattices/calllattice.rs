@@ -3,6 +3,23 @@ use crate::lattices::reachingdefslattice::LocIdx;
 use crate::lattices::{Lattice, VariableState};
 use std::cmp::Ordering;
 
+/// Which of a multi-table Wamr module's funcref tables a descriptor is: the one at a constant
+/// index, or whichever one the descriptor load at a location picked with a checked register.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Debug)]
+pub enum WamrTableId {
+    Const(u32),
+    LoadedAt(LocIdx),
+}
+
+/// What a `cmp` compared a register against: an immediate, the Lucet/Wasmtime table size, or
+/// the element count of a Wamr table descriptor.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Debug)]
+pub enum CheckBound {
+    Imm(u32),
+    TableSize,
+    WamrTableSize(WamrTableId),
+}
+
 #[derive(Clone, PartialEq, Eq, PartialOrd, Debug)]
 pub enum CallCheckValue {
     GuestTableBase,
@@ -11,7 +28,9 @@ pub enum CallCheckValue {
     PtrOffset(DAV),
     FnPtr,
     CheckedVal,
-    CheckFlag(u32, u8),
+    CheckFlag(CheckBound, u8, bool, LocIdx), // bound, checked register, whether it was cmp's first operand, location of the establishing cmp
+    TableEntryPtr,          // GuestTableBase + PtrOffset(Checked), materialized in a register
+    UncheckedTableEntryPtr, // GuestTableBase plus anything else
     
     // Wamr-specific values
     WamrExecEnv,
@@ -19,7 +38,19 @@ pub enum CallCheckValue {
     WamrFuncTypeTable,
     WamrFuncPtrsTable,
     WamrFuncIdx,
-    WamrChecked(u32)
+    WamrChecked(u32),
+    // multiple funcref tables (see WamrTables): a descriptor, its element count and function
+    // pointers, and an index checked against that count. Only values of the same table pair up.
+    WamrTableDescriptor(WamrTableId),
+    WamrTableSize(WamrTableId),
+    WamrTableFuncPtrs(WamrTableId),
+    WamrTableChecked(WamrTableId),
+
+    // Wasmtime-specific values (the table size, checked index and function pointer are shared
+    // with Lucet)
+    WasmtimeVMContext,
+    WasmtimeTableBase,
+    WasmtimeAnyfunc,
 }
 
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -43,6 +74,20 @@ impl CallCheckValueLattice {
     }
 }
 
+impl CallCheckValue {
+    /// The table a value of a multi-table Wamr module is about.
+    pub fn wamr_table(&self) -> Option<WamrTableId> {
+        match self {
+            CallCheckValue::WamrTableDescriptor(table)
+            | CallCheckValue::WamrTableSize(table)
+            | CallCheckValue::WamrTableFuncPtrs(table)
+            | CallCheckValue::WamrTableChecked(table) => Some(*table),
+            CallCheckValue::CheckFlag(CheckBound::WamrTableSize(table), _, _, _) => Some(*table),
+            _ => None,
+        }
+    }
+}
+
 impl Lattice for CallCheckValueLattice {
     fn meet(&self, other: &Self, loc_idx: &LocIdx) -> Self {
         if self.v.clone() == other.v {
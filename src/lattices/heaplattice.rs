@@ -1,22 +1,39 @@
-use crate::lattices::{ConstLattice, VariableState};
+use crate::lattices::reachingdefslattice::LocIdx;
+use crate::lattices::{ConstLattice, ConstMeet, VariableState};
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum HeapValue {
     HeapBase,
     GlobalsBase,
-    Bounded4GB,
-    Bounded256B,
+    Bounded(u64),       // an unsigned value below the given bound, e.g. Bounded(2) for a SETcc result
 
     // Lucet-specific values
     LucetTables,
     GuestTable0,
+    GuestTable0Entry,   // guest_table_0 plus the offset of an entry, from an index checked against the table size
+    LucetTableSize,     // the number of entries in guest_table_0, loaded from lucet_tables + 8
+    TableSizeCheck(u8, bool, LocIdx), // in zf: the cmp at the LocIdx of a register (first operand or not) with LucetTableSize
+    CheckedTableIndex,  // an index below the table size, on the in-bounds side of a check
+    CheckedTableOffset, // a CheckedTableIndex scaled by the entry size (shl 4)
+    GlobalsPtr(i64),    // a pointer into the globals area, at the given offset from GlobalsBase
+    SafePage,           // the page a Spectre-mitigated heap access selects when its bounds check fails
+    HeapBaseOrSafePage, // either of HeapBase and SafePage, as picked by the mitigation's cmov
 
     // Wamr-specific values
     WamrExecEnv,        // the value pointed to by %rdi at the beginning of Wamr AOT functions
     WamrModuleInstance, // Wamr allocates one of these per module, which contains a pointer to linear memory
     WamrFuncTypeTable,  // a pointer to a module's function type table
     WamrFuncPtrsTable,  // a pointer to a module's function pointer table
+    WamrTableDescriptor, // a pointer to the descriptor of one of a module's funcref tables (see WamrTables)
     WamrStackLimit,     // a pointer to the end of the stack, which is accessed sometimes to prevent overflow in native functions
+    WamrMemBound(u32),  // the memory bound of a ModuleInstance for accesses of the given width in bytes, loaded to check heap offsets against (--wamr-bounds-checks)
+    CheckedHeapOffset(u32), // an offset accesses of at most the given width are in bounds at, on the in-bounds side of a check
+    WamrMemBoundCheck(u8, bool, u32, LocIdx), // in zf: the cmp at the LocIdx of a register (first operand or not) with WamrMemBound of the given width
+
+    // Wasmtime-specific values
+    WasmtimeVMContext,  // the value pointed to by %rdi at the beginning of Wasmtime functions
+    WasmtimeTableBase,  // the base of the function table, an array of pointers to anyfuncs
+    WasmtimeAnyfunc,    // a pointer to a VMCallerCheckedAnyfunc, loaded from the function table
 }
 
 // Wamr-specific constants
@@ -24,6 +41,7 @@ pub const WAMR_MODULEINSTANCE_OFFSET: i64 = 0x10;   // the offset of the current
 pub const WAMR_STACKLIMIT_OFFSET: i64 = 0x18;       // the offset of the stack limit w/n a Wamr ExecEnv
 pub const WAMR_HEAPBASE_OFFSET: i64 = 0x150;        // the offset of the linear memory region base w/n a Wamr ModuleInstance
 pub const WAMR_EXCEPTION_OFFSET: i64 = 0x68;        // the offset of the current exception w/n a Wamr ModuleInstance
+pub const WAMR_EXCEPTION_SIZE: i64 = 128;           // the size of the exception message buffer at WAMR_EXCEPTION_OFFSET
 pub const WAMR_MEMBOUNDS_OFFSET: i64 = 0x1a0;       // the offset of the memory bound w/n a Wamr ModuleInstance
 pub const WAMR_GLOBALS_OFFSET: i64 = 0x1a8;         // the offset of global variables w/n a Wamr ModuleInstance
 pub const WAMR_FUNCPTRS_OFFSET: i64 = 0x28;         // the offset of function pointer table w/n a Wamr ModuleInstance
@@ -31,6 +49,55 @@ pub const WAMR_FUNCTYPE_OFFSET: i64 = 0x30;         // the offset of function ty
 pub const WAMR_PAGECNT_OFFSET: i64 = 0x144;         // the offset of the current page count w/n a Wamr ModuleInstance 
                                                     // (needed to call wasm_runtime_enlarge_memory)
 
+#[allow(non_upper_case_globals)]
+impl HeapValue {
+    // the bounds the analyses started out with, kept by name
+    pub const Bounded4GB: HeapValue = HeapValue::Bounded(1 << 32);
+    pub const Bounded256B: HeapValue = HeapValue::Bounded(256);
+
+    /// The bound of a Bounded value.
+    pub fn bound(&self) -> Option<u64> {
+        match self {
+            HeapValue::Bounded(bound) => Some(*bound),
+            _ => None,
+        }
+    }
+
+    /// Bounded by at most `limit`, i.e. at least as tight as Bounded(limit).
+    pub fn bounded_by(&self, limit: u64) -> bool {
+        self.bound().map_or(false, |bound| bound <= limit)
+    }
+
+    /// The heap base, or the safe page Lucet's Spectre mitigation swaps it for when the bounds
+    /// check fails. Only the first page past either is safe to access through both.
+    pub fn is_heap_base_or_safe_page(&self) -> bool {
+        match self {
+            HeapValue::HeapBase | HeapValue::SafePage | HeapValue::HeapBaseOrSafePage => true,
+            _ => false,
+        }
+    }
+}
+
+// Different bounds meet at the looser one, rounded up to a power of two so that a bound growing
+// around a loop (e.g. a counter) reaches 4GB in a few dozen iterations rather than billions.
+// Nothing looser than 4GB is tracked. The heap base and the safe page meet at either of them.
+impl ConstMeet for HeapValue {
+    fn meet_values(&self, other: &Self) -> Option<Self> {
+        match (self, other) {
+            (x, y) if x.is_heap_base_or_safe_page() && y.is_heap_base_or_safe_page() => Some(HeapValue::HeapBaseOrSafePage),
+            (HeapValue::Bounded(n), HeapValue::Bounded(m)) => {
+                let bound = n.max(m).checked_next_power_of_two()?;
+                if bound <= 1 << 32 {
+                    Some(HeapValue::Bounded(bound))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
 pub type HeapValueLattice = ConstLattice<HeapValue>;
 
 pub type HeapLattice = VariableState<HeapValueLattice>;
@@ -83,3 +150,61 @@ fn heap_lattice_test() {
         true
     );
 }
+
+#[test]
+fn heap_bounded_lattice_test() {
+    use crate::lattices::reachingdefslattice::LocIdx;
+    use crate::lattices::Lattice;
+
+    let loc = LocIdx { addr: 0, idx: 0 };
+    let two = HeapValueLattice::new(HeapValue::Bounded(2));
+    let byte = HeapValueLattice::new(HeapValue::Bounded256B);
+    let word = HeapValueLattice::new(HeapValue::Bounded4GB);
+    assert_eq!(HeapValue::Bounded256B, HeapValue::Bounded(256));
+
+    // the looser bound is the meet, and lower in the lattice
+    assert_eq!(two.meet(&byte, &loc), byte);
+    assert_eq!(word.meet(&two, &loc), word);
+    assert!(byte < two && word < byte);
+    assert!(!(two < byte));
+    assert_eq!(two.meet(&HeapValueLattice::new(HeapValue::HeapBase), &loc), HeapValueLattice::default());
+    assert_eq!(two.partial_cmp(&HeapValueLattice::new(HeapValue::HeapBase)), None);
+
+    // bounds are widened to a power of two, and incomparable unless one is that power of two
+    let (three, five) = (HeapValueLattice::new(HeapValue::Bounded(3)), HeapValueLattice::new(HeapValue::Bounded(5)));
+    assert_eq!(three.meet(&five, &loc), HeapValueLattice::new(HeapValue::Bounded(8)));
+    assert_eq!(three.partial_cmp(&five), None);
+    assert!(HeapValueLattice::new(HeapValue::Bounded(8)) < five);
+    // a bound above 4GB isn't kept
+    let huge = HeapValueLattice::new(HeapValue::Bounded((1 << 32) + 1));
+    assert_eq!(two.meet(&huge, &loc), HeapValueLattice::default());
+    assert_eq!(word.partial_cmp(&huge), None);
+
+    assert!(HeapValue::Bounded(2).bounded_by(256));
+    assert!(!HeapValue::Bounded4GB.bounded_by(256));
+    assert!(!HeapValue::HeapBase.bounded_by(256));
+}
+
+#[test]
+fn safe_page_lattice_test() {
+    use crate::lattices::reachingdefslattice::LocIdx;
+    use crate::lattices::Lattice;
+
+    let loc = LocIdx { addr: 0, idx: 0 };
+    let base = HeapValueLattice::new(HeapValue::HeapBase);
+    let page = HeapValueLattice::new(HeapValue::SafePage);
+    let either = HeapValueLattice::new(HeapValue::HeapBaseOrSafePage);
+
+    // the heap base and the safe page meet at either, which is below both
+    assert_eq!(base.meet(&page, &loc), either);
+    assert_eq!(page.meet(&base, &loc), either);
+    assert_eq!(either.meet(&base, &loc), either);
+    assert!(either < base && either < page);
+    assert_eq!(base.partial_cmp(&page), None);
+    // and anything else is unknown
+    assert_eq!(either.meet(&HeapValueLattice::new(HeapValue::Bounded4GB), &loc), HeapValueLattice::default());
+    assert_eq!(page.meet(&HeapValueLattice::new(HeapValue::GlobalsBase), &loc), HeapValueLattice::default());
+
+    assert!(HeapValue::HeapBaseOrSafePage.is_heap_base_or_safe_page());
+    assert!(!HeapValue::GlobalsBase.is_heap_base_or_safe_page());
+}
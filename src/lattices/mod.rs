@@ -6,12 +6,13 @@ pub mod regslattice;
 pub mod stackgrowthlattice;
 pub mod stacklattice;
 pub mod switchlattice;
+pub mod taintlattice;
 use crate::utils::ir_utils::{get_imm_offset, is_rsp};
 use crate::lattices::reachingdefslattice::LocIdx;
 use crate::lattices::regslattice::X86RegsLattice;
 use crate::lattices::stacklattice::StackLattice;
-use crate::utils::lifter::{Binopcode, MemArg, MemArgs, ValSize, Value};
-use crate::utils::utils::Compiler;
+use crate::utils::ir::{Binopcode, MemArg, MemArgs, ValSize, Value};
+use crate::utils::compiler::Compiler;
 use std::cmp::Ordering;
 use std::fmt::Debug;
 
@@ -59,14 +60,35 @@ impl Default for BooleanLattice {
     }
 }
 
+impl BooleanLattice {
+    pub fn new(v: bool) -> Self {
+        BooleanLattice { v: v }
+    }
+
+    pub fn is_true(&self) -> bool {
+        self.v
+    }
+}
+
 pub type Constu32Lattice = ConstLattice<u32>;
 
+/// How two different known values of a `ConstLattice` meet. By default they don't, and the
+/// meet is bottom; a value type with a coarser common value (e.g. the looser of two bounds)
+/// returns it instead.
+pub trait ConstMeet: Sized {
+    fn meet_values(&self, _other: &Self) -> Option<Self> {
+        None
+    }
+}
+
+impl ConstMeet for u32 {}
+
 #[derive(Eq, Clone, Debug)]
 pub struct ConstLattice<T: Eq + Clone + Debug> {
     pub v: Option<T>,
 }
 
-impl<T: Eq + Clone + Debug> PartialOrd for ConstLattice<T> {
+impl<T: Eq + Clone + Debug + ConstMeet> PartialOrd for ConstLattice<T> {
     fn partial_cmp(&self, other: &ConstLattice<T>) -> Option<Ordering> {
         match (self.v.as_ref(), other.v.as_ref()) {
             (None, None) => Some(Ordering::Equal),
@@ -74,9 +96,12 @@ impl<T: Eq + Clone + Debug> PartialOrd for ConstLattice<T> {
             (_, None) => Some(Ordering::Greater),
             (Some(x), Some(y)) => {
                 if x == y {
-                    Some(Ordering::Equal)
-                } else {
-                    None
+                    return Some(Ordering::Equal);
+                }
+                match x.meet_values(y) {
+                    Some(ref m) if m == x => Some(Ordering::Less),
+                    Some(ref m) if m == y => Some(Ordering::Greater),
+                    _ => None,
                 }
             }
         }
@@ -89,12 +114,12 @@ impl<T: Eq + Clone + Debug> PartialEq for ConstLattice<T> {
     }
 }
 
-impl<T: Eq + Clone + Debug> Lattice for ConstLattice<T> {
+impl<T: Eq + Clone + Debug + ConstMeet> Lattice for ConstLattice<T> {
     fn meet(&self, other: &Self, _loc_idx: &LocIdx) -> Self {
-        if self.v == other.v {
-            ConstLattice { v: self.v.clone() }
-        } else {
-            ConstLattice { v: None }
+        match (&self.v, &other.v) {
+            (Some(x), Some(y)) if x == y => ConstLattice { v: Some(x.clone()) },
+            (Some(x), Some(y)) => ConstLattice { v: x.meet_values(y) },
+            _ => ConstLattice { v: None },
         }
     }
 }
@@ -126,28 +151,62 @@ impl<T: Lattice + Clone> Lattice for VariableState<T> {
     }
 }
 
+impl<T: Lattice + Clone> VariableState<T> {
+    /// Resets every general purpose register and stack slot holding a `stale` value to bottom.
+    pub fn forget(&mut self, stale: impl Fn(&T) -> bool) {
+        for regnum in 0..16 {
+            if stale(&self.regs.get(&regnum, &ValSize::Size64)) {
+                self.regs.set(&regnum, &ValSize::Size64, Default::default());
+            }
+        }
+        self.stack.map.retain(|_, slot| !stale(&slot.value));
+    }
+
+    /// Resets the stack slots a callee may write: the `caller_window` bytes above the return
+    /// address a call pushes (see CompilerMetadata::caller_window).
+    pub fn forget_caller_window(&mut self, caller_window: i64) {
+        self.stack.forget_range(0, caller_window);
+    }
+}
+
+// Where a memory operand is on the stack: at a known offset from rsp, or anywhere, when rsp is
+// indexed by a register
+enum StackAddr {
+    Offset(i64),
+    Unknown,
+}
+
+// The stack address of `memargs`, or None if rsp isn't its base
+fn stack_addr(memargs: &MemArgs) -> Option<StackAddr> {
+    match memargs {
+        MemArgs::Mem1Arg(MemArg::Reg(4, _)) => Some(StackAddr::Offset(0)),
+        MemArgs::Mem2Args(MemArg::Reg(4, _), MemArg::Imm(_, _, offset)) => Some(StackAddr::Offset(*offset)),
+        MemArgs::Mem3Args(MemArg::Reg(4, _), MemArg::Imm(_, _, offset1), MemArg::Imm(_, _, offset2)) => {
+            Some(StackAddr::Offset(offset1 + offset2))
+        }
+        MemArgs::MemScale(MemArg::Reg(4, _), MemArg::Imm(_, _, index), MemArg::Imm(_, _, scale)) => {
+            Some(StackAddr::Offset(index * scale))
+        }
+        MemArgs::MemScaleDisp(MemArg::Reg(4, _), MemArg::Imm(_, _, index), MemArg::Imm(_, _, scale), MemArg::Imm(_, _, disp)) => {
+            Some(StackAddr::Offset(index * scale + disp))
+        }
+        MemArgs::Mem2Args(MemArg::Reg(4, _), _)
+        | MemArgs::Mem3Args(MemArg::Reg(4, _), _, _)
+        | MemArgs::MemScale(MemArg::Reg(4, _), _, _)
+        | MemArgs::MemScaleDisp(MemArg::Reg(4, _), _, _, _) => Some(StackAddr::Unknown),
+        _ => None,
+    }
+}
+
 impl<T: Lattice + Clone> VarState for VariableState<T> {
     type Var = T;
     fn set(&mut self, index: &Value, value: T) -> () {
         match index {
-            Value::Mem(memsize, memargs) => match memargs {
-                MemArgs::Mem1Arg(arg) => {
-                    if let MemArg::Reg(regnum, _) = arg {
-                        if *regnum == 4 {
-                            self.stack.update(0, value, memsize.to_u32() / 8)
-                        }
-                    }
-                }
-                MemArgs::Mem2Args(arg1, arg2) => {
-                    if let MemArg::Reg(regnum, _) = arg1 {
-                        if *regnum == 4 {
-                            if let MemArg::Imm(_, _, offset) = arg2 {
-                                self.stack.update(*offset, value, memsize.to_u32() / 8)
-                            }
-                        }
-                    }
-                }
-                _ => (),
+            Value::Mem(memsize, memargs) => match stack_addr(memargs) {
+                Some(StackAddr::Offset(offset)) => self.stack.update(offset, value, memsize.to_u32() / 8),
+                // the write may be to any slot
+                Some(StackAddr::Unknown) => self.stack.map.clear(),
+                None => (),
             },
             Value::Reg(regnum, s2) => {
                 if let ValSize::SizeOther = s2 {
@@ -161,26 +220,10 @@ impl<T: Lattice + Clone> VarState for VariableState<T> {
 
     fn get(&mut self, index: &Value) -> Option<T> {
         match index {
-            Value::Mem(memsize, memargs) => match memargs {
-                MemArgs::Mem1Arg(arg) => {
-                    if let MemArg::Reg(regnum, _) = arg {
-                        if *regnum == 4 {
-                            return Some(self.stack.get(0, memsize.to_u32() / 8));
-                        }
-                    }
-                    None
-                }
-                MemArgs::Mem2Args(arg1, arg2) => {
-                    if let MemArg::Reg(regnum, _) = arg1 {
-                        if *regnum == 4 {
-                            if let MemArg::Imm(_, _, offset) = arg2 {
-                                return Some(self.stack.get(*offset, memsize.to_u32() / 8));
-                            }
-                        }
-                    }
-                    None
-                }
-                _ => None,
+            Value::Mem(memsize, memargs) => match stack_addr(memargs) {
+                Some(StackAddr::Offset(offset)) => Some(self.stack.get(offset, memsize.to_u32() / 8)),
+                Some(StackAddr::Unknown) => Some(Default::default()),
+                None => None,
             },
             Value::Reg(regnum, s2) => Some(self.regs.get(regnum, s2)),
             Value::Imm(_, _, _) => None,
@@ -193,8 +236,9 @@ impl<T: Lattice + Clone> VarState for VariableState<T> {
 
     fn on_call(&mut self, compiler: Compiler) {
         match compiler {
-            // Lucet doesn't make any assumptions about register state after calls.
-            Compiler::Lucet => self.regs.clear_regs(),
+            // Lucet (and Wasmtime, also compiled by Cranelift) doesn't make any assumptions about
+            // register state after calls.
+            Compiler::Lucet | Compiler::Wasmtime => self.regs.clear_regs(),
             // Wamr (LLVM) uses a standard calling convention, which assumes that some registers
             // are preserved by the callee.
             Compiler::Wamr => self.regs.clear_regs_systemv(),
@@ -203,16 +247,13 @@ impl<T: Lattice + Clone> VarState for VariableState<T> {
 
     fn adjust_stack_offset(&mut self, opcode: &Binopcode, dst: &Value, src1: &Value, src2: &Value) {
         if is_rsp(dst) {
-            if is_rsp(src1) {
-                let adjustment = get_imm_offset(src2);
-                //println!("opcode = {:?} {:?} = {:?} {:?} {:?}", opcode, dst, src1, src2, adjustment);
-                match opcode {
-                    Binopcode::Add => self.stack.update_stack_offset(adjustment),
-                    Binopcode::Sub => self.stack.update_stack_offset(-adjustment),
-                    _ => panic!("Illegal RSP write"),
-                }
-            } else {
-                panic!("Illegal RSP write")
+            //println!("opcode = {:?} {:?} = {:?} {:?}", opcode, dst, src1, src2);
+            match (opcode, is_rsp(src1), get_imm_offset(src2)) {
+                (Binopcode::Add, true, Some(offset)) => self.stack.update_stack_offset(offset),
+                (Binopcode::Sub, true, Some(offset)) => self.stack.update_stack_offset(-offset),
+                // after any other write (aligning rsp, `sub rsp, rax`, ...) it's unknown where the
+                // slots are relative to it; the stack checker decides whether the write is allowed
+                _ => self.stack.map.clear(),
             }
         }
     }
@@ -249,3 +290,71 @@ fn u32_lattice_test() {
     assert_eq!(z1 == x1, false);
     assert_eq!(x1.lt(&y1), false);
 }
+
+#[test]
+fn rsp_alignment_clears_stack_test() {
+    use crate::utils::ir::ImmType;
+
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let slot = Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size32, 8)));
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size8, v);
+    let mut state: VariableState<BooleanLattice> = Default::default();
+    state.set(&slot, BooleanLattice { v: true });
+    state.adjust_stack_offset(&Binopcode::Sub, &rsp, &rsp, &imm(16));
+    assert_eq!(state.stack.offset, -16);
+    assert_eq!(state.stack.map.len(), 1);
+
+    // and rsp, -32: the slot can't be found relative to rsp anymore
+    state.adjust_stack_offset(&Binopcode::And, &rsp, &rsp, &imm(-32));
+    assert_eq!(state.stack.offset, -16);
+    assert!(state.stack.map.is_empty());
+
+    // sub rsp, rax: likewise, without an immediate to adjust by
+    state.set(&slot, BooleanLattice { v: true });
+    state.adjust_stack_offset(&Binopcode::Sub, &rsp, &rsp, &Value::Reg(0, ValSize::Size64));
+    assert_eq!(state.stack.offset, -16);
+    assert!(state.stack.map.is_empty());
+}
+
+#[test]
+fn stack_addressing_test() {
+    use crate::utils::ir::ImmType;
+
+    let rsp = MemArg::Reg(4, ValSize::Size64);
+    let rax = MemArg::Reg(0, ValSize::Size64);
+    let imm = |v: i64| MemArg::Imm(ImmType::Signed, ValSize::Size32, v);
+    let mem = |memargs: MemArgs| Value::Mem(ValSize::Size64, memargs);
+    let val = |v: u32| ConstLattice::<u32>::new(v);
+    let mut state: VariableState<ConstLattice<u32>> = Default::default();
+
+    // the same slot, [rsp+16], however it is addressed
+    state.set(&mem(MemArgs::Mem2Args(rsp.clone(), imm(16))), val(1));
+    assert_eq!(state.get(&mem(MemArgs::Mem3Args(rsp.clone(), imm(8), imm(8)))), Some(val(1)));
+    assert_eq!(state.get(&mem(MemArgs::MemScale(rsp.clone(), imm(2), imm(8)))), Some(val(1)));
+    assert_eq!(state.get(&mem(MemArgs::MemScaleDisp(rsp.clone(), imm(1), imm(8), imm(8)))), Some(val(1)));
+    state.set(&mem(MemArgs::MemScaleDisp(rsp.clone(), imm(2), imm(4), imm(8))), val(2));
+    assert_eq!(state.get(&mem(MemArgs::Mem2Args(rsp.clone(), imm(16)))), Some(val(2)));
+    state.set(&mem(MemArgs::Mem1Arg(rsp.clone())), val(3));
+    assert_eq!(state.get(&mem(MemArgs::Mem3Args(rsp.clone(), imm(-8), imm(8)))), Some(val(3)));
+
+    // indexed by a register, the access may be to any slot: nothing is known of what it reads,
+    // and a write forgets every slot
+    let indexed = vec![
+        MemArgs::Mem2Args(rsp.clone(), rax.clone()),
+        MemArgs::Mem3Args(rsp.clone(), rax.clone(), imm(8)),
+        MemArgs::MemScale(rsp.clone(), rax.clone(), imm(8)),
+        MemArgs::MemScaleDisp(rsp.clone(), rax.clone(), imm(8), imm(8)),
+    ];
+    for memargs in indexed {
+        assert_eq!(state.get(&mem(memargs.clone())), Some(Default::default()));
+        let mut written = state.clone();
+        written.set(&mem(memargs), val(4));
+        assert!(written.stack.map.is_empty());
+        assert_eq!(written.get(&mem(MemArgs::Mem2Args(rsp.clone(), imm(16)))), Some(Default::default()));
+    }
+
+    // not on the stack
+    assert_eq!(state.get(&mem(MemArgs::Mem3Args(rax.clone(), MemArg::Reg(1, ValSize::Size64), imm(8)))), None);
+    state.set(&mem(MemArgs::MemScale(rax, imm(0), imm(8))), val(5));
+    assert_eq!(state.stack.map.len(), 2);
+}
@@ -1,14 +1,48 @@
-use crate::lattices::{ConstLattice, VarState};
-use crate::utils::lifter::{Binopcode, Value};
-use crate::utils::utils::Compiler;
+use crate::lattices::reachingdefslattice::LocIdx;
+use crate::lattices::{ConstLattice, ConstMeet, Lattice, VarState};
+use crate::utils::ir::{Binopcode, Value};
+use crate::utils::compiler::Compiler;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
-pub type StackGrowthLattice = ConstLattice<(i64, i64, HashMap<u8, i64>)>;
+// stackgrowth, probestack, the offsets callee-saved registers were saved at (Wamr), whether
+// the path here raised an exception in the ModuleInstance (Wamr), the stackgrowth %rbp was
+// set to if it is a frame pointer (`mov rbp, rsp`), which `mov rsp, rbp` (`leave`) restores, the
+// lowest stackgrowth a check against the stack limit showed to be above it (Wamr), and whether the
+// path here has allocated a frame, i.e. moved rsp below where it was at entry, freed since or not
+pub type StackGrowthLattice = ConstLattice<(i64, i64, HashMap<u8, i64>, bool, Option<i64>, Option<i64>, bool)>;
+
+impl ConstMeet for (i64, i64, HashMap<u8, i64>, bool, Option<i64>, Option<i64>, bool) {
+    // paths that only disagree on the frame pointer meet without it, paths that checked the
+    // stack limit down to different stackgrowths have both checked it down to the higher one, and
+    // a frame allocated on either path is taken to be allocated
+    fn meet_values(&self, other: &Self) -> Option<Self> {
+        let (stackgrowth, probestack, saved, raised, frame_pointer, checked, allocated) = self;
+        if (stackgrowth, probestack, saved, raised) != (&other.0, &other.1, &other.2, &other.3) {
+            return None;
+        }
+        let frame_pointer = if *frame_pointer == other.4 { *frame_pointer } else { None };
+        let checked = match (checked, other.5) {
+            (Some(x), Some(y)) => Some(std::cmp::max(*x, y)),
+            _ => None,
+        };
+        Some((*stackgrowth, *probestack, saved.clone(), *raised, frame_pointer, checked, *allocated || other.6))
+    }
+}
 
 // Wamr stack memory constants
 pub const WAMR_STACK_UPPER_BOUND: i64 = 4096;
 pub const WAMR_GUARD_PAGE_COUNT: i64 = 3;
 pub const WAMR_STACK_LOWER_BOUND: i64 = -4096 * WAMR_GUARD_PAGE_COUNT;
+// Argument spill area the caller provides above the return address
+pub const WAMR_CALLER_WINDOW: i64 = 32;
+
+/// The lowest stackgrowth a Wamr function may access at: the guard pages below the stack limit
+/// protect the frame down to WAMR_STACK_LOWER_BOUND below where rsp was last checked against the
+/// limit, or below the function's entry.
+pub fn wamr_stack_lower_bound(checked: Option<i64>) -> i64 {
+    std::cmp::min(0, checked.unwrap_or(0)) + WAMR_STACK_LOWER_BOUND
+}
 
 impl VarState for StackGrowthLattice {
     type Var = i64;
@@ -38,35 +72,99 @@ impl VarState for StackGrowthLattice {
 impl StackGrowthLattice {
     pub fn get_stackgrowth(&self) -> Option<i64> {
         match self.v {
-            Some((stackgrowth, _, _)) => Some(stackgrowth),
+            Some((stackgrowth, _, _, _, _, _, _)) => Some(stackgrowth),
             None => None,
         }
     }
 
     pub fn get_probestack(&self) -> Option<i64> {
         match self.v {
-            Some((_, probestack, _)) => Some(probestack),
+            Some((_, probestack, _, _, _, _, _)) => Some(probestack),
+            None => None,
+        }
+    }
+
+    /// The stackgrowth %rbp holds, if it is a frame pointer.
+    pub fn get_frame_pointer(&self) -> Option<i64> {
+        match self.v {
+            Some((_, _, _, _, frame_pointer, _, _)) => frame_pointer,
+            None => None,
+        }
+    }
+
+    /// The lowest stackgrowth rsp was checked to be above the stack limit at, on every path here.
+    pub fn get_checked_limit(&self) -> Option<i64> {
+        match self.v {
+            Some((_, _, _, _, _, checked, _)) => checked,
             None => None,
         }
     }
 
     pub fn clear(&mut self) -> () {
         match self.v {
-            Some(_) => self.v = Some((0, 4096, HashMap::new())),
+            Some(_) => self.v = Some((0, 4096, HashMap::new(), false, None, None, true)),
             None => {},
         }
     }
 }
 
+/// Whether %rsp still points into the frame under analysis: `lost_at` is None while the stack
+/// analysis follows it, and otherwise the address of an instruction after which it didn't (a write
+/// to %rsp it couldn't follow, or where paths with different stack states met).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RspTracking {
+    pub lost_at: Option<u64>,
+}
+
+impl PartialOrd for RspTracking {
+    fn partial_cmp(&self, other: &RspTracking) -> Option<Ordering> {
+        match (self.lost_at, other.lost_at) {
+            (None, None) => Some(Ordering::Equal),
+            (None, Some(_)) => Some(Ordering::Greater),
+            (Some(_), None) => Some(Ordering::Less),
+            (Some(x), Some(y)) if x == y => Some(Ordering::Equal),
+            (Some(_), Some(_)) => None,
+        }
+    }
+}
+
+impl Lattice for RspTracking {
+    // a path that lost track of rsp loses it for good; of two, report the lower address
+    fn meet(&self, other: &Self, _loc: &LocIdx) -> Self {
+        let lost_at = match (self.lost_at, other.lost_at) {
+            (Some(x), Some(y)) => Some(std::cmp::min(x, y)),
+            (x, y) => x.or(y),
+        };
+        RspTracking { lost_at: lost_at }
+    }
+}
+
+// RspTracking holds no variables, and nothing but a write to rsp (see `StackAnalyzer::loses_rsp`)
+// changes it: a call returns with rsp where it was
+impl VarState for RspTracking {
+    type Var = u64;
+    fn get(&mut self, _index: &Value) -> Option<Self::Var> {
+        None
+    }
+    fn set(&mut self, _index: &Value, _v: Self::Var) -> () {}
+    fn set_to_bot(&mut self, _index: &Value) -> () {}
+    fn on_call(&mut self, _compiler: Compiler) -> () {}
+    fn adjust_stack_offset(
+        &mut self,
+        _opcode: &Binopcode,
+        _dst: &Value,
+        _src1: &Value,
+        _src2: &Value,
+    ) {
+    }
+}
+
 #[test]
 fn stack_growth_lattice_test() {
-    use crate::lattices::reachingdefslattice::LocIdx;
-    use crate::lattices::Lattice;
-
     let x1 = StackGrowthLattice { v: None };
-    let x2 = StackGrowthLattice { v: Some((1, 4096)) };
-    let x3 = StackGrowthLattice { v: Some((1, 4096)) };
-    let x4 = StackGrowthLattice { v: Some((2, 4096)) };
+    let x2 = StackGrowthLattice { v: Some((1, 4096, HashMap::new(), false, None, None, false)) };
+    let x3 = StackGrowthLattice { v: Some((1, 4096, HashMap::new(), false, None, None, false)) };
+    let x4 = StackGrowthLattice { v: Some((2, 4096, HashMap::new(), false, None, None, false)) };
 
     assert_eq!(x1 == x2, false);
     assert_eq!(x2 == x3, true);
@@ -89,7 +187,7 @@ fn stack_growth_lattice_test() {
         true
     );
     assert_eq!(
-        x2.meet(&x3, &LocIdx { addr: 0, idx: 0 }) == StackGrowthLattice { v: Some((1, 4096)) },
+        x2.meet(&x3, &LocIdx { addr: 0, idx: 0 }) == StackGrowthLattice { v: Some((1, 4096, HashMap::new(), false, None, None, false)) },
         true
     );
     assert_eq!(
@@ -97,3 +195,63 @@ fn stack_growth_lattice_test() {
         true
     );
 }
+
+#[test]
+fn frame_pointer_meet_test() {
+    let loc = LocIdx { addr: 0, idx: 0 };
+    let framed = StackGrowthLattice { v: Some((-16, 4096, HashMap::new(), false, Some(-8), None, false)) };
+    let unframed = StackGrowthLattice { v: Some((-16, 4096, HashMap::new(), false, None, None, false)) };
+    let elsewhere = StackGrowthLattice { v: Some((-24, 4096, HashMap::new(), false, Some(-8), None, false)) };
+
+    // losing the frame pointer keeps the rest of the state
+    assert_eq!(framed.meet(&unframed, &loc), unframed);
+    assert_eq!(framed.meet(&StackGrowthLattice { v: Some((-16, 4096, HashMap::new(), false, Some(-16), None, false)) }, &loc), unframed);
+    assert!(unframed < framed);
+    assert_eq!(framed.meet(&elsewhere, &loc), StackGrowthLattice { v: None });
+    assert_eq!(unframed.get_frame_pointer(), None);
+    assert_eq!(framed.get_frame_pointer(), Some(-8));
+}
+
+#[test]
+fn checked_limit_meet_test() {
+    let loc = LocIdx { addr: 0, idx: 0 };
+    let checked = |limit: Option<i64>| StackGrowthLattice { v: Some((-16, 4096, HashMap::new(), false, None, limit, false)) };
+
+    // both paths checked down to the higher stackgrowth, and an unchecked path checked nothing
+    assert_eq!(checked(Some(-0x8000)).meet(&checked(Some(-0x100)), &loc), checked(Some(-0x100)));
+    assert_eq!(checked(Some(-0x100)).meet(&checked(Some(-0x8000)), &loc), checked(Some(-0x100)));
+    assert_eq!(checked(Some(-0x100)).meet(&checked(None), &loc), checked(None));
+    assert!(checked(None) < checked(Some(-0x100)));
+    assert!(checked(Some(-0x100)) < checked(Some(-0x8000)));
+
+    assert_eq!(wamr_stack_lower_bound(None), WAMR_STACK_LOWER_BOUND);
+    assert_eq!(wamr_stack_lower_bound(Some(0x10)), WAMR_STACK_LOWER_BOUND);
+    assert_eq!(wamr_stack_lower_bound(Some(-0x8000)), -0x8000 + WAMR_STACK_LOWER_BOUND);
+}
+
+#[test]
+fn allocated_meet_test() {
+    let loc = LocIdx { addr: 0, idx: 0 };
+    let state = |allocated: bool| StackGrowthLattice { v: Some((0, 4096, HashMap::new(), false, None, None, allocated)) };
+
+    // a frame allocated (and freed) on either path has been allocated where they meet
+    assert_eq!(state(true).meet(&state(false), &loc), state(true));
+    assert_eq!(state(false).meet(&state(true), &loc), state(true));
+    assert!(state(true) < state(false));
+}
+
+
+#[test]
+fn rsp_tracking_meet_test() {
+    let loc = LocIdx { addr: 0, idx: 0 };
+    let tracked = RspTracking::default();
+    let lost = RspTracking { lost_at: Some(0x20) };
+    let lost_earlier = RspTracking { lost_at: Some(0x10) };
+
+    assert_eq!(tracked.meet(&tracked, &loc), tracked);
+    assert_eq!(tracked.meet(&lost, &loc), lost);
+    assert_eq!(lost.meet(&tracked, &loc), lost);
+    assert_eq!(lost.meet(&lost_earlier, &loc), lost_earlier);
+    assert!(lost < tracked);
+    assert_eq!(lost.partial_cmp(&lost_earlier), None);
+}
@@ -80,6 +80,12 @@ impl<T: Lattice + Clone> StackLattice<T> {
         }
     }
 
+    /// Removes every slot overlapping [rsp + start, rsp + end).
+    pub fn forget_range(&mut self, start: i64, end: i64) {
+        let offset = self.offset;
+        self.map.retain(|k, slot| k - offset >= end || k - offset + slot.size as i64 <= start);
+    }
+
     pub fn update_stack_offset(&mut self, adjustment: i64) -> () {
         if (adjustment & 3) != 0 {
             panic!("Unsafe: Attempt to make stack not 4-byte aligned.");
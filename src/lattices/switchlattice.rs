@@ -1,19 +1,25 @@
 use crate::lattices::reachingdefslattice::ReachingDefnLattice;
-use crate::lattices::{ConstLattice, VariableState};
+use crate::lattices::{ConstLattice, ConstMeet, VariableState};
+use std::collections::HashMap;
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum SwitchValue {
     SwitchBase(u32),
-    ZF(u32, u8, ReachingDefnLattice),
+    ZF(u32, u8, bool, ReachingDefnLattice), // a cmp of a register (first operand or not) with a bound
     UpperBound(u32),
     JmpOffset(u32, u32), // base + bound
     JmpTarget(u32, u32), //base + bound
 }
 
+impl ConstMeet for SwitchValue {}
+
 pub type SwitchValueLattice = ConstLattice<SwitchValue>;
 
 pub type SwitchLattice = VariableState<SwitchValueLattice>;
 
+// addr -> (base, bound) of each jump table load `mov r, [base + idx*4]` with idx < bound
+pub type JumpTableLoads = HashMap<u64, (u32, u32)>;
+
 #[test]
 fn switch_lattice_test() {
     use crate::lattices::reachingdefslattice::LocIdx;
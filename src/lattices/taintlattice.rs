@@ -0,0 +1,24 @@
+use crate::lattices::{BooleanLattice, VariableState};
+
+// true = clean: the value is known not to come (unchecked) from guest-controlled data. Meeting
+// with `&&` then keeps a value clean only if it is clean along every path, and anything the
+// analysis knows nothing about (the default) is treated as tainted.
+pub type TaintLattice = VariableState<BooleanLattice>;
+
+#[test]
+fn taint_lattice_test() {
+    use crate::lattices::reachingdefslattice::LocIdx;
+    use crate::lattices::Lattice;
+    use crate::utils::ir::ValSize;
+
+    let mut x: TaintLattice = Default::default();
+    let mut y: TaintLattice = Default::default();
+    x.regs.set(&0, &ValSize::Size64, BooleanLattice::new(true));
+    x.regs.set(&1, &ValSize::Size64, BooleanLattice::new(true));
+    y.regs.set(&1, &ValSize::Size64, BooleanLattice::new(true));
+
+    let z = x.meet(&y, &LocIdx { addr: 0, idx: 0 });
+    assert_eq!(z.regs.get(&0, &ValSize::Size64).is_true(), false);
+    assert_eq!(z.regs.get(&1, &ValSize::Size64).is_true(), true);
+    assert_eq!(z.regs.get(&2, &ValSize::Size64).is_true(), false);
+}
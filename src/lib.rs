@@ -0,0 +1,99 @@
+//! VeriWasm: SFI safety checks for native-compiled Wasm.
+//!
+//! The crate is split into two layers:
+//!
+//! * the core (always built, without a feature of its own): the lifted IR (`utils::ir`), the
+//!   lattices, the worklist engine in `analyses`, the `Checker` trait, and the heap/stack analyses
+//!   and checkers that only depend on those.
+//! * `loader` (default): ELF loading and CFG recovery through yaxpeax-core, the x86 lifter, the
+//!   jump-table and call analyses that need a recovered CFG, `verify_module` and the `veriwasm`
+//!   binary, which is a thin wrapper around it.
+//!
+//! With `loader`, a whole module is verified the way the binary does it:
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use veriwasm::{verify_module, VerifyError, VerifyOptions};
+//!
+//! match verify_module(Path::new("module.so"), VerifyOptions::default()) {
+//!     Ok(report) => println!("{} functions verified", report.functions.len()),
+//!     Err(VerifyError::Unsafe(report)) => println!("{} functions failed", report.failures().len()),
+//!     Err(VerifyError::Load(e)) => println!("Unable to load module: {}", e),
+//!     Err(VerifyError::Io(e)) => println!("{}", e),
+//! }
+//! ```
+//!
+//! With `ffi`, the same is exported to C from the `libveriwasm` cdylib; `include/veriwasm.h`
+//! declares it.
+//!
+//! Building with `--no-default-features` gives the core layer on its own. A
+//! caller only has to provide lifted IR and a `ControlFlow` implementation:
+//!
+//! ```
+//! use veriwasm::analyses::stack_analyzer::StackAnalyzer;
+//! use veriwasm::analyses::{run_worklist, ControlFlow};
+//! use veriwasm::checkers::stack_checker::check_stack;
+//! use veriwasm::utils::compiler::{Compiler, CompilerMetadata};
+//! use veriwasm::utils::ir::{mk_value_i64, Binopcode, IRMap, Stmt, ValSize, Value};
+//!
+//! // A function made of a single basic block.
+//! struct SingleBlock(u64);
+//!
+//! impl ControlFlow for SingleBlock {
+//!     fn entrypoint(&self) -> u64 {
+//!         self.0
+//!     }
+//!     fn successors(&self, _addr: u64) -> Vec<u64> {
+//!         vec![]
+//!     }
+//! }
+//!
+//! let rsp = Value::Reg(4, ValSize::Size64);
+//! let mut irmap = IRMap::new();
+//! irmap.insert(
+//!     0x1000,
+//!     vec![
+//!         (0x1000, vec![Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), mk_value_i64(16))]),
+//!         (0x1004, vec![Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), mk_value_i64(16))]),
+//!         (0x1008, vec![Stmt::Ret]),
+//!     ],
+//! );
+//!
+//! let analyzer = StackAnalyzer {
+//!     metadata: CompilerMetadata {
+//!         compiler: Compiler::Lucet,
+//!         model: Compiler::Lucet.model(),
+//!         guest_table_0: 0,
+//!         lucet_tables: 0,
+//!         lucet_probestack: 0,
+//!         lucet_safe_page: None,
+//!         globals_size: -1,
+//!         call_table_size: -1,
+//!         caller_window: 0,
+//!         strict_stack: false,
+//!         strict_rebound: false,
+//!         runtime_fields: vec![],
+//!         wamr_memories: Default::default(),
+//!         wamr_tables: None,
+//!         options: Default::default(),
+//!         abi: Default::default(),
+//!         readonly_data: vec![],
+//!         wasmtime_layout: Default::default(),
+//!     },
+//!     stack_limit_cmps: Default::default(),
+//! };
+//! let result = run_worklist(&SingleBlock(0x1000), &irmap, &analyzer);
+//! assert!(check_stack(result, &irmap, &analyzer).is_ok());
+//! ```
+
+pub mod analyses;
+pub mod checkers;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod lattices;
+pub mod utils;
+#[cfg(feature = "loader")]
+pub mod verify;
+
+#[cfg(feature = "loader")]
+pub use verify::{verify_module, VerifyError, VerifyOptions};
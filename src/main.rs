@@ -1,148 +1,165 @@
-pub mod analyses;
-pub mod checkers;
-pub mod lattices;
-pub mod utils;
-use crate::analyses::call_analyzer::CallAnalyzer;
-use crate::analyses::heap_analyzer::HeapAnalyzer;
-use crate::analyses::reaching_defs::{analyze_reaching_defs,ReachingDefnAnalyzer};
-use crate::analyses::run_worklist;
-use crate::analyses::stack_analyzer::StackAnalyzer;
-use crate::checkers::call_checker::check_calls;
-use crate::checkers::heap_checker::check_heap;
-use crate::checkers::stack_checker::check_stack;
-use crate::utils::ir_utils::has_indirect_calls;
-use crate::utils::utils::{Compiler,fully_resolved_cfg,get_data};
-use utils::utils::{load_metadata, load_program, wamr_get_native_addrs};
-use clap::{App, Arg};
-use serde_json;
+use veriwasm::analyses::SEQUENTIAL_ENV;
+use veriwasm::utils::assumptions::{check_assumptions, Assumptions, HostConfig};
+use veriwasm::utils::caveats::parse_caveat_kinds;
+use veriwasm::utils::compiler::{Abi, AnalysisOptions, Compiler, UnknownInstrPolicy};
+use veriwasm::utils::ir_spill::SPILL_THRESHOLD_BLOCKS;
+use veriwasm::utils::logging::{OrderedLogger, Verbosity};
+use veriwasm::utils::minimize::{bundle_path, minimize, ReproBundle};
+use veriwasm::utils::persist::{query_state, PERSISTED_ANALYSES};
+use veriwasm::utils::stats::{convert_stats, StatsFormat, CONVERTED_STATS_NOTE};
+use veriwasm::utils::symbols::SymbolMap;
+use veriwasm::utils::utils::CfgLimits;
+use veriwasm::utils::valid_funcs::{FuncsDriftPolicy, TrustedFunc};
+use veriwasm::utils::wasmtime_layout::WasmtimeLayout;
+use veriwasm::verify::{verify_module, verify_module_logged};
+use veriwasm::{VerifyError, VerifyOptions};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::env;
 use std::fs;
-use std::panic;
-use std::time::Instant;
+use std::path::Path;
 use std::str::FromStr;
-use yaxpeax_core::analyses::control_flow::check_cfg_integrity;
 
-pub struct Config {
-    module_path: String,
-    _num_jobs: u32,
-    output_path: String,
-    has_output: bool,
-    _quiet: bool,
-    compiler: Compiler,
-    funcs: Vec<u32>,
-    globals_size: i64,
-    call_table_size: i64,
+// `veriwasm query`: print a persisted analysis state
+fn query(matches: &ArgMatches) {
+    let dir = matches.value_of("persist dir").unwrap();
+    let func = matches.value_of("func").unwrap();
+    let addr_str = matches.value_of("addr").unwrap();
+    let addr = u64::from_str_radix(addr_str.trim_start_matches("0x"), 16).expect("Invalid address");
+    let analysis = matches.value_of("analysis").unwrap_or("heap");
+    match query_state(dir, func, addr, analysis) {
+        Ok(state) => println!("{}", state),
+        Err(e) => panic!("{}", e),
+    }
 }
 
-fn run(config: Config) {
-    let mut func_counter = 0;
-    let mut info: Vec<(std::string::String, usize, f64, f64, f64, f64)> = vec![];
-    let program = load_program(&config.module_path);
-
-    println!("Loading Metadata");
-    let metadata = load_metadata(&config.module_path, config.compiler, config.globals_size+config.call_table_size*4);
-    let (x86_64_data, func_addrs, plt) = get_data(&config.module_path, &program, &config.funcs);
-    let mut valid_funcs: Vec<u64> = func_addrs.clone().iter().map(|x| x.0).collect();
-    if let Compiler::Wamr = metadata.compiler {
-        // Wamr has a few special functions that shouldn't be verified, but should be call-able
-        valid_funcs.extend(wamr_get_native_addrs(&program));
+// `veriwasm check-assumptions`: compare an assumptions manifest against a host config
+fn check_host(matches: &ArgMatches) {
+    let manifest = matches.value_of("manifest").unwrap();
+    let host_config = matches.value_of("host config").unwrap();
+    let assumed = Assumptions::load(manifest).unwrap_or_else(|e| panic!("{}", e));
+    let host = HostConfig::load(host_config).unwrap_or_else(|e| panic!("Invalid host config {}", e));
+    let check = check_assumptions(&assumed, &host);
+    for name in &check.unchecked {
+        println!("unchecked: {}", name);
     }
-    for (addr, func_name) in &func_addrs {
-        println!("Generating CFG for {:?}", func_name);
-        let start = Instant::now();
-        let (cfg, irmap) = fully_resolved_cfg(&program, &x86_64_data.contexts, &metadata, *addr);
-        func_counter += 1;
-        println!("Analyzing: {:?}", func_name);
-        check_cfg_integrity(&cfg.blocks, &cfg.graph);
-
-        println!("Checking Heap Safety");
-        let heap_start = Instant::now();
-        let heap_analyzer = HeapAnalyzer {
-            metadata: metadata.clone(),
-        };
-        let heap_result = run_worklist(&cfg, &irmap, &heap_analyzer);
-        let heap_safe = check_heap(heap_result, &irmap, &heap_analyzer, &func_addrs);
-        if !heap_safe {
-            panic!("Not Heap Safe");
-        }
-
-        println!("Checking Stack Safety");
-        let stack_start = Instant::now();
-        let stack_analyzer = StackAnalyzer { 
-            metadata: metadata.clone(),
-        };
-        let stack_result = run_worklist(&cfg, &irmap, &stack_analyzer);
-        let stack_safe = check_stack(stack_result, &irmap, &stack_analyzer);
-        if !stack_safe {
-            panic!("Not Stack Safe");
-        }
-
-        let call_start = Instant::now();
-        println!("Checking Call Safety");
-        if has_indirect_calls(&irmap) {
-            let reaching_defs = analyze_reaching_defs(&cfg, &irmap, &metadata);
-            let call_analyzer = CallAnalyzer {
-                metadata: metadata.clone(),
-                reaching_defs: reaching_defs.clone(),
-                reaching_analyzer: ReachingDefnAnalyzer {metadata: metadata.clone(), cfg: cfg.clone(), irmap: irmap.clone()},
-            };
-            let call_result = run_worklist(&cfg, &irmap, &call_analyzer);
-            let call_safe = check_calls(call_result, &irmap, &call_analyzer, &valid_funcs, &plt);
-            if !call_safe {
-                panic!("Not Call Safe");
-            }
-
-        }
-        let end = Instant::now();
-        info.push((
-            func_name.to_string(),
-            cfg.blocks.len(),
-            (heap_start - start).as_secs_f64(),
-            (stack_start - heap_start).as_secs_f64(),
-            (call_start - stack_start).as_secs_f64(),
-            (end - call_start).as_secs_f64(),
-        ));
-        println!(
-            "Verified {:?} at {:?} blocks. CFG: {:?}s Stack: {:?}s Heap: {:?}s Calls: {:?}s",
-            func_name,
-            cfg.blocks.len(),
-            (heap_start - start).as_secs_f64(),
-            (stack_start - heap_start).as_secs_f64(),
-            (call_start - stack_start).as_secs_f64(),
-            (end - call_start).as_secs_f64()
-        );
+    for msg in &check.incompatible {
+        println!("incompatible: {}", msg);
     }
-    if config.has_output {
-        let data = serde_json::to_string(&info).unwrap();
-        println!("Dumping Stats to {}", config.output_path);
-        fs::write(config.output_path, data).expect("Unable to write file");
+    if !check.compatible() {
+        std::process::exit(1);
     }
+    println!("The host config satisfies every assumption it describes");
+}
+
+// `veriwasm convert-stats`: rewrite an old (array per function) stats file in the named layout
+fn convert(matches: &ArgMatches) {
+    let old = matches.value_of("old stats").unwrap();
+    let new = matches.value_of("new stats").unwrap();
+    let data = fs::read_to_string(old).unwrap_or_else(|e| panic!("{}: {}", old, e));
+    let converted = convert_stats(&data).unwrap_or_else(|e| panic!("{}: {}", old, e));
+    fs::write(new, converted.to_string()).unwrap_or_else(|e| panic!("{}: {}", new, e));
+    eprintln!("Note: {}", CONVERTED_STATS_NOTE);
+}
 
-    let mut total_cfg_time = 0.0;
-    let mut total_heap_time = 0.0;
-    let mut total_stack_time = 0.0;
-    let mut total_call_time = 0.0;
-    for (_, _, cfg_time, heap_time, stack_time, call_time) in &info {
-        total_cfg_time += cfg_time;
-        total_heap_time += heap_time;
-        total_stack_time += stack_time;
-        total_call_time += call_time;
+// `veriwasm minimize`: shrink the repro bundle of a function failing a rule
+fn minimize_module(matches: &ArgMatches) {
+    let module_path = Path::new(matches.value_of("module").unwrap());
+    let target = matches.value_of("target verdict").unwrap();
+    let failing = |config: VerifyOptions| match verify_module(module_path, config) {
+        Ok(_) => panic!("{} verifies, there's no failure to minimize", module_path.display()),
+        Err(VerifyError::Load(e)) | Err(VerifyError::Io(e)) => panic!("{}", e),
+        Err(VerifyError::Unsafe(report)) => report,
+    };
+    let report = failing(VerifyOptions {
+        keep_going: true,
+        only_funcs: matches.value_of("func").map_or(vec![], |func| vec![func.to_string()]),
+        ..Default::default()
+    });
+    let func_name = report
+        .failures()
+        .into_iter()
+        .find(|f| f.rule_id() == Some(target) || f.failing_description.as_ref().map_or(false, |d| d.starts_with(target)))
+        .map(|f| f.name.clone())
+        .unwrap_or_else(|| panic!("No function of {} fails {}", module_path.display(), target));
+    // verify the failing function alone again, writing its repro bundle
+    let dir = env::temp_dir().join(format!("veriwasm-minimize-{}", std::process::id()));
+    let dir = dir.to_string_lossy().to_string();
+    failing(VerifyOptions {
+        keep_going: true,
+        only_funcs: vec![func_name.clone()],
+        repro_dir: Some(dir.clone()),
+        ..Default::default()
+    });
+    let bundle = ReproBundle::load(&bundle_path(&dir, &func_name).to_string_lossy()).unwrap_or_else(|e| panic!("{}", e));
+    let _ = fs::remove_dir_all(&dir);
+    let minimized = minimize(&bundle, target).unwrap_or_else(|e| panic!("{}", e));
+    let output = matches.value_of("output").map_or(format!("{}.min.json", func_name.replace('/', "_")), |s| s.to_string());
+    minimized.bundle.save(Path::new(&output)).unwrap_or_else(|e| panic!("{}: {}", output, e));
+    for step in &minimized.log {
+        println!("{}", step);
     }
-    println!("Verified {:?} functions", func_counter);
     println!(
-        "Total time = {:?}s CFG: {:?} Heap: {:?}s Stack: {:?}s Call: {:?}s",
-        total_cfg_time + total_heap_time + total_stack_time + total_call_time,
-        total_cfg_time,
-        total_heap_time,
-        total_stack_time,
-        total_call_time
+        "{} still fails {} at 0x{:x}: {} -> {} (written to {})",
+        func_name,
+        minimized.failure.rule,
+        minimized.failure.addr,
+        bundle.size(),
+        minimized.bundle.size(),
+        output
     );
-    println!("Done!");
 }
 
 fn main() {
     let matches = App::new("VeriWasm")
         .version("0.1.0")
         .about("Validates safety of native Wasm code")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("query")
+                .about("Prints a persisted analysis state right before an instruction")
+                .arg(Arg::with_name("persist dir").required(true).help("Directory written by --persist-states"))
+                .arg(Arg::with_name("func").long("func").takes_value(true).required(true).help("Function name"))
+                .arg(Arg::with_name("addr").long("addr").takes_value(true).required(true).help("Instruction address (hex)"))
+                .arg(
+                    Arg::with_name("analysis")
+                        .long("analysis")
+                        .takes_value(true)
+                        .possible_values(&PERSISTED_ANALYSES)
+                        .help("Analysis to query (default heap)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check-assumptions")
+                .about("Checks that a host runtime provides what a verified module was assumed to get")
+                .arg(Arg::with_name("manifest").required(true).help("Manifest written by --assumptions"))
+                .arg(
+                    Arg::with_name("host config")
+                        .long("host-config")
+                        .takes_value(true)
+                        .required(true)
+                        .help("JSON description of the host runtime"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("convert-stats")
+                .about("Converts a stats file of the old array-per-function layout to the named one")
+                .arg(Arg::with_name("old stats").required(true).help("Stats file without a version"))
+                .arg(Arg::with_name("new stats").required(true).help("Path to write the converted file to")),
+        )
+        .subcommand(
+            SubCommand::with_name("minimize")
+                .about("Shrinks the repro bundle of a function failing a rule to a smaller one failing it the same way")
+                .arg(Arg::with_name("module").required(true).help("Native Wasm module with the failing function"))
+                .arg(
+                    Arg::with_name("target verdict")
+                        .long("target-verdict")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Rule id to preserve: a property (heap) or a finer rule (cfi.unresolved-jump)"),
+                )
+                .arg(Arg::with_name("func").long("func").takes_value(true).help("Failing function (default the first failing the rule)"))
+                .arg(Arg::with_name("output").short("o").takes_value(true).help("Path to write the minimized bundle to")),
+        )
         .arg(
             Arg::with_name("module path")
                 .short("i")
@@ -157,6 +174,17 @@ fn main() {
                 .takes_value(true)
                 .help("Number of parallel threads (default 1)"),
         )
+        .arg(
+            Arg::with_name("funcs")
+                .long("func")
+                .takes_value(true)
+                .help("Comma-separated list of function names or hex addresses to verify (default all)"),
+        )
+        .arg(
+            Arg::with_name("keep going")
+                .long("keep-going")
+                .help("Verify the remaining functions after one fails"),
+        )
         .arg(
             Arg::with_name("stats output path")
                 .short("o")
@@ -164,24 +192,80 @@ fn main() {
                 .takes_value(true)
                 .help("Path to output stats file"),
         )
-        .arg(Arg::with_name("quiet").short("q").long("quiet"))
+        .arg(
+            Arg::with_name("stats format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["json", "csv", "text", "legacy"])
+                .requires("stats output path")
+                .help("Format of the stats file: json, csv, text or legacy (default json)"),
+        )
+        .arg(
+            Arg::with_name("legacy stats")
+                .long("legacy-stats")
+                .conflicts_with("stats format")
+                .requires("stats output path")
+                .help("Write the stats file in the old array-per-function layout (same as --format legacy)"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .help("Only print failures and the final summary"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .conflicts_with("quiet")
+                .help("Also trace every statement checked"),
+        )
+        .arg(
+            Arg::with_name("taint audit")
+                .long("taint-audit")
+                .help("Warn about table indices that may be guest-controlled and unchecked"),
+        )
+        .arg(
+            Arg::with_name("check stack args")
+                .long("check-stack-args")
+                .help("Warn about functions reading more stack arguments than a direct caller writes"),
+        )
         .arg(
             Arg::with_name("wamr")
                 .short("w")
                 .long("wamr")
                 .help("Enables parsing and analysis of Wasm Micro Runtime binaries (WAMR)")
         )
+        .arg(
+            Arg::with_name("lucet")
+                .long("lucet")
+                .conflicts_with("wamr")
+                .help("Verifies the module as a Lucet binary (default: detected from its symbols)")
+        )
+        .arg(
+            Arg::with_name("wasmtime")
+                .long("wasmtime")
+                .conflicts_with_all(&["wamr", "lucet"])
+                .help("Verifies the module as a Wasmtime (Cranelift) binary (default: detected from its symbols)")
+        )
+        .arg(
+            Arg::with_name("vmctx offsets")
+                .long("vmctx-offsets")
+                .takes_value(true)
+                .requires("wasmtime")
+                .help("Comma-separated key=offset VMContext fields: heap_base, heap_bound, table_base, table_size, globals, globals_size (Wasmtime-only)"),
+        )
         .arg(
             Arg::with_name("trusted")
                 .short("t")
                 .takes_value(true)
-                .help("Comma-separated list of function numbers to trust (WAMR-only)"),
+                .help("Comma-separated list of functions to trust rather than verify: numbers, names or hex addresses"),
         )
         .arg(
             Arg::with_name("globals")
                 .short("g")
                 .takes_value(true)
-                .help("Size of global data in memory (WAMR-only)"),
+                .help("Size of global data in memory (WAMR and Lucet; Lucet default: read from the module data, else 4096)"),
         )
         .arg(
             Arg::with_name("calls")
@@ -189,29 +273,319 @@ fn main() {
                 .takes_value(true)
                 .help("# of functions in the indirect call table (WAMR-only)"),
         )
+        .arg(
+            Arg::with_name("caller window")
+                .long("caller-window")
+                .takes_value(true)
+                .help("Bytes above the return address writable before the frame is set up (WAMR-only)"),
+        )
+        .arg(
+            Arg::with_name("strict stack")
+                .long("strict-stack")
+                .help("Reject reads of the return address (WAMR-only)"),
+        )
+        .arg(
+            Arg::with_name("exception epilogue")
+                .long("allow-exception-epilogue")
+                .requires("wamr")
+                .help("Let a ret reached only after raising an exception skip restoring callee-saved registers (WAMR-only)"),
+        )
+        .arg(
+            Arg::with_name("wamr bounds checks")
+                .long("wamr-bounds-checks")
+                .requires("wamr")
+                .help("Accept heap accesses at offsets checked against the memory bound of their width, for builds without guard pages (WAMR-only)"),
+        )
+        .arg(
+            Arg::with_name("safe page")
+                .long("safe-page")
+                .takes_value(true)
+                .help("Address of the page Spectre-mitigated heap accesses fall back to, if the module has no lucet_safe_page symbol (Lucet-only)"),
+        )
+        .arg(
+            Arg::with_name("strict rebound")
+                .long("strict-rebound-after-call")
+                .help("Forget all bounds checks established before a call, including spilled ones"),
+        )
+        .arg(
+            Arg::with_name("unknown instr")
+                .long("unknown-instr")
+                .takes_value(true)
+                .possible_values(&["reject", "clear", "ignore"])
+                .help("What to do with an instruction the lifter doesn't model: fail the function, clear everything it writes, or skip it (default clear)"),
+        )
+        .arg(
+            Arg::with_name("abi")
+                .long("abi")
+                .takes_value(true)
+                .possible_values(&["lp64", "x32"])
+                .help("Data model of the module: lp64, or x32 for 32-bit pointers (default lp64)"),
+        )
+        .arg(
+            Arg::with_name("analysis options")
+                .long("analysis-opts")
+                .takes_value(true)
+                .help("Comma-separated key=val list turning optional analysis refinements on or off"),
+        )
+        .arg(
+            Arg::with_name("spill dir")
+                .long("spill-ir")
+                .takes_value(true)
+                .help("Directory to spill the IR of every function to while it is analyzed"),
+        )
+        .arg(
+            Arg::with_name("spill threshold")
+                .long("spill-threshold")
+                .takes_value(true)
+                .help("Spill the IR of functions with more blocks than this (default 20000)"),
+        )
+        .arg(
+            Arg::with_name("max cfg rounds")
+                .long("max-cfg-rounds")
+                .takes_value(true)
+                .help("Rounds of resolving a function's indirect jumps before it is unverifiable (default 32)"),
+        )
+        .arg(
+            Arg::with_name("max switch targets")
+                .long("max-switch-targets")
+                .takes_value(true)
+                .help("Targets across all the switches of a function before it is unverifiable (default 65536)"),
+        )
+        .arg(
+            Arg::with_name("persist dir")
+                .long("persist-states")
+                .takes_value(true)
+                .help("Directory to save final heap and stack states to, for `veriwasm query`"),
+        )
+        .arg(
+            Arg::with_name("only")
+                .long("only")
+                .takes_value(true)
+                .requires("persist dir")
+                .help("Comma-separated list of functions whose states are persisted (default all)"),
+        )
+        .arg(
+            Arg::with_name("failure cache")
+                .long("cache-failures")
+                .takes_value(true)
+                .help("File caching failing functions, which are reported again without re-verifying"),
+        )
+        .arg(
+            Arg::with_name("recheck percent")
+                .long("recheck-percent")
+                .takes_value(true)
+                .requires("failure cache")
+                .help("Percentage of cached failures to verify again anyway (default 10)"),
+        )
+        .arg(
+            Arg::with_name("cache seed")
+                .long("cache-seed")
+                .takes_value(true)
+                .requires("failure cache")
+                .help("Seed choosing which cached failures are verified again (default 0)"),
+        )
+        .arg(
+            Arg::with_name("deps")
+                .long("deps")
+                .takes_value(true)
+                .help("File recording each function's result and the bytes its verification read"),
+        )
+        .arg(
+            Arg::with_name("changed ranges")
+                .long("changed-ranges")
+                .takes_value(true)
+                .requires("deps")
+                .help("JSON list of [offset, len] byte ranges of the module changed since --deps was written; only functions depending on them are verified"),
+        )
+        .arg(
+            Arg::with_name("patch")
+                .long("patch")
+                .takes_value(true)
+                .help("JSON list of [offset, original, new] byte patches to apply to the module before verifying it"),
+        )
+        .arg(
+            Arg::with_name("funcs snapshot")
+                .long("funcs-snapshot")
+                .takes_value(true)
+                .help("Path to write the functions direct calls may target to, with where each was found"),
+        )
+        .arg(
+            Arg::with_name("funcs expect")
+                .long("funcs-expect")
+                .takes_value(true)
+                .help("Functions snapshot the functions direct calls may target must match"),
+        )
+        .arg(
+            Arg::with_name("funcs expect policy")
+                .long("funcs-expect-policy")
+                .takes_value(true)
+                .possible_values(&["fail", "warn"])
+                .help("Whether a mismatch with --funcs-expect fails verification or only warns (default fail)"),
+        )
+        .arg(
+            Arg::with_name("dump cfg")
+                .long("dump-cfg")
+                .takes_value(true)
+                .help("Directory to write each function's resolved CFG to, as a Graphviz dot file with its heap and stack states"),
+        )
+        .arg(
+            Arg::with_name("deny caveats")
+                .long("deny-caveats")
+                .takes_value(true)
+                .help("Comma-separated soundness caveat kinds that fail verification instead of only being reported, or all"),
+        )
+        .arg(
+            Arg::with_name("ir golden")
+                .long("ir-golden")
+                .takes_value(true)
+                .help("Directory of golden IR to compare the IR of functions with, reporting differences as ir_drift caveats"),
+        )
+        .arg(
+            Arg::with_name("ir golden update")
+                .long("ir-golden-update")
+                .requires("ir golden")
+                .help("Writes the IR of every function verified as its golden IR instead of comparing them"),
+        )
+        .arg(
+            Arg::with_name("cache dir")
+                .long("cache-dir")
+                .takes_value(true)
+                .help("Directory to keep each function's resolved CFG and IR in across runs, to skip building them again"),
+        )
+        .arg(
+            Arg::with_name("no cache")
+                .long("no-cache")
+                .help("Build every CFG again, ignoring --cache-dir"),
+        )
+        .arg(
+            Arg::with_name("compare stats")
+                .long("compare-stats")
+                .takes_value(true)
+                .help("Stats file of an earlier run to compare block counts and times against"),
+        )
+        .arg(
+            Arg::with_name("regression factor")
+                .long("regression-factor")
+                .takes_value(true)
+                .requires("compare stats")
+                .help("Slowdown (or change in block count) flagged by --compare-stats (default 2)"),
+        )
+        .arg(
+            Arg::with_name("wamr layout")
+                .long("wamr-layout")
+                .takes_value(true)
+                .requires("wamr")
+                .help("File of ModuleInstance fields generated code may atomically update, and of its linear memories (WAMR-only)"),
+        )
+        .arg(
+            Arg::with_name("html report")
+                .long("html-report")
+                .takes_value(true)
+                .help("Path to write a self-contained HTML report of the run to"),
+        )
+        .arg(
+            Arg::with_name("symbol map")
+                .long("symbol-map")
+                .takes_value(true)
+                .help("File of canonical=actual lines renaming the Lucet metadata symbols"),
+        )
+        .arg(
+            Arg::with_name("no symbols")
+                .long("no-symbols")
+                .help("Take the functions to verify from the Lucet function manifest, even if the module has symbols"),
+        )
+        .arg(
+            Arg::with_name("state fingerprint")
+                .long("state-fingerprint")
+                .takes_value(true)
+                .help("Print a fingerprint of every block's heap and stack state in this function"),
+        )
+        .arg(
+            Arg::with_name("assumptions")
+                .long("assumptions")
+                .takes_value(true)
+                .help("Path to write the manifest of what the verdict assumes of the runtime to, if every function verified"),
+        )
+        .arg(
+            Arg::with_name("paranoid")
+                .long("paranoid")
+                .help("Check that every meet is commutative and associative (slow)"),
+        )
+        .arg(
+            Arg::with_name("sequential")
+                .long("sequential")
+                .help("Analyze every function on one thread, however big (same as setting VERIWASM_SEQUENTIAL)"),
+        )
+        .arg(
+            Arg::with_name("record schedule")
+                .long("record-schedule")
+                .takes_value(true)
+                .conflicts_with("replay schedule")
+                .help("Path to record the order each analysis of each function visits blocks in to"),
+        )
+        .arg(
+            Arg::with_name("replay schedule")
+                .long("replay-schedule")
+                .takes_value(true)
+                .help("Path of an order recorded with --record-schedule to visit blocks in instead of the usual one"),
+        )
+        .arg(
+            Arg::with_name("repro dir")
+                .long("repro-dir")
+                .takes_value(true)
+                .help("Directory to write a repro bundle of every function failing a check to (see veriwasm minimize)"),
+        )
         .get_matches();
 
+    if let Some(query_matches) = matches.subcommand_matches("query") {
+        query(query_matches);
+        return;
+    }
+    if let Some(check_matches) = matches.subcommand_matches("check-assumptions") {
+        check_host(check_matches);
+        return;
+    }
+    if let Some(convert_matches) = matches.subcommand_matches("convert-stats") {
+        convert(convert_matches);
+        return;
+    }
+    if let Some(minimize_matches) = matches.subcommand_matches("minimize") {
+        minimize_module(minimize_matches);
+        return;
+    }
+
     let module_path = matches.value_of("module path").unwrap();
     let num_jobs_opt = matches.value_of("jobs");
     let output_path = matches.value_of("stats output path").unwrap_or("");
     let num_jobs = num_jobs_opt
         .map(|s| s.parse::<u32>().unwrap_or(1))
         .unwrap_or(1);
-    let quiet = matches.is_present("quiet");
-    let wamr = matches.is_present("wamr");
-    let compiler: Compiler;
-    let funcs: Vec<u32>;
-    if wamr {
-        compiler = Compiler::Wamr;
-        if let Some(func_str) = matches.value_of("trusted") {
-            funcs = func_str.split(",").map(|s| u32::from_str(s).unwrap()).collect();
-        } else {
-            funcs = vec![];
-        }
+    let keep_going = matches.is_present("keep going");
+    let verbosity = if matches.is_present("quiet") {
+        Verbosity::Quiet
+    } else if matches.is_present("verbose") {
+        Verbosity::Verbose
     } else {
-        compiler = Compiler::Lucet;
-        funcs = vec![];
-    }
+        Verbosity::Normal
+    };
+    let taint_audit = matches.is_present("taint audit");
+    let check_stack_args = matches.is_present("check stack args");
+    let compiler = if matches.is_present("wamr") {
+        Some(Compiler::Wamr)
+    } else if matches.is_present("lucet") {
+        Some(Compiler::Lucet)
+    } else if matches.is_present("wasmtime") {
+        Some(Compiler::Wasmtime)
+    } else {
+        None
+    };
+    let funcs: Vec<TrustedFunc> = match matches.value_of("trusted") {
+        Some(func_str) => func_str
+            .split(",")
+            .map(|s| s.parse::<TrustedFunc>().unwrap_or_else(|e| panic!("Invalid trusted function: {}", e)))
+            .collect(),
+        None => vec![],
+    };
     let globals_size_opt = matches.value_of("globals");
     let globals_size = globals_size_opt
         .map(|s| s.parse::<i64>().unwrap_or(-1))
@@ -221,19 +595,165 @@ fn main() {
         .map(|s| s.parse::<i64>().unwrap_or(-1))
         .unwrap_or(-1);
 
-    let has_output = if output_path == "" { false } else { true };
+    let caller_window = matches.value_of("caller window")
+        .map(|s| s.parse::<i64>().expect("Invalid caller window"));
+    let strict_stack = matches.is_present("strict stack");
+    let safe_page = matches
+        .value_of("safe page")
+        .map(|s| u64::from_str_radix(s.trim_start_matches("0x"), 16).expect("Invalid safe page address"));
+    let strict_rebound = matches.is_present("strict rebound");
+    let mut analysis_options = match matches.value_of("analysis options") {
+        Some(s) => AnalysisOptions::parse(s).unwrap_or_else(|e| panic!("Invalid analysis options: {}", e)),
+        None => AnalysisOptions::default(),
+    };
+    analysis_options.paranoid = matches.is_present("paranoid");
+    if matches.is_present("sequential") {
+        env::set_var(SEQUENTIAL_ENV, "1");
+    }
+    analysis_options.exception_epilogue = matches.is_present("exception epilogue");
+    analysis_options.wamr_bounds_checks = matches.is_present("wamr bounds checks");
+    if let Some(policy) = matches.value_of("unknown instr") {
+        analysis_options.unknown_instr = UnknownInstrPolicy::from_str(policy).unwrap();
+    }
+    let abi = matches
+        .value_of("abi")
+        .map(|s| Abi::from_str(s).unwrap())
+        .unwrap_or(Abi::Lp64);
+    let wasmtime_layout = match matches.value_of("vmctx offsets") {
+        Some(s) => WasmtimeLayout::parse(s).unwrap_or_else(|e| panic!("Invalid VMContext offsets: {}", e)),
+        None => WasmtimeLayout::default(),
+    };
+    let spill_dir = matches.value_of("spill dir").map(|s| s.to_string());
+    let spill_threshold = matches
+        .value_of("spill threshold")
+        .map(|s| s.parse::<usize>().expect("Invalid spill threshold"))
+        .unwrap_or(SPILL_THRESHOLD_BLOCKS);
+    let default_limits = CfgLimits::default();
+    let cfg_limits = CfgLimits {
+        max_rounds: matches
+            .value_of("max cfg rounds")
+            .map(|s| s.parse::<usize>().expect("Invalid CFG round limit"))
+            .unwrap_or(default_limits.max_rounds),
+        max_targets: matches
+            .value_of("max switch targets")
+            .map(|s| s.parse::<usize>().expect("Invalid switch target limit"))
+            .unwrap_or(default_limits.max_targets),
+    };
+    let persist_dir = matches.value_of("persist dir").map(|s| s.to_string());
+    let persist_funcs: Vec<String> = matches
+        .value_of("only")
+        .map(|s| s.split(",").map(|f| f.to_string()).collect())
+        .unwrap_or(vec![]);
+    let only_funcs: Vec<String> = matches
+        .value_of("funcs")
+        .map(|s| s.split(",").map(|f| f.to_string()).collect())
+        .unwrap_or(vec![]);
+    let failure_cache = matches.value_of("failure cache").map(|s| s.to_string());
+    let recheck_percent = matches
+        .value_of("recheck percent")
+        .map(|s| s.parse::<u64>().expect("Invalid recheck percentage"))
+        .unwrap_or(10);
+    let cache_seed = matches
+        .value_of("cache seed")
+        .map(|s| s.parse::<u64>().expect("Invalid cache seed"))
+        .unwrap_or(0);
+    let deps = matches.value_of("deps").map(|s| s.to_string());
+    let changed_ranges = matches.value_of("changed ranges").map(|s| s.to_string());
+    let patch = matches.value_of("patch").map(|s| s.to_string());
+    let funcs_snapshot = matches.value_of("funcs snapshot").map(|s| s.to_string());
+    let funcs_expect = matches.value_of("funcs expect").map(|s| s.to_string());
+    let funcs_expect_policy = matches
+        .value_of("funcs expect policy")
+        .map(|s| s.parse::<FuncsDriftPolicy>().unwrap_or_else(|e| panic!("{}", e)))
+        .unwrap_or(FuncsDriftPolicy::Fail);
+    let dump_cfg = matches.value_of("dump cfg").map(|s| s.to_string());
+    let deny_caveats = matches
+        .value_of("deny caveats")
+        .map(|s| parse_caveat_kinds(s).unwrap_or_else(|e| panic!("{}", e)))
+        .unwrap_or_default();
+    let ir_golden = matches.value_of("ir golden").map(|s| s.to_string());
+    let cache_dir = matches.value_of("cache dir").filter(|_| !matches.is_present("no cache")).map(|s| s.to_string());
+    let compare_stats = matches.value_of("compare stats").map(|s| s.to_string());
+    let regression_factor = matches
+        .value_of("regression factor")
+        .map(|s| s.parse::<f64>().expect("Invalid regression factor"))
+        .unwrap_or(2.0);
+    let wamr_layout = matches.value_of("wamr layout").map(|s| s.to_string());
+    let html_report = matches.value_of("html report").map(|s| s.to_string());
+    let state_fingerprint = matches.value_of("state fingerprint").map(|s| s.to_string());
+    let assumptions = matches.value_of("assumptions").map(|s| s.to_string());
+    let no_symbols = matches.is_present("no symbols");
+    let symbol_map = match matches.value_of("symbol map") {
+        Some(path) => SymbolMap::load(path).unwrap_or_else(|e| panic!("Invalid symbol map {}", e)),
+        None => SymbolMap::default(),
+    };
 
-    let config = Config {
-        module_path: module_path.to_string(),
-        _num_jobs: num_jobs,
-        output_path: output_path.to_string(),
-        has_output: has_output,
-        _quiet: quiet,
+    let stats_output = if output_path == "" { None } else { Some(output_path.to_string()) };
+    let stats_format = matches
+        .value_of("stats format")
+        .map(|s| s.parse::<StatsFormat>().unwrap_or_else(|e| panic!("{}", e)))
+        .unwrap_or(if matches.is_present("legacy stats") { StatsFormat::Legacy } else { StatsFormat::Json });
+
+    let config = VerifyOptions {
         compiler: compiler,
-        funcs: funcs,
+        trusted_funcs: funcs,
         globals_size: globals_size,
         call_table_size: call_table_size,
+        num_jobs: num_jobs,
+        keep_going: keep_going,
+        stats_output: stats_output,
+        stats_format: stats_format,
+        taint_audit: taint_audit,
+        check_stack_args: check_stack_args,
+        symbol_map: symbol_map,
+        no_symbols: no_symbols,
+        caller_window: caller_window,
+        strict_stack: strict_stack,
+        strict_rebound: strict_rebound,
+        safe_page: safe_page,
+        analysis_options: analysis_options,
+        abi: abi,
+        wasmtime_layout: wasmtime_layout,
+        persist_dir: persist_dir,
+        persist_funcs: persist_funcs,
+        spill_dir: spill_dir,
+        spill_threshold: spill_threshold,
+        cfg_limits: cfg_limits,
+        only_funcs: only_funcs,
+        failure_cache: failure_cache,
+        recheck_percent: recheck_percent,
+        cache_seed: cache_seed,
+        deps: deps,
+        changed_ranges: changed_ranges,
+        compare_stats: compare_stats,
+        regression_factor: regression_factor,
+        wamr_layout: wamr_layout,
+        html_report: html_report,
+        state_fingerprint: state_fingerprint,
+        assumptions: assumptions,
+        patch: patch,
+        funcs_snapshot: funcs_snapshot,
+        funcs_expect: funcs_expect,
+        funcs_expect_policy: funcs_expect_policy,
+        dump_cfg: dump_cfg,
+        deny_caveats: deny_caveats,
+        ir_golden: ir_golden,
+        ir_golden_update: matches.is_present("ir golden update"),
+        cache_dir: cache_dir,
+        record_schedule: matches.value_of("record schedule").map(|s| s.to_string()),
+        replay_schedule: matches.value_of("replay schedule").map(|s| s.to_string()),
+        repro_dir: matches.value_of("repro dir").map(|s| s.to_string()),
     };
 
-    run(config);
+    let logger: &'static OrderedLogger = Box::leak(Box::new(OrderedLogger::new(std::io::stdout(), !config.keep_going, verbosity)));
+    log::set_logger(logger).expect("Unable to install logger");
+    // the summary is logged at info level even when quiet
+    log::set_max_level(verbosity.level().max(log::LevelFilter::Info));
+    let result = verify_module_logged(Path::new(module_path), config, Some(logger));
+    logger.shutdown();
+    match result {
+        Ok(_) => (),
+        Err(VerifyError::Unsafe(_)) => std::process::exit(1),
+        Err(VerifyError::Load(e)) | Err(VerifyError::Io(e)) => panic!("{}", e),
+    }
 }
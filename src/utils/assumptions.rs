@@ -0,0 +1,567 @@
+use crate::checkers::heap_checker::HEAP_GUARD_LIMIT;
+use crate::lattices::stackgrowthlattice::WAMR_GUARD_PAGE_COUNT;
+use crate::utils::compiler::{Abi, Compiler, CompilerMetadata};
+use crate::utils::compiler_model::LucetModel;
+use crate::utils::wamr_layout::{RuntimeField, WAMR_LAYOUT};
+use crate::utils::valid_funcs::TrustedFunc;
+use serde_json::{json, Map, Value as Json};
+use std::collections::BTreeMap;
+use std::fs;
+use std::str::FromStr;
+
+/*
+ * The assumptions manifest (`--assumptions`). A verdict only holds for a runtime that sets the
+ * module up the way the checks assumed: a large enough heap guard region, the expected value in
+ * rdi on entry, guard pages below the stack, and the runtime structures at the offsets the heap
+ * checker's rules were written against. A successful run writes these down, and
+ * `veriwasm check-assumptions` compares them against a host config describing the runtime the
+ * module will be loaded into.
+ */
+
+pub const ASSUMPTIONS_VERSION: u64 = 1;
+
+// the granularity of stack probes, and so the smallest guard page the stack checks allow for
+pub const PAGE_SIZE: i64 = 4096;
+// bytes below the stack pointer the caller has already probed on entry
+pub const ENTRY_PROBED: i64 = 4096;
+// the largest linear memory the heap checks prove accesses against
+pub const MAX_HEAP_SIZE: u64 = 1 << 32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assumptions {
+    pub compiler: Compiler,
+    pub abi: Abi,
+    pub page_size: i64,
+    // the largest linear memory, and the bytes from the heap base that must be mapped or guarded
+    pub heap_size: u64,
+    pub heap_reservation: u64,
+    // what rdi points to on entry to every function
+    pub entry_rdi: String,
+    pub stack_probed: i64,
+    pub stack_guard_pages: i64,
+    pub caller_window: i64,
+    pub strict_stack: bool,
+    // the addresses of the Lucet tables and probestack in the module (Lucet-only)
+    pub tables: BTreeMap<String, u64>,
+    // the globals size in effect, and the # of entries of the function index table (Wamr-only)
+    pub globals_size: i64,
+    pub call_table_size: i64,
+    // the ExecEnv and ModuleInstance offsets (Wamr), or the VMContext offsets (Wasmtime)
+    pub layout: BTreeMap<String, i64>,
+    pub runtime_fields: Vec<RuntimeField>,
+    // the names of the functions left unverified (see valid_funcs::TrustedFunc)
+    pub trusted_funcs: Vec<String>,
+    pub options: String,
+}
+
+fn compiler_name(compiler: Compiler) -> &'static str {
+    match compiler {
+        Compiler::Lucet => "lucet",
+        Compiler::Wamr => "wamr",
+        Compiler::Wasmtime => "wasmtime",
+    }
+}
+
+fn parse_compiler(s: &str) -> Option<Compiler> {
+    match s {
+        "lucet" => Some(Compiler::Lucet),
+        "wamr" => Some(Compiler::Wamr),
+        "wasmtime" => Some(Compiler::Wasmtime),
+        _ => None,
+    }
+}
+
+fn parse_fields(json: &Json) -> Option<Vec<RuntimeField>> {
+    json.as_array()?
+        .iter()
+        .map(|f| Some(RuntimeField { offset: f.get(0)?.as_i64()?, width: f.get(1)?.as_i64()? }))
+        .collect()
+}
+
+fn parse_layout(json: &Json) -> Option<BTreeMap<String, i64>> {
+    json.as_object()?.iter().map(|(name, offset)| Some((name.clone(), offset.as_i64()?))).collect()
+}
+
+// names, or the numbers of WAMR functions, as manifests had them before other functions could
+// be trusted
+fn parse_funcs(json: &Json) -> Option<Vec<String>> {
+    json.as_array()?
+        .iter()
+        .map(|f| match f.as_u64() {
+            Some(n) => Some(format!("aot_func#{}", n)),
+            None => Some(f.as_str()?.to_string()),
+        })
+        .collect()
+}
+
+// what a host vouches for, by number or name as with -t
+fn parse_trusted(json: &Json) -> Option<Vec<TrustedFunc>> {
+    json.as_array()?
+        .iter()
+        .map(|f| match f.as_u64() {
+            Some(n) => Some(TrustedFunc::Number(n as u32)),
+            None => f.as_str()?.parse().ok(),
+        })
+        .collect()
+}
+
+impl Assumptions {
+    /// What a verdict reached with `metadata` and the trusted functions `trusted_funcs` (by
+    /// address and name) relies on.
+    pub fn from_metadata(metadata: &CompilerMetadata, trusted_funcs: &[(u64, String)]) -> Assumptions {
+        let mut tables = BTreeMap::new();
+        let mut layout = BTreeMap::new();
+        let (entry_rdi, stack_guard_pages, globals_size) = match metadata.compiler {
+            Compiler::Lucet => {
+                tables.insert("guest_table_0".to_string(), metadata.guest_table_0);
+                tables.insert("lucet_tables".to_string(), metadata.lucet_tables);
+                tables.insert("lucet_probestack".to_string(), metadata.lucet_probestack);
+                // heap accesses may be redirected to it, so it has to be mapped
+                if let Some(page) = metadata.lucet_safe_page {
+                    tables.insert("lucet_safe_page".to_string(), page);
+                }
+                ("heap_base", 1, LucetModel.globals_size(metadata))
+            }
+            Compiler::Wamr => {
+                for (name, offset) in WAMR_LAYOUT.iter() {
+                    layout.insert(name.to_string(), *offset);
+                }
+                // the first memory takes the place of the single one, and any others are numbered
+                for (idx, memory) in metadata.wamr_memories.0.iter().enumerate() {
+                    let suffix = if idx == 0 { String::new() } else { format!("_{}", idx) };
+                    layout.insert(format!("WAMR_HEAPBASE_OFFSET{}", suffix), memory.heap_base);
+                    layout.insert(format!("WAMR_MEMBOUNDS_OFFSET{}", suffix), memory.mem_bound);
+                }
+                // the descriptor fields are offsets within each descriptor, not the ModuleInstance, and the
+                // descriptor array has to have as many tables as were assumed
+                if let Some(tables) = &metadata.wamr_tables {
+                    layout.insert("WAMR_TABLES_OFFSET".to_string(), tables.descriptors);
+                    layout.insert("WAMR_TABLE_COUNT".to_string(), tables.count as i64);
+                    layout.insert("WAMR_TABLE_SIZE_FIELD".to_string(), tables.size_field);
+                    layout.insert("WAMR_TABLE_BASE_FIELD".to_string(), tables.base_field);
+                }
+                ("exec_env", WAMR_GUARD_PAGE_COUNT, metadata.globals_size)
+            }
+            Compiler::Wasmtime => {
+                let vmctx = &metadata.wasmtime_layout;
+                for (name, offset) in vec![
+                    ("heap_base", vmctx.heap_base),
+                    ("heap_bound", vmctx.heap_bound),
+                    ("table_base", vmctx.table_base),
+                    ("table_size", vmctx.table_size),
+                    ("globals", vmctx.globals),
+                ] {
+                    layout.insert(name.to_string(), offset);
+                }
+                ("vmctx", 1, vmctx.globals_size)
+            }
+        };
+        Assumptions {
+            compiler: metadata.compiler,
+            abi: metadata.abi,
+            page_size: PAGE_SIZE,
+            heap_size: MAX_HEAP_SIZE,
+            heap_reservation: HEAP_GUARD_LIMIT,
+            entry_rdi: entry_rdi.to_string(),
+            stack_probed: ENTRY_PROBED,
+            stack_guard_pages: stack_guard_pages,
+            caller_window: metadata.caller_window,
+            strict_stack: metadata.strict_stack,
+            tables: tables,
+            globals_size: globals_size,
+            call_table_size: metadata.call_table_size,
+            layout: layout,
+            runtime_fields: metadata.runtime_fields.clone(),
+            trusted_funcs: trusted_funcs.iter().map(|(_, name)| name.clone()).collect(),
+            options: metadata.options.to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        json!({
+            "version": ASSUMPTIONS_VERSION,
+            "compiler": compiler_name(self.compiler),
+            "abi": self.abi.to_string(),
+            "page_size": self.page_size,
+            "heap": { "size": self.heap_size, "reservation": self.heap_reservation },
+            "registers": { "rdi": self.entry_rdi },
+            "stack": {
+                "probed": self.stack_probed,
+                "guard_pages": self.stack_guard_pages,
+                "caller_window": self.caller_window,
+                "strict": self.strict_stack,
+            },
+            "tables": self.tables,
+            "globals_size": self.globals_size,
+            "call_table_size": self.call_table_size,
+            "layout": self.layout,
+            "runtime_fields": self.runtime_fields.iter().map(|f| json!([f.offset, f.width])).collect::<Vec<Json>>(),
+            "trusted_funcs": self.trusted_funcs,
+            "analysis_options": self.options,
+        })
+    }
+
+    pub fn from_json(json: &Json) -> Option<Assumptions> {
+        if json.get("version")?.as_u64()? != ASSUMPTIONS_VERSION {
+            return None;
+        }
+        let heap = json.get("heap")?;
+        let stack = json.get("stack")?;
+        Some(Assumptions {
+            compiler: parse_compiler(json.get("compiler")?.as_str()?)?,
+            abi: Abi::from_str(json.get("abi")?.as_str()?).ok()?,
+            page_size: json.get("page_size")?.as_i64()?,
+            heap_size: heap.get("size")?.as_u64()?,
+            heap_reservation: heap.get("reservation")?.as_u64()?,
+            entry_rdi: json.get("registers")?.get("rdi")?.as_str()?.to_string(),
+            stack_probed: stack.get("probed")?.as_i64()?,
+            stack_guard_pages: stack.get("guard_pages")?.as_i64()?,
+            caller_window: stack.get("caller_window")?.as_i64()?,
+            strict_stack: stack.get("strict")?.as_bool()?,
+            tables: json
+                .get("tables")?
+                .as_object()?
+                .iter()
+                .map(|(name, addr)| Some((name.clone(), addr.as_u64()?)))
+                .collect::<Option<BTreeMap<String, u64>>>()?,
+            globals_size: json.get("globals_size")?.as_i64()?,
+            call_table_size: json.get("call_table_size")?.as_i64()?,
+            layout: parse_layout(json.get("layout")?)?,
+            runtime_fields: parse_fields(json.get("runtime_fields")?)?,
+            trusted_funcs: parse_funcs(json.get("trusted_funcs")?)?,
+            options: json.get("analysis_options")?.as_str()?.to_string(),
+        })
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(&self.to_json()).unwrap())
+    }
+
+    pub fn load(path: &str) -> Result<Assumptions, String> {
+        let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let json: Json = serde_json::from_str(&data).map_err(|e| format!("{}: {}", path, e))?;
+        Assumptions::from_json(&json).ok_or_else(|| format!("{}: malformed assumptions manifest", path))
+    }
+}
+
+pub const HOST_CONFIG_KEYS: [&str; 13] = [
+    "compiler",
+    "abi",
+    "page_size",
+    "heap_reservation",
+    "max_heap_size",
+    "entry_rdi",
+    "stack_guard_pages",
+    "caller_window",
+    "globals_size",
+    "call_table_size",
+    "layout",
+    "writable_fields",
+    "trusted_funcs",
+];
+
+/// What the runtime a module is loaded into provides, as far as the host knows it. Every field is
+/// optional; the assumptions about fields left out are reported as unchecked. See the README for
+/// the schema.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HostConfig {
+    pub compiler: Option<Compiler>,
+    pub abi: Option<Abi>,
+    pub page_size: Option<i64>,
+    // bytes mapped or guarded from the heap base, and the largest memory the runtime grows to
+    pub heap_reservation: Option<u64>,
+    pub max_heap_size: Option<u64>,
+    pub entry_rdi: Option<String>,
+    pub stack_guard_pages: Option<i64>,
+    // bytes above the return address the caller reserves
+    pub caller_window: Option<i64>,
+    pub globals_size: Option<i64>,
+    pub call_table_size: Option<i64>,
+    // the offsets of the runtime structures in the host's build, by manifest layout name
+    pub layout: BTreeMap<String, i64>,
+    pub writable_fields: Option<Vec<RuntimeField>>,
+    pub trusted_funcs: Option<Vec<TrustedFunc>>,
+}
+
+impl HostConfig {
+    pub fn parse(data: &str) -> Result<HostConfig, String> {
+        let json: Json = serde_json::from_str(data).map_err(|e| e.to_string())?;
+        let object: &Map<String, Json> = json.as_object().ok_or("expected a JSON object")?;
+        let mut config = HostConfig::default();
+        for (key, value) in object {
+            let invalid = || format!("invalid value for {}: {}", key, value);
+            match key.as_str() {
+                "compiler" => config.compiler = Some(value.as_str().and_then(parse_compiler).ok_or_else(invalid)?),
+                "abi" => config.abi = Some(value.as_str().and_then(|s| Abi::from_str(s).ok()).ok_or_else(invalid)?),
+                "page_size" => config.page_size = Some(value.as_i64().ok_or_else(invalid)?),
+                "heap_reservation" => config.heap_reservation = Some(value.as_u64().ok_or_else(invalid)?),
+                "max_heap_size" => config.max_heap_size = Some(value.as_u64().ok_or_else(invalid)?),
+                "entry_rdi" => config.entry_rdi = Some(value.as_str().ok_or_else(invalid)?.to_string()),
+                "stack_guard_pages" => config.stack_guard_pages = Some(value.as_i64().ok_or_else(invalid)?),
+                "caller_window" => config.caller_window = Some(value.as_i64().ok_or_else(invalid)?),
+                "globals_size" => config.globals_size = Some(value.as_i64().ok_or_else(invalid)?),
+                "call_table_size" => config.call_table_size = Some(value.as_i64().ok_or_else(invalid)?),
+                "layout" => config.layout = parse_layout(value).ok_or_else(invalid)?,
+                "writable_fields" => config.writable_fields = Some(parse_fields(value).ok_or_else(invalid)?),
+                "trusted_funcs" => config.trusted_funcs = Some(parse_trusted(value).ok_or_else(invalid)?),
+                _ => return Err(format!("unknown host config key {:?} (expected one of {:?})", key, HOST_CONFIG_KEYS)),
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &str) -> Result<HostConfig, String> {
+        let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        HostConfig::parse(&data).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AssumptionCheck {
+    // assumptions the host config contradicts
+    pub incompatible: Vec<String>,
+    // assumptions the host config says nothing about
+    pub unchecked: Vec<String>,
+}
+
+impl AssumptionCheck {
+    pub fn compatible(&self) -> bool {
+        self.incompatible.is_empty()
+    }
+}
+
+/// Compares what the verdict assumed against what the host provides.
+pub fn check_assumptions(assumed: &Assumptions, host: &HostConfig) -> AssumptionCheck {
+    let mut check = AssumptionCheck::default();
+    let unchecked = &mut check.unchecked;
+    if host.compiler.is_none() {
+        unchecked.push("compiler".to_string());
+    }
+    if host.abi.is_none() {
+        unchecked.push("abi".to_string());
+    }
+    if host.heap_reservation.is_none() {
+        unchecked.push("heap_reservation".to_string());
+    }
+    if host.max_heap_size.is_none() {
+        unchecked.push("max_heap_size".to_string());
+    }
+    if host.entry_rdi.is_none() {
+        unchecked.push("entry_rdi".to_string());
+    }
+    if host.stack_guard_pages.is_none() {
+        unchecked.push("stack_guard_pages".to_string());
+    }
+    if host.caller_window.is_none() {
+        unchecked.push("caller_window".to_string());
+    }
+    if assumed.globals_size >= 0 && host.globals_size.is_none() {
+        unchecked.push("globals_size".to_string());
+    }
+    if assumed.call_table_size >= 0 && host.call_table_size.is_none() {
+        unchecked.push("call_table_size".to_string());
+    }
+    for name in assumed.layout.keys().filter(|name| !host.layout.contains_key(*name)) {
+        unchecked.push(format!("layout.{}", name));
+    }
+    if !assumed.runtime_fields.is_empty() && host.writable_fields.is_none() {
+        unchecked.push("writable_fields".to_string());
+    }
+    if !assumed.trusted_funcs.is_empty() && host.trusted_funcs.is_none() {
+        unchecked.push("trusted_funcs".to_string());
+    }
+
+    let incompatible = &mut check.incompatible;
+    if let Some(compiler) = host.compiler {
+        if compiler != assumed.compiler {
+            incompatible.push(format!("compiler: verified as {}, host runs {}", compiler_name(assumed.compiler), compiler_name(compiler)));
+        }
+    }
+    if let Some(abi) = host.abi {
+        if abi != assumed.abi {
+            incompatible.push(format!("abi: verified as {}, host uses {}", assumed.abi, abi));
+        }
+    }
+    let page_size = host.page_size.unwrap_or(PAGE_SIZE);
+    if page_size < assumed.page_size {
+        incompatible.push(format!("page_size: host pages of {} bytes are smaller than the {} assumed", page_size, assumed.page_size));
+    }
+    if let Some(reservation) = host.heap_reservation {
+        if reservation < assumed.heap_reservation {
+            incompatible.push(format!(
+                "heap_reservation: host maps or guards 0x{:x} bytes from the heap base, 0x{:x} assumed",
+                reservation, assumed.heap_reservation
+            ));
+        }
+    }
+    if let Some(size) = host.max_heap_size {
+        if size > assumed.heap_size {
+            incompatible.push(format!("max_heap_size: host memory grows to 0x{:x} bytes, past the 0x{:x} assumed", size, assumed.heap_size));
+        }
+    }
+    if let Some(rdi) = &host.entry_rdi {
+        if *rdi != assumed.entry_rdi {
+            incompatible.push(format!("entry_rdi: host passes {} in rdi, {} assumed", rdi, assumed.entry_rdi));
+        }
+    }
+    if let Some(pages) = host.stack_guard_pages {
+        if pages * page_size < assumed.stack_guard_pages * assumed.page_size {
+            incompatible.push(format!(
+                "stack_guard_pages: host guards {} bytes below the stack, {} assumed",
+                pages * page_size,
+                assumed.stack_guard_pages * assumed.page_size
+            ));
+        }
+    }
+    if let Some(window) = host.caller_window {
+        if window < assumed.caller_window {
+            incompatible.push(format!("caller_window: host reserves {} bytes above the return address, {} assumed", window, assumed.caller_window));
+        }
+    }
+    if let Some(size) = host.globals_size {
+        if assumed.globals_size >= 0 && size < assumed.globals_size {
+            incompatible.push(format!("globals_size: host globals are {} bytes, {} assumed", size, assumed.globals_size));
+        }
+    }
+    if let Some(size) = host.call_table_size {
+        if assumed.call_table_size >= 0 && size < assumed.call_table_size {
+            incompatible.push(format!("call_table_size: host table has {} entries, {} assumed", size, assumed.call_table_size));
+        }
+    }
+    for (name, offset) in &host.layout {
+        match assumed.layout.get(name) {
+            Some(assumed_offset) if assumed_offset == offset => (),
+            Some(assumed_offset) => incompatible.push(format!("layout.{}: host offset 0x{:x}, 0x{:x} assumed", name, offset, assumed_offset)),
+            None => incompatible.push(format!("layout.{}: not a field the module was verified against", name)),
+        }
+    }
+    if let Some(fields) = &host.writable_fields {
+        for field in &assumed.runtime_fields {
+            if !fields.iter().any(|host_field| host_field.contains(field.offset, field.width)) {
+                incompatible.push(format!("writable_fields: 0x{:x} ({} bytes) is not writable in the host", field.offset, field.width));
+            }
+        }
+    }
+    if let Some(funcs) = &host.trusted_funcs {
+        for func in &assumed.trusted_funcs {
+            if !funcs.iter().any(|host_func| host_func.matches_name(func)) {
+                incompatible.push(format!("trusted_funcs: function {} was trusted, the host doesn't vouch for it", func));
+            }
+        }
+    }
+    check
+}
+
+#[cfg(test)]
+fn wamr_assumptions() -> Assumptions {
+    let metadata = CompilerMetadata {
+        globals_size: 64,
+        call_table_size: 8,
+        caller_window: 32,
+        runtime_fields: vec![RuntimeField { offset: 0x1c0, width: 4 }],
+        ..CompilerMetadata::for_test(Compiler::Wamr)
+    };
+    Assumptions::from_metadata(&metadata, &[(0x1000, "aot_func#3".to_string())])
+}
+
+#[test]
+fn assumptions_roundtrip_test() {
+    let assumed = wamr_assumptions();
+    assert_eq!(assumed.entry_rdi, "exec_env");
+    assert_eq!(assumed.stack_guard_pages, WAMR_GUARD_PAGE_COUNT);
+    assert_eq!(assumed.layout.len(), WAMR_LAYOUT.len());
+    assert!(assumed.tables.is_empty());
+    assert_eq!(Assumptions::from_json(&assumed.to_json()), Some(assumed.clone()));
+
+    // a manifest from another version isn't read as this one
+    let mut json = assumed.to_json();
+    json["version"] = json!(ASSUMPTIONS_VERSION + 1);
+    assert_eq!(Assumptions::from_json(&json), None);
+}
+
+#[test]
+fn check_assumptions_test() {
+    let assumed = wamr_assumptions();
+    let matching = HostConfig::parse(
+        r#"{
+            "compiler": "wamr",
+            "abi": "lp64",
+            "page_size": 4096,
+            "heap_reservation": 8589934592,
+            "max_heap_size": 4294967296,
+            "entry_rdi": "exec_env",
+            "stack_guard_pages": 3,
+            "caller_window": 32,
+            "globals_size": 64,
+            "call_table_size": 16,
+            "layout": { "WAMR_HEAPBASE_OFFSET": 336, "WAMR_MEMBOUNDS_OFFSET": 416 },
+            "writable_fields": [[448, 8]],
+            "trusted_funcs": [3, 4]
+        }"#,
+    )
+    .unwrap();
+    let check = check_assumptions(&assumed, &matching);
+    assert!(check.compatible(), "{:?}", check.incompatible);
+    // only the layout offsets the host left out
+    assert_eq!(check.unchecked.len(), WAMR_LAYOUT.len() - 2);
+    assert!(check.unchecked.contains(&"layout.WAMR_PAGECNT_OFFSET".to_string()));
+
+    // a host that says nothing contradicts nothing, but leaves everything unchecked
+    let check = check_assumptions(&assumed, &HostConfig::default());
+    assert!(check.compatible());
+    assert!(check.unchecked.contains(&"heap_reservation".to_string()));
+    assert!(check.unchecked.contains(&"trusted_funcs".to_string()));
+
+    let mismatching = HostConfig::parse(
+        r#"{
+            "compiler": "wamr",
+            "heap_reservation": 4294967296,
+            "entry_rdi": "heap_base",
+            "stack_guard_pages": 1,
+            "call_table_size": 4,
+            "layout": { "WAMR_HEAPBASE_OFFSET": 344, "WAMR_TLS_OFFSET": 8 },
+            "writable_fields": [[448, 2]],
+            "trusted_funcs": []
+        }"#,
+    )
+    .unwrap();
+    let check = check_assumptions(&assumed, &mismatching);
+    let failed: Vec<&str> = check.incompatible.iter().map(|msg| msg.split(':').next().unwrap()).collect();
+    assert_eq!(
+        failed,
+        vec![
+            "heap_reservation",
+            "entry_rdi",
+            "stack_guard_pages",
+            "call_table_size",
+            "layout.WAMR_HEAPBASE_OFFSET",
+            "layout.WAMR_TLS_OFFSET",
+            "writable_fields",
+            "trusted_funcs",
+        ]
+    );
+
+    // larger pages make up for fewer of them
+    let big_pages = HostConfig::parse(r#"{ "page_size": 16384, "stack_guard_pages": 1 }"#).unwrap();
+    assert!(check_assumptions(&assumed, &big_pages).compatible());
+    let small_pages = HostConfig::parse(r#"{ "page_size": 1024, "stack_guard_pages": 12 }"#).unwrap();
+    assert!(!check_assumptions(&assumed, &small_pages).compatible());
+
+    // a host may vouch for a function by name too, and a manifest of numbers names WAMR functions
+    let by_name = HostConfig::parse(r#"{ "trusted_funcs": ["aot_func#3"] }"#).unwrap();
+    assert!(check_assumptions(&assumed, &by_name).compatible());
+    let other = HostConfig::parse(r#"{ "trusted_funcs": ["guest_func_3", "0x1000"] }"#).unwrap();
+    assert!(!check_assumptions(&assumed, &other).compatible());
+    let mut json = assumed.to_json();
+    json["trusted_funcs"] = json!([3]);
+    assert_eq!(Assumptions::from_json(&json), Some(assumed.clone()));
+}
+
+#[test]
+fn host_config_parse_test() {
+    assert_eq!(HostConfig::parse("{}"), Ok(HostConfig::default()));
+    assert!(HostConfig::parse(r#"{ "heap_guard": 4096 }"#).is_err());
+    assert!(HostConfig::parse(r#"{ "compiler": "v8" }"#).is_err());
+    assert!(HostConfig::parse(r#"{ "stack_guard_pages": "three" }"#).is_err());
+    assert!(HostConfig::parse("[]").is_err());
+}
@@ -0,0 +1,247 @@
+//! Soundness caveats: the shortcuts and heuristics a verdict rests on.
+//!
+//! A function can verify only because the lifter skipped an instruction it doesn't model, or
+//! checked a vector access as 8 bytes wide, and a module only because some of its functions were
+//! trusted, skipped or taken to be native. Each of these is recorded as a caveat where it is
+//! taken, and reported with the verdict rather than only in the log. `--deny-caveats` turns the
+//! kinds named into failures.
+//!
+//! Caveats found while a function is verified are collected per thread: `collect_caveats` wraps
+//! the verification of one function and returns what `record_caveat` recorded meanwhile. Caveats
+//! recorded outside of `collect_caveats` (e.g. while lifting prologues) are dropped.
+
+use serde_json::{json, Value as Json};
+use std::cell::RefCell;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CaveatKind {
+    // an instruction the lifter doesn't model, lifted as nothing
+    UnliftedInstruction,
+    // a memory operand as wide as an xmm or ymm register, checked as 8 bytes wide
+    AssumedWidth,
+    // a function named with -t, callable without being verified
+    TrustedFunction,
+    // a Wamr runtime function AOT code calls, found by its symbol name
+    NativeFunction,
+    // a Wamr function left unverified (and uncallable) for not starting like an AOT function
+    SkippedFunction,
+    // a function start inside the PLT range, which was cut short there
+    ShrunkPlt,
+    // a function lifted differently from its golden IR (see utils::ir_golden)
+    IrDrift,
+    // an entry of a function table that points outside the code section
+    TableEntry,
+}
+
+pub const CAVEAT_KINDS: [CaveatKind; 8] = [
+    CaveatKind::UnliftedInstruction,
+    CaveatKind::AssumedWidth,
+    CaveatKind::TrustedFunction,
+    CaveatKind::NativeFunction,
+    CaveatKind::SkippedFunction,
+    CaveatKind::ShrunkPlt,
+    CaveatKind::IrDrift,
+    CaveatKind::TableEntry,
+];
+
+impl CaveatKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CaveatKind::UnliftedInstruction => "unlifted_instruction",
+            CaveatKind::AssumedWidth => "assumed_width",
+            CaveatKind::TrustedFunction => "trusted_function",
+            CaveatKind::NativeFunction => "native_function",
+            CaveatKind::SkippedFunction => "skipped_function",
+            CaveatKind::ShrunkPlt => "shrunk_plt",
+            CaveatKind::IrDrift => "ir_drift",
+            CaveatKind::TableEntry => "table_entry",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CaveatKind> {
+        CAVEAT_KINDS.iter().find(|kind| kind.name() == name).copied()
+    }
+}
+
+impl fmt::Display for CaveatKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Parses the comma-separated caveat kinds of `--deny-caveats`; `all` names every kind.
+pub fn parse_caveat_kinds(s: &str) -> Result<Vec<CaveatKind>, String> {
+    let mut kinds = vec![];
+    for name in s.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+        if name == "all" {
+            kinds.extend(CAVEAT_KINDS.iter());
+            continue;
+        }
+        match CaveatKind::from_name(name) {
+            Some(kind) => kinds.push(kind),
+            None => {
+                let names: Vec<&str> = CAVEAT_KINDS.iter().map(|kind| kind.name()).collect();
+                return Err(format!("unknown caveat kind {:?} (expected all or one of {})", name, names.join(", ")));
+            }
+        }
+    }
+    kinds.sort();
+    kinds.dedup();
+    Ok(kinds)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SoundnessCaveat {
+    pub kind: CaveatKind,
+    // the function it was found while verifying, None for the module's caveats
+    pub function: Option<String>,
+    pub addr: Option<u64>,
+    pub description: String,
+    // how often it was taken at this location
+    pub count: usize,
+    // its kind was named with --deny-caveats, so the module doesn't verify
+    pub denied: bool,
+}
+
+impl SoundnessCaveat {
+    pub fn new(kind: CaveatKind, addr: Option<u64>, description: String) -> Self {
+        SoundnessCaveat {
+            kind: kind,
+            function: None,
+            addr: addr,
+            description: description,
+            count: 1,
+            denied: false,
+        }
+    }
+
+    pub fn location(&self) -> String {
+        match (&self.function, self.addr) {
+            (Some(function), Some(addr)) => format!("{} at 0x{:x}", function, addr),
+            (Some(function), None) => function.clone(),
+            (None, Some(addr)) => format!("0x{:x}", addr),
+            (None, None) => "module".to_string(),
+        }
+    }
+
+    /// The opcode of a skipped instruction, as the lifter describes it (`unimplemented instr
+    /// <opcode>: <instr>`, or `invalid instr`).
+    pub fn opcode(&self) -> Option<&str> {
+        if self.kind != CaveatKind::UnliftedInstruction {
+            return None;
+        }
+        if self.description == "invalid instr" {
+            return Some("Invalid");
+        }
+        let rest = self.description.strip_prefix("unimplemented instr ")?;
+        rest.find(':').map(|end| &rest[..end])
+    }
+
+    pub fn to_json(&self) -> Json {
+        json!({
+            "kind": self.kind.name(),
+            "function": self.function,
+            "addr": self.addr,
+            "description": self.description,
+            "count": self.count,
+            "denied": self.denied,
+        })
+    }
+
+    pub fn from_json(json: &Json) -> Option<SoundnessCaveat> {
+        Some(SoundnessCaveat {
+            kind: CaveatKind::from_name(json.get("kind")?.as_str()?)?,
+            function: json.get("function").and_then(|f| f.as_str()).map(|f| f.to_string()),
+            addr: json.get("addr").and_then(|a| a.as_u64()),
+            description: json.get("description")?.as_str()?.to_string(),
+            count: json.get("count")?.as_u64()? as usize,
+            denied: json.get("denied").and_then(|d| d.as_bool()).unwrap_or(false),
+        })
+    }
+}
+
+/// Adds `caveat` to `caveats`, counting it with an earlier one of the same kind at the same
+/// location instead (an instruction is lifted again each time its block is).
+pub fn merge_caveat(caveats: &mut Vec<SoundnessCaveat>, caveat: SoundnessCaveat) {
+    let same = caveats
+        .iter_mut()
+        .find(|c| c.kind == caveat.kind && c.function == caveat.function && c.addr == caveat.addr);
+    match same {
+        Some(earlier) => earlier.count += caveat.count,
+        None => caveats.push(caveat),
+    }
+}
+
+thread_local! {
+    static COLLECTED: RefCell<Option<Vec<SoundnessCaveat>>> = RefCell::new(None);
+}
+
+/// Records a caveat taken on the current thread, if caveats are being collected.
+pub fn record_caveat(kind: CaveatKind, addr: Option<u64>, description: String) {
+    COLLECTED.with(|collected| {
+        if let Some(caveats) = collected.borrow_mut().as_mut() {
+            merge_caveat(caveats, SoundnessCaveat::new(kind, addr, description));
+        }
+    });
+}
+
+/// Records caveats taken earlier again on the current thread (e.g. those of a CFG read back from
+/// the CFG cache instead of being lifted again), if caveats are being collected.
+pub fn record_caveats(caveats: Vec<SoundnessCaveat>) {
+    COLLECTED.with(|collected| {
+        if let Some(collected) = collected.borrow_mut().as_mut() {
+            for caveat in caveats {
+                merge_caveat(collected, caveat);
+            }
+        }
+    });
+}
+
+/// Runs `f`, returning what it returns and the caveats recorded on this thread while it ran.
+pub fn collect_caveats<T, F: FnOnce() -> T>(f: F) -> (T, Vec<SoundnessCaveat>) {
+    let outer = COLLECTED.with(|collected| collected.replace(Some(vec![])));
+    let result = f();
+    let caveats = COLLECTED.with(|collected| collected.replace(outer)).unwrap_or_default();
+    (result, caveats)
+}
+
+#[test]
+fn caveat_kinds_test() {
+    for kind in CAVEAT_KINDS.iter() {
+        assert_eq!(CaveatKind::from_name(kind.name()), Some(*kind));
+    }
+    assert_eq!(
+        parse_caveat_kinds("trusted_function, unlifted_instruction,trusted_function"),
+        Ok(vec![CaveatKind::UnliftedInstruction, CaveatKind::TrustedFunction])
+    );
+    assert_eq!(parse_caveat_kinds("all").unwrap().len(), CAVEAT_KINDS.len());
+    assert_eq!(parse_caveat_kinds(""), Ok(vec![]));
+    assert!(parse_caveat_kinds("unlifted").is_err());
+}
+
+#[test]
+fn collect_caveats_test() {
+    record_caveat(CaveatKind::AssumedWidth, Some(0x10), "dropped".to_string());
+    let (result, caveats) = collect_caveats(|| {
+        record_caveat(CaveatKind::UnliftedInstruction, Some(0x20), "unimplemented instr: cpuid".to_string());
+        record_caveat(CaveatKind::UnliftedInstruction, Some(0x20), "unimplemented instr: cpuid".to_string());
+        let (_, inner) = collect_caveats(|| record_caveat(CaveatKind::AssumedWidth, Some(0x30), "inner".to_string()));
+        assert_eq!(inner.len(), 1);
+        record_caveat(CaveatKind::UnliftedInstruction, Some(0x28), "unimplemented instr: rdtsc".to_string());
+        7
+    });
+    assert_eq!(result, 7);
+    assert_eq!(caveats.len(), 2);
+    assert_eq!((caveats[0].addr, caveats[0].count), (Some(0x20), 2));
+    assert_eq!((caveats[1].addr, caveats[1].count), (Some(0x28), 1));
+    assert_eq!(caveats[0].location(), "0x20");
+    assert_eq!(caveats[0].to_json()["kind"], "unlifted_instruction");
+    assert_eq!(SoundnessCaveat::from_json(&caveats[0].to_json()).as_ref(), Some(&caveats[0]));
+    assert_eq!(collect_caveats(|| ()).1, vec![]);
+    let (_, replayed) = collect_caveats(|| {
+        record_caveats(caveats.clone());
+        record_caveats(caveats.clone());
+    });
+    assert_eq!((replayed.len(), replayed[0].count), (2, 4));
+}
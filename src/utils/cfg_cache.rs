@@ -0,0 +1,197 @@
+use crate::utils::caveats::SoundnessCaveat;
+use crate::utils::failure_cache::verifier_key;
+use crate::utils::ir::{IRMap, InstrOrigin, InstrProvenance};
+use crate::utils::persist::{block_from_json, block_to_json};
+use serde_json::{json, Value as Json};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use yaxpeax_core::analyses::control_flow::{VW_Block, VW_CFG};
+
+/*
+ * CFG cache (`--cache-dir <dir>`): the resolved CFG of each function, with its IR, kept across
+ * runs so that verifying a module again can go straight to the analyses. Building the CFG
+ * (disassembling, lifting, and resolving jump tables round by round) is most of the time spent
+ * on large modules, and is deterministic given the module, the metadata and the verifier.
+ *
+ * An entry is `<dir>/<module file name>/<addr>.json`, holding the blocks and edges of the CFG,
+ * the IR of each block in the persisted IR schema (see persist.rs) with its provenance, and the
+ * soundness caveats taken while lifting it, which are reported again on a hit. Every entry
+ * carries the key of the run that wrote it, a hash of the module, of everything else the CFG
+ * depends on (the flags of failure_cache.rs, with the CFG limits and patches) and of the verifier
+ * itself, so a changed module or a rebuilt veriwasm misses and overwrites it.
+ */
+
+// bumped when the entries change
+const CFG_CACHE_VERSION: u64 = 1;
+
+pub struct CfgCache {
+    dir: PathBuf,
+    key: u64,
+}
+
+fn origin_name(origin: InstrOrigin) -> &'static str {
+    match origin {
+        InstrOrigin::Lifted => "lifted",
+        InstrOrigin::Synthesized => "synthesized",
+        InstrOrigin::ConsumedByProbestack => "probestack",
+    }
+}
+
+fn origin_from_name(name: &str) -> Option<InstrOrigin> {
+    match name {
+        "lifted" => Some(InstrOrigin::Lifted),
+        "synthesized" => Some(InstrOrigin::Synthesized),
+        "probestack" => Some(InstrOrigin::ConsumedByProbestack),
+        _ => None,
+    }
+}
+
+impl CfgCache {
+    /// The cache in `dir` for the module named `module_name` with contents `module`, verified
+    /// with flags `flags`.
+    pub fn new(dir: &str, module_name: &str, module: &[u8], flags: u64) -> Self {
+        let mut hasher = DefaultHasher::new();
+        CFG_CACHE_VERSION.hash(&mut hasher);
+        verifier_key().hash(&mut hasher);
+        module.hash(&mut hasher);
+        flags.hash(&mut hasher);
+        CfgCache {
+            dir: Path::new(dir).join(module_name.replace('/', "_")),
+            key: hasher.finish(),
+        }
+    }
+
+    fn path(&self, addr: u64) -> PathBuf {
+        self.dir.join(format!("{:x}.json", addr))
+    }
+
+    /// The CFG and IR of the function at `addr`, with the caveats taken lifting it, if the cache
+    /// has them for this module and verifier.
+    pub fn load(&self, addr: u64) -> Option<(VW_CFG, IRMap, Vec<SoundnessCaveat>)> {
+        let data = fs::read_to_string(self.path(addr)).ok()?;
+        let json: Json = serde_json::from_str(&data).ok()?;
+        if json.get("key")?.as_u64()? != self.key || json.get("addr")?.as_u64()? != addr {
+            return None;
+        }
+        entry_from_json(&json)
+    }
+
+    /// Writes the CFG and IR of the function at `addr` to the cache, replacing what it had.
+    pub fn store(&self, addr: u64, cfg: &VW_CFG, irmap: &IRMap, caveats: &[SoundnessCaveat]) -> std::io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let mut entry = entry_to_json(cfg, irmap, caveats);
+        entry["key"] = json!(self.key);
+        entry["addr"] = json!(addr);
+        let path = self.path(addr);
+        fs::write(&path, entry.to_string())?;
+        Ok(path)
+    }
+}
+
+/// The CFG and IR of a function, with the caveats taken lifting it, as a cache entry holds them
+/// (also the body of a repro bundle, see minimize.rs).
+pub fn entry_to_json(cfg: &VW_CFG, irmap: &IRMap, caveats: &[SoundnessCaveat]) -> Json {
+    let blocks: Vec<Json> = cfg.blocks.values().map(|block| json!([block.start, block.end])).collect();
+    let edges: Vec<Json> = cfg.graph.all_edges().map(|(from, to, _)| json!([from, to])).collect();
+    let mut addrs = irmap.keys();
+    addrs.sort();
+    let ir: Vec<Json> = addrs
+        .iter()
+        .filter_map(|addr| {
+            let block = irmap.get(addr)?;
+            let provenance = irmap.provenance(addr).map(|provenance| {
+                provenance.iter().map(|instr| json!([instr.len, origin_name(instr.origin)])).collect::<Vec<Json>>()
+            });
+            Some(json!({"addr": addr, "block": block_to_json(&block), "provenance": provenance}))
+        })
+        .collect();
+    let caveats: Vec<Json> = caveats.iter().map(|caveat| caveat.to_json()).collect();
+    json!({
+        "entrypoint": cfg.entrypoint,
+        "blocks": blocks,
+        "edges": edges,
+        "ir": ir,
+        "caveats": caveats,
+    })
+}
+
+pub fn entry_from_json(json: &Json) -> Option<(VW_CFG, IRMap, Vec<SoundnessCaveat>)> {
+    let mut cfg = VW_CFG::new(json.get("entrypoint")?.as_u64()?);
+    for block in json.get("blocks")?.as_array()? {
+        let (start, end) = (block.get(0)?.as_u64()?, block.get(1)?.as_u64()?);
+        cfg.blocks.insert(start, VW_Block { start: start, end: end });
+        cfg.graph.add_node(start);
+    }
+    for edge in json.get("edges")?.as_array()? {
+        cfg.graph.add_edge(edge.get(0)?.as_u64()?, edge.get(1)?.as_u64()?, ());
+    }
+    let mut irmap = IRMap::new();
+    for entry in json.get("ir")?.as_array()? {
+        let addr = entry.get("addr")?.as_u64()?;
+        let block = block_from_json(entry.get("block")?)?;
+        match entry.get("provenance")?.as_array() {
+            Some(instrs) => {
+                let mut provenance = vec![];
+                for instr in instrs {
+                    let len = instr.get(0)?.as_u64()? as u8;
+                    provenance.push(InstrProvenance { len: len, origin: origin_from_name(instr.get(1)?.as_str()?)? });
+                }
+                if provenance.len() != block.len() {
+                    return None;
+                }
+                irmap.insert_lifted(addr, block, provenance);
+            }
+            None => irmap.insert(addr, block),
+        }
+    }
+    let mut caveats = vec![];
+    for caveat in json.get("caveats")?.as_array()? {
+        caveats.push(SoundnessCaveat::from_json(caveat)?);
+    }
+    Some((cfg, irmap, caveats))
+}
+
+#[test]
+fn cfg_cache_test() {
+    use crate::utils::caveats::CaveatKind;
+    use crate::utils::ir::{Stmt, ValSize, Value};
+
+    let dir = std::env::temp_dir().join("veriwasm_cfg_cache_test");
+    let _ = fs::remove_dir_all(&dir);
+    let dir = dir.to_string_lossy().to_string();
+    let mut cfg = VW_CFG::new(0x1000);
+    for (start, end) in [(0x1000, 0x1005), (0x1006, 0x1006)].iter() {
+        cfg.blocks.insert(*start, VW_Block { start: *start, end: *end });
+        cfg.graph.add_node(*start);
+    }
+    cfg.graph.add_edge(0x1000, 0x1006, ());
+    let mut irmap = IRMap::new();
+    let lifted = |len: u8| InstrProvenance { len: len, origin: InstrOrigin::Lifted };
+    irmap.insert_lifted(0x1000, vec![(0x1000, vec![Stmt::Clear(Value::Reg(0, ValSize::Size64), vec![])]), (0x1003, vec![])], vec![lifted(3), lifted(3)]);
+    irmap.insert(0x1006, vec![(0x1006, vec![Stmt::Ret])]);
+    let caveats = vec![SoundnessCaveat::new(CaveatKind::UnliftedInstruction, Some(0x1003), "unimplemented instr: cpuid".to_string())];
+
+    // a stored function reads back as it was
+    let cache = CfgCache::new(&dir, "lib/module.so", b"module", 7);
+    assert_eq!(cache.load(0x1000).map(|_| ()), None);
+    let path = cache.store(0x1000, &cfg, &irmap, &caveats).unwrap();
+    assert!(path.ends_with("lib_module.so/1000.json"));
+    let (cached_cfg, cached_irmap, cached_caveats) = cache.load(0x1000).unwrap();
+    assert_eq!(format!("{:?}", cached_cfg.blocks), format!("{:?}", cfg.blocks));
+    let edges = |cfg: &VW_CFG| cfg.graph.all_edges().map(|(from, to, _)| (from, to)).collect::<Vec<_>>();
+    assert_eq!(edges(&cached_cfg), edges(&cfg));
+    assert_eq!(format!("{:?}", cached_irmap), format!("{:?}", irmap));
+    assert_eq!(cached_irmap.provenance(&0x1000), irmap.provenance(&0x1000));
+    assert_eq!(cached_irmap.provenance(&0x1006), None);
+    assert_eq!(cached_caveats, caveats);
+
+    // but not for another module, or other flags, until it is stored again
+    assert!(CfgCache::new(&dir, "lib/module.so", b"module'", 7).load(0x1000).is_none());
+    let other_flags = CfgCache::new(&dir, "lib/module.so", b"module", 8);
+    assert!(other_flags.load(0x1000).is_none());
+    other_flags.store(0x1000, &cfg, &irmap, &[]).unwrap();
+    assert_eq!(other_flags.load(0x1000).map(|(_, _, caveats)| caveats.len()), Some(0));
+    assert!(cache.load(0x1000).is_none());
+}
@@ -0,0 +1,325 @@
+use crate::analyses::ControlFlow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use yaxpeax_x86::long_mode::Opcode;
+
+/*
+ * Normalization of the basic blocks the CFG layer recovers, before they are lifted.
+ *
+ * Block ends are inclusive (the last byte of the block). A jump target found in the middle of a
+ * block that was already discovered can leave the old, unsplit block behind next to the new
+ * pieces, so that two blocks overlap; splitting a block at its own start leaves an empty head
+ * (its end before its start). Lifting either shape gives duplicate or empty IR, and a statement
+ * checked twice under different states. Overlaps are resolved by re-splitting the stale block,
+ * empty blocks are dropped with their edges redirected, and a graph that can't be made
+ * consistent is an error for the function. Afterwards blocks are disjoint and non-empty.
+ */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl BlockRange {
+    pub fn is_empty(&self) -> bool {
+        self.end < self.start
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.start <= addr && addr <= self.end
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockGraph {
+    pub entrypoint: u64,
+    // keyed by start address
+    pub blocks: BTreeMap<u64, BlockRange>,
+    pub edges: BTreeSet<(u64, u64)>,
+}
+
+/// An instruction the lifter can't make sense of, which makes its function unverifiable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LiftError {
+    pub addr: u64,
+    pub opcode: Opcode,
+    // what is wrong with the instruction
+    pub reason: String,
+}
+
+impl fmt::Display for LiftError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "can't lift {:?} at 0x{:x}: {}", self.opcode, self.addr, self.reason)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CfgError {
+    MissingEntry(u64),
+    // a node of the graph without a block
+    MissingBlock(u64),
+    // an edge from or to an address that doesn't start a block
+    DanglingEdge(u64, u64),
+    // the second block starts inside the first, but no block ends where the first one does
+    UnresolvableOverlap(u64, u64),
+    // the entry block is empty and doesn't fall through to exactly one block
+    EmptyEntry(u64),
+    // a multi-instruction pattern the lifter recognizes is split across the edge between two
+    // blocks in a way it can't be put back together
+    SplitPattern(&'static str, u64, u64),
+    // resolving the switches of the function at the address didn't settle within the rounds given
+    ResolutionLimit(u64, usize),
+    // a round of resolution dropped a target (of the switch at the second address) that an
+    // earlier one found, so the rounds may go on disagreeing
+    UnstableTargets(u64, u64, i64),
+    // the switches of the function have more targets than the limit
+    TooManyTargets(u64, usize, usize),
+    // an instruction of the function can't be lifted
+    Lift(LiftError),
+}
+
+impl From<LiftError> for CfgError {
+    fn from(e: LiftError) -> Self {
+        CfgError::Lift(e)
+    }
+}
+
+impl fmt::Display for CfgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CfgError::MissingEntry(addr) => write!(f, "no block at the entrypoint 0x{:x}", addr),
+            CfgError::MissingBlock(addr) => write!(f, "no block for the node 0x{:x}", addr),
+            CfgError::DanglingEdge(from, to) => write!(f, "edge 0x{:x} -> 0x{:x} between unknown blocks", from, to),
+            CfgError::UnresolvableOverlap(first, second) => {
+                write!(f, "block 0x{:x} overlaps block 0x{:x} and can't be re-split", first, second)
+            }
+            CfgError::EmptyEntry(addr) => write!(f, "empty entry block 0x{:x}", addr),
+            CfgError::SplitPattern(pattern, from, to) => {
+                write!(f, "{} sequence split across the edge 0x{:x} -> 0x{:x}", pattern, from, to)
+            }
+            CfgError::ResolutionLimit(addr, rounds) => {
+                write!(f, "the indirect jumps of 0x{:x} were still being resolved after {} rounds", addr, rounds)
+            }
+            CfgError::UnstableTargets(addr, switch, target) => write!(
+                f,
+                "resolving the indirect jumps of 0x{:x} doesn't converge: the switch at 0x{:x} lost its target 0x{:x}",
+                addr, switch, target
+            ),
+            CfgError::TooManyTargets(addr, targets, limit) => {
+                write!(f, "the switches of 0x{:x} have {} targets, more than the limit of {}", addr, targets, limit)
+            }
+            CfgError::Lift(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl BlockGraph {
+    fn successors_of(&self, addr: u64) -> Vec<u64> {
+        self.edges.iter().filter(|(from, _)| *from == addr).map(|(_, to)| *to).collect()
+    }
+
+    fn predecessors_of(&self, addr: u64) -> Vec<u64> {
+        self.edges.iter().filter(|(_, to)| *to == addr).map(|(from, _)| *from).collect()
+    }
+
+    fn remove_edges_of(&mut self, addr: u64) {
+        self.edges.retain(|(from, to)| *from != addr && *to != addr);
+    }
+}
+
+impl ControlFlow for BlockGraph {
+    fn entrypoint(&self) -> u64 {
+        self.entrypoint
+    }
+
+    fn successors(&self, addr: u64) -> Vec<u64> {
+        self.successors_of(addr)
+    }
+}
+
+/// Splits a stale block at the start of the first block inside it. The stale block's edges come
+/// from its last instruction, so they move to the block that now ends where it did, and what's
+/// left of it falls through to the block it overlapped.
+fn resplit(graph: &mut BlockGraph, stale: BlockRange, next: u64) -> Result<(), CfgError> {
+    let tail = graph
+        .blocks
+        .values()
+        .find(|b| b.start > stale.start && !b.is_empty() && b.contains(stale.end))
+        .copied();
+    match tail {
+        Some(tail) if tail.end == stale.end => {
+            for succ in graph.successors_of(stale.start) {
+                graph.edges.insert((tail.start, succ));
+            }
+            graph.edges.retain(|(from, _)| *from != stale.start);
+            graph.edges.insert((stale.start, next));
+            graph.blocks.insert(stale.start, BlockRange { start: stale.start, end: next - 1 });
+            Ok(())
+        }
+        _ => Err(CfgError::UnresolvableOverlap(stale.start, next)),
+    }
+}
+
+/// Drops an empty block, connecting its predecessors straight to its successors.
+fn bypass(graph: &mut BlockGraph, empty: u64) -> Result<(), CfgError> {
+    let succs: Vec<u64> = graph.successors_of(empty).into_iter().filter(|s| *s != empty).collect();
+    if graph.entrypoint == empty {
+        match succs.as_slice() {
+            [succ] => graph.entrypoint = *succ,
+            _ => return Err(CfgError::EmptyEntry(empty)),
+        }
+    }
+    for pred in graph.predecessors_of(empty).into_iter().filter(|p| *p != empty) {
+        for succ in &succs {
+            graph.edges.insert((pred, *succ));
+        }
+    }
+    graph.remove_edges_of(empty);
+    graph.blocks.remove(&empty);
+    Ok(())
+}
+
+/// Makes the blocks of `graph` disjoint and non-empty. Returns the number of blocks re-split or
+/// dropped.
+pub fn normalize_blocks(graph: &mut BlockGraph) -> Result<usize, CfgError> {
+    if !graph.blocks.contains_key(&graph.entrypoint) {
+        return Err(CfgError::MissingEntry(graph.entrypoint));
+    }
+    if let Some((from, to)) = graph
+        .edges
+        .iter()
+        .find(|(from, to)| !graph.blocks.contains_key(from) || !graph.blocks.contains_key(to))
+    {
+        return Err(CfgError::DanglingEdge(*from, *to));
+    }
+    let mut fixes = 0;
+
+    let starts: Vec<u64> = graph.blocks.keys().copied().collect();
+    for (start, next) in starts.iter().zip(starts.iter().skip(1)) {
+        let block = graph.blocks[start];
+        if !block.is_empty() && block.end >= *next {
+            resplit(graph, block, *next)?;
+            fixes += 1;
+        }
+    }
+
+    let empty: Vec<u64> = graph.blocks.values().filter(|b| b.is_empty()).map(|b| b.start).collect();
+    for start in empty {
+        bypass(graph, start)?;
+        fixes += 1;
+    }
+    Ok(fixes)
+}
+
+#[cfg(test)]
+fn block_graph(entrypoint: u64, blocks: &[(u64, u64)], edges: &[(u64, u64)]) -> BlockGraph {
+    BlockGraph {
+        entrypoint: entrypoint,
+        blocks: blocks.iter().map(|(start, end)| (*start, BlockRange { start: *start, end: *end })).collect(),
+        edges: edges.iter().copied().collect(),
+    }
+}
+
+#[test]
+fn overlapping_blocks_test() {
+    // 0x0..0x11 is the stale unsplit block; 0x8 was found to be a jump target and split off
+    let mut graph = block_graph(
+        0x0,
+        &[(0x0, 0x11), (0x8, 0x11), (0x12, 0x16)],
+        &[(0x0, 0x8), (0x0, 0x12), (0x8, 0x8), (0x8, 0x12)],
+    );
+    assert_eq!(normalize_blocks(&mut graph), Ok(1));
+    assert_eq!(graph, block_graph(
+        0x0,
+        &[(0x0, 0x7), (0x8, 0x11), (0x12, 0x16)],
+        &[(0x0, 0x8), (0x8, 0x8), (0x8, 0x12)],
+    ));
+    // normalizing again changes nothing
+    assert_eq!(normalize_blocks(&mut graph), Ok(0));
+
+    // the stale block's edges move to its tail, even if the tail didn't have them yet
+    let mut graph = block_graph(0x0, &[(0x0, 0x11), (0x8, 0x11), (0x12, 0x16)], &[(0x0, 0x12)]);
+    assert_eq!(normalize_blocks(&mut graph), Ok(1));
+    assert_eq!(graph.edges, [(0x0, 0x8), (0x8, 0x12)].iter().copied().collect());
+
+    // a jump into the middle of an instruction: no block ends where the stale one does
+    let mut graph = block_graph(0x0, &[(0x0, 0x11), (0x9, 0x14)], &[(0x0, 0x9)]);
+    assert_eq!(normalize_blocks(&mut graph), Err(CfgError::UnresolvableOverlap(0x0, 0x9)));
+}
+
+#[test]
+fn empty_blocks_test() {
+    // 0x10 is an empty head between 0x0 and 0x20, which both jump to it
+    let mut graph = block_graph(
+        0x0,
+        &[(0x0, 0xf), (0x10, 0xf), (0x20, 0x2f), (0x30, 0x3f)],
+        &[(0x0, 0x10), (0x20, 0x10), (0x10, 0x30), (0x10, 0x10)],
+    );
+    assert_eq!(normalize_blocks(&mut graph), Ok(1));
+    assert_eq!(graph, block_graph(
+        0x0,
+        &[(0x0, 0xf), (0x20, 0x2f), (0x30, 0x3f)],
+        &[(0x0, 0x30), (0x20, 0x30)],
+    ));
+
+    // an empty entry falls through to the real one
+    let mut graph = block_graph(0x4, &[(0x4, 0x3), (0x8, 0xf)], &[(0x4, 0x8)]);
+    assert_eq!(normalize_blocks(&mut graph), Ok(1));
+    assert_eq!(graph.entrypoint, 0x8);
+    let mut graph = block_graph(0x4, &[(0x4, 0x3), (0x8, 0xf), (0x10, 0x1f)], &[(0x4, 0x8), (0x4, 0x10)]);
+    assert_eq!(normalize_blocks(&mut graph), Err(CfgError::EmptyEntry(0x4)));
+
+    let mut graph = block_graph(0x0, &[(0x0, 0xf)], &[(0x0, 0x40)]);
+    assert_eq!(normalize_blocks(&mut graph), Err(CfgError::DanglingEdge(0x0, 0x40)));
+    let mut graph = block_graph(0x8, &[(0x0, 0xf)], &[]);
+    assert_eq!(normalize_blocks(&mut graph), Err(CfgError::MissingEntry(0x8)));
+}
+
+#[test]
+fn mid_block_jump_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::stack_analyzer::StackAnalyzer;
+    use crate::checkers::stack_checker::check_stack;
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::ir::{Binopcode, IRMap, ImmType, Stmt, Unopcode, ValSize, Value};
+    use yaxpeax_x86::long_mode::Opcode;
+
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let rax = Value::Reg(0, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    // sub rsp, 8; xor eax, eax; loop: add rax, 1; cmp rax, 10; jne loop; add rsp, 8; ret
+    let program = vec![
+        (0x0, Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm(8))),
+        (0x4, Stmt::Unop(Unopcode::Mov, rax.clone(), imm(0))),
+        (0x8, Stmt::Binop(Binopcode::Add, rax.clone(), rax.clone(), imm(1))),
+        (0xc, Stmt::Binop(Binopcode::Cmp, rax.clone(), rax.clone(), imm(10))),
+        (0x10, Stmt::Branch(Opcode::JNE, imm(0x8))),
+        (0x12, Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), imm(8))),
+        (0x16, Stmt::Ret),
+    ];
+    // the CFG layer found the jump to 0x8 after recovering 0x0..0x11 whole
+    let mut graph = block_graph(
+        0x0,
+        &[(0x0, 0x11), (0x8, 0x11), (0x12, 0x16)],
+        &[(0x0, 0x8), (0x0, 0x12), (0x8, 0x8), (0x8, 0x12)],
+    );
+    normalize_blocks(&mut graph).unwrap();
+
+    // lifted the way lift_cfg does, each block from its own start to its (inclusive) end
+    let mut irmap = IRMap::new();
+    for block in graph.blocks.values() {
+        let stmts = program.iter().filter(|(addr, _)| block.contains(*addr)).map(|(addr, stmt)| (*addr, vec![stmt.clone()]));
+        irmap.insert(block.start, stmts.collect());
+    }
+    // every instruction is lifted exactly once
+    assert_eq!(irmap.iter().map(|(_, block)| block.len()).sum::<usize>(), program.len());
+
+    let analyzer = StackAnalyzer {
+        metadata: CompilerMetadata::for_test(Compiler::Lucet),
+        stack_limit_cmps: Default::default(),
+    };
+    let result = run_worklist(&graph, &irmap, &analyzer);
+    assert_eq!(result, run_worklist(&graph, &irmap, &analyzer));
+    assert!(check_stack(result, &irmap, &analyzer).is_ok());
+}
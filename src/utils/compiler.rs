@@ -0,0 +1,436 @@
+use crate::lattices::heaplattice::{
+    HeapValue, WAMR_FUNCPTRS_OFFSET, WAMR_FUNCTYPE_OFFSET, WAMR_GLOBALS_OFFSET, WAMR_MODULEINSTANCE_OFFSET,
+    WAMR_PAGECNT_OFFSET, WAMR_STACKLIMIT_OFFSET,
+};
+use crate::utils::compiler_model::{CompilerModel, LucetModel, WamrModel, WasmtimeModel};
+use crate::utils::wamr_layout::{RuntimeField, WamrMemories, WamrMemory, WamrTables, WAMR_BOUND_WIDTHS};
+use crate::utils::wasmtime_layout::WasmtimeLayout;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compiler {
+    Lucet,
+    Wamr,
+    Wasmtime,
+}
+
+impl Compiler {
+    pub fn model(&self) -> &'static dyn CompilerModel {
+        match self {
+            Compiler::Lucet => &LucetModel,
+            Compiler::Wamr => &WamrModel,
+            Compiler::Wasmtime => &WasmtimeModel,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CompilerMetadata {
+    pub compiler: Compiler,
+    // per-compiler checks, normally `compiler.model()`
+    pub model: &'static dyn CompilerModel,
+
+    // Lucet specific
+    pub guest_table_0: u64,
+    pub lucet_tables: u64,
+    pub lucet_probestack: u64,
+    // the page a Spectre-mitigated heap access selects instead of the heap when its bounds check
+    // fails (see HeapValue::SafePage), if the module is mitigated
+    pub lucet_safe_page: Option<u64>,
+
+    // size of the globals area, -1 if unknown (for Lucet, 4096 then), and the rest Wamr specific
+    pub globals_size: i64,
+    // # of entries in the function index table
+    pub call_table_size: i64,
+    // bytes above the return address that may be written before the frame is set up
+    pub caller_window: i64,
+    // reject reads of the return address
+    pub strict_stack: bool,
+    // bounds checked before a call are stale after it, even when spilled or callee-saved
+    pub strict_rebound: bool,
+    // ModuleInstance fields generated code may write, including atomically
+    pub runtime_fields: Vec<RuntimeField>,
+    // where the ModuleInstance keeps the base and bound of each linear memory
+    pub wamr_memories: WamrMemories,
+    // where the ModuleInstance keeps its funcref tables, if it has several
+    pub wamr_tables: Option<WamrTables>,
+    pub options: AnalysisOptions,
+    // the width of pointers, which for x32 modules is narrower than the ISA's
+    pub abi: Abi,
+    // the address ranges of the module's read-only data sections, which rip-relative loads of
+    // constants read from
+    pub readonly_data: Vec<(u64, u64)>,
+
+    // Wasmtime specific
+    pub wasmtime_layout: WasmtimeLayout,
+}
+
+#[cfg(test)]
+impl CompilerMetadata {
+    /// Metadata for a module of `compiler` that nothing is known about, which tests override the
+    /// fields they need of with struct update syntax
+    pub fn for_test(compiler: Compiler) -> Self {
+        CompilerMetadata {
+            compiler: compiler,
+            model: compiler.model(),
+            guest_table_0: 0,
+            lucet_tables: 0,
+            lucet_probestack: 0,
+            lucet_safe_page: None,
+            globals_size: -1,
+            call_table_size: -1,
+            caller_window: 0,
+            strict_stack: false,
+            strict_rebound: false,
+            runtime_fields: vec![],
+            wamr_memories: Default::default(),
+            wamr_tables: None,
+            options: Default::default(),
+            abi: Default::default(),
+            readonly_data: vec![],
+            wasmtime_layout: Default::default(),
+        }
+    }
+}
+
+impl CompilerMetadata {
+    /// Where Wamr's function index table starts in the ModuleInstance: right after the global
+    /// data, with the table's current and maximum sizes in the 8 bytes before it.
+    pub fn wamr_table_offset(&self) -> i64 {
+        WAMR_GLOBALS_OFFSET + self.globals_size.max(0)
+    }
+
+    /// The end of Wamr's function index table, and so of the global data and table together.
+    pub fn wamr_table_end(&self) -> i64 {
+        self.wamr_table_offset() + 4 * self.call_table_size.max(0)
+    }
+
+    /// What a `width`-byte read of the ModuleInstance at `disp`, or at `idx*4 + disp` if `indexed`,
+    /// reads of the global data and the function index table. The heap checker only accepts the
+    /// reads this bounds, and the call checker bounds the index of every `Indexed` read.
+    pub fn wamr_table_read(&self, disp: i64, width: i64, indexed: bool) -> WamrTableRead {
+        let (table_offset, table_end) = (self.wamr_table_offset(), self.wamr_table_end());
+        if disp < WAMR_GLOBALS_OFFSET - 8 {
+            return WamrTableRead::Outside;
+        }
+        if indexed {
+            // an index is only bounded by the table's size if it counts entries from its start
+            if disp == table_offset && width <= 4 && disp + width <= table_end {
+                return WamrTableRead::Indexed;
+            }
+            return WamrTableRead::Misindexed;
+        }
+        let in_bounds = disp + width <= table_end;
+        if disp >= table_offset {
+            WamrTableRead::Entry { in_bounds: in_bounds }
+        } else {
+            WamrTableRead::Global { in_bounds: in_bounds }
+        }
+    }
+
+    /// The ExecEnv and ModuleInstance fields at a fixed offset that Wamr code may read, as the
+    /// value pointing to the structure and the field's offset in it.
+    pub fn wamr_fields(&self) -> Vec<(HeapValue, i64)> {
+        let mut fields = vec![
+            (HeapValue::WamrExecEnv, WAMR_MODULEINSTANCE_OFFSET),
+            (HeapValue::WamrExecEnv, WAMR_STACKLIMIT_OFFSET),
+            (HeapValue::WamrModuleInstance, WAMR_FUNCTYPE_OFFSET),
+            (HeapValue::WamrModuleInstance, WAMR_FUNCPTRS_OFFSET),
+            (HeapValue::WamrModuleInstance, WAMR_PAGECNT_OFFSET),
+        ];
+        for memory in self.wamr_memories.0.iter() {
+            fields.push((HeapValue::WamrModuleInstance, memory.heap_base));
+            fields.push((HeapValue::WamrModuleInstance, memory.mem_bound));
+        }
+        // and the bounds of wider accesses after it, which only bounds-check builds have
+        if let Some(memory) = self.wamr_mem_bound() {
+            for width in WAMR_BOUND_WIDTHS.iter().skip(1) {
+                fields.push((HeapValue::WamrModuleInstance, memory.bound_offset(*width)));
+            }
+        }
+        fields
+    }
+
+    /// The fields holding the base of a linear memory (see `wamr_fields`).
+    pub fn wamr_heap_bases(&self) -> Vec<(HeapValue, i64)> {
+        self.wamr_memories.0.iter().map(|memory| (HeapValue::WamrModuleInstance, memory.heap_base)).collect()
+    }
+
+    /// The memory whose bounds (see `WamrMemory::bound_offset`) heap offsets may be checked
+    /// against, with `--wamr-bounds-checks`. Only a single memory has them: heap bases of
+    /// different memories aren't told apart, so the bound of one would let offsets into another
+    /// through.
+    pub fn wamr_mem_bound(&self) -> Option<WamrMemory> {
+        match self.wamr_memories.0.as_slice() {
+            [memory] if self.options.wamr_bounds_checks => Some(*memory),
+            _ => None,
+        }
+    }
+
+    /// Whether `addr`, as computed by the code, is the address `known` from the module's symbols.
+    pub fn is_addr(&self, addr: u64, known: u64) -> bool {
+        self.abi.normalize_addr(addr) == self.abi.normalize_addr(known)
+    }
+}
+
+/// How a ModuleInstance-relative read relates to Wamr's global data and function index table,
+/// which follows it (see `CompilerMetadata::wamr_table_read`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WamrTableRead {
+    /// below the table's sizes, so neither
+    Outside,
+    /// a constant offset into the global data or the table's sizes: not a function index
+    Global { in_bounds: bool },
+    /// a constant entry of the table
+    Entry { in_bounds: bool },
+    /// an entry of the table at an index, which has to be less than its size
+    Indexed,
+    /// an index scaled from anywhere but the table's start, or wider than an entry, which reads
+    /// global data (or past the table) as function indices
+    Misindexed,
+}
+
+/// The data model of the code. x32 code is x86-64 code whose pointers are 32 bits, so the heap
+/// base and table addresses may be moved through 32-bit registers and spilled to 4-byte slots,
+/// and only the low 32 bits of an address are significant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Abi {
+    Lp64,
+    X32,
+}
+
+impl Default for Abi {
+    fn default() -> Self {
+        Abi::Lp64
+    }
+}
+
+impl Abi {
+    pub fn pointer_bits(&self) -> u32 {
+        match self {
+            Abi::Lp64 => 64,
+            Abi::X32 => 32,
+        }
+    }
+
+    pub fn normalize_addr(&self, addr: u64) -> u64 {
+        match self {
+            Abi::Lp64 => addr,
+            Abi::X32 => addr & 0xffff_ffff,
+        }
+    }
+}
+
+impl FromStr for Abi {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Abi, String> {
+        match s {
+            "lp64" => Ok(Abi::Lp64),
+            "x32" => Ok(Abi::X32),
+            _ => Err(format!("unknown ABI {:?} (expected lp64 or x32)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Abi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Abi::Lp64 => write!(f, "lp64"),
+            Abi::X32 => write!(f, "x32"),
+        }
+    }
+}
+
+/// What the lifter does with an instruction it doesn't model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UnknownInstrPolicy {
+    // fail the function
+    Reject,
+    // lift it as a clear of its destination and of the flags
+    Clear,
+    // lift it as nothing, recording it as an unlifted_instruction caveat
+    Ignore,
+}
+
+impl Default for UnknownInstrPolicy {
+    fn default() -> Self {
+        UnknownInstrPolicy::Clear
+    }
+}
+
+impl FromStr for UnknownInstrPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<UnknownInstrPolicy, String> {
+        match s {
+            "reject" => Ok(UnknownInstrPolicy::Reject),
+            "clear" => Ok(UnknownInstrPolicy::Clear),
+            "ignore" => Ok(UnknownInstrPolicy::Ignore),
+            _ => Err(format!("unknown policy {:?} (expected reject, clear or ignore)", s)),
+        }
+    }
+}
+
+impl fmt::Display for UnknownInstrPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnknownInstrPolicy::Reject => write!(f, "reject"),
+            UnknownInstrPolicy::Clear => write!(f, "clear"),
+            UnknownInstrPolicy::Ignore => write!(f, "ignore"),
+        }
+    }
+}
+
+/// Switches for the optional refinements of the analyses, to measure how much each contributes.
+/// The defaults are the full analyses, except for the experimental `value_numbering`; turning a
+/// refinement off only loses precision. `paranoid`, `exception_epilogue`, `wamr_bounds_checks`
+/// and `unknown_instr` aren't refinements and aren't `key=val` options; they are set by
+/// `--paranoid`, `--allow-exception-epilogue`, `--wamr-bounds-checks` and `--unknown-instr`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AnalysisOptions {
+    // a write to a 32b or 16b register bounds the 64b register to 4GB
+    pub bound_32bit: bool,
+    // a write to an 8b register bounds the 64b register to 256B
+    pub bound_8bit: bool,
+    // values written to the stack are tracked through reloads
+    pub stack_tracking: bool,
+    // a bounds check on a register also checks the registers with the same reaching definition
+    pub propagate_checks: bool,
+    // ... and the stack slots with the same reaching definition, which needs stack tracking
+    pub propagate_checks_to_stack: bool,
+    // ... and the registers holding the same value, recomputed in the block (off by default)
+    pub value_numbering: bool,
+    // check that every meet in the worklist is commutative and associative
+    pub paranoid: bool,
+    // a Wamr ret reached only after raising an exception may leave callee-saved registers unrestored
+    pub exception_epilogue: bool,
+    // a Wamr heap offset compared against the memory bound, rather than kept below 4GB, is safe
+    pub wamr_bounds_checks: bool,
+    // what the lifter does with an instruction it doesn't model
+    pub unknown_instr: UnknownInstrPolicy,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        AnalysisOptions {
+            bound_32bit: true,
+            bound_8bit: true,
+            stack_tracking: true,
+            propagate_checks: true,
+            propagate_checks_to_stack: true,
+            value_numbering: false,
+            paranoid: false,
+            exception_epilogue: false,
+            wamr_bounds_checks: false,
+            unknown_instr: UnknownInstrPolicy::Clear,
+        }
+    }
+}
+
+pub const ANALYSIS_OPTIONS: [&str; 6] = [
+    "bound_32bit",
+    "bound_8bit",
+    "stack_tracking",
+    "propagate_checks",
+    "propagate_checks_to_stack",
+    "value_numbering",
+];
+
+impl AnalysisOptions {
+    fn knob(&mut self, key: &str) -> Option<&mut bool> {
+        match key {
+            "bound_32bit" => Some(&mut self.bound_32bit),
+            "bound_8bit" => Some(&mut self.bound_8bit),
+            "stack_tracking" => Some(&mut self.stack_tracking),
+            "propagate_checks" => Some(&mut self.propagate_checks),
+            "propagate_checks_to_stack" => Some(&mut self.propagate_checks_to_stack),
+            "value_numbering" => Some(&mut self.value_numbering),
+            _ => None,
+        }
+    }
+
+    /// Parses a `key=val,...` list of changes to the defaults. A refinement that needs one that
+    /// was turned off is turned off with it, unless it was asked for, which is an error.
+    pub fn parse(s: &str) -> Result<AnalysisOptions, String> {
+        let mut options = AnalysisOptions::default();
+        let mut given: Vec<&str> = vec![];
+        for setting in s.split(",").map(|setting| setting.trim()).filter(|setting| !setting.is_empty()) {
+            let (key, val) = match setting.find('=') {
+                Some(idx) => (&setting[..idx], &setting[idx + 1..]),
+                None => return Err(format!("expected key=val, got {:?}", setting)),
+            };
+            let knob = options
+                .knob(key)
+                .ok_or_else(|| format!("unknown analysis option {:?} (expected one of {:?})", key, ANALYSIS_OPTIONS))?;
+            *knob = match val {
+                "true" | "on" | "1" => true,
+                "false" | "off" | "0" => false,
+                _ => return Err(format!("invalid value {:?} for {} (expected true or false)", val, key)),
+            };
+            given.push(key);
+        }
+        if !options.stack_tracking && options.propagate_checks_to_stack {
+            if given.contains(&"propagate_checks_to_stack") {
+                return Err("propagate_checks_to_stack needs stack_tracking".to_string());
+            }
+            options.propagate_checks_to_stack = false;
+        }
+        if !options.propagate_checks && options.value_numbering {
+            if given.contains(&"value_numbering") {
+                return Err("value_numbering needs propagate_checks".to_string());
+            }
+            options.value_numbering = false;
+        }
+        Ok(options)
+    }
+}
+
+impl fmt::Display for AnalysisOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut options = *self;
+        let settings: Vec<String> = ANALYSIS_OPTIONS
+            .iter()
+            .map(|key| format!("{}={}", key, options.knob(key).unwrap()))
+            .collect();
+        write!(f, "{}", settings.join(","))
+    }
+}
+
+#[test]
+fn analysis_options_test() {
+    let defaults = AnalysisOptions::default();
+    assert_eq!(AnalysisOptions::parse(""), Ok(defaults));
+    assert_eq!(
+        defaults.to_string(),
+        "bound_32bit=true,bound_8bit=true,stack_tracking=true,propagate_checks=true,propagate_checks_to_stack=true,value_numbering=false"
+    );
+
+    let options = AnalysisOptions::parse("bound_32bit=false, propagate_checks=off").unwrap();
+    assert!(!options.bound_32bit && !options.propagate_checks);
+    assert!(options.bound_8bit && options.stack_tracking && options.propagate_checks_to_stack);
+    // what's recorded reads back as the same options
+    assert_eq!(AnalysisOptions::parse(&options.to_string()), Ok(options));
+
+    // stack propagation goes with stack tracking, unless explicitly asked for
+    let options = AnalysisOptions::parse("stack_tracking=false").unwrap();
+    assert!(!options.stack_tracking && !options.propagate_checks_to_stack);
+    assert!(AnalysisOptions::parse("stack_tracking=false,propagate_checks_to_stack=true").is_err());
+    assert!(AnalysisOptions::parse("value_numbering=true").unwrap().value_numbering);
+    assert!(AnalysisOptions::parse("propagate_checks=false,value_numbering=true").is_err());
+
+    assert!(AnalysisOptions::parse("widening=true").is_err());
+    assert!(AnalysisOptions::parse("bound_8bit").is_err());
+    assert!(AnalysisOptions::parse("bound_8bit=maybe").is_err());
+}
+
+#[test]
+fn abi_test() {
+    assert_eq!("lp64".parse::<Abi>(), Ok(Abi::Lp64));
+    assert_eq!("x32".parse::<Abi>(), Ok(Abi::X32));
+    assert!("ilp32".parse::<Abi>().is_err());
+    assert_eq!(Abi::X32.to_string().parse::<Abi>(), Ok(Abi::X32));
+
+    assert_eq!(Abi::Lp64.normalize_addr(0xffff_ffff_8000_1000), 0xffff_ffff_8000_1000);
+    // a sign-extended 32-bit address is the same x32 address
+    assert_eq!(Abi::X32.normalize_addr(0xffff_ffff_8000_1000), 0x8000_1000);
+}
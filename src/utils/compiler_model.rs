@@ -0,0 +1,743 @@
+use crate::lattices::calllattice::{CallCheckLattice, CallCheckValue, CallCheckValueLattice, CheckBound};
+use crate::lattices::heaplattice::{HeapLattice, HeapValue, HeapValueLattice};
+use crate::lattices::heaplattice::{WAMR_EXCEPTION_OFFSET, WAMR_EXCEPTION_SIZE, WAMR_GLOBALS_OFFSET};
+use crate::lattices::reachingdefslattice::LocIdx;
+use crate::lattices::switchlattice::JumpTableLoads;
+use crate::utils::compiler::{CompilerMetadata, WamrTableRead};
+use crate::utils::ir::{MemArg, MemArgs, ValSize, Value};
+use crate::utils::ir_utils::rel_target;
+use crate::utils::wamr_layout::record_wamr_layout_use;
+use crate::utils::wasmtime_layout::{WASMTIME_ANYFUNC_FUNC_PTR_OFFSET, WASMTIME_ANYFUNC_TYPE_INDEX_OFFSET,
+                                    WASMTIME_ANYFUNC_VMCTX_OFFSET, WASMTIME_TABLE_ENTRY_SIZE};
+
+/// What the analyses and checkers need to know about the code a particular compiler emits and the
+/// runtime it targets. `CompilerMetadata::model` points to the model for the module being checked.
+/// Models are shared by the threads verifying functions in parallel.
+pub trait CompilerModel: Sync {
+    /// Register facts that hold on entry to every function.
+    fn init_heap_state(&self, state: &mut HeapLattice);
+    fn init_call_state(&self, state: &mut CallCheckLattice);
+
+    /// Whether `access` reads or writes runtime metadata (tables, context structures).
+    fn is_metadata_access(&self, metadata: &CompilerMetadata, state: &HeapLattice, access: &Value) -> bool;
+    /// Whether `access` is to a runtime field generated code may write, so that it may also be
+    /// the target of an atomic read-modify-write.
+    fn is_runtime_writable(&self, metadata: &CompilerMetadata, state: &HeapLattice, access: &Value) -> bool;
+    /// Whether `access` stays within the globals area.
+    fn is_global_access(&self, metadata: &CompilerMetadata, state: &HeapLattice, access: &Value) -> bool;
+    /// Whether `access` is a lookup in a jump table or function table. `jump_tables` holds the
+    /// bounds-checked jump table loads the switch analysis found in the function.
+    fn is_jump_table_access(
+        &self,
+        metadata: &CompilerMetadata,
+        state: &HeapLattice,
+        access: &Value,
+        loc_idx: &LocIdx,
+        jump_tables: &JumpTableLoads,
+    ) -> bool;
+
+    /// Whether a call to `target` is made with the registers the callee expects.
+    fn check_call_site(
+        &self,
+        state: &HeapLattice,
+        target: &Value,
+        len: u8,
+        loc_idx: &LocIdx,
+        func_addrs: &Vec<(u64, String)>,
+    ) -> bool;
+
+    /// Records in zf (which stands for all of the flags) the bounds check a `cmp src1, src2` makes,
+    /// if it makes one.
+    fn refine_cmp(&self, state: &mut CallCheckLattice, src1: &Value, src2: &Value, loc_idx: &LocIdx);
+    /// The value a register checked against `bound` has on the in-bounds side of the branch, if
+    /// that side is enough to call it checked: `strict` when it excludes the bound itself
+    /// (index < bound), not when it includes it (index <= bound).
+    fn checked_value(&self, bound: CheckBound, strict: bool) -> Option<CallCheckValue>;
+
+    /// Why a direct call `offset` bytes into the PLT is not allowed, if it isn't.
+    fn plt_target_error(&self, offset: u64) -> Option<&'static str>;
+}
+
+pub struct LucetModel;
+
+impl LucetModel {
+    pub const PLT_ENTRY_SIZE: u64 = 16;
+    // the size of the globals area, unless one is given or read from the module data
+    pub const GLOBALS_SIZE: i64 = 4096;
+
+    pub fn globals_size(&self, metadata: &CompilerMetadata) -> i64 {
+        if metadata.globals_size >= 0 {
+            metadata.globals_size
+        } else {
+            LucetModel::GLOBALS_SIZE
+        }
+    }
+}
+
+impl CompilerModel for LucetModel {
+    fn init_heap_state(&self, state: &mut HeapLattice) {
+        state.regs.rdi = HeapValueLattice::new(HeapValue::HeapBase);
+    }
+
+    fn init_call_state(&self, _state: &mut CallCheckLattice) {}
+
+    fn is_metadata_access(&self, _metadata: &CompilerMetadata, state: &HeapLattice, access: &Value) -> bool {
+        if let Value::Mem(_size, memargs) = access {
+            match memargs{
+                //Case 1: mem[globals_base], or mem[guest_table_0 + offset] added up beforehand
+                MemArgs::Mem1Arg(MemArg::Reg(regnum,ValSize::Size64)) => {
+                    match state.regs.get(regnum,&ValSize::Size64).v {
+                        Some(HeapValue::GlobalsBase) | Some(HeapValue::GuestTable0Entry) => return true,
+                        _ => (),
+                    }
+                },
+                //Case 2: mem[lucet_tables + 8], or mem[guest_table_0 + offset + 8] added up beforehand
+                MemArgs::Mem2Args(MemArg::Reg(regnum,ValSize::Size64), MemArg::Imm(_,_,8)) => {
+                    match state.regs.get(regnum,&ValSize::Size64).v {
+                        Some(HeapValue::LucetTables) | Some(HeapValue::GuestTable0Entry) => return true,
+                        _ => (),
+                    }
+                },
+                MemArgs::Mem2Args(MemArg::Reg(regnum1,ValSize::Size64), MemArg::Reg(regnum2,ValSize::Size64)) => {
+                    if let Some(HeapValue::GuestTable0) = state.regs.get(regnum1,&ValSize::Size64).v{
+                        return true
+                    }
+                    if let Some(HeapValue::GuestTable0) = state.regs.get(regnum2,&ValSize::Size64).v{
+                        return true
+                    }
+                },
+                MemArgs::Mem3Args(MemArg::Reg(regnum1,ValSize::Size64),MemArg::Reg(regnum2,ValSize::Size64), MemArg::Imm(_,_,8)) => {
+                    match (state.regs.get(regnum1,&ValSize::Size64).v,state.regs.get(regnum2,&ValSize::Size64).v){
+                        (Some(HeapValue::GuestTable0),_) => return true,
+                        (_,Some(HeapValue::GuestTable0)) => return true,
+                        _ => ()
+                    }
+                }
+                _ => return false
+            }
+        }
+        false
+    }
+
+    fn is_runtime_writable(&self, _metadata: &CompilerMetadata, _state: &HeapLattice, _access: &Value) -> bool {
+        false
+    }
+
+    fn is_global_access(&self, metadata: &CompilerMetadata, state: &HeapLattice, access: &Value) -> bool {
+        if let Value::Mem(memsize, memargs) = access {
+            let width = (memsize.to_u32() / 8) as i64;
+            let globals_size = self.globals_size(metadata);
+            match memargs {
+                MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) => {
+                    match state.regs.get(regnum, &ValSize::Size64).v {
+                        Some(HeapValue::GlobalsBase) => return width <= globals_size,
+                        // a pointer to a global derived earlier
+                        Some(HeapValue::GlobalsPtr(offset)) => return offset + width <= globals_size,
+                        _ => (),
+                    }
+                }
+                MemArgs::Mem2Args(
+                    MemArg::Reg(regnum, ValSize::Size64),
+                    MemArg::Imm(_, _, globals_offset),
+                ) => {
+                    match state.regs.get(regnum, &ValSize::Size64).v {
+                        Some(HeapValue::GlobalsBase) => {
+                            return *globals_offset >= 0 && *globals_offset + width <= globals_size;
+                        }
+                        Some(HeapValue::GlobalsPtr(offset)) => {
+                            let offset = offset + *globals_offset;
+                            return offset >= 0 && offset + width <= globals_size;
+                        }
+                        _ => (),
+                    }
+                }
+                // a global indexed by a bounded register, e.g. a small table of globals
+                MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Reg(index, index_size)) => {
+                    let offset = match state.regs.get(regnum, &ValSize::Size64).v {
+                        Some(HeapValue::GlobalsBase) => 0,
+                        Some(HeapValue::GlobalsPtr(offset)) => offset,
+                        _ => return false,
+                    };
+                    if let Some(bound) = state.regs.get(index, index_size).v.and_then(|v| v.bound()) {
+                        return offset + (bound as i64 - 1) + width <= globals_size;
+                    }
+                }
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    fn is_jump_table_access(
+        &self,
+        _metadata: &CompilerMetadata,
+        _state: &HeapLattice,
+        access: &Value,
+        loc_idx: &LocIdx,
+        jump_tables: &JumpTableLoads,
+    ) -> bool {
+        if let Value::Mem(_size, memargs) = access {
+            match memargs {
+                // only a load the switch analysis saw with a switch base and a bounded index
+                MemArgs::MemScale(MemArg::Reg(_, ValSize::Size64), MemArg::Reg(_, _), MemArg::Imm(_, _, 4)) => {
+                    return jump_tables.contains_key(&loc_idx.addr)
+                }
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    fn check_call_site(
+        &self,
+        state: &HeapLattice,
+        _target: &Value,
+        _len: u8,
+        _loc_idx: &LocIdx,
+        _func_addrs: &Vec<(u64, String)>,
+    ) -> bool {
+        // For Lucet, this means rdi points to the HeapBase
+        match state.regs.rdi.v {
+            Some(HeapValue::HeapBase) => true,
+            _ => {
+                log::error!("Call failure {:?}", state.stack.get(0, 8));
+                false
+            }
+        }
+    }
+
+    fn refine_cmp(&self, state: &mut CallCheckLattice, src1: &Value, src2: &Value, loc_idx: &LocIdx) {
+        match (src1, src2) {
+            (Value::Reg(regnum1,size1), Value::Reg(regnum2, size2)) => {
+                if let Some(CallCheckValue::TableSize) = state.regs.get(regnum2, size2).v{
+                    state.regs.zf =
+                        CallCheckValueLattice::new(CallCheckValue::CheckFlag(CheckBound::TableSize, *regnum1, true, *loc_idx))
+                }
+                if let Some(CallCheckValue::TableSize) = state.regs.get(regnum1, size1).v{
+                    state.regs.zf =
+                        CallCheckValueLattice::new(CallCheckValue::CheckFlag(CheckBound::TableSize, *regnum2, false, *loc_idx))
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // the index has to be below the table size itself
+    fn checked_value(&self, bound: CheckBound, strict: bool) -> Option<CallCheckValue> {
+        match bound {
+            CheckBound::TableSize if strict => Some(CallCheckValue::CheckedVal),
+            _ => None,
+        }
+    }
+
+    fn plt_target_error(&self, offset: u64) -> Option<&'static str> {
+        if offset % LucetModel::PLT_ENTRY_SIZE == 0 {
+            None
+        } else {
+            Some("interior of a PLT slot")
+        }
+    }
+}
+
+pub struct WamrModel;
+
+// Whether the `size` bytes at `offset` of the ModuleInstance lie within its exception buffer
+fn in_exception_buffer(offset: i64, size: i64) -> bool {
+    offset >= WAMR_EXCEPTION_OFFSET && offset + size <= WAMR_EXCEPTION_OFFSET + WAMR_EXCEPTION_SIZE
+}
+
+impl CompilerModel for WamrModel {
+    fn init_heap_state(&self, state: &mut HeapLattice) {
+        state.regs.rdi = HeapValueLattice::new(HeapValue::WamrExecEnv);
+    }
+
+    fn init_call_state(&self, state: &mut CallCheckLattice) {
+        state.regs.rdi = CallCheckValueLattice::new(CallCheckValue::WamrExecEnv);
+    }
+
+    fn is_metadata_access(&self, metadata: &CompilerMetadata, state: &HeapLattice, access: &Value) -> bool {
+        if self.is_runtime_writable(metadata, state, access) {
+            return true;
+        }
+        if let Value::Mem(size, memargs) = access {
+            match memargs {
+                MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, offset)) => {
+                    let base = state.regs.get(regnum, &ValSize::Size64).v;
+                    //Case 1: mem[WamrExecEnv+k] or mem[WamrModuleInstance+k] for a field at a fixed
+                    //offset k: the ModuleInstance, the stack limit, the function tables, the page
+                    //count, and the base and bound of each linear memory (see CompilerMetadata::wamr_fields)
+                    if let Some(base) = base {
+                        if metadata.wamr_fields().contains(&(base, *offset)) {
+                            record_wamr_layout_use(*offset);
+                            return true;
+                        }
+                    }
+                    //Case 2: mem[WamrModuleInstance+WAMR_EXCEPTION_OFFSET+k], within the exception buffer
+                    if let Some(HeapValue::WamrModuleInstance) = base {
+                        if in_exception_buffer(*offset, (size.to_u32() / 8) as i64) {
+                            record_wamr_layout_use(WAMR_EXCEPTION_OFFSET);
+                            return true;
+                        }
+                    }
+                    //Case 3: with several funcref tables, an entry of the descriptor array at a
+                    //constant index, and the element count and function pointers of a descriptor
+                    if let Some(tables) = &metadata.wamr_tables {
+                        let width = (size.to_u32() / 8) as i64;
+                        match base {
+                            Some(HeapValue::WamrModuleInstance) => {
+                                if tables.descriptor_at(*offset, width).is_some() {
+                                    return true;
+                                }
+                            }
+                            Some(HeapValue::WamrTableDescriptor) => {
+                                return (*offset == tables.size_field && width == 4) || (*offset == tables.base_field && width == 8);
+                            }
+                            _ => (),
+                        }
+                    }
+                },
+                //Case 4: mem[WamrStackLimit]
+                MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) => {
+                    if let Some(HeapValue::WamrStackLimit) = state.regs.get(regnum,&ValSize::Size64).v {
+                        return true;
+                    }
+                }
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    fn is_runtime_writable(&self, metadata: &CompilerMetadata, state: &HeapLattice, access: &Value) -> bool {
+        // mem[WamrModuleInstance+offset] within one of the configured fields
+        if let Value::Mem(memsize, MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, offset))) = access {
+            if let Some(HeapValue::WamrModuleInstance) = state.regs.get(regnum, &ValSize::Size64).v {
+                let size = (memsize.to_u32() / 8) as i64;
+                return metadata.runtime_fields.iter().any(|field| field.contains(*offset, size));
+            }
+        }
+        false
+    }
+
+    fn is_global_access(&self, metadata: &CompilerMetadata, state: &HeapLattice, access: &Value) -> bool {
+        if let Value::Mem(memsize, memargs) = access {
+            match memargs {
+                /*
+                MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) => {
+                    // accessing the base global variable memory
+                    if let Some(HeapValue::GlobalsBase) = state.regs.get(regnum, &ValSize::Size64).v {
+                        // allowed regardless of the size of global memory in order to
+                        // enable calling aot_invoke_native with a parameter
+                        return true;
+                    }
+                },
+                */
+                MemArgs::Mem2Args(
+                    MemArg::Reg(regnum, ValSize::Size64),
+                    MemArg::Imm(_, _, globals_offset),
+                ) => {
+                    // accessing an offset from global variable memory
+                    if let Some(HeapValue::WamrModuleInstance) = state.regs.get(regnum, &ValSize::Size64).v {
+                        // constant-index lookups read the function index table through here too
+                        match metadata.wamr_table_read(*globals_offset, (memsize.to_u32()/8) as i64, false) {
+                            WamrTableRead::Global { in_bounds } | WamrTableRead::Entry { in_bounds } => {
+                                log::debug!("upper bound: {:x}, offset: {:x}", metadata.wamr_table_end(), *globals_offset+((memsize.to_u32()/8) as i64));
+                                record_wamr_layout_use(WAMR_GLOBALS_OFFSET);
+                                return in_bounds;
+                            },
+                            _ => (),
+                        }
+                    }
+                },
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    fn is_jump_table_access(
+        &self,
+        metadata: &CompilerMetadata,
+        state: &HeapLattice,
+        access: &Value,
+        _loc_idx: &LocIdx,
+        _jump_tables: &JumpTableLoads,
+    ) -> bool {
+        if let Value::Mem(size, memargs) = access {
+            match memargs {
+                // Case 1: an access to the table of function indexes
+                MemArgs::MemScaleDisp(MemArg::Reg(regnum, ValSize::Size64),
+                                      MemArg::Reg(_, _), MemArg::Imm(_, _, 4),
+                                      MemArg::Imm(_, _, immval)) => {
+                    if let Some(HeapValue::WamrModuleInstance) = state.regs.get(regnum, &ValSize::Size64).v {
+                        // only from the table's start, so that the call checker's bound on the
+                        // index (the table's size) bounds the access
+                        if let WamrTableRead::Indexed = metadata.wamr_table_read(*immval, (size.to_u32()/8) as i64, true) {
+                            record_wamr_layout_use(WAMR_GLOBALS_OFFSET);
+                            return true;
+                        }
+                    }
+                },
+                // Case 2: an access to the table of function types
+                MemArgs::MemScale(MemArg::Reg(regnum, ValSize::Size64),
+                                  MemArg::Reg(_, ValSize::Size64), MemArg::Imm(_, _, 4)) => {
+                    if let Some(HeapValue::WamrFuncTypeTable) = state.regs.get(regnum, &ValSize::Size64).v {
+                        // responsibility of call checker to check this is a valid index
+                        return true;
+                    }
+                },
+                // Case 3: an access to the table of function pointers, or to those of a table
+                // descriptor
+                MemArgs::MemScale(MemArg::Reg(regnum, ValSize::Size64),
+                                  MemArg::Reg(_, ValSize::Size64), MemArg::Imm(_, _, 8)) => {
+                    if let Some(HeapValue::WamrFuncPtrsTable) = state.regs.get(regnum, &ValSize::Size64).v {
+                        // responsibility of call checker to check this is a valid index
+                        return true;
+                    }
+                },
+                // Case 4: with several funcref tables, an entry of the descriptor array at a
+                // register index
+                MemArgs::MemScaleDisp(MemArg::Reg(regnum, ValSize::Size64),
+                                      MemArg::Reg(_, _), MemArg::Imm(_, _, 8),
+                                      MemArg::Imm(_, _, disp)) => {
+                    if let (Some(HeapValue::WamrModuleInstance), Some(tables)) = (state.regs.get(regnum, &ValSize::Size64).v, &metadata.wamr_tables) {
+                        // responsibility of call checker to check the index against the table count
+                        return *disp == tables.descriptors && size.to_u32() == 64;
+                    }
+                },
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    fn check_call_site(
+        &self,
+        state: &HeapLattice,
+        target: &Value,
+        len: u8,
+        loc_idx: &LocIdx,
+        func_addrs: &Vec<(u64, String)>,
+    ) -> bool {
+        // For Wamr, this means rdi points to the current ExecEnv
+        match state.regs.rdi.v {
+            Some(HeapValue::WamrExecEnv) => (),
+            _ => {
+                if let Value::Imm(_, immsize, imm) = target {
+                    // handle the exception of calling trusted functions like
+                    // aot_invoke_native and aot_enlarge_memory
+                    let call_target = rel_target(immsize, *imm, loc_idx.addr, len as u64);
+                    for (a, _) in func_addrs {
+                        if call_target == *a {
+                            log::error!("Called aot function without correct value in %rdi!");
+                            return false;
+                        }
+                    }
+                } else {
+                    log::error!("Invalid call instruction: call {:?}", target);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn refine_cmp(&self, state: &mut CallCheckLattice, src1: &Value, src2: &Value, loc_idx: &LocIdx) {
+        match (src1, src2) {
+            (Value::Imm(_, _, immval), Value::Reg(regnum, regsize)) |
+            (Value::Reg(regnum, regsize), Value::Imm(_, _, immval)) => {
+                let index_first = if let Value::Reg(_, _) = src1 { true } else { false };
+                match state.regs.get(regnum, regsize).v {
+                    Some(_) => (),
+                    _ => state.regs.zf = CallCheckValueLattice::new(CallCheckValue::CheckFlag(CheckBound::Imm(*immval as u32), *regnum, index_first, *loc_idx)),
+                }
+            },
+            // an index into one of several tables, against the element count of its descriptor
+            (Value::Reg(regnum1, size1), Value::Reg(regnum2, size2)) => {
+                if let Some(CallCheckValue::WamrTableSize(table)) = state.regs.get(regnum2, size2).v {
+                    state.regs.zf = CallCheckValueLattice::new(CallCheckValue::CheckFlag(CheckBound::WamrTableSize(table), *regnum1, true, *loc_idx));
+                }
+                if let Some(CallCheckValue::WamrTableSize(table)) = state.regs.get(regnum1, size1).v {
+                    state.regs.zf = CallCheckValueLattice::new(CallCheckValue::CheckFlag(CheckBound::WamrTableSize(table), *regnum2, false, *loc_idx));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // WamrChecked keeps the bound, which the call checker requires to be a valid index itself.
+    // An index checked against a table's element count has to be below it, and is only checked
+    // for that table.
+    fn checked_value(&self, bound: CheckBound, strict: bool) -> Option<CallCheckValue> {
+        match bound {
+            CheckBound::Imm(bound) => Some(CallCheckValue::WamrChecked(bound)),
+            CheckBound::WamrTableSize(table) if strict => Some(CallCheckValue::WamrTableChecked(table)),
+            _ => None,
+        }
+    }
+
+    fn plt_target_error(&self, _offset: u64) -> Option<&'static str> {
+        Some("PLT slot")
+    }
+}
+
+pub struct WasmtimeModel;
+
+impl CompilerModel for WasmtimeModel {
+    fn init_heap_state(&self, state: &mut HeapLattice) {
+        state.regs.rdi = HeapValueLattice::new(HeapValue::WasmtimeVMContext);
+    }
+
+    fn init_call_state(&self, state: &mut CallCheckLattice) {
+        state.regs.rdi = CallCheckValueLattice::new(CallCheckValue::WasmtimeVMContext);
+    }
+
+    fn is_metadata_access(&self, metadata: &CompilerMetadata, state: &HeapLattice, access: &Value) -> bool {
+        if let Value::Mem(_size, memargs) = access {
+            match memargs {
+                //Case 1: mem[WasmtimeVMContext + offset] for the memory and table fields
+                MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, offset)) => {
+                    match state.regs.get(regnum, &ValSize::Size64).v {
+                        Some(HeapValue::WasmtimeVMContext) => return metadata.wasmtime_layout.is_field(*offset),
+                        //Case 2: the fields of an anyfunc
+                        Some(HeapValue::WasmtimeAnyfunc) => {
+                            return *offset == WASMTIME_ANYFUNC_FUNC_PTR_OFFSET ||
+                                   *offset == WASMTIME_ANYFUNC_TYPE_INDEX_OFFSET ||
+                                   *offset == WASMTIME_ANYFUNC_VMCTX_OFFSET
+                        }
+                        _ => (),
+                    }
+                },
+                //Case 3: mem[WasmtimeAnyfunc], its function pointer
+                MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) => {
+                    if let Some(HeapValue::WasmtimeAnyfunc) = state.regs.get(regnum, &ValSize::Size64).v {
+                        return true;
+                    }
+                },
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    fn is_runtime_writable(&self, _metadata: &CompilerMetadata, _state: &HeapLattice, _access: &Value) -> bool {
+        false
+    }
+
+    fn is_global_access(&self, metadata: &CompilerMetadata, state: &HeapLattice, access: &Value) -> bool {
+        // the defined globals are in the VMContext itself
+        if let Value::Mem(memsize, MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, offset))) = access {
+            if let Some(HeapValue::WasmtimeVMContext) = state.regs.get(regnum, &ValSize::Size64).v {
+                let width = (memsize.to_u32() / 8) as i64;
+                return metadata.wasmtime_layout.is_global(*offset, width);
+            }
+        }
+        false
+    }
+
+    fn is_jump_table_access(
+        &self,
+        metadata: &CompilerMetadata,
+        state: &HeapLattice,
+        access: &Value,
+        loc_idx: &LocIdx,
+        jump_tables: &JumpTableLoads,
+    ) -> bool {
+        if let Value::Mem(_size, MemArgs::MemScale(MemArg::Reg(regnum, ValSize::Size64), MemArg::Reg(_, _), MemArg::Imm(_, _, scale))) = access {
+            // an access to the function table: responsibility of call checker to check the index
+            if let Some(HeapValue::WasmtimeTableBase) = state.regs.get(regnum, &ValSize::Size64).v {
+                return *scale == WASMTIME_TABLE_ENTRY_SIZE;
+            }
+        }
+        // Cranelift emits the same switch jump tables for Wasmtime as for Lucet
+        LucetModel.is_jump_table_access(metadata, state, access, loc_idx, jump_tables)
+    }
+
+    fn check_call_site(
+        &self,
+        state: &HeapLattice,
+        _target: &Value,
+        _len: u8,
+        _loc_idx: &LocIdx,
+        _func_addrs: &Vec<(u64, String)>,
+    ) -> bool {
+        // For Wasmtime, this means rdi points to a VMContext: the caller's, or the callee's as
+        // read from its anyfunc
+        match state.regs.rdi.v {
+            Some(HeapValue::WasmtimeVMContext) => true,
+            _ => {
+                log::error!("Call failure {:?}", state.regs.rdi.v);
+                false
+            }
+        }
+    }
+
+    // table indices are checked against the table size as for Lucet
+    fn refine_cmp(&self, state: &mut CallCheckLattice, src1: &Value, src2: &Value, loc_idx: &LocIdx) {
+        LucetModel.refine_cmp(state, src1, src2, loc_idx)
+    }
+
+    fn checked_value(&self, bound: CheckBound, strict: bool) -> Option<CallCheckValue> {
+        LucetModel.checked_value(bound, strict)
+    }
+
+    // libcalls go through the VMContext rather than a PLT
+    fn plt_target_error(&self, _offset: u64) -> Option<&'static str> {
+        Some("PLT slot")
+    }
+}
+
+#[test]
+fn compiler_model_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::utils::compiler::Compiler;
+    use crate::utils::ir::{IRMap, ImmType, Stmt, Unopcode};
+
+    // a backend with Lucet's calling convention that trusts no runtime data structures
+    struct RejectModel;
+    impl CompilerModel for RejectModel {
+        fn init_heap_state(&self, state: &mut HeapLattice) { LucetModel.init_heap_state(state) }
+        fn init_call_state(&self, _state: &mut CallCheckLattice) {}
+        fn is_metadata_access(&self, _metadata: &CompilerMetadata, _state: &HeapLattice, _access: &Value) -> bool { false }
+        fn is_runtime_writable(&self, _metadata: &CompilerMetadata, _state: &HeapLattice, _access: &Value) -> bool { false }
+        fn is_global_access(&self, _metadata: &CompilerMetadata, _state: &HeapLattice, _access: &Value) -> bool { false }
+        fn is_jump_table_access(&self, _metadata: &CompilerMetadata, _state: &HeapLattice, _access: &Value, _loc_idx: &LocIdx, _jump_tables: &JumpTableLoads) -> bool { false }
+        fn check_call_site(&self, _state: &HeapLattice, _target: &Value, _len: u8, _loc_idx: &LocIdx, _func_addrs: &Vec<(u64, String)>) -> bool { false }
+        fn refine_cmp(&self, _state: &mut CallCheckLattice, _src1: &Value, _src2: &Value, _loc_idx: &LocIdx) {}
+        fn checked_value(&self, _bound: CheckBound, _strict: bool) -> Option<CallCheckValue> { None }
+        fn plt_target_error(&self, _offset: u64) -> Option<&'static str> { Some("rejected") }
+    }
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    // mov rax, [rdi - 16]; mov rcx, [rax + 8]; ret
+    // the second access is only safe as a global access
+    let mem = |regnum: u8, offset: i64| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)),
+    );
+    let mut irmap = IRMap::new();
+    irmap.insert(0, vec![
+        (0, vec![Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size64), mem(7, -16))]),
+        (4, vec![Stmt::Unop(Unopcode::Mov, Value::Reg(1, ValSize::Size64), mem(0, 8))]),
+        (8, vec![Stmt::Ret]),
+    ]);
+    let lucet = HeapAnalyzer { metadata: metadata.clone() };
+    assert!(check_heap(run_worklist(&SingleBlock(0), &irmap, &lucet), &irmap, &lucet, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_ok());
+
+    let mut rejecting = metadata.clone();
+    rejecting.model = &RejectModel;
+    let rejecting = HeapAnalyzer { metadata: rejecting };
+    assert!(check_heap(run_worklist(&SingleBlock(0), &irmap, &rejecting), &irmap, &rejecting, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new()).is_err());
+    assert_eq!(RejectModel.plt_target_error(0), Some("rejected"));
+    assert_eq!(LucetModel.plt_target_error(LucetModel::PLT_ENTRY_SIZE), None);
+    assert_eq!(WamrModel.plt_target_error(0), Some("PLT slot"));
+}
+
+#[test]
+fn wamr_exception_buffer_test() {
+    use crate::utils::compiler::Compiler;
+    use crate::utils::ir::ImmType;
+
+    let metadata = CompilerMetadata {
+        globals_size: 0,
+        call_table_size: 0,
+        ..CompilerMetadata::for_test(Compiler::Wamr)
+    };
+    let mut state: HeapLattice = Default::default();
+    state.regs.rdi = HeapValueLattice::new(HeapValue::WamrModuleInstance);
+    // mov [rdi + WAMR_EXCEPTION_OFFSET + k], r (of `size`)
+    let access = |size: ValSize, k: i64| {
+        let field = Value::Mem(
+            size,
+            MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, WAMR_EXCEPTION_OFFSET + k)),
+        );
+        WamrModel.is_metadata_access(&metadata, &state, &field)
+    };
+    assert!(access(ValSize::Size32, 0));
+    assert!(access(ValSize::Size8, WAMR_EXCEPTION_SIZE - 1));
+    assert!(!access(ValSize::Size8, WAMR_EXCEPTION_SIZE));
+    // a wider write has to end within the buffer too
+    assert!(access(ValSize::Size64, WAMR_EXCEPTION_SIZE - 8));
+    assert!(!access(ValSize::Size64, WAMR_EXCEPTION_SIZE - 7));
+    assert!(!access(ValSize::Size8, -1));
+    // only through the ModuleInstance
+    state.regs.rdi = HeapValueLattice::new(HeapValue::WamrExecEnv);
+    assert!(!access(ValSize::Size8, 1));
+}
+
+#[test]
+fn wamr_memories_test() {
+    use crate::analyses::heap_analyzer::wamr_is_heapbase_access;
+    use crate::utils::compiler::Compiler;
+    use crate::utils::ir::ImmType;
+    use crate::utils::wamr_layout::{WamrMemories, WamrMemory};
+
+    let mut metadata = CompilerMetadata {
+        globals_size: 0,
+        call_table_size: 0,
+        ..CompilerMetadata::for_test(Compiler::Wamr)
+    };
+    let mut state: HeapLattice = Default::default();
+    state.regs.rdi = HeapValueLattice::new(HeapValue::WamrModuleInstance);
+    let memargs = |offset: i64| MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset));
+    // mov r, [rdi + offset]
+    let is_metadata = |metadata: &CompilerMetadata, offset: i64| {
+        WamrModel.is_metadata_access(metadata, &state, &Value::Mem(ValSize::Size64, memargs(offset)))
+    };
+
+    // the single memory of the default layout
+    assert!(is_metadata(&metadata, 0x150) && is_metadata(&metadata, 0x1a0));
+    assert!(!is_metadata(&metadata, 0x1d0));
+    assert!(wamr_is_heapbase_access(&state, &memargs(0x150), &metadata.wamr_heap_bases()));
+
+    // a second memory instance after it
+    metadata.wamr_memories = WamrMemories(vec![
+        WamrMemory { heap_base: 0x150, mem_bound: 0x1a0 },
+        WamrMemory { heap_base: 0x1d0, mem_bound: 0x220 },
+    ]);
+    assert!(is_metadata(&metadata, 0x1d0) && is_metadata(&metadata, 0x220));
+    assert!(wamr_is_heapbase_access(&state, &memargs(0x1d0), &metadata.wamr_heap_bases()));
+    // its bound is no heap base, and the fields are only read through the ModuleInstance
+    assert!(!wamr_is_heapbase_access(&state, &memargs(0x220), &metadata.wamr_heap_bases()));
+    state.regs.rdi = HeapValueLattice::new(HeapValue::WamrExecEnv);
+    assert!(!is_metadata(&metadata, 0x1d0));
+}
+
+#[test]
+fn lucet_globals_size_test() {
+    use crate::utils::compiler::Compiler;
+    use crate::utils::ir::ImmType;
+
+    let mut metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let mut state: HeapLattice = Default::default();
+    state.regs.rax = HeapValueLattice::new(HeapValue::GlobalsBase);
+    // mov r, [rax + offset] (of `size`)
+    let access = |metadata: &CompilerMetadata, size: ValSize, offset: i64| {
+        let global = Value::Mem(
+            size,
+            MemArgs::Mem2Args(MemArg::Reg(0, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)),
+        );
+        LucetModel.is_global_access(metadata, &state, &global)
+    };
+
+    // 4096 bytes unless told otherwise
+    assert!(access(&metadata, ValSize::Size64, LucetModel::GLOBALS_SIZE - 8));
+    assert!(!access(&metadata, ValSize::Size64, LucetModel::GLOBALS_SIZE - 4));
+    assert!(access(&metadata, ValSize::Size32, LucetModel::GLOBALS_SIZE - 4));
+    assert!(!access(&metadata, ValSize::Size8, LucetModel::GLOBALS_SIZE));
+    assert!(!access(&metadata, ValSize::Size8, -8));
+    // more than 512 globals
+    metadata.globals_size = 8 * 600;
+    assert!(access(&metadata, ValSize::Size64, 8 * 599));
+    assert!(!access(&metadata, ValSize::Size64, 8 * 600 - 4));
+    // and fewer
+    metadata.globals_size = 16;
+    assert!(access(&metadata, ValSize::Size64, 8));
+    assert!(!access(&metadata, ValSize::Size64, 16));
+    metadata.globals_size = 0;
+    assert!(!access(&metadata, ValSize::Size64, 0));
+}
@@ -0,0 +1,161 @@
+use crate::analyses::AnalysisResult;
+use crate::checkers::heap_checker::AccessClasses;
+use crate::lattices::reachingdefslattice::LocIdx;
+use crate::utils::cfg_normalize::{BlockGraph, BlockRange};
+use crate::utils::ir::{IRMap, InstrOrigin};
+use crate::utils::persist::Persist;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+/*
+ * Graphviz export of a function's resolved CFG (`--dump-cfg <dir>`): one node per block, labeled
+ * with its address range and lifted statements, and the edges of the graph, switch targets
+ * included. The entry state of each block under the analyses that ran can be added to its label,
+ * in the persisted encoding, which leaves out default entries, and each statement the class the
+ * heap checker accepted its memory accesses as. Functions with thousands of blocks
+ * get shorter labels, so the graph stays small enough for dot to lay out.
+ */
+
+// the statements shown per block, and in functions with more than LARGE_FUNCTION_BLOCKS blocks
+const MAX_LABEL_STMTS: usize = 32;
+const MAX_LARGE_LABEL_STMTS: usize = 4;
+const LARGE_FUNCTION_BLOCKS: usize = 1000;
+// states are cut to this many characters
+const MAX_STATE_CHARS: usize = 400;
+
+/// The entry state of each block under one analysis, as shown in the graph.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DotAnnotation {
+    pub analysis: String,
+    pub states: BTreeMap<u64, String>,
+}
+
+impl DotAnnotation {
+    pub fn new<State: Persist>(analysis: &str, result: &AnalysisResult<State>) -> Self {
+        DotAnnotation {
+            analysis: analysis.to_string(),
+            states: result.iter().map(|(addr, state)| (*addr, state.to_json().to_string())).collect(),
+        }
+    }
+}
+
+// Escapes `s` for a double-quoted dot string
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn truncate(s: &str, max_chars: usize) -> String {
+    match s.char_indices().nth(max_chars) {
+        Some((at, _)) => format!("{}...", &s[..at]),
+        None => s.to_string(),
+    }
+}
+
+/// The dot graph of the blocks and edges of `graph`, with the statements of each block in
+/// `irmap`, the class of their memory accesses in `accesses`, and its entry state under each of
+/// `annotations`.
+pub fn graph_to_dot(func_name: &str, graph: &BlockGraph, irmap: &IRMap, annotations: &[DotAnnotation], accesses: &AccessClasses) -> String {
+    let max_stmts = if graph.blocks.len() > LARGE_FUNCTION_BLOCKS { MAX_LARGE_LABEL_STMTS } else { MAX_LABEL_STMTS };
+    let mut dot = format!("digraph \"{}\" {{\n    node [shape=box, fontname=monospace];\n", escape(func_name));
+    for (addr, block) in graph.blocks.iter() {
+        let mut lines = vec![format!("0x{:x}-0x{:x}", block.start, block.end)];
+        let stmts: Vec<String> = irmap.get(addr).map_or(vec![], |irblock| {
+            let provenance = irmap.provenance(addr);
+            let mut stmts = vec![];
+            for (idx, (stmt_addr, instr_stmts)) in irblock.iter().enumerate() {
+                stmts.extend(instr_stmts.iter().enumerate().map(|(stmt_idx, stmt)| {
+                    let loc = LocIdx { addr: *stmt_addr, idx: stmt_idx as u32 };
+                    match accesses.get(&loc) {
+                        Some(classes) => {
+                            let names: Vec<&str> = classes.iter().map(|(_, class)| class.name()).collect();
+                            format!("0x{:x}: {:?}  ; {}", stmt_addr, stmt, names.join(", "))
+                        }
+                        None => format!("0x{:x}: {:?}", stmt_addr, stmt),
+                    }
+                }));
+                // an instruction lifted without statements is shown as such
+                if let Some(InstrOrigin::ConsumedByProbestack) = provenance.as_ref().map(|p| p[idx].origin) {
+                    stmts.push(format!("0x{:x}: (part of the probestack call)", stmt_addr));
+                }
+            }
+            stmts
+        });
+        lines.extend(stmts.iter().take(max_stmts).cloned());
+        if stmts.len() > max_stmts {
+            lines.push(format!("... {} more", stmts.len() - max_stmts));
+        }
+        for annotation in annotations {
+            if let Some(state) = annotation.states.get(addr) {
+                lines.push(format!("{}: {}", annotation.analysis, truncate(state, MAX_STATE_CHARS)));
+            }
+        }
+        let label: String = lines.iter().map(|line| format!("{}\\l", escape(line))).collect();
+        let style = if *addr == graph.entrypoint { ", style=bold" } else { "" };
+        dot.push_str(&format!("    \"0x{:x}\" [label=\"{}\"{}];\n", addr, label, style));
+    }
+    for (from, to) in graph.edges.iter() {
+        dot.push_str(&format!("    \"0x{:x}\" -> \"0x{:x}\";\n", from, to));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// The dot graph of the resolved CFG `cfg` (see `graph_to_dot`).
+pub fn cfg_to_dot(func_name: &str, cfg: &VW_CFG, irmap: &IRMap, annotations: &[DotAnnotation], accesses: &AccessClasses) -> String {
+    let graph = BlockGraph {
+        entrypoint: cfg.entrypoint,
+        blocks: cfg.blocks.iter().map(|(addr, block)| (*addr, BlockRange { start: block.start, end: block.end })).collect(),
+        edges: cfg.graph.all_edges().map(|(from, to, _)| (from, to)).collect(),
+    };
+    graph_to_dot(func_name, &graph, irmap, annotations, accesses)
+}
+
+/// Writes the dot graph of one function to `<dir>/<func>.dot`.
+pub fn write_dot(dir: &str, func_name: &str, dot: &str) -> std::io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = Path::new(dir).join(format!("{}.dot", func_name.replace('/', "_")));
+    fs::write(&path, dot)?;
+    Ok(path)
+}
+
+#[test]
+fn graph_to_dot_test() {
+    use crate::checkers::heap_checker::AccessClass;
+    use crate::lattices::heaplattice::{HeapLattice, HeapValue, HeapValueLattice};
+    use crate::utils::ir::{ImmType, Stmt, Unopcode, ValSize, Value};
+
+    // 0 branches to 0x10 and back to itself
+    let graph = BlockGraph {
+        entrypoint: 0,
+        blocks: vec![(0, BlockRange { start: 0, end: 0xf }), (0x10, BlockRange { start: 0x10, end: 0x10 })].into_iter().collect(),
+        edges: vec![(0, 0), (0, 0x10)].into_iter().collect(),
+    };
+    let mov = |v: i64| Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size64), Value::Imm(ImmType::Signed, ValSize::Size64, v));
+    let mut irmap = IRMap::new();
+    irmap.insert(0, (0..40).map(|i| (i as u64 * 4, vec![mov(i)])).collect());
+    irmap.insert(0x10, vec![(0x10, vec![Stmt::Ret])]);
+    let mut state: HeapLattice = Default::default();
+    state.regs.rdi = HeapValueLattice::new(HeapValue::HeapBase);
+    let mut result = AnalysisResult::new();
+    result.insert(0x10, state);
+
+    let mut accesses = AccessClasses::new();
+    accesses.insert(LocIdx { addr: 4, idx: 0 }, vec![("Mem(..)".to_string(), AccessClass::Heap)]);
+
+    let dot = graph_to_dot("guest_func_\"0\"", &graph, &irmap, &[DotAnnotation::new("heap", &result)], &accesses);
+    assert!(dot.starts_with("digraph \"guest_func_\\\"0\\\"\" {"));
+    assert!(dot.contains("    \"0x0\" -> \"0x0\";\n    \"0x0\" -> \"0x10\";\n"));
+    assert!(dot.contains("[label=\"0x10-0x10\\l0x10: Ret\\lheap: "));
+    // the entry block's label is cut after MAX_LABEL_STMTS statements
+    assert!(dot.contains(&format!("0x{:x}: {:?}\\l... 8 more\\l", (MAX_LABEL_STMTS - 1) * 4, mov(MAX_LABEL_STMTS as i64 - 1))));
+    assert!(!dot.contains(&format!("{:?}", mov(MAX_LABEL_STMTS as i64))));
+    assert_eq!(dot.matches("style=bold").count(), 1);
+    // statements are followed by the class of their accesses
+    assert!(dot.contains(&format!("0x4: {:?}  ; heap-base+bounded\\l", mov(1))));
+    assert_eq!(dot.matches("  ; ").count(), 1);
+
+    assert_eq!(truncate("abcdef", 3), "abc...");
+    assert_eq!(truncate("abc", 3), "abc");
+}
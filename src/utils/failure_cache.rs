@@ -0,0 +1,216 @@
+use crate::utils::compiler::{Compiler, CompilerMetadata};
+use crate::utils::ir::IRMap;
+use serde_json::{json, Map, Value as Json};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+
+/// A failure seen in an earlier run, replayed instead of re-analysing the function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachedFailure {
+    pub func: String,
+    pub flags: u64,
+    pub report: String,
+}
+
+/// Opt-in cache of failing functions (`--cache-failures`), keyed by a hash of the lifted
+/// function. A hit is only replayed if it was recorded with the same flags, and a sample of
+/// hits is re-verified every run so a fix can't stay hidden behind the cache.
+pub struct FailureCache {
+    path: String,
+    recheck_percent: u64,
+    rng: u64,
+    pub entries: HashMap<u64, CachedFailure>,
+}
+
+/// A hash of a lifted function, independent of HashMap iteration order.
+pub fn function_key(irmap: &IRMap) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let mut blocks: Vec<_> = irmap.iter().collect();
+    blocks.sort_by_key(|(addr, _)| *addr);
+    for (addr, block) in blocks {
+        addr.hash(&mut hasher);
+        format!("{:?}", block).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// A hash of the verifier's version and of the sources it was built from (see build.rs), so that
+/// results and CFGs kept across runs are invalidated by changes to the verifier itself.
+pub fn verifier_key() -> u64 {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    env!("VERIWASM_BUILD_ID").hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A fingerprint of everything besides the code that decides whether a function verifies.
+/// `extra` covers command-line options that aren't part of the metadata.
+pub fn flags_fingerprint(metadata: &CompilerMetadata, extra: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    match metadata.compiler {
+        Compiler::Lucet => "lucet",
+        Compiler::Wamr => "wamr",
+        Compiler::Wasmtime => "wasmtime",
+    }
+    .hash(&mut hasher);
+    metadata.guest_table_0.hash(&mut hasher);
+    metadata.lucet_tables.hash(&mut hasher);
+    metadata.lucet_probestack.hash(&mut hasher);
+    metadata.lucet_safe_page.hash(&mut hasher);
+    metadata.globals_size.hash(&mut hasher);
+    metadata.call_table_size.hash(&mut hasher);
+    metadata.caller_window.hash(&mut hasher);
+    metadata.strict_stack.hash(&mut hasher);
+    metadata.strict_rebound.hash(&mut hasher);
+    metadata.runtime_fields.hash(&mut hasher);
+    metadata.wamr_memories.hash(&mut hasher);
+    metadata.wamr_tables.hash(&mut hasher);
+    metadata.options.hash(&mut hasher);
+    metadata.abi.hash(&mut hasher);
+    metadata.readonly_data.hash(&mut hasher);
+    metadata.wasmtime_layout.hash(&mut hasher);
+    extra.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl FailureCache {
+    /// Loads the cache at `path`, starting empty if there is none yet.
+    pub fn load(path: &str, recheck_percent: u64, seed: u64) -> Result<FailureCache, String> {
+        let mut cache = FailureCache {
+            path: path.to_string(),
+            recheck_percent: recheck_percent,
+            rng: seed,
+            entries: HashMap::new(),
+        };
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(_) => return Ok(cache),
+        };
+        let json: Json = serde_json::from_str(&data).map_err(|e| format!("{}: {}", path, e))?;
+        let entries = json.as_object().ok_or_else(|| format!("{}: malformed failure cache", path))?;
+        for (key, entry) in entries {
+            let parse = || -> Option<(u64, CachedFailure)> {
+                Some((
+                    u64::from_str_radix(key, 16).ok()?,
+                    CachedFailure {
+                        func: entry.get("func")?.as_str()?.to_string(),
+                        flags: u64::from_str_radix(entry.get("flags")?.as_str()?, 16).ok()?,
+                        report: entry.get("report")?.as_str()?.to_string(),
+                    },
+                ))
+            };
+            let (key, failure) = parse().ok_or_else(|| format!("{}: malformed entry {}", path, key))?;
+            cache.entries.insert(key, failure);
+        }
+        Ok(cache)
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let entries: Map<String, Json> = self
+            .entries
+            .iter()
+            .map(|(key, failure)| {
+                let entry = json!({
+                    "func": failure.func,
+                    "flags": format!("{:x}", failure.flags),
+                    "report": failure.report,
+                });
+                (format!("{:x}", key), entry)
+            })
+            .collect();
+        fs::write(&self.path, Json::Object(entries).to_string())
+    }
+
+    // splitmix64, so that which entries get re-verified is reproducible from the seed
+    fn next_random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// The cached failure report to replay for this function, or None if it has to be verified:
+    /// there is no entry, the entry was recorded with other flags, or it was picked for a re-check.
+    pub fn lookup(&mut self, key: u64, flags: u64) -> Option<String> {
+        let report = match self.entries.get(&key) {
+            Some(failure) if failure.flags == flags => failure.report.clone(),
+            _ => return None,
+        };
+        if self.next_random() % 100 < self.recheck_percent {
+            return None;
+        }
+        Some(report)
+    }
+
+    pub fn record_failure(&mut self, key: u64, func: &str, flags: u64, report: &str) {
+        self.entries.insert(
+            key,
+            CachedFailure {
+                func: func.to_string(),
+                flags: flags,
+                report: report.to_string(),
+            },
+        );
+    }
+
+    pub fn record_pass(&mut self, key: u64) {
+        self.entries.remove(&key);
+    }
+}
+
+#[test]
+fn failure_cache_test() {
+    use crate::utils::ir::Stmt;
+
+    let path = std::env::temp_dir().join(format!("veriwasm-failure-cache-{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let flags = flags_fingerprint(&metadata, "");
+    let mut broken = IRMap::new();
+    broken.insert(0, vec![(0, vec![Stmt::Undefined]), (2, vec![Stmt::Ret])]);
+    let mut fixed = IRMap::new();
+    fixed.insert(0, vec![(0, vec![Stmt::Ret])]);
+    let key = function_key(&broken);
+    assert_eq!(key, function_key(&broken.clone()));
+    assert_ne!(key, function_key(&fixed));
+
+    let mut cache = FailureCache::load(path, 0, 1).unwrap();
+    assert_eq!(cache.lookup(key, flags), None);
+    cache.record_failure(key, "guest_func_0", flags, "Not Heap Safe");
+    cache.save().unwrap();
+
+    // a hit is replayed, unless the flags changed
+    let mut cache = FailureCache::load(path, 0, 1).unwrap();
+    assert_eq!(cache.lookup(key, flags), Some("Not Heap Safe".to_string()));
+    let mut strict = metadata.clone();
+    strict.strict_stack = true;
+    assert_eq!(cache.lookup(key, flags_fingerprint(&strict, "")), None);
+    let mut strict = metadata.clone();
+    strict.strict_rebound = true;
+    assert_eq!(cache.lookup(key, flags_fingerprint(&strict, "")), None);
+    let mut coarse = metadata.clone();
+    coarse.options.stack_tracking = false;
+    assert_eq!(cache.lookup(key, flags_fingerprint(&coarse, "")), None);
+    assert_eq!(cache.lookup(key, flags_fingerprint(&metadata, "--symbol-map")), None);
+
+    // a sampled re-check that passes drops the entry
+    let mut cache = FailureCache::load(path, 100, 1).unwrap();
+    assert_eq!(cache.lookup(key, flags), None);
+    cache.record_pass(key);
+    cache.save().unwrap();
+    let mut cache = FailureCache::load(path, 0, 1).unwrap();
+    assert_eq!(cache.lookup(key, flags), None);
+
+    // sampling is reproducible from the seed
+    let picks = |seed: u64| {
+        let mut cache = FailureCache::load(path, 50, seed).unwrap();
+        cache.record_failure(key, "guest_func_0", flags, "Not Heap Safe");
+        (0..32).map(|_| cache.lookup(key, flags).is_some()).collect::<Vec<bool>>()
+    };
+    assert_eq!(picks(7), picks(7));
+    assert!(picks(7).contains(&true) && picks(7).contains(&false));
+    fs::remove_file(path).unwrap();
+}
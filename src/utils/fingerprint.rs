@@ -0,0 +1,87 @@
+use crate::analyses::AnalysisResult;
+use crate::utils::persist::Persist;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+
+/*
+ * `--state-fingerprint <func>`: a hash of every block's entry state, for finding where two runs
+ * that should agree diverge. States are hashed through their persisted encoding, which leaves out
+ * default entries and orders everything it holds, so equal states hash the same however their
+ * maps happen to be laid out. Printing the table in two runs and diffing the output points at the
+ * analysis and the block whose state differs.
+ */
+
+/// The fingerprint of each block's entry state, by block address.
+pub fn state_fingerprints<State: Persist>(result: &AnalysisResult<State>) -> BTreeMap<u64, u64> {
+    result
+        .iter()
+        .map(|(addr, state)| {
+            let mut hasher = DefaultHasher::new();
+            state.to_json().to_string().hash(&mut hasher);
+            (*addr, hasher.finish())
+        })
+        .collect()
+}
+
+/// One `<analysis> 0x<block> <fingerprint>` line per block, in address order.
+pub fn fingerprint_table(analysis: &str, fingerprints: &BTreeMap<u64, u64>) -> Vec<String> {
+    fingerprints
+        .iter()
+        .map(|(addr, fingerprint)| format!("{:<6} 0x{:x} {:016x}", analysis, addr, fingerprint))
+        .collect()
+}
+
+#[test]
+fn state_fingerprint_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::Diamond;
+    use crate::lattices::heaplattice::{HeapLattice, HeapValue, HeapValueLattice};
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::ir::{IRMap, ImmType, MemArg, MemArgs, Stmt, Unopcode, ValSize, Value};
+
+    let analyzer = HeapAnalyzer {
+        metadata: CompilerMetadata::for_test(Compiler::Lucet),
+    };
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let slot = |offset: i64| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(4, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, offset)),
+    );
+    let mut irmap = IRMap::new();
+    // spill the heap base to two stack slots, so the states hold maps
+    irmap.insert(0, vec![
+        (0, vec![Stmt::Unop(Unopcode::Mov, slot(8), reg(7))]),
+        (4, vec![Stmt::Unop(Unopcode::Mov, slot(16), reg(7))]),
+    ]);
+    irmap.insert(0x10, vec![(0x10, vec![Stmt::Unop(Unopcode::Mov, reg(0), reg(7))])]);
+    irmap.insert(0x20, vec![(0x20, vec![Stmt::Unop(Unopcode::Mov, reg(1), reg(7))])]);
+    irmap.insert(0x30, vec![(0x30, vec![Stmt::Ret])]);
+
+    // stable across runs, and tells the blocks apart
+    let first = state_fingerprints(&run_worklist(&Diamond, &irmap, &analyzer));
+    let second = state_fingerprints(&run_worklist(&Diamond, &irmap, &analyzer));
+    assert_eq!(first, second);
+    assert_eq!(first.keys().copied().collect::<Vec<u64>>(), vec![0, 0x10, 0x20, 0x30]);
+    assert_ne!(first[&0x10], first[&0x30]);
+
+    // and doesn't depend on the order entries were added in
+    let (base, bounded) = (HeapValueLattice::new(HeapValue::HeapBase), HeapValueLattice::new(HeapValue::Bounded4GB));
+    let mut a: HeapLattice = Default::default();
+    a.stack.update(8, base.clone(), 8);
+    a.stack.update(16, bounded.clone(), 8);
+    a.stack.update(24, base.clone(), 8);
+    let mut b: HeapLattice = Default::default();
+    b.stack.update(24, base.clone(), 8);
+    b.stack.update(16, bounded.clone(), 8);
+    b.stack.update(8, base.clone(), 8);
+    let (mut results_a, mut results_b) = (AnalysisResult::new(), AnalysisResult::new());
+    results_a.insert(0, a);
+    results_b.insert(0, b);
+    assert_eq!(state_fingerprints(&results_a), state_fingerprints(&results_b));
+
+    let table = fingerprint_table("heap", &first);
+    assert_eq!(table.len(), 4);
+    assert!(table[3].starts_with("heap   0x30 "));
+}
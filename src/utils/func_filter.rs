@@ -0,0 +1,89 @@
+/*
+ * `--func`: restricting a run to some of the module's functions, given by name or by hex address.
+ * Only which functions get verified changes; the checkers still see every function in the module
+ * as a valid call target.
+ */
+
+// Unknown names list at most this many suggestions
+const MAX_SUGGESTIONS: usize = 5;
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur.push(substitute.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// The names in `funcs` closest to `name`, closest first: those containing it, or a few edits away.
+pub fn close_matches<'a>(name: &str, funcs: &'a [(u64, String)]) -> Vec<&'a str> {
+    let max_distance = (name.len() / 3).max(2);
+    let mut matches: Vec<(usize, &str)> = funcs
+        .iter()
+        .map(|(_, func_name)| (edit_distance(name, func_name), func_name.as_str()))
+        .filter(|(distance, func_name)| *distance <= max_distance || func_name.contains(name))
+        .collect();
+    matches.sort();
+    matches.dedup();
+    matches.into_iter().take(MAX_SUGGESTIONS).map(|(_, func_name)| func_name).collect()
+}
+
+/// The functions of `funcs` picked by `selectors`, each a function name or a 0x-prefixed address,
+/// in the order of `funcs`.
+pub fn select_funcs(funcs: &[(u64, String)], selectors: &[String]) -> Result<Vec<(u64, String)>, String> {
+    let mut selected: Vec<bool> = vec![false; funcs.len()];
+    for selector in selectors {
+        let selector = selector.trim();
+        let idx = match selector.strip_prefix("0x") {
+            Some(hex) => {
+                let addr = u64::from_str_radix(hex, 16).map_err(|_| format!("Invalid function address {:?}", selector))?;
+                funcs.iter().position(|(func_addr, _)| *func_addr == addr)
+                    .ok_or_else(|| format!("No function at 0x{:x}", addr))?
+            }
+            None => match funcs.iter().position(|(_, func_name)| func_name == selector) {
+                Some(idx) => idx,
+                None => {
+                    let suggestions = close_matches(selector, funcs);
+                    return Err(if suggestions.is_empty() {
+                        format!("Unknown function {:?}", selector)
+                    } else {
+                        format!("Unknown function {:?}, did you mean: {}", selector, suggestions.join(", "))
+                    });
+                }
+            },
+        };
+        selected[idx] = true;
+    }
+    Ok(funcs.iter().zip(selected).filter(|(_, picked)| *picked).map(|(func, _)| func.clone()).collect())
+}
+
+#[test]
+fn select_funcs_test() {
+    let funcs: Vec<(u64, String)> = vec![
+        (0x1000, "guest_func_0".to_string()),
+        (0x2000, "guest_func_1".to_string()),
+        (0x3000, "guest_func_12".to_string()),
+        (0x4000, "lucet_probestack".to_string()),
+    ];
+    let selectors = |s: &str| -> Vec<String> { s.split(",").map(|f| f.to_string()).collect() };
+
+    // names and addresses mix, and the module's order is kept
+    let picked = select_funcs(&funcs, &selectors("guest_func_12,0x1000")).unwrap();
+    assert_eq!(picked, vec![funcs[0].clone(), funcs[2].clone()]);
+    assert_eq!(select_funcs(&funcs, &selectors("0x2000,guest_func_1")).unwrap(), vec![funcs[1].clone()]);
+
+    assert_eq!(select_funcs(&funcs, &selectors("0x2001")), Err("No function at 0x2001".to_string()));
+    assert_eq!(select_funcs(&funcs, &selectors("0xzz")), Err("Invalid function address \"0xzz\"".to_string()));
+    assert_eq!(
+        select_funcs(&funcs, &selectors("guest_func_2")),
+        Err("Unknown function \"guest_func_2\", did you mean: guest_func_0, guest_func_1, guest_func_12".to_string())
+    );
+    assert_eq!(close_matches("probestack", &funcs), vec!["lucet_probestack"]);
+    assert_eq!(select_funcs(&funcs, &selectors("main")), Err("Unknown function \"main\"".to_string()));
+}
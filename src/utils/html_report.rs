@@ -0,0 +1,365 @@
+use crate::utils::caveats::SoundnessCaveat;
+use crate::utils::stats::FunctionStats;
+use std::fs;
+
+/*
+ * A single self-contained HTML page summarizing a run, for readers who won't dig through the log
+ * or the stats JSON: a sortable overview of every function, the soundness caveats the verdict rests
+ * on, and a section per failing function.
+ *
+ * Pages are filled in from the templates below; `{{name}}` is replaced by an escaped value and
+ * `{{{name}}}` by already rendered HTML. Nothing run-dependent besides the results goes into the
+ * page (no timestamps), so the same run always renders the same report.
+ */
+
+const PAGE_TEMPLATE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>VeriWasm report: {{module}}</title>
+<style>
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1.5em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }
+th.sortable { cursor: pointer; background: #f0f0f0; }
+tr.failed td.verdict { color: #b00; font-weight: bold; }
+tr.verified td.verdict { color: #070; }
+tr.denied td { color: #b00; }
+pre { background: #f6f6f6; padding: 0.6em; overflow-x: auto; }
+section.failure { border-left: 4px solid #b00; padding-left: 1em; margin-bottom: 2em; }
+</style>
+</head>
+<body>
+<h1>VeriWasm report</h1>
+<table>
+{{{header}}}</table>
+<h2>Functions</h2>
+<p>{{summary}}</p>
+<table id="functions">
+<thead><tr><th class="sortable">Function</th><th class="sortable">Address</th><th class="sortable">Verdict</th><th class="sortable">Blocks</th><th class="sortable">CFG (s)</th><th class="sortable">Heap (s)</th><th class="sortable">Stack (s)</th><th class="sortable">Call (s)</th><th class="sortable">Direct calls</th><th class="sortable">Indirect calls</th></tr></thead>
+<tbody>
+{{{rows}}}</tbody>
+</table>
+<h2>Soundness caveats</h2>
+{{{caveats}}}<h2>Failures</h2>
+{{{failures}}}<script>
+document.querySelectorAll("#functions th.sortable").forEach(function (th, col) {
+  th.addEventListener("click", function () {
+    var body = document.querySelector("#functions tbody");
+    var rows = Array.prototype.slice.call(body.rows);
+    var asc = th.getAttribute("data-order") !== "asc";
+    th.setAttribute("data-order", asc ? "asc" : "desc");
+    rows.sort(function (a, b) {
+      var x = a.cells[col].getAttribute("data-sort"), y = b.cells[col].getAttribute("data-sort");
+      var cmp = isNaN(x) || isNaN(y) ? x.localeCompare(y) : x - y;
+      return asc ? cmp : -cmp;
+    });
+    rows.forEach(function (row) { body.appendChild(row); });
+  });
+});
+</script>
+</body>
+</html>
+"##;
+
+const HEADER_ROW_TEMPLATE: &str = "<tr><th>{{key}}</th><td>{{value}}</td></tr>\n";
+
+const FUNCTION_ROW_TEMPLATE: &str = r#"<tr class="{{class}}"><td data-sort="{{name}}">{{{name_cell}}}</td><td data-sort="{{addr_sort}}">{{addr}}</td><td class="verdict" data-sort="{{verdict}}">{{verdict}}</td><td data-sort="{{blocks}}">{{blocks}}</td><td data-sort="{{cfg_time}}">{{cfg_time}}</td><td data-sort="{{heap_time}}">{{heap_time}}</td><td data-sort="{{stack_time}}">{{stack_time}}</td><td data-sort="{{call_time}}">{{call_time}}</td><td data-sort="{{direct_calls}}">{{direct_calls}}</td><td data-sort="{{indirect_calls}}">{{indirect_calls}}</td></tr>
+"#;
+
+const FAILURE_TEMPLATE: &str = r#"<section class="failure" id="{{anchor}}">
+<h3>{{name}}</h3>
+<p>Rule <code>{{rule}}</code>: {{message}}</p>
+<pre>{{log}}</pre>
+{{{accesses}}}</section>
+"#;
+
+const CAVEATS_TEMPLATE: &str = r#"<table id="caveats">
+<thead><tr><th>Kind</th><th>Location</th><th>Count</th><th>Description</th></tr></thead>
+<tbody>
+{{{rows}}}</tbody>
+</table>
+"#;
+
+const CAVEAT_ROW_TEMPLATE: &str = r#"<tr class="{{class}}"><td><code>{{kind}}</code></td><td>{{location}}</td><td>{{count}}</td><td>{{description}}</td></tr>
+"#;
+
+const ACCESSES_TEMPLATE: &str = "<details><summary>Memory accesses by class</summary>\n<pre>{{accesses}}</pre>\n</details>\n";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    Verified,
+    Failed,
+    // failed in an earlier run and unchanged since, see failure_cache.rs
+    CachedFailure,
+}
+
+impl Verdict {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Verdict::Verified => "verified",
+            Verdict::Failed => "failed",
+            Verdict::CachedFailure => "failed (cached)",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FailureDetail {
+    // the check that failed: heap, stack or call, or cfg or panic if it failed before the checks
+    pub rule: String,
+    pub message: String,
+    // what was logged while verifying the function, including the failing statement and access
+    pub log: Vec<String>,
+    // the memory accesses the heap checker accepted, with their class (see access_class_lines)
+    pub accesses: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionReport {
+    pub stats: FunctionStats,
+    pub verdict: Verdict,
+    pub failure: Option<FailureDetail>,
+}
+
+pub struct HtmlReport {
+    pub path: String,
+    // module metadata and flags, shown as a key/value table on top
+    pub header: Vec<(String, String)>,
+    pub functions: Vec<FunctionReport>,
+    // see caveats.rs
+    pub caveats: Vec<SoundnessCaveat>,
+}
+
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Fills in `template`: `{{{key}}}` is replaced by the raw value, `{{key}}` by the escaped one.
+pub fn render(template: &str, vars: &[(&str, String)]) -> String {
+    let mut out = template.to_string();
+    for (key, value) in vars {
+        out = out.replace(&format!("{{{{{{{}}}}}}}", key), value);
+        out = out.replace(&format!("{{{{{}}}}}", key), &escape_html(value));
+    }
+    out
+}
+
+fn anchor(func: &FunctionReport) -> String {
+    let name: String = func.stats.name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    format!("failure-{}", name)
+}
+
+fn render_row(func: &FunctionReport) -> String {
+    let stats = &func.stats;
+    let name_cell = match func.failure {
+        Some(_) => format!("<a href=\"#{}\">{}</a>", anchor(func), escape_html(&stats.name)),
+        None => escape_html(&stats.name),
+    };
+    let class = if func.verdict == Verdict::Verified { "verified" } else { "failed" };
+    render(
+        FUNCTION_ROW_TEMPLATE,
+        &[
+            ("class", class.to_string()),
+            ("name_cell", name_cell),
+            ("name", stats.name.clone()),
+            ("addr_sort", stats.addr.map_or("0".to_string(), |a| a.to_string())),
+            ("addr", stats.addr.map_or("-".to_string(), |a| format!("0x{:x}", a))),
+            ("verdict", func.verdict.name().to_string()),
+            ("blocks", stats.blocks.to_string()),
+            ("cfg_time", format!("{:.3}", stats.cfg_time)),
+            ("heap_time", format!("{:.3}", stats.heap_time)),
+            ("stack_time", format!("{:.3}", stats.stack_time)),
+            ("call_time", format!("{:.3}", stats.call_time)),
+            ("direct_calls", stats.direct_calls.to_string()),
+            ("indirect_calls", stats.indirect_calls.to_string()),
+        ],
+    )
+}
+
+fn render_failure(func: &FunctionReport, failure: &FailureDetail) -> String {
+    let accesses = match failure.accesses.is_empty() {
+        true => String::new(),
+        false => render(ACCESSES_TEMPLATE, &[("accesses", failure.accesses.join("\n"))]),
+    };
+    render(
+        FAILURE_TEMPLATE,
+        &[
+            ("anchor", anchor(func)),
+            ("name", func.stats.name.clone()),
+            ("rule", failure.rule.clone()),
+            ("message", failure.message.clone()),
+            ("log", failure.log.join("\n")),
+            ("accesses", accesses),
+        ],
+    )
+}
+
+fn render_caveats(caveats: &[SoundnessCaveat]) -> String {
+    if caveats.is_empty() {
+        return "<p>None.</p>\n".to_string();
+    }
+    let rows: String = caveats
+        .iter()
+        .map(|caveat| {
+            render(
+                CAVEAT_ROW_TEMPLATE,
+                &[
+                    ("class", if caveat.denied { "denied" } else { "allowed" }.to_string()),
+                    ("kind", caveat.kind.name().to_string()),
+                    ("location", caveat.location()),
+                    ("count", caveat.count.to_string()),
+                    ("description", caveat.description.clone()),
+                ],
+            )
+        })
+        .collect();
+    render(CAVEATS_TEMPLATE, &[("rows", rows)])
+}
+
+impl HtmlReport {
+    pub fn new(path: &str, header: Vec<(String, String)>) -> HtmlReport {
+        HtmlReport {
+            path: path.to_string(),
+            header: header,
+            functions: vec![],
+            caveats: vec![],
+        }
+    }
+
+    pub fn add(&mut self, func: FunctionReport) {
+        self.functions.push(func);
+    }
+
+    pub fn render(&self) -> String {
+        let module = self.header.iter().find(|(k, _)| k == "Module").map_or("", |(_, v)| v);
+        let header: String = self
+            .header
+            .iter()
+            .map(|(k, v)| render(HEADER_ROW_TEMPLATE, &[("key", k.clone()), ("value", v.clone())]))
+            .collect();
+        let rows: String = self.functions.iter().map(render_row).collect();
+        let mut failures: String = self
+            .functions
+            .iter()
+            .filter_map(|f| f.failure.as_ref().map(|failure| render_failure(f, failure)))
+            .collect();
+        if failures.is_empty() {
+            failures = "<p>None.</p>\n".to_string();
+        }
+        let failed = self.functions.iter().filter(|f| f.verdict != Verdict::Verified).count();
+        let summary = format!("{} functions, {} verified, {} failed", self.functions.len(), self.functions.len() - failed, failed);
+        render(
+            PAGE_TEMPLATE,
+            &[
+                ("module", module.to_string()),
+                ("header", header),
+                ("summary", summary),
+                ("rows", rows),
+                ("caveats", render_caveats(&self.caveats)),
+                ("failures", failures),
+            ],
+        )
+    }
+
+    pub fn write(&self) -> std::io::Result<()> {
+        fs::write(&self.path, self.render())
+    }
+}
+
+#[test]
+fn html_report_test() {
+    let stats = |name: &str, addr: u64, blocks: usize| FunctionStats {
+        name: name.to_string(),
+        addr: Some(addr),
+        blocks: blocks,
+        cfg_time: 0.01,
+        heap_time: 0.02,
+        stack_time: 0.03,
+        call_time: 0.0,
+        direct_calls: 2,
+        indirect_calls: 1,
+        worklist: Default::default(),
+    };
+    let mut report = HtmlReport::new(
+        "report.html",
+        vec![
+            ("Module".to_string(), "fixture<1>.so".to_string()),
+            ("Compiler".to_string(), "Lucet".to_string()),
+        ],
+    );
+    report.add(FunctionReport {
+        stats: stats("guest_func_0", 0x1000, 12),
+        verdict: Verdict::Verified,
+        failure: None,
+    });
+    report.add(FunctionReport {
+        stats: stats("guest_func_1", 0x2000, 7),
+        verdict: Verdict::Failed,
+        failure: Some(FailureDetail {
+            rule: "heap".to_string(),
+            message: "Not Heap Safe".to_string(),
+            log: vec![
+                "mem[Some(HeapBase) + None]".to_string(),
+                "0x2010 failing statement: Unop(Mov, ...)".to_string(),
+            ],
+            accesses: vec!["0x2008[0]: Mem(Size64, Mem1Arg(Reg(7, Size64))): heap-base+bounded".to_string()],
+        }),
+    });
+    assert!(report.render().contains("<h2>Soundness caveats</h2>\n<p>None.</p>"));
+    report.caveats.push(SoundnessCaveat {
+        function: Some("guest_func_0".to_string()),
+        denied: true,
+        ..SoundnessCaveat::new(crate::utils::caveats::CaveatKind::UnliftedInstruction, Some(0x1008), "unimplemented instr: cpuid".to_string())
+    });
+    let html = report.render();
+
+    assert!(html.contains("<h2>Soundness caveats</h2>\n<table id=\"caveats\">"));
+    assert!(html.contains("<tr class=\"denied\"><td><code>unlifted_instruction</code></td><td>guest_func_0 at 0x1008</td><td>1</td>"));
+    assert!(html.contains("guest_func_0") && html.contains("guest_func_1"));
+    assert!(html.contains("<code>heap</code>: Not Heap Safe"));
+    assert!(html.contains(">verified<") && html.contains(">failed<"));
+    assert!(html.contains("<a href=\"#failure-guest_func_1\">guest_func_1</a>"));
+    assert!(html.contains("0x2010 failing statement"));
+    assert!(html.contains("<pre>0x2008[0]: Mem(Size64, Mem1Arg(Reg(7, Size64))): heap-base+bounded</pre>"));
+    assert_eq!(html.matches("<details>").count(), 1);
+    assert!(html.contains("2 functions, 1 verified, 1 failed"));
+    // names and log lines are escaped, and nothing is left unfilled
+    assert!(html.contains("fixture&lt;1&gt;.so") && !html.contains("fixture<1>"));
+    assert!(!html.contains("{{"));
+    assert_eq!(html, report.render());
+
+    // smoke check that the page is well-formed: every element opened is closed, in order
+    assert!(html.starts_with("<!DOCTYPE html>") && html.trim_end().ends_with("</html>"));
+    let void = ["meta", "!DOCTYPE"];
+    let mut open: Vec<String> = vec![];
+    let mut rest = &html[..];
+    while let Some(start) = rest.find('<') {
+        let end = rest[start..].find('>').unwrap() + start;
+        let tag = &rest[start + 1..end];
+        let name = tag.trim_start_matches('/').split_whitespace().next().unwrap();
+        if tag.starts_with('/') {
+            assert_eq!(open.pop().as_deref(), Some(name));
+        } else if !void.contains(&name) {
+            open.push(name.to_string());
+        }
+        rest = &rest[end + 1..];
+        // the script and style bodies aren't markup
+        if name == "script" || name == "style" {
+            let close = rest.find(&format!("</{}>", name)).unwrap();
+            rest = &rest[close..];
+        }
+    }
+    assert!(open.is_empty());
+}
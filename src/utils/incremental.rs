@@ -0,0 +1,228 @@
+use crate::utils::failure_cache::verifier_key;
+use crate::utils::verification_report::FunctionResult;
+use object::{Object, ObjectSegment};
+use serde_json::{json, Value as Json};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+
+/*
+ * Incremental re-verification (`--deps` and `--changed-ranges`). A run with `--deps <file>`
+ * records each function's result along with the bytes its verification read: the code of its
+ * blocks, and the jump tables its indirect jumps were resolved through. Given the byte ranges of
+ * the module that changed since, a later run only verifies the functions whose recorded bytes
+ * intersect a changed range, and those the record doesn't know; the rest keep their recorded
+ * results. Changes to anything else the checks depend on (the flags, the function list, the PLT
+ * or the verifier itself) show up in the record's fingerprint, and make every function stale.
+ */
+
+/// The bytes verifying a function read, as half-open address ranges.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FunctionDeps {
+    pub code: Vec<(u64, u64)>,
+    pub data: Vec<(u64, u64)>,
+}
+
+impl FunctionDeps {
+    pub fn intersects(&self, changed: &[(u64, u64)]) -> bool {
+        self.code
+            .iter()
+            .chain(self.data.iter())
+            .any(|(start, end)| changed.iter().any(|(changed_start, changed_end)| start < changed_end && changed_start < end))
+    }
+
+    fn to_json(&self) -> Json {
+        json!({ "code": self.code, "data": self.data })
+    }
+
+    fn from_json(json: &Json) -> Option<FunctionDeps> {
+        let ranges = |key: &str| -> Option<Vec<(u64, u64)>> {
+            json.get(key)?
+                .as_array()?
+                .iter()
+                .map(|range| Some((range.get(0)?.as_u64()?, range.get(1)?.as_u64()?)))
+                .collect()
+        };
+        Some(FunctionDeps { code: ranges("code")?, data: ranges("data")? })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedFunction {
+    pub result: FunctionResult,
+    // None if the function failed before its CFG was built, so it is always verified again
+    pub deps: Option<FunctionDeps>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DependencyRecord {
+    pub fingerprint: u64,
+    // by address
+    pub functions: BTreeMap<u64, RecordedFunction>,
+}
+
+/// A fingerprint of the verifier (see verifier_key), the flags (see flags_fingerprint) and of the
+/// functions and PLT the direct call checks accept as targets.
+pub fn dependency_fingerprint(flags: u64, funcs: &[(u64, String)], valid_funcs: &[u64], plt: (u64, u64)) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    verifier_key().hash(&mut hasher);
+    flags.hash(&mut hasher);
+    funcs.hash(&mut hasher);
+    valid_funcs.hash(&mut hasher);
+    plt.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl DependencyRecord {
+    /// Loads the record at `path`, starting empty if there is none yet.
+    pub fn load(path: &str) -> Result<DependencyRecord, String> {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(_) => return Ok(DependencyRecord::default()),
+        };
+        let json: Json = serde_json::from_str(&data).map_err(|e| format!("{}: {}", path, e))?;
+        let parse = || -> Option<DependencyRecord> {
+            let mut record = DependencyRecord {
+                fingerprint: u64::from_str_radix(json.get("fingerprint")?.as_str()?, 16).ok()?,
+                functions: BTreeMap::new(),
+            };
+            for func in json.get("functions")?.as_array()? {
+                let result = FunctionResult::from_json(func.get("result")?)?;
+                let deps = match func.get("deps")? {
+                    Json::Null => None,
+                    deps => Some(FunctionDeps::from_json(deps)?),
+                };
+                record.functions.insert(result.addr, RecordedFunction { result: result, deps: deps });
+            }
+            Some(record)
+        };
+        parse().ok_or_else(|| format!("{}: malformed dependency record", path))
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let functions: Vec<Json> = self
+            .functions
+            .values()
+            .map(|func| json!({
+                "result": func.result.to_json(),
+                "deps": func.deps.as_ref().map(|deps| deps.to_json()),
+            }))
+            .collect();
+        let json = json!({
+            "fingerprint": format!("{:x}", self.fingerprint),
+            "functions": functions,
+        });
+        fs::write(path, json.to_string())
+    }
+
+    /// The functions of `funcs` that have to be verified again after the bytes at `changed`
+    /// (address ranges) changed, in the order of `funcs`.
+    pub fn stale_functions(&self, funcs: &[(u64, String)], fingerprint: u64, changed: &[(u64, u64)]) -> Vec<(u64, String)> {
+        if fingerprint != self.fingerprint {
+            return funcs.to_vec();
+        }
+        funcs
+            .iter()
+            .filter(|(addr, name)| match self.functions.get(addr) {
+                Some(RecordedFunction { result, deps: Some(deps) }) if result.name == *name => deps.intersects(changed),
+                _ => true,
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Reads a `--changed-ranges` file: a JSON list of `[offset, len]` pairs of file offsets, returned
+/// as half-open ranges.
+pub fn parse_changed_ranges(data: &str) -> Result<Vec<(u64, u64)>, String> {
+    let json: Json = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    let ranges = json.as_array().ok_or("expected a list of [offset, len] pairs")?;
+    ranges
+        .iter()
+        .map(|range| match (range.get(0).and_then(|o| o.as_u64()), range.get(1).and_then(|l| l.as_u64())) {
+            (Some(offset), Some(len)) if range.as_array().map_or(false, |r| r.len() == 2) => Ok((offset, offset + len)),
+            _ => Err(format!("malformed range {}", range)),
+        })
+        .collect()
+}
+
+/// The loaded segments of an ELF module, as (file offset, file size, address).
+pub fn load_segments(module: &[u8]) -> Result<Vec<(u64, u64, u64)>, String> {
+    let file = object::File::parse(module).map_err(|e| e.to_string())?;
+    Ok(file
+        .segments()
+        .map(|segment| {
+            let (offset, size) = segment.file_range();
+            (offset, size, segment.address())
+        })
+        .collect())
+}
+
+/// The address ranges the file offset ranges `ranges` are loaded at. Bytes that aren't loaded
+/// (symbols, section headers) are dropped: they can only matter through the function list,
+/// which the fingerprint covers.
+pub fn file_ranges_to_addrs(segments: &[(u64, u64, u64)], ranges: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    let mut addrs = vec![];
+    for (start, end) in ranges {
+        for (offset, size, addr) in segments {
+            let (overlap_start, overlap_end) = ((*start).max(*offset), (*end).min(offset + size));
+            if overlap_start < overlap_end {
+                addrs.push((addr + (overlap_start - offset), addr + (overlap_end - offset)));
+            }
+        }
+    }
+    addrs
+}
+
+#[test]
+fn stale_functions_test() {
+    use crate::utils::verification_report::Property;
+
+    // guest_func_0 switches through a jump table at 0x8000..0x8010, guest_func_1 has none
+    let mut record = DependencyRecord { fingerprint: 7, functions: BTreeMap::new() };
+    record.functions.insert(0x1000, RecordedFunction {
+        result: FunctionResult::verified("guest_func_0", 0x1000),
+        deps: Some(FunctionDeps { code: vec![(0x1000, 0x1040), (0x1050, 0x1080)], data: vec![(0x8000, 0x8010)] }),
+    });
+    record.functions.insert(0x2000, RecordedFunction {
+        result: FunctionResult::failed("guest_func_1", 0x2000, Some(Property::Heap), "Not Heap Safe", None),
+        deps: Some(FunctionDeps { code: vec![(0x2000, 0x2020)], data: vec![] }),
+    });
+    let funcs = vec![(0x1000, "guest_func_0".to_string()), (0x2000, "guest_func_1".to_string())];
+    let stale = |changed: &[(u64, u64)]| -> Vec<u64> {
+        record.stale_functions(&funcs, 7, changed).iter().map(|(addr, _)| *addr).collect()
+    };
+
+    // a byte of the jump table changed: only the function switching through it is stale
+    assert_eq!(stale(&[(0x800c, 0x800d)]), vec![0x1000]);
+    assert_eq!(stale(&[(0x2010, 0x2011)]), vec![0x2000]);
+    // the gap between guest_func_0's blocks, and the byte right after its table
+    assert!(stale(&[(0x1040, 0x1050), (0x8010, 0x8020)]).is_empty());
+    // other flags or functions, a renamed function, or one failing before its CFG was built
+    assert_eq!(record.stale_functions(&funcs, 8, &[]).len(), 2);
+    let renamed = vec![(0x1000, "guest_func_2".to_string())];
+    assert_eq!(record.stale_functions(&renamed, 7, &[]).len(), 1);
+    record.functions.get_mut(&0x2000).unwrap().deps = None;
+    assert_eq!(stale(&[]), vec![0x2000]);
+
+    let path = std::env::temp_dir().join(format!("veriwasm_deps_{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    record.save(path).unwrap();
+    assert_eq!(DependencyRecord::load(path), Ok(record));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn changed_ranges_test() {
+    assert_eq!(parse_changed_ranges("[[16, 4], [4096, 1]]"), Ok(vec![(16, 20), (4096, 4097)]));
+    assert!(parse_changed_ranges("[[16]]").is_err());
+    assert!(parse_changed_ranges("{}").is_err());
+
+    // .text at file offset 0x1000 loaded at 0x1000, .rodata at 0x3000 loaded at 0x4000
+    let segments = vec![(0x1000, 0x1000, 0x1000), (0x3000, 0x800, 0x4000)];
+    assert_eq!(
+        file_ranges_to_addrs(&segments, &[(0x1ff0, 0x3010), (0x3800, 0x3900), (0x10, 0x20)]),
+        vec![(0x1ff0, 0x2000), (0x4000, 0x4010)]
+    );
+}
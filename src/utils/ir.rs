@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub enum ImmType {
+    Signed,
+    Unsigned,
+}
+#[derive(Debug, Clone, Copy)]
+pub enum ValSize {
+    Size8,
+    Size16,
+    Size32,
+    Size64,
+    SizeOther,
+}
+
+impl ValSize {
+    pub fn to_u32(&self) -> u32 {
+        match self {
+            ValSize::Size8 => 8,
+            ValSize::Size16 => 16,
+            ValSize::Size32 => 32,
+            ValSize::Size64 => 64,
+            ValSize::SizeOther => 64, //panic!("unknown size? {:?}")
+        }
+    }
+}
+
+pub fn valsize(num: u32) -> ValSize {
+    match num {
+        8 => ValSize::Size8,
+        16 => ValSize::Size16,
+        32 => ValSize::Size32,
+        64 => ValSize::Size64,
+        _ => unimplemented!("{:?}", num),
+    }
+}
+
+pub fn mk_value_i64(num: i64) -> Value {
+    Value::Imm(ImmType::Signed, ValSize::Size64, num)
+}
+
+
+#[derive(Debug, Clone)]
+pub enum MemArgs {
+    Mem1Arg(MemArg), // [arg]
+    Mem2Args(MemArg, MemArg), // [arg1 + arg2]
+    Mem3Args(MemArg, MemArg, MemArg), // [arg1 + arg2 + arg3]
+    MemScale(MemArg, MemArg, MemArg), // [arg1 + arg2 * arg3]
+    MemScaleDisp(MemArg, MemArg, MemArg, MemArg), // [arg1 + arg2 * arg3 + arg4]
+}
+#[derive(Debug, Clone)]
+pub enum MemArg {
+    Reg(u8, ValSize), // register mappings captured in `crate::lattices::regslattice`
+    Imm(ImmType, ValSize, i64), // signed, size, const
+}
+#[derive(Debug, Clone)]
+pub enum Value {
+    Mem(ValSize, MemArgs), // mem[memargs]
+    Reg(u8, ValSize), // register mappings captured in `crate::lattices::regslattice`
+    Imm(ImmType, ValSize, i64), // signed, size, const
+}
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Clear(Value, Vec<Value>), // clear v <- vs
+    Unop(Unopcode, Value, Value), // v1 <- uop v2
+    Binop(Binopcode, Value, Value, Value), // v1 <- bop v2 v3
+    Undefined, // undefined
+    Ret, // return
+    Branch(yaxpeax_x86::long_mode::Opcode, Value), // br branch-type v
+    Call(Value, u8), // call v (with the call's encoded length, which relative targets are from)
+    ProbeStack(u64), // probestack
+    Forbidden(yaxpeax_x86::long_mode::Opcode), // an instruction guest code may never execute
+}
+
+impl Value {
+    /// The width of the value in bytes, e.g. of the memory a `Value::Mem` reads or writes.
+    pub fn width(&self) -> u32 {
+        match self {
+            Value::Mem(size, _) | Value::Reg(_, size) | Value::Imm(_, size, _) => size.to_u32() / 8,
+        }
+    }
+
+    /// What writing to the value does to the rest of its register.
+    pub fn upper_bits(&self) -> UpperBits {
+        match self {
+            Value::Reg(_, ValSize::Size32) => UpperBits::Cleared,
+            Value::Reg(_, ValSize::Size16) | Value::Reg(_, ValSize::Size8) => UpperBits::Kept,
+            _ => UpperBits::Whole,
+        }
+    }
+}
+
+impl Stmt {
+    /// The width in bytes of what the statement writes, or 0 if it writes nothing.
+    pub fn width(&self) -> u32 {
+        match self {
+            Stmt::Clear(dst, _) | Stmt::Unop(_, dst, _) => dst.width(),
+            Stmt::Binop(Binopcode::Cmp, _, _, _) | Stmt::Binop(Binopcode::Test, _, _, _) => 0,
+            Stmt::Binop(_, dst, _, _) => dst.width(),
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Unopcode {
+    Mov,
+    MovZx, // a zero extension; the source keeps its own (narrower) width
+    MovSx, // a sign extension, likewise
+    Set,
+    Xchg, // an atomic read-modify-write of the destination (xchg, cmpxchg or xadd)
+}
+/// How a move fills its destination from a narrower source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extension {
+    // the source is as wide as the destination (or is truncated to it)
+    None,
+    Zero,
+    Sign,
+}
+
+/// What a register write does to the bits of the 64-bit register above the destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpperBits {
+    // the destination is the whole register, or memory
+    Whole,
+    // a 32-bit write clears them
+    Cleared,
+    // an 8 or 16-bit write keeps them
+    Kept,
+}
+
+/// The widths in bits a move reads and writes, and how the one becomes the other. Every move
+/// producer and consumer goes by this rather than by the width of the destination alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovKind {
+    pub src_bits: u32,
+    pub dst_bits: u32,
+    pub extension: Extension,
+    pub upper: UpperBits,
+}
+
+impl Stmt {
+    /// What the statement moves, if it is a mov, movzx or movsx. An immediate is lifted with
+    /// its extended value, so it is as wide as the destination; a wider source (`movd eax, xmm0`)
+    /// is read only to the destination's width.
+    pub fn mov_kind(&self) -> Option<MovKind> {
+        let (opcode, dst, src) = match self {
+            Stmt::Unop(Unopcode::Set, _, _) | Stmt::Unop(Unopcode::Xchg, _, _) => return None,
+            Stmt::Unop(opcode, dst, src) => (opcode, dst, src),
+            _ => return None,
+        };
+        let dst_bits = dst.width() * 8;
+        let src_bits = match src {
+            Value::Imm(_, _, _) => dst_bits,
+            _ => (src.width() * 8).min(dst_bits),
+        };
+        let extension = match opcode {
+            _ if src_bits == dst_bits => Extension::None,
+            Unopcode::MovSx => Extension::Sign,
+            _ => Extension::Zero,
+        };
+        Some(MovKind { src_bits: src_bits, dst_bits: dst_bits, extension: extension, upper: dst.upper_bits() })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Binopcode {
+    Test,
+    Rol,
+    Cmp,
+    Shl,
+    Shr,
+    And,
+    Add,
+    Sub,
+    // a conditional move, `dst = cond ? src2 : src1` (src1 being dst): either source may be left
+    Cmov,
+    // an unsigned (Div) or signed (Idiv) division of rdx:rax by src2, at the destination's width:
+    // `rax = rax / src2`, which reads rdx too, and then `rdx = rdx % src2`, by which point the
+    // low half of the dividend is the quotient, so only the remainder's bound by src2 is known
+    Div,
+    Idiv,
+}
+
+/// The halves (rdx, rax) of the dividend of a `Div` or `Idiv` writing `dst`, read at its width.
+pub fn dividend(dst: &Value) -> (Value, Value) {
+    let size = match dst {
+        Value::Reg(_, size) => *size,
+        _ => ValSize::Size64,
+    };
+    (Value::Reg(2, size), Value::Reg(0, size))
+}
+
+pub type IRBlock = Vec<(u64, Vec<Stmt>)>;
+
+/// How the statements of an IRBlock entry relate to the instruction at its address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstrOrigin {
+    // lifted from the instruction alone, into one or more statements
+    Lifted,
+    // a statement standing for a sequence of instructions starting with this one (ProbeStack)
+    Synthesized,
+    // no statements: the instruction is part of the probestack sequence before it
+    ConsumedByProbestack,
+}
+
+/// The bytes an IRBlock entry was lifted from, and how.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstrProvenance {
+    pub len: u8,
+    pub origin: InstrOrigin,
+}
+
+/// The provenance of every entry of a lifted block, in the order of the entries.
+pub type BlockProvenance = Vec<InstrProvenance>;
+
+/// The ranges of the block from `start` to `end` (inclusive, as block ends are) that no entry
+/// of `block` was lifted from, half-open and in order. Lifting accounts for every byte, so this
+/// is empty for a block straight from the lifter.
+pub fn unaccounted_bytes(start: u64, end: u64, block: &IRBlock, provenance: &BlockProvenance) -> Vec<(u64, u64)> {
+    let mut gaps = vec![];
+    let mut next = start;
+    for ((addr, _), instr) in block.iter().zip(provenance.iter()) {
+        if *addr > next {
+            gaps.push((next, *addr));
+        }
+        next = next.max(addr + instr.len as u64);
+    }
+    if next <= end {
+        gaps.push((next, end + 1));
+    }
+    gaps
+}
+
+/// Where the blocks of a spilled `IRMap` live, fetched one at a time (see ir_spill.rs).
+pub trait BlockStore: Send + Sync {
+    // in address order
+    fn addrs(&self) -> Vec<u64>;
+    fn load(&self, addr: u64) -> Option<Arc<IRBlock>>;
+}
+
+#[derive(Clone)]
+enum IRBlocks {
+    InMemory(HashMap<u64, Arc<IRBlock>>),
+    Spilled(Arc<dyn BlockStore>),
+}
+
+/// The lifted blocks of a function, by address. Very large functions can be spilled to disk, so
+/// blocks are handed out shared rather than borrowed from the map. The provenance of the blocks
+/// the lifter produced stays in memory either way; IR built by hand has none.
+#[derive(Clone)]
+pub struct IRMap {
+    blocks: IRBlocks,
+    provenance: HashMap<u64, Arc<BlockProvenance>>,
+}
+
+impl Default for IRMap {
+    fn default() -> Self {
+        IRMap { blocks: IRBlocks::InMemory(HashMap::new()), provenance: HashMap::new() }
+    }
+}
+
+impl std::fmt::Debug for IRMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.blocks {
+            IRBlocks::InMemory(blocks) => f.debug_map().entries(blocks.iter()).finish(),
+            IRBlocks::Spilled(store) => write!(f, "Spilled({} blocks)", store.addrs().len()),
+        }
+    }
+}
+
+impl IRMap {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The blocks of `store`, with the provenance of `irmap`, whose blocks it holds.
+    pub fn spilled(store: Arc<dyn BlockStore>, irmap: IRMap) -> Self {
+        IRMap { blocks: IRBlocks::Spilled(store), provenance: irmap.provenance }
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.blocks, IRBlocks::Spilled(_))
+    }
+
+    pub fn insert(&mut self, addr: u64, block: IRBlock) {
+        match &mut self.blocks {
+            IRBlocks::InMemory(blocks) => {
+                blocks.insert(addr, Arc::new(block));
+            }
+            IRBlocks::Spilled(_) => panic!("Spilled IR is read-only"),
+        }
+    }
+
+    /// Inserts a block from the lifter, with the provenance of each of its entries.
+    pub fn insert_lifted(&mut self, addr: u64, block: IRBlock, provenance: BlockProvenance) {
+        assert_eq!(block.len(), provenance.len(), "provenance of the block at 0x{:x}", addr);
+        self.insert(addr, block);
+        self.provenance.insert(addr, Arc::new(provenance));
+    }
+
+    pub fn get(&self, addr: &u64) -> Option<Arc<IRBlock>> {
+        match &self.blocks {
+            IRBlocks::InMemory(blocks) => blocks.get(addr).cloned(),
+            IRBlocks::Spilled(store) => store.load(*addr),
+        }
+    }
+
+    /// The provenance of the entries of the block at `addr`, if the lifter produced it.
+    pub fn provenance(&self, addr: &u64) -> Option<Arc<BlockProvenance>> {
+        self.provenance.get(addr).cloned()
+    }
+
+    pub fn contains_key(&self, addr: &u64) -> bool {
+        match &self.blocks {
+            IRBlocks::InMemory(blocks) => blocks.contains_key(addr),
+            IRBlocks::Spilled(store) => store.addrs().contains(addr),
+        }
+    }
+
+    pub fn keys(&self) -> Vec<u64> {
+        match &self.blocks {
+            IRBlocks::InMemory(blocks) => blocks.keys().copied().collect(),
+            IRBlocks::Spilled(store) => store.addrs(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every block, loading spilled ones as they're reached.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, Arc<IRBlock>)> + '_ {
+        self.keys().into_iter().filter_map(move |addr| self.get(&addr).map(|block| (addr, block)))
+    }
+}
+
+impl<'a> IntoIterator for &'a IRMap {
+    type Item = (u64, Arc<IRBlock>);
+    type IntoIter = Box<dyn Iterator<Item = (u64, Arc<IRBlock>)> + 'a>;
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
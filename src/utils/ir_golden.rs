@@ -0,0 +1,216 @@
+use crate::utils::ir::{IRMap, Stmt, Value};
+use crate::utils::persist::Persist;
+use crate::utils::stmt_id::{stable_id, StmtId};
+use serde_json::{json, Value as Json};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/*
+ * Golden IR: the lifted IR of a function, stored to detect any change in how it is lifted
+ * (`--ir-golden <dir>`). Changes in the lifter are where a soundness regression in a function
+ * that verifies would hide, so for the few functions that matter most (e.g. bounds-check helpers)
+ * the IR is compared with a golden file on every run.
+ *
+ * A golden file `<dir>/<func>.json` holds the function's instructions in address order, each
+ * with its offset from the function's start and its statements in the persisted IR schema (see
+ * persist.rs), named by their stable ids (see stmt_id.rs) with the offset for the address. The
+ * IR is normalized before it is stored or compared, so moving the function (within the module
+ * or to another build) changes nothing:
+ *  - instruction addresses are dropped: instructions are compared in order, and their offsets
+ *    only name statements in differences;
+ *  - the displacements of direct calls are dropped, as the callee moves independently of the
+ *    caller; branch displacements are kept, being relative within the function;
+ *  - everything else, including immediates, is compared as lifted, so a changed constant or
+ *    an address computed rip-relative is a difference.
+ */
+
+#[derive(Clone, Debug)]
+pub struct GoldenInstr {
+    // from the start of the function
+    pub offset: u64,
+    pub stmts: Vec<Stmt>,
+}
+
+/// A statement that differs from the golden IR, named by its stable id (with offsets for
+/// addresses): either side is None for a statement only the other has.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IrDiff {
+    pub id: StmtId,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+impl fmt::Display for IrDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.expected, &self.actual) {
+            (Some(expected), Some(actual)) => write!(f, "{}: expected {}, got {}", self.id, expected, actual),
+            (Some(expected), None) => write!(f, "{}: expected {}, missing", self.id, expected),
+            (None, Some(actual)) => write!(f, "{}: unexpected {}", self.id, actual),
+            (None, None) => write!(f, "{}", self.id),
+        }
+    }
+}
+
+fn normalize(stmt: &Stmt) -> Stmt {
+    match stmt {
+        Stmt::Call(Value::Imm(immtype, size, _), len) => Stmt::Call(Value::Imm(immtype.clone(), *size, 0), *len),
+        _ => stmt.clone(),
+    }
+}
+
+/// The normalized IR of the function at `entry`, in address order.
+pub fn golden_ir(irmap: &IRMap, entry: u64) -> Vec<GoldenInstr> {
+    let mut addrs = irmap.keys();
+    addrs.sort();
+    let mut instrs = vec![];
+    for block_addr in addrs {
+        if let Some(block) = irmap.get(&block_addr) {
+            for (addr, stmts) in block.iter() {
+                instrs.push(GoldenInstr {
+                    offset: addr.wrapping_sub(entry),
+                    stmts: stmts.iter().map(normalize).collect(),
+                });
+            }
+        }
+    }
+    instrs
+}
+
+fn stmt_ids(instr: &GoldenInstr) -> Vec<StmtId> {
+    (0..instr.stmts.len() as u32).filter_map(|idx| stable_id(&instr.stmts, instr.offset, idx)).collect()
+}
+
+/// The statements of `actual` that differ from `expected`, pairing instructions and their
+/// statements by position.
+pub fn diff_ir(expected: &[GoldenInstr], actual: &[GoldenInstr]) -> Vec<IrDiff> {
+    let render = |stmt: &Stmt| format!("{:?}", stmt);
+    let empty = |offset: u64| GoldenInstr { offset: offset, stmts: vec![] };
+    let mut diffs = vec![];
+    for i in 0..expected.len().max(actual.len()) {
+        let (expected, actual) = match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) => (e.clone(), a.clone()),
+            (Some(e), None) => (e.clone(), empty(e.offset)),
+            (None, Some(a)) => (empty(a.offset), a.clone()),
+            (None, None) => break,
+        };
+        let (expected_ids, actual_ids) = (stmt_ids(&expected), stmt_ids(&actual));
+        for j in 0..expected.stmts.len().max(actual.stmts.len()) {
+            let (e, a) = (expected.stmts.get(j), actual.stmts.get(j));
+            if let (Some(e), Some(a)) = (e, a) {
+                if e.to_json() == a.to_json() {
+                    continue;
+                }
+            }
+            diffs.push(IrDiff {
+                id: *expected_ids.get(j).or(actual_ids.get(j)).unwrap(),
+                expected: e.map(render),
+                actual: a.map(render),
+            });
+        }
+    }
+    diffs
+}
+
+pub fn golden_to_json(func_name: &str, instrs: &[GoldenInstr]) -> Json {
+    let instrs: Vec<Json> = instrs
+        .iter()
+        .map(|instr| {
+            let stmts: Vec<Json> = stmt_ids(instr)
+                .iter()
+                .zip(instr.stmts.iter())
+                .map(|(id, stmt)| json!({"id": id.to_string(), "stmt": stmt.to_json()}))
+                .collect();
+            json!({"offset": instr.offset, "stmts": stmts})
+        })
+        .collect();
+    json!({"func": func_name, "instrs": instrs})
+}
+
+pub fn golden_from_json(json: &Json) -> Option<Vec<GoldenInstr>> {
+    let mut instrs = vec![];
+    for instr in json.get("instrs")?.as_array()? {
+        let mut stmts = vec![];
+        for stmt in instr.get("stmts")?.as_array()? {
+            stmts.push(Stmt::from_json(stmt.get("stmt")?)?);
+        }
+        instrs.push(GoldenInstr { offset: instr.get("offset")?.as_u64()?, stmts: stmts });
+    }
+    Some(instrs)
+}
+
+pub fn golden_path(dir: &str, func_name: &str) -> PathBuf {
+    Path::new(dir).join(format!("{}.json", func_name.replace('/', "_")))
+}
+
+/// The golden IR of `func_name` in `dir`, or None if it has none.
+pub fn load_golden(dir: &str, func_name: &str) -> Result<Option<Vec<GoldenInstr>>, String> {
+    let path = golden_path(dir, func_name);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let json: Json = serde_json::from_str(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    golden_from_json(&json).map(Some).ok_or_else(|| format!("{}: malformed golden IR", path.display()))
+}
+
+pub fn write_golden(dir: &str, func_name: &str, instrs: &[GoldenInstr]) -> std::io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = golden_path(dir, func_name);
+    fs::write(&path, serde_json::to_string_pretty(&golden_to_json(func_name, instrs)).unwrap())?;
+    Ok(path)
+}
+
+#[test]
+fn ir_golden_test() {
+    use crate::utils::ir::{Binopcode, ImmType, MemArg, MemArgs, Unopcode, ValSize};
+    use crate::utils::stmt_id::StmtRole;
+    use yaxpeax_x86::long_mode::Opcode;
+
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size32, v);
+    let heap = |disp: i64| Value::Mem(ValSize::Size32, MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size32, disp)));
+    // a bounds-check helper at `entry`, calling another function at `callee`
+    let helper = |entry: u64, callee: u64, disp: i64| {
+        let mut irmap = IRMap::new();
+        irmap.insert(entry, vec![
+            (entry, vec![Stmt::Binop(Binopcode::Cmp, reg(6), reg(6), imm(0x1000))]),
+            (entry + 4, vec![Stmt::Branch(Opcode::JAE, imm(0x10))]),
+        ]);
+        irmap.insert(entry + 6, vec![
+            (entry + 6, vec![Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size32), heap(disp))]),
+            (entry + 9, vec![Stmt::Call(imm(callee as i64 - (entry as i64 + 14)), 5)]),
+            (entry + 14, vec![Stmt::Binop(Binopcode::Add, reg(4), reg(4), imm(8)), Stmt::Clear(Value::Reg(16, ValSize::Size8), vec![])]),
+            (entry + 18, vec![Stmt::Ret]),
+        ]);
+        irmap
+    };
+    let golden = golden_ir(&helper(0x1000, 0x5000, 0x10), 0x1000);
+
+    // the golden file round-trips, and matches the IR it was written from
+    let json = golden_to_json("bounds_check", &golden);
+    assert_eq!(json["instrs"][4]["stmts"][1]["id"], "0xe:flags.0");
+    let loaded = golden_from_json(&serde_json::from_str(&json.to_string()).unwrap()).unwrap();
+    assert_eq!(diff_ir(&loaded, &golden), vec![]);
+    assert_eq!(golden_from_json(&json!({"instrs": [{"offset": 0, "stmts": [{"stmt": ["nop"]}]}]})).map(|i| i.len()), None);
+
+    // moving the helper, and its callee somewhere else, changes nothing
+    assert_eq!(diff_ir(&golden, &golden_ir(&helper(0x8000, 0x2000, 0x10), 0x8000)), vec![]);
+
+    // a different operand is reported at its statement
+    let diffs = diff_ir(&golden, &golden_ir(&helper(0x1000, 0x5000, 0x14), 0x1000));
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(diffs[0].id, StmtId { addr: 6, role: StmtRole::Primary, ordinal: 0 });
+    assert!(diffs[0].to_string().starts_with("0x6:primary.0: expected Unop(Mov"));
+    assert!(diffs[0].actual.as_ref().unwrap().contains("20)"));
+
+    // as are statements and instructions that appeared or disappeared
+    let mut fewer = golden.clone();
+    fewer[4].stmts.pop();
+    fewer.pop();
+    let diffs = diff_ir(&golden, &fewer);
+    assert_eq!(diffs.len(), 2);
+    assert_eq!((diffs[0].id.addr, diffs[0].id.role, diffs[0].actual.is_none()), (0xe, StmtRole::FlagClear, true));
+    assert_eq!(diffs[1].to_string(), "0x12:primary.0: expected Ret, missing");
+    assert_eq!(diff_ir(&fewer, &golden)[1].to_string(), "0x12:primary.0: unexpected Ret");
+}
@@ -0,0 +1,213 @@
+use crate::utils::ir::{BlockStore, IRBlock, IRMap};
+use crate::utils::persist::{block_from_json, block_to_json};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/*
+ * Spilling the IR of very large functions to disk. Every block is written to a per-function file
+ * in the persisted-state schema (see persist.rs), one block after another, and only an index of
+ * where each one starts stays in memory. Blocks are decoded again when an analysis or checker
+ * asks for them, and the most recently used ones are kept in a small cache, so the IR resident at
+ * any time is bounded by the cache rather than by the size of the function.
+ */
+
+// Blocks kept decoded per spilled function
+pub const SPILL_CACHE_BLOCKS: usize = 1024;
+// Functions with more blocks than this are spilled even without --spill-ir
+pub const SPILL_THRESHOLD_BLOCKS: usize = 20000;
+
+struct BlockCache {
+    file: File,
+    capacity: usize,
+    blocks: HashMap<u64, Arc<IRBlock>>,
+    // least recently used first
+    order: VecDeque<u64>,
+    loads: usize,
+    high_water: usize,
+}
+
+pub struct SpilledBlocks {
+    path: PathBuf,
+    // block address -> offset and length of its encoding
+    index: BTreeMap<u64, (u64, usize)>,
+    cache: Mutex<BlockCache>,
+}
+
+impl SpilledBlocks {
+    /// The number of blocks decoded from disk so far, and the most ever held in the cache at once.
+    pub fn stats(&self) -> (usize, usize) {
+        let cache = self.cache.lock().unwrap();
+        (cache.loads, cache.high_water)
+    }
+
+    fn read_block(cache: &mut BlockCache, offset: u64, len: usize) -> io::Result<IRBlock> {
+        let mut buf = vec![0; len];
+        cache.file.seek(SeekFrom::Start(offset))?;
+        cache.file.read_exact(&mut buf)?;
+        let json = serde_json::from_slice(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        block_from_json(&json).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed spilled block"))
+    }
+}
+
+impl BlockStore for SpilledBlocks {
+    fn addrs(&self) -> Vec<u64> {
+        self.index.keys().copied().collect()
+    }
+
+    fn load(&self, addr: u64) -> Option<Arc<IRBlock>> {
+        let (offset, len) = *self.index.get(&addr)?;
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(block) = cache.blocks.get(&addr).cloned() {
+            cache.order.retain(|cached| *cached != addr);
+            cache.order.push_back(addr);
+            return Some(block);
+        }
+        let block = Arc::new(
+            SpilledBlocks::read_block(&mut cache, offset, len)
+                .unwrap_or_else(|e| panic!("Unable to read spilled block 0x{:x} from {:?}: {}", addr, self.path, e)),
+        );
+        cache.loads += 1;
+        if cache.blocks.len() >= cache.capacity {
+            if let Some(evicted) = cache.order.pop_front() {
+                cache.blocks.remove(&evicted);
+            }
+        }
+        cache.blocks.insert(addr, block.clone());
+        cache.order.push_back(addr);
+        cache.high_water = cache.high_water.max(cache.blocks.len());
+        Some(block)
+    }
+}
+
+impl Drop for SpilledBlocks {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Writes the blocks of `irmap` to a file for `func_name` in `dir`, returning the store that reads
+/// them back with a cache of `cache_blocks` blocks. The file is removed when the store is dropped.
+pub fn spill_blocks(irmap: &IRMap, dir: &str, func_name: &str, cache_blocks: usize) -> io::Result<Arc<SpilledBlocks>> {
+    fs::create_dir_all(dir)?;
+    let path = Path::new(dir).join(format!("{}.{}.ir", func_name.replace('/', "_"), std::process::id()));
+    let mut writer = io::BufWriter::new(File::create(&path)?);
+    let mut index = BTreeMap::new();
+    let mut offset = 0;
+    let mut addrs = irmap.keys();
+    addrs.sort();
+    for addr in addrs {
+        let encoded = block_to_json(&irmap.get(&addr).unwrap()).to_string();
+        writer.write_all(encoded.as_bytes())?;
+        index.insert(addr, (offset, encoded.len()));
+        offset += encoded.len() as u64;
+    }
+    writer.flush()?;
+    Ok(Arc::new(SpilledBlocks {
+        index: index,
+        cache: Mutex::new(BlockCache {
+            file: File::open(&path)?,
+            capacity: cache_blocks.max(1),
+            blocks: HashMap::new(),
+            order: VecDeque::new(),
+            loads: 0,
+            high_water: 0,
+        }),
+        path: path,
+    }))
+}
+
+/// `irmap` with its blocks moved to disk, see `spill_blocks`.
+pub fn spill_irmap(irmap: IRMap, dir: &str, func_name: &str, cache_blocks: usize) -> io::Result<IRMap> {
+    let store = spill_blocks(&irmap, dir, func_name, cache_blocks)?;
+    Ok(IRMap::spilled(store, irmap))
+}
+
+#[test]
+fn spilled_irmap_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::analyses::stack_analyzer::StackAnalyzer;
+    use crate::analyses::{run_worklist, ControlFlow};
+    use crate::checkers::heap_checker::check_heap;
+    use crate::checkers::stack_checker::check_stack;
+    use crate::lattices::switchlattice::JumpTableLoads;
+    use crate::analyses::stack_analyzer::UntrackedRsp;
+    use crate::utils::compiler::{Compiler, CompilerMetadata};
+    use crate::utils::ir::{Binopcode, ImmType, MemArg, MemArgs, Stmt, Unopcode, ValSize, Value};
+
+    // a chain of blocks, each falling through to the next
+    struct Chain(u64);
+    impl ControlFlow for Chain {
+        fn entrypoint(&self) -> u64 { 0 }
+        fn successors(&self, addr: u64) -> Vec<u64> {
+            if addr / 0x10 + 1 < self.0 { vec![addr + 0x10] } else { vec![] }
+        }
+    }
+
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let imm = |v: i64| Value::Imm(ImmType::Signed, ValSize::Size64, v);
+    let heap = |index: u8| Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Reg(index, ValSize::Size64)),
+    );
+    // sub rsp, 8; then blocks of mov eax, eax; mov rcx, [rdi + rax] (or rdx, unchecked); add rsp, 8; ret
+    let function = |num_blocks: u64, index: u8| {
+        let mut irmap = IRMap::new();
+        for i in 0..num_blocks {
+            let addr = i * 0x10;
+            let mut block = vec![];
+            if i == 0 {
+                block.push((addr, vec![Stmt::Binop(Binopcode::Sub, reg(4), reg(4), imm(8))]));
+            }
+            block.push((addr + 0x4, vec![Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size32), Value::Reg(0, ValSize::Size32))]));
+            block.push((addr + 0x6, vec![Stmt::Unop(Unopcode::Mov, reg(1), heap(index))]));
+            if i + 1 == num_blocks {
+                block.push((addr + 0xa, vec![Stmt::Binop(Binopcode::Add, reg(4), reg(4), imm(8))]));
+                block.push((addr + 0xe, vec![Stmt::Ret]));
+            }
+            irmap.insert(addr, block);
+        }
+        irmap
+    };
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let heap_analyzer = HeapAnalyzer { metadata: metadata.clone() };
+    let stack_analyzer = StackAnalyzer { metadata: metadata, stack_limit_cmps: Default::default() };
+    let verdicts = |cfg: &Chain, irmap: &IRMap| {
+        let heap_verdict = check_heap(run_worklist(cfg, irmap, &heap_analyzer), irmap, &heap_analyzer, &vec![], &JumpTableLoads::new(), &UntrackedRsp::new());
+        let stack_verdict = check_stack(run_worklist(cfg, irmap, &stack_analyzer), irmap, &stack_analyzer);
+        (heap_verdict.map_err(|e| e.to_string()), stack_verdict.map_err(|e| e.to_string()))
+    };
+    let dir = std::env::temp_dir().join(format!("veriwasm-spill-{}", std::process::id()));
+    let dir = dir.to_str().unwrap();
+
+    // spilling changes nothing about the verdicts, passing or failing
+    for index in vec![0, 2] {
+        let irmap = function(8, index);
+        let spilled = spill_irmap(irmap.clone(), dir, "guest_func_0", 2).unwrap();
+        assert!(spilled.is_spilled());
+        let mut addrs = irmap.keys();
+        addrs.sort();
+        assert_eq!(spilled.keys(), addrs);
+        assert_eq!(verdicts(&Chain(8), &spilled), verdicts(&Chain(8), &irmap));
+    }
+    assert!(verdicts(&Chain(8), &function(8, 0)).0.is_ok());
+    assert!(verdicts(&Chain(8), &function(8, 2)).0.is_err());
+
+    // a huge function: the decoded IR never exceeds the cache, though every block gets loaded
+    let num_blocks = 20000;
+    let irmap = function(num_blocks, 0);
+    let store = spill_blocks(&irmap, dir, "guest_func_huge", 64).unwrap();
+    let spilled = IRMap::spilled(store.clone(), IRMap::new());
+    drop(irmap);
+    assert!(verdicts(&Chain(num_blocks), &spilled).0.is_ok());
+    let (loads, high_water) = store.stats();
+    assert!(loads >= num_blocks as usize);
+    assert_eq!(high_water, 64);
+    let path = store.path.clone();
+    drop(spilled);
+    drop(store);
+    assert!(!path.exists());
+    let _ = fs::remove_dir_all(dir);
+}
@@ -1,15 +1,33 @@
-use crate::utils::lifter::{MemArg, MemArgs, ValSize, Value, Stmt, IRMap};
+use crate::utils::compiler::Compiler;
+use crate::lattices::reachingdefslattice::LocIdx;
+use crate::utils::ir::{Binopcode, ImmType, MemArg, MemArgs, ValSize, Value, Stmt, IRBlock, IRMap, Unopcode};
+use yaxpeax_x86::long_mode::Opcode;
 
+// Whether `v` is all of rsp. Its narrower forms are not: see is_narrow_rsp.
 pub fn is_rsp(v: &Value) -> bool {
     match v {
         Value::Reg(4, ValSize::Size64) => return true,
-        Value::Reg(4, ValSize::Size32)
-        | Value::Reg(4, ValSize::Size16)
-        | Value::Reg(4, ValSize::Size8) => panic!("Illegal RSP access"),
         _ => return false,
     }
 }
 
+// Whether `v` is esp, sp or spl. Generated code never uses them, and writing one moves rsp in a
+// way the stack analysis can't follow.
+pub fn is_narrow_rsp(v: &Value) -> bool {
+    match v {
+        Value::Reg(4, ValSize::Size32) | Value::Reg(4, ValSize::Size16) | Value::Reg(4, ValSize::Size8) => true,
+        _ => false,
+    }
+}
+
+// Whether `stmt` writes a narrower form of rsp
+pub fn writes_narrow_rsp(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Clear(dst, _) | Stmt::Unop(_, dst, _) | Stmt::Binop(_, dst, _, _) => is_narrow_rsp(dst),
+        _ => false,
+    }
+}
+
 pub fn is_callee_saved_reg(v: &Value) -> bool {
     match v {
         Value::Reg(regnum, _size) => {
@@ -37,13 +55,11 @@ pub fn is_irrelevant_reg(v: &Value) -> bool {
     false
 }
 
+// An address computed from esp isn't a stack address (it is truncated to 32 bits), so an access
+// through one is left to whatever else it may be, and rejected as nothing else.
 pub fn memarg_is_stack(memarg: &MemArg) -> bool {
-    if let MemArg::Reg(4, regsize) = memarg {
-        if let ValSize::Size64 = regsize {
-            return true;
-        } else {
-            panic!("Non 64 bit version of rsp being used")
-        };
+    if let MemArg::Reg(4, ValSize::Size64) = memarg {
+        return true;
     }
     return false;
 }
@@ -76,13 +92,15 @@ pub fn is_stack_access(v: &Value) -> bool {
     false
 }
 
-pub fn extract_stack_offset(memargs: &MemArgs) -> i64 {
+// The offset from rsp of a stack access, if it is rsp plus an immediate; an indexed access
+// (`[rsp + rax]`) may be to any slot.
+pub fn extract_stack_offset(memargs: &MemArgs) -> Option<i64> {
     match memargs {
-        MemArgs::Mem1Arg(_memarg) => 0,
+        MemArgs::Mem1Arg(_memarg) => Some(0),
         MemArgs::Mem2Args(_memarg1, memarg2) => get_imm_mem_offset(memarg2),
         MemArgs::Mem3Args(_memarg1, _memarg2, _memarg3)
         | MemArgs::MemScale(_memarg1, _memarg2, _memarg3) 
-        | MemArgs::MemScaleDisp(_memarg1, _memarg2, _memarg3, _) => panic!("extract_stack_offset failed"),
+        | MemArgs::MemScaleDisp(_memarg1, _memarg2, _memarg3, _) => None,
     }
 }
 
@@ -94,41 +112,123 @@ pub fn is_mem_access(v: &Value) -> bool {
     }
 }
 
-pub fn get_imm_offset(v: &Value) -> i64 {
+pub fn get_imm_offset(v: &Value) -> Option<i64> {
     if let Value::Imm(_, _, v) = v {
-        *v
+        Some(*v)
     } else {
-        panic!("get_imm_offset called on something that is not an imm offset")
+        None
+    }
+}
+
+/// How far `rsp = rsp <opcode> src2` moves rsp (negative is growth), or None if it isn't an
+/// adjustment we can follow. Aligning rsp with `and rsp, -align` (align a power of two) counts as
+/// growing the stack by the most it can, align - 1 bytes; rsp is then only known to within that,
+/// so the function has to restore rsp from a register (e.g. `mov rsp, rbp`) before it returns.
+pub fn rsp_adjustment(opcode: &Binopcode, src2: &Value) -> Option<i64> {
+    match (opcode, src2) {
+        (Binopcode::Add, Value::Imm(_, _, offset)) => Some(*offset),
+        (Binopcode::Sub, Value::Imm(_, _, offset)) => Some(-*offset),
+        (Binopcode::And, Value::Imm(_, _, mask)) => {
+            // the mask is sign-extended to 64 bits
+            let align = -(*mask as i32 as i64);
+            if align > 1 && align & (align - 1) == 0 {
+                Some(-(align - 1))
+            } else {
+                None
+            }
+        }
+        _ => None,
     }
 }
 
-pub fn get_imm_mem_offset(v: &MemArg) -> i64 {
+pub fn get_imm_mem_offset(v: &MemArg) -> Option<i64> {
     if let MemArg::Imm(_, _, v) = v {
-        *v
+        Some(*v)
     } else {
-        panic!("get_imm_offset called on something that is not an imm offset")
+        None
     }
 }
 
-pub fn has_indirect_calls(irmap: &IRMap) -> bool {
+// The absolute address an immediate refers to. The lifter sign-extends signed immediates from
+// their encoded width, so a 32-bit 0x80001000 arrives as a negative i64. A narrow immediate can
+// only name an address above 2GB through a 32-bit operation, which zero-extends its result
+// (sign-extended, it would be a non-canonical/kernel address), so its encoded bits are
+// zero-extended here.
+pub fn imm_to_addr(immtype: &ImmType, size: &ValSize, value: i64) -> u64 {
+    match (immtype, size) {
+        (ImmType::Unsigned, _) | (_, ValSize::Size64) | (_, ValSize::SizeOther) => value as u64,
+        (ImmType::Signed, size) => (value as u64) & ((1u64 << size.to_u32()) - 1),
+    }
+}
+
+// The target of a relative call or jump at `addr`. Displacements are signed no matter how they
+// were decoded, so they are sign-extended from their encoded width.
+pub fn rel_target(size: &ValSize, value: i64, addr: u64, instr_len: u64) -> u64 {
+    let disp = match size {
+        ValSize::Size8 => value as i8 as i64,
+        ValSize::Size16 => value as i16 as i64,
+        ValSize::Size32 => value as i32 as i64,
+        ValSize::Size64 | ValSize::SizeOther => value,
+    };
+    addr.wrapping_add(instr_len).wrapping_add(disp as u64)
+}
+
+// Whether `stmt` is a Wamr tail dispatch, `jmp [base + idx*8]` through a register: newer wamrc
+// emits wrappers that load a function index and tail-call through the function pointer table.
+// It is an indirect call that doesn't return, checked as one by the call checker (that the base
+// is the table and the index a function index) and as a ret by the stack checker, rather than a
+// switch to resolve.
+pub fn is_tail_dispatch(ir_stmt: &Stmt, compiler: Compiler) -> bool {
+    match (compiler, ir_stmt) {
+        (
+            Compiler::Wamr,
+            Stmt::Branch(
+                Opcode::JMP,
+                Value::Mem(_, MemArgs::MemScale(MemArg::Reg(_, ValSize::Size64), MemArg::Reg(_, ValSize::Size64), MemArg::Imm(_, _, 8))),
+            ),
+        ) => true,
+        _ => false,
+    }
+}
+
+pub fn count_tail_dispatches(irmap: &IRMap, compiler: Compiler) -> usize {
+    let mut count = 0;
     for (_block_addr, ir_block) in irmap {
-        for (_addr, ir_stmts) in ir_block {
-            for (_idx, ir_stmt) in ir_stmts.iter().enumerate() {
+        for (_addr, ir_stmts) in ir_block.iter() {
+            count += ir_stmts.iter().filter(|ir_stmt| is_tail_dispatch(ir_stmt, compiler)).count();
+        }
+    }
+    count
+}
+
+pub fn has_indirect_calls(irmap: &IRMap, compiler: Compiler) -> bool {
+    count_indirect_calls(irmap, compiler) > 0
+}
+
+// Tail dispatches count as indirect calls
+pub fn count_indirect_calls(irmap: &IRMap, compiler: Compiler) -> usize {
+    let mut count = 0;
+    for (_block_addr, ir_block) in irmap {
+        for (_addr, ir_stmts) in ir_block.iter() {
+            for ir_stmt in ir_stmts {
                 match ir_stmt {
-                    Stmt::Call(Value::Reg(_, _)) | Stmt::Call(Value::Mem(_, _)) => return true,
+                    Stmt::Call(Value::Reg(_, _), _) | Stmt::Call(Value::Mem(_, _), _) => count += 1,
+                    _ if is_tail_dispatch(ir_stmt, compiler) => count += 1,
                     _ => (),
                 }
             }
         }
     }
-    false
+    count
 }
 
-pub fn has_indirect_jumps(irmap: &IRMap) -> bool {
+// Indirect jumps to resolve into switches: not tail dispatches
+pub fn has_indirect_jumps(irmap: &IRMap, compiler: Compiler) -> bool {
     for (_block_addr, ir_block) in irmap {
-        for (_addr, ir_stmts) in ir_block {
+        for (_addr, ir_stmts) in ir_block.iter() {
             for (_idx, ir_stmt) in ir_stmts.iter().enumerate() {
                 match ir_stmt {
+                    _ if is_tail_dispatch(ir_stmt, compiler) => (),
                     Stmt::Branch(_, Value::Reg(_, _)) | Stmt::Branch(_, Value::Mem(_, _)) => {
                         return true
                     }
@@ -139,3 +239,94 @@ pub fn has_indirect_jumps(irmap: &IRMap) -> bool {
     }
     false
 }
+
+// Checks that no instruction between the cmp at `cmp_loc` and the end of the block can have
+// written the flags without the lifter modeling it. Instructions whose flag writes are modeled
+// end with a Clear of zf, which would already have discarded the CheckFlag; what's left must be
+// flag-neutral: movs and the Add/Sub that lea, push and pop lift to.
+pub fn flags_reach_branch(ir_block: &IRBlock, cmp_loc: &LocIdx) -> bool {
+    if !ir_block.iter().any(|(addr, _)| *addr == cmp_loc.addr) {
+        log::info!("the flags of the cmp at 0x{:x} reach a branch in another block", cmp_loc.addr);
+        return false;
+    }
+    for (addr, ir_stmts) in ir_block.iter().filter(|(addr, _)| *addr > cmp_loc.addr) {
+        if ir_stmts.iter().any(|stmt| if let Stmt::Clear(dst, _) = stmt { is_zf(dst) } else { false }) {
+            continue;
+        }
+        for stmt in ir_stmts {
+            match stmt {
+                Stmt::Unop(Unopcode::Mov, _, _)
+                | Stmt::Binop(Binopcode::Add, _, _, _)
+                | Stmt::Binop(Binopcode::Sub, _, _, _)
+                | Stmt::Branch(_, _) => (),
+                _ => {
+                    log::info!("0x{:x} may clobber the flags set at 0x{:x}: {:?}", addr, cmp_loc.addr, stmt);
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+// The conditional branch ending `ir_block`, if it ends in one
+pub fn branch_opcode(ir_block: &IRBlock) -> Option<Opcode> {
+    match ir_block.last()?.1.last()? {
+        Stmt::Branch(opcode, _) => Some(*opcode),
+        _ => None,
+    }
+}
+
+// After a `cmp`, the side of an unsigned conditional branch the checked index is in bounds on:
+// whether that's the taken side, and whether it excludes the bound itself (index < bound) or not
+// (index <= bound). `index_first` is whether the index was the cmp's first operand. Other
+// branches (jz, or the signed ones) say nothing about the index's bounds.
+pub fn in_bounds_side(opcode: &Opcode, index_first: bool) -> Option<(bool, bool)> {
+    match (opcode, index_first) {
+        // index < bound
+        (Opcode::JB, true) | (Opcode::JA, false) => Some((true, true)),
+        // index >= bound
+        (Opcode::JNB, true) | (Opcode::JNA, false) => Some((false, true)),
+        // index <= bound
+        (Opcode::JNA, true) | (Opcode::JNB, false) => Some((true, false)),
+        // index > bound
+        (Opcode::JA, true) | (Opcode::JB, false) => Some((false, false)),
+        _ => None,
+    }
+}
+
+#[test]
+fn imm_to_addr_test() {
+    let high = 0x8000_1000u64;
+    // mov eax, 0x80001000 (sign-extended by the lifter) and movabs rax, 0x80001000
+    assert_eq!(imm_to_addr(&ImmType::Signed, &ValSize::Size32, 0x8000_1000u32 as i32 as i64), high);
+    assert_eq!(imm_to_addr(&ImmType::Unsigned, &ValSize::Size32, 0x8000_1000), high);
+    assert_eq!(imm_to_addr(&ImmType::Signed, &ValSize::Size64, high as i64), high);
+    assert_eq!(imm_to_addr(&ImmType::Signed, &ValSize::Size64, -1), u64::MAX);
+    assert_eq!(imm_to_addr(&ImmType::Signed, &ValSize::Size8, -1), 0xff);
+
+    // call rel32 backwards, and forwards into the upper 2GB
+    assert_eq!(rel_target(&ValSize::Size32, -0x10, 0x1000, 5), 0xff5);
+    assert_eq!(rel_target(&ValSize::Size32, 0xffff_fff0u32 as i64, 0x1000, 5), 0xff5);
+    assert_eq!(rel_target(&ValSize::Size32, 0x7fff_0000, 0x1_0000, 5), 0x8000_0005);
+}
+
+#[test]
+fn stack_offset_test() {
+    let rsp = MemArg::Reg(4, ValSize::Size64);
+    let rax = MemArg::Reg(0, ValSize::Size64);
+    let imm = |v: i64| MemArg::Imm(ImmType::Signed, ValSize::Size64, v);
+    assert_eq!(extract_stack_offset(&MemArgs::Mem1Arg(rsp.clone())), Some(0));
+    assert_eq!(extract_stack_offset(&MemArgs::Mem2Args(rsp.clone(), imm(-8))), Some(-8));
+    // [rsp + rax] and [rsp + rax*8 + 0x10] may be any slot
+    assert_eq!(extract_stack_offset(&MemArgs::Mem2Args(rsp.clone(), rax.clone())), None);
+    assert_eq!(extract_stack_offset(&MemArgs::MemScaleDisp(rsp.clone(), rax.clone(), imm(8), imm(0x10))), None);
+    assert_eq!(get_imm_offset(&Value::Reg(0, ValSize::Size64)), None);
+    assert_eq!(get_imm_mem_offset(&rax), None);
+
+    // mov esp, eax
+    let narrow = Stmt::Unop(Unopcode::Mov, Value::Reg(4, ValSize::Size32), Value::Reg(0, ValSize::Size32));
+    assert!(!is_rsp(&Value::Reg(4, ValSize::Size32)));
+    assert!(writes_narrow_rsp(&narrow));
+    assert!(!writes_narrow_rsp(&Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size32), Value::Reg(4, ValSize::Size32))));
+}
@@ -1,141 +1,55 @@
-use crate::utils::utils::{CompilerMetadata, Compiler};
-use std::collections::HashMap;
-use yaxpeax_arch::Arch;
+use crate::utils::caveats::{record_caveat, CaveatKind};
+use crate::utils::cfg_normalize::{CfgError, LiftError};
+use crate::utils::compiler::{CompilerMetadata, Compiler, UnknownInstrPolicy};
+use crate::utils::ir_utils::rel_target;
+use crate::utils::ir::{
+    mk_value_i64, unaccounted_bytes, Binopcode, BlockProvenance, IRBlock, IRMap, ImmType, InstrOrigin, InstrProvenance, MemArg,
+    MemArgs, Stmt, Unopcode, ValSize, Value,
+};
+use yaxpeax_arch::{Arch, LengthedInstruction};
 use yaxpeax_core::analyses::control_flow::VW_CFG;
 use yaxpeax_core::arch::x86_64::analyses::data_flow::Location;
 use yaxpeax_core::arch::InstructionSpan;
 use yaxpeax_core::data::{Direction, ValueLocations};
 use yaxpeax_core::memory::repr::process::ModuleData;
+use yaxpeax_core::memory::MemoryRepr;
 use yaxpeax_x86::long_mode::Opcode::*;
 use yaxpeax_x86::long_mode::{Arch as AMD64, Opcode, Operand, RegisterBank};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-#[derive(Debug, Clone)]
-pub enum ImmType {
-    Signed,
-    Unsigned,
-}
-#[derive(Debug, Clone, Copy)]
-pub enum ValSize {
-    Size8,
-    Size16,
-    Size32,
-    Size64,
-    SizeOther,
-}
-
-impl ValSize {
-    pub fn to_u32(&self) -> u32 {
-        match self {
-            ValSize::Size8 => 8,
-            ValSize::Size16 => 16,
-            ValSize::Size32 => 32,
-            ValSize::Size64 => 64,
-            ValSize::SizeOther => 64, //panic!("unknown size? {:?}")
-        }
-    }
-}
-
-pub fn valsize(num: u32) -> ValSize {
-    match num {
-        8 => ValSize::Size8,
-        16 => ValSize::Size16,
-        32 => ValSize::Size32,
-        64 => ValSize::Size64,
-        _ => unimplemented!("{:?}", num),
-    }
-}
-
-pub fn mk_value_i64(num: i64) -> Value {
-    Value::Imm(ImmType::Signed, ValSize::Size64, num)
-}
-
+// The helpers lifting an instruction fail with what is wrong with it; lift adds where it is
+type Lifted<T> = Result<T, String>;
 
-#[derive(Debug, Clone)]
-pub enum MemArgs {
-    Mem1Arg(MemArg), // [arg]
-    Mem2Args(MemArg, MemArg), // [arg1 + arg2]
-    Mem3Args(MemArg, MemArg, MemArg), // [arg1 + arg2 + arg3]
-    MemScale(MemArg, MemArg, MemArg), // [arg1 + arg2 * arg3]
-    MemScaleDisp(MemArg, MemArg, MemArg, MemArg), // [arg1 + arg2 * arg3 + arg4]
-}
-#[derive(Debug, Clone)]
-pub enum MemArg {
-    Reg(u8, ValSize), // register mappings captured in `crate::lattices::regslattice`
-    Imm(ImmType, ValSize, i64), // signed, size, const
-}
-#[derive(Debug, Clone)]
-pub enum Value {
-    Mem(ValSize, MemArgs), // mem[memargs]
-    Reg(u8, ValSize), // register mappings captured in `crate::lattices::regslattice`
-    Imm(ImmType, ValSize, i64), // signed, size, const
-}
-
-#[derive(Debug, Clone)]
-pub enum Stmt {
-    Clear(Value, Vec<Value>), // clear v <- vs
-    Unop(Unopcode, Value, Value), // v1 <- uop v2
-    Binop(Binopcode, Value, Value, Value), // v1 <- bop v2 v3
-    Undefined, // undefined
-    Ret, // return
-    Branch(yaxpeax_x86::long_mode::Opcode, Value), // br branch-type v
-    Call(Value), // call v
-    ProbeStack(u64), // probestack
-}
-
-impl Stmt {
-    pub fn width(&self) -> u32 {
-        unimplemented!("Width not implemented")
-    }
-}
-
-#[derive(Debug, Clone)]
-pub enum Unopcode {
-    Mov,
-    Set,
-}
-#[derive(Debug, Clone)]
-pub enum Binopcode {
-    Test,
-    Rol,
-    Cmp,
-    Shl,
-    And,
-    Add,
-    Sub,
-}
-
-fn get_reg_size(reg: yaxpeax_x86::long_mode::RegSpec) -> ValSize {
+fn get_reg_size(reg: yaxpeax_x86::long_mode::RegSpec) -> Lifted<ValSize> {
     let size = match reg.bank {
         RegisterBank::Q => ValSize::Size64,
         RegisterBank::D => ValSize::Size32,
         RegisterBank::W => ValSize::Size16,
         RegisterBank::B => ValSize::Size8,
         RegisterBank::rB => ValSize::Size8,
-        RegisterBank::RIP => panic!("Write to RIP: {:?}", reg.bank),
-        RegisterBank::EIP => panic!("Write to EIP: {:?}", reg.bank),
+        RegisterBank::RIP | RegisterBank::EIP => return Err(format!("write to {:?}", reg.bank)),
         _ => ValSize::SizeOther, //xmm and ymm
     };
-    return size;
+    Ok(size)
 }
 
-fn convert_reg(reg: yaxpeax_x86::long_mode::RegSpec) -> Value {
-    let size = get_reg_size(reg);
-    Value::Reg(reg.num, size)
+fn convert_reg(reg: yaxpeax_x86::long_mode::RegSpec) -> Lifted<Value> {
+    Ok(Value::Reg(reg.num, get_reg_size(reg)?))
 }
 
-fn convert_memarg_reg(reg: yaxpeax_x86::long_mode::RegSpec) -> MemArg {
+fn convert_memarg_reg(reg: yaxpeax_x86::long_mode::RegSpec) -> Lifted<MemArg> {
     let size = match reg.bank {
         RegisterBank::Q => ValSize::Size64,
         RegisterBank::D => ValSize::Size32,
         RegisterBank::W => ValSize::Size16,
         RegisterBank::B => ValSize::Size8,
-        _ => panic!("Unknown register bank: {:?}", reg.bank),
+        _ => return Err(format!("address in a {:?} register", reg.bank)),
     };
-    MemArg::Reg(reg.num, size)
+    Ok(MemArg::Reg(reg.num, size))
 }
 
-fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Value {
-    match op {
+fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Lifted<Value> {
+    let value = match op {
         Operand::ImmediateI8(imm) => Value::Imm(ImmType::Signed, ValSize::Size8, imm as i64),
         Operand::ImmediateU8(imm) => Value::Imm(ImmType::Unsigned, ValSize::Size8, imm as i64),
         Operand::ImmediateI16(imm) => Value::Imm(ImmType::Signed, ValSize::Size16, imm as i64),
@@ -144,7 +58,7 @@ fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Val
         Operand::ImmediateI32(imm) => Value::Imm(ImmType::Signed, ValSize::Size32, imm as i64),
         Operand::ImmediateU64(imm) => Value::Imm(ImmType::Unsigned, ValSize::Size64, imm as i64),
         Operand::ImmediateI64(imm) => Value::Imm(ImmType::Signed, ValSize::Size64, imm as i64),
-        Operand::Register(reg) => convert_reg(reg),
+        Operand::Register(reg) => convert_reg(reg)?,
         //u32 and u64 are address sizes
         Operand::DisplacementU32(imm) => Value::Mem(
             memsize,
@@ -154,23 +68,28 @@ fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Val
             memsize,
             MemArgs::Mem1Arg(MemArg::Imm(ImmType::Unsigned, ValSize::Size64, imm as i64)),
         ), //mem[c]
-        Operand::RegDeref(reg) => Value::Mem(memsize, MemArgs::Mem1Arg(convert_memarg_reg(reg))), // mem[reg]
+        Operand::RegDeref(reg) => Value::Mem(memsize, MemArgs::Mem1Arg(convert_memarg_reg(reg)?)), // mem[reg]
+        // mem[rip + c], with c made absolute by lift (see rebase_rip)
+        Operand::RegDisp(reg, imm) if reg.bank == RegisterBank::RIP => Value::Mem(
+            memsize,
+            MemArgs::Mem1Arg(MemArg::Imm(ImmType::Signed, ValSize::Size32, imm as i64)),
+        ),
         Operand::RegDisp(reg, imm) => Value::Mem(
             memsize,
             MemArgs::Mem2Args(
-                convert_memarg_reg(reg),
+                convert_memarg_reg(reg)?,
                 MemArg::Imm(ImmType::Signed, ValSize::Size32, imm as i64),
             ),
         ), //mem[reg + c]
         Operand::RegIndexBase(reg1, reg2) => Value::Mem(
             memsize,
-            MemArgs::Mem2Args(convert_memarg_reg(reg1), convert_memarg_reg(reg2)),
+            MemArgs::Mem2Args(convert_memarg_reg(reg1)?, convert_memarg_reg(reg2)?),
         ), // mem[reg1 + reg2]
         Operand::RegIndexBaseDisp(reg1, reg2, imm) => Value::Mem(
             memsize,
             MemArgs::Mem3Args(
-                convert_memarg_reg(reg1),
-                convert_memarg_reg(reg2),
+                convert_memarg_reg(reg1)?,
+                convert_memarg_reg(reg2)?,
                 MemArg::Imm(ImmType::Signed, ValSize::Size32, imm as i64),
             ),
         ), //mem[reg1 + reg2 + c]
@@ -178,14 +97,14 @@ fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Val
             if scale == 1 {
                 Value::Mem(
                     memsize,
-                    MemArgs::Mem1Arg(convert_memarg_reg(reg))
+                    MemArgs::Mem1Arg(convert_memarg_reg(reg)?)
                 )
             } else {
                 Value::Mem(
                     memsize,
                     MemArgs::MemScale(
                         MemArg::Imm(ImmType::Unsigned, ValSize::Size32, 0),
-                        convert_memarg_reg(reg),
+                        convert_memarg_reg(reg)?,
                         MemArg::Imm(ImmType::Unsigned, ValSize::Size32, scale as i64)
                     )
                 )
@@ -196,7 +115,7 @@ fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Val
                 Value::Mem(
                     memsize,
                     MemArgs::Mem2Args(
-                        convert_memarg_reg(reg),
+                        convert_memarg_reg(reg)?,
                         MemArg::Imm(ImmType::Signed, ValSize::Size32, imm as i64)
                     )
                 )
@@ -205,7 +124,7 @@ fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Val
                     memsize,
                     MemArgs::MemScale(
                         MemArg::Imm(ImmType::Unsigned, ValSize::Size32, imm as i64),
-                        convert_memarg_reg(reg),
+                        convert_memarg_reg(reg)?,
                         MemArg::Imm(ImmType::Unsigned, ValSize::Size32, scale as i64)
                     )
                 )
@@ -217,14 +136,14 @@ fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Val
             if scale == 1 {
                 Value::Mem(
                     memsize,
-                    MemArgs::Mem2Args(convert_memarg_reg(reg1), convert_memarg_reg(reg2)),
+                    MemArgs::Mem2Args(convert_memarg_reg(reg1)?, convert_memarg_reg(reg2)?),
                 )
             } else {
                 Value::Mem(
                     memsize,
                     MemArgs::MemScale(
-                        convert_memarg_reg(reg1),
-                        convert_memarg_reg(reg2),
+                        convert_memarg_reg(reg1)?,
+                        convert_memarg_reg(reg2)?,
                         MemArg::Imm(ImmType::Signed, ValSize::Size32, scale as i64),
                     ),
                 )
@@ -235,8 +154,8 @@ fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Val
                 Value::Mem(
                     memsize,
                     MemArgs::Mem3Args(
-                        convert_memarg_reg(reg1),
-                        convert_memarg_reg(reg2),
+                        convert_memarg_reg(reg1)?,
+                        convert_memarg_reg(reg2)?,
                         MemArg::Imm(ImmType::Signed, ValSize::Size32, imm as i64),
                     ),
                 )
@@ -244,42 +163,71 @@ fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Val
                 Value::Mem(
                     memsize,
                     MemArgs::MemScaleDisp(
-                        convert_memarg_reg(reg1),
-                        convert_memarg_reg(reg2),
+                        convert_memarg_reg(reg1)?,
+                        convert_memarg_reg(reg2)?,
                         MemArg::Imm(ImmType::Unsigned, ValSize::Size32, scale as i64),
                         MemArg::Imm(ImmType::Signed, ValSize::Size32, imm as i64),
                     ),
                 )
             }
         } //mem[reg1 + reg2*c1 + c2]
-        Operand::Nothing => panic!("Nothing Operand?"),
-    }
+        Operand::Nothing => return Err("missing operand".to_string()),
+    };
+    Ok(value)
 }
 
-fn get_sources(instr: &yaxpeax_x86::long_mode::Instruction) -> Vec<Value> {
+fn get_sources(instr: &yaxpeax_x86::long_mode::Instruction) -> Lifted<Vec<Value>> {
     match instr.operand_count() {
-        0 => vec![],
-        1 => vec![convert_operand(instr.operand(0), ValSize::Size32)],
-        2 => vec![
-            convert_operand(instr.operand(0), ValSize::Size32),
-            convert_operand(instr.operand(1), ValSize::Size32),
-        ],
-        3 => vec![
-            convert_operand(instr.operand(0), ValSize::Size32),
-            convert_operand(instr.operand(1), ValSize::Size32),
-            convert_operand(instr.operand(2), ValSize::Size32),
-        ],
-        4 => vec![
-            convert_operand(instr.operand(0), ValSize::Size32),
-            convert_operand(instr.operand(1), ValSize::Size32),
-            convert_operand(instr.operand(2), ValSize::Size32),
-            convert_operand(instr.operand(3), ValSize::Size32),
-        ],
-        _ => panic!("Too many arguments?"),
+        count @ 0..=4 => (0..count).map(|i| convert_operand(instr.operand(i), ValSize::Size32)).collect(),
+        count => Err(format!("{} operands", count)),
+    }
+}
+
+// An instruction the lifter doesn't model, as a clear of every location it writes: its
+// destination, the registers it writes implicitly (e.g. cpuid's eax to edx, or the rsi, rdi and
+// rcx of string instructions) and the flags, whether or not it writes them. A write that can't be
+// cleared, to memory other than its destination operand, rejects the instruction instead.
+fn clear_unknown(instr: &yaxpeax_x86::long_mode::Instruction) -> Lifted<Vec<Stmt>> {
+    let srcs = get_sources(instr)?;
+    let mut dsts: Vec<Value> = vec![];
+    for (loc, dir) in <AMD64 as ValueLocations>::decompose(instr).into_iter() {
+        let dst = match (loc, dir) {
+            (_, Direction::Read) => continue,
+            (Some(Location::Register(reg)), Direction::Write) => convert_reg(reg)?,
+            (Some(Location::CF), Direction::Write)
+            | (Some(Location::PF), Direction::Write)
+            | (Some(Location::AF), Direction::Write)
+            | (Some(Location::ZF), Direction::Write)
+            | (Some(Location::SF), Direction::Write)
+            | (Some(Location::OF), Direction::Write)
+            | (Some(Location::DF), Direction::Write) => continue,
+            // a memory write, which may only be to the destination operand: a string instruction
+            // repeats its write over a range the operand doesn't describe
+            (None, Direction::Write) if !is_string_instr(instr.opcode) => match convert_operand(instr.operand(0), ValSize::Size8) {
+                Ok(dst @ Value::Mem(_, _)) => dst,
+                _ => return Err("writes memory besides its destination".to_string()),
+            },
+            (None, Direction::Write) => return Err("string instruction writing memory".to_string()),
+            (loc, _) => return Err(format!("writes {:?}, which can't be cleared", loc)),
+        };
+        // Values have no equality, so compare them by how they print
+        if !dsts.iter().any(|seen| format!("{:?}", seen) == format!("{:?}", dst)) {
+            dsts.push(dst);
+        }
     }
+    let mut stmts: Vec<Stmt> = dsts.into_iter().map(|dst| Stmt::Clear(dst, srcs.clone())).collect();
+    stmts.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), srcs));
+    Ok(stmts)
 }
 
-fn clear_dst(instr: &yaxpeax_x86::long_mode::Instruction) -> Vec<Stmt> {
+fn is_string_instr(opcode: Opcode) -> bool {
+    match opcode {
+        Opcode::MOVS | Opcode::STOS | Opcode::LODS | Opcode::SCAS | Opcode::CMPS => true,
+        _ => false,
+    }
+}
+
+fn clear_dst(instr: &yaxpeax_x86::long_mode::Instruction) -> Lifted<Vec<Stmt>> {
     let uses_vec = <AMD64 as ValueLocations>::decompose(instr);
      let writes_to_zf = uses_vec
         .iter()
@@ -287,23 +235,23 @@ fn clear_dst(instr: &yaxpeax_x86::long_mode::Instruction) -> Vec<Stmt> {
             (Some(Location::ZF), Direction::Write) => true,
             _ => false,
         });
-    let srcs: Vec<Value> = get_sources(instr);
+    let srcs: Vec<Value> = get_sources(instr)?;
     let mut stmts : Vec<Stmt> = Vec::new();
 
-    stmts.push(Stmt::Clear(convert_operand(instr.operand(0), ValSize::Size8), srcs.clone()));
+    stmts.push(Stmt::Clear(convert_operand(instr.operand(0), ValSize::Size8)?, srcs.clone()));
     if writes_to_zf {
         stmts.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), srcs));
     };
-    stmts
+    Ok(stmts)
 }
 
-fn get_operand_size(op: yaxpeax_x86::long_mode::Operand) -> Option<ValSize> {
-    match op {
+fn get_operand_size(op: yaxpeax_x86::long_mode::Operand) -> Lifted<Option<ValSize>> {
+    let size = match op {
         Operand::ImmediateI8(_) | Operand::ImmediateU8(_) => Some(ValSize::Size8),
         Operand::ImmediateI16(_) | Operand::ImmediateU16(_) => Some(ValSize::Size16),
         Operand::ImmediateU32(_) | Operand::ImmediateI32(_) => Some(ValSize::Size32),
         Operand::ImmediateU64(_) | Operand::ImmediateI64(_) => Some(ValSize::Size64),
-        Operand::Register(reg) => Some(get_reg_size(reg)),
+        Operand::Register(reg) => Some(get_reg_size(reg)?),
         //u32 and u64 are address sizes
         Operand::DisplacementU32(_)
         | Operand::DisplacementU64(_)
@@ -316,145 +264,487 @@ fn get_operand_size(op: yaxpeax_x86::long_mode::Operand) -> Option<ValSize> {
         | Operand::RegIndexBaseScale(_, _, _)
         | Operand::RegIndexBaseScaleDisp(_, _, _, _)
         | Operand::Nothing => None,
+    };
+    Ok(size)
+}
+
+// movzx/movsx: the source is lifted with its own width, which for a memory source is the one
+// the opcode implies (`src_size`, or the destination's when the opcode doesn't say)
+fn extend(opcode: Unopcode, instr: &yaxpeax_x86::long_mode::Instruction, src_size: Option<ValSize>) -> Lifted<Stmt> {
+    let dst_size = get_operand_size(instr.operand(0))?.ok_or("extension to memory")?;
+    let src_size = get_operand_size(instr.operand(1))?.or(src_size).unwrap_or(dst_size);
+    Ok(Stmt::Unop(
+        opcode,
+        convert_operand(instr.operand(0), dst_size)?,
+        convert_operand(instr.operand(1), src_size)?,
+    ))
+}
+
+// A move. Register and immediate operands keep their own widths (see Stmt::mov_kind for what
+// that makes of a move between different widths); a memory operand is as wide as the other
+// operand, or `mem_size` when the opcode says otherwise: movd and movq move 32 and 64 bits to or
+// from an xmm register, whose width says nothing about the move's.
+fn mov(instr: &yaxpeax_x86::long_mode::Instruction, mem_size: Option<ValSize>) -> Lifted<Stmt> {
+    let memsize = match (
+        mem_size,
+        get_operand_size(instr.operand(0))?,
+        get_operand_size(instr.operand(1))?,
+    ) {
+        (Some(x), _, _) => x,
+        (None, None, None) => return Err(TWO_MEMORY_ARGS.to_string()),
+        (None, Some(x), _) => x,
+        (None, None, Some(x)) => x,
+    };
+    Ok(Stmt::Unop(
+        Unopcode::Mov,
+        convert_operand(instr.operand(0), memsize)?,
+        convert_operand(instr.operand(1), memsize)?,
+    ))
+}
+
+// `div src` divides rdx:rax (or edx:eax, dx:ax) by src into the quotient in rax and the
+// remainder in rdx. An 8-bit division leaves them in al and ah, which has no register of its own
+// here, so both are cleared.
+fn div(instr: &yaxpeax_x86::long_mode::Instruction) -> Lifted<Vec<Stmt>> {
+    let opcode = if instr.opcode == Opcode::IDIV { Binopcode::Idiv } else { Binopcode::Div };
+    let size = match get_operand_size(instr.operand(0))? {
+        Some(size) => size,
+        None => match instr.operand(0).width() {
+            2 => ValSize::Size16,
+            4 => ValSize::Size32,
+            8 => ValSize::Size64,
+            _ => ValSize::Size8,
+        },
+    };
+    let (rax, rdx) = (Value::Reg(0, size), Value::Reg(2, size));
+    let mut stmts = match size {
+        ValSize::Size16 | ValSize::Size32 | ValSize::Size64 => {
+            let src = convert_operand(instr.operand(0), size)?;
+            // the quotient replaces rax, so a divisor read through rax is gone by the remainder
+            let remainder = if mentions_reg(&src, 0) {
+                Stmt::Clear(rdx.clone(), vec![])
+            } else {
+                Stmt::Binop(opcode.clone(), rdx.clone(), rdx.clone(), src.clone())
+            };
+            vec![Stmt::Binop(opcode, rax.clone(), rax, src), remainder]
+        }
+        _ => vec![
+            Stmt::Clear(Value::Reg(0, ValSize::Size64), vec![]),
+            Stmt::Clear(Value::Reg(2, ValSize::Size64), vec![]),
+        ],
+    };
+    stmts.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), get_sources(instr)?));
+    Ok(stmts)
+}
+
+fn mentions_reg(value: &Value, regnum: u8) -> bool {
+    let arg = |arg: &MemArg| matches!(arg, MemArg::Reg(r, _) if *r == regnum);
+    match value {
+        Value::Reg(r, _) => *r == regnum,
+        Value::Mem(_, MemArgs::Mem1Arg(a)) => arg(a),
+        Value::Mem(_, MemArgs::Mem2Args(a, b)) | Value::Mem(_, MemArgs::MemScale(a, b, _)) => arg(a) || arg(b),
+        Value::Mem(_, MemArgs::Mem3Args(a, b, c)) | Value::Mem(_, MemArgs::MemScaleDisp(a, b, _, c)) => arg(a) || arg(b) || arg(c),
+        Value::Imm(_, _, _) => false,
     }
 }
 
-fn unop(opcode: Unopcode, instr: &yaxpeax_x86::long_mode::Instruction) -> Stmt {
+const TWO_MEMORY_ARGS: &str = "two memory operands";
+
+// xchg with a memory operand is an atomic read-modify-write, so its store is marked as one
+// (Unopcode::Xchg); the register then holds the old contents of memory, which aren't tracked
+fn xchg(instr: &yaxpeax_x86::long_mode::Instruction) -> Lifted<Vec<Stmt>> {
     let memsize = match (
-        get_operand_size(instr.operand(0)),
-        get_operand_size(instr.operand(1)),
+        get_operand_size(instr.operand(0))?,
+        get_operand_size(instr.operand(1))?,
     ) {
-        (None, None) => panic!("Two Memory Args?"),
+        (None, None) => return Err(TWO_MEMORY_ARGS.to_string()),
         (Some(x), None) => x,
         (None, Some(x)) => x,
         (Some(x), Some(_y)) => x,
     };
-    Stmt::Unop(
-        opcode,
-        convert_operand(instr.operand(0), memsize),
-        convert_operand(instr.operand(1), memsize),
-    )
+    let op0 = convert_operand(instr.operand(0), memsize)?;
+    let op1 = convert_operand(instr.operand(1), memsize)?;
+    let stmts = match (&op0, &op1) {
+        (Value::Mem(_, _), _) => vec![
+            Stmt::Unop(Unopcode::Xchg, op0.clone(), op1.clone()),
+            Stmt::Clear(op1, vec![op0]),
+        ],
+        (_, Value::Mem(_, _)) => vec![
+            Stmt::Unop(Unopcode::Xchg, op1.clone(), op0.clone()),
+            Stmt::Clear(op0, vec![op1]),
+        ],
+        _ => vec![
+            Stmt::Clear(op0.clone(), vec![op0.clone(), op1.clone()]),
+            Stmt::Clear(op1.clone(), vec![op0, op1]),
+        ],
+    };
+    Ok(stmts)
+}
+
+// cmpxchg dst, src compares rax with dst, then either stores src to dst or loads dst into rax.
+// xadd dst, src stores dst + src to dst and the old dst to src. Neither result is tracked, so
+// everything written is cleared; a memory destination is also marked as an atomic
+// read-modify-write, like xchg's.
+fn atomic_rmw(instr: &yaxpeax_x86::long_mode::Instruction) -> Lifted<Vec<Stmt>> {
+    let memsize = match (
+        get_operand_size(instr.operand(0))?,
+        get_operand_size(instr.operand(1))?,
+    ) {
+        (None, None) => return Err(TWO_MEMORY_ARGS.to_string()),
+        (Some(x), None) => x,
+        (None, Some(x)) => x,
+        (Some(x), Some(_y)) => x,
+    };
+    let dst = convert_operand(instr.operand(0), memsize)?;
+    let src = convert_operand(instr.operand(1), memsize)?;
+    let zf = Value::Reg(16, ValSize::Size8);
+    let mut stmts: Vec<Stmt> = Vec::new();
+    if let Value::Mem(_, _) = dst {
+        stmts.push(Stmt::Unop(Unopcode::Xchg, dst.clone(), src.clone()));
+    }
+    if let Opcode::CMPXCHG = instr.opcode {
+        let rax = Value::Reg(0, ValSize::Size64);
+        let srcs = vec![dst.clone(), src, rax.clone()];
+        stmts.push(Stmt::Clear(zf, srcs.clone()));
+        stmts.push(Stmt::Clear(dst.clone(), srcs));
+        stmts.push(Stmt::Clear(rax.clone(), vec![dst, rax]));
+    } else {
+        let srcs = vec![dst.clone(), src.clone()];
+        stmts.push(Stmt::Clear(zf, srcs.clone()));
+        stmts.push(Stmt::Clear(dst, srcs.clone()));
+        stmts.push(Stmt::Clear(src, srcs));
+    }
+    Ok(stmts)
 }
 
-fn binop(opcode: Binopcode, instr: &yaxpeax_x86::long_mode::Instruction) -> Stmt {
+fn binop(opcode: Binopcode, instr: &yaxpeax_x86::long_mode::Instruction) -> Lifted<Stmt> {
     let memsize = match (
-        get_operand_size(instr.operand(0)),
-        get_operand_size(instr.operand(1)),
+        get_operand_size(instr.operand(0))?,
+        get_operand_size(instr.operand(1))?,
     ) {
-        (None, None) => panic!("Two Memory Args?"),
+        (None, None) => return Err(TWO_MEMORY_ARGS.to_string()),
         (Some(x), None) => x,
         (None, Some(x)) => x,
         (Some(x), Some(_y)) => x,
     };
     // if two operands than dst is src1
     if instr.operand_count() == 2 {
-        Stmt::Binop(
+        Ok(Stmt::Binop(
             opcode,
-            convert_operand(instr.operand(0), memsize),
-            convert_operand(instr.operand(0), memsize),
-            convert_operand(instr.operand(1), memsize),
-        )
+            convert_operand(instr.operand(0), memsize)?,
+            convert_operand(instr.operand(0), memsize)?,
+            convert_operand(instr.operand(1), memsize)?,
+        ))
     } else {
-        Stmt::Binop(
+        Ok(Stmt::Binop(
             opcode,
-            convert_operand(instr.operand(0), memsize),
-            convert_operand(instr.operand(1), memsize),
-            convert_operand(instr.operand(2), memsize),
-        )
+            convert_operand(instr.operand(0), memsize)?,
+            convert_operand(instr.operand(1), memsize)?,
+            convert_operand(instr.operand(2), memsize)?,
+        ))
     }
 }
 
-fn branch(instr: &yaxpeax_x86::long_mode::Instruction) -> Stmt {
-    Stmt::Branch(
+fn branch(instr: &yaxpeax_x86::long_mode::Instruction) -> Lifted<Stmt> {
+    Ok(Stmt::Branch(
         instr.opcode,
-        convert_operand(instr.operand(0), ValSize::Size64),
-    )
+        convert_operand(instr.operand(0), ValSize::Size64)?,
+    ))
 }
 
-fn call(instr: &yaxpeax_x86::long_mode::Instruction, _metadata: &CompilerMetadata) -> Stmt {
-    let dst = convert_operand(instr.operand(0), ValSize::Size64);
-    Stmt::Call(dst)
+fn call(instr: &yaxpeax_x86::long_mode::Instruction, _metadata: &CompilerMetadata) -> Lifted<Stmt> {
+    let dst = convert_operand(instr.operand(0), ValSize::Size64)?;
+    Ok(Stmt::Call(dst, instr.length))
 }
 
-fn lea(instr: &yaxpeax_x86::long_mode::Instruction, addr: &u64) -> Vec<Stmt> {
+fn lea(instr: &yaxpeax_x86::long_mode::Instruction, addr: &u64) -> Lifted<Vec<Stmt>> {
     let dst = instr.operand(0);
     let src1 = instr.operand(1);
     if let Operand::RegDisp(reg, _imm) = src1 {
         if reg.bank == RegisterBank::RIP {
             //addr + instruction length + displacement
             let target = (*addr as i64) + (instr.length as i64) + (instr.disp as i64);
-            return vec![Stmt::Unop(
+            return Ok(vec![Stmt::Unop(
                 Unopcode::Mov,
-                convert_operand(dst, ValSize::SizeOther),
+                convert_operand(dst, ValSize::SizeOther)?,
                 Value::Imm(ImmType::Signed, ValSize::Size64, target),
-            )];
+            )]);
         }
     }
 
-    match convert_operand(src1, get_operand_size(dst.clone()).unwrap()) {
+    let dst_size = get_operand_size(dst.clone())?.ok_or("lea to memory")?;
+    match convert_operand(src1, dst_size)? {
         Value::Mem(memsize, memargs) => match memargs {
             // an LEA of the form "lea [imm], dst"
-            MemArgs::Mem1Arg(_) => vec![unop(Unopcode::Mov, instr)],
+            MemArgs::Mem1Arg(_) => Ok(vec![mov(instr, None)?]),
             // an LEA of the form "lea [reg+imm], dst"
             MemArgs::Mem2Args(arg1, arg2) => {
                 if let MemArg::Reg(regnum, regsize) = arg1 {
                     if let MemArg::Imm(immtype, immsize, immval) = arg2 {
-                        return vec![Stmt::Binop(Binopcode::Add, convert_operand(dst, memsize), 
+                        return Ok(vec![Stmt::Binop(Binopcode::Add, convert_operand(dst, memsize)?, 
                                            Value::Reg(regnum, regsize), 
-                                           Value::Imm(immtype, immsize, immval))];
+                                           Value::Imm(immtype, immsize, immval))]);
                     } else {
                         // LEAs don't actually load from memory, so it's safe to just clear the destination
-                        return vec![Stmt::Clear(Value::Reg(regnum, regsize), vec![])];
+                        return Ok(vec![Stmt::Clear(Value::Reg(regnum, regsize), vec![])]);
                     }
                 }
                 return clear_dst(instr);
             },
+            // an LEA of the form "lea [reg*2^k], dst" is a shift, which keeps a bound on reg
+            MemArgs::MemScale(MemArg::Imm(_, _, 0), MemArg::Reg(regnum, regsize), MemArg::Imm(_, _, scale))
+                if scale == 2 || scale == 4 || scale == 8 =>
+            {
+                Ok(vec![Stmt::Binop(Binopcode::Shl, convert_operand(dst, memsize)?,
+                                 Value::Reg(regnum, regsize),
+                                 Value::Imm(ImmType::Unsigned, ValSize::Size8, scale.trailing_zeros() as i64))])
+            },
             _ => {
-                if let Value::Reg(regnum, regsize) = convert_operand(dst, memsize) {
+                if let Value::Reg(regnum, regsize) = convert_operand(dst, memsize)? {
                     // LEAs don't actually load from memory, so it's safe to just clear the destination
-                    return vec![Stmt::Clear(Value::Reg(regnum, regsize), vec![])];
+                    return Ok(vec![Stmt::Clear(Value::Reg(regnum, regsize), vec![])]);
                 }
                 return clear_dst(instr);
             }
         },
-        _ => panic!("Illegal lea"),
+        _ => Err("lea of a register or immediate".to_string()),
     }
 }
 
+// Instructions that escape the sandbox or need privileges no guest has. `int 3` is left out,
+// since compilers emit it as a trap/padding and it can only stop the process.
+pub const FORBIDDEN_OPCODES: [Opcode; 25] = [
+    Opcode::SYSCALL,
+    Opcode::SYSRET,
+    Opcode::SYSENTER,
+    Opcode::SYSEXIT,
+    Opcode::INT,
+    Opcode::INTO,
+    Opcode::HLT,
+    Opcode::CLI,
+    Opcode::STI,
+    Opcode::RDMSR,
+    Opcode::WRMSR,
+    Opcode::IN,
+    Opcode::OUT,
+    Opcode::INS,
+    Opcode::OUTS,
+    Opcode::SWAPGS,
+    Opcode::LGDT,
+    Opcode::LIDT,
+    Opcode::INVLPG,
+    Opcode::WBINVD,
+    Opcode::VMCALL,
+    Opcode::VMLAUNCH,
+    Opcode::VMRESUME,
+    Opcode::VMREAD,
+    Opcode::VMWRITE,
+];
+
+// The jumps lifted to `Stmt::Branch`
+pub const BRANCH_OPCODES: [Opcode; 17] = [
+    Opcode::JMP,
+    Opcode::JO,
+    Opcode::JNO,
+    Opcode::JB,
+    Opcode::JNB,
+    Opcode::JZ,
+    Opcode::JNZ,
+    Opcode::JA,
+    Opcode::JNA,
+    Opcode::JS,
+    Opcode::JNS,
+    Opcode::JP,
+    Opcode::JNP,
+    Opcode::JL,
+    Opcode::JGE,
+    Opcode::JLE,
+    Opcode::JG,
+];
+
+// push src: sub rsp, 8; mov [rsp], src
+fn push(src: Value) -> Vec<Stmt> {
+    let rsp = Value::Reg(4, ValSize::Size64);
+    vec![
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp, mk_value_i64(8)),
+        Stmt::Unop(Unopcode::Mov, Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64))), src),
+    ]
+}
+
+// pop dst: mov dst, [rsp]; add rsp, 8
+fn pop(dst: Value) -> Vec<Stmt> {
+    let rsp = Value::Reg(4, ValSize::Size64);
+    vec![
+        Stmt::Unop(Unopcode::Mov, dst, Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64)))),
+        Stmt::Binop(Binopcode::Add, rsp.clone(), rsp, mk_value_i64(8)),
+    ]
+}
+
+// enter size, level: push rbp; mov rbp, rsp; sub rsp, size. A nonzero nesting level also copies
+// level - 1 frame pointers of the enclosing frames from below the old rbp, and pushes the new
+// rbp, before rbp is set.
+fn enter(instr: &yaxpeax_x86::long_mode::Instruction) -> Lifted<Vec<Stmt>> {
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let rbp = Value::Reg(5, ValSize::Size64);
+    let (size, level) = match (
+        convert_operand(instr.operand(0), ValSize::SizeOther)?,
+        convert_operand(instr.operand(1), ValSize::SizeOther)?,
+    ) {
+        (Value::Imm(_, _, size), Value::Imm(_, _, level)) => (size & 0xffff, level % 32),
+        _ => return Err("enter without immediate operands".to_string()),
+    };
+    let mut stmts = push(rbp.clone());
+    for i in 1..level {
+        let outer = Value::Mem(
+            ValSize::Size64,
+            MemArgs::Mem2Args(MemArg::Reg(5, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, -8 * i)),
+        );
+        stmts.extend(push(outer));
+    }
+    stmts.push(Stmt::Unop(Unopcode::Mov, rbp.clone(), rsp.clone()));
+    if level > 0 {
+        if level > 1 {
+            stmts.push(Stmt::Binop(Binopcode::Add, rbp.clone(), rbp.clone(), mk_value_i64(8 * (level - 1))));
+        }
+        stmts.extend(push(rbp));
+    }
+    if size > 0 {
+        stmts.push(Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp, mk_value_i64(size)));
+    }
+    Ok(stmts)
+}
+
+// leave: mov rsp, rbp; pop rbp
+fn leave() -> Vec<Stmt> {
+    let mut stmts = vec![Stmt::Unop(Unopcode::Mov, Value::Reg(4, ValSize::Size64), Value::Reg(5, ValSize::Size64))];
+    stmts.extend(pop(Value::Reg(5, ValSize::Size64)));
+    stmts
+}
+
+fn is_forbidden(instr: &yaxpeax_x86::long_mode::Instruction) -> bool {
+    if let Opcode::INT = instr.opcode {
+        if let Ok(Value::Imm(_, _, 3)) = convert_operand(instr.operand(0), ValSize::SizeOther) {
+            return false;
+        }
+    }
+    FORBIDDEN_OPCODES.contains(&instr.opcode)
+}
+
+// The first statement that isn't a zf clear is the instruction's primary translation; see
+// utils::stmt_id for how statements are named across lifter changes.
 pub fn lift(
     instr: &yaxpeax_x86::long_mode::Instruction,
     addr: &u64,
     metadata: &CompilerMetadata,
-) -> Vec<Stmt> {
+) -> Result<Vec<Stmt>, LiftError> {
+    let mut stmts = lift_instr(instr, addr, metadata).map_err(|reason| LiftError {
+        addr: *addr,
+        opcode: instr.opcode,
+        reason: reason,
+    })?;
+    if has_rip_operand(instr) {
+        rebase_rip(&mut stmts, *addr + instr.length as u64);
+    }
+    // vector accesses are lifted without their width, and the memory operands of instructions
+    // lifted as clears at a default one
+    if stmts.iter().any(assumes_width) {
+        record_caveat(CaveatKind::AssumedWidth, Some(*addr), format!("{}: memory operand checked at an assumed width", instr));
+    }
+    Ok(stmts)
+}
+
+fn has_rip_operand(instr: &yaxpeax_x86::long_mode::Instruction) -> bool {
+    (0..instr.operand_count()).any(|i| matches!(instr.operand(i), Operand::RegDisp(reg, _) if reg.bank == RegisterBank::RIP))
+}
+
+// An instruction has at most one memory operand, so with a rip-relative one every absolute address
+// in its statements is that operand's displacement, which is from the next instruction
+fn rebase_rip(stmts: &mut Vec<Stmt>, next_addr: u64) {
+    let rebase = |value: &mut Value| {
+        if let Value::Mem(_, MemArgs::Mem1Arg(arg)) = value {
+            if let MemArg::Imm(_, _, disp) = *arg {
+                *arg = MemArg::Imm(ImmType::Unsigned, ValSize::Size64, (next_addr as i64).wrapping_add(disp));
+            }
+        }
+    };
+    for stmt in stmts.iter_mut() {
+        match stmt {
+            Stmt::Clear(dst, srcs) => {
+                rebase(dst);
+                for src in srcs.iter_mut() {
+                    rebase(src);
+                }
+            }
+            Stmt::Unop(_, dst, src) => {
+                rebase(dst);
+                rebase(src);
+            }
+            Stmt::Binop(_, dst, src1, src2) => {
+                rebase(dst);
+                rebase(src1);
+                rebase(src2);
+            }
+            Stmt::Branch(_, target) | Stmt::Call(target, _) => rebase(target),
+            _ => (),
+        }
+    }
+}
+
+fn assumes_width(stmt: &Stmt) -> bool {
+    let other = |v: &Value| matches!(v, Value::Mem(ValSize::SizeOther, _));
+    let mem = |v: &Value| matches!(v, Value::Mem(_, _));
+    match stmt {
+        // zf is cleared from the sources of the statement before it
+        Stmt::Clear(Value::Reg(16, _), _) => false,
+        Stmt::Clear(dst, srcs) => mem(dst) || srcs.iter().any(mem),
+        Stmt::Unop(_, dst, src) => other(dst) || other(src),
+        Stmt::Binop(_, dst, src1, src2) => other(dst) || other(src1) || other(src2),
+        Stmt::Branch(_, v) | Stmt::Call(v, _) => other(v),
+        _ => false,
+    }
+}
+
+fn lift_instr(
+    instr: &yaxpeax_x86::long_mode::Instruction,
+    addr: &u64,
+    metadata: &CompilerMetadata,
+) -> Lifted<Vec<Stmt>> {
     let mut instrs = Vec::new();
+    if is_forbidden(instr) {
+        instrs.push(Stmt::Forbidden(instr.opcode));
+        return Ok(instrs);
+    }
     match instr.opcode {
-        Opcode::MOV => instrs.push(unop(Unopcode::Mov, instr)),
-        Opcode::MOVSX => instrs.push(unop(Unopcode::Mov, instr)),
-        Opcode::MOVSXD => instrs.push(unop(Unopcode::Mov, instr)),
-        Opcode::MOVSD => instrs.push(unop(Unopcode::Mov, instr)),
-        Opcode::MOVD => instrs.push(unop(Unopcode::Mov, instr)),
-        Opcode::MOVQ => instrs.push(unop(Unopcode::Mov, instr)),
-        Opcode::MOVZX_b => instrs.push(unop(Unopcode::Mov, instr)),
-        Opcode::MOVSX_b => instrs.push(unop(Unopcode::Mov, instr)),
-        Opcode::MOVZX_w => instrs.push(unop(Unopcode::Mov, instr)),
-        Opcode::MOVSX_w => instrs.push(unop(Unopcode::Mov, instr)),
-        Opcode::LEA => instrs.extend( lea(instr, addr) ),
-
-        Opcode::TEST => instrs.push(binop(Binopcode::Test, instr)),
-        Opcode::CMP => instrs.push(binop(Binopcode::Cmp, instr)),
-
-        Opcode::AND => {instrs.push(binop(Binopcode::And, instr)); instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), get_sources(instr)))} ,
-        Opcode::ADD => {instrs.push(binop(Binopcode::Add, instr)); instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), get_sources(instr)))} ,
-        Opcode::SUB => {instrs.push(binop(Binopcode::Sub, instr)); instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), get_sources(instr)))} ,
+        Opcode::MOV => instrs.push(mov(instr, None)?),
+        Opcode::MOVSX => instrs.push(extend(Unopcode::MovSx, instr, None)?),
+        Opcode::MOVSXD => instrs.push(extend(Unopcode::MovSx, instr, Some(ValSize::Size32))?),
+        Opcode::MOVSD => instrs.push(mov(instr, Some(ValSize::Size64))?),
+        Opcode::MOVD => instrs.push(mov(instr, Some(ValSize::Size32))?),
+        Opcode::MOVQ => instrs.push(mov(instr, Some(ValSize::Size64))?),
+        Opcode::MOVZX_b => instrs.push(extend(Unopcode::MovZx, instr, Some(ValSize::Size8))?),
+        Opcode::MOVSX_b => instrs.push(extend(Unopcode::MovSx, instr, Some(ValSize::Size8))?),
+        Opcode::MOVZX_w => instrs.push(extend(Unopcode::MovZx, instr, Some(ValSize::Size16))?),
+        Opcode::MOVSX_w => instrs.push(extend(Unopcode::MovSx, instr, Some(ValSize::Size16))?),
+        Opcode::LEA => instrs.extend(lea(instr, addr)?),
+
+        Opcode::TEST => instrs.push(binop(Binopcode::Test, instr)?),
+        Opcode::CMP => instrs.push(binop(Binopcode::Cmp, instr)?),
+
+        Opcode::AND => {instrs.push(binop(Binopcode::And, instr)?); instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), get_sources(instr)?))} ,
+        Opcode::ADD => {instrs.push(binop(Binopcode::Add, instr)?); instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), get_sources(instr)?))} ,
+        Opcode::SUB => {instrs.push(binop(Binopcode::Sub, instr)?); instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), get_sources(instr)?))} ,
         // SHLX is the same as SHL, but doesn't modify flags
-        Opcode::SHLX => instrs.push(binop(Binopcode::Shl, instr)),
-        Opcode::SHL => {instrs.push(binop(Binopcode::Shl, instr)); instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), get_sources(instr)))} ,
+        Opcode::SHLX => instrs.push(binop(Binopcode::Shl, instr)?),
+        Opcode::SHL => {instrs.push(binop(Binopcode::Shl, instr)?); instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), get_sources(instr)?))} ,
+        // SHRX is the same as SHR, but doesn't modify flags
+        Opcode::SHRX => instrs.push(binop(Binopcode::Shr, instr)?),
+        Opcode::SHR => {instrs.push(binop(Binopcode::Shr, instr)?); instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), get_sources(instr)?))} ,
 
         Opcode::UD2 => instrs.push(Stmt::Undefined),
 
         Opcode::RETURN => instrs.push(Stmt::Ret),
 
-        Opcode::JMP => instrs.push(branch(instr)),
+        Opcode::JMP => instrs.push(branch(instr)?),
         Opcode::JO
         | Opcode::JNO
         | Opcode::JB
@@ -470,66 +760,44 @@ pub fn lift(
         | Opcode::JL
         | Opcode::JGE
         | Opcode::JLE
-        | Opcode::JG => instrs.push(branch(instr)),
+        | Opcode::JG => instrs.push(branch(instr)?),
 
-        Opcode::CALL => instrs.push(call(instr, metadata)),
+        Opcode::CALL => instrs.push(call(instr, metadata)?),
 
         Opcode::PUSH => {
-            let width = instr.operand(0).width();
-            assert_eq!(width, 8); //8 bytes
-            instrs.push(Stmt::Binop(
-                Binopcode::Sub,
-                Value::Reg(4, ValSize::Size64),
-                Value::Reg(4, ValSize::Size64),
-                mk_value_i64(width.into()),
-            ));
-            instrs.push(Stmt::Unop(
-                Unopcode::Mov,
-                Value::Mem(
-                    valsize((width * 8) as u32),
-                    MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64)),
-                ),
-                convert_operand(instr.operand(0), ValSize::SizeOther),
-            ))
+            //8 bytes, even in x32 code, which pushes pointers zero-extended
+            if instr.operand(0).width() != 8 {
+                return Err(format!("push of {} bytes", instr.operand(0).width()));
+            }
+            instrs.extend(push(convert_operand(instr.operand(0), ValSize::Size64)?))
         }
         Opcode::POP => {
-            let width = instr.operand(0).width();
-            assert_eq!(width, 8); //8 bytes
-            instrs.push(Stmt::Unop(
-                Unopcode::Mov,
-                convert_operand(instr.operand(0), ValSize::SizeOther),
-                Value::Mem(
-                    valsize((width * 8) as u32),
-                    MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64)),
-                ),
-            ));
-            instrs.push(Stmt::Binop(
-                Binopcode::Add,
-                Value::Reg(4, ValSize::Size64),
-                Value::Reg(4, ValSize::Size64),
-                mk_value_i64(width.into()),
-            ))
+            //8 bytes, even in x32 code, which pushes pointers zero-extended
+            if instr.operand(0).width() != 8 {
+                return Err(format!("pop of {} bytes", instr.operand(0).width()));
+            }
+            instrs.extend(pop(convert_operand(instr.operand(0), ValSize::Size64)?))
         }
+        Opcode::ENTER => instrs.extend(enter(instr)?),
+        Opcode::LEAVE => instrs.extend(leave()),
 
-        Opcode::NOP | Opcode::FILD | Opcode::STD | Opcode::CLD | Opcode::STI => (),
-        Opcode::IDIV | Opcode::DIV => {
-            // instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), vec![]));
-            instrs.push(Stmt::Clear(Value::Reg(0, ValSize::Size64), vec![])); // clear RAX
-            instrs.push(Stmt::Clear(Value::Reg(2, ValSize::Size64), vec![])); // clear RDX
-            instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), get_sources(instr)));
-        }
+        Opcode::XCHG => instrs.extend(xchg(instr)?),
+        Opcode::CMPXCHG | Opcode::XADD => instrs.extend(atomic_rmw(instr)?),
+
+        Opcode::NOP | Opcode::FILD | Opcode::STD | Opcode::CLD => (),
+        Opcode::IDIV | Opcode::DIV => instrs.extend(div(instr)?),
 
         Opcode::XOR => {
             //XOR reg, reg => mov reg, 0
             if instr.operand_count() == 2 && instr.operand(0) == instr.operand(1) {
                 instrs.push(Stmt::Unop(
                     Unopcode::Mov,
-                    convert_operand(instr.operand(0), ValSize::Size64),
+                    convert_operand(instr.operand(0), ValSize::Size64)?,
                     Value::Imm(ImmType::Signed, ValSize::Size64, 0),
                 ));
-                instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), get_sources(instr)));
+                instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), get_sources(instr)?));
             } else {
-                instrs.extend(clear_dst(instr))
+                instrs.extend(clear_dst(instr)?)
             }
         }
 
@@ -549,15 +817,10 @@ pub fn lift(
         | SETGE
         | SETLE
         | SETG => instrs.push(Stmt::Unop(Unopcode::Set, 
-                              convert_operand(instr.operand(0), ValSize::Size8), 
+                              convert_operand(instr.operand(0), ValSize::Size8)?, 
                               Value::Reg(16, ValSize::Size8))),
-        Opcode::OR
-        | Opcode::SHR
-        | Opcode::RCL
-        | Opcode::RCR
-        | Opcode::ROL
-        | Opcode::ROR
-        | Opcode::CMOVA
+        // the destination keeps its value or takes the source's, whichever the flags pick
+        Opcode::CMOVA
         | Opcode::CMOVB
         | Opcode::CMOVG
         | Opcode::CMOVGE
@@ -572,7 +835,12 @@ pub fn lift(
         | Opcode::CMOVO
         | Opcode::CMOVP
         | Opcode::CMOVS
-        | Opcode::CMOVZ
+        | Opcode::CMOVZ => instrs.push(binop(Binopcode::Cmov, instr)?),
+        Opcode::OR
+        | Opcode::RCL
+        | Opcode::RCR
+        | Opcode::ROL
+        | Opcode::ROR
         | Opcode::SAR
         | Opcode::ADC
         | Opcode::ROUNDSS
@@ -713,29 +981,37 @@ pub fn lift(
         | Opcode::BSF 
 
         // new Wamr instructions
-        | Opcode::SHRX
         | Opcode::RORX
         | Opcode::MULX
         | Opcode::ANDN
         | Opcode::BT
         | Opcode::INC 
         | Opcode::DEC 
-        | Opcode::NEG => instrs.extend(clear_dst(instr)),
+        | Opcode::NEG => instrs.extend(clear_dst(instr)?),
         _ => {
-            if instr.opcode == Opcode::Invalid {
-                println!("invalid instr at addr: {:x}", addr);
+            let description = if instr.opcode == Opcode::Invalid {
+                "invalid instr".to_string()
             } else {
-                println!("unimplemented instr: {:?} at addr {:x}", instr, addr);
+                format!("unimplemented instr {:?}: {}", instr.opcode, instr)
+            };
+            match metadata.options.unknown_instr {
+                UnknownInstrPolicy::Reject => return Err(description),
+                // there's nothing to clear in an instruction that doesn't decode
+                UnknownInstrPolicy::Clear if instr.opcode == Opcode::Invalid => return Err(description),
+                UnknownInstrPolicy::Clear => {
+                    log::warn!("{} at addr {:x}, lifted as a clear", description, addr);
+                    instrs.extend(clear_unknown(instr)?)
+                }
+                UnknownInstrPolicy::Ignore => {
+                    log::warn!("{} at addr {:x}, skipped", description, addr);
+                    record_caveat(CaveatKind::UnliftedInstruction, Some(*addr), description);
+                }
             }
-            //unimplemented!()
         },
     };
-    instrs
+    Ok(instrs)
 }
 
-pub type IRBlock = Vec<(u64, Vec<Stmt>)>;
-pub type IRMap = HashMap<u64, IRBlock>;
-
 fn is_probestack(
     instr: &yaxpeax_x86::long_mode::Instruction,
     addr: &u64,
@@ -746,9 +1022,8 @@ fn is_probestack(
         return false;
     }
     if let Opcode::CALL = instr.opcode {
-        if let Value::Imm(_, _, offset) = convert_operand(instr.operand(0), ValSize::SizeOther) {
-            // 5 = size of call instruction
-            if 5 + offset + (*addr as i64) == metadata.lucet_probestack as i64 {
+        if let Ok(Value::Imm(_, immsize, offset)) = convert_operand(instr.operand(0), ValSize::SizeOther) {
+            if metadata.is_addr(rel_target(&immsize, offset, *addr, instr.length as u64), metadata.lucet_probestack) {
                 return true;
             }
         }
@@ -758,10 +1033,10 @@ fn is_probestack(
 
 fn extract_probestack_arg(instr: &yaxpeax_x86::long_mode::Instruction) -> Option<u64> {
     if let Opcode::MOV = instr.opcode {
-        if let Value::Reg(0, ValSize::Size32) =
+        if let Ok(Value::Reg(0, ValSize::Size32)) =
             convert_operand(instr.operand(0), ValSize::SizeOther)
         {
-            if let Value::Imm(_, _, x) = convert_operand(instr.operand(1), ValSize::SizeOther) {
+            if let Ok(Value::Imm(_, _, x)) = convert_operand(instr.operand(1), ValSize::SizeOther) {
                 if instr.operand_count() == 2 {
                     return Some(x as u64);
                 }
@@ -771,62 +1046,692 @@ fn extract_probestack_arg(instr: &yaxpeax_x86::long_mode::Instruction) -> Option
     None
 }
 
-fn check_probestack_suffix(instr: &yaxpeax_x86::long_mode::Instruction) -> bool {
+fn check_probestack_suffix(instr: &yaxpeax_x86::long_mode::Instruction) -> Lifted<()> {
     if let Opcode::SUB = instr.opcode {
-        if let Value::Reg(4, ValSize::Size64) =
+        if let Ok(Value::Reg(4, ValSize::Size64)) =
             convert_operand(instr.operand(0), ValSize::SizeOther)
         {
             //size is dummy
-            if let Value::Reg(0, ValSize::Size64) =
+            if let Ok(Value::Reg(0, ValSize::Size64)) =
                 convert_operand(instr.operand(1), ValSize::SizeOther)
             {
                 if instr.operand_count() == 2 {
-                    return true;
+                    return Ok(());
                 }
             }
         }
     }
-    panic!("Broken Probestack?")
+    Err("probestack call not followed by sub rsp, rax".to_string())
 }
 
-pub fn lift_cfg(program: &ModuleData, cfg: &VW_CFG, metadata: &CompilerMetadata) -> IRMap {
+const PROBESTACK_ARG: &str = "probestack mov eax, size/call";
+const PROBESTACK_SUFFIX: &str = "probestack call/sub rsp, rax";
+const PROBESTACK_NO_ARG: &str = "probestack call without a stack size moved into eax";
+
+// What the last instruction of a block leaves for a probestack sequence continuing in its
+// successors: the stack size moved into eax, or the probestack call itself
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct ProbestackTail {
+    arg: Option<u64>,
+    call: bool,
+}
+
+// The stack size of the probestack call starting `block_addr`, which every predecessor has to
+// have moved into eax, the same size in each
+fn incoming_probestack_arg(
+    block_addr: u64,
+    preds: &[u64],
+    tails: &HashMap<u64, ProbestackTail>,
+) -> Result<u64, CfgError> {
+    let mut arg = None;
+    for pred in preds {
+        match (tails.get(pred).and_then(|tail| tail.arg), arg) {
+            (Some(size), None) => arg = Some(size),
+            (Some(size), Some(prev)) if size == prev => (),
+            _ => return Err(CfgError::SplitPattern(PROBESTACK_ARG, *pred, block_addr)),
+        }
+    }
+    arg.ok_or_else(|| LiftError { addr: block_addr, opcode: Opcode::CALL, reason: PROBESTACK_NO_ARG.to_string() }.into())
+}
+
+// The blocks starting with the `sub rsp, rax` of a probestack call ending their predecessors,
+// which must then all end with the call
+fn probestack_suffix_blocks(
+    preds: &BTreeMap<u64, Vec<u64>>,
+    tails: &HashMap<u64, ProbestackTail>,
+) -> Result<HashSet<u64>, CfgError> {
+    let ends_in_call = |pred: &u64| tails.get(pred).map_or(false, |tail| tail.call);
+    let mut blocks = HashSet::new();
+    for (block_addr, block_preds) in preds {
+        if !block_preds.iter().any(ends_in_call) {
+            continue;
+        }
+        if let Some(pred) = block_preds.iter().find(|pred| !ends_in_call(pred)) {
+            return Err(CfgError::SplitPattern(PROBESTACK_SUFFIX, *pred, *block_addr));
+        }
+        blocks.insert(*block_addr);
+    }
+    Ok(blocks)
+}
+
+/// Lifts every block of `cfg`, which must be disjoint and non-empty (see cfg_normalize.rs).
+/// A probestack sequence may be split across blocks as long as each piece is reached only from
+/// the one before it; otherwise the split is an error naming the edge.
+pub fn lift_cfg(program: &ModuleData, cfg: &VW_CFG, metadata: &CompilerMetadata) -> Result<IRMap, CfgError> {
     let mut irmap = IRMap::new();
     let g = &cfg.graph;
+    let block_instrs = move |block_addr: u64| {
+        let block = cfg.get_block(block_addr);
+        program.instructions_spanning(<AMD64 as Arch>::Decoder::default(), block.start, block.end)
+    };
+    let mut preds: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    let mut tails: HashMap<u64, ProbestackTail> = HashMap::new();
+    for block_addr in g.nodes() {
+        for succ in g.neighbors(block_addr) {
+            preds.entry(succ).or_insert_with(Vec::new).push(block_addr);
+        }
+        let mut tail = ProbestackTail::default();
+        let mut iter = block_instrs(block_addr);
+        while let Some((addr, instr)) = iter.next() {
+            tail = ProbestackTail {
+                arg: extract_probestack_arg(instr),
+                call: is_probestack(instr, &addr, &metadata),
+            };
+        }
+        tails.insert(block_addr, tail);
+    }
+    let suffix_blocks = probestack_suffix_blocks(&preds, &tails)?;
+    let no_preds = vec![];
     for block_addr in g.nodes() {
-        let mut block_ir: Vec<(u64, Vec<Stmt>)> = Vec::new();
+        let incoming_arg = || incoming_probestack_arg(block_addr, preds.get(&block_addr).unwrap_or(&no_preds), &tails);
+        let mut instrs = vec![];
+        let mut iter = block_instrs(block_addr);
+        while let Some((addr, instr)) = iter.next() {
+            instrs.push((addr, instr.clone()));
+        }
+        let (block_ir, provenance) = lift_block(&instrs, suffix_blocks.contains(&block_addr), incoming_arg, metadata)?;
         let block = cfg.get_block(block_addr);
-        let mut iter = program.instructions_spanning(
-            <AMD64 as Arch>::Decoder::default(),
-            block.start,
-            block.end,
+        debug_assert!(
+            unaccounted_bytes(block.start, block.end, &block_ir, &provenance).is_empty(),
+            "lifting the block at 0x{:x} skipped bytes",
+            block_addr
         );
-        let mut probestack_suffix = false;
-        let mut x: Option<u64> = None;
-        while let Some((addr, instr)) = iter.next() {
-            if probestack_suffix {
-                //1. fail if it isnt sub, rsp, rax
-                //2. skip
-                probestack_suffix = false;
-                check_probestack_suffix(instr);
+        irmap.insert_lifted(block_addr, block_ir, provenance);
+    }
+    Ok(irmap)
+}
 
-                continue;
-            }
-            if is_probestack(instr, &addr, &metadata) {
-                match x {
-                    Some(v) => {
-                        let ir = (addr, vec![Stmt::ProbeStack(v)]);
-                        block_ir.push(ir);
-                        probestack_suffix = true;
-                        continue;
-                    }
-                    None => panic!("probestack broken"),
-                }
+// Lifts the instructions of a block, with an entry for each of them: the probestack call
+// becomes a single ProbeStack statement, and the `sub rsp, rax` after it an entry without
+// statements, so that the entries account for every byte of the block. `probestack_suffix` is
+// whether the block starts with that sub, and `incoming_arg` the stack size moved into eax at
+// the end of its predecessors, for a block starting with the call.
+fn lift_block(
+    instrs: &[(u64, yaxpeax_x86::long_mode::Instruction)],
+    mut probestack_suffix: bool,
+    incoming_arg: impl Fn() -> Result<u64, CfgError>,
+    metadata: &CompilerMetadata,
+) -> Result<(IRBlock, BlockProvenance), CfgError> {
+    let mut block_ir: IRBlock = Vec::new();
+    let mut provenance: BlockProvenance = Vec::new();
+    let mut x: Option<u64> = None;
+    for (addr, instr) in instrs.iter() {
+        let (addr, len) = (*addr, instr.length);
+        if probestack_suffix {
+            //1. fail if it isnt sub, rsp, rax
+            //2. skip
+            probestack_suffix = false;
+            check_probestack_suffix(instr).map_err(|reason| LiftError { addr: addr, opcode: instr.opcode, reason: reason })?;
+
+            block_ir.push((addr, vec![]));
+            provenance.push(InstrProvenance { len: len, origin: InstrOrigin::ConsumedByProbestack });
+            continue;
+        }
+        if is_probestack(instr, &addr, &metadata) {
+            let v = match x {
+                Some(v) => v,
+                // the mov ended the predecessors
+                None if block_ir.is_empty() => incoming_arg()?,
+                None => return Err(LiftError { addr: addr, opcode: instr.opcode, reason: PROBESTACK_NO_ARG.to_string() }.into()),
+            };
+            block_ir.push((addr, vec![Stmt::ProbeStack(v)]));
+            provenance.push(InstrProvenance { len: len, origin: InstrOrigin::Synthesized });
+            probestack_suffix = true;
+            continue;
+        }
+        block_ir.push((addr, lift(instr, &addr, metadata)?));
+        provenance.push(InstrProvenance { len: len, origin: InstrOrigin::Lifted });
+        x = extract_probestack_arg(instr);
+    }
+    Ok((block_ir, provenance))
+}
+
+/// The bytes and disassembly of the instruction at `addr`, for reporting a failing statement.
+pub fn disassemble_at(program: &ModuleData, addr: u64) -> Option<(Vec<u8>, String)> {
+    // 15 bytes = longest x86 instruction
+    let mut iter = program.instructions_spanning(<AMD64 as Arch>::Decoder::default(), addr, addr + 15);
+    let (instr_addr, instr) = iter.next()?;
+    if instr_addr != addr {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = (addr..addr + instr.len()).map(|byte_addr| program.read(byte_addr)).collect();
+    Some((bytes?, instr.to_string()))
+}
+
+// Lifts the straight-line code at the start of the function at `addr`, up to `max_stmts`
+// statements or the first control flow, without building a CFG.
+pub fn lift_prologue(program: &ModuleData, addr: u64, metadata: &CompilerMetadata, max_stmts: usize) -> Result<Vec<Stmt>, LiftError> {
+    let mut stmts: Vec<Stmt> = Vec::new();
+    // 15 bytes = longest x86 instruction
+    let mut iter = program.instructions_spanning(
+        <AMD64 as Arch>::Decoder::default(),
+        addr,
+        addr + (max_stmts as u64) * 15,
+    );
+    while let Some((instr_addr, instr)) = iter.next() {
+        for stmt in lift(instr, &instr_addr, metadata)? {
+            let is_control_flow = match stmt {
+                Stmt::Ret | Stmt::Branch(_, _) | Stmt::Call(_, _) => true,
+                _ => false,
+            };
+            stmts.push(stmt);
+            if is_control_flow || stmts.len() >= max_stmts {
+                return Ok(stmts);
             }
-            let ir = (addr, lift(instr, &addr, metadata));
-            block_ir.push(ir);
-            x = extract_probestack_arg(instr);
         }
-        irmap.insert(block_addr, block_ir);
     }
-    irmap
+    Ok(stmts)
+}
+
+#[test]
+fn lift_atomic_rmw_test() {
+    use yaxpeax_arch::Decoder;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Wamr);
+    let lifted = |bytes: &[u8]| {
+        let instr = <AMD64 as Arch>::Decoder::default().decode(bytes.iter().cloned()).unwrap();
+        assert_eq!(instr.len() as usize, bytes.len());
+        format!("{:?}", lift(&instr, &0, &metadata).unwrap())
+    };
+    let expected = |stmts: Vec<Stmt>| format!("{:?}", stmts);
+    let reg64 = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let zf = Value::Reg(16, ValSize::Size8);
+    let ecx = Value::Reg(1, ValSize::Size32);
+    let rdi_mem = Value::Mem(ValSize::Size32, MemArgs::Mem1Arg(MemArg::Reg(7, ValSize::Size64)));
+
+    // xchg rcx, rdx
+    assert_eq!(lifted(&[0x48, 0x87, 0xd1]), expected(vec![
+        Stmt::Clear(reg64(1), vec![reg64(1), reg64(2)]),
+        Stmt::Clear(reg64(2), vec![reg64(1), reg64(2)]),
+    ]));
+    // cmpxchg rcx, rdx
+    assert_eq!(lifted(&[0x48, 0x0f, 0xb1, 0xd1]), expected(vec![
+        Stmt::Clear(zf.clone(), vec![reg64(1), reg64(2), reg64(0)]),
+        Stmt::Clear(reg64(1), vec![reg64(1), reg64(2), reg64(0)]),
+        Stmt::Clear(reg64(0), vec![reg64(1), reg64(0)]),
+    ]));
+    // cmpxchg dword [rdi], ecx: the store is checked as an atomic read-modify-write
+    assert_eq!(lifted(&[0x0f, 0xb1, 0x0f]), expected(vec![
+        Stmt::Unop(Unopcode::Xchg, rdi_mem.clone(), ecx.clone()),
+        Stmt::Clear(zf.clone(), vec![rdi_mem.clone(), ecx.clone(), reg64(0)]),
+        Stmt::Clear(rdi_mem.clone(), vec![rdi_mem.clone(), ecx.clone(), reg64(0)]),
+        Stmt::Clear(reg64(0), vec![rdi_mem.clone(), reg64(0)]),
+    ]));
+    // xadd rcx, rdx
+    assert_eq!(lifted(&[0x48, 0x0f, 0xc1, 0xd1]), expected(vec![
+        Stmt::Clear(zf.clone(), vec![reg64(1), reg64(2)]),
+        Stmt::Clear(reg64(1), vec![reg64(1), reg64(2)]),
+        Stmt::Clear(reg64(2), vec![reg64(1), reg64(2)]),
+    ]));
+    // xadd dword [rdi], ecx
+    assert_eq!(lifted(&[0x0f, 0xc1, 0x0f]), expected(vec![
+        Stmt::Unop(Unopcode::Xchg, rdi_mem.clone(), ecx.clone()),
+        Stmt::Clear(zf, vec![rdi_mem.clone(), ecx.clone()]),
+        Stmt::Clear(rdi_mem.clone(), vec![rdi_mem.clone(), ecx.clone()]),
+        Stmt::Clear(ecx.clone(), vec![rdi_mem, ecx]),
+    ]));
+}
+
+#[test]
+fn lift_scaled_lea_test() {
+    use yaxpeax_arch::Decoder;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let lifted = |bytes: &[u8]| {
+        let instr = <AMD64 as Arch>::Decoder::default().decode(bytes.iter().cloned()).unwrap();
+        format!("{:?}", lift(&instr, &0, &metadata).unwrap())
+    };
+    let rax = Value::Reg(0, ValSize::Size64);
+
+    // lea rax, [rax*8]
+    assert_eq!(lifted(&[0x48, 0x8d, 0x04, 0xc5, 0x00, 0x00, 0x00, 0x00]), format!("{:?}", vec![
+        Stmt::Binop(Binopcode::Shl, rax.clone(), rax.clone(), Value::Imm(ImmType::Unsigned, ValSize::Size8, 3)),
+    ]));
+    // lea rax, [rax*8 + 0x10] still just clears rax
+    assert_eq!(lifted(&[0x48, 0x8d, 0x04, 0xc5, 0x10, 0x00, 0x00, 0x00]), format!("{:?}", vec![
+        Stmt::Clear(rax, vec![]),
+    ]));
+}
+
+#[test]
+fn lift_extension_test() {
+    use yaxpeax_arch::Decoder;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let lifted = |bytes: &[u8]| {
+        let instr = <AMD64 as Arch>::Decoder::default().decode(bytes.iter().cloned()).unwrap();
+        format!("{:?}", lift(&instr, &0, &metadata).unwrap())
+    };
+    let rdi_mem = |size: ValSize| Value::Mem(size, MemArgs::Mem1Arg(MemArg::Reg(7, ValSize::Size64)));
+    let eax = Value::Reg(0, ValSize::Size32);
+    let rax = Value::Reg(0, ValSize::Size64);
+
+    // the source keeps its own width
+    // movzx eax, byte [rdi]
+    assert_eq!(lifted(&[0x0f, 0xb6, 0x07]), format!("{:?}", vec![Stmt::Unop(Unopcode::MovZx, eax.clone(), rdi_mem(ValSize::Size8))]));
+    // movzx eax, word [rdi]
+    assert_eq!(lifted(&[0x0f, 0xb7, 0x07]), format!("{:?}", vec![Stmt::Unop(Unopcode::MovZx, eax.clone(), rdi_mem(ValSize::Size16))]));
+    // movzx eax, cl
+    assert_eq!(lifted(&[0x0f, 0xb6, 0xc1]), format!("{:?}", vec![Stmt::Unop(Unopcode::MovZx, eax.clone(), Value::Reg(1, ValSize::Size8))]));
+    // movsx eax, byte [rdi]
+    assert_eq!(lifted(&[0x0f, 0xbe, 0x07]), format!("{:?}", vec![Stmt::Unop(Unopcode::MovSx, eax, rdi_mem(ValSize::Size8))]));
+    // movsxd rax, dword [rdi]
+    assert_eq!(lifted(&[0x48, 0x63, 0x07]), format!("{:?}", vec![Stmt::Unop(Unopcode::MovSx, rax, rdi_mem(ValSize::Size32))]));
+}
+
+#[test]
+fn lift_mov_kind_test() {
+    use crate::utils::ir::{Extension, MovKind, UpperBits};
+    use yaxpeax_arch::Decoder;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let kind = |src_bits: u32, dst_bits: u32, extension: Extension, upper: UpperBits| {
+        Some(MovKind { src_bits: src_bits, dst_bits: dst_bits, extension: extension, upper: upper })
+    };
+    let cases: Vec<(&[u8], Option<MovKind>)> = vec![
+        // mov eax, [rdi]
+        (&[0x8b, 0x07], kind(32, 32, Extension::None, UpperBits::Cleared)),
+        // mov rax, [rdi]
+        (&[0x48, 0x8b, 0x07], kind(64, 64, Extension::None, UpperBits::Whole)),
+        // mov al, [rdi]
+        (&[0x8a, 0x07], kind(8, 8, Extension::None, UpperBits::Kept)),
+        // mov ax, [rdi]
+        (&[0x66, 0x8b, 0x07], kind(16, 16, Extension::None, UpperBits::Kept)),
+        // mov [rdi], eax
+        (&[0x89, 0x07], kind(32, 32, Extension::None, UpperBits::Whole)),
+        // mov rax, -1 (an immediate is lifted extended)
+        (&[0x48, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff], kind(64, 64, Extension::None, UpperBits::Whole)),
+        // movzx eax, byte [rdi]
+        (&[0x0f, 0xb6, 0x07], kind(8, 32, Extension::Zero, UpperBits::Cleared)),
+        // movzx ax, cl
+        (&[0x66, 0x0f, 0xb6, 0xc1], kind(8, 16, Extension::Zero, UpperBits::Kept)),
+        // movsx eax, word [rdi]
+        (&[0x0f, 0xbf, 0x07], kind(16, 32, Extension::Sign, UpperBits::Cleared)),
+        // movsxd rax, dword [rdi]
+        (&[0x48, 0x63, 0x07], kind(32, 64, Extension::Sign, UpperBits::Whole)),
+        // movd xmm0, [rdi] reads 32 bits and zero extends them
+        (&[0x66, 0x0f, 0x6e, 0x07], kind(32, 64, Extension::Zero, UpperBits::Whole)),
+        // movq rax, xmm0
+        (&[0x66, 0x48, 0x0f, 0x7e, 0xc0], kind(64, 64, Extension::None, UpperBits::Whole)),
+        // add eax, ecx isn't a move
+        (&[0x01, 0xc8], None),
+    ];
+    for (bytes, expected) in cases {
+        let instr = <AMD64 as Arch>::Decoder::default().decode(bytes.iter().cloned()).unwrap();
+        let stmts = lift(&instr, &0, &metadata).unwrap();
+        assert_eq!(stmts[0].mov_kind(), expected, "{}", instr);
+    }
+}
+
+#[test]
+fn lift_cmov_test() {
+    use yaxpeax_arch::Decoder;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let lifted = |bytes: &[u8]| {
+        let instr = <AMD64 as Arch>::Decoder::default().decode(bytes.iter().cloned()).unwrap();
+        format!("{:?}", lift(&instr, &0, &metadata).unwrap())
+    };
+    let (rdi, rcx) = (Value::Reg(7, ValSize::Size64), Value::Reg(1, ValSize::Size64));
+
+    // cmova rdi, rcx: rdi keeps its value or takes rcx's, and the flags are left alone
+    assert_eq!(lifted(&[0x48, 0x0f, 0x47, 0xf9]), format!("{:?}", vec![Stmt::Binop(Binopcode::Cmov, rdi.clone(), rdi, rcx)]));
+    // cmovb eax, [rdi]
+    let eax = Value::Reg(0, ValSize::Size32);
+    let rdi_mem = Value::Mem(ValSize::Size32, MemArgs::Mem1Arg(MemArg::Reg(7, ValSize::Size64)));
+    assert_eq!(lifted(&[0x0f, 0x42, 0x07]), format!("{:?}", vec![Stmt::Binop(Binopcode::Cmov, eax.clone(), eax, rdi_mem)]));
+}
+
+#[test]
+fn lift_div_test() {
+    use yaxpeax_arch::Decoder;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Wamr);
+    let lifted = |bytes: &[u8]| {
+        let instr = <AMD64 as Arch>::Decoder::default().decode(bytes.iter().cloned()).unwrap();
+        lift(&instr, &0, &metadata).unwrap()
+    };
+    let reg = |regnum: u8, size: ValSize| Value::Reg(regnum, size);
+
+    // div ecx: the quotient into eax, then the remainder into edx
+    let (eax, ecx, edx) = (reg(0, ValSize::Size32), reg(1, ValSize::Size32), reg(2, ValSize::Size32));
+    assert_eq!(
+        format!("{:?}", &lifted(&[0xf7, 0xf1])[..2]),
+        format!("{:?}", vec![
+            Stmt::Binop(Binopcode::Div, eax.clone(), eax.clone(), ecx.clone()),
+            Stmt::Binop(Binopcode::Div, edx.clone(), edx.clone(), ecx),
+        ])
+    );
+    // idiv rcx
+    let (rax, rcx, rdx) = (reg(0, ValSize::Size64), reg(1, ValSize::Size64), reg(2, ValSize::Size64));
+    assert_eq!(
+        format!("{:?}", &lifted(&[0x48, 0xf7, 0xf9])[..2]),
+        format!("{:?}", vec![
+            Stmt::Binop(Binopcode::Idiv, rax.clone(), rax, rcx.clone()),
+            Stmt::Binop(Binopcode::Idiv, rdx.clone(), rdx, rcx),
+        ])
+    );
+    // the flags are left undefined
+    assert!(matches!(lifted(&[0x48, 0xf7, 0xf9])[2], Stmt::Clear(Value::Reg(16, _), _)));
+    // div eax: the divisor is gone by the time the remainder is lifted
+    let stmts = lifted(&[0xf7, 0xf0]);
+    assert_eq!(format!("{:?}", stmts[1]), format!("{:?}", Stmt::Clear(edx, vec![])));
+    // div cl leaves its results in al and ah
+    let stmts = lifted(&[0xf6, 0xf1]);
+    assert_eq!(format!("{:?}", stmts[0]), format!("{:?}", Stmt::Clear(reg(0, ValSize::Size64), vec![])));
+    assert_eq!(format!("{:?}", stmts[1]), format!("{:?}", Stmt::Clear(reg(2, ValSize::Size64), vec![])));
+    // test ecx, ecx before the division is still a test, whose branch is resolved as any other
+    assert!(matches!(lifted(&[0x85, 0xc9])[0], Stmt::Binop(Binopcode::Test, _, _, _)));
+}
+
+#[test]
+fn lift_frame_test() {
+    use yaxpeax_arch::Decoder;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let lifted = |bytes: &[u8]| {
+        let instr = <AMD64 as Arch>::Decoder::default().decode(bytes.iter().cloned()).unwrap();
+        assert_eq!(instr.len() as usize, bytes.len());
+        format!("{:?}", lift(&instr, &0, &metadata).unwrap())
+    };
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let rbp = Value::Reg(5, ValSize::Size64);
+    let top = Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64)));
+
+    // leave
+    assert_eq!(lifted(&[0xc9]), format!("{:?}", vec![
+        Stmt::Unop(Unopcode::Mov, rsp.clone(), rbp.clone()),
+        Stmt::Unop(Unopcode::Mov, rbp.clone(), top.clone()),
+        Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), mk_value_i64(8)),
+    ]));
+    // enter 0x20, 0
+    assert_eq!(lifted(&[0xc8, 0x20, 0x00, 0x00]), format!("{:?}", vec![
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), mk_value_i64(8)),
+        Stmt::Unop(Unopcode::Mov, top.clone(), rbp.clone()),
+        Stmt::Unop(Unopcode::Mov, rbp.clone(), rsp.clone()),
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), mk_value_i64(0x20)),
+    ]));
+    // enter 0, 2 copies the enclosing frame pointer and pushes its own
+    let outer = Value::Mem(ValSize::Size64, MemArgs::Mem2Args(MemArg::Reg(5, ValSize::Size64), MemArg::Imm(ImmType::Signed, ValSize::Size64, -8)));
+    assert_eq!(lifted(&[0xc8, 0x00, 0x00, 0x02]), format!("{:?}", vec![
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), mk_value_i64(8)),
+        Stmt::Unop(Unopcode::Mov, top.clone(), rbp.clone()),
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), mk_value_i64(8)),
+        Stmt::Unop(Unopcode::Mov, top.clone(), outer),
+        Stmt::Unop(Unopcode::Mov, rbp.clone(), rsp.clone()),
+        Stmt::Binop(Binopcode::Add, rbp.clone(), rbp.clone(), mk_value_i64(8)),
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), mk_value_i64(8)),
+        Stmt::Unop(Unopcode::Mov, top, rbp),
+    ]));
+}
+
+#[test]
+fn split_probestack_test() {
+    let tail = |arg: Option<u64>, call: bool| ProbestackTail { arg: arg, call: call };
+    let mut tails: HashMap<u64, ProbestackTail> = HashMap::new();
+    // 0x10 and 0x20 end with mov eax, 0x2000, 0x30 with mov eax, 0x3000
+    tails.insert(0x10, tail(Some(0x2000), false));
+    tails.insert(0x20, tail(Some(0x2000), false));
+    tails.insert(0x30, tail(Some(0x3000), false));
+    tails.insert(0x40, tail(None, false));
+    assert_eq!(incoming_probestack_arg(0x100, &[0x10, 0x20], &tails), Ok(0x2000));
+    assert_eq!(
+        incoming_probestack_arg(0x100, &[0x10, 0x30], &tails),
+        Err(CfgError::SplitPattern(PROBESTACK_ARG, 0x30, 0x100))
+    );
+    assert_eq!(
+        incoming_probestack_arg(0x100, &[0x40], &tails),
+        Err(CfgError::SplitPattern(PROBESTACK_ARG, 0x40, 0x100))
+    );
+
+    // 0x50 ends with the probestack call, whose sub rsp, rax starts 0x200
+    tails.insert(0x50, tail(None, true));
+    let mut preds: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    preds.insert(0x100, vec![0x10, 0x20]);
+    preds.insert(0x200, vec![0x50]);
+    assert_eq!(probestack_suffix_blocks(&preds, &tails), Ok(vec![0x200].into_iter().collect()));
+    // a second way into 0x200 would skip a sub rsp, rax its call never made
+    preds.insert(0x200, vec![0x50, 0x40]);
+    assert_eq!(
+        probestack_suffix_blocks(&preds, &tails),
+        Err(CfgError::SplitPattern(PROBESTACK_SUFFIX, 0x40, 0x200))
+    );
+}
+
+#[test]
+fn lift_block_provenance_test() {
+    use yaxpeax_arch::Decoder;
+
+    // is_probestack passes over Lucet modules, so these probestack calls are from a Wamr one
+    let metadata = CompilerMetadata {
+        lucet_probestack: 0x5000,
+        ..CompilerMetadata::for_test(Compiler::Wamr)
+    };
+    let decode = |start: u64, bytes: &[u8]| {
+        let mut instrs = vec![];
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let instr = <AMD64 as Arch>::Decoder::default().decode(bytes[offset..].iter().cloned()).unwrap();
+            instrs.push((start + offset as u64, instr.clone()));
+            offset += instr.length as usize;
+        }
+        instrs
+    };
+    let origins = |provenance: &BlockProvenance| provenance.iter().map(|instr| instr.origin).collect::<Vec<_>>();
+    let no_arg = || -> Result<u64, CfgError> { panic!("no predecessors") };
+
+    // mov eax, 0x2000; call lucet_probestack; sub rsp, rax; push rbp; mov rbp, rsp;
+    // push rbx; push r12; push r13; push r14; push r15; ret
+    let bytes: Vec<u8> = vec![
+        0xb8, 0x00, 0x20, 0x00, 0x00,
+        0xe8, 0xf6, 0x3f, 0x00, 0x00,
+        0x48, 0x29, 0xc4,
+        0x55,
+        0x48, 0x89, 0xe5,
+        0x53, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56, 0x41, 0x57,
+        0xc3,
+    ];
+    let instrs = decode(0x1000, &bytes);
+    let (block, provenance) = lift_block(&instrs, false, no_arg, &metadata).unwrap();
+    // every instruction has an entry, and together they cover every byte of the block
+    assert_eq!(block.len(), 11);
+    assert_eq!(unaccounted_bytes(0x1000, 0x1000 + bytes.len() as u64 - 1, &block, &provenance), vec![]);
+    assert_eq!(&origins(&provenance)[..4], &[InstrOrigin::Lifted, InstrOrigin::Synthesized, InstrOrigin::ConsumedByProbestack, InstrOrigin::Lifted]);
+    assert!(matches!(block[1].1.as_slice(), [Stmt::ProbeStack(0x2000)]));
+    assert!(block[2].1.is_empty());
+    // each push is lowered to several statements of a single entry
+    assert!(block[3..10].iter().all(|(_, stmts)| !stmts.is_empty()));
+    assert_eq!(provenance[8], InstrProvenance { len: 2, origin: InstrOrigin::Lifted });
+
+    // the sequence split after the mov, and after the call
+    let (block, provenance) = lift_block(&instrs[1..], false, || Ok(0x2000), &metadata).unwrap();
+    assert_eq!(unaccounted_bytes(0x1005, 0x1000 + bytes.len() as u64 - 1, &block, &provenance), vec![]);
+    let (block, provenance) = lift_block(&instrs[2..], true, no_arg, &metadata).unwrap();
+    assert_eq!(origins(&provenance)[0], InstrOrigin::ConsumedByProbestack);
+    assert_eq!(unaccounted_bytes(0x100a, 0x1000 + bytes.len() as u64 - 1, &block, &provenance), vec![]);
+
+    // a block the entries don't cover, before, between and after them
+    let (block, provenance) = lift_block(&instrs[3..5], false, no_arg, &metadata).unwrap();
+    assert_eq!(unaccounted_bytes(0x100c, 0x1012, &block, &provenance), vec![(0x100c, 0x100d), (0x1011, 0x1013)]);
+    let gapped: IRBlock = vec![block[0].clone(), (0x1010, block[1].1.clone())];
+    assert_eq!(unaccounted_bytes(0x100d, 0x1012, &gapped, &provenance), vec![(0x100e, 0x1010)]);
+}
+
+#[test]
+fn lift_error_test() {
+    use yaxpeax_arch::Decoder;
+
+    // is_probestack passes over Lucet modules, so these probestack calls are from a Wamr one
+    let metadata = CompilerMetadata {
+        lucet_probestack: 0x5000,
+        ..CompilerMetadata::for_test(Compiler::Wamr)
+    };
+    let decode = |start: u64, bytes: &[u8]| {
+        let mut instrs = vec![];
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let instr = <AMD64 as Arch>::Decoder::default().decode(bytes[offset..].iter().cloned()).unwrap();
+            instrs.push((start + offset as u64, instr.clone()));
+            offset += instr.length as usize;
+        }
+        instrs
+    };
+    let lift_one = |bytes: &[u8]| {
+        let (addr, instr) = decode(0x1000, bytes).remove(0);
+        lift(&instr, &addr, &metadata)
+    };
+    let no_arg = || -> Result<u64, CfgError> { panic!("no predecessors") };
+
+    // push ax
+    let err = lift_one(&[0x66, 0x50]).unwrap_err();
+    assert_eq!(err, LiftError { addr: 0x1000, opcode: Opcode::PUSH, reason: "push of 2 bytes".to_string() });
+    assert_eq!(err.to_string(), "can't lift PUSH at 0x1000: push of 2 bytes");
+
+    // mov eax, 0x2000; call lucet_probestack; ret: the call's sub rsp, rax is missing
+    let instrs = decode(0x1000, &[0xb8, 0x00, 0x20, 0x00, 0x00, 0xe8, 0xf6, 0x3f, 0x00, 0x00, 0xc3]);
+    let err = lift_block(&instrs, false, no_arg, &metadata).unwrap_err();
+    assert!(matches!(err, CfgError::Lift(LiftError { addr: 0x100a, opcode: Opcode::RETURN, .. })));
+    // push rbp; call lucet_probestack: nothing moved the stack size into eax
+    let instrs = decode(0x1000, &[0x55, 0xe8, 0xfa, 0x3f, 0x00, 0x00]);
+    let err = lift_block(&instrs, false, no_arg, &metadata).unwrap_err();
+    assert_eq!(err, CfgError::Lift(LiftError { addr: 0x1001, opcode: Opcode::CALL, reason: PROBESTACK_NO_ARG.to_string() }));
+}
+
+#[test]
+fn lift_rip_relative_test() {
+    use yaxpeax_arch::Decoder;
+
+    let metadata = CompilerMetadata {
+        lucet_probestack: 0x5000,
+        ..CompilerMetadata::for_test(Compiler::Lucet)
+    };
+    let lift_at = |addr: u64, bytes: &[u8]| {
+        let instr = <AMD64 as Arch>::Decoder::default().decode(bytes.iter().cloned()).unwrap();
+        lift(&instr, &addr, &metadata).unwrap()
+    };
+    let absolute = |size: ValSize, addr: i64| Value::Mem(size, MemArgs::Mem1Arg(MemArg::Imm(ImmType::Unsigned, ValSize::Size64, addr)));
+
+    // mov rax, [rip + 0x10]: from the end of the 7-byte instruction
+    assert_eq!(
+        lift_at(0x1000, &[0x48, 0x8b, 0x05, 0x10, 0x00, 0x00, 0x00]),
+        vec![Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size64), absolute(ValSize::Size64, 0x1017))]
+    );
+    // movsd xmm0, [rip - 8]: an f64 constant right before the load
+    assert_eq!(
+        lift_at(0x2008, &[0xf2, 0x0f, 0x10, 0x05, 0xf8, 0xff, 0xff, 0xff]),
+        vec![Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::SizeOther), absolute(ValSize::Size64, 0x2008))]
+    );
+    // mov [rip + 0x10], rax: a store is lifted the same way, and left to the heap checker
+    assert_eq!(
+        lift_at(0x1000, &[0x48, 0x89, 0x05, 0x10, 0x00, 0x00, 0x00]),
+        vec![Stmt::Unop(Unopcode::Mov, absolute(ValSize::Size64, 0x1017), Value::Reg(0, ValSize::Size64))]
+    );
+    // lea rax, [rip + 0x10] still computes the address itself
+    assert_eq!(
+        lift_at(0x1000, &[0x48, 0x8d, 0x05, 0x10, 0x00, 0x00, 0x00]),
+        vec![Stmt::Unop(Unopcode::Mov, Value::Reg(0, ValSize::Size64), Value::Imm(ImmType::Signed, ValSize::Size64, 0x1017))]
+    );
+}
+
+#[test]
+fn lift_caveats_test() {
+    use crate::utils::caveats::collect_caveats;
+    use crate::utils::compiler::AnalysisOptions;
+    use yaxpeax_arch::Decoder;
+
+    let metadata = CompilerMetadata {
+        options: AnalysisOptions { unknown_instr: UnknownInstrPolicy::Ignore, ..Default::default() },
+        ..CompilerMetadata::for_test(Compiler::Lucet)
+    };
+    let caveats = |bytes: &[u8]| {
+        let instr = <AMD64 as Arch>::Decoder::default().decode(bytes.iter().cloned()).unwrap();
+        let (lifted, caveats) = collect_caveats(|| lift(&instr, &0x1000, &metadata));
+        assert!(lifted.is_ok(), "{}", instr);
+        caveats.iter().map(|c| (c.kind, c.addr)).collect::<Vec<_>>()
+    };
+
+    // cpuid isn't modeled, and is skipped
+    assert_eq!(caveats(&[0x0f, 0xa2]), vec![(CaveatKind::UnliftedInstruction, Some(0x1000))]);
+    // movups [rax], xmm0
+    assert_eq!(caveats(&[0x0f, 0x11, 0x00]), vec![(CaveatKind::AssumedWidth, Some(0x1000))]);
+    // add [rax], ecx and push qword [rax] are lifted at their own widths
+    assert_eq!(caveats(&[0x01, 0x08]), vec![]);
+    assert_eq!(caveats(&[0xff, 0x30]), vec![]);
+    // xorps xmm0, xmm0 has no memory operand
+    assert_eq!(caveats(&[0x0f, 0x57, 0xc0]), vec![]);
+}
+
+#[test]
+fn unknown_instr_test() {
+    use crate::utils::caveats::collect_caveats;
+    use crate::utils::compiler::AnalysisOptions;
+    use yaxpeax_arch::Decoder;
+
+    let metadata = |policy: UnknownInstrPolicy| CompilerMetadata {
+        options: AnalysisOptions { unknown_instr: policy, ..Default::default() },
+        ..CompilerMetadata::for_test(Compiler::Lucet)
+    };
+    let lift_with = |policy: UnknownInstrPolicy, bytes: &[u8]| {
+        let instr = <AMD64 as Arch>::Decoder::default().decode(bytes.iter().cloned()).unwrap();
+        collect_caveats(|| lift(&instr, &0x1000, &metadata(policy)))
+    };
+    let eax = Value::Reg(0, ValSize::Size32);
+    let zf = Value::Reg(16, ValSize::Size8);
+
+    // bswap eax isn't modeled
+    let bswap = [0x0f, 0xc8];
+    let (lifted, caveats) = lift_with(UnknownInstrPolicy::Reject, &bswap);
+    let err = lifted.unwrap_err();
+    assert_eq!((err.addr, err.opcode), (0x1000, Opcode::BSWAP));
+    assert!(err.reason.starts_with("unimplemented instr BSWAP"), "{}", err.reason);
+    assert!(caveats.is_empty());
+
+    // clearing its destination and the flags is the default
+    assert_eq!(AnalysisOptions::default().unknown_instr, UnknownInstrPolicy::Clear);
+    let (lifted, caveats) = lift_with(UnknownInstrPolicy::Clear, &bswap);
+    let expected = vec![Stmt::Clear(eax.clone(), vec![eax.clone()]), Stmt::Clear(zf, vec![eax])];
+    assert_eq!(format!("{:?}", lifted.unwrap()), format!("{:?}", expected));
+    assert!(caveats.is_empty());
+
+    // ignoring it lifts nothing, and is recorded
+    let (lifted, caveats) = lift_with(UnknownInstrPolicy::Ignore, &bswap);
+    assert!(lifted.unwrap().is_empty());
+    assert_eq!(caveats.len(), 1);
+    assert_eq!((caveats[0].kind, caveats[0].addr), (CaveatKind::UnliftedInstruction, Some(0x1000)));
+    assert_eq!(caveats[0].opcode(), Some("BSWAP"));
+
+    // cpuid writes eax, ebx, ecx and edx without naming them as operands
+    let (lifted, _) = lift_with(UnknownInstrPolicy::Clear, &[0x0f, 0xa2]);
+    let cleared: Vec<u8> = lifted
+        .unwrap()
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::Clear(Value::Reg(regnum, _), _) => Some(*regnum),
+            _ => None,
+        })
+        .collect();
+    for regnum in 0..4 {
+        assert!(cleared.contains(&regnum), "{:?}", cleared);
+    }
+    assert!(cleared.contains(&16));
 }
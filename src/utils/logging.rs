@@ -0,0 +1,262 @@
+//! Per-function log capture.
+//!
+//! Diagnostics logged while a function is being verified are buffered under that function's
+//! index and written out by a single writer thread in index order, so the log reads the same as
+//! a sequential run regardless of which function finishes first. A function that fails is
+//! written out as soon as it finishes (marked as out of order) so failures surface promptly;
+//! when its turn comes in the ordered pass only a short note is written in its place. Unless the
+//! run keeps going after failures, nothing after the first one in order is written, as in a
+//! sequential run that stops there.
+//!
+//! How much is written depends on the verbosity: a quiet run only writes errors (failure
+//! diagnostics) and records logged with the `SUMMARY` target.
+
+use log::{LevelFilter, Log, Metadata, Record};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+/// Target of the records that are written whatever the verbosity, e.g. the final counts:
+/// `info!(target: SUMMARY, ...)`.
+pub const SUMMARY: &str = "summary";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    // also traces every statement checked
+    Verbose,
+}
+
+impl Verbosity {
+    pub fn level(&self) -> LevelFilter {
+        match self {
+            Verbosity::Quiet => LevelFilter::Error,
+            Verbosity::Normal => LevelFilter::Info,
+            Verbosity::Verbose => LevelFilter::Trace,
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT_FUNC: Cell<Option<usize>> = Cell::new(None);
+}
+
+/// Routes records logged on the current thread to the buffer of function `idx`, or straight to
+/// the output if `idx` is None.
+pub fn set_current_function(idx: Option<usize>) {
+    CURRENT_FUNC.with(|f| f.set(idx));
+}
+
+enum LogMsg {
+    Line(Option<usize>, String),
+    Finished(usize, bool, Sender<Vec<String>>),
+    Shutdown,
+}
+
+#[derive(Default)]
+struct FuncLog {
+    lines: Vec<String>,
+    finished: bool,
+    failed: bool,
+    shown: bool,
+}
+
+pub struct OrderedLogger {
+    level: LevelFilter,
+    sender: Mutex<Sender<LogMsg>>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl OrderedLogger {
+    /// Starts the writer thread. Functions are expected to be numbered 0..n in the order their
+    /// output should appear (e.g. by address). With `stop_at_failure`, output ends with the first
+    /// failed function.
+    pub fn new<W: Write + Send + 'static>(out: W, stop_at_failure: bool, verbosity: Verbosity) -> Self {
+        let (sender, receiver) = channel();
+        let writer = thread::spawn(move || write_ordered(out, receiver, stop_at_failure));
+        OrderedLogger {
+            level: verbosity.level(),
+            sender: Mutex::new(sender),
+            writer: Mutex::new(Some(writer)),
+        }
+    }
+
+    /// Marks function `idx` as done. Returns once the writer has dealt with it, so a failure is
+    /// on the output before the caller reports it. The lines logged for a failed function are
+    /// handed back (for reports); nothing is for a passing one.
+    pub fn finish_function(&self, idx: usize, failed: bool) -> Vec<String> {
+        let (ack_sender, ack_receiver) = channel();
+        if self.send(LogMsg::Finished(idx, failed, ack_sender)) {
+            return ack_receiver.recv().unwrap_or_default();
+        }
+        vec![]
+    }
+
+    /// Writes out everything still buffered and stops the writer thread.
+    pub fn shutdown(&self) {
+        self.send(LogMsg::Shutdown);
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            let _ = writer.join();
+        }
+    }
+
+    fn send(&self, msg: LogMsg) -> bool {
+        self.sender.lock().unwrap().send(msg).is_ok()
+    }
+}
+
+impl Log for OrderedLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level || metadata.target() == SUMMARY
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let idx = CURRENT_FUNC.with(|f| f.get());
+            self.send(LogMsg::Line(idx, format!("{}", record.args())));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn write_lines<W: Write>(out: &mut W, lines: &Vec<String>) {
+    for line in lines {
+        let _ = writeln!(out, "{}", line);
+    }
+}
+
+fn write_ordered<W: Write>(mut out: W, receiver: Receiver<LogMsg>, stop_at_failure: bool) {
+    let mut logs: BTreeMap<usize, FuncLog> = BTreeMap::new();
+    let mut next: usize = 0;
+    let mut stopped = false;
+    for msg in receiver {
+        match msg {
+            LogMsg::Line(None, line) => {
+                let _ = writeln!(out, "{}", line);
+            }
+            LogMsg::Line(Some(idx), line) => logs.entry(idx).or_default().lines.push(line),
+            LogMsg::Finished(idx, failed, ack) => {
+                let log = logs.entry(idx).or_default();
+                log.finished = true;
+                log.failed = failed;
+                let lines = if failed { log.lines.clone() } else { vec![] };
+                if failed && idx != next {
+                    let _ = writeln!(out, "[function {}: out of order, failed]", idx);
+                    write_lines(&mut out, &log.lines);
+                    log.shown = true;
+                }
+                while !stopped && logs.get(&next).map_or(false, |log| log.finished) {
+                    let log = logs.remove(&next).unwrap();
+                    if log.shown {
+                        let _ = writeln!(out, "[function {}: output shown above]", next);
+                    } else {
+                        write_lines(&mut out, &log.lines);
+                    }
+                    stopped = stop_at_failure && log.failed;
+                    next += 1;
+                }
+                let _ = out.flush();
+                let _ = ack.send(lines);
+            }
+            LogMsg::Shutdown => break,
+        }
+    }
+    // functions that never finished still get their output, in order
+    for (_, log) in logs {
+        if !stopped && !log.shown {
+            write_lines(&mut out, &log.lines);
+        }
+    }
+    let _ = out.flush();
+}
+
+#[cfg(test)]
+#[derive(Clone)]
+struct SharedBuf(std::sync::Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn run_logged_functions(finish_order: &Vec<usize>, failed: Option<usize>, stop_at_failure: bool) -> String {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    log::set_max_level(log::LevelFilter::Info);
+    let buf = SharedBuf(Arc::new(Mutex::new(Vec::new())));
+    let logger = Arc::new(OrderedLogger::new(buf.clone(), stop_at_failure, Verbosity::Normal));
+    let mut workers = vec![];
+    for (rank, idx) in finish_order.iter().enumerate() {
+        let idx = *idx;
+        let logger = logger.clone();
+        workers.push(thread::spawn(move || {
+            set_current_function(Some(idx));
+            for i in 0..3 {
+                logger.log(&Record::builder().level(log::Level::Info).args(format_args!("func {} line {}", idx, i)).build());
+            }
+            thread::sleep(Duration::from_millis(10 * rank as u64));
+            let lines = logger.finish_function(idx, failed == Some(idx));
+            assert_eq!(lines.len(), if failed == Some(idx) { 3 } else { 0 });
+        }));
+    }
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    logger.shutdown();
+    let out = buf.0.lock().unwrap().clone();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn ordered_logger_test() {
+    let expected: String = (0..4)
+        .flat_map(|idx| (0..3).map(move |i| format!("func {} line {}\n", idx, i)))
+        .collect();
+    let forward = run_logged_functions(&vec![0, 1, 2, 3], None, true);
+    let backward = run_logged_functions(&vec![3, 2, 1, 0], None, true);
+    assert_eq!(forward, expected);
+    assert_eq!(backward, expected);
+
+    // a failure is shown immediately, and only noted in the ordered pass, which stops there
+    let failing = run_logged_functions(&vec![2, 0, 1, 3], Some(2), true);
+    assert!(failing.starts_with("[function 2: out of order, failed]\nfunc 2 line 0\n"));
+    assert!(failing.ends_with("func 1 line 2\n[function 2: output shown above]\n"));
+    assert!(!failing.contains("func 3"));
+    assert_eq!(failing, run_logged_functions(&vec![2, 0, 1, 3], Some(2), true));
+
+    // when the run keeps going, the functions after the failure follow in order
+    let continuing = run_logged_functions(&vec![2, 0, 1, 3], Some(2), false);
+    assert!(continuing.starts_with("[function 2: out of order, failed]\nfunc 2 line 0\n"));
+    assert!(continuing.ends_with("[function 2: output shown above]\nfunc 3 line 0\nfunc 3 line 1\nfunc 3 line 2\n"));
+}
+
+#[test]
+fn verbosity_test() {
+    let record = |level: log::Level, target: &'static str| Metadata::builder().level(level).target(target).build();
+    let quiet = OrderedLogger::new(std::io::sink(), true, Verbosity::Quiet);
+    let normal = OrderedLogger::new(std::io::sink(), true, Verbosity::Normal);
+    let verbose = OrderedLogger::new(std::io::sink(), true, Verbosity::Verbose);
+
+    assert!(!quiet.enabled(&record(log::Level::Warn, "veriwasm")));
+    assert!(quiet.enabled(&record(log::Level::Error, "veriwasm")));
+    assert!(quiet.enabled(&record(log::Level::Info, SUMMARY)));
+    assert!(normal.enabled(&record(log::Level::Info, "veriwasm")));
+    assert!(!normal.enabled(&record(log::Level::Trace, "veriwasm")));
+    assert!(verbose.enabled(&record(log::Level::Trace, "veriwasm")));
+    for logger in [quiet, normal, verbose].iter() {
+        logger.shutdown();
+    }
+}
@@ -0,0 +1,250 @@
+use crate::utils::incremental::load_segments;
+use object::Object;
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+/*
+ * Lucet modules describe themselves in a `lucet_module` structure (lucet-module's
+ * SerializedModule), which survives stripping as a dynamic symbol: the module data, the tables and
+ * the function manifest, each as a (pointer, length) pair. The manifest is an array of
+ * FunctionSpecs, giving the address and size of every guest function in order, so it names the
+ * functions of a module without a symbol table. The module data is lucet-module's ModuleData,
+ * serialized with bincode, which starts with the spec of the linear memory and the list of globals,
+ * of which each takes an 8-byte slot of the globals area.
+ */
+
+pub const LUCET_MODULE_SYMBOL: &str = "lucet_module";
+// offsets of the (pointer, length) pairs in a SerializedModule
+const MODULE_DATA_OFFSET: u64 = 0;
+const TABLES_OFFSET: u64 = 16;
+const FUNCTION_MANIFEST_OFFSET: u64 = 32;
+// a FunctionSpec: code address (8 bytes), code length (4, padded to 8), traps address and length
+const FUNCTION_SPEC_SIZE: u64 = 32;
+// the manifest of a module too large to be one is taken to be garbage
+const MAX_FUNCTIONS: u64 = 1 << 20;
+// the bytes of each non-zero page of the initial linear memory, and the most pages it may have
+const SPARSE_PAGE_SIZE: u64 = 4096;
+const MAX_SPARSE_PAGES: u64 = 1 << 20;
+// the slot of a global
+const GLOBAL_SLOT_SIZE: u64 = 8;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LucetManifest {
+    // the address of the table list (the `lucet_tables` symbol), and of its first table (`guest_table_0`)
+    pub tables: u64,
+    pub guest_table_0: Option<u64>,
+    // the (address, size) of each function, in manifest order
+    pub functions: Vec<(u64, u64)>,
+}
+
+/// The name given to the `idx`th function of the manifest, which is the one lucetc gives it.
+pub fn manifest_func_name(idx: usize) -> String {
+    format!("guest_func_{}", idx)
+}
+
+/// Reads the manifest of the SerializedModule at `lucet_module`, where `read_u64` reads a
+/// (relocated) little-endian word of the module.
+pub fn parse_manifest(lucet_module: u64, read_u64: impl Fn(u64) -> Option<u64>) -> Result<LucetManifest, String> {
+    let word = |addr: u64| read_u64(addr).ok_or_else(|| format!("can't read the Lucet module at 0x{:x}", addr));
+    let (tables, tables_len) = (word(lucet_module + TABLES_OFFSET)?, word(lucet_module + TABLES_OFFSET + 8)?);
+    let (manifest, manifest_len) = (word(lucet_module + FUNCTION_MANIFEST_OFFSET)?, word(lucet_module + FUNCTION_MANIFEST_OFFSET + 8)?);
+    if manifest_len > MAX_FUNCTIONS {
+        return Err(format!("function manifest at 0x{:x} claims {} functions", manifest, manifest_len));
+    }
+    let guest_table_0 = if tables_len > 0 { Some(word(tables)?) } else { None };
+    let mut functions = vec![];
+    for idx in 0..manifest_len {
+        let spec = manifest + idx * FUNCTION_SPEC_SIZE;
+        let (code_addr, code_len) = (word(spec)?, word(spec + 8)? & 0xffff_ffff);
+        if code_addr == 0 {
+            return Err(format!("function {} of the manifest at 0x{:x} has no address", idx, manifest));
+        }
+        functions.push((code_addr, code_len));
+    }
+    Ok(LucetManifest { tables: tables, guest_table_0: guest_table_0, functions: functions })
+}
+
+/// The size of the globals area of the SerializedModule at `lucet_module`, from the number of
+/// globals in its module data. Anything in the way that doesn't look like a ModuleData is an error,
+/// rather than a size read from the wrong bytes.
+pub fn parse_globals_size(lucet_module: u64, read_u64: impl Fn(u64) -> Option<u64>) -> Result<i64, String> {
+    let word = |addr: u64| read_u64(addr).ok_or_else(|| format!("can't read the Lucet module at 0x{:x}", addr));
+    let (data, data_len) = (word(lucet_module + MODULE_DATA_OFFSET)?, word(lucet_module + MODULE_DATA_OFFSET + 8)?);
+    let end = data.checked_add(data_len).ok_or_else(|| format!("module data at 0x{:x} claims {} bytes", data, data_len))?;
+    // the next `len` bytes of the module data, as a little-endian integer
+    let field = |pos: &mut u64, len: u64| -> Result<u64, String> {
+        if *pos + len > end {
+            return Err(format!("module data at 0x{:x} ends early", data));
+        }
+        let value = word(*pos)?;
+        *pos += len;
+        Ok(if len < 8 { value & ((1 << (len * 8)) - 1) } else { value })
+    };
+    let option = |pos: &mut u64| match field(pos, 1)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        tag => Err(format!("module data at 0x{:x} has an option tagged {} at 0x{:x}", data, tag, *pos - 1)),
+    };
+    let mut pos = data;
+    if option(&mut pos)? {
+        // the reserved, guard and initial sizes of the heap, and its max size if any
+        for _ in 0..3 {
+            field(&mut pos, 8)?;
+        }
+        if option(&mut pos)? {
+            field(&mut pos, 8)?;
+        }
+        // the initial contents of each page, if not zeroes
+        let pages = field(&mut pos, 8)?;
+        if pages > MAX_SPARSE_PAGES {
+            return Err(format!("module data at 0x{:x} claims {} initial pages", data, pages));
+        }
+        for _ in 0..pages {
+            if option(&mut pos)? {
+                let len = field(&mut pos, 8)?;
+                if len != SPARSE_PAGE_SIZE {
+                    return Err(format!("module data at 0x{:x} has an initial page of {} bytes", data, len));
+                }
+                pos += len;
+            }
+        }
+    }
+    let globals = field(&mut pos, 8)?;
+    // every global takes at least a slot's worth of module data to describe
+    if globals.saturating_mul(GLOBAL_SLOT_SIZE) > end - pos {
+        return Err(format!("module data at 0x{:x} claims {} globals", data, globals));
+    }
+    Ok((globals * GLOBAL_SLOT_SIZE) as i64)
+}
+
+/// Reads little-endian words of the ELF file `module` by address. The pointers of a shared
+/// object are relative relocations, whose addend is the address, so those read as their addend.
+pub fn module_reader(module: &[u8]) -> Result<impl Fn(u64) -> Option<u64> + '_, String> {
+    let file = object::File::parse(module).map_err(|e| e.to_string())?;
+    let relocations: HashMap<u64, i64> = file
+        .dynamic_relocations()
+        .into_iter()
+        .flatten()
+        .map(|(offset, relocation)| (offset, relocation.addend()))
+        .collect();
+    let segments = load_segments(module)?;
+    Ok(move |addr: u64| {
+        if let Some(addend) = relocations.get(&addr) {
+            return Some(*addend as u64);
+        }
+        let (offset, _, start) = segments.iter().find(|(_, size, start)| addr >= *start && addr + 8 <= start + size)?;
+        let at = (offset + (addr - start)) as usize;
+        module.get(at..at + 8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    })
+}
+
+// The address of the SerializedModule of the ELF file `module`
+fn lucet_module_addr(module: &[u8]) -> Result<u64, String> {
+    let file = object::File::parse(module).map_err(|e| e.to_string())?;
+    file.symbols()
+        .chain(file.dynamic_symbols())
+        .find(|(_, symbol)| symbol.name() == Some(LUCET_MODULE_SYMBOL))
+        .map(|(_, symbol)| symbol.address())
+        .ok_or_else(|| format!("no {} symbol", LUCET_MODULE_SYMBOL))
+}
+
+/// Reads the manifest of the Lucet module in the ELF file `module`.
+pub fn load_manifest(module: &[u8]) -> Result<LucetManifest, String> {
+    parse_manifest(lucet_module_addr(module)?, module_reader(module)?)
+}
+
+/// Reads the size of the globals area of the Lucet module in the ELF file `module`.
+pub fn load_globals_size(module: &[u8]) -> Result<i64, String> {
+    parse_globals_size(lucet_module_addr(module)?, module_reader(module)?)
+}
+
+#[test]
+fn parse_manifest_test() {
+    // a SerializedModule at 0x1000, with one table at 0x2000 and three functions at 0x3000
+    let mut memory: HashMap<u64, u64> = HashMap::new();
+    memory.insert(0x1000 + TABLES_OFFSET, 0x2000);
+    memory.insert(0x1000 + TABLES_OFFSET + 8, 1);
+    memory.insert(0x1000 + FUNCTION_MANIFEST_OFFSET, 0x3000);
+    memory.insert(0x1000 + FUNCTION_MANIFEST_OFFSET + 8, 3);
+    memory.insert(0x2000, 0x2800);
+    for (idx, (addr, len)) in vec![(0x4000, 0x20), (0x4020, 0x100), (0x4120, 0x8)].into_iter().enumerate() {
+        let spec = 0x3000 + idx as u64 * FUNCTION_SPEC_SIZE;
+        memory.insert(spec, addr);
+        // the padding after the length isn't part of it
+        memory.insert(spec + 8, 0xdead_0000_0000 | len);
+    }
+    let read = |memory: &HashMap<u64, u64>, addr: u64| Some(*memory.get(&addr).unwrap_or(&0));
+
+    let manifest = parse_manifest(0x1000, |addr| read(&memory, addr)).unwrap();
+    assert_eq!(manifest.tables, 0x2000);
+    assert_eq!(manifest.guest_table_0, Some(0x2800));
+    assert_eq!(manifest.functions, vec![(0x4000, 0x20), (0x4020, 0x100), (0x4120, 0x8)]);
+    assert_eq!(manifest_func_name(2), "guest_func_2");
+
+    // a manifest whose pointers weren't relocated
+    let mut unrelocated = memory.clone();
+    unrelocated.insert(0x3000 + FUNCTION_SPEC_SIZE, 0);
+    assert_eq!(
+        parse_manifest(0x1000, |addr| read(&unrelocated, addr)),
+        Err("function 1 of the manifest at 0x3000 has no address".to_string())
+    );
+    // memory outside the module
+    assert!(parse_manifest(0x1000, |addr| if addr >= 0x3000 { None } else { read(&memory, addr) }).is_err());
+    let mut huge = memory.clone();
+    huge.insert(0x1000 + FUNCTION_MANIFEST_OFFSET + 8, 1 << 40);
+    assert!(parse_manifest(0x1000, |addr| read(&huge, addr)).is_err());
+}
+
+#[test]
+fn parse_globals_size_test() {
+    // a SerializedModule at 0x1000 whose module data, at 0x2000, is `data`
+    let image = |data: &[u8], data_len: u64| {
+        let mut image = vec![0u8; 0x2000];
+        image[0x1000..0x1008].copy_from_slice(&0x2000u64.to_le_bytes());
+        image[0x1008..0x1010].copy_from_slice(&data_len.to_le_bytes());
+        image.extend_from_slice(data);
+        image
+    };
+    let parse = |image: &Vec<u8>| {
+        parse_globals_size(0x1000, |addr| {
+            image.get(addr as usize..addr as usize + 8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        })
+    };
+    let u64_bytes = |v: u64| v.to_le_bytes().to_vec();
+    // the GlobalSpecs after the count, and the rest of the module data
+    let specs = vec![0u8; 600 * 16];
+
+    // no linear memory, then 600 globals
+    let mut no_memory = vec![0u8];
+    no_memory.extend(u64_bytes(600));
+    no_memory.extend(&specs);
+    assert_eq!(parse(&image(&no_memory, no_memory.len() as u64)), Ok(600 * 8));
+
+    // a linear memory with a max size and three initial pages, the first not zeroes
+    let mut memory = vec![1u8];
+    for size in vec![1 << 32, 1 << 32, 0x10000] {
+        memory.extend(u64_bytes(size));
+    }
+    memory.push(1);
+    memory.extend(u64_bytes(0x10000));
+    memory.extend(u64_bytes(3));
+    memory.push(1);
+    memory.extend(u64_bytes(SPARSE_PAGE_SIZE));
+    memory.extend(vec![0xab; SPARSE_PAGE_SIZE as usize]);
+    memory.extend(vec![0u8, 0u8]);
+    let mut with_memory = memory.clone();
+    with_memory.extend(u64_bytes(600));
+    with_memory.extend(&specs);
+    assert_eq!(parse(&image(&with_memory, with_memory.len() as u64)), Ok(600 * 8));
+
+    // module data cut short, or whose globals couldn't all be described in it
+    assert!(parse(&image(&with_memory, memory.len() as u64 + 4)).is_err());
+    assert!(parse(&image(&with_memory, memory.len() as u64 + 8 + 100)).is_err());
+    // bytes that aren't a ModuleData
+    let mut garbled = with_memory.clone();
+    garbled[0] = 2;
+    assert!(parse(&image(&garbled, garbled.len() as u64)).is_err());
+    let mut odd_page = with_memory.clone();
+    odd_page[1 + 24 + 9 + 8 + 1] = 0x10;
+    assert!(parse(&image(&odd_page, odd_page.len() as u64)).is_err());
+}
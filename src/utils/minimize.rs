@@ -0,0 +1,524 @@
+use crate::analyses::call_analyzer::CallAnalyzer;
+use crate::analyses::heap_analyzer::HeapAnalyzer;
+use crate::analyses::reaching_defs::{analyze_reaching_defs, ReachingDefnAnalyzer};
+use crate::analyses::run_worklist;
+use crate::analyses::stack_analyzer::{untracked_rsp, wamr_stack_limit_cmps, StackAnalyzer};
+use crate::checkers::call_checker::{check_calls, check_direct_calls};
+use crate::checkers::heap_checker::check_heap;
+use crate::checkers::jump_resolver::TableMemory;
+use crate::checkers::stack_checker::check_stack;
+use crate::checkers::CheckFailure;
+use crate::utils::cfg_cache::{entry_from_json, entry_to_json};
+use crate::utils::compiler::CompilerMetadata;
+use crate::utils::ir::IRMap;
+use crate::utils::ir_utils::has_indirect_calls;
+use crate::utils::persist::Persist;
+use crate::utils::utils::{analyze_jump_table_reads, analyze_jump_tables, check_cfi};
+use crate::utils::valid_funcs::ValidFuncs;
+use crate::utils::verification_report::Property;
+use serde_json::{json, Value as Json};
+use std::collections::BTreeMap;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
+use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+/*
+ * Repro bundles (`--repro-dir <dir>`) and their minimization (`veriwasm minimize`). A bundle is
+ * everything the checks read of a function that failed one: its resolved CFG and IR (as a CFG
+ * cache entry holds them, see cfg_cache.rs), the metadata it was verified with, the module's
+ * functions and valid call targets, the PLT range, and the bytes of the jump tables its indirect
+ * jumps go through. Replaying a bundle runs the checks of verify.rs again without the module, so
+ * a bundle can be shared where the module can't.
+ *
+ * Minimizing shrinks a bundle for as long as it keeps failing the target rule at the same
+ * address, delta-debugging style: each candidate reduction is replayed, and kept only if the
+ * failure is preserved. The reductions are tried in turn until a pass over all of them keeps none:
+ *  - truncating the function after the failing instruction: the blocks starting past it are
+ *    dropped with their edges, and the failing block ends with it;
+ *  - dropping blocks, and then instructions, in chunks halved down to single ones;
+ *  - dropping the jump tables, functions and call targets the failure doesn't need;
+ *  - zeroing the metadata fields, one at a time.
+ */
+
+// bumped when the bundles change
+const REPRO_BUNDLE_VERSION: u64 = 1;
+
+#[derive(Clone)]
+pub struct ReproBundle {
+    pub func_name: String,
+    pub metadata: CompilerMetadata,
+    pub cfg: VW_CFG,
+    pub irmap: IRMap,
+    // the module's functions, which the heap checker checks call sites against
+    pub func_addrs: Vec<(u64, String)>,
+    pub valid_funcs: ValidFuncs,
+    pub plt: (u64, u64),
+    // the bytes of each jump table, by its address
+    pub data: BTreeMap<u64, Vec<u8>>,
+}
+
+impl TableMemory for ReproBundle {
+    fn read_byte(&self, addr: u64) -> Option<u8> {
+        let (start, bytes) = self.data.range(..=addr).next_back()?;
+        bytes.get((addr - start) as usize).cloned()
+    }
+}
+
+/// The bytes of the jump tables verifying the function of `cfg` reads from `program`, by address.
+pub fn jump_table_bytes(program: &dyn TableMemory, cfg: &VW_CFG, irmap: &IRMap, metadata: &CompilerMetadata) -> BTreeMap<u64, Vec<u8>> {
+    analyze_jump_table_reads(cfg, irmap, metadata)
+        .into_iter()
+        .filter_map(|(start, end)| Some((start, (start..end).map(|addr| program.read_byte(addr)).collect::<Option<Vec<u8>>>()?)))
+        .collect()
+}
+
+/// How a bundle fails: the property it fails (its rule id, as in the report), at which
+/// instruction, and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReproFailure {
+    pub rule: String,
+    pub addr: u64,
+    pub description: String,
+}
+
+impl ReproFailure {
+    /// Whether this is a failure of `target`: a property (`heap`), or a finer rule the
+    /// description starts with (`cfi.unresolved-jump`).
+    pub fn matches(&self, target: &str) -> bool {
+        self.rule == target || self.description.starts_with(target)
+    }
+}
+
+/// A minimized bundle, the failure it preserved, and the reductions that were kept, in order.
+pub struct Minimized {
+    pub bundle: ReproBundle,
+    pub failure: ReproFailure,
+    pub log: Vec<String>,
+}
+
+// The zeroed value of each metadata field minimizing tries
+const ZEROED_FIELDS: &[(&str, fn(&mut CompilerMetadata))] = &[
+    ("guest_table_0", |m| m.guest_table_0 = 0),
+    ("lucet_tables", |m| m.lucet_tables = 0),
+    ("lucet_probestack", |m| m.lucet_probestack = 0),
+    ("lucet_safe_page", |m| m.lucet_safe_page = None),
+    ("globals_size", |m| m.globals_size = 0),
+    ("call_table_size", |m| m.call_table_size = 0),
+    ("caller_window", |m| m.caller_window = 0),
+    ("strict_stack", |m| m.strict_stack = false),
+    ("strict_rebound", |m| m.strict_rebound = false),
+    ("runtime_fields", |m| m.runtime_fields = vec![]),
+    ("wamr_memories", |m| m.wamr_memories = Default::default()),
+    ("wamr_tables", |m| m.wamr_tables = None),
+    ("options", |m| m.options = Default::default()),
+    ("readonly_data", |m| m.readonly_data = vec![]),
+    ("wasmtime_layout", |m| m.wasmtime_layout = Default::default()),
+];
+
+// An `[addr, value]` entry of a bundle
+fn addr_pair(entry: &Json) -> Option<(u64, &Json)> {
+    Some((entry.get(0)?.as_u64()?, entry.get(1)?))
+}
+
+impl ReproBundle {
+    /// What minimizing shrinks: the blocks, instructions, jump table bytes, functions, call
+    /// targets and nonzero metadata fields of the bundle.
+    pub fn size(&self) -> usize {
+        let instrs: usize = self.irmap.iter().map(|(_, block)| block.len()).sum();
+        let data: usize = self.data.values().map(|bytes| bytes.len()).sum();
+        let fields = ZEROED_FIELDS
+            .iter()
+            .filter(|(_, zero)| {
+                let mut zeroed = self.metadata.clone();
+                zero(&mut zeroed);
+                zeroed.to_json() != self.metadata.to_json()
+            })
+            .count();
+        self.cfg.blocks.len() + instrs + data + self.func_addrs.len() + self.valid_funcs.len() + fields
+    }
+
+    pub fn to_json(&self) -> Json {
+        let mut json = entry_to_json(&self.cfg, &self.irmap, &[]);
+        json["version"] = json!(REPRO_BUNDLE_VERSION);
+        json["func_name"] = json!(self.func_name);
+        json["metadata"] = self.metadata.to_json();
+        json["func_addrs"] = json!(self.func_addrs.iter().map(|(addr, name)| json!([addr, name])).collect::<Vec<Json>>());
+        json["valid_funcs"] = self.valid_funcs.to_json();
+        json["plt"] = json!([self.plt.0, self.plt.1]);
+        json["data"] = json!(self.data.iter().map(|(addr, bytes)| json!([addr, bytes])).collect::<Vec<Json>>());
+        json
+    }
+
+    pub fn from_json(json: &Json) -> Result<ReproBundle, String> {
+        if json.get("version").and_then(|v| v.as_u64()) != Some(REPRO_BUNDLE_VERSION) {
+            return Err(format!("not a version {} repro bundle", REPRO_BUNDLE_VERSION));
+        }
+        let (cfg, irmap, _) = entry_from_json(json).ok_or("malformed CFG or IR")?;
+        let metadata = json.get("metadata").and_then(CompilerMetadata::from_json).ok_or("malformed metadata")?;
+        let func_addrs: Vec<(u64, String)> = json
+            .get("func_addrs")
+            .and_then(|funcs| funcs.as_array())
+            .and_then(|funcs| funcs.iter().map(|f| addr_pair(f).and_then(|(addr, name)| Some((addr, name.as_str()?.to_string())))).collect::<Option<_>>())
+            .ok_or("malformed functions")?;
+        let valid_funcs = ValidFuncs::from_json(json.get("valid_funcs").ok_or("no valid functions")?)?;
+        let plt = json
+            .get("plt")
+            .and_then(|plt| Some((plt.get(0)?.as_u64()?, plt.get(1)?.as_u64()?)))
+            .ok_or("malformed PLT range")?;
+        let bytes = |bytes: &Json| -> Option<Vec<u8>> {
+            bytes.as_array()?.iter().map(|b| b.as_u64().filter(|b| *b <= 0xff).map(|b| b as u8)).collect()
+        };
+        let data: BTreeMap<u64, Vec<u8>> = json
+            .get("data")
+            .and_then(|data| data.as_array())
+            .and_then(|data| data.iter().map(|d| addr_pair(d).and_then(|(addr, b)| Some((addr, bytes(b)?)))).collect::<Option<_>>())
+            .ok_or("malformed jump table data")?;
+        Ok(ReproBundle {
+            func_name: json.get("func_name").and_then(|name| name.as_str()).ok_or("no function name")?.to_string(),
+            metadata: metadata,
+            cfg: cfg,
+            irmap: irmap,
+            func_addrs: func_addrs,
+            valid_funcs: valid_funcs,
+            plt: plt,
+            data: data,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<ReproBundle, String> {
+        let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        serde_json::from_str(&data)
+            .map_err(|e| e.to_string())
+            .and_then(|json| ReproBundle::from_json(&json))
+            .map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(&self.to_json()).unwrap())
+    }
+
+    // The bundle without the blocks in `dropped`, and their edges
+    fn without_blocks(&self, dropped: &[u64]) -> ReproBundle {
+        let mut bundle = self.clone();
+        for addr in dropped {
+            bundle.cfg.blocks.remove(addr);
+            bundle.cfg.graph.remove_node(*addr);
+        }
+        bundle.irmap = self.rebuilt_irmap(|block| !dropped.contains(&block), |_| true);
+        bundle
+    }
+
+    // The bundle without the instructions at `dropped`, their blocks kept even if left empty
+    fn without_instrs(&self, dropped: &[u64]) -> ReproBundle {
+        let mut bundle = self.clone();
+        bundle.irmap = self.rebuilt_irmap(|_| true, |addr| !dropped.contains(&addr));
+        bundle
+    }
+
+    // The IR of the blocks `keep_block` accepts, with the instructions `keep_instr` accepts
+    fn rebuilt_irmap(&self, keep_block: impl Fn(u64) -> bool, keep_instr: impl Fn(u64) -> bool) -> IRMap {
+        let mut irmap = IRMap::new();
+        for (addr, block) in self.irmap.iter() {
+            if !keep_block(addr) {
+                continue;
+            }
+            let kept: Vec<bool> = block.iter().map(|(instr_addr, _)| keep_instr(*instr_addr)).collect();
+            let instrs = block.iter().zip(kept.iter()).filter(|(_, keep)| **keep).map(|(instr, _)| instr.clone()).collect();
+            match self.irmap.provenance(&addr) {
+                Some(provenance) => {
+                    let provenance = provenance.iter().zip(kept.iter()).filter(|(_, keep)| **keep).map(|(instr, _)| instr.clone()).collect();
+                    irmap.insert_lifted(addr, instrs, provenance);
+                }
+                None => irmap.insert(addr, instrs),
+            }
+        }
+        irmap
+    }
+
+    // The bundle cut short after the instruction at `addr`: the blocks starting past it dropped,
+    // and the block it is in ending with it
+    fn truncated_after(&self, addr: u64) -> ReproBundle {
+        let past: Vec<u64> = self.cfg.blocks.keys().filter(|start| **start > addr).cloned().collect();
+        let mut bundle = self.without_blocks(&past);
+        let cut = bundle
+            .irmap
+            .iter()
+            .flat_map(|(_, block)| block.iter().map(|(instr_addr, _)| *instr_addr).collect::<Vec<u64>>())
+            .filter(|instr_addr| *instr_addr > addr)
+            .collect::<Vec<u64>>();
+        if let Some(first_cut) = cut.iter().min() {
+            if let Some((_, block)) = bundle.cfg.blocks.range_mut(..=addr).next_back() {
+                block.end = block.end.min(first_cut - 1);
+            }
+        }
+        bundle.without_instrs(&cut)
+    }
+
+    fn block_addrs(&self) -> Vec<u64> {
+        self.cfg.blocks.keys().cloned().collect()
+    }
+
+    fn instr_addrs(&self) -> Vec<u64> {
+        let mut addrs: Vec<u64> = self.irmap.iter().flat_map(|(_, block)| block.iter().map(|(addr, _)| *addr).collect::<Vec<u64>>()).collect();
+        addrs.sort();
+        addrs
+    }
+}
+
+/// Runs the checks verify.rs runs on the function of `bundle`, in the same order, returning the
+/// first failure, or None if it passes them all.
+pub fn replay(bundle: &ReproBundle) -> Option<ReproFailure> {
+    let (cfg, irmap, metadata) = (&bundle.cfg, &bundle.irmap, &bundle.metadata);
+    let failed = |property: Property, site: CheckFailure| {
+        Some(ReproFailure { rule: property.name().to_string(), addr: site.loc.addr, description: site.description })
+    };
+    if let Err(site) = check_cfi(bundle, cfg, irmap, metadata) {
+        return failed(Property::Cfi, site);
+    }
+    let heap_analyzer = HeapAnalyzer { metadata: metadata.clone() };
+    let heap_result = run_worklist(cfg, irmap, &heap_analyzer);
+    let stack_analyzer = StackAnalyzer {
+        metadata: metadata.clone(),
+        stack_limit_cmps: wamr_stack_limit_cmps(irmap, &heap_result, &heap_analyzer),
+    };
+    let stack_result = run_worklist(cfg, irmap, &stack_analyzer);
+    let untracked = untracked_rsp(cfg, irmap, &stack_result, &stack_analyzer);
+    let jump_tables = analyze_jump_tables(cfg, irmap, metadata);
+    if let Err(site) = check_heap(heap_result, irmap, &heap_analyzer, &bundle.func_addrs, &jump_tables, &untracked) {
+        return failed(Property::Heap, site);
+    }
+    if let Err(site) = check_stack(stack_result, irmap, &stack_analyzer) {
+        return failed(Property::Stack, site);
+    }
+    if let Err(site) = check_direct_calls(irmap, metadata.model, &bundle.valid_funcs, &bundle.plt) {
+        return failed(Property::Call, site);
+    }
+    if has_indirect_calls(irmap, metadata.compiler) {
+        let call_analyzer = CallAnalyzer {
+            metadata: metadata.clone(),
+            reaching_defs: analyze_reaching_defs(cfg, irmap, metadata),
+            reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), cfg.clone(), irmap.clone()),
+            withheld_refinements: AtomicUsize::new(0),
+        };
+        let call_result = run_worklist(cfg, irmap, &call_analyzer);
+        if let Err(site) = check_calls(call_result, irmap, &call_analyzer, &bundle.valid_funcs, &bundle.plt) {
+            return failed(Property::Call, site);
+        }
+    }
+    None
+}
+
+// A candidate reduction can leave IR the analyses were never meant to see; one they panic on
+// doesn't reproduce anything
+fn replay_candidate(bundle: &ReproBundle) -> Option<ReproFailure> {
+    panic::catch_unwind(AssertUnwindSafe(|| replay(bundle))).ok().flatten()
+}
+
+// Drops the `items` of the bundle in chunks, halving the chunks down to single items, and keeps
+// each drop that preserves the failure
+fn drop_chunks(
+    current: &mut ReproBundle,
+    log: &mut Vec<String>,
+    what: &str,
+    items: impl Fn(&ReproBundle) -> Vec<u64>,
+    drop: impl Fn(&ReproBundle, &[u64]) -> ReproBundle,
+    preserves: &dyn Fn(&ReproBundle) -> bool,
+) {
+    let mut chunk = (items(current).len() + 1) / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        loop {
+            let piece: Vec<u64> = items(current).into_iter().skip(start).take(chunk).collect();
+            if piece.is_empty() {
+                break;
+            }
+            let candidate = drop(current, &piece);
+            if preserves(&candidate) {
+                log.push(format!("dropped {} {} from 0x{:x}", piece.len(), what, piece[0]));
+                *current = candidate;
+            } else {
+                start += chunk;
+            }
+        }
+        chunk /= 2;
+    }
+}
+
+/// Minimizes `bundle`, which has to fail `target` (see ReproFailure::matches), to a smaller one
+/// failing the same rule at the same instruction.
+pub fn minimize(bundle: &ReproBundle, target: &str) -> Result<Minimized, String> {
+    let failure = match replay_candidate(bundle) {
+        Some(failure) if failure.matches(target) => failure,
+        Some(failure) => return Err(format!("{} fails {} at 0x{:x}, not {}", bundle.func_name, failure.rule, failure.addr, target)),
+        None => return Err(format!("{} doesn't fail", bundle.func_name)),
+    };
+    let preserves = |candidate: &ReproBundle| {
+        replay_candidate(candidate).map_or(false, |found| found.rule == failure.rule && found.addr == failure.addr)
+    };
+    let mut current = bundle.clone();
+    let mut log = vec![];
+    loop {
+        let before = current.size();
+        let truncated = current.truncated_after(failure.addr);
+        if truncated.size() < current.size() && preserves(&truncated) {
+            log.push(format!("truncated after 0x{:x}", failure.addr));
+            current = truncated;
+        }
+        let (entry, failing) = (current.cfg.entrypoint, failure.addr);
+        let failing_block = current.cfg.blocks.range(..=failing).next_back().map(|(start, _)| *start);
+        drop_chunks(
+            &mut current,
+            &mut log,
+            "blocks",
+            |b| b.block_addrs().into_iter().filter(|addr| *addr != entry && Some(*addr) != failing_block).collect(),
+            |b, blocks| b.without_blocks(blocks),
+            &preserves,
+        );
+        drop_chunks(
+            &mut current,
+            &mut log,
+            "instructions",
+            |b| b.instr_addrs().into_iter().filter(|addr| *addr != failing).collect(),
+            |b, instrs| b.without_instrs(instrs),
+            &preserves,
+        );
+        drop_chunks(
+            &mut current,
+            &mut log,
+            "jump tables",
+            |b| b.data.keys().cloned().collect(),
+            |b, tables| {
+                let mut b = b.clone();
+                b.data.retain(|addr, _| !tables.contains(addr));
+                b
+            },
+            &preserves,
+        );
+        drop_chunks(
+            &mut current,
+            &mut log,
+            "functions",
+            |b| b.func_addrs.iter().map(|(addr, _)| *addr).collect(),
+            |b, funcs| {
+                let mut b = b.clone();
+                b.func_addrs.retain(|(addr, _)| !funcs.contains(addr));
+                b
+            },
+            &preserves,
+        );
+        drop_chunks(
+            &mut current,
+            &mut log,
+            "call targets",
+            |b| b.valid_funcs.addrs(),
+            |b, funcs| {
+                let mut b = b.clone();
+                for addr in funcs {
+                    b.valid_funcs.remove(*addr);
+                }
+                b
+            },
+            &preserves,
+        );
+        for (name, zero) in ZEROED_FIELDS.iter() {
+            let mut candidate = current.clone();
+            zero(&mut candidate.metadata);
+            if candidate.metadata.to_json() != current.metadata.to_json() && preserves(&candidate) {
+                log.push(format!("zeroed {}", name));
+                current = candidate;
+            }
+        }
+        if current.size() == before {
+            break;
+        }
+    }
+    Ok(Minimized { bundle: current, failure: failure, log: log })
+}
+
+pub fn bundle_path(dir: &str, func_name: &str) -> PathBuf {
+    Path::new(dir).join(format!("{}.json", func_name.replace('/', "_")))
+}
+
+pub fn write_bundle(dir: &str, bundle: &ReproBundle) -> std::io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = bundle_path(dir, &bundle.func_name);
+    bundle.save(&path)?;
+    Ok(path)
+}
+
+#[test]
+fn minimize_test() {
+    use crate::utils::compiler::Compiler;
+    use crate::utils::ir::{mk_value_i64, Binopcode, MemArg, MemArgs, Stmt, Unopcode, ValSize, Value};
+    use crate::utils::valid_funcs::FuncSource;
+    use yaxpeax_core::analyses::control_flow::VW_Block;
+
+    let metadata = CompilerMetadata {
+        guest_table_0: 0x8000,
+        lucet_tables: 0x9000,
+        readonly_data: vec![(0x6000, 0x6100)],
+        ..CompilerMetadata::for_test(Compiler::Lucet)
+    };
+    let reg = |regnum: u8| Value::Reg(regnum, ValSize::Size64);
+    let rsp = reg(4);
+    let mov = |dst: u8, src: u8| Stmt::Unop(Unopcode::Mov, reg(dst), reg(src));
+    // 0x0: sub rsp, 16; mov rbx, rdi; mov rdx, rsi; jmp 0x10
+    // 0x10: mov r8, rdx; mov rax, [rcx] (rcx isn't known to be anything); add rsp, 16; jmp 0x20
+    // 0x20: ret
+    let mut cfg = VW_CFG::new(0);
+    for (start, end) in [(0x0, 0xf), (0x10, 0x1f), (0x20, 0x20)].iter() {
+        cfg.blocks.insert(*start, VW_Block { start: *start, end: *end });
+        cfg.graph.add_node(*start);
+    }
+    cfg.graph.add_edge(0x0, 0x10, ());
+    cfg.graph.add_edge(0x10, 0x20, ());
+    let mut irmap = IRMap::new();
+    irmap.insert(0x0, vec![
+        (0x0, vec![Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), mk_value_i64(16))]),
+        (0x4, vec![mov(3, 7)]),
+        (0x8, vec![mov(2, 6)]),
+        (0xb, vec![]),
+    ]);
+    irmap.insert(0x10, vec![
+        (0x10, vec![mov(8, 2)]),
+        (0x14, vec![Stmt::Unop(Unopcode::Mov, reg(0), Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(1, ValSize::Size64))))]),
+        (0x18, vec![Stmt::Binop(Binopcode::Add, rsp.clone(), rsp.clone(), mk_value_i64(16))]),
+        (0x1c, vec![]),
+    ]);
+    irmap.insert(0x20, vec![(0x20, vec![Stmt::Ret])]);
+    let bundle = ReproBundle {
+        func_name: "guest_func_3".to_string(),
+        metadata: metadata,
+        cfg: cfg,
+        irmap: irmap,
+        func_addrs: vec![(0x0, "guest_func_3".to_string()), (0x100, "guest_func_4".to_string())],
+        valid_funcs: ValidFuncs::from_addrs(&[0x0, 0x100], FuncSource::Symbol),
+        plt: (0, 0),
+        data: vec![(0x7000, vec![0x10, 0, 0, 0, 0x20, 0, 0, 0])].into_iter().collect(),
+    };
+    let failure = replay(&bundle).unwrap();
+    assert_eq!((failure.rule.as_str(), failure.addr), ("heap", 0x14));
+
+    // a bundle reads back the same, and replays the same
+    let read_back = ReproBundle::from_json(&bundle.to_json()).unwrap();
+    assert_eq!(read_back.to_json(), bundle.to_json());
+    assert_eq!(replay(&read_back), Some(failure.clone()));
+
+    let minimized = minimize(&bundle, "heap").unwrap();
+    assert!(minimized.bundle.size() < bundle.size());
+    assert!(minimized.bundle.to_json().to_string().len() < bundle.to_json().to_string().len());
+    assert_eq!(minimized.failure, failure);
+    assert_eq!(replay(&minimized.bundle), Some(failure));
+    // nothing past the failing load, and nothing it doesn't need, is left
+    assert_eq!(minimized.bundle.instr_addrs(), vec![0x14]);
+    assert_eq!(minimized.bundle.block_addrs(), vec![0x0, 0x10]);
+    assert!(minimized.bundle.data.is_empty() && minimized.bundle.func_addrs.is_empty() && minimized.bundle.valid_funcs.is_empty());
+    assert_eq!(minimized.bundle.metadata.lucet_tables, 0);
+    assert_eq!(minimized.log[0], "truncated after 0x14");
+    assert!(minimized.log.contains(&"zeroed readonly_data".to_string()));
+
+    // a bundle is only minimized towards the failure it has
+    assert!(minimize(&bundle, "stack").unwrap_err().contains("fails heap at 0x14, not stack"));
+}
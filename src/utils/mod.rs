@@ -1,4 +1,56 @@
-pub mod utils;
+#[cfg(feature = "loader")]
+pub mod assumptions;
+#[cfg(feature = "loader")]
+pub mod caveats;
+#[cfg(feature = "loader")]
+pub mod cfg_cache;
+pub mod cfg_normalize;
+pub mod compiler;
+pub mod compiler_model;
+#[cfg(feature = "loader")]
+pub mod dot;
+#[cfg(feature = "loader")]
+pub mod failure_cache;
+#[cfg(feature = "loader")]
+pub mod fingerprint;
+pub mod func_filter;
+#[cfg(feature = "loader")]
+pub mod html_report;
+#[cfg(feature = "loader")]
+pub mod incremental;
+pub mod ir;
+#[cfg(feature = "loader")]
+pub mod ir_golden;
+#[cfg(feature = "loader")]
+pub mod ir_spill;
 pub mod ir_utils;
+pub mod logging;
+#[cfg(feature = "loader")]
+pub mod lucet_manifest;
+#[cfg(feature = "loader")]
+pub mod minimize;
+#[cfg(feature = "loader")]
+pub mod module_layout;
+pub mod schedule;
+pub mod stmt_id;
+pub mod symbols;
+#[cfg(feature = "loader")]
+pub mod table_funcs;
+#[cfg(feature = "loader")]
 pub mod lifter;
+#[cfg(feature = "loader")]
+pub mod patch;
+#[cfg(feature = "loader")]
+pub mod persist;
+#[cfg(feature = "loader")]
+pub mod stats;
+#[cfg(feature = "loader")]
 pub mod testing;
+#[cfg(feature = "loader")]
+pub mod utils;
+#[cfg(feature = "loader")]
+pub mod valid_funcs;
+#[cfg(feature = "loader")]
+pub mod verification_report;
+pub mod wamr_layout;
+pub mod wasmtime_layout;
@@ -0,0 +1,126 @@
+use object::{BinaryFormat, Object, ObjectSection, SectionKind, SymbolKind};
+
+/// The container format of a native module. COFF objects count as PE.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModuleFormat {
+    Elf,
+    Pe,
+    MachO,
+}
+
+impl ModuleFormat {
+    /// The section holding the code
+    fn text_section(&self) -> &'static str {
+        match self {
+            ModuleFormat::Elf | ModuleFormat::Pe => ".text",
+            ModuleFormat::MachO => "__text",
+        }
+    }
+
+    /// The section of the stubs calls to imported functions go through, if the format has one.
+    /// A PE calls imports indirectly through its import address table instead.
+    fn plt_section(&self) -> Option<&'static str> {
+        match self {
+            ModuleFormat::Elf => Some(".plt"),
+            ModuleFormat::Pe => None,
+            ModuleFormat::MachO => Some("__stubs"),
+        }
+    }
+}
+
+/// What the verifier needs to know of a module before disassembling it, read the same way
+/// whatever its format.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleLayout {
+    pub format: ModuleFormat,
+    pub entrypoint: u64,
+    /// The range of the code section
+    pub text: (u64, u64),
+    /// The range of the import stubs, empty if the module has none
+    pub plt: (u64, u64),
+    /// The ranges of the non-empty read-only data sections, by address
+    pub rodata: Vec<(u64, u64)>,
+    /// The defined symbols, by address. Mach-O names are without their leading underscore, so
+    /// that they read as the compiler named them.
+    pub symbols: Vec<(u64, String)>,
+}
+
+impl ModuleLayout {
+    pub fn parse(module: &[u8]) -> Result<ModuleLayout, String> {
+        let file = object::File::parse(module).map_err(|e| e.to_string())?;
+        let format = match file.format() {
+            BinaryFormat::Elf => ModuleFormat::Elf,
+            BinaryFormat::Pe | BinaryFormat::Coff => ModuleFormat::Pe,
+            BinaryFormat::MachO => ModuleFormat::MachO,
+            other => return Err(format!("{:?} modules aren't supported", other)),
+        };
+        let range = |name: &str| file.section_by_name(name).map(|section| (section.address(), section.address() + section.size()));
+        let text = range(format.text_section()).ok_or_else(|| format!("no {} section", format.text_section()))?;
+        let plt = format.plt_section().and_then(|name| range(name)).unwrap_or((0, 0));
+        let mut rodata: Vec<(u64, u64)> = file
+            .sections()
+            .filter(|section| matches!(section.kind(), SectionKind::ReadOnlyData | SectionKind::ReadOnlyString))
+            .filter(|section| section.size() > 0)
+            .map(|section| (section.address(), section.address() + section.size()))
+            .collect();
+        rodata.sort();
+
+        let mut symbols: Vec<(u64, String)> = file
+            .symbols()
+            .chain(file.dynamic_symbols())
+            .filter(|(_, symbol)| !symbol.is_undefined())
+            .filter(|(_, symbol)| !matches!(symbol.kind(), SymbolKind::Section | SymbolKind::File))
+            .filter_map(|(_, symbol)| {
+                let name = symbol.name()?;
+                let name = match format {
+                    ModuleFormat::MachO => name.strip_prefix('_').unwrap_or(name),
+                    _ => name,
+                };
+                if name.is_empty() {
+                    None
+                } else {
+                    Some((symbol.address(), name.to_string()))
+                }
+            })
+            .collect();
+        symbols.sort();
+        symbols.dedup();
+        Ok(ModuleLayout { format: format, entrypoint: file.entry(), text: text, plt: plt, rodata: rodata, symbols: symbols })
+    }
+
+    /// The address of the symbol `name`, the last one if there are several
+    pub fn symbol_addr(&self, name: &str) -> Option<u64> {
+        self.symbols.iter().filter(|(_, symbol)| symbol == name).map(|(addr, _)| *addr).last()
+    }
+}
+
+#[test]
+fn module_layout_test() {
+    let elf = ModuleLayout::parse(include_bytes!("../../tests/layout/module.so")).unwrap();
+    assert_eq!(elf.format, ModuleFormat::Elf);
+    assert_eq!(elf.text, (0x1020, 0x1034));
+    assert_eq!(elf.plt, (0x1000, 0x1020));
+    // its only read-only data section, .eh_frame, is empty
+    assert_eq!(elf.rodata, vec![]);
+    assert_eq!(elf.symbol_addr("aot_func#0"), Some(0x1020));
+    assert_eq!(elf.symbol_addr("aot_func#1"), Some(0x102b));
+    // imports aren't defined here
+    assert_eq!(elf.symbol_addr("aot_set_exception_with_id"), None);
+
+    let pe = ModuleLayout::parse(include_bytes!("../../tests/layout/module.obj")).unwrap();
+    assert_eq!(pe.format, ModuleFormat::Pe);
+    assert_eq!(pe.text, (0, 0x15));
+    assert_eq!(pe.plt, (0, 0));
+    assert_eq!(pe.symbol_addr("aot_func#0"), Some(0));
+    assert_eq!(pe.symbol_addr("aot_func#1"), Some(0xc));
+    assert_eq!(pe.symbol_addr("__imp_aot_set_exception_with_id"), None);
+
+    let macho = ModuleLayout::parse(include_bytes!("../../tests/layout/module.macho.o")).unwrap();
+    assert_eq!(macho.format, ModuleFormat::MachO);
+    assert_eq!(macho.text, (0, 0x14));
+    assert_eq!(macho.plt, (0x14, 0x1a));
+    assert_eq!(macho.symbol_addr("aot_func#0"), Some(0));
+    assert_eq!(macho.symbol_addr("aot_func#1"), Some(0xb));
+
+    assert!(ModuleLayout::parse(b"not a module").is_err());
+}
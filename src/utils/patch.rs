@@ -0,0 +1,146 @@
+use crate::utils::incremental::load_segments;
+use serde_json::Value as Json;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use yaxpeax_core::memory::repr::process::ModuleData;
+
+/*
+ * Verifying a hot-patched module (`--patch`) without writing out the patched file. A patch list
+ * is a JSON list of `[offset, original, new]` triples: a file offset, and the bytes found there
+ * and written there instead, as hex strings of the same length. The patches are checked against
+ * the module file and applied to the loaded image only, so the file itself stays as it was. The
+ * functions whose code or jump tables a patch touches are flagged in the report, whatever their
+ * verdict, and the report records a digest of the list.
+ */
+
+#[derive(Clone, Debug, PartialEq, Hash)]
+pub struct Patch {
+    pub offset: u64,
+    pub original: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PatchList {
+    // in file order, without overlaps
+    pub patches: Vec<Patch>,
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if hex.is_empty() || hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl PatchList {
+    pub fn parse(data: &str) -> Result<PatchList, String> {
+        let json: Json = serde_json::from_str(data).map_err(|e| e.to_string())?;
+        let triples = json.as_array().ok_or("expected a list of [offset, original, new] triples")?;
+        let mut patches = vec![];
+        for triple in triples {
+            let field = |i: usize| triple.as_array().filter(|t| t.len() == 3).and_then(|t| t.get(i));
+            let patch = match (field(0).and_then(|o| o.as_u64()), field(1).and_then(|o| o.as_str()), field(2).and_then(|n| n.as_str())) {
+                (Some(offset), Some(original), Some(new)) => match (parse_hex(original), parse_hex(new)) {
+                    (Some(original), Some(new)) => Patch { offset: offset, original: original, new: new },
+                    _ => return Err(format!("patch at 0x{:x}: bytes aren't hex", offset)),
+                },
+                _ => return Err(format!("malformed patch {}", triple)),
+            };
+            if patch.original.len() != patch.new.len() {
+                return Err(format!(
+                    "patch at 0x{:x} replaces {} bytes with {}; patches are applied in place",
+                    patch.offset, patch.original.len(), patch.new.len()
+                ));
+            }
+            patches.push(patch);
+        }
+        patches.sort_by_key(|patch| patch.offset);
+        for pair in patches.windows(2) {
+            if pair[0].offset + pair[0].original.len() as u64 > pair[1].offset {
+                return Err(format!("patches at 0x{:x} and 0x{:x} overlap", pair[0].offset, pair[1].offset));
+            }
+        }
+        Ok(PatchList { patches: patches })
+    }
+
+    pub fn load(path: &str) -> Result<PatchList, String> {
+        let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        PatchList::parse(&data).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// A digest of the patches, which tells apart the patched states of a module.
+    pub fn digest(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.patches.hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Checks that the module file `module` has the original bytes of every patch, and returns
+    /// the address each patch is loaded at.
+    pub fn locate(&self, module: &[u8]) -> Result<Vec<u64>, String> {
+        let segments = load_segments(module)?;
+        let mut addrs = vec![];
+        for patch in self.patches.iter() {
+            let (start, end) = (patch.offset as usize, patch.offset as usize + patch.original.len());
+            match module.get(start..end) {
+                Some(found) if found == patch.original.as_slice() => (),
+                Some(found) => {
+                    return Err(format!(
+                        "patch at 0x{:x} expects {} but the module has {}",
+                        patch.offset, to_hex(&patch.original), to_hex(found)
+                    ))
+                }
+                None => return Err(format!("patch at 0x{:x} is past the end of the module", patch.offset)),
+            }
+            let len = patch.original.len() as u64;
+            let segment = segments.iter().find(|(offset, size, _)| patch.offset >= *offset && patch.offset + len <= offset + size);
+            match segment {
+                Some((offset, _, addr)) => addrs.push(addr + (patch.offset - offset)),
+                None => return Err(format!("patch at 0x{:x} isn't in a loaded segment", patch.offset)),
+            }
+        }
+        Ok(addrs)
+    }
+
+    /// Applies the patches to `program`, the loaded image of the module file `module`, once their
+    /// original bytes check out. Returns the patched address ranges, half-open.
+    pub fn apply(&self, module: &[u8], program: &mut ModuleData) -> Result<Vec<(u64, u64)>, String> {
+        let addrs = self.locate(module)?;
+        let mut ranges = vec![];
+        for (patch, addr) in self.patches.iter().zip(addrs) {
+            let end = addr + patch.new.len() as u64;
+            let segment = program
+                .segments
+                .iter_mut()
+                .find(|segment| addr >= segment.start as u64 && end <= (segment.start + segment.data.len()) as u64)
+                .ok_or_else(|| format!("patch at 0x{:x} isn't in the loaded image (0x{:x})", patch.offset, addr))?;
+            let at = (addr - segment.start as u64) as usize;
+            segment.data[at..at + patch.new.len()].copy_from_slice(&patch.new);
+            ranges.push((addr, end));
+        }
+        Ok(ranges)
+    }
+}
+
+#[test]
+fn patch_list_test() {
+    let list = PatchList::parse(r#"[[4096, "b801000000", "b802000000"], [16, "90", "cc"]]"#).unwrap();
+    // sorted by offset
+    assert_eq!(list.patches[0], Patch { offset: 16, original: vec![0x90], new: vec![0xcc] });
+    assert_eq!(list.patches[1].new, vec![0xb8, 0x02, 0, 0, 0]);
+    assert_eq!(list.digest(), PatchList::parse(r#"[[16, "90", "cc"], [4096, "b801000000", "b802000000"]]"#).unwrap().digest());
+    assert_ne!(list.digest(), PatchList::parse(r#"[[16, "90", "c3"], [4096, "b801000000", "b802000000"]]"#).unwrap().digest());
+
+    assert!(PatchList::parse(r#"[[16, "90", "cccc"]]"#).unwrap_err().contains("in place"));
+    assert!(PatchList::parse(r#"[[16, "9", "c"]]"#).unwrap_err().contains("hex"));
+    assert!(PatchList::parse(r#"[[16, "9090", "cccc"], [17, "90", "cc"]]"#).unwrap_err().contains("overlap"));
+    assert!(PatchList::parse(r#"[[16, "90"]]"#).is_err());
+    assert!(PatchList::parse(r#"{"offset": 16}"#).is_err());
+}
@@ -0,0 +1,606 @@
+use crate::analyses::heap_analyzer::HeapAnalyzer;
+use crate::analyses::stack_analyzer::StackAnalyzer;
+use crate::analyses::{AbstractAnalyzer, AnalysisResult};
+use crate::lattices::heaplattice::HeapValue;
+use crate::lattices::reachingdefslattice::LocIdx;
+use crate::lattices::stacklattice::StackSlot;
+use crate::lattices::{BooleanLattice, ConstLattice, Lattice, VarState, VariableState};
+use crate::utils::compiler::{Abi, AnalysisOptions, Compiler, CompilerMetadata};
+use crate::utils::ir::{Binopcode, IRBlock, IRMap, ImmType, MemArg, MemArgs, Stmt, Unopcode, ValSize, Value};
+use crate::utils::lifter::{BRANCH_OPCODES, FORBIDDEN_OPCODES};
+use crate::utils::wamr_layout::{RuntimeField, WamrMemories, WamrTables};
+use crate::utils::wasmtime_layout::WasmtimeLayout;
+use serde_json::{json, Map, Value as Json};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+use yaxpeax_x86::long_mode::Opcode;
+
+/// Analyses whose final states can be persisted and queried with `veriwasm query`. Both only need
+/// the compiler metadata to be rebuilt, which is what makes replaying a block possible offline.
+pub const PERSISTED_ANALYSES: [&str; 2] = ["heap", "stack"];
+
+/// A compact JSON encoding. Lattice encodings leave out default (bottom) entries.
+pub trait Persist: Sized {
+    fn to_json(&self) -> Json;
+    fn from_json(json: &Json) -> Option<Self>;
+}
+
+const REG_NAMES: [&str; 17] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12",
+    "r13", "r14", "r15", "zf",
+];
+
+const HEAP_VALUES: [HeapValue; 19] = [
+    HeapValue::HeapBase,
+    HeapValue::SafePage,
+    HeapValue::HeapBaseOrSafePage,
+    HeapValue::GlobalsBase,
+    HeapValue::LucetTables,
+    HeapValue::GuestTable0,
+    HeapValue::GuestTable0Entry,
+    HeapValue::LucetTableSize,
+    HeapValue::CheckedTableIndex,
+    HeapValue::CheckedTableOffset,
+    HeapValue::WamrExecEnv,
+    HeapValue::WamrModuleInstance,
+    HeapValue::WamrFuncTypeTable,
+    HeapValue::WamrFuncPtrsTable,
+    HeapValue::WamrTableDescriptor,
+    HeapValue::WamrStackLimit,
+    HeapValue::WasmtimeVMContext,
+    HeapValue::WasmtimeTableBase,
+    HeapValue::WasmtimeAnyfunc,
+];
+
+fn by_name<T: Debug + Clone>(options: &[T], json: &Json) -> Option<T> {
+    let name = json.as_str()?;
+    options.iter().find(|option| format!("{:?}", option) == name).cloned()
+}
+
+impl Persist for HeapValue {
+    fn to_json(&self) -> Json {
+        match self {
+            HeapValue::GlobalsPtr(offset) => json!({"GlobalsPtr": offset}),
+            HeapValue::Bounded(bound) => json!({"Bounded": bound}),
+            HeapValue::WamrMemBound(width) => json!({"WamrMemBound": width}),
+            HeapValue::CheckedHeapOffset(width) => json!({"CheckedHeapOffset": width}),
+            HeapValue::WamrMemBoundCheck(regnum, index_first, width, cmp_loc) => {
+                json!({"WamrMemBoundCheck": [regnum, index_first, width, cmp_loc.addr, cmp_loc.idx]})
+            }
+            HeapValue::TableSizeCheck(regnum, index_first, cmp_loc) => {
+                json!({"TableSizeCheck": [regnum, index_first, cmp_loc.addr, cmp_loc.idx]})
+            }
+            _ => json!(format!("{:?}", self)),
+        }
+    }
+    fn from_json(json: &Json) -> Option<Self> {
+        if let Some(offset) = json.get("GlobalsPtr") {
+            return Some(HeapValue::GlobalsPtr(offset.as_i64()?));
+        }
+        if let Some(bound) = json.get("Bounded") {
+            return Some(HeapValue::Bounded(bound.as_u64()?));
+        }
+        if let Some(width) = json.get("WamrMemBound") {
+            return Some(HeapValue::WamrMemBound(width.as_u64()? as u32));
+        }
+        if let Some(width) = json.get("CheckedHeapOffset") {
+            return Some(HeapValue::CheckedHeapOffset(width.as_u64()? as u32));
+        }
+        if let Some(check) = json.get("WamrMemBoundCheck") {
+            let cmp_loc = LocIdx { addr: check.get(3)?.as_u64()?, idx: check.get(4)?.as_u64()? as u32 };
+            let width = check.get(2)?.as_u64()? as u32;
+            return Some(HeapValue::WamrMemBoundCheck(check.get(0)?.as_u64()? as u8, check.get(1)?.as_bool()?, width, cmp_loc));
+        }
+        if let Some(check) = json.get("TableSizeCheck") {
+            let cmp_loc = LocIdx { addr: check.get(2)?.as_u64()?, idx: check.get(3)?.as_u64()? as u32 };
+            return Some(HeapValue::TableSizeCheck(check.get(0)?.as_u64()? as u8, check.get(1)?.as_bool()?, cmp_loc));
+        }
+        // states persisted before bounds were parametric
+        match json.as_str() {
+            Some("Bounded4GB") => return Some(HeapValue::Bounded4GB),
+            Some("Bounded256B") => return Some(HeapValue::Bounded256B),
+            _ => (),
+        }
+        by_name(&HEAP_VALUES, json)
+    }
+}
+
+impl Persist for BooleanLattice {
+    fn to_json(&self) -> Json {
+        json!(self.is_true())
+    }
+    fn from_json(json: &Json) -> Option<Self> {
+        json.as_bool().map(BooleanLattice::new)
+    }
+}
+
+impl<T: Eq + Clone + Debug + Persist> Persist for ConstLattice<T> {
+    fn to_json(&self) -> Json {
+        match &self.v {
+            Some(v) => v.to_json(),
+            None => Json::Null,
+        }
+    }
+    fn from_json(json: &Json) -> Option<Self> {
+        if json.is_null() {
+            return Some(ConstLattice { v: None });
+        }
+        T::from_json(json).map(ConstLattice::new)
+    }
+}
+
+// stackgrowth, probestack, the offsets of saved registers, whether an exception was raised, the
+// frame pointer, where the stack limit was checked and whether a frame was allocated, as in
+// `StackGrowthLattice`
+impl Persist for (i64, i64, HashMap<u8, i64>, bool, Option<i64>, Option<i64>, bool) {
+    fn to_json(&self) -> Json {
+        let saved: Map<String, Json> = self.2.iter().map(|(reg, offset)| (reg.to_string(), json!(offset))).collect();
+        json!([self.0, self.1, saved, self.3, self.4, self.5, self.6])
+    }
+    fn from_json(json: &Json) -> Option<Self> {
+        let mut saved = HashMap::new();
+        for (reg, offset) in json.get(2)?.as_object()? {
+            saved.insert(reg.parse().ok()?, offset.as_i64()?);
+        }
+        // states persisted before exceptions were tracked have three fields, before frame
+        // pointers were, four, before stack limit checks were, five, and before frame allocations
+        // were, six (which are taken to have allocated one)
+        let raised = json.get(3).and_then(|raised| raised.as_bool()).unwrap_or(false);
+        let frame_pointer = json.get(4).and_then(|frame_pointer| frame_pointer.as_i64());
+        let checked = json.get(5).and_then(|checked| checked.as_i64());
+        let allocated = json.get(6).and_then(|allocated| allocated.as_bool()).unwrap_or(true);
+        Some((json.get(0)?.as_i64()?, json.get(1)?.as_i64()?, saved, raised, frame_pointer, checked, allocated))
+    }
+}
+
+impl<T: Lattice + Clone + Persist> Persist for VariableState<T> {
+    fn to_json(&self) -> Json {
+        let mut regs = Map::new();
+        for (regnum, name) in REG_NAMES.iter().enumerate() {
+            let value = self.regs.get(&(regnum as u8), &ValSize::Size64);
+            if value != Default::default() {
+                regs.insert(name.to_string(), value.to_json());
+            }
+        }
+        let mut slots: Vec<(&i64, &StackSlot<T>)> = self.stack.map.iter().collect();
+        slots.sort_by_key(|(offset, _)| **offset);
+        let stack: Map<String, Json> = slots
+            .into_iter()
+            .map(|(offset, slot)| (offset.to_string(), json!([slot.size, slot.value.to_json()])))
+            .collect();
+        json!({"regs": regs, "stack_offset": self.stack.offset, "stack": stack})
+    }
+    fn from_json(json: &Json) -> Option<Self> {
+        let mut state: VariableState<T> = Default::default();
+        for (name, value) in json.get("regs")?.as_object()? {
+            let regnum = REG_NAMES.iter().position(|reg| reg == name)?;
+            state.regs.set(&(regnum as u8), &ValSize::Size64, T::from_json(value)?);
+        }
+        state.stack.offset = json.get("stack_offset")?.as_i64()?;
+        for (offset, slot) in json.get("stack")?.as_object()? {
+            state.stack.map.insert(
+                offset.parse().ok()?,
+                StackSlot {
+                    size: slot.get(0)?.as_u64()? as u32,
+                    value: T::from_json(slot.get(1)?)?,
+                },
+            );
+        }
+        Some(state)
+    }
+}
+
+impl Persist for CompilerMetadata {
+    fn to_json(&self) -> Json {
+        let compiler = match self.compiler {
+            Compiler::Lucet => "lucet",
+            Compiler::Wamr => "wamr",
+            Compiler::Wasmtime => "wasmtime",
+        };
+        json!({
+            "compiler": compiler,
+            "guest_table_0": self.guest_table_0,
+            "lucet_tables": self.lucet_tables,
+            "lucet_probestack": self.lucet_probestack,
+            "lucet_safe_page": self.lucet_safe_page,
+            "globals_size": self.globals_size,
+            "call_table_size": self.call_table_size,
+            "caller_window": self.caller_window,
+            "strict_stack": self.strict_stack,
+            "strict_rebound": self.strict_rebound,
+            "runtime_fields": self.runtime_fields.iter().map(|f| json!([f.offset, f.width])).collect::<Vec<Json>>(),
+            "wamr_memories": self.wamr_memories.to_string(),
+            "wamr_tables": self.wamr_tables.map(|tables| tables.to_string()),
+            "options": self.options.to_string(),
+            "abi": self.abi.to_string(),
+            "readonly_data": self.readonly_data.iter().map(|(start, end)| json!([start, end])).collect::<Vec<Json>>(),
+            "wasmtime_layout": self.wasmtime_layout.to_string(),
+        })
+    }
+    fn from_json(json: &Json) -> Option<Self> {
+        let compiler = match json.get("compiler")?.as_str()? {
+            "lucet" => Compiler::Lucet,
+            "wamr" => Compiler::Wamr,
+            "wasmtime" => Compiler::Wasmtime,
+            _ => return None,
+        };
+        Some(CompilerMetadata {
+            compiler: compiler,
+            model: compiler.model(),
+            guest_table_0: json.get("guest_table_0")?.as_u64()?,
+            lucet_tables: json.get("lucet_tables")?.as_u64()?,
+            lucet_probestack: json.get("lucet_probestack")?.as_u64()?,
+            // absent in states persisted before Spectre-mitigated modules were verified
+            lucet_safe_page: json.get("lucet_safe_page").and_then(|page| page.as_u64()),
+            globals_size: json.get("globals_size")?.as_i64()?,
+            // absent in states persisted while the table size was folded into globals_size
+            call_table_size: json.get("call_table_size").and_then(|n| n.as_i64()).unwrap_or(-1),
+            caller_window: json.get("caller_window")?.as_i64()?,
+            strict_stack: json.get("strict_stack")?.as_bool()?,
+            strict_rebound: json.get("strict_rebound").and_then(|b| b.as_bool()).unwrap_or(false),
+            // absent in states persisted before runtime fields were configurable
+            runtime_fields: match json.get("runtime_fields") {
+                Some(fields) => fields
+                    .as_array()?
+                    .iter()
+                    .map(|f| Some(RuntimeField { offset: f.get(0)?.as_i64()?, width: f.get(1)?.as_i64()? }))
+                    .collect::<Option<Vec<RuntimeField>>>()?,
+                None => vec![],
+            },
+            // absent in states persisted before multiple memories were supported
+            wamr_memories: match json.get("wamr_memories") {
+                Some(memories) => WamrMemories::parse(memories.as_str()?).ok()?,
+                None => WamrMemories::default(),
+            },
+            // absent in states persisted before multiple funcref tables were supported
+            wamr_tables: match json.get("wamr_tables").and_then(|tables| tables.as_str()) {
+                Some(tables) => Some(WamrTables::parse(tables).ok()?),
+                None => None,
+            },
+            options: match json.get("options") {
+                Some(options) => AnalysisOptions::parse(options.as_str()?).ok()?,
+                None => AnalysisOptions::default(),
+            },
+            // absent in states persisted before x32 modules were supported
+            abi: match json.get("abi") {
+                Some(abi) => abi.as_str()?.parse().ok()?,
+                None => Abi::Lp64,
+            },
+            // absent in states persisted before rip-relative loads were lifted
+            readonly_data: match json.get("readonly_data") {
+                Some(ranges) => ranges
+                    .as_array()?
+                    .iter()
+                    .map(|r| Some((r.get(0)?.as_u64()?, r.get(1)?.as_u64()?)))
+                    .collect::<Option<Vec<(u64, u64)>>>()?,
+                None => vec![],
+            },
+            // absent in states persisted before Wasmtime was supported
+            wasmtime_layout: match json.get("wasmtime_layout") {
+                Some(layout) => WasmtimeLayout::parse(layout.as_str()?).ok()?,
+                None => WasmtimeLayout::default(),
+            },
+        })
+    }
+}
+
+fn valsize_to_json(size: &ValSize) -> Json {
+    match size {
+        ValSize::SizeOther => json!(0),
+        _ => json!(size.to_u32()),
+    }
+}
+
+fn valsize_from_json(json: &Json) -> Option<ValSize> {
+    match json.as_u64()? {
+        0 => Some(ValSize::SizeOther),
+        8 => Some(ValSize::Size8),
+        16 => Some(ValSize::Size16),
+        32 => Some(ValSize::Size32),
+        64 => Some(ValSize::Size64),
+        _ => None,
+    }
+}
+
+fn immtype_to_json(immtype: &ImmType) -> Json {
+    match immtype {
+        ImmType::Signed => json!("s"),
+        ImmType::Unsigned => json!("u"),
+    }
+}
+
+fn immtype_from_json(json: &Json) -> Option<ImmType> {
+    match json.as_str()? {
+        "s" => Some(ImmType::Signed),
+        "u" => Some(ImmType::Unsigned),
+        _ => None,
+    }
+}
+
+impl Persist for MemArg {
+    fn to_json(&self) -> Json {
+        match self {
+            MemArg::Reg(regnum, size) => json!(["reg", regnum, valsize_to_json(size)]),
+            MemArg::Imm(immtype, size, v) => json!(["imm", immtype_to_json(immtype), valsize_to_json(size), v]),
+        }
+    }
+    fn from_json(json: &Json) -> Option<Self> {
+        match json.get(0)?.as_str()? {
+            "reg" => Some(MemArg::Reg(json.get(1)?.as_u64()? as u8, valsize_from_json(json.get(2)?)?)),
+            "imm" => Some(MemArg::Imm(
+                immtype_from_json(json.get(1)?)?,
+                valsize_from_json(json.get(2)?)?,
+                json.get(3)?.as_i64()?,
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl Persist for MemArgs {
+    fn to_json(&self) -> Json {
+        match self {
+            MemArgs::Mem1Arg(a) => json!(["1", a.to_json()]),
+            MemArgs::Mem2Args(a, b) => json!(["2", a.to_json(), b.to_json()]),
+            MemArgs::Mem3Args(a, b, c) => json!(["3", a.to_json(), b.to_json(), c.to_json()]),
+            MemArgs::MemScale(a, b, c) => json!(["scale", a.to_json(), b.to_json(), c.to_json()]),
+            MemArgs::MemScaleDisp(a, b, c, d) => {
+                json!(["scaledisp", a.to_json(), b.to_json(), c.to_json(), d.to_json()])
+            }
+        }
+    }
+    fn from_json(json: &Json) -> Option<Self> {
+        let arg = |i: usize| MemArg::from_json(json.get(i)?);
+        match json.get(0)?.as_str()? {
+            "1" => Some(MemArgs::Mem1Arg(arg(1)?)),
+            "2" => Some(MemArgs::Mem2Args(arg(1)?, arg(2)?)),
+            "3" => Some(MemArgs::Mem3Args(arg(1)?, arg(2)?, arg(3)?)),
+            "scale" => Some(MemArgs::MemScale(arg(1)?, arg(2)?, arg(3)?)),
+            "scaledisp" => Some(MemArgs::MemScaleDisp(arg(1)?, arg(2)?, arg(3)?, arg(4)?)),
+            _ => None,
+        }
+    }
+}
+
+impl Persist for Value {
+    fn to_json(&self) -> Json {
+        match self {
+            Value::Mem(size, memargs) => json!(["mem", valsize_to_json(size), memargs.to_json()]),
+            Value::Reg(regnum, size) => json!(["reg", regnum, valsize_to_json(size)]),
+            Value::Imm(immtype, size, v) => json!(["imm", immtype_to_json(immtype), valsize_to_json(size), v]),
+        }
+    }
+    fn from_json(json: &Json) -> Option<Self> {
+        match json.get(0)?.as_str()? {
+            "mem" => Some(Value::Mem(valsize_from_json(json.get(1)?)?, MemArgs::from_json(json.get(2)?)?)),
+            "reg" => Some(Value::Reg(json.get(1)?.as_u64()? as u8, valsize_from_json(json.get(2)?)?)),
+            "imm" => Some(Value::Imm(
+                immtype_from_json(json.get(1)?)?,
+                valsize_from_json(json.get(2)?)?,
+                json.get(3)?.as_i64()?,
+            )),
+            _ => None,
+        }
+    }
+}
+
+const UNOPCODES: [Unopcode; 5] = [Unopcode::Mov, Unopcode::MovZx, Unopcode::MovSx, Unopcode::Set, Unopcode::Xchg];
+const BINOPCODES: [Binopcode; 11] = [
+    Binopcode::Test,
+    Binopcode::Rol,
+    Binopcode::Cmp,
+    Binopcode::Shl,
+    Binopcode::Shr,
+    Binopcode::And,
+    Binopcode::Add,
+    Binopcode::Sub,
+    Binopcode::Cmov,
+    Binopcode::Div,
+    Binopcode::Idiv,
+];
+
+fn opcode_to_json(opcode: &Opcode) -> Json {
+    json!(format!("{:?}", opcode))
+}
+
+impl Persist for Stmt {
+    fn to_json(&self) -> Json {
+        match self {
+            Stmt::Clear(dst, srcs) => {
+                let srcs: Vec<Json> = srcs.iter().map(|src| src.to_json()).collect();
+                json!(["clear", dst.to_json(), srcs])
+            }
+            Stmt::Unop(opcode, dst, src) => json!(["unop", format!("{:?}", opcode), dst.to_json(), src.to_json()]),
+            Stmt::Binop(opcode, dst, src1, src2) => {
+                json!(["binop", format!("{:?}", opcode), dst.to_json(), src1.to_json(), src2.to_json()])
+            }
+            Stmt::Undefined => json!(["undefined"]),
+            Stmt::Ret => json!(["ret"]),
+            Stmt::Branch(opcode, target) => json!(["branch", opcode_to_json(opcode), target.to_json()]),
+            Stmt::Call(target, len) => json!(["call", target.to_json(), len]),
+            Stmt::ProbeStack(size) => json!(["probestack", size]),
+            Stmt::Forbidden(opcode) => json!(["forbidden", opcode_to_json(opcode)]),
+        }
+    }
+    fn from_json(json: &Json) -> Option<Self> {
+        let value = |i: usize| Value::from_json(json.get(i)?);
+        match json.get(0)?.as_str()? {
+            "clear" => {
+                let mut srcs = vec![];
+                for src in json.get(2)?.as_array()? {
+                    srcs.push(Value::from_json(src)?);
+                }
+                Some(Stmt::Clear(value(1)?, srcs))
+            }
+            "unop" => Some(Stmt::Unop(by_name(&UNOPCODES, json.get(1)?)?, value(2)?, value(3)?)),
+            "binop" => Some(Stmt::Binop(by_name(&BINOPCODES, json.get(1)?)?, value(2)?, value(3)?, value(4)?)),
+            "undefined" => Some(Stmt::Undefined),
+            "ret" => Some(Stmt::Ret),
+            "branch" => Some(Stmt::Branch(by_name(&BRANCH_OPCODES, json.get(1)?)?, value(2)?)),
+            "call" => Some(Stmt::Call(value(1)?, json.get(2)?.as_u64()? as u8)),
+            "probestack" => Some(Stmt::ProbeStack(json.get(1)?.as_u64()?)),
+            "forbidden" => Some(Stmt::Forbidden(by_name(&FORBIDDEN_OPCODES, json.get(1)?)?)),
+            _ => None,
+        }
+    }
+}
+
+pub fn block_to_json(block: &IRBlock) -> Json {
+    let instrs: Vec<Json> = block
+        .iter()
+        .map(|(addr, stmts)| {
+            let stmts: Vec<Json> = stmts.iter().map(|stmt| stmt.to_json()).collect();
+            json!([addr, stmts])
+        })
+        .collect();
+    json!(instrs)
+}
+
+pub fn block_from_json(json: &Json) -> Option<IRBlock> {
+    let mut block = vec![];
+    for instr in json.as_array()? {
+        let mut stmts = vec![];
+        for stmt in instr.get(1)?.as_array()? {
+            stmts.push(Stmt::from_json(stmt)?);
+        }
+        block.push((instr.get(0)?.as_u64()?, stmts));
+    }
+    Some(block)
+}
+
+/// The entry state of every block an analysis reached, along with the block itself so that
+/// states inside the block can be recomputed later.
+pub fn persist_result<State: Persist>(result: &AnalysisResult<State>, irmap: &IRMap) -> Json {
+    let mut blocks: Vec<(&u64, &State)> = result.iter().collect();
+    blocks.sort_by_key(|(addr, _)| **addr);
+    let blocks: Map<String, Json> = blocks
+        .into_iter()
+        .map(|(addr, state)| {
+            let block = irmap.get(addr).map(|block| block_to_json(&block)).unwrap_or(Json::Null);
+            (format!("0x{:x}", addr), json!({"state": state.to_json(), "block": block}))
+        })
+        .collect();
+    json!(blocks)
+}
+
+fn persisted_path(dir: &str, func_name: &str) -> std::path::PathBuf {
+    Path::new(dir).join(format!("{}.json", func_name.replace('/', "_")))
+}
+
+/// Writes the persisted analyses (see `persist_result`) of one function to `<dir>/<func>.json`.
+pub fn write_persisted(
+    dir: &str,
+    func_name: &str,
+    metadata: &CompilerMetadata,
+    analyses: Vec<(&str, Json)>,
+) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let analyses: Map<String, Json> = analyses.into_iter().map(|(name, json)| (name.to_string(), json)).collect();
+    let data = json!({"func": func_name, "metadata": metadata.to_json(), "analyses": analyses});
+    fs::write(persisted_path(dir, func_name), data.to_string())
+}
+
+/// Recomputes the state right before the instruction at `addr` by replaying its block from the
+/// persisted entry state.
+pub fn replay_state<State, A>(persisted: &Json, analyzer: &A, addr: u64) -> Result<State, String>
+where
+    State: Lattice + VarState + Clone + Persist,
+    A: AbstractAnalyzer<State>,
+{
+    let blocks = persisted.as_object().ok_or("malformed analysis")?;
+    for (block_addr, entry) in blocks {
+        let block = block_from_json(&entry["block"]).ok_or_else(|| format!("malformed block {}", block_addr))?;
+        if !block.iter().any(|(instr_addr, _)| *instr_addr == addr) {
+            continue;
+        }
+        let mut state = State::from_json(&entry["state"]).ok_or_else(|| format!("malformed state for {}", block_addr))?;
+        for (instr_addr, stmts) in block.iter() {
+            if *instr_addr == addr {
+                return Ok(state);
+            }
+            for (idx, stmt) in stmts.iter().enumerate() {
+                analyzer.aexec(&mut state, stmt, &LocIdx { addr: *instr_addr, idx: idx as u32 });
+            }
+        }
+    }
+    Err(format!("no persisted block contains 0x{:x}", addr))
+}
+
+/// Answers `veriwasm query`: the compact state of `analysis` right before `addr` in `func_name`.
+pub fn query_state(dir: &str, func_name: &str, addr: u64, analysis: &str) -> Result<String, String> {
+    let path = persisted_path(dir, func_name);
+    let data = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let persisted: Json = serde_json::from_str(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let metadata = CompilerMetadata::from_json(&persisted["metadata"]).ok_or("malformed metadata")?;
+    let result = persisted["analyses"]
+        .get(analysis)
+        .ok_or_else(|| format!("{} was not persisted for {}", analysis, func_name))?;
+    let state = match analysis {
+        "heap" => replay_state(result, &HeapAnalyzer { metadata: metadata }, addr)?.to_json(),
+        "stack" => replay_state(result, &StackAnalyzer { metadata: metadata, stack_limit_cmps: Default::default() }, addr)?.to_json(),
+        _ => return Err(format!("unknown analysis {} (expected one of {:?})", analysis, PERSISTED_ANALYSES)),
+    };
+    Ok(state.to_string())
+}
+
+#[test]
+fn persist_query_test() {
+    use crate::analyses::run_worklist;
+    use crate::analyses::test_cfgs::SingleBlock;
+    use crate::lattices::heaplattice::HeapValueLattice;
+
+    let metadata = CompilerMetadata::for_test(Compiler::Lucet);
+    let rax = Value::Reg(0, ValSize::Size64);
+    let rdi = Value::Reg(7, ValSize::Size64);
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let slot = Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64)));
+    let imm = Value::Imm(ImmType::Signed, ValSize::Size64, 8);
+    // sub rsp, 8; mov rax, rdi; mov [rsp], rax; add rsp, 8; ret
+    let mut irmap = IRMap::new();
+    irmap.insert(0x10, vec![
+        (0x10, vec![Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), imm.clone())]),
+        (0x14, vec![Stmt::Unop(Unopcode::Mov, rax.clone(), rdi)]),
+        (0x17, vec![Stmt::Unop(Unopcode::Mov, slot, rax)]),
+        (0x1b, vec![Stmt::Binop(Binopcode::Add, rsp.clone(), rsp, imm)]),
+        (0x1f, vec![Stmt::Ret]),
+    ]);
+    let analyzer = HeapAnalyzer { metadata: metadata.clone() };
+    let result = run_worklist(&SingleBlock(0x10), &irmap, &analyzer);
+
+    let dir = std::env::temp_dir().join(format!("veriwasm-persist-{}", std::process::id()));
+    let dir = dir.to_str().unwrap();
+    write_persisted(dir, "guest_func_0", &metadata, vec![("heap", persist_result(&result, &irmap))]).unwrap();
+
+    // what the live analysis reports right before `add rsp, 8`
+    let mut live = result.get(&0x10).unwrap().clone();
+    for (addr, stmts) in irmap.get(&0x10).unwrap().iter().take(3) {
+        for (idx, stmt) in stmts.iter().enumerate() {
+            analyzer.aexec(&mut live, stmt, &LocIdx { addr: *addr, idx: idx as u32 });
+        }
+    }
+    assert_eq!(live.regs.rax, HeapValueLattice::new(HeapValue::HeapBase));
+
+    let printed = query_state(dir, "guest_func_0", 0x1b, "heap").unwrap();
+    assert_eq!(printed, live.to_json().to_string());
+    assert!(printed.contains("\"rax\":\"HeapBase\""));
+    assert_eq!(query_state(dir, "guest_func_0", 0x14, "heap").unwrap().contains("\"rax\""), false);
+    assert!(query_state(dir, "guest_func_0", 0x99, "heap").is_err());
+    assert!(query_state(dir, "guest_func_0", 0x1b, "stack").is_err());
+    fs::remove_dir_all(dir).unwrap();
+
+    // the analysis options are replayed with the states
+    let mut coarse = metadata.clone();
+    coarse.options = AnalysisOptions::parse("bound_32bit=false,stack_tracking=false").unwrap();
+    assert_eq!(CompilerMetadata::from_json(&coarse.to_json()).unwrap().options, coarse.options);
+    // and so are the funcref tables
+    let mut tables = metadata.clone();
+    tables.wamr_tables = Some(WamrTables { descriptors: 0x1c0, count: 2, size_field: 0, base_field: 8 });
+    assert_eq!(CompilerMetadata::from_json(&tables.to_json()).unwrap().wamr_tables, tables.wamr_tables);
+    assert_eq!(CompilerMetadata::from_json(&metadata.to_json()).unwrap().wamr_tables, None);
+    // and the read-only data rip-relative loads were checked against
+    let mut rodata = metadata.clone();
+    rodata.readonly_data = vec![(0x2000, 0x2040)];
+    assert_eq!(CompilerMetadata::from_json(&rodata.to_json()).unwrap().readonly_data, rodata.readonly_data);
+}
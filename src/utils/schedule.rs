@@ -0,0 +1,187 @@
+use crate::analyses::Visit;
+use std::collections::BTreeMap;
+use std::fs;
+
+/*
+ * Worklist schedules (`--record-schedule <path>`, `--replay-schedule <path>`): the order the
+ * worklist of each analysis of each function visited its blocks in. The fixed point an analysis
+ * reaches shouldn't depend on that order, but when a result does (a lattice bug, or a meet losing
+ * more on one order than another) reproducing it takes the exact order of the run that showed it.
+ *
+ * The file is the magic `VWSCHED1`, then an entry per analysis of each function: the function's
+ * name (a u32 length, then UTF-8), the analysis's name (a u8 length, then UTF-8), the number of
+ * visits (u32), and each visit as its block's address (u64), its visit number (u32), and whether
+ * it changed a successor's state (a u8, 0 or 1). Integers are little-endian.
+ */
+
+const SCHEDULE_MAGIC: &[u8] = b"VWSCHED1";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schedules {
+    // (function, analysis) -> the visits of its worklist, in order
+    entries: BTreeMap<(String, String), Vec<Visit>>,
+}
+
+// Reads the fields of a schedule file in turn, None past its end
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Some(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Some(u64::from_le_bytes(buf))
+    }
+
+    fn string(&mut self, len: usize) -> Option<String> {
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn entry(&mut self) -> Option<((String, String), Vec<Visit>)> {
+        let func_len = self.u32()? as usize;
+        let func = self.string(func_len)?;
+        let analysis_len = self.u8()? as usize;
+        let analysis = self.string(analysis_len)?;
+        let count = self.u32()?;
+        let mut visits = vec![];
+        for _ in 0..count {
+            let addr = self.u64()?;
+            let visit = self.u32()?;
+            let changed = match self.u8()? {
+                0 => false,
+                1 => true,
+                _ => return None,
+            };
+            if visit == 0 {
+                return None;
+            }
+            visits.push(Visit { addr: addr, visit: visit, changed: changed });
+        }
+        Some(((func, analysis), visits))
+    }
+}
+
+impl Schedules {
+    /// The visits the worklist of `analysis` made analyzing `func_name`, if recorded.
+    pub fn get(&self, func_name: &str, analysis: &str) -> Option<&Vec<Visit>> {
+        self.entries.get(&(func_name.to_string(), analysis.to_string()))
+    }
+
+    pub fn insert(&mut self, func_name: &str, analysis: &str, visits: Vec<Visit>) {
+        self.entries.insert((func_name.to_string(), analysis.to_string()), visits);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = SCHEDULE_MAGIC.to_vec();
+        for ((func, analysis), visits) in self.entries.iter() {
+            data.extend_from_slice(&(func.len() as u32).to_le_bytes());
+            data.extend_from_slice(func.as_bytes());
+            data.push(analysis.len() as u8);
+            data.extend_from_slice(analysis.as_bytes());
+            data.extend_from_slice(&(visits.len() as u32).to_le_bytes());
+            for visit in visits.iter() {
+                data.extend_from_slice(&visit.addr.to_le_bytes());
+                data.extend_from_slice(&visit.visit.to_le_bytes());
+                data.push(visit.changed as u8);
+            }
+        }
+        data
+    }
+
+    /// The schedules in `data`, or an error if it isn't a schedule file at all. A file that is
+    /// truncated or corrupt part way keeps the schedules before the damage, with a warning saying
+    /// so: the functions whose schedules were lost are analyzed in the usual order.
+    pub fn from_bytes(data: &[u8]) -> Result<(Schedules, Option<String>), String> {
+        if !data.starts_with(SCHEDULE_MAGIC) {
+            return Err("not a worklist schedule".to_string());
+        }
+        let mut reader = Reader { data: data, pos: SCHEDULE_MAGIC.len() };
+        let mut schedules = Schedules::default();
+        while reader.pos < data.len() {
+            let start = reader.pos;
+            match reader.entry() {
+                Some(((func, analysis), visits)) => {
+                    schedules.entries.insert((func, analysis), visits);
+                }
+                None => {
+                    let warning = format!(
+                        "truncated or corrupt at byte {}, keeping the {} schedules before it",
+                        start,
+                        schedules.len()
+                    );
+                    return Ok((schedules, Some(warning)));
+                }
+            }
+        }
+        Ok((schedules, None))
+    }
+
+    pub fn load(path: &str) -> Result<(Schedules, Option<String>), String> {
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Schedules::from_bytes(&data).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+}
+
+#[test]
+fn schedule_file_test() {
+    let visit = |addr: u64, visit: u32, changed: bool| Visit { addr: addr, visit: visit, changed: changed };
+    let mut schedules = Schedules::default();
+    schedules.insert("guest_func_0", "heap", vec![visit(0x10, 1, true), visit(0x20, 1, true), visit(0x10, 2, false)]);
+    schedules.insert("guest_func_0", "stack", vec![visit(0x10, 1, false)]);
+    schedules.insert("guest_func_1", "heap", vec![]);
+    let data = schedules.to_bytes();
+    assert_eq!(Schedules::from_bytes(&data), Ok((schedules.clone(), None)));
+    assert_eq!(schedules.get("guest_func_0", "stack"), Some(&vec![visit(0x10, 1, false)]));
+    assert_eq!(schedules.get("guest_func_0", "call"), None);
+
+    // a file cut short keeps the entries before the cut
+    let (truncated, warning) = Schedules::from_bytes(&data[..data.len() - 30]).unwrap();
+    assert_eq!(truncated.len(), 1);
+    assert!(truncated.get("guest_func_0", "heap").is_some());
+    assert!(warning.unwrap().starts_with("truncated or corrupt"));
+    // as does one whose bytes were garbled
+    let mut garbled = data.clone();
+    let changed_byte = SCHEDULE_MAGIC.len() + 4 + "guest_func_0".len() + 1 + "heap".len() + 4 + 12;
+    assert_eq!(garbled[changed_byte], 1);
+    garbled[changed_byte] = 7;
+    let (kept, warning) = Schedules::from_bytes(&garbled).unwrap();
+    assert_eq!(kept.len(), 0);
+    assert!(warning.is_some());
+    // a huge length doesn't read past the end
+    let mut huge = SCHEDULE_MAGIC.to_vec();
+    huge.extend_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(Schedules::from_bytes(&huge).unwrap().0.len(), 0);
+    // and anything else isn't a schedule
+    assert!(Schedules::from_bytes(b"{\"heap\": []}").is_err());
+    assert!(Schedules::from_bytes(&[]).is_err());
+}
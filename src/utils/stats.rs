@@ -0,0 +1,565 @@
+use crate::analyses::WorklistStats;
+use serde_json::{json, Value as Json};
+use std::collections::BTreeMap;
+use std::fs;
+
+// Version 1 (no "version" key) stored each function as an array:
+//   [name, blocks, cfg_time, heap_time, stack_time, call_time, direct_calls]
+// Version 2 stores named fields and the function's address.
+pub const STATS_VERSION: u64 = 2;
+
+// Functions faster than this (in seconds) are never flagged as regressed, it's all noise
+const MIN_REGRESSION_TIME: f64 = 0.01;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionStats {
+    pub name: String,
+    pub addr: Option<u64>,
+    pub blocks: usize,
+    pub cfg_time: f64,
+    pub heap_time: f64,
+    pub stack_time: f64,
+    pub call_time: f64,
+    pub direct_calls: usize,
+    pub indirect_calls: usize,
+    // only counted when the stats are written out (`-o`)
+    pub worklist: WorklistSummary,
+}
+
+/// The worklist iterations of the analyses run on a function, for finding the functions whose
+/// loops take long to reach a fixed point.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorklistSummary {
+    pub iterations: usize,
+    pub meets: usize,
+    // the most any one block was analyzed, by any analysis
+    pub max_block_visits: usize,
+    // the wall time of each analysis's worklist, in seconds
+    pub analysis_times: BTreeMap<String, f64>,
+}
+
+impl WorklistSummary {
+    /// Adds a run of the analysis `name`, which took `time` seconds.
+    pub fn add(&mut self, name: &str, stats: &WorklistStats, time: f64) {
+        self.iterations += stats.iterations;
+        self.meets += stats.meets;
+        self.max_block_visits = self.max_block_visits.max(stats.max_block_visits());
+        *self.analysis_times.entry(name.to_string()).or_insert(0.0) += time;
+    }
+
+    // the summary of several functions
+    fn merge(&mut self, other: &WorklistSummary) {
+        self.iterations += other.iterations;
+        self.meets += other.meets;
+        self.max_block_visits = self.max_block_visits.max(other.max_block_visits);
+        for (name, time) in other.analysis_times.iter() {
+            *self.analysis_times.entry(name.clone()).or_insert(0.0) += time;
+        }
+    }
+
+    fn from_json(json: &Json) -> WorklistSummary {
+        let count = |key: &str| json.get(key).and_then(|n| n.as_u64()).unwrap_or(0) as usize;
+        WorklistSummary {
+            iterations: count("worklist_iterations"),
+            meets: count("worklist_meets"),
+            max_block_visits: count("max_block_visits"),
+            analysis_times: json
+                .get("analysis_times")
+                .and_then(|times| times.as_object())
+                .map(|times| times.iter().filter_map(|(name, time)| Some((name.clone(), time.as_f64()?))).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl FunctionStats {
+    pub fn total_time(&self) -> f64 {
+        self.cfg_time + self.heap_time + self.stack_time + self.call_time
+    }
+
+    pub fn to_json(&self) -> Json {
+        json!({
+            "name": self.name,
+            "addr": self.addr,
+            "blocks": self.blocks,
+            "cfg_time": self.cfg_time,
+            "heap_time": self.heap_time,
+            "stack_time": self.stack_time,
+            "call_time": self.call_time,
+            "direct_calls": self.direct_calls,
+            "indirect_calls": self.indirect_calls,
+            "worklist_iterations": self.worklist.iterations,
+            "worklist_meets": self.worklist.meets,
+            "max_block_visits": self.worklist.max_block_visits,
+            "analysis_times": self.worklist.analysis_times,
+        })
+    }
+
+    fn from_json(json: &Json, version: u64) -> Option<FunctionStats> {
+        if version == 1 {
+            let fields = json.as_array()?;
+            return Some(FunctionStats {
+                name: fields.get(0)?.as_str()?.to_string(),
+                addr: None,
+                blocks: fields.get(1)?.as_u64()? as usize,
+                cfg_time: fields.get(2)?.as_f64()?,
+                heap_time: fields.get(3)?.as_f64()?,
+                stack_time: fields.get(4)?.as_f64()?,
+                call_time: fields.get(5)?.as_f64()?,
+                direct_calls: fields.get(6).and_then(|n| n.as_u64()).unwrap_or(0) as usize,
+                indirect_calls: 0,
+                worklist: Default::default(),
+            });
+        }
+        Some(FunctionStats {
+            name: json.get("name")?.as_str()?.to_string(),
+            addr: json.get("addr").and_then(|a| a.as_u64()),
+            blocks: json.get("blocks")?.as_u64()? as usize,
+            cfg_time: json.get("cfg_time")?.as_f64()?,
+            heap_time: json.get("heap_time")?.as_f64()?,
+            stack_time: json.get("stack_time")?.as_f64()?,
+            call_time: json.get("call_time")?.as_f64()?,
+            direct_calls: json.get("direct_calls").and_then(|n| n.as_u64()).unwrap_or(0) as usize,
+            indirect_calls: json.get("indirect_calls").and_then(|n| n.as_u64()).unwrap_or(0) as usize,
+            worklist: WorklistSummary::from_json(json),
+        })
+    }
+}
+
+/// Reads the functions of a stats file, in the order they were verified (address order).
+pub fn parse_stats(data: &str) -> Result<Vec<FunctionStats>, String> {
+    let json: Json = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    let version = json.get("version").and_then(|v| v.as_u64()).unwrap_or(1);
+    if version > STATS_VERSION {
+        return Err(format!("stats version {} is newer than this veriwasm ({})", version, STATS_VERSION));
+    }
+    let funcs = json.get("functions").and_then(|f| f.as_array()).ok_or("no functions in stats")?;
+    funcs
+        .iter()
+        .map(|f| FunctionStats::from_json(f, version).ok_or(format!("malformed function entry: {}", f)))
+        .collect()
+}
+
+pub fn load_stats(path: &str) -> Result<Vec<FunctionStats>, String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse_stats(&data).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// How the stats file (`-o`) is written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StatsFormat {
+    // the versioned JSON document, read back by --compare-stats
+    Json,
+    // one row per function under a header row
+    Csv,
+    // the per-function lines of the log
+    Text,
+    // the version 1 array-per-function JSON, kept for one release for older scripts
+    Legacy,
+}
+
+impl std::str::FromStr for StatsFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(StatsFormat::Json),
+            "csv" => Ok(StatsFormat::Csv),
+            "text" => Ok(StatsFormat::Text),
+            "legacy" => Ok(StatsFormat::Legacy),
+            _ => Err(format!("unknown stats format {} (expected json, csv, text or legacy)", s)),
+        }
+    }
+}
+
+/// Sums every function into a single summary record named `total`.
+pub fn stats_totals(funcs: &Vec<FunctionStats>) -> FunctionStats {
+    funcs.iter().fold(
+        FunctionStats {
+            name: "total".to_string(),
+            addr: None,
+            blocks: 0,
+            cfg_time: 0.0,
+            heap_time: 0.0,
+            stack_time: 0.0,
+            call_time: 0.0,
+            direct_calls: 0,
+            indirect_calls: 0,
+            worklist: Default::default(),
+        },
+        |mut total, f| {
+            total.blocks += f.blocks;
+            total.cfg_time += f.cfg_time;
+            total.heap_time += f.heap_time;
+            total.stack_time += f.stack_time;
+            total.call_time += f.call_time;
+            total.direct_calls += f.direct_calls;
+            total.indirect_calls += f.indirect_calls;
+            total.worklist.merge(&f.worklist);
+            total
+        },
+    )
+}
+
+pub fn stats_csv(funcs: &Vec<FunctionStats>) -> String {
+    let mut lines = vec![
+        "name,addr,blocks,cfg_time,heap_time,stack_time,call_time,direct_calls,indirect_calls,worklist_iterations,max_block_visits"
+            .to_string(),
+    ];
+    for f in funcs.iter().chain(std::iter::once(&stats_totals(funcs))) {
+        lines.push(format!(
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            f.name,
+            f.addr.map(|a| format!("0x{:x}", a)).unwrap_or_default(),
+            f.blocks,
+            f.cfg_time,
+            f.heap_time,
+            f.stack_time,
+            f.call_time,
+            f.direct_calls,
+            f.indirect_calls,
+            f.worklist.iterations,
+            f.worklist.max_block_visits
+        ));
+    }
+    lines.join("\n") + "\n"
+}
+
+pub fn stats_text(funcs: &Vec<FunctionStats>) -> String {
+    let mut lines = Vec::new();
+    for f in funcs.iter().chain(std::iter::once(&stats_totals(funcs))) {
+        lines.push(format!(
+            "{}: {} blocks, {:.3}s (CFG: {:.3}s Heap: {:.3}s Stack: {:.3}s Call: {:.3}s)",
+            f.name,
+            f.blocks,
+            f.total_time(),
+            f.cfg_time,
+            f.heap_time,
+            f.stack_time,
+            f.call_time
+        ));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// The named (current version) document of `funcs`: each function and the totals. The stats file
+/// of a run adds its other sections to it.
+pub fn stats_named(funcs: &Vec<FunctionStats>) -> Json {
+    let functions: Vec<Json> = funcs.iter().map(|f| f.to_json()).collect();
+    json!({
+        "version": STATS_VERSION,
+        "functions": functions,
+        "totals": stats_totals(funcs).to_json(),
+    })
+}
+
+/// The version 1 layout: no version key, and one `[name, blocks, cfg_time, heap_time, stack_time,
+/// call_time, direct_calls]` array per function. The totals aren't included, so older readers
+/// don't mistake them for a function.
+pub fn stats_legacy(funcs: &Vec<FunctionStats>) -> Json {
+    let functions: Vec<Json> = funcs
+        .iter()
+        .map(|f| json!([f.name, f.blocks, f.cfg_time, f.heap_time, f.stack_time, f.call_time, f.direct_calls]))
+        .collect();
+    json!({ "functions": functions })
+}
+
+// Revisions before the version key didn't all write the arrays in the same order (and
+// graph_stats.py reads the second column as a time), so a converted file says how it was read.
+pub const CONVERTED_STATS_NOTE: &str = "converted from a stats file without a version, whose arrays were read as \
+    [name, blocks, cfg_time, heap_time, stack_time, call_time, direct_calls]; files from older revisions may have \
+    had another column order, so check the block counts and times before comparing them. Addresses, indirect calls \
+    and worklist counts were not recorded.";
+
+/// The named document of an old (version 1) stats file, with a note on how its arrays were read.
+pub fn convert_stats(data: &str) -> Result<Json, String> {
+    let json: Json = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    if let Some(version) = json.get("version").and_then(|v| v.as_u64()) {
+        return Err(format!("already a version {} stats file", version));
+    }
+    let mut converted = stats_named(&parse_stats(data)?);
+    converted["converted_from"] = json!(1);
+    converted["note"] = json!(CONVERTED_STATS_NOTE);
+    Ok(converted)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionDelta {
+    pub old: FunctionStats,
+    pub new: FunctionStats,
+    // matched by block count and position rather than by name
+    pub renamed: bool,
+    pub time_regressed: bool,
+    pub blocks_changed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    pub factor: f64,
+    pub matched: Vec<FunctionDelta>,
+    // functions only in the new run, and only in the old one
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+fn phase_totals(funcs: &Vec<&FunctionStats>) -> (usize, f64, f64, f64, f64) {
+    funcs.iter().fold((0, 0.0, 0.0, 0.0, 0.0), |(b, c, h, s, k), f| {
+        (b + f.blocks, c + f.cfg_time, h + f.heap_time, s + f.stack_time, k + f.call_time)
+    })
+}
+
+fn outside_factor(old: f64, new: f64, factor: f64) -> bool {
+    new > old * factor || old > new * factor
+}
+
+fn delta(old: &FunctionStats, new: &FunctionStats, renamed: bool, factor: f64) -> FunctionDelta {
+    FunctionDelta {
+        old: old.clone(),
+        new: new.clone(),
+        renamed: renamed,
+        time_regressed: new.total_time() > MIN_REGRESSION_TIME && new.total_time() > old.total_time() * factor,
+        blocks_changed: outside_factor(old.blocks as f64, new.blocks as f64, factor),
+    }
+}
+
+/// Matches the functions of two runs, by name where possible. The leftovers are matched up in
+/// address order when their block counts agree (renamed functions); the rest are reported as
+/// added or removed.
+pub fn compare_stats(old: &Vec<FunctionStats>, new: &Vec<FunctionStats>, factor: f64) -> Comparison {
+    let mut matched = Vec::new();
+    let mut old_left: Vec<&FunctionStats> = Vec::new();
+    for o in old {
+        match new.iter().find(|n| n.name == o.name) {
+            Some(n) => matched.push(delta(o, n, false, factor)),
+            None => old_left.push(o),
+        }
+    }
+    let mut added = Vec::new();
+    let mut next_old = 0;
+    for n in new.iter().filter(|n| !old.iter().any(|o| o.name == n.name)) {
+        match old_left[next_old..].iter().position(|o| o.blocks == n.blocks) {
+            Some(pos) => {
+                let o = old_left.remove(next_old + pos);
+                matched.push(delta(o, n, true, factor));
+                // matching keeps the address order, so anything skipped over stays unmatched
+                next_old += pos;
+            }
+            None => added.push(n.name.clone()),
+        }
+    }
+    Comparison {
+        factor: factor,
+        matched: matched,
+        added: added,
+        removed: old_left.iter().map(|o| o.name.clone()).collect(),
+    }
+}
+
+impl Comparison {
+    pub fn regressions(&self) -> Vec<&FunctionDelta> {
+        self.matched.iter().filter(|d| d.time_regressed).collect()
+    }
+
+    pub fn report(&self) -> String {
+        let mut lines = Vec::new();
+        let old: Vec<&FunctionStats> = self.matched.iter().map(|d| &d.old).collect();
+        let new: Vec<&FunctionStats> = self.matched.iter().map(|d| &d.new).collect();
+        let (ob, oc, oh, os, ok) = phase_totals(&old);
+        let (nb, nc, nh, ns, nk) = phase_totals(&new);
+        lines.push(format!(
+            "Compared {} functions: blocks {} -> {}, CFG {:.3}s -> {:.3}s, Heap {:.3}s -> {:.3}s, Stack {:.3}s -> {:.3}s, Call {:.3}s -> {:.3}s",
+            self.matched.len(), ob, nb, oc, nc, oh, nh, os, ns, ok, nk
+        ));
+        for d in &self.matched {
+            let mut notes = Vec::new();
+            if d.renamed {
+                notes.push(format!("renamed from {}", d.old.name));
+            }
+            if d.time_regressed {
+                notes.push(format!("time regressed more than {}x", self.factor));
+            }
+            if d.blocks_changed {
+                notes.push(format!("block count changed more than {}x", self.factor));
+            }
+            if !notes.is_empty() {
+                lines.push(format!(
+                    "  {}: {} -> {} blocks, {:.3}s -> {:.3}s ({})",
+                    d.new.name, d.old.blocks, d.new.blocks, d.old.total_time(), d.new.total_time(), notes.join(", ")
+                ));
+            }
+        }
+        for name in &self.added {
+            lines.push(format!("  unmatched, new: {}", name));
+        }
+        for name in &self.removed {
+            lines.push(format!("  unmatched, removed: {}", name));
+        }
+        lines.join("\n")
+    }
+
+    pub fn to_json(&self) -> Json {
+        let functions: Vec<Json> = self
+            .matched
+            .iter()
+            .map(|d| {
+                json!({
+                    "name": d.new.name,
+                    "old_name": d.old.name,
+                    "renamed": d.renamed,
+                    "blocks": [d.old.blocks, d.new.blocks],
+                    "cfg_time": [d.old.cfg_time, d.new.cfg_time],
+                    "heap_time": [d.old.heap_time, d.new.heap_time],
+                    "stack_time": [d.old.stack_time, d.new.stack_time],
+                    "call_time": [d.old.call_time, d.new.call_time],
+                    "time_regressed": d.time_regressed,
+                    "blocks_changed": d.blocks_changed,
+                })
+            })
+            .collect();
+        json!({
+            "factor": self.factor,
+            "functions": functions,
+            "unmatched": {
+                "new": self.added,
+                "removed": self.removed,
+            },
+        })
+    }
+}
+
+#[test]
+fn compare_stats_test() {
+    // an old (version 1) stats file and a new one
+    let old = parse_stats(
+        r#"{"functions": [
+            ["guest_func_0", 10, 0.01, 0.10, 0.10, 0.0, 1],
+            ["guest_func_1", 20, 0.02, 1.00, 1.00, 0.0, 0],
+            ["guest_func_2", 30, 0.01, 0.10, 0.10, 0.0, 2],
+            ["guest_func_3", 5, 0.01, 0.01, 0.01, 0.0, 0],
+            ["guest_func_4", 7, 0.01, 0.01, 0.01, 0.0, 0]
+        ]}"#,
+    )
+    .unwrap();
+    let func = |name: &str, addr: u64, blocks: usize, time: f64| {
+        json!({"name": name, "addr": addr, "blocks": blocks, "cfg_time": 0.01, "heap_time": time,
+               "stack_time": time, "call_time": 0.0, "direct_calls": 0})
+    };
+    let new = parse_stats(
+        &json!({
+            "version": STATS_VERSION,
+            "functions": [
+                // regressed
+                func("guest_func_0", 0x100, 10, 1.0),
+                // improved
+                func("guest_func_1", 0x200, 20, 0.1),
+                // renamed, same blocks
+                func("renamed_func_2", 0x300, 30, 0.1),
+                // much bigger than before, but verifies just as fast
+                func("guest_func_4", 0x500, 70, 0.01),
+                func("guest_func_5", 0x600, 3, 0.01),
+            ],
+        })
+        .to_string(),
+    )
+    .unwrap();
+    assert_eq!(new[2].addr, Some(0x300));
+
+    let comparison = compare_stats(&old, &new, 2.0);
+    let find = |name: &str| comparison.matched.iter().find(|d| d.new.name == name).unwrap();
+    assert!(find("guest_func_0").time_regressed);
+    assert!(!find("guest_func_1").time_regressed);
+    assert!(find("renamed_func_2").renamed);
+    assert_eq!(find("renamed_func_2").old.name, "guest_func_2");
+    assert!(find("guest_func_4").blocks_changed && !find("guest_func_4").time_regressed);
+    assert_eq!(comparison.regressions().len(), 1);
+    assert_eq!(comparison.added, vec!["guest_func_5".to_string()]);
+    assert_eq!(comparison.removed, vec!["guest_func_3".to_string()]);
+    assert!(comparison.report().contains("unmatched, removed: guest_func_3"));
+    assert_eq!(comparison.to_json()["unmatched"]["new"][0], "guest_func_5");
+
+    assert!(parse_stats(r#"{"version": 99, "functions": []}"#).is_err());
+}
+
+#[test]
+fn stats_format_test() {
+    let func = |name: &str, addr: u64, blocks: usize| FunctionStats {
+        name: name.to_string(),
+        addr: Some(addr),
+        blocks: blocks,
+        cfg_time: 0.5,
+        heap_time: 1.0,
+        stack_time: 0.25,
+        call_time: 0.25,
+        direct_calls: 1,
+        indirect_calls: 0,
+        worklist: WorklistSummary {
+            iterations: blocks * 2,
+            meets: blocks,
+            max_block_visits: blocks / 5,
+            analysis_times: vec![("heap".to_string(), 0.5), ("stack".to_string(), 0.125)].into_iter().collect(),
+        },
+    };
+    let funcs = vec![func("guest_func_0", 0x100, 10), func("guest_func_1", 0x200, 20)];
+    assert_eq!("csv".parse::<StatsFormat>(), Ok(StatsFormat::Csv));
+    assert!("yaml".parse::<StatsFormat>().is_err());
+
+    let total = stats_totals(&funcs);
+    assert_eq!((total.blocks, total.total_time(), total.direct_calls), (30, 4.0, 2));
+
+    let csv = stats_csv(&funcs);
+    let rows: Vec<&str> = csv.lines().collect();
+    assert_eq!(rows.len(), 4);
+    assert!(rows[0].starts_with("name,addr,blocks,"));
+    assert_eq!(rows[1], "guest_func_0,0x100,10,0.5,1,0.25,0.25,1,0,20,2");
+    assert_eq!(rows[3], "total,,30,1,2,0.5,0.5,2,0,60,4");
+    assert_eq!(total.worklist.analysis_times["heap"], 1.0);
+
+    // the worklist counts survive the JSON
+    let json = json!({"version": STATS_VERSION, "functions": [funcs[1].to_json()]});
+    assert_eq!(parse_stats(&json.to_string()).unwrap(), vec![funcs[1].clone()]);
+
+    assert!(stats_text(&funcs).lines().last().unwrap().starts_with("total: 30 blocks, 4.000s"));
+
+    // the legacy layout is still read as a version 1 stats file
+    let legacy = parse_stats(&stats_legacy(&funcs).to_string()).unwrap();
+    assert_eq!(legacy.len(), 2);
+    assert_eq!((legacy[1].name.as_str(), legacy[1].blocks, legacy[1].addr), ("guest_func_1", 20, None));
+}
+
+#[test]
+fn stats_golden_test() {
+    let func = |name: &str, addr: u64, blocks: usize| FunctionStats {
+        name: name.to_string(),
+        addr: Some(addr),
+        blocks: blocks,
+        cfg_time: 0.5,
+        heap_time: 1.0,
+        stack_time: 0.25,
+        call_time: 0.25,
+        direct_calls: 1,
+        indirect_calls: 0,
+        worklist: WorklistSummary {
+            iterations: blocks * 2,
+            meets: blocks,
+            max_block_visits: blocks / 5,
+            analysis_times: vec![("heap".to_string(), 0.5), ("stack".to_string(), 0.125)].into_iter().collect(),
+        },
+    };
+    let funcs = vec![func("guest_func_0", 0x100, 10), func("guest_func_1", 0x200, 20)];
+    let legacy = include_str!("../../tests/stats/legacy.json").trim_end();
+    let named = include_str!("../../tests/stats/named.json").trim_end();
+    let converted = include_str!("../../tests/stats/converted.json").trim_end();
+
+    // both layouts are written from the same records, byte for byte as before
+    assert_eq!(stats_legacy(&funcs).to_string(), legacy);
+    assert_eq!(stats_named(&funcs).to_string(), named);
+
+    // converting keeps what the old layout recorded, and only that
+    let document = convert_stats(legacy).unwrap();
+    assert_eq!(document.to_string(), converted);
+    let read_back = parse_stats(converted).unwrap();
+    assert_eq!(read_back, parse_stats(legacy).unwrap());
+    assert_eq!((read_back[0].addr, read_back[0].worklist.iterations), (None, 0));
+    assert_eq!(stats_legacy(&read_back).to_string(), legacy);
+
+    assert!(convert_stats(named).is_err());
+}
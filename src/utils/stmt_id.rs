@@ -0,0 +1,187 @@
+use crate::lattices::reachingdefslattice::LocIdx;
+use crate::utils::ir::{IRMap, Stmt, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/*
+ * Stable names for lifted statements.
+ *
+ * Internally a statement is identified by its LocIdx, i.e. (instruction address, position in the
+ * statements lifted from that instruction). The position shifts whenever the lifter adds or drops
+ * an auxiliary statement, so anything stored outside a single run (counterexamples, exported
+ * conditions, replay bundles) should refer to statements by StmtId instead: the instruction
+ * address, the statement's role, and its ordinal among the statements with that role.
+ */
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum StmtRole {
+    // the statement that translates the instruction itself
+    Primary,
+    // a clear of zf after an instruction that writes the flags
+    FlagClear,
+    // further steps of a multi-statement lowering (e.g. the store of a push)
+    Expansion,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct StmtId {
+    pub addr: u64,
+    pub role: StmtRole,
+    pub ordinal: u32,
+}
+
+fn is_flag_clear(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Clear(Value::Reg(16, _), _) => true,
+        _ => false,
+    }
+}
+
+/// The roles of the statements lifted from one instruction: zf clears are auxiliary, the first
+/// other statement is the primary translation and the rest are expansion steps.
+pub fn stmt_roles(stmts: &[Stmt]) -> Vec<StmtRole> {
+    let mut seen_primary = false;
+    stmts
+        .iter()
+        .map(|stmt| {
+            if is_flag_clear(stmt) {
+                StmtRole::FlagClear
+            } else if !seen_primary {
+                seen_primary = true;
+                StmtRole::Primary
+            } else {
+                StmtRole::Expansion
+            }
+        })
+        .collect()
+}
+
+/// The stable id of statement `idx` of the statements lifted from the instruction at `addr`.
+pub fn stable_id(stmts: &[Stmt], addr: u64, idx: u32) -> Option<StmtId> {
+    let roles = stmt_roles(stmts);
+    let role = *roles.get(idx as usize)?;
+    let ordinal = roles[..idx as usize].iter().filter(|r| **r == role).count() as u32;
+    Some(StmtId {
+        addr: addr,
+        role: role,
+        ordinal: ordinal,
+    })
+}
+
+/// The position of the statement named by `id` among the statements lifted from its instruction.
+pub fn resolve_id(stmts: &[Stmt], id: &StmtId) -> Option<u32> {
+    stmt_roles(stmts)
+        .iter()
+        .enumerate()
+        .filter(|(_, role)| **role == id.role)
+        .nth(id.ordinal as usize)
+        .map(|(idx, _)| idx as u32)
+}
+
+fn instruction_stmts(irmap: &IRMap, addr: u64) -> Option<Vec<Stmt>> {
+    irmap
+        .iter()
+        .find_map(|(_, block)| block.iter().find(|(instr_addr, _)| *instr_addr == addr).map(|(_, stmts)| stmts.clone()))
+}
+
+pub fn loc_to_stable(irmap: &IRMap, loc: &LocIdx) -> Option<StmtId> {
+    stable_id(&instruction_stmts(irmap, loc.addr)?, loc.addr, loc.idx)
+}
+
+pub fn stable_to_loc(irmap: &IRMap, id: &StmtId) -> Option<LocIdx> {
+    let idx = resolve_id(&instruction_stmts(irmap, id.addr)?, id)?;
+    Some(LocIdx {
+        addr: id.addr,
+        idx: idx,
+    })
+}
+
+impl fmt::Display for StmtRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            StmtRole::Primary => "primary",
+            StmtRole::FlagClear => "flags",
+            StmtRole::Expansion => "step",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// 0x<addr>:<role>.<ordinal>, e.g. 0x1a2b:step.0
+impl fmt::Display for StmtId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:x}:{}.{}", self.addr, self.role, self.ordinal)
+    }
+}
+
+impl FromStr for StmtId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StmtId, String> {
+        let malformed = || format!("malformed statement id: {}", s);
+        let mut parts = s.splitn(2, ':');
+        let addr = parts.next().ok_or_else(malformed)?;
+        let addr = u64::from_str_radix(addr.trim_start_matches("0x"), 16).map_err(|_| malformed())?;
+        let mut parts = parts.next().ok_or_else(malformed)?.splitn(2, '.');
+        let role = match parts.next() {
+            Some("primary") => StmtRole::Primary,
+            Some("flags") => StmtRole::FlagClear,
+            Some("step") => StmtRole::Expansion,
+            _ => return Err(malformed()),
+        };
+        let ordinal = parts.next().ok_or_else(malformed)?.parse().map_err(|_| malformed())?;
+        Ok(StmtId {
+            addr: addr,
+            role: role,
+            ordinal: ordinal,
+        })
+    }
+}
+
+#[test]
+fn stmt_id_test() {
+    use crate::utils::ir::{mk_value_i64, Binopcode, MemArg, MemArgs, Unopcode, ValSize};
+
+    let rsp = Value::Reg(4, ValSize::Size64);
+    let zf_clear = || Stmt::Clear(Value::Reg(16, ValSize::Size8), vec![]);
+    // push rax, as the lifter lowers it
+    let push = vec![
+        Stmt::Binop(Binopcode::Sub, rsp.clone(), rsp.clone(), mk_value_i64(8)),
+        Stmt::Unop(
+            Unopcode::Mov,
+            Value::Mem(ValSize::Size64, MemArgs::Mem1Arg(MemArg::Reg(4, ValSize::Size64))),
+            Value::Reg(0, ValSize::Size64),
+        ),
+    ];
+    // add rax, rbx
+    let add = vec![
+        Stmt::Binop(Binopcode::Add, Value::Reg(0, ValSize::Size64), Value::Reg(0, ValSize::Size64), Value::Reg(3, ValSize::Size64)),
+        zf_clear(),
+    ];
+    let mut irmap = IRMap::new();
+    irmap.insert(0x10, vec![(0x10, push.clone()), (0x11, add.clone())]);
+
+    let ids: Vec<StmtId> = (0..2).map(|idx| loc_to_stable(&irmap, &LocIdx { addr: 0x11, idx: idx }).unwrap()).collect();
+    assert_eq!(ids[0], StmtId { addr: 0x11, role: StmtRole::Primary, ordinal: 0 });
+    assert_eq!(ids[1], StmtId { addr: 0x11, role: StmtRole::FlagClear, ordinal: 0 });
+    let store = loc_to_stable(&irmap, &LocIdx { addr: 0x10, idx: 1 }).unwrap();
+    assert_eq!(store.to_string(), "0x10:step.0");
+    assert_eq!("0x10:step.0".parse::<StmtId>(), Ok(store));
+    assert!("0x10:bogus.0".parse::<StmtId>().is_err());
+    assert_eq!(loc_to_stable(&irmap, &LocIdx { addr: 0x11, idx: 2 }), None);
+
+    // a lifter that also clears the flags before each instruction: the raw indices shift, the
+    // stable ids of the primary and expansion statements don't
+    let with_aux = |stmts: &Vec<Stmt>| {
+        let mut stmts = stmts.clone();
+        stmts.insert(0, zf_clear());
+        stmts
+    };
+    let mut aux_irmap = IRMap::new();
+    aux_irmap.insert(0x10, vec![(0x10, with_aux(&push)), (0x11, with_aux(&add))]);
+    assert_eq!(stable_to_loc(&irmap, &ids[0]), Some(LocIdx { addr: 0x11, idx: 0 }));
+    assert_eq!(stable_to_loc(&aux_irmap, &ids[0]), Some(LocIdx { addr: 0x11, idx: 1 }));
+    assert_eq!(stable_to_loc(&aux_irmap, &store), Some(LocIdx { addr: 0x10, idx: 2 }));
+    assert_eq!(loc_to_stable(&aux_irmap, &LocIdx { addr: 0x10, idx: 2 }), Some(store));
+    assert_eq!(stable_to_loc(&irmap, &StmtId { addr: 0x10, role: StmtRole::FlagClear, ordinal: 0 }), None);
+}
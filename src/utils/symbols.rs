@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+
+/// Maps the canonical names of the symbols VeriWasm looks for (e.g. `lucet_tables`) to the names
+/// a particular toolchain actually uses for them. Read from `canonical=actual` lines.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SymbolMap {
+    pub names: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SymbolError {
+    Missing { canonical: String, tried: Vec<String> },
+    Ambiguous { name: String, addrs: Vec<u64> },
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolError::Missing { canonical, tried } => {
+                write!(f, "could not find symbol {} (tried: {})", canonical, tried.join(", "))
+            }
+            SymbolError::Ambiguous { name, addrs } => {
+                let addrs: Vec<String> = addrs.iter().map(|addr| format!("0x{:x}", addr)).collect();
+                write!(f, "symbol {} is defined more than once ({})", name, addrs.join(", "))
+            }
+        }
+    }
+}
+
+impl SymbolMap {
+    pub fn parse(contents: &str) -> Result<SymbolMap, String> {
+        let mut names = HashMap::new();
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(canonical), Some(actual)) if !canonical.trim().is_empty() && !actual.trim().is_empty() => {
+                    names.insert(canonical.trim().to_string(), actual.trim().to_string());
+                }
+                _ => return Err(format!("line {}: expected canonical=actual, got {:?}", lineno + 1, line)),
+            }
+        }
+        Ok(SymbolMap { names: names })
+    }
+
+    pub fn load(path: &str) -> Result<SymbolMap, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        SymbolMap::parse(&contents).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// The names to look for, in order, when resolving `canonical`.
+    pub fn candidates(&self, canonical: &str) -> Vec<String> {
+        match self.names.get(canonical) {
+            Some(actual) if actual != canonical => vec![actual.clone(), canonical.to_string()],
+            _ => vec![canonical.to_string()],
+        }
+    }
+
+    /// Resolves `canonical` against a symbol table of (name, address) pairs. The first candidate
+    /// name that is defined wins; a name defined at more than one address is an error.
+    pub fn resolve<'a, I>(&self, symbols: I, canonical: &str) -> Result<u64, SymbolError>
+    where
+        I: IntoIterator<Item = (&'a str, u64)> + Clone,
+    {
+        let tried = self.candidates(canonical);
+        for name in &tried {
+            let mut addrs: Vec<u64> = symbols
+                .clone()
+                .into_iter()
+                .filter(|(symbol, _)| symbol == name)
+                .map(|(_, addr)| addr)
+                .collect();
+            addrs.sort();
+            addrs.dedup();
+            match addrs.len() {
+                0 => continue,
+                1 => return Ok(addrs[0]),
+                _ => return Err(SymbolError::Ambiguous { name: name.clone(), addrs: addrs }),
+            }
+        }
+        Err(SymbolError::Missing {
+            canonical: canonical.to_string(),
+            tried: tried,
+        })
+    }
+}
+
+#[test]
+fn symbol_map_test() {
+    let symbols = vec![
+        ("guest_table_0", 0x1000),
+        ("acme_lucet_tables", 0x2000),
+        ("lucet_probestack", 0x3000),
+        ("lucet_probestack", 0x3000),
+        ("dup", 0x4000),
+        ("dup", 0x5000),
+    ];
+    let map = SymbolMap::parse("# rebranded\nlucet_tables = acme_lucet_tables\n\nmissing=also_missing\n").unwrap();
+
+    // exact, mapped, and duplicate-but-identical matches
+    assert_eq!(map.resolve(symbols.clone(), "guest_table_0"), Ok(0x1000));
+    assert_eq!(map.resolve(symbols.clone(), "lucet_tables"), Ok(0x2000));
+    assert_eq!(map.resolve(symbols.clone(), "lucet_probestack"), Ok(0x3000));
+    assert_eq!(
+        map.resolve(symbols.clone(), "dup"),
+        Err(SymbolError::Ambiguous { name: "dup".to_string(), addrs: vec![0x4000, 0x5000] })
+    );
+    assert_eq!(
+        map.resolve(symbols.clone(), "missing"),
+        Err(SymbolError::Missing {
+            canonical: "missing".to_string(),
+            tried: vec!["also_missing".to_string(), "missing".to_string()],
+        })
+    );
+    assert_eq!(SymbolMap::default().resolve(symbols, "lucet_tables").is_err(), true);
+    assert!(SymbolMap::parse("lucet_tables").is_err());
+}
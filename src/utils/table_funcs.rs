@@ -0,0 +1,137 @@
+use crate::utils::compiler::{Compiler, CompilerMetadata};
+
+/*
+ * Functions found through the function tables of a module rather than its symbols.
+ *
+ * Functions are verified (and made callable) by their symbols, or the Lucet function manifest,
+ * but a function reachable by call_indirect needn't have either: some WAMR builds strip the
+ * per-function symbols. Every function a table points to is verified, named after its slot,
+ * along with those of the symbols:
+ *  - Lucet: `guest_table_0`, the first table of `lucet_tables`, is an array of (type index,
+ *    function pointer) pairs, as long as the length next to its pointer in `lucet_tables`;
+ *  - WAMR: the function pointers of a Wamr ModuleInstance are set up by the runtime, so only a
+ *    build keeping a static array of them in the module has a table to read, given by a
+ *    `func_ptrs` line of the `--wamr-layout` file.
+ * Null entries are empty slots. An entry outside the code section is no function of the module,
+ * and is reported rather than verified.
+ */
+
+// a table element of guest_table_0: the type index, then the function pointer
+const LUCET_TABLE_ELEMENT_SIZE: u64 = 16;
+const LUCET_TABLE_FUNC_OFFSET: u64 = 8;
+// a table too large to be one is taken to be garbage
+const MAX_TABLE_ENTRIES: u64 = 1 << 20;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TableFuncs {
+    // the functions the table points to, once each, named after the first slot pointing to them
+    pub funcs: Vec<(u64, String)>,
+    pub null_entries: usize,
+    // the (slot, value) of entries outside the code section
+    pub bad_entries: Vec<(usize, u64)>,
+}
+
+/// The pointers of the table of `count` elements of `size` bytes at `table`, each `offset` bytes
+/// into its element, where `read_u64` reads a (relocated) word of the module.
+pub fn read_table(table: u64, count: u64, size: u64, offset: u64, read_u64: impl Fn(u64) -> Option<u64>) -> Result<Vec<u64>, String> {
+    if count > MAX_TABLE_ENTRIES {
+        return Err(format!("table at 0x{:x} claims {} entries", table, count));
+    }
+    (0..count)
+        .map(|idx| {
+            let addr = table + idx * size + offset;
+            read_u64(addr).ok_or_else(|| format!("can't read entry {} of the table at 0x{:x}", idx, table))
+        })
+        .collect()
+}
+
+/// Sorts the pointers of a table into functions of the code section `text` and the others.
+pub fn table_funcs(entries: &[u64], text: (u64, u64), name: impl Fn(usize) -> String) -> TableFuncs {
+    let mut found = TableFuncs::default();
+    for (idx, entry) in entries.iter().enumerate() {
+        if *entry == 0 {
+            found.null_entries += 1;
+        } else if *entry < text.0 || *entry >= text.1 {
+            found.bad_entries.push((idx, *entry));
+        } else if !found.funcs.iter().any(|(addr, _)| addr == entry) {
+            found.funcs.push((*entry, name(idx)));
+        }
+    }
+    found
+}
+
+/// The name given to a function found only through slot `idx` of a table.
+pub fn table_func_name(compiler: Compiler, idx: usize) -> String {
+    match compiler {
+        Compiler::Wamr => format!("wamr_func_{}", idx),
+        _ => format!("guest_table_0_{}", idx),
+    }
+}
+
+/// The functions of the function table of the module, if it has one that can be told from the
+/// module (see above); `func_ptrs` is the WAMR array of the layout file.
+pub fn load_table_funcs(
+    metadata: &CompilerMetadata,
+    func_ptrs: Option<(u64, u64)>,
+    text: (u64, u64),
+    read_u64: impl Fn(u64) -> Option<u64>,
+) -> Result<Option<TableFuncs>, String> {
+    let entries = match metadata.compiler {
+        Compiler::Lucet if metadata.lucet_tables != 0 => {
+            let table = read_u64(metadata.lucet_tables).ok_or_else(|| format!("can't read lucet_tables at 0x{:x}", metadata.lucet_tables))?;
+            let count = read_u64(metadata.lucet_tables + 8).ok_or_else(|| format!("can't read lucet_tables at 0x{:x}", metadata.lucet_tables))?;
+            read_table(table, count, LUCET_TABLE_ELEMENT_SIZE, LUCET_TABLE_FUNC_OFFSET, read_u64)?
+        }
+        Compiler::Wamr => match func_ptrs {
+            Some((addr, count)) => read_table(addr, count, 8, 0, read_u64)?,
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(table_funcs(&entries, text, |idx| table_func_name(metadata.compiler, idx))))
+}
+
+#[test]
+fn table_funcs_test() {
+    use std::collections::HashMap;
+
+    let text = (0x4000, 0x8000);
+    let metadata = |compiler: Compiler, lucet_tables: u64| CompilerMetadata {
+        guest_table_0: 0x2800,
+        lucet_tables: lucet_tables,
+        globals_size: 0,
+        call_table_size: 0,
+        ..CompilerMetadata::for_test(compiler)
+    };
+    // lucet_tables at 0x2000, pointing to a guest_table_0 of 5 elements at 0x2800, and a Wamr
+    // array of 4 function pointers at 0x3000
+    let mut memory: HashMap<u64, u64> = HashMap::new();
+    memory.insert(0x2000, 0x2800);
+    memory.insert(0x2008, 5);
+    for (idx, func) in [0x4000, 0, 0x4100, 0x4000, 0x9000].iter().enumerate() {
+        memory.insert(0x2800 + idx as u64 * 16, 3);
+        memory.insert(0x2808 + idx as u64 * 16, *func);
+    }
+    for (idx, func) in [0x4200, 0x4300, 0x7fff, 0].iter().enumerate() {
+        memory.insert(0x3000 + idx as u64 * 8, *func);
+    }
+    let read = |addr: u64| memory.get(&addr).copied();
+
+    // a function in two slots is found once; empty slots and pointers elsewhere aren't functions
+    let lucet = load_table_funcs(&metadata(Compiler::Lucet, 0x2000), None, text, read).unwrap().unwrap();
+    assert_eq!(lucet.funcs, vec![(0x4000, "guest_table_0_0".to_string()), (0x4100, "guest_table_0_2".to_string())]);
+    assert_eq!((lucet.null_entries, lucet.bad_entries.clone()), (1, vec![(4, 0x9000)]));
+
+    let wamr = load_table_funcs(&metadata(Compiler::Wamr, 0), Some((0x3000, 4)), text, read).unwrap().unwrap();
+    let names: Vec<&str> = wamr.funcs.iter().map(|(_, name)| name.as_str()).collect();
+    assert_eq!(names, vec!["wamr_func_0", "wamr_func_1", "wamr_func_2"]);
+    assert_eq!(wamr.null_entries, 1);
+
+    // nothing to read without a table
+    assert_eq!(load_table_funcs(&metadata(Compiler::Wamr, 0), None, text, read), Ok(None));
+    assert_eq!(load_table_funcs(&metadata(Compiler::Lucet, 0), None, text, read), Ok(None));
+    assert_eq!(load_table_funcs(&metadata(Compiler::Wasmtime, 0x2000), None, text, read), Ok(None));
+    // a table running off the module, or too large to be one
+    assert!(load_table_funcs(&metadata(Compiler::Wamr, 0), Some((0x3000, 5)), text, read).is_err());
+    assert!(read_table(0x3000, 1 << 40, 8, 0, read).is_err());
+}
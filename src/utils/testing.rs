@@ -2,47 +2,57 @@ use crate::analyses::call_analyzer::CallAnalyzer;
 use crate::analyses::heap_analyzer::HeapAnalyzer;
 use crate::analyses::reaching_defs::{analyze_reaching_defs,ReachingDefnAnalyzer};
 use crate::analyses::run_worklist;
-use crate::analyses::stack_analyzer::StackAnalyzer;
-use crate::checkers::call_checker::check_calls;
+use crate::analyses::stack_analyzer::{untracked_rsp, StackAnalyzer};
+use crate::checkers::call_checker::{check_calls, check_direct_calls};
 use crate::checkers::heap_checker::check_heap;
 use crate::checkers::stack_checker::check_stack;
 use crate::utils::ir_utils::has_indirect_calls;
-use crate::utils::utils::{fully_resolved_cfg,get_data,get_one_resolved_cfg};
-use crate::utils::utils::{Compiler, load_metadata, load_program};
+use crate::utils::utils::{analyze_jump_tables,fully_resolved_cfg,get_data,get_one_resolved_cfg,CfgLimits};
+use crate::utils::compiler::Compiler;
+use crate::utils::symbols::SymbolMap;
+use crate::utils::valid_funcs::ValidFuncs;
+use crate::utils::utils::{load_layout, load_metadata, load_program};
+use std::sync::atomic::AtomicUsize;
 use yaxpeax_core::analyses::control_flow::check_cfg_integrity;
 
 fn full_test_helper(path: &str) {
     let program = load_program(&path);
     println!("Loading Metadata");
-    let metadata = load_metadata(&path, Compiler::Lucet, -1);
-    let (x86_64_data, func_addrs, plt) = get_data(&path, &program, &vec![]);
-    let valid_funcs: Vec<u64> = func_addrs.clone().iter().map(|x| x.0).collect();
+    let metadata = load_metadata(&path, Some(Compiler::Lucet), -1, -1, &SymbolMap::default());
+    let (x86_64_data, func_addrs, plt, source, _) = get_data(&path, &load_layout(&path), &[], false);
+    let valid_funcs = ValidFuncs::from_addrs(&func_addrs.iter().map(|x| x.0).collect::<Vec<u64>>(), source);
     for (addr, _func_name) in &func_addrs {
-        let (cfg, irmap) = fully_resolved_cfg(&program, &x86_64_data.contexts, &metadata, *addr);
+        let (cfg, irmap) = fully_resolved_cfg(&program, &x86_64_data.contexts, &metadata, *addr, &CfgLimits::default()).unwrap();
         check_cfg_integrity(&cfg.blocks, &cfg.graph);
         let stack_analyzer = StackAnalyzer {
             metadata: metadata.clone(),
+            stack_limit_cmps: Default::default(),
         };
         let stack_result = run_worklist(&cfg, &irmap, &stack_analyzer);
-        let stack_safe = check_stack(stack_result, &irmap, &stack_analyzer);
+        let untracked_rsp = untracked_rsp(&cfg, &irmap, &stack_result, &stack_analyzer);
+        let stack_safe = check_stack(stack_result, &irmap, &stack_analyzer).is_ok();
         assert!(stack_safe);
         println!("Checking Heap Safety");
         let heap_analyzer = HeapAnalyzer {
             metadata: metadata.clone(),
         };
         let heap_result = run_worklist(&cfg, &irmap, &heap_analyzer);
-        let heap_safe = check_heap(heap_result, &irmap, &heap_analyzer, &func_addrs);
+        let jump_tables = analyze_jump_tables(&cfg, &irmap, &metadata);
+        let heap_safe = check_heap(heap_result, &irmap, &heap_analyzer, &func_addrs, &jump_tables, &untracked_rsp).is_ok();
         assert!(heap_safe);
         println!("Checking Call Safety");
-        if has_indirect_calls(&irmap) {
+        let direct_calls_safe = check_direct_calls(&irmap, metadata.model, &valid_funcs, &plt).is_ok();
+        assert!(direct_calls_safe);
+        if has_indirect_calls(&irmap, metadata.compiler) {
             let reaching_defs = analyze_reaching_defs(&cfg, &irmap, &metadata);
             let call_analyzer = CallAnalyzer {
                 metadata: metadata.clone(),
                 reaching_defs: reaching_defs.clone(),
-                reaching_analyzer: ReachingDefnAnalyzer {metadata: metadata.clone(), cfg: cfg.clone(), irmap: irmap.clone()},
+                reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), cfg.clone(), irmap.clone()),
+                withheld_refinements: AtomicUsize::new(0),
             };
             let call_result = run_worklist(&cfg, &irmap, &call_analyzer);
-            let call_safe = check_calls(call_result, &irmap, &call_analyzer, &valid_funcs, &plt);
+            let call_safe = check_calls(call_result, &irmap, &call_analyzer, &valid_funcs, &plt).is_ok();
             assert!(call_safe);
         }
     }
@@ -51,37 +61,43 @@ fn full_test_helper(path: &str) {
 
 fn negative_test_helper(path: &str, func_name: &str) {
     let program = load_program(&path);
-    let (_x86_64_data, func_addrs, plt) = get_data(&path, &program, &vec![]);
-    let valid_funcs: Vec<u64> = func_addrs.clone().iter().map(|x| x.0).collect();
+    let (_x86_64_data, func_addrs, plt, source, _) = get_data(&path, &load_layout(&path), &[], false);
+    let valid_funcs = ValidFuncs::from_addrs(&func_addrs.iter().map(|x| x.0).collect::<Vec<u64>>(), source);
     println!("Loading Metadata");
-    let metadata = load_metadata(&path, Compiler::Lucet, -1);
+    let metadata = load_metadata(&path, Some(Compiler::Lucet), -1, -1, &SymbolMap::default());
     let ((cfg, irmap),_x86_64_data) = get_one_resolved_cfg(path, Compiler::Lucet, func_name);
     println!("Analyzing: {:?}", func_name);
     check_cfg_integrity(&cfg.blocks, &cfg.graph);
     println!("Checking Stack Safety");
     let stack_analyzer = StackAnalyzer {
         metadata: metadata.clone(),
+        stack_limit_cmps: Default::default(),
     };
     let stack_result = run_worklist(&cfg, &irmap, &stack_analyzer);
-    let stack_safe = check_stack(stack_result, &irmap, &stack_analyzer);
+    let untracked_rsp = untracked_rsp(&cfg, &irmap, &stack_result, &stack_analyzer);
+    let stack_safe = check_stack(stack_result, &irmap, &stack_analyzer).is_ok();
     assert!(stack_safe);
     println!("Checking Heap Safety");
     let heap_analyzer = HeapAnalyzer {
         metadata: metadata.clone(),
     };
     let heap_result = run_worklist(&cfg, &irmap, &heap_analyzer);
-    let heap_safe = check_heap(heap_result, &irmap, &heap_analyzer, &func_addrs);
+    let jump_tables = analyze_jump_tables(&cfg, &irmap, &metadata);
+    let heap_safe = check_heap(heap_result, &irmap, &heap_analyzer, &func_addrs, &jump_tables, &untracked_rsp).is_ok();
     assert!(heap_safe);
     println!("Checking Call Safety");
-    if has_indirect_calls(&irmap) {
+    let direct_calls_safe = check_direct_calls(&irmap, metadata.model, &valid_funcs, &plt).is_ok();
+    assert!(direct_calls_safe);
+    if has_indirect_calls(&irmap, metadata.compiler) {
         let reaching_defs = analyze_reaching_defs(&cfg, &irmap, &metadata);
         let call_analyzer = CallAnalyzer {
             metadata: metadata.clone(),
             reaching_defs: reaching_defs.clone(),
-            reaching_analyzer: ReachingDefnAnalyzer {metadata: metadata.clone(), cfg: cfg.clone(), irmap: irmap.clone()},
+            reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), cfg.clone(), irmap.clone()),
+            withheld_refinements: AtomicUsize::new(0),
         };
         let call_result = run_worklist(&cfg, &irmap, &call_analyzer);
-        let call_safe = check_calls(call_result, &irmap, &call_analyzer, &valid_funcs, &plt);
+        let call_safe = check_calls(call_result, &irmap, &call_analyzer, &valid_funcs, &plt).is_ok();
         assert!(call_safe);
     }
     println!("Done! ");
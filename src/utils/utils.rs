@@ -2,30 +2,33 @@ use crate::analyses::jump_analyzer::analyze_jumps;
 use crate::analyses::jump_analyzer::SwitchAnalyzer;
 use crate::analyses::reaching_defs::analyze_reaching_defs;
 use crate::analyses::reaching_defs::ReachingDefnAnalyzer;
-use crate::checkers::jump_resolver::resolve_jumps;
-use crate::utils::ir_utils::has_indirect_jumps;
-use crate::utils::lifter::{MemArg, MemArgs, IRMap, lift_cfg};
+use crate::analyses::ControlFlow;
+use crate::checkers::jump_checker::check_jumps;
+use crate::checkers::jump_resolver::{jump_table_loads, jump_table_reads, resolve_jumps, TableMemory};
+use crate::checkers::CheckFailure;
+use crate::utils::caveats::{record_caveat, CaveatKind};
+use crate::utils::cfg_normalize::{normalize_blocks, BlockGraph, BlockRange, CfgError};
+use crate::utils::compiler::{Compiler, CompilerMetadata};
+use crate::lattices::stackgrowthlattice::WAMR_CALLER_WINDOW;
+use crate::lattices::switchlattice::JumpTableLoads;
+use crate::utils::ir::{MemArg, MemArgs, IRMap};
+use crate::utils::ir_utils::{count_tail_dispatches, has_indirect_jumps};
+use crate::utils::lifter::lift_cfg;
+use crate::utils::lucet_manifest::{load_globals_size, load_manifest, manifest_func_name, LucetManifest};
+use crate::utils::compiler_model::LucetModel;
+use crate::utils::module_layout::ModuleLayout;
+use crate::utils::symbols::{SymbolError, SymbolMap};
+use crate::utils::valid_funcs::{FuncSource, TrustedFunc};
+use std::collections::{BTreeSet, HashMap};
 use std::path::Path;
-use std::str::FromStr;
-use yaxpeax_arch::Arch;
 use yaxpeax_core::analyses::control_flow::{get_cfg, VW_CFG};
 use yaxpeax_core::arch::x86_64::x86_64Data;
 use yaxpeax_core::arch::x86_64::MergedContextTable;
 use yaxpeax_core::arch::SymbolQuery;
 use yaxpeax_core::arch::{BaseUpdate, Library, Symbol};
-use yaxpeax_core::memory::repr::process::{
-    ELFExport, ELFImport, ELFSymbol, ModuleData, ModuleInfo,
-};
+use yaxpeax_core::memory::repr::process::ModuleData;
 use yaxpeax_core::memory::repr::FileRepr;
-use yaxpeax_core::memory::MemoryRepr;
 use yaxpeax_core::ContextWrite;
-use yaxpeax_x86::long_mode::Arch as AMD64;
-
-#[derive(Clone, Copy)]
-pub enum Compiler {
-    Lucet,
-    Wamr,
-}
 
 pub fn load_program(binpath: &str) -> ModuleData {
     let program = yaxpeax_core::memory::reader::load_from_path(Path::new(binpath)).unwrap();
@@ -37,55 +40,112 @@ pub fn load_program(binpath: &str) -> ModuleData {
     program
 }
 
-fn get_function_starts(
-    entrypoint: &u64,
-    symbols: &std::vec::Vec<ELFSymbol>,
-    imports: &std::vec::Vec<ELFImport>,
-    exports: &std::vec::Vec<ELFExport>,
-    _text_section_idx: usize,
-) -> x86_64Data {
+/// The entrypoint, sections and symbols of the ELF, PE or Mach-O module at `binpath`.
+pub fn load_layout(binpath: &str) -> ModuleLayout {
+    let module = std::fs::read(binpath).unwrap_or_else(|e| panic!("{:?}: {}", binpath, e));
+    ModuleLayout::parse(&module).unwrap_or_else(|e| panic!("{:?} isn't a module veriwasm understands: {}", binpath, e))
+}
+
+impl ControlFlow for VW_CFG {
+    fn entrypoint(&self) -> u64 {
+        self.entrypoint
+    }
+
+    fn successors(&self, addr: u64) -> Vec<u64> {
+        self.graph.neighbors(addr).collect()
+    }
+}
+
+fn get_function_starts(entrypoint: u64, symbols: &[(u64, String)]) -> x86_64Data {
     let mut x86_64_data = x86_64Data::default();
 
     // start queuing up places we expect to find functions
     x86_64_data.contexts.put(
-        *entrypoint as u64,
+        entrypoint,
         BaseUpdate::Specialized(yaxpeax_core::arch::x86_64::x86Update::FunctionHint),
     );
 
     // copy in symbols (not really necessary here)
-    for sym in symbols {
+    for (addr, name) in symbols {
         x86_64_data.contexts.put(
-            sym.addr as u64,
-            BaseUpdate::DefineSymbol(Symbol(Library::This, sym.name.clone())),
+            *addr,
+            BaseUpdate::DefineSymbol(Symbol(Library::This, name.clone())),
         );
     }
 
-    //All symbols in text section should be function starts
-    for sym in symbols {
+    //All symbols in text section should be function starts (exported ones included)
+    for (addr, _) in symbols {
         x86_64_data.contexts.put(
-            sym.addr as u64,
+            *addr,
             BaseUpdate::Specialized(yaxpeax_core::arch::x86_64::x86Update::FunctionHint),
         );
     }
+    x86_64_data
+}
 
-    // and copy in names for imports
-    for import in imports {
-        x86_64_data.contexts.put(
-            import.value as u64,
-            BaseUpdate::DefineSymbol(Symbol(Library::Unknown, import.name.clone())),
-        );
+/// Makes the blocks of `cfg` disjoint and non-empty (see cfg_normalize.rs), so that lifting can
+/// take them as they are.
+fn normalize_cfg(cfg: &mut VW_CFG) -> Result<(), CfgError> {
+    let mut blocks = std::collections::BTreeMap::new();
+    for addr in cfg.graph.nodes() {
+        match cfg.blocks.get(&addr) {
+            Some(block) => blocks.insert(addr, BlockRange { start: block.start, end: block.end }),
+            None => return Err(CfgError::MissingBlock(addr)),
+        };
     }
+    let mut graph = BlockGraph {
+        entrypoint: cfg.entrypoint,
+        blocks: blocks,
+        edges: cfg.graph.all_edges().map(|(from, to, _)| (from, to)).collect(),
+    };
+    let fixes = normalize_blocks(&mut graph)?;
+    if fixes == 0 {
+        return Ok(());
+    }
+    log::info!("Re-split or dropped {} blocks of the CFG at 0x{:x}", fixes, cfg.entrypoint);
 
-    // exports are probably functions? hope for the best
-    for export in exports {
-        x86_64_data.contexts.put(
-            export.addr as u64,
-            BaseUpdate::Specialized(yaxpeax_core::arch::x86_64::x86Update::FunctionHint),
-        );
+    cfg.entrypoint = graph.entrypoint;
+    cfg.blocks.retain(|addr, _| graph.blocks.contains_key(addr));
+    for (addr, block) in cfg.blocks.iter_mut() {
+        block.end = graph.blocks[addr].end;
     }
-    x86_64_data
+    let nodes: Vec<u64> = cfg.graph.nodes().collect();
+    for node in nodes {
+        cfg.graph.remove_node(node);
+    }
+    for addr in graph.blocks.keys() {
+        cfg.graph.add_node(*addr);
+    }
+    for (from, to) in graph.edges {
+        cfg.graph.add_edge(from, to, ());
+    }
+    Ok(())
 }
 
+/// Bounds on resolving the indirect jumps of a function. Each round resolves the switches of
+/// the current CFG and rebuilds it with their targets, which may bring in blocks with switches
+/// of their own, so a crafted module could keep the rounds going.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CfgLimits {
+    // rounds of resolving and rebuilding
+    pub max_rounds: usize,
+    // targets across all the switches of the function
+    pub max_targets: usize,
+}
+
+impl Default for CfgLimits {
+    fn default() -> Self {
+        CfgLimits {
+            max_rounds: 32,
+            max_targets: 1 << 16,
+        }
+    }
+}
+
+// A round of resolution: what it built, the targets of each switch, and the number of indirect
+// jumps still unresolved
+type ResolutionRound<T> = (T, HashMap<u64, Vec<i64>>, u32);
+
 fn try_resolve_jumps(
     program: &ModuleData,
     contexts: &MergedContextTable,
@@ -93,49 +153,81 @@ fn try_resolve_jumps(
     metadata: &CompilerMetadata,
     irmap: &IRMap,
     _addr: u64,
-) -> (VW_CFG, IRMap, i32, u32) {
-    println!("Performing a reaching defs pass");
+) -> Result<ResolutionRound<(VW_CFG, IRMap)>, CfgError> {
+    log::info!("Performing a reaching defs pass");
     let reaching_defs = analyze_reaching_defs(cfg, &irmap, &metadata);
-    println!("Performing a jump resolution pass");
+    log::info!("Performing a jump resolution pass");
     let switch_analyzer = SwitchAnalyzer {
         metadata: metadata.clone(),
         reaching_defs: reaching_defs,
-        reaching_analyzer: ReachingDefnAnalyzer {metadata: metadata.clone(), cfg: cfg.clone(), irmap: irmap.clone()},
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), cfg.clone(), irmap.clone()),
     };
     let switch_results = analyze_jumps(cfg, &irmap, &switch_analyzer);
     let switch_targets = resolve_jumps(program, switch_results, &irmap, &switch_analyzer);
 
-    let (new_cfg, still_unresolved) =
+    let (mut new_cfg, still_unresolved) =
         get_cfg(program, contexts, cfg.entrypoint, Some(&switch_targets));
-    let irmap = lift_cfg(&program, &new_cfg, &metadata);
-    let num_targets = switch_targets.len();
-    return (new_cfg, irmap, num_targets as i32, still_unresolved);
+    normalize_cfg(&mut new_cfg)?;
+    let irmap = lift_cfg(&program, &new_cfg, &metadata)?;
+    // tail dispatches have no targets to resolve
+    let still_unresolved = still_unresolved.saturating_sub(count_tail_dispatches(&irmap, metadata.compiler) as u32);
+    Ok(((new_cfg, irmap), switch_targets, still_unresolved))
+}
+
+/// Runs rounds of resolution from `initial` until no indirect jump is left unresolved, or a round
+/// finds no new target. Every round has to find at least the targets of the one before; a round
+/// dropping one, more than `limits.max_targets` targets, or more than `limits.max_rounds`
+/// rounds, makes the function unverifiable.
+fn drive_resolution<T>(
+    addr: u64,
+    initial: T,
+    limits: &CfgLimits,
+    mut round: impl FnMut(&T) -> Result<ResolutionRound<T>, CfgError>,
+) -> Result<T, CfgError> {
+    let mut current = initial;
+    let mut targets: BTreeSet<(u64, i64)> = BTreeSet::new();
+    for rounds in 1..=limits.max_rounds {
+        let (next, switch_targets, still_unresolved) = round(&current)?;
+        let next_targets: BTreeSet<(u64, i64)> = switch_targets
+            .iter()
+            .flat_map(|(switch, switch_targets)| switch_targets.iter().map(move |target| (*switch, *target)))
+            .collect();
+        if next_targets.len() > limits.max_targets {
+            return Err(CfgError::TooManyTargets(addr, next_targets.len(), limits.max_targets));
+        }
+        if let Some((switch, target)) = targets.difference(&next_targets).next() {
+            return Err(CfgError::UnstableTargets(addr, *switch, *target));
+        }
+        let grew = next_targets.len() > targets.len();
+        current = next;
+        targets = next_targets;
+        if still_unresolved == 0 {
+            log::debug!("0x{:x}: indirect jumps resolved in {} rounds", addr, rounds);
+            return Ok(current);
+        }
+        if rounds > 1 && !grew {
+            // the jump checker reports the jumps left unresolved
+            log::warn!("0x{:x}: {} indirect jumps can't be resolved", addr, still_unresolved);
+            return Ok(current);
+        }
+    }
+    Err(CfgError::ResolutionLimit(addr, limits.max_rounds))
 }
 
 fn resolve_cfg(
     program: &ModuleData,
     contexts: &MergedContextTable,
-    cfg: &VW_CFG,
+    cfg: VW_CFG,
     metadata: &CompilerMetadata,
-    orig_irmap: &IRMap,
+    irmap: IRMap,
     addr: u64,
-) -> (VW_CFG, IRMap) {
-    let (mut cfg, mut irmap, mut resolved_switches, mut still_unresolved) =
-        try_resolve_jumps(program, contexts, cfg, metadata, orig_irmap, addr);
-    while still_unresolved != 0 {
-        let (new_cfg, new_irmap, new_resolved_switches, new_still_unresolved) =
-            try_resolve_jumps(program, contexts, &cfg, metadata, &irmap, addr);
-        cfg = new_cfg;
-        irmap = new_irmap;
-        if (new_resolved_switches == resolved_switches) && (new_still_unresolved != 0) {
-            panic!("Fixed Point Error");
-        }
-        resolved_switches = new_resolved_switches;
-        still_unresolved = new_still_unresolved;
-    }
+    limits: &CfgLimits,
+) -> Result<(VW_CFG, IRMap), CfgError> {
+    let (cfg, irmap) = drive_resolution(addr, (cfg, irmap), limits, |(cfg, irmap)| {
+        try_resolve_jumps(program, contexts, cfg, metadata, irmap, addr)
+    })?;
     assert_eq!(cfg.graph.node_count(), irmap.keys().len());
-    assert_eq!(still_unresolved, 0);
-    (cfg, irmap)
+    Ok((cfg, irmap))
 }
 
 pub fn fully_resolved_cfg(
@@ -143,147 +235,357 @@ pub fn fully_resolved_cfg(
     contexts: &MergedContextTable,
     metadata: &CompilerMetadata,
     addr: u64,
-) -> (VW_CFG, IRMap) {
-    let (cfg, _) = get_cfg(program, contexts, addr, None);
-    let irmap = lift_cfg(&program, &cfg, &metadata);
-    if !has_indirect_jumps(&irmap) {
-        return (cfg, irmap);
+    limits: &CfgLimits,
+) -> Result<(VW_CFG, IRMap), CfgError> {
+    let (mut cfg, _) = get_cfg(program, contexts, addr, None);
+    normalize_cfg(&mut cfg)?;
+    let irmap = lift_cfg(&program, &cfg, &metadata)?;
+    if !has_indirect_jumps(&irmap, metadata.compiler) {
+        return Ok((cfg, irmap));
     }
-    return resolve_cfg(program, contexts, &cfg, metadata, &irmap, addr);
+    return resolve_cfg(program, contexts, cfg, metadata, irmap, addr, limits);
 }
 
-pub fn get_data(
-    binpath: &str,
-    program: &ModuleData,
-    funcs: &Vec<u32>,
-) -> (x86_64Data, Vec<(u64, std::string::String)>, (u64,u64)) {
-    let (_, sections, entrypoint, imports, exports, symbols) =
-        match (program as &dyn MemoryRepr<<AMD64 as Arch>::Address>).module_info() {
-            Some(ModuleInfo::ELF(isa, _, _, sections, entry, _, imports, exports, symbols)) => {
-                (isa, sections, entry, imports, exports, symbols)
-            }
-            Some(other) => {
-                panic!("{:?} isn't an elf, but is a {:?}?", binpath, other);
-            }
-            None => {
-                panic!(
-                    "{:?} doesn't appear to be a binary yaxpeax understands.",
-                    binpath
-                );
-            }
-        };
-    // println!("Sections: {:?}", sections);
-    let plt_bounds = 
-    if let Some(plt_idx) = sections.iter().position(|x| x.name == ".plt"){
-        let plt = sections.get(plt_idx).unwrap();
-        (plt.start, plt.start + plt.size)
-        }
-    else{
-        (0,0)
+/// Finds the jump table loads in a fully resolved function, for the heap checker.
+pub fn analyze_jump_tables(cfg: &VW_CFG, irmap: &IRMap, metadata: &CompilerMetadata) -> JumpTableLoads {
+    // Wamr's tables are checked through the module instance instead
+    if let Compiler::Wamr = metadata.compiler {
+        return JumpTableLoads::new();
+    }
+    if !has_indirect_jumps(irmap, metadata.compiler) {
+        return JumpTableLoads::new();
+    }
+    let reaching_defs = analyze_reaching_defs(cfg, irmap, metadata);
+    let switch_analyzer = SwitchAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: reaching_defs,
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), cfg.clone(), irmap.clone()),
     };
+    let switch_results = analyze_jumps(cfg, irmap, &switch_analyzer);
+    jump_table_loads(switch_results, irmap, &switch_analyzer)
+}
+
+/// Checks that every indirect jump of a fully resolved function was resolved to blocks of the
+/// function.
+pub fn check_cfi(program: &dyn TableMemory, cfg: &VW_CFG, irmap: &IRMap, metadata: &CompilerMetadata) -> Result<(), CheckFailure> {
+    if !has_indirect_jumps(irmap, metadata.compiler) {
+        return Ok(());
+    }
+    let reaching_defs = analyze_reaching_defs(cfg, irmap, metadata);
+    let switch_analyzer = SwitchAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: reaching_defs,
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), cfg.clone(), irmap.clone()),
+    };
+    let switch_results = analyze_jumps(cfg, irmap, &switch_analyzer);
+    let switch_targets = resolve_jumps(program, switch_results.clone(), irmap, &switch_analyzer);
+    let block_starts: BTreeSet<u64> = cfg.blocks.keys().cloned().collect();
+    check_jumps(switch_results, irmap, &switch_analyzer, &switch_targets, &block_starts)
+}
 
-    let text_section_idx = sections.iter().position(|x| x.name == ".text").unwrap();
-    let text_section = sections.get(text_section_idx).unwrap();
+/// The jump tables verifying a fully resolved function reads, for `--deps`.
+pub fn analyze_jump_table_reads(cfg: &VW_CFG, irmap: &IRMap, metadata: &CompilerMetadata) -> Vec<(u64, u64)> {
+    if !has_indirect_jumps(irmap, metadata.compiler) {
+        return vec![];
+    }
+    let reaching_defs = analyze_reaching_defs(cfg, irmap, metadata);
+    let switch_analyzer = SwitchAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: reaching_defs,
+        reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), cfg.clone(), irmap.clone()),
+    };
+    let switch_results = analyze_jumps(cfg, irmap, &switch_analyzer);
+    jump_table_reads(switch_results, irmap, &switch_analyzer)
+}
+
+/// The disassembly contexts, the guest functions and the PLT range of the module laid out as
+/// `layout`, and where the functions came from: its symbols, or the Lucet function manifest if
+/// it has no function symbols or `no_symbols` is set. The PLT range is empty for a module
+/// without one.
+/// The module's code, the guest functions to verify, the PLT range, where the functions came
+/// from, and the functions `trusted` names, which are left out of the ones to verify.
+pub fn get_data(
+    binpath: &str,
+    layout: &ModuleLayout,
+    trusted: &[TrustedFunc],
+    no_symbols: bool,
+) -> (x86_64Data, Vec<(u64, std::string::String)>, (u64,u64), FuncSource, Vec<(u64, std::string::String)>) {
+    let plt_bounds = layout.plt;
+    let (text_start, text_end) = layout.text;
 
-    let mut x86_64_data =
-        get_function_starts(entrypoint, symbols, imports, exports, text_section_idx);
+    let mut x86_64_data = get_function_starts(layout.entrypoint, &layout.symbols);
 
+    let is_trusted = |addr: u64, name: &str| trusted.iter().any(|func| func.matches(addr, name));
     let mut addrs: Vec<(u64, std::string::String)> = Vec::new();
+    let mut trusted_addrs: Vec<(u64, std::string::String)> = Vec::new();
     while let Some(addr) = x86_64_data.contexts.function_hints.pop() {
-        if !((addr >= text_section.start) && (addr < (text_section.start + text_section.size))) {
+        if !((addr >= text_start) && (addr < text_end)) {
             continue;
         }
         if let Some(symbol) = x86_64_data.symbol_for(addr) {
-            if is_valid_func_name(&symbol.1, funcs) {
+            // a trusted function needn't be named like a guest function, e.g. a runtime stub
+            if is_trusted(addr, &symbol.1) {
+                trusted_addrs.push((addr, symbol.1.clone()));
+            } else if is_valid_func_name(&symbol.1) {
                 addrs.push((addr, symbol.1.clone()));
             }
-            else{println!("Symbol = 0x{:x} {:?}", addr, symbol.1);}
+            else{log::info!("Symbol = 0x{:x} {:?}", addr, symbol.1);}
+        }
+    }
+    let mut source = FuncSource::Symbol;
+    if no_symbols || addrs.is_empty() {
+        match std::fs::read(binpath).map_err(|e| e.to_string()).and_then(|module| load_manifest(&module)) {
+            Ok(manifest) => {
+                log::info!("{} functions from the Lucet function manifest", manifest.functions.len());
+                let (manifest_trusted, manifest_addrs): (Vec<_>, Vec<_>) =
+                    manifest_funcs(&manifest).into_iter().partition(|(addr, name)| is_trusted(*addr, name));
+                addrs = manifest_addrs;
+                trusted_addrs = manifest_trusted;
+                source = FuncSource::Manifest;
+                for (addr, name) in addrs.iter().chain(trusted_addrs.iter()) {
+                    x86_64_data.contexts.put(*addr, BaseUpdate::DefineSymbol(Symbol(Library::This, name.clone())));
+                }
+            }
+            Err(e) if no_symbols => panic!("{:?}: no Lucet function manifest: {}", binpath, e),
+            Err(e) => log::info!("No function symbols, and no Lucet function manifest: {}", e),
+        }
+    }
+    // an address needn't be a function symbol's, e.g. for a stub without one
+    for func in trusted.iter() {
+        if let TrustedFunc::Addr(addr) = func {
+            if *addr >= text_start && *addr < text_end && !trusted_addrs.iter().any(|(trusted_addr, _)| trusted_addr == addr) {
+                trusted_addrs.push((*addr, func.to_string()));
+            }
+        }
+        if !trusted_addrs.iter().any(|(addr, name)| func.matches(*addr, name)) {
+            log::warn!("Trusted function {} is not a function of the module", func);
         }
     }
-    (x86_64_data, addrs, plt_bounds)
+    trusted_addrs.sort();
+    let func_starts: Vec<u64> = addrs.iter().map(|(addr, _)| *addr).collect();
+    let (plt_bounds, overlapping) = disjoint_plt(plt_bounds, &func_starts);
+    for addr in overlapping {
+        log::warn!("Function 0x{:x} lies inside the PLT range, which now ends at 0x{:x}", addr, plt_bounds.1);
+        let description = format!("function start inside the PLT range, which now ends at 0x{:x}", plt_bounds.1);
+        record_caveat(CaveatKind::ShrunkPlt, Some(addr), description);
+    }
+    (x86_64_data, addrs, plt_bounds, source, trusted_addrs)
+}
+
+/// The guest functions of a Lucet function manifest, named as lucetc names them.
+fn manifest_funcs(manifest: &LucetManifest) -> Vec<(u64, std::string::String)> {
+    manifest.functions.iter().enumerate().map(|(idx, (addr, _size))| (*addr, manifest_func_name(idx))).collect()
+}
+
+/// The PLT range and the guest function addresses should be disjoint, since a call into the
+/// PLT is validated by a different rule than a call to a guest function. If some guest
+/// functions lie inside the range, it is cut short at the first of them. Returns the
+/// (possibly shrunk) range and the overlapping function addresses, in order.
+pub fn disjoint_plt(plt: (u64, u64), funcs: &[u64]) -> ((u64, u64), Vec<u64>) {
+    let (plt_start, plt_end) = plt;
+    let mut overlapping: Vec<u64> = funcs.iter()
+        .cloned()
+        .filter(|addr| *addr >= plt_start && *addr < plt_end)
+        .collect();
+    overlapping.sort();
+    match overlapping.first() {
+        Some(first) => ((plt_start, *first), overlapping),
+        None => (plt, overlapping),
+    }
+}
+
+/// Fresh disassembly contexts for the functions of the module laid out as `layout`. Workers
+/// verifying functions in parallel each set up their own.
+pub fn load_contexts(layout: &ModuleLayout) -> x86_64Data {
+    get_function_starts(layout.entrypoint, &layout.symbols)
 }
 
 pub fn get_one_resolved_cfg(binpath: &str, compiler: Compiler, func: &str) -> ((VW_CFG, IRMap),x86_64Data) {
     let program = load_program(binpath);
-    let metadata = load_metadata(binpath, compiler, -1);
+    let layout = load_layout(binpath);
+    let metadata = load_metadata(binpath, Some(compiler), -1, -1, &SymbolMap::default());
 
-    // grab some details from the binary and panic if it's not what we expected
-    let (_, sections, entrypoint, imports, exports, symbols) =
-        match (&program as &dyn MemoryRepr<<AMD64 as Arch>::Address>).module_info() {
-            Some(ModuleInfo::ELF(isa, _, _, sections, entry, _, imports, exports, symbols)) => {
-                (isa, sections, entry, imports, exports, symbols)
-            }
-            Some(other) => {
-                panic!("{:?} isn't an elf, but is a {:?}?", binpath, other);
-            }
-            None => {
-                panic!(
-                    "{:?} doesn't appear to be a binary yaxpeax understands.",
-                    binpath
-                );
-            }
-        };
+    let x86_64_data = get_function_starts(layout.entrypoint, &layout.symbols);
+    let addr = layout.symbol_addr(func).unwrap();
+    log::info!("Generating CFG for: {:?}", func);
+    let resolved = fully_resolved_cfg(&program, &x86_64_data.contexts, &metadata, addr, &CfgLimits::default())
+        .unwrap_or_else(|e| panic!("Malformed CFG for {:?}: {}", func, e));
+    return (resolved, x86_64_data);
+}
+
+// Symbols only one of the compilers emits, by the compiler that emits them
+const LUCET_SYMBOLS: [&str; 3] = ["lucet_probestack", "lucet_tables", "guest_table_0"];
+const WAMR_SYMBOLS: [&str; 3] = ["aot_set_exception_with_id", "aot_invoke_native", "wasm_runtime_enlarge_memory"];
 
-    let text_section_idx = sections.iter().position(|x| x.name == ".text").unwrap();
-    let x86_64_data = get_function_starts(entrypoint, symbols, imports, exports, text_section_idx);
-    let addr = get_symbol_addr(symbols, func).unwrap();
-    println!("Generating CFG for: {:?}", func);
-    return (fully_resolved_cfg(&program, &x86_64_data.contexts, &metadata, addr),x86_64_data);
+/// Whether `name` is a function Wasmtime compiled: `_wasm_function_N` in older releases,
+/// `wasm[M]::function[N]` in newer ones.
+fn is_wasmtime_func_name(name: &str) -> bool {
+    name.starts_with("_wasm_function_") || (name.starts_with("wasm[") && name.contains("]::function["))
 }
 
-fn get_symbol_addr(symbols: &Vec<ELFSymbol>, name: &str) -> std::option::Option<u64> {
-    let mut x = None;
-    for symbol in symbols.iter() {
-        if symbol.name == name {
-            x = Some(symbol.addr);
+/// The symbols of a module pointing at a compiler: the Lucet runtime symbols and `guest_func_`
+/// functions, the WAMR runtime symbols and `aot_func#` functions, and Wasmtime's functions.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompilerSignals {
+    pub lucet: Vec<String>,
+    pub wamr: Vec<String>,
+    pub wasmtime: Vec<String>,
+}
+
+impl CompilerSignals {
+    fn from_names<'a>(names: impl Iterator<Item = &'a str>) -> CompilerSignals {
+        let mut signals = CompilerSignals::default();
+        let (mut lucet_funcs, mut wamr_funcs, mut wasmtime_funcs) = (0, 0, 0);
+        for name in names {
+            if LUCET_SYMBOLS.contains(&name) {
+                signals.lucet.push(name.to_string());
+            } else if WAMR_SYMBOLS.contains(&name) {
+                signals.wamr.push(name.to_string());
+            } else if name.starts_with("guest_func_") {
+                lucet_funcs += 1;
+            } else if name.starts_with("aot_func#") {
+                wamr_funcs += 1;
+            } else if is_wasmtime_func_name(name) {
+                wasmtime_funcs += 1;
+            }
+        }
+        if lucet_funcs > 0 {
+            signals.lucet.push(format!("{} guest_func_* functions", lucet_funcs));
+        }
+        if wamr_funcs > 0 {
+            signals.wamr.push(format!("{} aot_func#* functions", wamr_funcs));
+        }
+        if wasmtime_funcs > 0 {
+            signals.wasmtime.push(format!("{} Wasmtime functions", wasmtime_funcs));
+        }
+        signals
+    }
+
+    /// The compiler if only one of them left signs in the module.
+    pub fn compiler(&self) -> Option<Compiler> {
+        match (self.lucet.is_empty(), self.wamr.is_empty(), self.wasmtime.is_empty()) {
+            (false, true, true) => Some(Compiler::Lucet),
+            (true, false, true) => Some(Compiler::Wamr),
+            (true, true, false) => Some(Compiler::Wasmtime),
+            _ => None,
+        }
+    }
+
+    fn found(&self, compiler: Compiler) -> &Vec<String> {
+        match compiler {
+            Compiler::Lucet => &self.lucet,
+            Compiler::Wamr => &self.wamr,
+            Compiler::Wasmtime => &self.wasmtime,
         }
     }
-    x
 }
 
-#[derive(Clone)]
-pub struct CompilerMetadata {
-    pub compiler: Compiler,
+pub fn compiler_signals(layout: &ModuleLayout) -> CompilerSignals {
+    CompilerSignals::from_names(layout.symbols.iter().map(|(_, name)| name.as_str()))
+}
 
-    // Lucet specific
-    pub guest_table_0: u64,
-    pub lucet_tables: u64,
-    pub lucet_probestack: u64,
+impl Compiler {
+    /// Which compiler produced the module laid out as `layout`, judging by its symbols, or None
+    /// if that's ambiguous.
+    pub fn detect(layout: &ModuleLayout) -> Option<Compiler> {
+        compiler_signals(layout).compiler()
+    }
+}
 
-    // Wamr specific
-    pub globals_size: i64,
+/// The compiler to verify `program` as: the one asked for, which must not contradict the
+/// module's symbols, or else the detected one.
+fn resolve_compiler(binpath: &str, signals: &CompilerSignals, compiler: Option<Compiler>) -> Result<Compiler, String> {
+    let describe = |found: &Vec<String>| if found.is_empty() { "nothing".to_string() } else { found.join(", ") };
+    match (compiler, signals.compiler()) {
+        (Some(asked), Some(detected)) if asked != detected => Err(format!(
+            "{:?} was compiled by {:?} (found {}), not {:?}; drop or fix the compiler flag",
+            binpath,
+            detected,
+            describe(signals.found(detected)),
+            asked
+        )),
+        (Some(asked), _) => Ok(asked),
+        (None, Some(detected)) => Ok(detected),
+        (None, None) => Err(format!(
+            "Unable to tell which compiler produced {:?} (Lucet: found {}; WAMR: found {}; Wasmtime: found {}); pass --lucet, --wamr or --wasmtime",
+            binpath,
+            describe(&signals.lucet),
+            describe(&signals.wamr),
+            describe(&signals.wasmtime)
+        )),
+    }
 }
 
-pub fn load_metadata(binpath: &str, compiler: Compiler, globals_size: i64) -> CompilerMetadata {
-    let program = load_program(binpath);
+/// `compiler` is the compiler asked for, or None to detect it from the module's symbols. Panics
+/// if the module doesn't look like the output of the compiler asked for, or if no compiler was
+/// asked for and the module could be either.
+pub fn load_metadata(
+    binpath: &str,
+    compiler: Option<Compiler>,
+    mut globals_size: i64,
+    call_table_size: i64,
+    symbol_map: &SymbolMap,
+) -> CompilerMetadata {
+    let layout = load_layout(binpath);
+    let symbols = &layout.symbols;
 
-    // grab some details from the binary and panic if it's not what we expected
-    let (_, _sections, _entrypoint, _imports, _exports, symbols) =
-        match (&program as &dyn MemoryRepr<<AMD64 as Arch>::Address>).module_info() {
-            Some(ModuleInfo::ELF(isa, _, _, sections, entry, _, imports, exports, symbols)) => {
-                (isa, sections, entry, imports, exports, symbols)
-            }
-            Some(other) => {
-                panic!("{:?} isn't an elf, but is a {:?}?", binpath, other);
-            }
-            None => {
-                panic!(
-                    "{:?} doesn't appear to be a binary yaxpeax understands.",
-                    binpath
-                );
-            }
-        };
+    let signals = compiler_signals(&layout);
+    let compiler = resolve_compiler(binpath, &signals, compiler).unwrap_or_else(|e| panic!("{}", e));
+    log::info!(
+        "Verifying as {:?} (Lucet: {:?}, WAMR: {:?}, Wasmtime: {:?})",
+        compiler, signals.lucet, signals.wamr, signals.wasmtime
+    );
 
     let mut guest_table_0: u64 = 0;
     let mut lucet_tables: u64 = 0;
     let mut lucet_probestack: u64 = 0;
+    let mut lucet_safe_page: Option<u64> = None;
     match compiler {
-        Compiler::Wamr => {},
+        Compiler::Wamr | Compiler::Wasmtime => {},
         Compiler::Lucet => {
-            guest_table_0 = get_symbol_addr(symbols, "guest_table_0").unwrap();
-            lucet_tables = get_symbol_addr(symbols, "lucet_tables").unwrap();
-            lucet_probestack = get_symbol_addr(symbols, "lucet_probestack").unwrap();
-            println!(
+            // None if the module has no such symbol
+            let resolve = |canonical: &str| {
+                let table = symbols.iter().map(|(addr, name)| (name.as_str(), *addr));
+                match symbol_map.resolve(table, canonical) {
+                    Ok(addr) => Some(addr),
+                    Err(SymbolError::Missing { .. }) => None,
+                    Err(e) => panic!("{:?}: {}", binpath, e),
+                }
+            };
+            match (resolve("guest_table_0"), resolve("lucet_tables")) {
+                (Some(table_0), Some(tables)) => {
+                    guest_table_0 = table_0;
+                    lucet_tables = tables;
+                }
+                _ => {
+                    // a stripped module: the tables are found through its lucet_module structure
+                    let manifest = std::fs::read(binpath)
+                        .map_err(|e| e.to_string())
+                        .and_then(|module| load_manifest(&module))
+                        .unwrap_or_else(|e| panic!("{:?}: no table symbols, and no Lucet function manifest: {}", binpath, e));
+                    lucet_tables = manifest.tables;
+                    guest_table_0 = manifest.guest_table_0.unwrap_or(0);
+                }
+            }
+            lucet_probestack = resolve("lucet_probestack").unwrap_or_else(|| {
+                log::warn!("{:?}: no lucet_probestack symbol, so calls to it won't verify", binpath);
+                0
+            });
+            // only Spectre-mitigated modules have one
+            lucet_safe_page = resolve("lucet_safe_page");
+            if globals_size < 0 {
+                match std::fs::read(binpath).map_err(|e| e.to_string()).and_then(|module| load_globals_size(&module)) {
+                    Ok(size) => {
+                        log::info!("{:?}: {} bytes of globals", binpath, size);
+                        globals_size = size;
+                    }
+                    Err(e) => log::info!(
+                        "{:?}: can't read the size of the globals ({}), assuming {} bytes",
+                        binpath, e, LucetModel::GLOBALS_SIZE
+                    ),
+                }
+            }
+            log::info!(
                 "guest_table_0 = {:x} lucet_tables = {:x} probestack = {:x}",
                 guest_table_0, lucet_tables, lucet_probestack
             );
@@ -291,26 +593,32 @@ pub fn load_metadata(binpath: &str, compiler: Compiler, globals_size: i64) -> Co
     }
     CompilerMetadata {
         compiler: compiler,
+        model: compiler.model(),
         guest_table_0: guest_table_0,
         lucet_tables: lucet_tables,
         lucet_probestack: lucet_probestack,
+        lucet_safe_page: lucet_safe_page,
         globals_size: globals_size,
+        call_table_size: call_table_size,
+        caller_window: WAMR_CALLER_WINDOW,
+        strict_stack: false,
+        strict_rebound: false,
+        runtime_fields: vec![],
+        wamr_memories: Default::default(),
+        wamr_tables: None,
+        options: Default::default(),
+        abi: Default::default(),
+        readonly_data: layout.rodata.clone(),
+        wasmtime_layout: Default::default(),
     }
 }
 
-pub fn wamr_get_native_addrs(program: &ModuleData) -> Vec<u64> {
-    let (_, _sections, _entrypoint, _imports, _exports, symbols) =
-        match (program as &dyn MemoryRepr<<AMD64 as Arch>::Address>).module_info() {
-            Some(ModuleInfo::ELF(isa, _, _, sections, entry, _, imports, exports, symbols)) =>
-                (isa, sections, entry, imports, exports, symbols),
-            _ => panic!("unreachable!"),
-        };
+/// The WAMR runtime functions AOT code calls, by address and name.
+pub fn wamr_get_native_addrs(layout: &ModuleLayout) -> Vec<(u64, String)> {
     let mut result = vec![];
-    for native_func_name in vec!["aot_set_exception_with_id", 
-                                 "aot_invoke_native",
-                                 "wasm_runtime_enlarge_memory"] {
-        let addr = get_symbol_addr(symbols, native_func_name).unwrap();
-        result.push(addr);
+    for native_func_name in WAMR_SYMBOLS.iter() {
+        let addr = layout.symbol_addr(native_func_name).unwrap();
+        result.push((addr, native_func_name.to_string()));
     }
     result
 }
@@ -342,18 +650,82 @@ pub fn get_rsp_offset(memargs: &MemArgs) -> Option<i64> {
 // func name is valid if:
 // a. starts with guest_func_ and ends in a number (Lucet-specific)
 // b. starts with aot_func# (Wamr-specific)
-pub fn is_valid_func_name(name: &String, funcs: &Vec<u32>) -> bool {
-    if name.starts_with("guest_func_") {
-        return true;
-    }
-    if name.starts_with("aot_func#") {
-        let func_num_str = &name[9..];
-        if let Ok(func_num) = u32::from_str(&func_num_str) {
-            if funcs.contains(&func_num) {
-                return false;
-            }
-        }
-        return true;
-    }
-    false
+// c. is a Wasmtime function name
+// (trusted functions are left out before, see get_data)
+pub fn is_valid_func_name(name: &String) -> bool {
+    name.starts_with("guest_func_") || name.starts_with("aot_func#") || is_wasmtime_func_name(name)
+}
+
+#[test]
+fn detect_compiler_test() {
+    let lucet = CompilerSignals::from_names(vec!["guest_func_0", "guest_func_1", "lucet_probestack", "memcpy"].into_iter());
+    assert_eq!(lucet.lucet, vec!["lucet_probestack".to_string(), "2 guest_func_* functions".to_string()]);
+    assert_eq!(lucet.compiler(), Some(Compiler::Lucet));
+    let wamr = CompilerSignals::from_names(vec!["aot_func#0", "aot_invoke_native"].into_iter());
+    assert_eq!(wamr.compiler(), Some(Compiler::Wamr));
+    let both = CompilerSignals::from_names(vec!["guest_func_0", "aot_func#0"].into_iter());
+    assert_eq!(both.compiler(), None);
+    let wasmtime = CompilerSignals::from_names(vec!["wasm[0]::function[0]", "_wasm_function_1", "wasm[0]::array_to_wasm_trampoline[0]"].into_iter());
+    assert_eq!(wasmtime.wasmtime, vec!["2 Wasmtime functions".to_string()]);
+    assert_eq!(wasmtime.compiler(), Some(Compiler::Wasmtime));
+    assert!(is_valid_func_name(&"wasm[0]::function[3]".to_string()));
+
+    assert_eq!(resolve_compiler("m.so", &lucet, None), Ok(Compiler::Lucet));
+    assert_eq!(resolve_compiler("m.so", &lucet, Some(Compiler::Lucet)), Ok(Compiler::Lucet));
+    let mismatch = resolve_compiler("m.so", &lucet, Some(Compiler::Wamr)).unwrap_err();
+    assert!(mismatch.contains("compiled by Lucet (found lucet_probestack, 2 guest_func_* functions), not Wamr"));
+    // an explicit flag settles an ambiguous module
+    assert_eq!(resolve_compiler("m.so", &both, Some(Compiler::Wamr)), Ok(Compiler::Wamr));
+    let ambiguous = resolve_compiler("m.so", &both, None).unwrap_err();
+    assert!(ambiguous.contains("Lucet: found 1 guest_func_* functions; WAMR: found 1 aot_func#* functions; Wasmtime: found nothing"));
+    assert!(resolve_compiler("m.so", &CompilerSignals::default(), None).unwrap_err().contains("found nothing"));
+}
+
+#[test]
+fn disjoint_plt_test() {
+    let plt = (0x1000, 0x1100);
+    assert_eq!(disjoint_plt(plt, &[0x100, 0x2000]), (plt, vec![]));
+    // the range ends exactly where a function starts: no overlap
+    assert_eq!(disjoint_plt(plt, &[0x1100]), (plt, vec![]));
+    assert_eq!(disjoint_plt(plt, &[0x2000, 0x10a0, 0x1040]), ((0x1000, 0x1040), vec![0x1040, 0x10a0]));
+    assert_eq!(disjoint_plt(plt, &[0x1000]), ((0x1000, 0x1000), vec![0x1000]));
+    assert_eq!(disjoint_plt((0, 0), &[0x0]), ((0, 0), vec![]));
+}
+
+#[test]
+fn drive_resolution_test() {
+    let limits = CfgLimits { max_rounds: 8, max_targets: 100 };
+    let targets = |switches: Vec<(u64, Vec<i64>)>| switches.into_iter().collect::<HashMap<u64, Vec<i64>>>();
+    // runs `round` (given the number of the round) from round 0, returning the last round run
+    let drive = |round: &dyn Fn(usize) -> (HashMap<u64, Vec<i64>>, u32)| {
+        drive_resolution(0x1000, 0usize, &limits, |done| {
+            let (switch_targets, still_unresolved) = round(*done + 1);
+            Ok((*done + 1, switch_targets, still_unresolved))
+        })
+    };
+
+    // two switches resolved in one round
+    let both = |_: usize| (targets(vec![(0x10, vec![0x20, 0x30]), (0x40, vec![0x50, 0x60])]), 0);
+    assert_eq!(drive(&both), Ok(1));
+    // the second switch is in a block only the first one's targets lead to
+    let nested = |round: usize| match round {
+        1 => (targets(vec![(0x10, vec![0x20, 0x30])]), 1),
+        _ => (targets(vec![(0x10, vec![0x20, 0x30]), (0x30, vec![0x50, 0x60])]), 0),
+    };
+    assert_eq!(drive(&nested), Ok(2));
+    // a jump nothing resolves, which the jump checker reports
+    let stuck = |_: usize| (targets(vec![(0x10, vec![0x20])]), 1);
+    assert_eq!(drive(&stuck), Ok(2));
+
+    // a switch among its own targets whose bound differs every other round
+    let oscillating = |round: usize| {
+        let other = if round % 2 == 1 { 0x50 } else { 0x60 };
+        (targets(vec![(0x40, vec![0x40, other])]), 1)
+    };
+    assert_eq!(drive(&oscillating), Err(CfgError::UnstableTargets(0x1000, 0x40, 0x50)));
+    // one that finds one more target every round
+    let growing = |round: usize| (targets(vec![(0x40, (0..round as i64).map(|i| 0x100 + i).collect())]), 1);
+    assert_eq!(drive(&growing), Err(CfgError::ResolutionLimit(0x1000, 8)));
+    let wide = |_: usize| (targets(vec![(0x10, (0..101).collect()), (0x40, vec![0x50])]), 0);
+    assert_eq!(drive(&wide), Err(CfgError::TooManyTargets(0x1000, 102, 100)));
 }
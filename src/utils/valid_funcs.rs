@@ -0,0 +1,325 @@
+use serde_json::{json, Value as Json};
+use std::collections::BTreeMap;
+use std::fs;
+
+/*
+ * The functions a direct call may target, and where each of them came from. The set is
+ * assembled from the module's symbols (or its Lucet function manifest) and, for WAMR, the
+ * runtime's native functions, so it moves with symbol parsing and the native-function
+ * heuristics as much as with the module. A direct call that fails names the entries nearest its
+ * target and their source, and `--funcs-snapshot` / `--funcs-expect` write the set out and check
+ * it against an earlier snapshot, so that a change of the set shows up as such.
+ */
+
+/// Where an entry of the set came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FuncSource {
+    // a function symbol of the module
+    Symbol,
+    // the Lucet function manifest, for a module without function symbols (or with --no-symbols)
+    Manifest,
+    // a WAMR runtime function AOT code calls, found by its symbol
+    Native,
+    // a module function named with -t, callable without being verified
+    Trusted,
+    // a function found only through a function table (see table_funcs.rs)
+    Table,
+}
+
+impl FuncSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FuncSource::Symbol => "symbol",
+            FuncSource::Manifest => "manifest",
+            FuncSource::Native => "native",
+            FuncSource::Trusted => "trusted",
+            FuncSource::Table => "table",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<FuncSource> {
+        match name {
+            "symbol" => Some(FuncSource::Symbol),
+            "manifest" => Some(FuncSource::Manifest),
+            "native" => Some(FuncSource::Native),
+            "trusted" => Some(FuncSource::Trusted),
+            "table" => Some(FuncSource::Table),
+            _ => None,
+        }
+    }
+}
+
+/// A function `-t` names, which is left unverified but stays a valid call target: by its number
+/// (`aot_func#<n>` or `guest_func_<n>`), its symbol name, or its address in hex.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TrustedFunc {
+    Number(u32),
+    Name(String),
+    Addr(u64),
+}
+
+impl TrustedFunc {
+    pub fn matches(&self, addr: u64, name: &str) -> bool {
+        match self {
+            TrustedFunc::Addr(trusted) => addr == *trusted,
+            _ => self.matches_name(name),
+        }
+    }
+
+    /// Whether the function named `name` is this one, which an address never says.
+    pub fn matches_name(&self, name: &str) -> bool {
+        match self {
+            TrustedFunc::Number(n) => name == format!("aot_func#{}", n) || name == format!("guest_func_{}", n),
+            TrustedFunc::Name(trusted) => name == trusted,
+            TrustedFunc::Addr(_) => false,
+        }
+    }
+}
+
+impl std::str::FromStr for TrustedFunc {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(n) = s.parse::<u32>() {
+            return Ok(TrustedFunc::Number(n));
+        }
+        if let Some(hex) = s.strip_prefix("0x") {
+            return u64::from_str_radix(hex, 16).map(TrustedFunc::Addr).map_err(|_| format!("invalid address {}", s));
+        }
+        if s.is_empty() {
+            return Err("empty function name".to_string());
+        }
+        Ok(TrustedFunc::Name(s.to_string()))
+    }
+}
+
+impl std::fmt::Display for TrustedFunc {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TrustedFunc::Number(n) => write!(f, "{}", n),
+            TrustedFunc::Name(name) => write!(f, "{}", name),
+            TrustedFunc::Addr(addr) => write!(f, "0x{:x}", addr),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidFunc {
+    pub name: String,
+    pub source: FuncSource,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ValidFuncs {
+    funcs: BTreeMap<u64, ValidFunc>,
+}
+
+/// What to do when the set differs from the one `--funcs-expect` gives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FuncsDriftPolicy {
+    Fail,
+    Warn,
+}
+
+impl std::str::FromStr for FuncsDriftPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(FuncsDriftPolicy::Fail),
+            "warn" => Ok(FuncsDriftPolicy::Warn),
+            _ => Err(format!("unknown policy {} (expected fail or warn)", s)),
+        }
+    }
+}
+
+/// The entries only in the current set, the ones only in the expected set, and the ones in both
+/// whose source changed, each in address order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FuncsDiff {
+    pub appeared: Vec<(u64, ValidFunc)>,
+    pub disappeared: Vec<(u64, ValidFunc)>,
+    pub moved: Vec<(u64, FuncSource, FuncSource)>,
+}
+
+impl FuncsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.appeared.is_empty() && self.disappeared.is_empty() && self.moved.is_empty()
+    }
+
+    /// One line per difference.
+    pub fn lines(&self) -> Vec<String> {
+        let appeared = self.appeared.iter().map(|(addr, func)| format!("+ 0x{:x} {} ({})", addr, func.name, func.source.name()));
+        let disappeared = self.disappeared.iter().map(|(addr, func)| format!("- 0x{:x} {} ({})", addr, func.name, func.source.name()));
+        let moved = self.moved.iter().map(|(addr, was, is)| format!("~ 0x{:x} {} -> {}", addr, was.name(), is.name()));
+        appeared.chain(disappeared).chain(moved).collect()
+    }
+}
+
+impl ValidFuncs {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The set of `addrs`, all from `source`.
+    pub fn from_addrs(addrs: &[u64], source: FuncSource) -> Self {
+        let mut funcs = ValidFuncs::new();
+        for addr in addrs {
+            funcs.insert(*addr, &format!("0x{:x}", addr), source);
+        }
+        funcs
+    }
+
+    /// Adds a function, unless the set already has one at `addr`, whose source is kept.
+    pub fn insert(&mut self, addr: u64, name: &str, source: FuncSource) {
+        self.funcs.entry(addr).or_insert_with(|| ValidFunc { name: name.to_string(), source: source });
+    }
+
+    pub fn remove(&mut self, addr: u64) {
+        self.funcs.remove(&addr);
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.funcs.contains_key(&addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.funcs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.funcs.is_empty()
+    }
+
+    pub fn count(&self, source: FuncSource) -> usize {
+        self.funcs.values().filter(|func| func.source == source).count()
+    }
+
+    /// The addresses of the set, in order.
+    pub fn addrs(&self) -> Vec<u64> {
+        self.funcs.keys().cloned().collect()
+    }
+
+    /// The entries right below and right above `target`.
+    pub fn nearest(&self, target: u64) -> (Option<(u64, &ValidFunc)>, Option<(u64, &ValidFunc)>) {
+        let below = self.funcs.range(..target).next_back().map(|(addr, func)| (*addr, func));
+        let above = self.funcs.range(target..).next().map(|(addr, func)| (*addr, func));
+        (below, above)
+    }
+
+    /// Names the entries nearest `target` and their source, for a call to it that isn't to an entry.
+    pub fn describe_nearest(&self, target: u64) -> String {
+        let describe = |(addr, func): (u64, &ValidFunc)| format!("0x{:x} {} ({})", addr, func.name, func.source.name());
+        match self.nearest(target) {
+            (None, None) => "no functions are valid targets".to_string(),
+            (Some(below), None) => format!("nearest valid target: {} below", describe(below)),
+            (None, Some(above)) => format!("nearest valid target: {} above", describe(above)),
+            (Some(below), Some(above)) => format!("nearest valid targets: {} below, {} above", describe(below), describe(above)),
+        }
+    }
+
+    pub fn diff(&self, expected: &ValidFuncs) -> FuncsDiff {
+        let mut diff = FuncsDiff::default();
+        for (addr, func) in self.funcs.iter() {
+            match expected.funcs.get(addr) {
+                None => diff.appeared.push((*addr, func.clone())),
+                Some(was) if was.source != func.source => diff.moved.push((*addr, was.source, func.source)),
+                Some(_) => (),
+            }
+        }
+        for (addr, func) in expected.funcs.iter() {
+            if !self.funcs.contains_key(addr) {
+                diff.disappeared.push((*addr, func.clone()));
+            }
+        }
+        diff
+    }
+
+    pub fn to_json(&self) -> Json {
+        let funcs: Vec<Json> = self
+            .funcs
+            .iter()
+            .map(|(addr, func)| json!({"addr": addr, "name": func.name, "source": func.source.name()}))
+            .collect();
+        json!({ "funcs": funcs })
+    }
+
+    pub fn from_json(json: &Json) -> Result<ValidFuncs, String> {
+        let entries = json.get("funcs").and_then(|funcs| funcs.as_array()).ok_or("expected a funcs list")?;
+        let mut funcs = ValidFuncs::new();
+        for entry in entries {
+            let addr = entry.get("addr").and_then(|addr| addr.as_u64());
+            let name = entry.get("name").and_then(|name| name.as_str());
+            let source = entry.get("source").and_then(|source| source.as_str());
+            match (addr, name, source.and_then(FuncSource::from_name)) {
+                (Some(addr), Some(name), Some(source)) => funcs.insert(addr, name, source),
+                _ => return Err(format!("malformed entry {}", entry)),
+            }
+        }
+        Ok(funcs)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_json().to_string()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<ValidFuncs, String> {
+        let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        serde_json::from_str(&data)
+            .map_err(|e| e.to_string())
+            .and_then(|json| ValidFuncs::from_json(&json))
+            .map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+#[test]
+fn valid_funcs_test() {
+    let mut funcs = ValidFuncs::new();
+    funcs.insert(0x1000, "aot_func#0", FuncSource::Symbol);
+    funcs.insert(0x1200, "aot_func#1", FuncSource::Symbol);
+    funcs.insert(0x8000, "aot_invoke_native", FuncSource::Native);
+    // the first source of an address is kept
+    funcs.insert(0x1200, "aot_invoke_native", FuncSource::Native);
+    assert_eq!(funcs.count(FuncSource::Symbol), 2);
+    assert_eq!(ValidFuncs::from_json(&funcs.to_json()), Ok(funcs.clone()));
+
+    assert_eq!(
+        funcs.describe_nearest(0x1205),
+        "nearest valid targets: 0x1200 aot_func#1 (symbol) below, 0x8000 aot_invoke_native (native) above"
+    );
+    assert_eq!(funcs.describe_nearest(0x9000), "nearest valid target: 0x8000 aot_invoke_native (native) below");
+    assert_eq!(ValidFuncs::new().describe_nearest(0x9000), "no functions are valid targets");
+
+    // a snapshot taken when the native function was still found
+    let snapshot = funcs.clone();
+    let mut current = ValidFuncs::new();
+    current.insert(0x1000, "aot_func#0", FuncSource::Symbol);
+    current.insert(0x1200, "aot_func#1", FuncSource::Native);
+    let diff = current.diff(&snapshot);
+    assert!(diff.appeared.is_empty());
+    assert_eq!(diff.disappeared, vec![(0x8000, ValidFunc { name: "aot_invoke_native".to_string(), source: FuncSource::Native })]);
+    assert_eq!(diff.lines(), vec!["- 0x8000 aot_invoke_native (native)", "~ 0x1200 symbol -> native"]);
+    assert!(funcs.diff(&snapshot).is_empty());
+
+    assert!(ValidFuncs::from_json(&json!({"funcs": [{"addr": 16, "name": "f", "source": "heuristic"}]})).is_err());
+    assert_eq!("warn".parse::<FuncsDriftPolicy>(), Ok(FuncsDriftPolicy::Warn));
+}
+
+#[test]
+fn trusted_func_test() {
+    let trusted: Vec<TrustedFunc> = "3,lucet_builtin_stub,0x4010".split(',').map(|s| s.parse().unwrap()).collect();
+    assert_eq!(
+        trusted,
+        vec![TrustedFunc::Number(3), TrustedFunc::Name("lucet_builtin_stub".to_string()), TrustedFunc::Addr(0x4010)]
+    );
+    // a number is a WAMR or a Lucet function
+    assert!(trusted[0].matches(0x1000, "aot_func#3"));
+    assert!(trusted[0].matches(0x1000, "guest_func_3"));
+    assert!(!trusted[0].matches(0x1000, "guest_func_33"));
+    assert!(trusted[1].matches(0x2000, "lucet_builtin_stub"));
+    assert!(trusted[2].matches(0x4010, "guest_func_7"));
+    assert!(!trusted[2].matches(0x4011, "guest_func_7"));
+    let names: Vec<String> = trusted.iter().map(|f| f.to_string()).collect();
+    assert_eq!(names, vec!["3", "lucet_builtin_stub", "0x4010"]);
+    assert!("0xzz".parse::<TrustedFunc>().is_err());
+    assert!("".parse::<TrustedFunc>().is_err());
+    assert_eq!(FuncSource::from_name(FuncSource::Trusted.name()), Some(FuncSource::Trusted));
+}
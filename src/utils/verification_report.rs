@@ -0,0 +1,336 @@
+use crate::checkers::CheckFailure;
+use crate::utils::caveats::{merge_caveat, CaveatKind, SoundnessCaveat};
+use serde_json::{json, Value as Json};
+
+/*
+ * The outcome of a run, function by function: whether each property was verified and, for a
+ * function that failed, why and at which statement. The properties are checked in the order
+ * below and a function's verification stops at the first that fails, so the ones after it are
+ * left unchecked.
+ */
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Property {
+    Cfi,
+    Heap,
+    Stack,
+    Call,
+}
+
+impl Property {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Property::Cfi => "cfi",
+            Property::Heap => "heap",
+            Property::Stack => "stack",
+            Property::Call => "call",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PropertyResult {
+    Passed,
+    Failed,
+    // an earlier property failed, or the failure was replayed from the failure cache
+    Unchecked,
+}
+
+impl PropertyResult {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PropertyResult::Passed => "passed",
+            PropertyResult::Failed => "failed",
+            PropertyResult::Unchecked => "unchecked",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PropertyResult> {
+        match name {
+            "passed" => Some(PropertyResult::Passed),
+            "failed" => Some(PropertyResult::Failed),
+            "unchecked" => Some(PropertyResult::Unchecked),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionResult {
+    pub name: String,
+    pub addr: u64,
+    // every indirect jump was resolved to blocks of the function
+    pub cfi: PropertyResult,
+    pub heap: PropertyResult,
+    pub stack: PropertyResult,
+    pub call: PropertyResult,
+    // None if the function was verified
+    pub reason: Option<String>,
+    // the statement a checker rejected, if the failure came down to one
+    pub failing_addr: Option<u64>,
+    pub failing_stmt: Option<String>,
+    // why it was rejected, and the abstract state right before it
+    pub failing_description: Option<String>,
+    pub failing_state: Option<String>,
+    // the machine instruction the statement was lifted from, as hex bytes and disassembly
+    pub failing_bytes: Option<String>,
+    pub failing_disasm: Option<String>,
+    // a `--patch` patch touches the function's code or jump tables, whatever the verdict
+    pub patched: bool,
+}
+
+impl FunctionResult {
+    pub fn verified(name: &str, addr: u64) -> FunctionResult {
+        FunctionResult {
+            name: name.to_string(),
+            addr: addr,
+            cfi: PropertyResult::Passed,
+            heap: PropertyResult::Passed,
+            stack: PropertyResult::Passed,
+            call: PropertyResult::Passed,
+            reason: None,
+            failing_addr: None,
+            failing_stmt: None,
+            failing_description: None,
+            failing_state: None,
+            failing_bytes: None,
+            failing_disasm: None,
+            patched: false,
+        }
+    }
+
+    /// A function that failed `property`, having passed the ones checked before it. `property` is
+    /// None when the failure isn't down to a single property (a panic, or a cached failure).
+    pub fn failed(name: &str, addr: u64, property: Option<Property>, reason: &str, site: Option<&CheckFailure>) -> FunctionResult {
+        let result = |p: Property| match property {
+            Some(failed) if failed == p => PropertyResult::Failed,
+            Some(failed) if (p as usize) < (failed as usize) => PropertyResult::Passed,
+            _ => PropertyResult::Unchecked,
+        };
+        FunctionResult {
+            name: name.to_string(),
+            addr: addr,
+            cfi: result(Property::Cfi),
+            heap: result(Property::Heap),
+            stack: result(Property::Stack),
+            call: result(Property::Call),
+            reason: Some(reason.to_string()),
+            failing_addr: site.map(|site| site.loc.addr),
+            failing_stmt: site.map(|site| format!("{:?}", site.stmt)),
+            failing_description: site.map(|site| site.description.clone()),
+            failing_state: site.map(|site| site.state.clone()).filter(|state| !state.is_empty()),
+            failing_bytes: None,
+            failing_disasm: None,
+            patched: false,
+        }
+    }
+
+    /// The property the function failed, if its failure came down to one.
+    pub fn rule_id(&self) -> Option<&'static str> {
+        vec![("cfi", self.cfi), ("heap", self.heap), ("stack", self.stack), ("call", self.call)]
+            .into_iter()
+            .find(|(_, result)| *result == PropertyResult::Failed)
+            .map(|(name, _)| name)
+    }
+
+    /// Records the instruction the failing statement was lifted from.
+    pub fn set_failing_instruction(&mut self, bytes: &[u8], disasm: &str) {
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        self.failing_bytes = Some(hex.join(" "));
+        self.failing_disasm = Some(disasm.to_string());
+    }
+
+    pub fn passed(&self) -> bool {
+        self.reason.is_none()
+    }
+
+    pub fn to_json(&self) -> Json {
+        json!({
+            "name": self.name,
+            "addr": self.addr,
+            "cfi": self.cfi.name(),
+            "heap": self.heap.name(),
+            "stack": self.stack.name(),
+            "call": self.call.name(),
+            "reason": self.reason,
+            "failing_addr": self.failing_addr,
+            "failing_stmt": self.failing_stmt,
+            "failing_description": self.failing_description,
+            "failing_state": self.failing_state,
+            "failing_bytes": self.failing_bytes,
+            "failing_disasm": self.failing_disasm,
+            "patched": self.patched,
+        })
+    }
+
+    pub fn from_json(json: &Json) -> Option<FunctionResult> {
+        let property = |key: &str| PropertyResult::from_name(json.get(key)?.as_str()?);
+        let string = |key: &str| json.get(key).and_then(|s| s.as_str()).map(|s| s.to_string());
+        Some(FunctionResult {
+            name: json.get("name")?.as_str()?.to_string(),
+            addr: json.get("addr")?.as_u64()?,
+            cfi: property("cfi")?,
+            heap: property("heap")?,
+            stack: property("stack")?,
+            call: property("call")?,
+            reason: string("reason"),
+            failing_addr: json.get("failing_addr").and_then(|a| a.as_u64()),
+            failing_stmt: string("failing_stmt"),
+            failing_description: string("failing_description"),
+            failing_state: string("failing_state"),
+            failing_bytes: string("failing_bytes"),
+            failing_disasm: string("failing_disasm"),
+            // reports from before `--patch` don't have it
+            patched: json.get("patched").and_then(|p| p.as_bool()).unwrap_or(false),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerificationReport {
+    // in address order
+    pub functions: Vec<FunctionResult>,
+    // the digest of the `--patch` patches the module was verified with
+    pub patch_digest: Option<String>,
+    // the shortcuts and heuristics the verdict rests on, see caveats.rs
+    pub caveats: Vec<SoundnessCaveat>,
+}
+
+impl VerificationReport {
+    pub fn add(&mut self, func: FunctionResult) {
+        self.functions.push(func);
+    }
+
+    pub fn failures(&self) -> Vec<&FunctionResult> {
+        self.functions.iter().filter(|f| !f.passed()).collect()
+    }
+
+    pub fn add_caveat(&mut self, caveat: SoundnessCaveat) {
+        merge_caveat(&mut self.caveats, caveat);
+    }
+
+    /// Marks the caveats of the given kinds as denied, which fails the module.
+    pub fn deny_caveats(&mut self, kinds: &[CaveatKind]) {
+        for caveat in self.caveats.iter_mut() {
+            caveat.denied = kinds.contains(&caveat.kind);
+        }
+    }
+
+    pub fn denied_caveats(&self) -> Vec<&SoundnessCaveat> {
+        self.caveats.iter().filter(|c| c.denied).collect()
+    }
+
+    /// The instructions the lifter skipped (under `--unknown-instr ignore`), with their opcode and
+    /// how often each was lifted.
+    pub fn skipped_instructions(&self) -> Vec<Json> {
+        self.caveats
+            .iter()
+            .filter(|c| c.kind == CaveatKind::UnliftedInstruction)
+            .map(|c| json!({"function": c.function, "addr": c.addr, "opcode": c.opcode(), "count": c.count}))
+            .collect()
+    }
+
+    pub fn all_verified(&self) -> bool {
+        self.functions.iter().all(|f| f.passed()) && self.denied_caveats().is_empty()
+    }
+
+    pub fn to_json(&self) -> Json {
+        let functions: Vec<Json> = self.functions.iter().map(|f| f.to_json()).collect();
+        let caveats: Vec<Json> = self.caveats.iter().map(|c| c.to_json()).collect();
+        json!({
+            "verified": self.functions.len() - self.failures().len(),
+            "failed": self.failures().len(),
+            "functions": functions,
+            "patch_digest": self.patch_digest,
+            "caveats": caveats,
+            "skipped_instructions": self.skipped_instructions(),
+        })
+    }
+}
+
+#[test]
+fn verification_report_test() {
+    use crate::lattices::reachingdefslattice::LocIdx;
+    use crate::utils::ir::Stmt;
+
+    let mut report = VerificationReport::default();
+    report.add(FunctionResult::verified("guest_func_0", 0x1000));
+    assert!(report.all_verified());
+
+    let site = CheckFailure {
+        loc: LocIdx { addr: 0x2010, idx: 0 },
+        stmt: Stmt::Ret,
+        description: "stack growth -8 at ret".to_string(),
+        state: "ConstLattice { v: Some((-8, 4096, {})) }".to_string(),
+    };
+    let mut stack = FunctionResult::failed("guest_func_1", 0x2000, Some(Property::Stack), "Not Stack Safe", Some(&site));
+    stack.set_failing_instruction(&[0xc3], "ret");
+    report.add(stack);
+    report.add(FunctionResult::failed("guest_func_2", 0x3000, None, "Not Heap Safe (cached)", None));
+    assert!(!report.all_verified());
+    assert_eq!(report.failures().len(), 2);
+
+    let stack = &report.functions[1];
+    assert_eq!((stack.cfi, stack.heap, stack.stack, stack.call), (PropertyResult::Passed, PropertyResult::Passed, PropertyResult::Failed, PropertyResult::Unchecked));
+    let cached = &report.functions[2];
+    assert_eq!((cached.heap, cached.stack, cached.call), (PropertyResult::Unchecked, PropertyResult::Unchecked, PropertyResult::Unchecked));
+    let cfi = FunctionResult::failed("guest_func_3", 0x4000, Some(Property::Cfi), "Not CFI Safe", None);
+    assert_eq!((cfi.cfi, cfi.heap), (PropertyResult::Failed, PropertyResult::Unchecked));
+
+    let json = report.to_json();
+    assert_eq!(json["verified"], 1);
+    assert_eq!(json["failed"], 2);
+    assert_eq!(json["functions"][0]["reason"], Json::Null);
+    assert_eq!(json["functions"][1]["stack"], "failed");
+    assert_eq!(json["functions"][1]["failing_addr"], 0x2010);
+    assert_eq!(json["functions"][1]["failing_stmt"], "Ret");
+    assert_eq!(json["functions"][1]["failing_description"], "stack growth -8 at ret");
+    assert_eq!(json["functions"][1]["failing_bytes"], "c3");
+    assert_eq!(json["functions"][1]["failing_disasm"], "ret");
+    assert_eq!(json["functions"][2]["failing_stmt"], Json::Null);
+    assert_eq!(json["functions"][0]["patched"], false);
+    assert_eq!(json["patch_digest"], Json::Null);
+    for func in &report.functions {
+        assert_eq!(FunctionResult::from_json(&func.to_json()).as_ref(), Some(func));
+    }
+
+    let mut patched = FunctionResult::verified("guest_func_4", 0x5000);
+    patched.patched = true;
+    assert_eq!(FunctionResult::from_json(&patched.to_json()), Some(patched.clone()));
+    let mut old = patched.to_json();
+    old.as_object_mut().unwrap().remove("patched");
+    assert_eq!(FunctionResult::from_json(&old).map(|f| f.patched), Some(false));
+    report.patch_digest = Some("0123456789abcdef".to_string());
+    assert_eq!(report.to_json()["patch_digest"], "0123456789abcdef");
+
+    let mut caveats = VerificationReport::default();
+    caveats.add(FunctionResult::verified("guest_func_0", 0x1000));
+    let trusted = SoundnessCaveat::new(CaveatKind::TrustedFunction, Some(0x2000), "guest_func_1 is trusted".to_string());
+    caveats.add_caveat(trusted.clone());
+    caveats.add_caveat(trusted);
+    caveats.add_caveat(SoundnessCaveat::new(CaveatKind::ShrunkPlt, Some(0x400), "PLT ends at 0x400".to_string()));
+    assert_eq!(caveats.caveats.len(), 2);
+    assert_eq!(caveats.caveats[0].count, 2);
+    // caveats are reported, and only fail the module once their kind is denied
+    caveats.deny_caveats(&[]);
+    assert!(caveats.all_verified());
+    assert_eq!(caveats.to_json()["caveats"][1]["kind"], "shrunk_plt");
+    caveats.deny_caveats(&[CaveatKind::TrustedFunction]);
+    assert!(!caveats.all_verified());
+    assert!(caveats.failures().is_empty());
+    assert_eq!(caveats.denied_caveats().len(), 1);
+    assert_eq!(caveats.to_json()["caveats"][0]["denied"], true);
+
+    // skipped instructions are listed on their own too, by opcode
+    assert_eq!(caveats.to_json()["skipped_instructions"], json!([]));
+    let skipped = SoundnessCaveat {
+        function: Some("guest_func_0".to_string()),
+        ..SoundnessCaveat::new(CaveatKind::UnliftedInstruction, Some(0x1008), "unimplemented instr CPUID: cpuid".to_string())
+    };
+    caveats.add_caveat(skipped.clone());
+    caveats.add_caveat(skipped);
+    assert_eq!(
+        caveats.to_json()["skipped_instructions"],
+        json!([{"function": "guest_func_0", "addr": 0x1008, "opcode": "CPUID", "count": 2}])
+    );
+}
@@ -0,0 +1,351 @@
+//! Accounting of which Wamr layout constants the heap checker's whitelist rules matched.
+//!
+//! Every ExecEnv/ModuleInstance field the generated code may touch is reached through one of
+//! these rules, so a constant that never matches on a module is either irrelevant to it or
+//! wrong, and one that matches far more often than the function count suggests may be
+//! colliding with a different field.
+
+use crate::lattices::heaplattice::{
+    WAMR_EXCEPTION_OFFSET, WAMR_FUNCPTRS_OFFSET, WAMR_FUNCTYPE_OFFSET, WAMR_GLOBALS_OFFSET,
+    WAMR_HEAPBASE_OFFSET, WAMR_MEMBOUNDS_OFFSET, WAMR_MODULEINSTANCE_OFFSET,
+    WAMR_PAGECNT_OFFSET, WAMR_STACKLIMIT_OFFSET,
+};
+use std::fmt;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const WAMR_LAYOUT: [(&str, i64); 9] = [
+    ("WAMR_MODULEINSTANCE_OFFSET", WAMR_MODULEINSTANCE_OFFSET),
+    ("WAMR_STACKLIMIT_OFFSET", WAMR_STACKLIMIT_OFFSET),
+    ("WAMR_HEAPBASE_OFFSET", WAMR_HEAPBASE_OFFSET),
+    ("WAMR_EXCEPTION_OFFSET", WAMR_EXCEPTION_OFFSET),
+    ("WAMR_MEMBOUNDS_OFFSET", WAMR_MEMBOUNDS_OFFSET),
+    ("WAMR_GLOBALS_OFFSET", WAMR_GLOBALS_OFFSET),
+    ("WAMR_FUNCPTRS_OFFSET", WAMR_FUNCPTRS_OFFSET),
+    ("WAMR_FUNCTYPE_OFFSET", WAMR_FUNCTYPE_OFFSET),
+    ("WAMR_PAGECNT_OFFSET", WAMR_PAGECNT_OFFSET),
+];
+
+// matches per verified function above which a constant is reported as a possible collision
+pub const SUSPICIOUS_USES_PER_FUNCTION: usize = 64;
+
+static WAMR_LAYOUT_USES: [AtomicUsize; 9] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Records that a rule keyed on the layout constant with value `offset` matched.
+pub fn record_wamr_layout_use(offset: i64) {
+    if let Some(idx) = WAMR_LAYOUT.iter().position(|(_, value)| *value == offset) {
+        WAMR_LAYOUT_USES[idx].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn wamr_layout_uses() -> Vec<(&'static str, usize)> {
+    WAMR_LAYOUT
+        .iter()
+        .zip(WAMR_LAYOUT_USES.iter())
+        .map(|((name, _), uses)| (*name, uses.load(Ordering::Relaxed)))
+        .collect()
+}
+
+/// Zeroes the counts, at the start of each verification. They aren't kept per run, so two
+/// modules verified at the same time in one process count each other's matches too.
+pub fn reset_wamr_layout_uses() {
+    for uses in WAMR_LAYOUT_USES.iter() {
+        uses.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Splits the constants into those that never matched and those that matched suspiciously
+/// often for `num_funcs` verified functions.
+pub fn unusual_wamr_layout_uses(num_funcs: usize) -> (Vec<&'static str>, Vec<&'static str>) {
+    classify_uses(&wamr_layout_uses(), num_funcs)
+}
+
+fn classify_uses(uses: &Vec<(&'static str, usize)>, num_funcs: usize) -> (Vec<&'static str>, Vec<&'static str>) {
+    let unused = uses.iter().filter(|(_, n)| *n == 0).map(|(name, _)| *name).collect();
+    let frequent = uses
+        .iter()
+        .filter(|(_, n)| *n > SUSPICIOUS_USES_PER_FUNCTION * std::cmp::max(num_funcs, 1))
+        .map(|(name, _)| *name)
+        .collect();
+    (unused, frequent)
+}
+
+/// A ModuleInstance field the runtime expects generated code to write, e.g. a lock word that
+/// shared-memory code spins on with `xchg`. These vary by WAMR configuration, so they come from
+/// a layout file (`--wamr-layout`) rather than a constant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RuntimeField {
+    pub offset: i64,
+    pub width: i64,
+}
+
+impl RuntimeField {
+    /// Whether an access of `size` bytes at `offset` lies within the field.
+    pub fn contains(&self, offset: i64, size: i64) -> bool {
+        offset >= self.offset && offset + size <= self.offset + self.width
+    }
+}
+
+/// Where a ModuleInstance keeps a linear memory's base and bound. A runtime built with
+/// multi-memory has an array of memory instances, each with its own pair.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WamrMemory {
+    pub heap_base: i64,
+    pub mem_bound: i64,
+}
+
+// The access widths a bounds-check build keeps a memory bound for, in the order of their fields
+pub const WAMR_BOUND_WIDTHS: [u32; 5] = [1, 2, 4, 8, 16];
+
+impl WamrMemory {
+    /// The field of the bound for accesses `width` bytes wide, one of WAMR_BOUND_WIDTHS. A
+    /// bounds-check build keeps one 8-byte bound per width (mem_bound_check_1byte to
+    /// mem_bound_check_16bytes), each the memory size less the width, from `mem_bound` on.
+    pub fn bound_offset(&self, width: u32) -> i64 {
+        self.mem_bound + 8 * width.trailing_zeros() as i64
+    }
+}
+
+/// The linear memories of a ModuleInstance, by default the single one at WAMR_HEAPBASE_OFFSET
+/// and WAMR_MEMBOUNDS_OFFSET.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WamrMemories(pub Vec<WamrMemory>);
+
+impl Default for WamrMemories {
+    fn default() -> Self {
+        WamrMemories(vec![WamrMemory { heap_base: WAMR_HEAPBASE_OFFSET, mem_bound: WAMR_MEMBOUNDS_OFFSET }])
+    }
+}
+
+impl WamrMemories {
+    /// Parses a `base:bound,...` list of hex offsets, as written by Display.
+    pub fn parse(s: &str) -> Result<WamrMemories, String> {
+        let mut memories = Vec::new();
+        for memory in s.split(',').map(|memory| memory.trim()).filter(|memory| !memory.is_empty()) {
+            let offsets: Vec<Option<i64>> = memory.split(':').map(parse_offset).collect();
+            match offsets.as_slice() {
+                [Some(heap_base), Some(mem_bound)] => memories.push(WamrMemory { heap_base: *heap_base, mem_bound: *mem_bound }),
+                _ => return Err(format!("expected base:bound, got {:?}", memory)),
+            }
+        }
+        if memories.is_empty() {
+            return Err("no memories".to_string());
+        }
+        Ok(WamrMemories(memories))
+    }
+}
+
+impl fmt::Display for WamrMemories {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let memories: Vec<String> = self.0.iter().map(|m| format!("0x{:x}:0x{:x}", m.heap_base, m.mem_bound)).collect();
+        write!(f, "{}", memories.join(","))
+    }
+}
+
+/// Where a ModuleInstance built with multiple funcref tables keeps them: an array of `count`
+/// pointers to table descriptors at offset `descriptors`, each descriptor holding its element
+/// count (4 bytes) at `size_field` and a pointer to its function pointers at `base_field`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WamrTables {
+    pub descriptors: i64,
+    pub count: u32,
+    pub size_field: i64,
+    pub base_field: i64,
+}
+
+impl WamrTables {
+    /// The table whose descriptor pointer is `width` bytes at ModuleInstance offset `offset`,
+    /// if that is exactly one of the array's entries.
+    pub fn descriptor_at(&self, offset: i64, width: i64) -> Option<u32> {
+        let rel = offset - self.descriptors;
+        if width == 8 && rel >= 0 && rel % 8 == 0 && rel / 8 < self.count as i64 {
+            Some((rel / 8) as u32)
+        } else {
+            None
+        }
+    }
+
+    /// Parses a `descriptors:count:size:base` list, offsets in hex, as written by Display.
+    pub fn parse(s: &str) -> Result<WamrTables, String> {
+        let words: Vec<&str> = s.split(':').collect();
+        match words.as_slice() {
+            [descriptors, count, size_field, base_field] => {
+                let malformed = || format!("expected descriptors:count:size:base, got {:?}", s);
+                Ok(WamrTables {
+                    descriptors: parse_offset(descriptors).ok_or_else(malformed)?,
+                    count: count.parse().map_err(|_| malformed())?,
+                    size_field: parse_offset(size_field).ok_or_else(malformed)?,
+                    base_field: parse_offset(base_field).ok_or_else(malformed)?,
+                })
+            }
+            _ => Err(format!("expected descriptors:count:size:base, got {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for WamrTables {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:x}:{}:0x{:x}:0x{:x}", self.descriptors, self.count, self.size_field, self.base_field)
+    }
+}
+
+fn parse_offset(s: &str) -> Option<i64> {
+    i64::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
+
+/// What a layout file gives: the runtime-writable fields, the memories if the ModuleInstance
+/// doesn't have the default one, the funcref tables if it has several, and the static array of
+/// function pointers of builds that keep one in the module.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WamrLayout {
+    pub runtime_fields: Vec<RuntimeField>,
+    pub memories: Option<WamrMemories>,
+    pub tables: Option<WamrTables>,
+    // the address in the module of the array, and its number of pointers
+    pub func_ptrs: Option<(u64, u64)>,
+}
+
+/// Parses a layout file: one `writable <offset> <width>` line per runtime-writable
+/// ModuleInstance field, one `memory <base offset> <bound offset>` line per linear memory, in
+/// order, at most one `tables <descriptors offset> <count> <size field> <base field>` line and at
+/// most one `func_ptrs <address> <count>` line, offsets and addresses in hex, `#` starting a comment.
+pub fn parse_wamr_layout(text: &str) -> Result<WamrLayout, String> {
+    let mut fields = Vec::new();
+    let mut memories = Vec::new();
+    let mut tables = None;
+    let mut func_ptrs = None;
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.splitn(2, '#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let malformed = || {
+            format!(
+                "line {}: expected `writable <offset> <width>`, `memory <base> <bound>`, `tables <descriptors> <count> <size> <base>` or `func_ptrs <address> <count>`: {}",
+                lineno + 1,
+                line
+            )
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["writable", offset, width] => {
+                let offset = parse_offset(offset).ok_or_else(malformed)?;
+                let width = width.parse::<i64>().map_err(|_| malformed())?;
+                if width <= 0 {
+                    return Err(malformed());
+                }
+                fields.push(RuntimeField { offset: offset, width: width });
+            }
+            ["memory", heap_base, mem_bound] => {
+                let heap_base = parse_offset(heap_base).ok_or_else(malformed)?;
+                let mem_bound = parse_offset(mem_bound).ok_or_else(malformed)?;
+                memories.push(WamrMemory { heap_base: heap_base, mem_bound: mem_bound });
+            }
+            ["tables", descriptors, count, size_field, base_field] if tables.is_none() => {
+                tables = Some(WamrTables {
+                    descriptors: parse_offset(descriptors).ok_or_else(malformed)?,
+                    count: count.parse().map_err(|_| malformed())?,
+                    size_field: parse_offset(size_field).ok_or_else(malformed)?,
+                    base_field: parse_offset(base_field).ok_or_else(malformed)?,
+                });
+            }
+            ["func_ptrs", addr, count] if func_ptrs.is_none() => {
+                let addr = parse_offset(addr).filter(|addr| *addr > 0).ok_or_else(malformed)?;
+                func_ptrs = Some((addr as u64, count.parse().map_err(|_| malformed())?));
+            }
+            _ => return Err(malformed()),
+        }
+    }
+    Ok(WamrLayout {
+        runtime_fields: fields,
+        memories: if memories.is_empty() { None } else { Some(WamrMemories(memories)) },
+        tables: tables,
+        func_ptrs: func_ptrs,
+    })
+}
+
+pub fn load_wamr_layout(path: &str) -> Result<WamrLayout, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse_wamr_layout(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+#[test]
+fn wamr_layout_uses_test() {
+    let before = wamr_layout_uses();
+    record_wamr_layout_use(WAMR_PAGECNT_OFFSET);
+    // not a layout constant
+    record_wamr_layout_use(0x1234);
+    let after = wamr_layout_uses();
+    let pagecnt = WAMR_LAYOUT.iter().position(|(name, _)| *name == "WAMR_PAGECNT_OFFSET").unwrap();
+    assert!(after[pagecnt].1 > before[pagecnt].1);
+
+    let uses = vec![("A", 0), ("B", 10), ("C", 1000)];
+    assert_eq!(classify_uses(&uses, 2), (vec!["A"], vec!["C"]));
+    assert_eq!(classify_uses(&uses, 100), (vec!["A"], vec![]));
+}
+
+#[test]
+fn wamr_layout_file_test() {
+    let layout = parse_wamr_layout("# lock words\nwritable 0x1c0 4\n\nwritable 1c8 8 # futex\n").unwrap();
+    assert_eq!(layout.memories, None);
+    let fields = layout.runtime_fields;
+    assert_eq!(fields, vec![RuntimeField { offset: 0x1c0, width: 4 }, RuntimeField { offset: 0x1c8, width: 8 }]);
+    assert!(fields[0].contains(0x1c0, 4));
+    assert!(!fields[0].contains(0x1c0, 8));
+    assert!(!fields[0].contains(0x1bc, 4));
+    assert!(parse_wamr_layout("writable 0x1c0").is_err());
+    assert!(parse_wamr_layout("readonly 0x1c0 4").is_err());
+    assert!(parse_wamr_layout("memory 0x150").is_err());
+}
+
+#[test]
+fn wamr_memories_test() {
+    // two memory instances, the first one where a single memory would be
+    let layout = parse_wamr_layout("writable 0x1c0 4\nmemory 0x150 0x1a0\nmemory 0x1d0 0x220\n").unwrap();
+    let memories = layout.memories.unwrap();
+    assert_eq!(memories.0[1], WamrMemory { heap_base: 0x1d0, mem_bound: 0x220 });
+    assert_eq!(memories.0[0], WamrMemories::default().0[0]);
+    assert_eq!(memories.to_string(), "0x150:0x1a0,0x1d0:0x220");
+    assert_eq!(WamrMemories::parse(&memories.to_string()), Ok(memories));
+    assert!(WamrMemories::parse("0x150").is_err());
+    assert!(WamrMemories::parse("").is_err());
+}
+
+#[test]
+fn wamr_tables_test() {
+    let layout = parse_wamr_layout("memory 0x150 0x1a0\ntables 0x1c0 2 0 8 # two funcref tables\n").unwrap();
+    let tables = layout.tables.unwrap();
+    assert_eq!(tables, WamrTables { descriptors: 0x1c0, count: 2, size_field: 0, base_field: 8 });
+    assert_eq!(tables.descriptor_at(0x1c0, 8), Some(0));
+    assert_eq!(tables.descriptor_at(0x1c8, 8), Some(1));
+    // past the array, inside an entry, or not a whole pointer
+    assert_eq!(tables.descriptor_at(0x1d0, 8), None);
+    assert_eq!(tables.descriptor_at(0x1c4, 8), None);
+    assert_eq!(tables.descriptor_at(0x1c0, 4), None);
+    assert_eq!(tables.to_string(), "0x1c0:2:0x0:0x8");
+    assert_eq!(WamrTables::parse(&tables.to_string()), Ok(tables));
+    assert_eq!(parse_wamr_layout("writable 0x1c0 4\n").unwrap().tables, None);
+    assert!(parse_wamr_layout("tables 0x1c0 2 0").is_err());
+    assert!(parse_wamr_layout("tables 0x1c0 2 0 8\ntables 0x1d0 2 0 8").is_err());
+    assert!(WamrTables::parse("0x1c0:two:0:8").is_err());
+}
+
+#[test]
+fn wamr_func_ptrs_test() {
+    let layout = parse_wamr_layout("tables 0x1c0 2 0 8\nfunc_ptrs 0x4a000 12 # stripped build\n").unwrap();
+    assert_eq!(layout.func_ptrs, Some((0x4a000, 12)));
+    assert_eq!(parse_wamr_layout("writable 0x1c0 4\n").unwrap().func_ptrs, None);
+    assert!(parse_wamr_layout("func_ptrs 0x4a000").is_err());
+    assert!(parse_wamr_layout("func_ptrs 0x4a000 twelve").is_err());
+    assert!(parse_wamr_layout("func_ptrs 0x4a000 12\nfunc_ptrs 0x4b000 12").is_err());
+}
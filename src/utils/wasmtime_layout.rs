@@ -0,0 +1,131 @@
+//! The VMContext fields Wasmtime (Cranelift) code reaches through %rdi.
+//!
+//! Wasmtime lays the VMContext out per module: the imported functions, tables, memories and
+//! globals come first, then the defined ones. The defaults are the layout of a module with no
+//! imports and one defined table and memory; any other module needs its offsets given with
+//! `--vmctx-offsets`.
+
+use std::fmt;
+
+// A table holds pointers to VMCallerCheckedAnyfunc entries, which are laid out as below
+pub const WASMTIME_TABLE_ENTRY_SIZE: i64 = 8;
+pub const WASMTIME_ANYFUNC_FUNC_PTR_OFFSET: i64 = 0;
+pub const WASMTIME_ANYFUNC_TYPE_INDEX_OFFSET: i64 = 8;
+pub const WASMTIME_ANYFUNC_VMCTX_OFFSET: i64 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WasmtimeLayout {
+    // the linear memory's base and current length
+    pub heap_base: i64,
+    pub heap_bound: i64,
+    // the function table's base and current number of elements
+    pub table_base: i64,
+    pub table_size: i64,
+    // the defined globals, which live in the VMContext itself
+    pub globals: i64,
+    pub globals_size: i64,
+}
+
+impl Default for WasmtimeLayout {
+    fn default() -> Self {
+        WasmtimeLayout {
+            table_base: 0x40,
+            table_size: 0x48,
+            heap_base: 0x50,
+            heap_bound: 0x58,
+            globals: 0x60,
+            globals_size: 0,
+        }
+    }
+}
+
+pub const WASMTIME_LAYOUT_FIELDS: [&str; 6] = [
+    "heap_base",
+    "heap_bound",
+    "table_base",
+    "table_size",
+    "globals",
+    "globals_size",
+];
+
+impl WasmtimeLayout {
+    fn field(&mut self, key: &str) -> Option<&mut i64> {
+        match key {
+            "heap_base" => Some(&mut self.heap_base),
+            "heap_bound" => Some(&mut self.heap_bound),
+            "table_base" => Some(&mut self.table_base),
+            "table_size" => Some(&mut self.table_size),
+            "globals" => Some(&mut self.globals),
+            "globals_size" => Some(&mut self.globals_size),
+            _ => None,
+        }
+    }
+
+    /// Parses a `key=val,...` list of changes to the default offsets. Values are decimal, or hex
+    /// with a `0x` prefix.
+    pub fn parse(s: &str) -> Result<WasmtimeLayout, String> {
+        let mut layout = WasmtimeLayout::default();
+        for setting in s.split(",").map(|setting| setting.trim()).filter(|setting| !setting.is_empty()) {
+            let (key, val) = match setting.find('=') {
+                Some(idx) => (&setting[..idx], &setting[idx + 1..]),
+                None => return Err(format!("expected key=val, got {:?}", setting)),
+            };
+            let field = layout
+                .field(key)
+                .ok_or_else(|| format!("unknown VMContext field {:?} (expected one of {:?})", key, WASMTIME_LAYOUT_FIELDS))?;
+            let parsed = match val.strip_prefix("0x") {
+                Some(hex) => i64::from_str_radix(hex, 16),
+                None => val.parse::<i64>(),
+            };
+            *field = match parsed {
+                Ok(offset) if offset >= 0 => offset,
+                _ => return Err(format!("invalid offset {:?} for {}", val, key)),
+            };
+        }
+        Ok(layout)
+    }
+
+    /// Whether `offset` in the VMContext is one of the memory or table fields.
+    pub fn is_field(&self, offset: i64) -> bool {
+        offset == self.heap_base || offset == self.heap_bound || offset == self.table_base || offset == self.table_size
+    }
+
+    /// Whether `[offset, offset + width)` in the VMContext is within the globals.
+    pub fn is_global(&self, offset: i64, width: i64) -> bool {
+        offset >= self.globals && offset + width <= self.globals + self.globals_size
+    }
+}
+
+impl fmt::Display for WasmtimeLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut layout = *self;
+        let settings: Vec<String> = WASMTIME_LAYOUT_FIELDS
+            .iter()
+            .map(|key| format!("{}=0x{:x}", key, layout.field(key).unwrap()))
+            .collect();
+        write!(f, "{}", settings.join(","))
+    }
+}
+
+#[test]
+fn wasmtime_layout_test() {
+    let defaults = WasmtimeLayout::default();
+    assert_eq!(WasmtimeLayout::parse(""), Ok(defaults));
+    assert_eq!(
+        defaults.to_string(),
+        "heap_base=0x50,heap_bound=0x58,table_base=0x40,table_size=0x48,globals=0x60,globals_size=0x0"
+    );
+
+    let layout = WasmtimeLayout::parse("heap_base=0x70, globals_size=16").unwrap();
+    assert_eq!((layout.heap_base, layout.globals_size), (0x70, 16));
+    assert_eq!(layout.table_base, defaults.table_base);
+    assert_eq!(WasmtimeLayout::parse(&layout.to_string()), Ok(layout));
+
+    assert!(layout.is_field(0x70) && layout.is_field(0x48) && !layout.is_field(0x50));
+    assert!(layout.is_global(0x60, 8) && layout.is_global(0x68, 8) && !layout.is_global(0x6c, 8));
+
+    assert!(WasmtimeLayout::parse("vmctx=0").is_err());
+    assert!(WasmtimeLayout::parse("heap_base").is_err());
+    assert!(WasmtimeLayout::parse("heap_base=-8").is_err());
+    assert!(WasmtimeLayout::parse("heap_base=0xzz").is_err());
+}
@@ -0,0 +1,1449 @@
+use crate::analyses::call_analyzer::CallAnalyzer;
+use crate::analyses::heap_analyzer::HeapAnalyzer;
+use crate::analyses::reaching_defs::{analyze_reaching_defs,ReachingDefnAnalyzer};
+use crate::analyses::{run_worklist, run_worklist_instrumented, run_worklist_scheduled, AbstractAnalyzer, AnalysisResult, ReplayNotes, Scheduling};
+use crate::analyses::stack_analyzer::{untracked_rsp, wamr_stack_limit_cmps, StackAnalyzer};
+use crate::analyses::taint_analyzer::TaintAnalyzer;
+use crate::checkers::call_checker::{check_calls, check_direct_calls};
+use crate::checkers::heap_checker::{access_class_lines, classify_heap, AccessClasses};
+use crate::checkers::prologue_checker::{match_wamr_prologue, PROLOGUE_WINDOW};
+use crate::checkers::stack_checker::{check_stack, stack_arg_extents, stack_arg_mismatches, StackArgExtents};
+use crate::checkers::taint_checker::check_taint;
+use crate::checkers::CheckFailure;
+use crate::lattices::{Lattice, VarState};
+use crate::utils::assumptions::Assumptions;
+use crate::utils::compiler::{Abi, AnalysisOptions, Compiler, CompilerMetadata};
+use crate::utils::cfg_cache::CfgCache;
+use crate::utils::cfg_normalize::CfgError;
+use crate::utils::failure_cache::{flags_fingerprint, function_key, FailureCache};
+use crate::utils::fingerprint::{fingerprint_table, state_fingerprints};
+use crate::utils::func_filter::select_funcs;
+use crate::utils::html_report::{FailureDetail, FunctionReport, HtmlReport, Verdict};
+use crate::utils::caveats::{collect_caveats, record_caveat, record_caveats, CaveatKind, SoundnessCaveat, CAVEAT_KINDS};
+use crate::utils::ir::IRMap;
+use crate::utils::ir_golden::{diff_ir, golden_ir, load_golden, write_golden};
+use crate::utils::ir_spill::{spill_irmap, SPILL_CACHE_BLOCKS, SPILL_THRESHOLD_BLOCKS};
+use crate::utils::ir_utils::{count_indirect_calls, has_indirect_calls};
+use crate::utils::lifter::{disassemble_at, lift_prologue};
+use crate::utils::lucet_manifest::module_reader;
+use crate::utils::minimize::{jump_table_bytes, write_bundle, ReproBundle};
+use crate::utils::module_layout::ModuleLayout;
+use crate::utils::persist::{persist_result, write_persisted};
+use crate::utils::schedule::Schedules;
+use crate::utils::stats::{
+    compare_stats, load_stats, stats_csv, stats_legacy, stats_named, stats_text, stats_totals, FunctionStats, StatsFormat, WorklistSummary,
+};
+use crate::utils::symbols::SymbolMap;
+use crate::utils::table_funcs::load_table_funcs;
+use crate::utils::wamr_layout::{load_wamr_layout, reset_wamr_layout_uses, unusual_wamr_layout_uses, wamr_layout_uses};
+use crate::utils::wasmtime_layout::WasmtimeLayout;
+use crate::utils::dot::{cfg_to_dot, write_dot, DotAnnotation};
+use crate::utils::logging::{set_current_function, OrderedLogger, SUMMARY};
+use crate::utils::patch::PatchList;
+use crate::utils::incremental::{
+    dependency_fingerprint, file_ranges_to_addrs, load_segments, parse_changed_ranges, DependencyRecord, FunctionDeps,
+    RecordedFunction,
+};
+use crate::utils::utils::{analyze_jump_table_reads,analyze_jump_tables,check_cfi,fully_resolved_cfg,get_data,CfgLimits};
+use crate::utils::utils::{load_contexts, load_layout, load_metadata, load_program, wamr_get_native_addrs};
+use crate::utils::valid_funcs::{FuncSource, FuncsDriftPolicy, TrustedFunc, ValidFuncs};
+use crate::utils::verification_report::{FunctionResult, Property, VerificationReport};
+use log::{debug, info};
+use serde_json;
+use std::cell::RefCell;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
+use yaxpeax_core::analyses::control_flow::{check_cfg_integrity, VW_CFG};
+use yaxpeax_core::arch::x86_64::MergedContextTable;
+use yaxpeax_core::memory::repr::process::ModuleData;
+
+/*
+ * Verifying a whole module: what the `veriwasm` binary runs, for embedding in other tools. Each
+ * function of the module is lifted, analyzed and checked for heap, stack and call safety, and
+ * the outcome of every function is collected into a `VerificationReport`.
+ */
+
+/// How a module is verified. `VerifyOptions::default()` verifies a Lucet module the way
+/// `veriwasm -i <module>` does with no other flags.
+#[derive(Clone, Debug)]
+pub struct VerifyOptions {
+    // None to detect the compiler from the module's symbols
+    pub compiler: Option<Compiler>,
+    // functions to leave unverified, which calls may still target
+    pub trusted_funcs: Vec<TrustedFunc>,
+    // size of global data in memory, and # of functions in the indirect call table (Wamr-only)
+    pub globals_size: i64,
+    pub call_table_size: i64,
+    pub num_jobs: u32,
+    pub keep_going: bool,
+    pub stats_output: Option<String>,
+    pub stats_format: StatsFormat,
+    pub taint_audit: bool,
+    // warn about direct calls writing fewer stack arguments than their callee reads
+    pub check_stack_args: bool,
+    pub symbol_map: SymbolMap,
+    // take the functions from the Lucet function manifest even if the module has symbols
+    pub no_symbols: bool,
+    pub caller_window: Option<i64>,
+    pub strict_stack: bool,
+    pub strict_rebound: bool,
+    // the page Spectre-mitigated heap accesses fall back to (Lucet-only), if not found in the module
+    pub safe_page: Option<u64>,
+    pub analysis_options: AnalysisOptions,
+    pub abi: Abi,
+    // the VMContext offsets (Wasmtime-only)
+    pub wasmtime_layout: WasmtimeLayout,
+    pub persist_dir: Option<String>,
+    pub persist_funcs: Vec<String>,
+    // spill the IR of every function here, rather than only of those above spill_threshold blocks
+    pub spill_dir: Option<String>,
+    pub spill_threshold: usize,
+    // bounds on resolving a function's indirect jumps
+    pub cfg_limits: CfgLimits,
+    // the functions to verify, by name or address (default all)
+    pub only_funcs: Vec<String>,
+    pub failure_cache: Option<String>,
+    pub recheck_percent: u64,
+    pub cache_seed: u64,
+    // the record of each function's result and the bytes it depended on, and the byte ranges of
+    // the module changed since it was written (only the functions depending on them are verified)
+    pub deps: Option<String>,
+    pub changed_ranges: Option<String>,
+    pub compare_stats: Option<String>,
+    pub regression_factor: f64,
+    pub wamr_layout: Option<String>,
+    pub html_report: Option<String>,
+    pub state_fingerprint: Option<String>,
+    // where to write what the verdict assumes of the runtime, if every function verified
+    pub assumptions: Option<String>,
+    // the patches to apply to the module before verifying it (see utils::patch)
+    pub patch: Option<String>,
+    // where to write the functions direct calls may target, and a snapshot of them to check
+    // against, failing or warning when they differ (see utils::valid_funcs)
+    pub funcs_snapshot: Option<String>,
+    pub funcs_expect: Option<String>,
+    pub funcs_expect_policy: FuncsDriftPolicy,
+    // where to write the resolved CFG of every function to, as a dot graph (see utils::dot)
+    pub dump_cfg: Option<String>,
+    // the soundness caveats that fail the module rather than only being reported (see
+    // utils::caveats)
+    pub deny_caveats: Vec<CaveatKind>,
+    // where the golden IR of functions is, to compare their IR with, and whether to rewrite it
+    // instead (see utils::ir_golden)
+    pub ir_golden: Option<String>,
+    pub ir_golden_update: bool,
+    // where to keep the resolved CFG and IR of every function across runs (see utils::cfg_cache)
+    pub cache_dir: Option<String>,
+    // where to write the order the worklist of each analysis visited blocks in, or to read one to
+    // visit them in instead (see utils::schedule)
+    pub record_schedule: Option<String>,
+    pub replay_schedule: Option<String>,
+    // where to write a repro bundle of every function failing a check (see utils::minimize)
+    pub repro_dir: Option<String>,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        VerifyOptions {
+            compiler: None,
+            trusted_funcs: vec![],
+            globals_size: -1,
+            call_table_size: -1,
+            num_jobs: 1,
+            keep_going: false,
+            stats_output: None,
+            stats_format: StatsFormat::Json,
+            taint_audit: false,
+            check_stack_args: false,
+            symbol_map: SymbolMap::default(),
+            no_symbols: false,
+            caller_window: None,
+            strict_stack: false,
+            strict_rebound: false,
+            safe_page: None,
+            analysis_options: AnalysisOptions::default(),
+            abi: Abi::Lp64,
+            wasmtime_layout: WasmtimeLayout::default(),
+            persist_dir: None,
+            persist_funcs: vec![],
+            spill_dir: None,
+            spill_threshold: SPILL_THRESHOLD_BLOCKS,
+            cfg_limits: CfgLimits::default(),
+            only_funcs: vec![],
+            failure_cache: None,
+            recheck_percent: 10,
+            cache_seed: 0,
+            deps: None,
+            changed_ranges: None,
+            compare_stats: None,
+            regression_factor: 2.0,
+            wamr_layout: None,
+            html_report: None,
+            state_fingerprint: None,
+            assumptions: None,
+            patch: None,
+            funcs_snapshot: None,
+            funcs_expect: None,
+            funcs_expect_policy: FuncsDriftPolicy::Fail,
+            dump_cfg: None,
+            deny_caveats: vec![],
+            ir_golden: None,
+            ir_golden_update: false,
+            cache_dir: None,
+            record_schedule: None,
+            replay_schedule: None,
+            repro_dir: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum VerifyError {
+    // the module or one of the inputs it was verified with couldn't be loaded or parsed
+    Load(String),
+    // the module was verified, and some of its functions aren't safe
+    Unsafe(VerificationReport),
+    // one of the outputs asked for (a dump, a report, a cache) couldn't be written, or read back
+    Io(String),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::Load(msg) | VerifyError::Io(msg) => write!(f, "{}", msg),
+            VerifyError::Unsafe(report) if report.failures().is_empty() => {
+                write!(f, "{} denied soundness caveats", report.denied_caveats().len())
+            }
+            VerifyError::Unsafe(report) => write!(f, "{} functions failed", report.failures().len()),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast_ref::<String>() {
+        Some(msg) => msg.clone(),
+        None => payload.downcast_ref::<&str>().map_or("panicked".to_string(), |msg| msg.to_string()),
+    }
+}
+
+fn io_error(what: String, e: impl fmt::Display) -> VerifyError {
+    VerifyError::Io(format!("{}: {}", what, e))
+}
+
+// Loading panics on a malformed module, which is a load error rather than a crash
+fn load<T>(f: impl FnOnce() -> T) -> Result<T, VerifyError> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| VerifyError::Load(panic_message(payload)))
+}
+
+// What verifying one function came to
+enum Outcome {
+    Verified {
+        result: FunctionResult,
+        stats: FunctionStats,
+        key: u64,
+        taint_findings: usize,
+        deps: Option<FunctionDeps>,
+        stack_args: StackArgExtents,
+    },
+    // `key` is None for a failure replayed from the failure cache, or one from before the checks
+    // (which has no `deps` either)
+    Failed { result: FunctionResult, entry: FunctionReport, key: Option<u64>, msg: String, deps: Option<FunctionDeps> },
+}
+
+// The outcome of a function that failed `rule` before any property was checked
+fn early_failure(func_name: &str, addr: u64, rule: &str, msg: String, cfg_time: f64) -> Outcome {
+    Outcome::Failed {
+        result: FunctionResult::failed(func_name, addr, None, &msg, None),
+        entry: FunctionReport {
+            stats: FunctionStats {
+                name: func_name.to_string(),
+                addr: Some(addr),
+                blocks: 0,
+                cfg_time: cfg_time,
+                heap_time: 0.0,
+                stack_time: 0.0,
+                call_time: 0.0,
+                direct_calls: 0,
+                indirect_calls: 0,
+                worklist: Default::default(),
+            },
+            verdict: Verdict::Failed,
+            failure: Some(FailureDetail {
+                rule: rule.to_string(),
+                message: msg.clone(),
+                log: vec![],
+                accesses: vec![],
+            }),
+        },
+        key: None,
+        msg: msg,
+        deps: None,
+    }
+}
+
+// Failure cache lookups draw from a sequence of random numbers, so they are made in address order
+// whichever order the workers get to them in
+#[derive(Default)]
+struct LookupTurns {
+    next: Mutex<usize>,
+    taken: Condvar,
+}
+
+impl LookupTurns {
+    fn take<T>(&self, func_idx: usize, lookup: impl FnOnce() -> T) -> T {
+        let mut next = self.next.lock().unwrap();
+        while *next < func_idx {
+            next = self.taken.wait(next).unwrap();
+        }
+        let result = lookup();
+        *next = (*next).max(func_idx + 1);
+        self.taken.notify_all();
+        result
+    }
+}
+
+// Everything verifying a function needs, shared read-only between the workers
+struct FunctionVerifier<'a> {
+    config: &'a VerifyOptions,
+    module_path: &'a str,
+    program: &'a ModuleData,
+    module_layout: &'a ModuleLayout,
+    metadata: &'a CompilerMetadata,
+    func_addrs: &'a Vec<(u64, String)>,
+    valid_funcs: &'a ValidFuncs,
+    plt: (u64, u64),
+    flags: u64,
+    failure_cache: &'a Mutex<Option<FailureCache>>,
+    cfg_cache: Option<CfgCache>,
+    lookup_turns: Option<LookupTurns>,
+    recorded_schedules: &'a Mutex<Option<Schedules>>,
+    replay_schedules: Option<Schedules>,
+}
+
+impl FunctionVerifier<'_> {
+    // Gives up the lookup turn of a function that didn't get to its lookup
+    fn skip_lookup(&self, func_idx: usize) {
+        if let Some(turns) = &self.lookup_turns {
+            turns.take(func_idx, || ());
+        }
+    }
+
+    // Runs the worklist of the analysis `name` on `func_name`, counting its iterations into
+    // `worklist` when the stats are written out, and recording or replaying the order it visits
+    // blocks in
+    fn run_analysis<State: VarState + Lattice + Clone + Send, T: AbstractAnalyzer<State> + Sync>(
+        &self,
+        func_name: &str,
+        name: &str,
+        cfg: &VW_CFG,
+        irmap: &IRMap,
+        analyzer: &T,
+        worklist: &RefCell<WorklistSummary>,
+    ) -> AnalysisResult<State> {
+        let recording = self.recorded_schedules.lock().unwrap().is_some();
+        if self.config.stats_output.is_none() && !recording && self.replay_schedules.is_none() {
+            return run_worklist(cfg, irmap, analyzer);
+        }
+        let start = Instant::now();
+        let (result, stats) = match &self.replay_schedules {
+            Some(schedules) => match schedules.get(func_name, name) {
+                Some(schedule) => {
+                    let mut notes = ReplayNotes::default();
+                    let ran = run_worklist_scheduled(cfg, irmap, analyzer, Scheduling::Replay(schedule, &mut notes));
+                    if let Some(reason) = &notes.fallback {
+                        log::warn!("{}: the {} schedule no longer fits ({}), visiting the rest in the usual order", func_name, name, reason);
+                    }
+                    if !notes.skipped.is_empty() {
+                        info!("{}: skipped {} visits of the {} schedule to blocks not on the worklist", func_name, notes.skipped.len(), name);
+                    }
+                    ran
+                }
+                None => {
+                    log::warn!("{}: no {} schedule recorded, visiting blocks in the usual order", func_name, name);
+                    run_worklist_instrumented(cfg, irmap, analyzer)
+                }
+            },
+            None if recording => {
+                let mut visits = vec![];
+                let ran = run_worklist_scheduled(cfg, irmap, analyzer, Scheduling::Record(&mut visits));
+                if let Some(schedules) = self.recorded_schedules.lock().unwrap().as_mut() {
+                    schedules.insert(func_name, name, visits);
+                }
+                ran
+            }
+            None => run_worklist_instrumented(cfg, irmap, analyzer),
+        };
+        worklist.borrow_mut().add(name, &stats, (Instant::now() - start).as_secs_f64());
+        result
+    }
+
+    // Writes the CFG of `func_name` with the states of the analyses run so far, for --dump-cfg;
+    // it is rewritten as each analysis finishes, so a function failing a check keeps its states
+    fn dump_cfg(&self, func_name: &str, cfg: &VW_CFG, irmap: &IRMap, annotations: &[DotAnnotation], accesses: &AccessClasses) -> Result<(), VerifyError> {
+        if let Some(dir) = &self.config.dump_cfg {
+            let dot = cfg_to_dot(func_name, cfg, irmap, annotations, accesses);
+            let path = write_dot(dir, func_name, &dot).map_err(|e| io_error(format!("Unable to write CFG to {}", dir), e))?;
+            debug!("Wrote the CFG of {:?} to {}", func_name, path.display());
+        }
+        Ok(())
+    }
+
+    // The resolved CFG and IR of the function at `addr`, from the CFG cache if it has them, with
+    // the caveats taken lifting it recorded either way
+    fn resolve_cfg(&self, contexts: &MergedContextTable, addr: u64, func_name: &str) -> Result<(VW_CFG, IRMap), CfgError> {
+        let cache = match &self.cfg_cache {
+            Some(cache) => cache,
+            None => return fully_resolved_cfg(self.program, contexts, self.metadata, addr, &self.config.cfg_limits),
+        };
+        if let Some((cfg, irmap, caveats)) = cache.load(addr) {
+            debug!("Read the CFG of {:?} from the CFG cache", func_name);
+            record_caveats(caveats);
+            return Ok((cfg, irmap));
+        }
+        let (resolved, caveats) = collect_caveats(|| fully_resolved_cfg(self.program, contexts, self.metadata, addr, &self.config.cfg_limits));
+        if let Ok((cfg, irmap)) = &resolved {
+            if let Err(e) = cache.store(addr, cfg, irmap, &caveats) {
+                log::warn!("Unable to write the CFG of {:?} to the CFG cache: {}", func_name, e);
+            }
+        }
+        record_caveats(caveats);
+        resolved
+    }
+
+    // Writes the repro bundle of `func_name`, which failed a check, for --repro-dir
+    fn write_repro(&self, func_name: &str, cfg: &VW_CFG, irmap: &IRMap) -> Result<(), VerifyError> {
+        let dir = match &self.config.repro_dir {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        let bundle = ReproBundle {
+            func_name: func_name.to_string(),
+            metadata: self.metadata.clone(),
+            cfg: cfg.clone(),
+            irmap: irmap.clone(),
+            func_addrs: self.func_addrs.clone(),
+            valid_funcs: self.valid_funcs.clone(),
+            plt: self.plt,
+            data: jump_table_bytes(self.program, cfg, irmap, self.metadata),
+        };
+        let path = write_bundle(dir, &bundle).map_err(|e| io_error(format!("Unable to write repro bundle to {}", dir), e))?;
+        info!("Wrote the repro bundle of {:?} to {}", func_name, path.display());
+        Ok(())
+    }
+
+    // Compares the IR of `func_name` with its golden IR, if it has one, for --ir-golden: a
+    // difference is an ir_drift caveat. With --ir-golden-update, it is written as the golden IR
+    fn check_ir_golden(&self, func_name: &str, addr: u64, irmap: &IRMap) -> Result<(), VerifyError> {
+        let dir = match &self.config.ir_golden {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        let actual = golden_ir(irmap, addr);
+        if self.config.ir_golden_update {
+            let path = write_golden(dir, func_name, &actual).map_err(|e| io_error(format!("Unable to write golden IR to {}", dir), e))?;
+            info!("Wrote the golden IR of {:?} to {}", func_name, path.display());
+            return Ok(());
+        }
+        let expected = match load_golden(dir, func_name).map_err(|e| io_error("Unable to read golden IR".to_string(), e))? {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+        let diffs = diff_ir(&expected, &actual);
+        if diffs.is_empty() {
+            return Ok(());
+        }
+        log::warn!("The IR of {:?} differs from its golden IR in {} statement(s):", func_name, diffs.len());
+        for diff in diffs.iter() {
+            log::warn!("  {}", diff);
+        }
+        record_caveat(CaveatKind::IrDrift, Some(addr), format!("{} statement(s) differ from the golden IR, first {}", diffs.len(), diffs[0]));
+        Ok(())
+    }
+
+    // Verifies function `func_idx` with its log captured; a panic in the analyses is a failure
+    fn verify_logged(&self, logger: Option<&OrderedLogger>, contexts: &MergedContextTable, func_idx: usize, addr: u64, func_name: &str) -> Result<Outcome, VerifyError> {
+        set_current_function(Some(func_idx));
+        let start = Instant::now();
+        let mut outcome = match panic::catch_unwind(AssertUnwindSafe(|| self.verify(contexts, func_idx, addr, func_name))) {
+            Ok(outcome) => outcome,
+            Err(payload) => Ok(early_failure(func_name, addr, "panic", panic_message(payload), (Instant::now() - start).as_secs_f64())),
+        };
+        self.skip_lookup(func_idx);
+        let failed = !matches!(outcome, Ok(Outcome::Verified { .. }));
+        let log = logger.map_or(vec![], |logger| logger.finish_function(func_idx, failed));
+        if let Ok(Outcome::Failed { entry, .. }) = &mut outcome {
+            if let Some(failure) = &mut entry.failure {
+                failure.log = log;
+            }
+        }
+        set_current_function(None);
+        outcome
+    }
+
+    fn verify(&self, contexts: &MergedContextTable, func_idx: usize, addr: u64, func_name: &str) -> Result<Outcome, VerifyError> {
+        let (config, metadata, flags) = (self.config, self.metadata, self.flags);
+        let (func_addrs, valid_funcs, plt) = (self.func_addrs, self.valid_funcs, &self.plt);
+        info!("Generating CFG for {:?}", func_name);
+        let start = Instant::now();
+        let (cfg, irmap) = match self.resolve_cfg(contexts, addr, func_name) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                let msg = format!("Malformed CFG: {}", e);
+                return Ok(early_failure(func_name, addr, "cfg", msg, (Instant::now() - start).as_secs_f64()));
+            }
+        };
+        info!("Analyzing: {:?}", func_name);
+        self.check_ir_golden(func_name, addr, &irmap)?;
+        check_cfg_integrity(&cfg.blocks, &cfg.graph);
+        // the bytes verifying the function reads, for --deps and --patch
+        let deps = (config.deps.is_some() || config.patch.is_some()).then(|| FunctionDeps {
+            code: cfg.blocks.values().map(|block| (block.start, block.end + 1)).collect(),
+            data: analyze_jump_table_reads(&cfg, &irmap, metadata),
+        });
+        let spill_dir = match &config.spill_dir {
+            Some(dir) => Some(dir.clone()),
+            None if cfg.blocks.len() > config.spill_threshold => Some(std::env::temp_dir().to_string_lossy().to_string()),
+            None => None,
+        };
+        let irmap = match spill_dir {
+            Some(dir) => {
+                info!("Spilling the IR of {:?} ({} blocks) to {}", func_name, cfg.blocks.len(), dir);
+                spill_irmap(irmap, &dir, func_name, SPILL_CACHE_BLOCKS).map_err(|e| io_error(format!("Unable to spill IR to {}", dir), e))?
+            }
+            None => irmap,
+        };
+        let func_key = function_key(&irmap);
+        let indirect_calls = count_indirect_calls(&irmap, metadata.compiler);
+        self.dump_cfg(func_name, &cfg, &irmap, &[], &AccessClasses::new())?;
+        let worklist = RefCell::new(WorklistSummary::default());
+        // how the heap checker classified each memory access, once it has run
+        let accesses = RefCell::new(AccessClasses::new());
+        // the outcome of failing `property` at `site`, timing the phases started at `marks`
+        let failure = |verdict: Verdict, property: Option<Property>, msg: &str, site: Option<&CheckFailure>, marks: &[Instant]| -> Result<Outcome, VerifyError> {
+            let now = Instant::now();
+            let phase = |i: usize| marks.get(i).map_or(0.0, |mark: &Instant| (*marks.get(i + 1).unwrap_or(&now) - *mark).as_secs_f64());
+            let entry = FunctionReport {
+                stats: FunctionStats {
+                    name: func_name.to_string(),
+                    addr: Some(addr),
+                    blocks: cfg.blocks.len(),
+                    cfg_time: phase(0),
+                    heap_time: phase(1),
+                    stack_time: phase(2),
+                    call_time: phase(3),
+                    direct_calls: 0,
+                    indirect_calls: indirect_calls,
+                    worklist: worklist.borrow().clone(),
+                },
+                verdict: verdict,
+                failure: Some(FailureDetail {
+                    rule: property.map_or("cached", |p| p.name()).to_string(),
+                    message: msg.to_string(),
+                    log: vec![],
+                    accesses: access_class_lines(&accesses.borrow()),
+                }),
+            };
+            if site.is_some() {
+                self.write_repro(func_name, &cfg, &irmap)?;
+            }
+            let key = if verdict == Verdict::CachedFailure { None } else { Some(func_key) };
+            let mut result = FunctionResult::failed(func_name, addr, property, msg, site);
+            if let Some((bytes, disasm)) = site.and_then(|site| disassemble_at(self.program, site.loc.addr)) {
+                result.set_failing_instruction(&bytes, &disasm);
+            }
+            Ok(Outcome::Failed {
+                result: result,
+                entry: entry,
+                key: key,
+                msg: msg.to_string(),
+                deps: deps.clone(),
+            })
+        };
+        if let Some(turns) = &self.lookup_turns {
+            // a failure committed on the main thread may have poisoned the lock, it's still sound
+            let cached = turns.take(func_idx, || {
+                let mut cache = self.failure_cache.lock().unwrap_or_else(|e| e.into_inner());
+                cache.as_mut().and_then(|c| c.lookup(func_key, flags))
+            });
+            if let Some(report) = cached {
+                info!("{:?} failed in an earlier run and is unchanged, not re-verifying", func_name);
+                return failure(Verdict::CachedFailure, None, &format!("{} (cached)", report), None, &[start]);
+            }
+        }
+
+        info!("Checking CFI");
+        if let Err(site) = check_cfi(self.program, &cfg, &irmap, metadata) {
+            return failure(Verdict::Failed, Some(Property::Cfi), "Not CFI Safe", Some(&site), &[start]);
+        }
+
+        info!("Checking Heap Safety");
+        let heap_start = Instant::now();
+        let heap_analyzer = HeapAnalyzer {
+            metadata: metadata.clone(),
+        };
+        let heap_result = self.run_analysis(func_name, "heap", &cfg, &irmap, &heap_analyzer, &worklist);
+        let mut annotations = vec![];
+        if config.dump_cfg.is_some() {
+            annotations.push(DotAnnotation::new("heap", &heap_result));
+        }
+        let fingerprint = config.state_fingerprint.as_ref().map_or(false, |f| f == func_name);
+        if fingerprint {
+            for line in fingerprint_table("heap", &state_fingerprints(&heap_result)) {
+                info!(target: SUMMARY, "{}", line);
+            }
+        }
+        let persist_dir = match &config.persist_dir {
+            Some(dir) if config.persist_funcs.is_empty() || config.persist_funcs.iter().any(|f| f == func_name) => Some(dir),
+            _ => None,
+        };
+        let persisted_heap = persist_dir.map(|_| persist_result(&heap_result, &irmap));
+        // accesses through rsp are only stack accesses while the stack analysis follows it
+        let stack_analyzer = StackAnalyzer {
+            metadata: metadata.clone(),
+            stack_limit_cmps: wamr_stack_limit_cmps(&irmap, &heap_result, &heap_analyzer),
+        };
+        let stack_result = self.run_analysis(func_name, "stack", &cfg, &irmap, &stack_analyzer, &worklist);
+        let untracked_rsp = untracked_rsp(&cfg, &irmap, &stack_result, &stack_analyzer);
+        let jump_tables = analyze_jump_tables(&cfg, &irmap, metadata);
+        let (heap_checked, heap_accesses) =
+            classify_heap(heap_result, &irmap, &heap_analyzer, func_addrs, &jump_tables, &untracked_rsp);
+        accesses.replace(heap_accesses);
+        if config.dump_cfg.is_some() {
+            self.dump_cfg(func_name, &cfg, &irmap, &annotations, &accesses.borrow())?;
+        }
+        if let Err(site) = heap_checked {
+            return failure(Verdict::Failed, Some(Property::Heap), "Not Heap Safe", Some(&site), &[start, heap_start]);
+        }
+
+        info!("Checking Stack Safety");
+        let stack_start = Instant::now();
+        if config.dump_cfg.is_some() {
+            annotations.push(DotAnnotation::new("stack", &stack_result));
+            self.dump_cfg(func_name, &cfg, &irmap, &annotations, &accesses.borrow())?;
+        }
+        if fingerprint {
+            for line in fingerprint_table("stack", &state_fingerprints(&stack_result)) {
+                info!(target: SUMMARY, "{}", line);
+            }
+        }
+        if let (Some(dir), Some(heap)) = (persist_dir, persisted_heap) {
+            let stack = persist_result(&stack_result, &irmap);
+            write_persisted(dir, func_name, metadata, vec![("heap", heap), ("stack", stack)])
+                .map_err(|e| io_error(format!("Unable to persist states to {}", dir), e))?;
+            info!("Persisted states of {:?} to {}", func_name, dir);
+        }
+        let stack_args = stack_arg_extents(&stack_result, &irmap, &stack_analyzer);
+        if let Err(site) = check_stack(stack_result, &irmap, &stack_analyzer) {
+            return failure(Verdict::Failed, Some(Property::Stack), "Not Stack Safe", Some(&site), &[start, heap_start, stack_start]);
+        }
+
+        let call_start = Instant::now();
+        info!("Checking Call Safety");
+        let direct_calls = match check_direct_calls(&irmap, metadata.model, valid_funcs, plt) {
+            Ok(sites) => {
+                for site in sites.iter() {
+                    debug!("0x{:x}: direct call to 0x{:x} accepted as {:?}", site.addr, site.target, site.rule);
+                }
+                sites.len()
+            }
+            Err(site) => return failure(Verdict::Failed, Some(Property::Call), "Not Call Safe", Some(&site), &[start, heap_start, stack_start, call_start]),
+        };
+        if has_indirect_calls(&irmap, metadata.compiler) {
+            let reaching_defs = analyze_reaching_defs(&cfg, &irmap, metadata);
+            let call_analyzer = CallAnalyzer {
+                metadata: metadata.clone(),
+                reaching_defs: reaching_defs.clone(),
+                reaching_analyzer: ReachingDefnAnalyzer::new(metadata.clone(), cfg.clone(), irmap.clone()),
+                withheld_refinements: AtomicUsize::new(0),
+            };
+            let call_result = self.run_analysis(func_name, "call", &cfg, &irmap, &call_analyzer, &worklist);
+            if call_analyzer.withheld_refinements.load(Ordering::Relaxed) > 0 {
+                info!("Withheld {} bounds check refinements", call_analyzer.withheld_refinements.load(Ordering::Relaxed));
+            }
+            if let Err(site) = check_calls(call_result, &irmap, &call_analyzer, valid_funcs, plt) {
+                return failure(Verdict::Failed, Some(Property::Call), "Not Call Safe", Some(&site), &[start, heap_start, stack_start, call_start]);
+            }
+
+        }
+        let mut taint_findings = 0;
+        if config.taint_audit {
+            let taint_analyzer = TaintAnalyzer {
+                metadata: metadata.clone(),
+            };
+            let taint_result = self.run_analysis(func_name, "taint", &cfg, &irmap, &taint_analyzer, &worklist);
+            for finding in check_taint(taint_result, &irmap, &taint_analyzer) {
+                taint_findings += 1;
+                match finding.source {
+                    Some(source) => log::warn!("Taint audit: 0x{:x} indexes with r{} (tainted at 0x{:x})", finding.sink, finding.regnum, source),
+                    None => log::warn!("Taint audit: 0x{:x} indexes with r{} (tainted on entry to its block)", finding.sink, finding.regnum),
+                }
+            }
+        }
+        let end = Instant::now();
+        info!(
+            "Verified {:?} at {:?} blocks. CFG: {:?}s Stack: {:?}s Heap: {:?}s Calls: {:?}s Direct calls: {:?} Stack args out/in: {:?}/{:?}",
+            func_name,
+            cfg.blocks.len(),
+            (heap_start - start).as_secs_f64(),
+            (stack_start - heap_start).as_secs_f64(),
+            (call_start - stack_start).as_secs_f64(),
+            (end - call_start).as_secs_f64(),
+            direct_calls,
+            stack_args.max_outgoing(),
+            stack_args.max_arg_read
+        );
+        Ok(Outcome::Verified {
+            result: FunctionResult::verified(func_name, addr),
+            stats: FunctionStats {
+                name: func_name.to_string(),
+                addr: Some(addr),
+                blocks: cfg.blocks.len(),
+                cfg_time: (heap_start - start).as_secs_f64(),
+                heap_time: (stack_start - heap_start).as_secs_f64(),
+                stack_time: (call_start - stack_start).as_secs_f64(),
+                call_time: (end - call_start).as_secs_f64(),
+                direct_calls: direct_calls,
+                indirect_calls: indirect_calls,
+                worklist: worklist.into_inner(),
+            },
+            key: func_key,
+            taint_findings: taint_findings,
+            deps: deps,
+            stack_args: stack_args,
+        })
+    }
+}
+
+// Whether a patch touches the function at `addr`, going by the bytes verifying it read. A function
+// that failed before they were known is only taken to be patched at its first byte.
+fn touches_patch(patched: &[(u64, u64)], addr: u64, deps: &Option<FunctionDeps>) -> bool {
+    match deps {
+        Some(deps) => deps.intersects(patched),
+        None => patched.iter().any(|(start, end)| *start <= addr && addr < *end),
+    }
+}
+
+/// Verifies the module at `path`. Only a module whose every function (of those picked by
+/// `only_funcs`) is safe is `Ok`; the others are `VerifyError::Unsafe`, with the report saying
+/// which functions failed and why.
+pub fn verify_module(path: &Path, config: VerifyOptions) -> Result<VerificationReport, VerifyError> {
+    verify_module_logged(path, config, None)
+}
+
+/// `verify_module`, with each function's log grouped by `logger` (which must be the installed
+/// logger) and handed back in the report of a failing function.
+pub fn verify_module_logged(path: &Path, config: VerifyOptions, logger: Option<&OrderedLogger>) -> Result<VerificationReport, VerifyError> {
+    let mut taint_findings = 0;
+    let mut info: Vec<FunctionStats> = vec![];
+    let mut report = VerificationReport::default();
+    let module_path = path.to_string_lossy().to_string();
+    // the layout constant counts are process-wide, so only count this run
+    reset_wamr_layout_uses();
+    let mut program = load(|| load_program(&module_path))?;
+    let module_layout = load(|| load_layout(&module_path))?;
+    info!("{:?} module", module_layout.format);
+    let patched_ranges = match &config.patch {
+        Some(patch_path) => {
+            let patches = PatchList::load(patch_path).map_err(|e| VerifyError::Load(format!("Invalid patch {}", e)))?;
+            let module = fs::read(&module_path).map_err(|e| VerifyError::Load(format!("{:?}: {}", module_path, e)))?;
+            let ranges = patches
+                .apply(&module, &mut program)
+                .map_err(|e| VerifyError::Load(format!("Unable to apply {}: {}", patch_path, e)))?;
+            info!("Applied {} patches from {} (digest {})", ranges.len(), patch_path, patches.digest());
+            report.patch_digest = Some(patches.digest());
+            ranges
+        }
+        None => vec![],
+    };
+
+    info!("Loading Metadata");
+    let mut mapped: Vec<_> = config.symbol_map.names.iter().collect();
+    mapped.sort();
+    for (canonical, actual) in mapped {
+        info!("Symbol map: {} -> {}", canonical, actual);
+    }
+    let mut metadata = load(|| load_metadata(
+        &module_path,
+        config.compiler,
+        config.globals_size,
+        config.call_table_size,
+        &config.symbol_map,
+    ))?;
+    if let Some(caller_window) = config.caller_window {
+        metadata.caller_window = caller_window;
+    }
+    metadata.strict_stack = config.strict_stack;
+    metadata.strict_rebound = config.strict_rebound;
+    if let Some(page) = config.safe_page {
+        metadata.lucet_safe_page = Some(page);
+    }
+    if let Some(page) = metadata.lucet_safe_page {
+        info!("Spectre-mitigated heap accesses may fall back to the safe page at 0x{:x}", page);
+    }
+    metadata.options = config.analysis_options;
+    metadata.abi = config.abi;
+    if metadata.abi != Abi::Lp64 {
+        info!("ABI: {}", metadata.abi);
+    }
+    if metadata.options != AnalysisOptions::default() {
+        info!("Analysis options: {}", metadata.options);
+    }
+    metadata.wasmtime_layout = config.wasmtime_layout;
+    if let Compiler::Wasmtime = metadata.compiler {
+        info!("VMContext offsets: {}", metadata.wasmtime_layout);
+    }
+    // the static array of function pointers of a Wamr build that keeps one in the module
+    let mut wamr_func_ptrs = None;
+    if let Some(path) = &config.wamr_layout {
+        let layout = load_wamr_layout(path).map_err(|e| VerifyError::Load(format!("Invalid WAMR layout {}", e)))?;
+        metadata.runtime_fields = layout.runtime_fields;
+        for field in &metadata.runtime_fields {
+            info!("Writable runtime field: 0x{:x} ({} bytes)", field.offset, field.width);
+        }
+        if let Some(memories) = layout.memories {
+            metadata.wamr_memories = memories;
+            info!("Linear memories (base:bound): {}", metadata.wamr_memories);
+        }
+        if let Some(tables) = layout.tables {
+            metadata.wamr_tables = Some(tables);
+            info!("Funcref tables (descriptors:count:size:base): {}", tables);
+        }
+        wamr_func_ptrs = layout.func_ptrs;
+    }
+    let failure_cache = match &config.failure_cache {
+        Some(path) => Some(
+            FailureCache::load(path, config.recheck_percent, config.cache_seed)
+                .map_err(|e| VerifyError::Load(format!("Invalid failure cache {}", e)))?,
+        ),
+        None => None,
+    };
+    let previous_stats = match &config.compare_stats {
+        Some(path) => Some(load_stats(path).map_err(|e| VerifyError::Load(format!("Unable to load stats {}", e)))?),
+        None => None,
+    };
+    let mut symbol_map: Vec<_> = config.symbol_map.names.iter().collect();
+    symbol_map.sort();
+    let mut extra_flags = format!("{:?} {:?} {:?}", symbol_map, config.trusted_funcs, config.cfg_limits);
+    if let Some(digest) = &report.patch_digest {
+        extra_flags.push_str(&format!(" patch {}", digest));
+    }
+    let flags = flags_fingerprint(&metadata, &extra_flags);
+    let (data, data_caveats) = collect_caveats(|| load(|| get_data(&module_path, &module_layout, &config.trusted_funcs, config.no_symbols)));
+    let (_, mut func_addrs, plt, func_source, trusted_addrs) = data?;
+    for caveat in data_caveats {
+        report.add_caveat(caveat);
+    }
+    if !trusted_addrs.is_empty() {
+        log::warn!("{} functions are TRUSTED, not verified, and may be called:", trusted_addrs.len());
+        for (addr, func_name) in trusted_addrs.iter() {
+            log::warn!("    trusted: 0x{:x} {}", addr, func_name);
+            let description = format!("{} is trusted, not verified, and may be called", func_name);
+            report.add_caveat(SoundnessCaveat::new(CaveatKind::TrustedFunction, Some(*addr), description));
+        }
+    }
+    // a function only a function table points to is verified (and callable) too
+    let mut table_only = HashSet::new();
+    let has_table = match metadata.compiler {
+        Compiler::Lucet => metadata.lucet_tables != 0,
+        Compiler::Wamr => wamr_func_ptrs.is_some(),
+        Compiler::Wasmtime => false,
+    };
+    if has_table {
+        let found = fs::read(&module_path)
+            .map_err(|e| e.to_string())
+            .and_then(|module| load_table_funcs(&metadata, wamr_func_ptrs, module_layout.text, module_reader(&module)?));
+        match found {
+            Ok(Some(found)) => {
+                for (addr, func_name) in found.funcs {
+                    let known = func_addrs.iter().chain(trusted_addrs.iter()).any(|(known, _)| *known == addr);
+                    if !known {
+                        info!("{:?}: found only through the function table", func_name);
+                        table_only.insert(addr);
+                        func_addrs.push((addr, func_name));
+                    }
+                }
+                if found.null_entries > 0 {
+                    info!("{} empty function table entries", found.null_entries);
+                }
+                for (idx, entry) in found.bad_entries {
+                    log::warn!("Function table entry {} is 0x{:x}, outside the code section", idx, entry);
+                    let description = format!("function table entry {} points outside the code section", idx);
+                    report.add_caveat(SoundnessCaveat::new(CaveatKind::TableEntry, Some(entry), description));
+                }
+            }
+            Ok(None) => (),
+            Err(e) if wamr_func_ptrs.is_some() => return Err(VerifyError::Load(format!("Unable to read the function pointers: {}", e))),
+            Err(e) => log::warn!("Unable to read the function table: {}", e),
+        }
+    }
+    if let Compiler::Wamr = metadata.compiler {
+        // only functions that start like Wamr AOT functions are verified (and callable) as guest code
+        func_addrs.retain(|(addr, func_name)| {
+            let prologue = match lift_prologue(&program, *addr, &metadata, PROLOGUE_WINDOW) {
+                Ok(prologue) => prologue,
+                Err(e) => {
+                    info!("{:?}: prologue can't be lifted ({}), not an AOT guest function, skipping", func_name, e);
+                    let description = format!("{}'s prologue can't be lifted ({}), skipped", func_name, e);
+                    report.add_caveat(SoundnessCaveat::new(CaveatKind::SkippedFunction, Some(*addr), description));
+                    return false;
+                }
+            };
+            match match_wamr_prologue(&prologue) {
+                Some(template) => {
+                    info!("{:?}: AOT guest function ({:?})", func_name, template);
+                    true
+                }
+                None => {
+                    info!("{:?}: not an AOT guest function, skipping", func_name);
+                    let description = format!("{} doesn't start like an AOT guest function, skipped", func_name);
+                    report.add_caveat(SoundnessCaveat::new(CaveatKind::SkippedFunction, Some(*addr), description));
+                    false
+                }
+            }
+        });
+    }
+    let mut valid_funcs = ValidFuncs::new();
+    for (addr, func_name) in func_addrs.iter() {
+        valid_funcs.insert(*addr, func_name, if table_only.contains(addr) { FuncSource::Table } else { func_source });
+    }
+    for (addr, func_name) in trusted_addrs.iter() {
+        valid_funcs.insert(*addr, func_name, FuncSource::Trusted);
+    }
+    if let Compiler::Wamr = metadata.compiler {
+        // Wamr has a few special functions that shouldn't be verified, but should be call-able
+        for (addr, func_name) in load(|| wamr_get_native_addrs(&module_layout))? {
+            valid_funcs.insert(addr, &func_name, FuncSource::Native);
+            let description = format!("{} is taken to be a runtime function by its name, and may be called", func_name);
+            report.add_caveat(SoundnessCaveat::new(CaveatKind::NativeFunction, Some(addr), description));
+        }
+    }
+    if let Some(path) = &config.funcs_snapshot {
+        valid_funcs.save(path).map_err(|e| VerifyError::Load(format!("Unable to write functions snapshot {}", e)))?;
+    }
+    if let Some(path) = &config.funcs_expect {
+        let expected = ValidFuncs::load(path).map_err(|e| VerifyError::Load(format!("Invalid functions snapshot {}", e)))?;
+        let diff = valid_funcs.diff(&expected);
+        if !diff.is_empty() {
+            let lines = diff.lines();
+            match config.funcs_expect_policy {
+                FuncsDriftPolicy::Fail => {
+                    return Err(VerifyError::Load(format!("The valid call targets differ from {}:\n{}", path, lines.join("\n"))))
+                }
+                FuncsDriftPolicy::Warn => {
+                    for line in lines {
+                        log::warn!("Valid call targets differ from {}: {}", path, line);
+                    }
+                }
+            }
+        }
+    }
+    if let (Some(first), Some(last)) = (func_addrs.iter().map(|x| x.0).min(), func_addrs.iter().map(|x| x.0).max()) {
+        if plt.0 < plt.1 {
+            info!("PLT: 0x{:x}-0x{:x}, functions: 0x{:x}-0x{:x}", plt.0, plt.1, first, last);
+        } else {
+            info!("No PLT, functions: 0x{:x}-0x{:x}", first, last);
+        }
+    }
+    let mut html_report = config.html_report.as_ref().map(|path| {
+        let mut header = vec![
+            ("Module".to_string(), module_path.clone()),
+            ("Compiler".to_string(), format!("{:?}", metadata.compiler)),
+            ("Caller window".to_string(), metadata.caller_window.to_string()),
+            ("Strict stack".to_string(), metadata.strict_stack.to_string()),
+            ("Strict rebound after call".to_string(), metadata.strict_rebound.to_string()),
+            ("Analysis options".to_string(), metadata.options.to_string()),
+            ("ABI".to_string(), metadata.abi.to_string()),
+        ];
+        let trusted: Vec<String> = trusted_addrs.iter().map(|(addr, name)| format!("{} (0x{:x})", name, addr)).collect();
+        header.push(("Trusted functions".to_string(), if trusted.is_empty() { "none".to_string() } else { trusted.join(", ") }));
+        if let Compiler::Wamr = metadata.compiler {
+            header.push(("Globals size".to_string(), metadata.globals_size.to_string()));
+            header.push(("Call table size".to_string(), metadata.call_table_size.to_string()));
+            header.push(("Trusted native functions".to_string(), valid_funcs.count(FuncSource::Native).to_string()));
+            let fields: Vec<String> = metadata.runtime_fields.iter().map(|f| format!("0x{:x} ({} bytes)", f.offset, f.width)).collect();
+            header.push(("Writable runtime fields".to_string(), if fields.is_empty() { "none".to_string() } else { fields.join(", ") }));
+            header.push(("Linear memories (base:bound)".to_string(), metadata.wamr_memories.to_string()));
+            if let Some(tables) = metadata.wamr_tables {
+                header.push(("Funcref tables (descriptors:count:size:base)".to_string(), tables.to_string()));
+            }
+        }
+        if let Compiler::Wasmtime = metadata.compiler {
+            header.push(("VMContext offsets".to_string(), metadata.wasmtime_layout.to_string()));
+        }
+        if let Some(digest) = &report.patch_digest {
+            header.push(("Patch".to_string(), digest.clone()));
+        }
+        for (canonical, actual) in &symbol_map {
+            header.push((format!("Symbol {}", canonical), actual.to_string()));
+        }
+        HtmlReport::new(path, header)
+    });
+    // log output is grouped per function, in address order
+    let mut ordered_funcs = if config.only_funcs.is_empty() {
+        func_addrs.clone()
+    } else {
+        select_funcs(&func_addrs, &config.only_funcs).map_err(VerifyError::Load)?
+    };
+    ordered_funcs.sort_by_key(|(addr, _)| *addr);
+    let deps_fingerprint = dependency_fingerprint(flags, &func_addrs, &valid_funcs.addrs(), plt);
+    let mut recorded: BTreeMap<u64, RecordedFunction> = BTreeMap::new();
+    // the stack argument extents of the verified functions, for --check-stack-args
+    let mut stack_args: BTreeMap<u64, StackArgExtents> = BTreeMap::new();
+    // functions none of whose recorded dependencies changed keep their recorded results
+    let mut kept: Vec<RecordedFunction> = vec![];
+    if let (Some(deps_path), Some(ranges_path)) = (&config.deps, &config.changed_ranges) {
+        let prior = DependencyRecord::load(deps_path).map_err(|e| VerifyError::Load(format!("Invalid dependency record {}", e)))?;
+        let changed = fs::read_to_string(ranges_path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| parse_changed_ranges(&data))
+            .map_err(|e| VerifyError::Load(format!("Invalid changed ranges {}: {}", ranges_path, e)))?;
+        let segments = fs::read(&module_path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| load_segments(&data))
+            .map_err(|e| VerifyError::Load(format!("{:?}: {}", module_path, e)))?;
+        let changed = file_ranges_to_addrs(&segments, &changed);
+        if prior.fingerprint != deps_fingerprint {
+            info!("The flags or functions changed since {} was written, verifying every function", deps_path);
+        }
+        let stale = prior.stale_functions(&ordered_funcs, deps_fingerprint, &changed);
+        info!("{} of {} functions depend on the {} changed ranges", stale.len(), ordered_funcs.len(), changed.len());
+        let stale_addrs: HashSet<u64> = stale.iter().map(|(addr, _)| *addr).collect();
+        kept = ordered_funcs
+            .iter()
+            .filter(|(addr, _)| !stale_addrs.contains(addr))
+            .map(|(addr, _)| prior.functions[addr].clone())
+            .collect();
+        ordered_funcs = stale;
+    }
+    let failure_cache = Mutex::new(failure_cache);
+    let cfg_cache = match &config.cache_dir {
+        Some(dir) => {
+            let module = fs::read(&module_path).map_err(|e| VerifyError::Load(format!("{:?}: {}", module_path, e)))?;
+            let module_name = path.file_name().map_or(module_path.clone(), |name| name.to_string_lossy().to_string());
+            info!("Caching CFGs in {}", dir);
+            Some(CfgCache::new(dir, &module_name, &module, flags))
+        }
+        None => None,
+    };
+    if config.record_schedule.is_some() && config.replay_schedule.is_some() {
+        return Err(VerifyError::Load("A schedule can't be recorded while replaying one".to_string()));
+    }
+    let recorded_schedules = Mutex::new(config.record_schedule.as_ref().map(|_| Schedules::default()));
+    let replay_schedules = match &config.replay_schedule {
+        Some(path) => {
+            let (schedules, warning) = Schedules::load(path).map_err(|e| VerifyError::Load(format!("Invalid worklist schedule {}", e)))?;
+            if let Some(warning) = warning {
+                log::warn!("Worklist schedule {} is {}", path, warning);
+            }
+            info!("Replaying {} worklist schedules from {}", schedules.len(), path);
+            Some(schedules)
+        }
+        None => None,
+    };
+    let verifier = FunctionVerifier {
+        config: &config,
+        module_path: &module_path,
+        program: &program,
+        module_layout: &module_layout,
+        metadata: &metadata,
+        func_addrs: &func_addrs,
+        valid_funcs: &valid_funcs,
+        plt: plt,
+        flags: flags,
+        failure_cache: &failure_cache,
+        cfg_cache: cfg_cache,
+        lookup_turns: failure_cache.lock().unwrap().as_ref().map(|_| LookupTurns::default()),
+        recorded_schedules: &recorded_schedules,
+        replay_schedules: replay_schedules,
+    };
+    let next_func = AtomicUsize::new(0);
+    let first_failure = AtomicUsize::new(usize::MAX);
+    let (sender, receiver) = channel();
+    // the first I/O error writing a function's outputs, which ends verification
+    let mut io_failure = None;
+    thread::scope(|scope| {
+        for _ in 0..config.num_jobs.max(1) {
+            let sender = sender.clone();
+            let (verifier, ordered_funcs, next_func, first_failure) = (&verifier, &ordered_funcs, &next_func, &first_failure);
+            scope.spawn(move || {
+                // the disassembly contexts aren't thread-safe, so each worker sets up its own
+                let contexts = load_contexts(verifier.module_layout);
+                loop {
+                    let func_idx = next_func.fetch_add(1, Ordering::SeqCst);
+                    if func_idx >= ordered_funcs.len() {
+                        break;
+                    }
+                    // unless we keep going, nothing after the first failure (by address) gets reported
+                    if !verifier.config.keep_going && func_idx > first_failure.load(Ordering::SeqCst) {
+                        verifier.skip_lookup(func_idx);
+                        break;
+                    }
+                    let (addr, func_name) = &ordered_funcs[func_idx];
+                    let (outcome, caveats) =
+                        collect_caveats(|| verifier.verify_logged(logger, &contexts.contexts, func_idx, *addr, func_name));
+                    if !matches!(outcome, Ok(Outcome::Verified { .. })) {
+                        first_failure.fetch_min(func_idx, Ordering::SeqCst);
+                    }
+                    if sender.send((func_idx, (outcome, caveats))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // outcomes are committed in address order, so the stats, the failure cache and the
+        // failures reported are the same as for a sequential run
+        let mut pending = BTreeMap::new();
+        let mut next_outcome = 0;
+        let mut stopped = false;
+        for (func_idx, outcome) in receiver.iter() {
+            pending.insert(func_idx, outcome);
+            while let Some((outcome, caveats)) = pending.remove(&next_outcome) {
+                let func_name = &ordered_funcs[next_outcome].1;
+                next_outcome += 1;
+                // functions past the first failure that were already in progress
+                if stopped {
+                    continue;
+                }
+                let outcome = match outcome {
+                    Ok(outcome) => outcome,
+                    Err(e) => {
+                        io_failure = Some(e);
+                        stopped = true;
+                        continue;
+                    }
+                };
+                for caveat in caveats {
+                    report.add_caveat(SoundnessCaveat { function: Some(func_name.clone()), ..caveat });
+                }
+                match outcome {
+                    Outcome::Verified { mut result, stats, key, taint_findings: findings, deps, stack_args: extents } => {
+                        result.patched = touches_patch(&patched_ranges, result.addr, &deps);
+                        if result.patched {
+                            log::warn!("{}: verified, but patched", result.name);
+                        }
+                        if let Some(cache) = failure_cache.lock().unwrap().as_mut() {
+                            cache.record_pass(key);
+                        }
+                        if let Some(report) = &mut html_report {
+                            report.add(FunctionReport {
+                                stats: stats.clone(),
+                                verdict: Verdict::Verified,
+                                failure: None,
+                            });
+                        }
+                        taint_findings += findings;
+                        if config.check_stack_args {
+                            stack_args.insert(result.addr, extents);
+                        }
+                        info.push(stats);
+                        if config.deps.is_some() {
+                            recorded.insert(result.addr, RecordedFunction { result: result.clone(), deps: deps });
+                        }
+                        report.add(result);
+                    }
+                    Outcome::Failed { mut result, entry, key, msg, deps } => {
+                        result.patched = touches_patch(&patched_ranges, result.addr, &deps);
+                        match (&result.failing_addr, &result.failing_stmt) {
+                            (Some(addr), Some(stmt)) => log::error!("{}: {} at 0x{:x}: {}", result.name, msg, addr, stmt),
+                            _ => log::error!("{}: {}", result.name, msg),
+                        }
+                        if let (Some(bytes), Some(disasm)) = (&result.failing_bytes, &result.failing_disasm) {
+                            log::error!("    instruction: {}  ({})", disasm, bytes);
+                        }
+                        if let Some(description) = &result.failing_description {
+                            log::error!("    check: {}", description);
+                        }
+                        if let Some(state) = &result.failing_state {
+                            log::error!("    state: {}", state);
+                        }
+                        if result.patched {
+                            log::error!("    patched");
+                        }
+                        if let (Some(cache), Some(key)) = (failure_cache.lock().unwrap().as_mut(), key) {
+                            cache.record_failure(key, &result.name, flags, &msg);
+                        }
+                        if let Some(html_report) = &mut html_report {
+                            html_report.add(entry);
+                        }
+                        if config.deps.is_some() {
+                            recorded.insert(result.addr, RecordedFunction { result: result.clone(), deps: deps });
+                        }
+                        report.add(result);
+                        stopped = !config.keep_going;
+                    }
+                }
+            }
+        }
+    });
+    drop(verifier);
+    if let Some(e) = io_failure {
+        return Err(e);
+    }
+    if let (Some(path), Some(schedules)) = (&config.record_schedule, recorded_schedules.into_inner().unwrap()) {
+        info!("Writing {} worklist schedules to {}", schedules.len(), path);
+        schedules.save(path).map_err(|e| io_error(format!("Unable to write worklist schedules to {}", path), e))?;
+    }
+    if let Some(path) = &config.deps {
+        for func in kept {
+            info!("{:?} is unchanged, keeping its recorded result", func.result.name);
+            report.add(func.result.clone());
+            recorded.insert(func.result.addr, func);
+        }
+        report.functions.sort_by_key(|func| func.addr);
+        let record = DependencyRecord { fingerprint: deps_fingerprint, functions: recorded };
+        record.save(path).map_err(|e| io_error(format!("Unable to write dependency record to {}", path), e))?;
+    }
+    if config.check_stack_args {
+        // reading garbage the caller left on its stack is a correctness (or leak) concern, not an
+        // escape from the sandbox, so these are only warnings
+        let name = |addr: u64| func_addrs.iter().find(|(a, _)| *a == addr).map_or(format!("0x{:x}", addr), |(_, n)| n.clone());
+        let mismatches = stack_arg_mismatches(&stack_args);
+        for mismatch in mismatches.iter() {
+            log::warn!(
+                "{} reads {} bytes of stack arguments, but {} only writes {} for its call at 0x{:x}",
+                name(mismatch.callee),
+                mismatch.read,
+                name(mismatch.caller),
+                mismatch.written,
+                mismatch.call_site
+            );
+        }
+        info!("Stack arguments: {} callees read more than a caller writes", mismatches.len());
+    }
+    report.deny_caveats(&config.deny_caveats);
+    let func_counter = info.len();
+    let failure_cache = failure_cache.into_inner().unwrap();
+    if let Some(cache) = &failure_cache {
+        cache.save().map_err(|e| io_error("Unable to write failure cache".to_string(), e))?;
+    }
+    if let Some(html_report) = &mut html_report {
+        html_report.caveats = report.caveats.clone();
+    }
+    if let Some(report) = &html_report {
+        info!("Writing HTML report to {}", report.path);
+        report.write().map_err(|e| io_error(format!("Unable to write HTML report to {}", report.path), e))?;
+    }
+    let layout_uses: Vec<(&str, usize)> = match metadata.compiler {
+        Compiler::Wamr => wamr_layout_uses(),
+        Compiler::Lucet | Compiler::Wasmtime => vec![],
+    };
+    if let Compiler::Wamr = metadata.compiler {
+        let (unused, frequent) = unusual_wamr_layout_uses(func_counter);
+        for name in unused {
+            info!("Layout constant {} never matched (wrong offset, or unused by this module?)", name);
+        }
+        for name in frequent {
+            info!("Layout constant {} matched unusually often (colliding with another field?)", name);
+        }
+    }
+    let comparison = previous_stats.map(|previous| {
+        let comparison = compare_stats(&previous, &info, config.regression_factor);
+        for line in comparison.report().lines() {
+            info!(target: SUMMARY, "{}", line);
+        }
+        comparison
+    });
+    if let Some(output_path) = &config.stats_output {
+        let data = match config.stats_format {
+            StatsFormat::Json => {
+                let layout_stats: HashMap<&str, usize> = layout_uses.into_iter().collect();
+                let mut stats = stats_named(&info);
+                stats["wamr_layout"] = serde_json::json!(layout_stats);
+                stats["comparison"] = serde_json::json!(comparison.map(|c| c.to_json()));
+                stats["analysis_options"] = serde_json::json!(config.analysis_options.to_string());
+                stats["verification"] = report.to_json();
+                serde_json::to_string(&stats).unwrap()
+            }
+            StatsFormat::Csv => stats_csv(&info),
+            StatsFormat::Text => stats_text(&info),
+            StatsFormat::Legacy => serde_json::to_string(&stats_legacy(&info)).unwrap(),
+        };
+        info!("Dumping Stats to {}", output_path);
+        fs::write(output_path, data).map_err(|e| io_error(format!("Unable to write stats to {}", output_path), e))?;
+    }
+
+    let totals = stats_totals(&info);
+    info!(target: SUMMARY, "Verified {:?} functions", func_counter);
+    if !report.failures().is_empty() {
+        info!(target: SUMMARY, "{} functions failed", report.failures().len());
+    }
+    for kind in CAVEAT_KINDS.iter() {
+        let caveats: Vec<&SoundnessCaveat> = report.caveats.iter().filter(|c| c.kind == *kind).collect();
+        if caveats.is_empty() {
+            continue;
+        }
+        let denied = if config.deny_caveats.contains(kind) { " (denied)" } else { "" };
+        let count: usize = caveats.iter().map(|c| c.count).sum();
+        info!(target: SUMMARY, "Soundness caveat {}: {} times at {} locations{}", kind, count, caveats.len(), denied);
+        for caveat in caveats {
+            info!("    {}: {} ({} times)", caveat.location(), caveat.description, caveat.count);
+        }
+    }
+    for caveat in report.denied_caveats() {
+        log::error!("{}: denied {}: {}", caveat.location(), caveat.kind, caveat.description);
+    }
+    // the instructions skipped under --unknown-instr ignore, by opcode
+    let mut skipped: BTreeMap<&str, usize> = BTreeMap::new();
+    for caveat in report.caveats.iter().filter(|c| c.kind == CaveatKind::UnliftedInstruction) {
+        *skipped.entry(caveat.opcode().unwrap_or("unknown")).or_insert(0) += caveat.count;
+    }
+    if !skipped.is_empty() {
+        let counts: Vec<String> = skipped.iter().map(|(opcode, count)| format!("{} ({} times)", opcode, count)).collect();
+        info!(target: SUMMARY, "Skipped unimplemented instructions: {}", counts.join(", "));
+    }
+    if config.taint_audit {
+        info!("Taint audit: {} findings", taint_findings);
+    }
+    info!(
+        "Total time = {:?}s CFG: {:?} Heap: {:?}s Stack: {:?}s Call: {:?}s",
+        totals.total_time(),
+        totals.cfg_time,
+        totals.heap_time,
+        totals.stack_time,
+        totals.call_time
+    );
+    // a manifest is only written for a module that verified, since only then is there a verdict
+    // for the host to compose with
+    if let Some(path) = config.assumptions.as_ref().filter(|_| report.all_verified()) {
+        info!("Writing assumptions manifest to {}", path);
+        Assumptions::from_metadata(&metadata, &trusted_addrs)
+            .save(path)
+            .map_err(|e| io_error(format!("Unable to write assumptions manifest to {}", path), e))?;
+    }
+    info!("Done!");
+    if report.all_verified() {
+        Ok(report)
+    } else {
+        Err(VerifyError::Unsafe(report))
+    }
+}
+
+
+#[test]
+fn verify_module_test() {
+    let report = verify_module(Path::new("./veriwasm_public_data/firefox_libs/liboggwasm.so"), VerifyOptions::default()).unwrap();
+    assert!(!report.functions.is_empty());
+    assert!(report.all_verified());
+
+    match verify_module(Path::new("./veriwasm_public_data/missing.so"), VerifyOptions::default()) {
+        Err(VerifyError::Load(_)) => (),
+        other => panic!("Expected a load error, got {:?}", other),
+    }
+    let only_missing = VerifyOptions {
+        only_funcs: vec!["no_such_function".to_string()],
+        ..VerifyOptions::default()
+    };
+    match verify_module(Path::new("./veriwasm_public_data/firefox_libs/liboggwasm.so"), only_missing) {
+        Err(VerifyError::Load(e)) => assert!(e.starts_with("Unknown function")),
+        other => panic!("Expected a load error, got {:?}", other),
+    }
+    // outputs that can't be written, for the module or for a function, are errors rather than panics
+    let unwritable = "./veriwasm_public_data/firefox_libs/liboggwasm.so/out".to_string();
+    let stats = VerifyOptions { stats_output: Some(unwritable.clone()), ..VerifyOptions::default() };
+    let dump = VerifyOptions { dump_cfg: Some(unwritable), ..VerifyOptions::default() };
+    for config in vec![stats, dump] {
+        match verify_module(Path::new("./veriwasm_public_data/firefox_libs/liboggwasm.so"), config) {
+            Err(VerifyError::Io(_)) => (),
+            other => panic!("Expected an I/O error, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn verify_cached_module_test() {
+    let path = Path::new("./veriwasm_public_data/firefox_libs/liboggwasm.so");
+    let dir = std::env::temp_dir().join("veriwasm_verify_cfg_cache");
+    let _ = fs::remove_dir_all(&dir);
+    let cached = || VerifyOptions { cache_dir: Some(dir.to_string_lossy().to_string()), ..VerifyOptions::default() };
+
+    // the first run fills the cache, and the second reads every CFG back from it
+    let uncached = verify_module(path, VerifyOptions::default()).unwrap();
+    assert_eq!(verify_module(path, cached()).unwrap(), uncached);
+    let entries = fs::read_dir(dir.join("liboggwasm.so")).unwrap().count();
+    assert_eq!(entries, uncached.functions.len());
+    assert_eq!(verify_module(path, cached()).unwrap(), uncached);
+}
+
+#[test]
+fn verify_schedule_test() {
+    let path = Path::new("./veriwasm_public_data/firefox_libs/liboggwasm.so");
+    let schedule = std::env::temp_dir().join("veriwasm_verify_schedule");
+    let schedule_path = schedule.to_string_lossy().to_string();
+    let replaying = |path: &str| VerifyOptions { replay_schedule: Some(path.to_string()), ..VerifyOptions::default() };
+
+    let recording = VerifyOptions { record_schedule: Some(schedule_path.clone()), ..VerifyOptions::default() };
+    let recorded = verify_module(path, recording).unwrap();
+    let (schedules, warning) = Schedules::load(&schedule_path).unwrap();
+    assert_eq!(warning, None);
+    // at least a heap and a stack schedule for every function
+    assert!(schedules.len() >= 2 * recorded.functions.len());
+    assert_eq!(verify_module(path, replaying(&schedule_path)).unwrap(), recorded);
+
+    // a truncated schedule only loses the order of the functions past the cut
+    let data = fs::read(&schedule).unwrap();
+    let truncated = std::env::temp_dir().join("veriwasm_verify_schedule_truncated");
+    fs::write(&truncated, &data[..data.len() / 2]).unwrap();
+    assert_eq!(verify_module(path, replaying(&truncated.to_string_lossy())).unwrap(), recorded);
+    // while a file that isn't a schedule is a load error
+    match verify_module(path, replaying("./veriwasm_public_data/firefox_libs/liboggwasm.so")) {
+        Err(VerifyError::Load(e)) => assert!(e.starts_with("Invalid worklist schedule")),
+        other => panic!("Expected a load error, got {:?}", other),
+    }
+}
+
+#[test]
+fn verify_patched_module_test() {
+    let path = Path::new("./veriwasm_public_data/firefox_libs/liboggwasm.so");
+    let module = fs::read(path).unwrap();
+    let segments = load_segments(&module).unwrap();
+    let report = verify_module(path, VerifyOptions::default()).unwrap();
+    assert_eq!(report.patch_digest, None);
+    let func = &report.functions[0];
+    let (offset, _, base) = segments.iter().find(|(_, size, base)| func.addr >= *base && func.addr < base + size).unwrap();
+    let func_offset = offset + (func.addr - base);
+    let bytes = |len: usize| module[func_offset as usize..func_offset as usize + len].iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    let with_patch = |name: &str, patch: String| {
+        let patch_path = std::env::temp_dir().join(name);
+        fs::write(&patch_path, patch).unwrap();
+        VerifyOptions { patch: Some(patch_path.to_string_lossy().to_string()), keep_going: true, ..VerifyOptions::default() }
+    };
+
+    // rewriting a function's first bytes with themselves changes no verdict, but flags it
+    let benign = with_patch("veriwasm_benign.patch", format!("[[{}, \"{}\", \"{}\"]]", func_offset, bytes(3), bytes(3)));
+    let patched = verify_module(path, benign).unwrap();
+    assert!(patched.patch_digest.is_some());
+    assert_eq!(patched.functions.len(), report.functions.len());
+    assert!(patched.functions[0].patched);
+    assert!(patched.functions[1..].iter().all(|f| !f.patched));
+
+    // an unchecked load, `mov rax, [rsi]`, doesn't verify
+    let malicious = with_patch("veriwasm_malicious.patch", format!("[[{}, \"{}\", \"488b06\"]]", func_offset, bytes(3)));
+    match verify_module(path, malicious) {
+        Err(VerifyError::Unsafe(report)) => {
+            let failure = report.failures()[0];
+            assert_eq!(failure.addr, func.addr);
+            assert!(failure.patched);
+        }
+        other => panic!("Expected the patched function to fail, got {:?}", other),
+    }
+
+    // bytes that aren't in the module are a load error naming the patch
+    let stale = with_patch("veriwasm_stale.patch", format!("[[{}, \"{}\", \"90\"]]", func_offset, if bytes(1) == "cc" { "90" } else { "cc" }));
+    match verify_module(path, stale) {
+        Err(VerifyError::Load(e)) => assert!(e.contains(&format!("patch at 0x{:x}", func_offset))),
+        other => panic!("Expected a load error, got {:?}", other),
+    }
+}
@@ -0,0 +1,62 @@
+// Builds tests/ffi/verify_fixture.c against include/veriwasm.h and runs it on the cdylib cargo
+// built alongside this test, so the exported ABI is exercised from C rather than from Rust.
+#![cfg(feature = "ffi")]
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn cdylib() -> PathBuf {
+    // this test runs from target/<profile>/deps, and the cdylib is copied up to target/<profile>
+    let deps = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let name = format!("{}veriwasm{}", env::consts::DLL_PREFIX, env::consts::DLL_SUFFIX);
+    let profile = deps.parent().unwrap().join(&name);
+    if profile.exists() {
+        profile
+    } else {
+        deps.join(&name)
+    }
+}
+
+fn build_fixture() -> PathBuf {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let out = env::temp_dir().join(format!("veriwasm_verify_fixture_{}", std::process::id()));
+    let status = Command::new(env::var("CC").unwrap_or("cc".to_string()))
+        .arg("-std=gnu99")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg(root.join("tests/ffi/verify_fixture.c"))
+        .arg("-ldl")
+        .arg("-o")
+        .arg(&out)
+        .status()
+        .expect("Unable to run the C compiler");
+    assert!(status.success());
+    out
+}
+
+#[test]
+fn ffi_verify_test() {
+    let fixture = build_fixture();
+    let run_with = |module: &str, args: &[&str]| Command::new(&fixture).arg(cdylib()).arg(module).args(args).output().unwrap();
+    let run = |module: &str| run_with(module, &[]);
+
+    let output = run("./veriwasm_public_data/firefox_libs/liboggwasm.so");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.lines().count() > 0);
+    assert!(stdout.lines().all(|line| line.ends_with(" 1 -")));
+
+    let output = run("./veriwasm_public_data/missing.so");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+
+    // a struct_size ending partway into a field leaves that field at its default, rather than
+    // taking its first bytes from the caller
+    let output = run_with("./veriwasm_public_data/firefox_libs/liboggwasm.so", &["partial"]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+
+    std::fs::remove_file(&fixture).unwrap();
+}
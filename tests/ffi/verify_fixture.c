@@ -0,0 +1,69 @@
+/*
+ * Loads libveriwasm with dlopen and verifies a module through the C interface, the way an
+ * embedder would. Usage: verify_fixture <path to libveriwasm> <module> [partial]
+ * With partial, struct_size ends halfway into compiler, which is set to no compiler at all.
+ * Prints one line per function and exits with the status veriwasm_verify returned.
+ */
+#include <dlfcn.h>
+#include <stddef.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "veriwasm.h"
+
+#define LOAD(name) \
+    __typeof__(&name) p_##name = (__typeof__(&name))dlsym(lib, #name); \
+    if (!p_##name) { fprintf(stderr, "missing symbol %s\n", #name); return 100; }
+
+int main(int argc, char **argv) {
+    if (argc != 3 && !(argc == 4 && strcmp(argv[3], "partial") == 0)) {
+        fprintf(stderr, "usage: %s <libveriwasm> <module> [partial]\n", argv[0]);
+        return 100;
+    }
+    void *lib = dlopen(argv[1], RTLD_NOW);
+    if (!lib) {
+        fprintf(stderr, "%s\n", dlerror());
+        return 100;
+    }
+    LOAD(veriwasm_verify);
+    LOAD(veriwasm_report_function_count);
+    LOAD(veriwasm_report_function_verified);
+    LOAD(veriwasm_report_function_addr);
+    LOAD(veriwasm_report_function_name);
+    LOAD(veriwasm_report_function_rule_id);
+    LOAD(veriwasm_report_to_json);
+    LOAD(veriwasm_string_free);
+    LOAD(veriwasm_report_free);
+
+    VeriwasmOptions options = {0};
+    options.struct_size = sizeof(options);
+    options.compiler = VERIWASM_COMPILER_DETECT;
+    options.globals_size = -1;
+    options.call_table_size = -1;
+    options.num_jobs = 2;
+    options.keep_going = 1;
+    if (argc == 4) {
+        options.struct_size = offsetof(VeriwasmOptions, compiler) + sizeof(options.compiler) / 2;
+        options.compiler = 7;
+    }
+
+    VeriwasmReport *report = NULL;
+    int status = p_veriwasm_verify(argv[2], &options, &report);
+    if (report) {
+        size_t count = p_veriwasm_report_function_count(report);
+        for (size_t i = 0; i < count; i++) {
+            const char *rule = p_veriwasm_report_function_rule_id(report, i);
+            printf("%s 0x%llx %d %s\n", p_veriwasm_report_function_name(report, i),
+                   (unsigned long long)p_veriwasm_report_function_addr(report, i),
+                   p_veriwasm_report_function_verified(report, i), rule ? rule : "-");
+        }
+        char *json = p_veriwasm_report_to_json(report);
+        if (!json || json[0] != '{') {
+            return 100;
+        }
+        p_veriwasm_string_free(json);
+        p_veriwasm_report_free(report);
+    }
+    dlclose(lib);
+    return status;
+}
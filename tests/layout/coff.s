@@ -0,0 +1,16 @@
+# Two WAMR AOT functions, the first calling the runtime through its import address table entry
+# Built into module.obj with: llvm-mc -triple=x86_64-pc-windows-msvc -filetype=obj -o module.obj coff.s
+        .text
+        .globl  "aot_func#0"
+"aot_func#0":
+        push    %rbp
+        mov     %rsp, %rbp
+        call    *__imp_aot_set_exception_with_id(%rip)
+        pop     %rbp
+        ret
+
+        .globl  "aot_func#1"
+"aot_func#1":
+        sub     $8, %rsp
+        add     $8, %rsp
+        ret
@@ -0,0 +1,18 @@
+# Two WAMR AOT functions, the first calling the runtime through the PLT
+# Built into module.so with: gcc -shared -nostdlib -Wl,--build-id=none -o module.so elf.s
+        .text
+        .globl  "aot_func#0"
+        .type   "aot_func#0", @function
+"aot_func#0":
+        push    %rbp
+        mov     %rsp, %rbp
+        call    aot_set_exception_with_id@PLT
+        pop     %rbp
+        ret
+
+        .globl  "aot_func#1"
+        .type   "aot_func#1", @function
+"aot_func#1":
+        sub     $8, %rsp
+        add     $8, %rsp
+        ret
@@ -0,0 +1,24 @@
+# Two WAMR AOT functions, the first calling the runtime through its stub in __stubs
+# Built into module.macho.o with: llvm-mc -triple=x86_64-apple-macosx10.15 -filetype=obj -o module.macho.o macho.s
+        .section __TEXT,__text,regular,pure_instructions
+        .globl  "_aot_func#0"
+"_aot_func#0":
+        push    %rbp
+        mov     %rsp, %rbp
+        call    Lstub
+        pop     %rbp
+        ret
+
+        .globl  "_aot_func#1"
+"_aot_func#1":
+        sub     $8, %rsp
+        add     $8, %rsp
+        ret
+
+        .section __TEXT,__stubs,symbol_stubs,pure_instructions,6
+Lstub:
+        jmpq    *Lslot(%rip)
+
+        .section __DATA,__la_symbol_ptr,lazy_symbol_pointers
+Lslot:
+        .quad   0
@@ -0,0 +1,36 @@
+# Heap accesses from a Lucet build with Spectre mitigations, whose safe page is at 0x5000: the
+# bounds check's cmova swaps the heap base in rdi for the safe page, then a load through it.
+# One per 0x40 bytes, each a single block ending in ret.
+# Built into mitigated.bin with: as -o mitigated.o mitigated.s && objcopy -O binary -j .text mitigated.o mitigated.bin
+        .text
+# 0x0: 16 bytes past the base
+        mov     $0x5000, %rcx
+        cmp     $0x1000, %esi
+        cmova   %rcx, %rdi
+        mov     0x10(%rdi), %eax
+        ret
+
+        .balign 0x40, 0xcc
+# 0x40: the last 4 bytes of the page
+        mov     $0x5000, %rcx
+        cmp     $0x1000, %esi
+        cmova   %rcx, %rdi
+        mov     0xffc(%rdi), %eax
+        ret
+
+        .balign 0x40, 0xcc
+# 0x80: one byte past the end of the page
+        mov     $0x5000, %rcx
+        cmp     $0x1000, %esi
+        cmova   %rcx, %rdi
+        mov     0xffd(%rdi), %eax
+        ret
+
+        .balign 0x40, 0xcc
+# 0xc0: an index below 4GB, which the safe page isn't followed by a guard region for
+        mov     $0x5000, %rcx
+        cmp     $0x1000, %esi
+        cmova   %rcx, %rdi
+        mov     %esi, %esi
+        mov     (%rdi,%rsi), %eax
+        ret